@@ -0,0 +1,512 @@
+
+
+
+//! Native AAudio output and input, for Android 8.0 (API 26) and later.
+//!
+//! This skips the layers between cpal and the device, and exposes the performance and sharing
+//! modes of the stream. AAudio uses MMAP on its own when the device supports it, for exclusive
+//! low latency streams.
+
+
+
+use std::ffi::{ c_char, c_int, c_void, CStr };
+use std::sync::{ Arc, Mutex, OnceLock };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::{ self, Sender };
+
+use crate::engine::{ PerformanceMode, SharingMode };
+use crate::input::{ self, InputConfig };
+use crate::mixer::{ self, Mixer, SoundSource };
+use crate::tap::{ Tap, TapWriter };
+
+
+
+const DIRECTION_OUTPUT: i32 = 0;
+const DIRECTION_INPUT: i32 = 1;
+const FORMAT_PCM_I16: i32 = 1;
+const SHARING_MODE_EXCLUSIVE: i32 = 0;
+const SHARING_MODE_SHARED: i32 = 1;
+const PERFORMANCE_MODE_NONE: i32 = 10;
+const PERFORMANCE_MODE_POWER_SAVING: i32 = 11;
+const PERFORMANCE_MODE_LOW_LATENCY: i32 = 12;
+const CALLBACK_RESULT_CONTINUE: i32 = 0;
+const ERROR_DISCONNECTED: i32 = -899;
+const OK: i32 = 0;
+/// echo cancellation, noise suppression and gain control, as the
+/// device does them
+const INPUT_PRESET_VOICE_COMMUNICATION: i32 = 7;
+
+
+
+#[repr(C)]
+struct AAudioStreamBuilder {
+	_private: [u8; 0]
+}
+
+#[repr(C)]
+struct AAudioStream {
+	_private: [u8; 0]
+}
+
+type DataCallback = extern "C" fn (*mut AAudioStream, *mut c_void, *mut c_void, i32) -> i32;
+type ErrorCallback = extern "C" fn (*mut AAudioStream, *mut c_void, i32);
+
+// libaaudio is missing before android 8.0, linking it would stop the
+// app from loading at all there, so it is opened at runtime instead
+extern "C" {
+	fn dlopen (filename: *const c_char, flag: c_int) -> *mut c_void;
+	fn dlsym (handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+/// RTLD_LAZY, the same value on 32 and 64 bit android
+const RTLD_LAZY: c_int = 1;
+
+/// declare the functions used from libaaudio, and how to load them
+///
+/// the optional ones are from later android versions, and are `None`
+/// on the ones without them
+macro_rules! api {
+	(
+		$( fn $name:ident ($($arg:ident: $ty:ty),*) $(-> $ret:ty)?; )*
+		optional {
+			$( fn $optional:ident ($($optional_arg:ident: $optional_ty:ty),*) $(-> $optional_ret:ty)?; )*
+		}
+	) => {
+
+		#[allow(non_snake_case)]
+		struct Api {
+			$( $name: unsafe extern "C" fn ($($ty),*) $(-> $ret)?, )*
+			$( $optional: Option<unsafe extern "C" fn ($($optional_ty),*) $(-> $optional_ret)?>, )*
+		}
+
+		impl Api {
+
+			/// # Safety
+			///
+			/// `library` must be a handle of libaaudio
+			unsafe fn load (library: *mut c_void) -> Option<Self> {
+				Some(Self {
+					$( $name: {
+						let symbol = dlsym(library, concat!(stringify!($name), "\0").as_ptr() as *const c_char);
+						if symbol.is_null() {
+							return None;
+						}
+						std::mem::transmute::<*mut c_void, unsafe extern "C" fn ($($ty),*) $(-> $ret)?>(symbol)
+					}, )*
+					$( $optional: {
+						let symbol = dlsym(library, concat!(stringify!($optional), "\0").as_ptr() as *const c_char);
+						(!symbol.is_null()).then(|| {
+							std::mem::transmute::<*mut c_void, unsafe extern "C" fn ($($optional_ty),*) $(-> $optional_ret)?>(symbol)
+						})
+					}, )*
+				})
+			}
+
+		}
+
+	}
+}
+
+api! {
+	fn AAudio_createStreamBuilder (builder: *mut *mut AAudioStreamBuilder) -> i32;
+	fn AAudio_convertResultToText (result: i32) -> *const c_char;
+	fn AAudioStreamBuilder_setDirection (builder: *mut AAudioStreamBuilder, direction: i32);
+	fn AAudioStreamBuilder_setFormat (builder: *mut AAudioStreamBuilder, format: i32);
+	fn AAudioStreamBuilder_setChannelCount (builder: *mut AAudioStreamBuilder, channels: i32);
+	fn AAudioStreamBuilder_setSampleRate (builder: *mut AAudioStreamBuilder, sample_rate: i32);
+	fn AAudioStreamBuilder_setSharingMode (builder: *mut AAudioStreamBuilder, mode: i32);
+	fn AAudioStreamBuilder_setPerformanceMode (builder: *mut AAudioStreamBuilder, mode: i32);
+	fn AAudioStreamBuilder_setDataCallback (builder: *mut AAudioStreamBuilder, callback: DataCallback, user_data: *mut c_void);
+	fn AAudioStreamBuilder_setErrorCallback (builder: *mut AAudioStreamBuilder, callback: ErrorCallback, user_data: *mut c_void);
+	fn AAudioStreamBuilder_openStream (builder: *mut AAudioStreamBuilder, stream: *mut *mut AAudioStream) -> i32;
+	fn AAudioStreamBuilder_delete (builder: *mut AAudioStreamBuilder) -> i32;
+	fn AAudioStream_requestStart (stream: *mut AAudioStream) -> i32;
+	fn AAudioStream_close (stream: *mut AAudioStream) -> i32;
+	fn AAudioStream_getSampleRate (stream: *mut AAudioStream) -> i32;
+	fn AAudioStream_getChannelCount (stream: *mut AAudioStream) -> i32;
+	fn AAudioStream_getSharingMode (stream: *mut AAudioStream) -> i32;
+	fn AAudioStream_getPerformanceMode (stream: *mut AAudioStream) -> i32;
+	fn AAudioStream_getFramesPerBurst (stream: *mut AAudioStream) -> i32;
+	fn AAudioStream_setBufferSizeInFrames (stream: *mut AAudioStream, frames: i32) -> i32;
+	optional {
+		// android 9.0
+		fn AAudioStreamBuilder_setInputPreset (builder: *mut AAudioStreamBuilder, preset: i32);
+	}
+}
+
+static API: OnceLock<Option<Api>> = OnceLock::new();
+
+/// libaaudio, if this device has it
+fn api () -> Option<&'static Api> {
+	API.get_or_init(|| {
+		// SAFETY: the library is never closed, so the functions stay valid
+		unsafe {
+			let library = dlopen(c"libaaudio.so".as_ptr(), RTLD_LAZY);
+			if library.is_null() {
+				return None;
+			}
+			Api::load(library)
+		}
+	}).as_ref()
+}
+
+
+
+fn result_text (api: &Api, result: i32) -> String {
+	// SAFETY: AAudio returns a static string for any value
+	unsafe { CStr::from_ptr((api.AAudio_convertResultToText)(result)) }.to_string_lossy().into_owned()
+}
+
+
+
+/// shared with the callbacks of a stream
+struct CallbackData {
+	api: &'static Api,
+	mixer: Arc<Mutex<Mixer>>,
+	channels: usize,
+	events: Sender<StreamEvent>,
+	/// a disconnect was already reported, a stream can report many
+	handled: AtomicBool
+}
+
+
+
+extern "C" fn data_callback (_: *mut AAudioStream, user_data: *mut c_void, audio_data: *mut c_void, frames: i32) -> i32 {
+	// SAFETY: `user_data` is the `CallbackData` of the stream, freed after it is closed, and
+	// `audio_data` has `frames` frames of the i16 format the stream was opened with
+	let data = unsafe { &*(user_data as *const CallbackData) };
+	let buffer = unsafe { std::slice::from_raw_parts_mut(audio_data as *mut i16, frames as usize * data.channels) };
+
+	buffer.fill(0);
+	if let Ok(mut mixer) = data.mixer.lock() {
+		mixer.write_samples(buffer);
+	}
+	CALLBACK_RESULT_CONTINUE
+}
+
+
+extern "C" fn error_callback (_: *mut AAudioStream, user_data: *mut c_void, error: i32) {
+	// SAFETY: same as in `data_callback`
+	let data = unsafe { &*(user_data as *const CallbackData) };
+	log::error!("aaudio stream error: {}", result_text(data.api, error));
+	// the stream can't be reopened from this callback, so the
+	// backend thread does it
+	if error == ERROR_DISCONNECTED && !data.handled.swap(true, Ordering::SeqCst) {
+		let _ = data.events.send(StreamEvent::RecreateStream);
+	}
+}
+
+
+
+/// an open and started AAudio output stream
+struct Stream {
+	api: &'static Api,
+	stream: *mut AAudioStream,
+	data: *mut CallbackData
+}
+
+impl Stream {
+
+	fn open (
+		mixer: &Arc<Mutex<Mixer>>,
+		performance_mode: PerformanceMode,
+		sharing_mode: SharingMode,
+		channels: u16,
+		events: Sender<StreamEvent>
+	) -> Result<Self, &'static str> {
+
+		let api = api().ok_or("aaudio is not available, it needs android 8.0")?;
+		let data = Box::into_raw(Box::new(CallbackData {
+			api,
+			mixer: mixer.clone(),
+			channels: channels as usize,
+			events,
+			handled: AtomicBool::new(false)
+		}));
+
+		let mut builder = std::ptr::null_mut();
+		let mut stream = std::ptr::null_mut();
+		// SAFETY: the builder is used only between its creation and deletion, and `data` lives
+		// until the stream is closed by `Drop`
+		let result = unsafe {
+			let result = (api.AAudio_createStreamBuilder)(&mut builder);
+			if result != OK {
+				drop(Box::from_raw(data));
+				log::error!("creating aaudio stream builder failed: {}", result_text(api, result));
+				return Err("aaudio is not available");
+			}
+			(api.AAudioStreamBuilder_setDirection)(builder, DIRECTION_OUTPUT);
+			(api.AAudioStreamBuilder_setFormat)(builder, FORMAT_PCM_I16);
+			(api.AAudioStreamBuilder_setChannelCount)(builder, channels as i32);
+			(api.AAudioStreamBuilder_setSharingMode)(builder, match sharing_mode {
+				SharingMode::Shared => SHARING_MODE_SHARED,
+				SharingMode::Exclusive => SHARING_MODE_EXCLUSIVE
+			});
+			(api.AAudioStreamBuilder_setPerformanceMode)(builder, match performance_mode {
+				PerformanceMode::None => PERFORMANCE_MODE_NONE,
+				PerformanceMode::PowerSaving => PERFORMANCE_MODE_POWER_SAVING,
+				PerformanceMode::LowLatency => PERFORMANCE_MODE_LOW_LATENCY
+			});
+			(api.AAudioStreamBuilder_setDataCallback)(builder, data_callback, data as *mut c_void);
+			(api.AAudioStreamBuilder_setErrorCallback)(builder, error_callback, data as *mut c_void);
+			let result = (api.AAudioStreamBuilder_openStream)(builder, &mut stream);
+			(api.AAudioStreamBuilder_delete)(builder);
+			result
+		};
+		if result != OK {
+			// SAFETY: no stream was opened, so nothing else uses `data`
+			drop(unsafe { Box::from_raw(data) });
+			log::error!("opening aaudio stream failed: {}", result_text(api, result));
+			return Err("failed to open aaudio stream");
+		}
+		let this = Self { api, stream, data };
+
+		// SAFETY: `stream` is open until `Drop`
+		unsafe {
+			let sample_rate = (api.AAudioStream_getSampleRate)(stream);
+			let channels = (api.AAudioStream_getChannelCount)(stream);
+			mixer.lock().unwrap().set_config(channels as u16, mixer::SampleRate(sample_rate as u32));
+			// the callback doesn't run before the start
+			(*data).channels = channels as usize;
+
+			// the default buffer is large, two bursts is the usual
+			// minimum that doesn't glitch
+			if performance_mode == PerformanceMode::LowLatency {
+				(api.AAudioStream_setBufferSizeInFrames)(stream, (api.AAudioStream_getFramesPerBurst)(stream) * 2);
+			}
+
+			log::info!(
+				"opened aaudio stream: {}Hz, {} channels, sharing mode {}, performance mode {}",
+				sample_rate,
+				channels,
+				(api.AAudioStream_getSharingMode)(stream),
+				(api.AAudioStream_getPerformanceMode)(stream)
+			);
+
+			let result = (api.AAudioStream_requestStart)(stream);
+			if result != OK {
+				log::error!("starting aaudio stream failed: {}", result_text(api, result));
+				return Err("failed to start aaudio stream");
+			}
+		}
+
+		Ok(this)
+
+	}
+
+}
+
+// the pointers are only used by the thread that owns the stream, and
+// by the callbacks, which AAudio synchronizes with `close`
+unsafe impl Send for Stream {}
+
+impl Drop for Stream {
+	fn drop (&mut self) {
+		// SAFETY: closing stops the callbacks, so `data` is not used after it
+		unsafe {
+			(self.api.AAudioStream_close)(self.stream);
+			drop(Box::from_raw(self.data));
+		}
+	}
+}
+
+
+
+enum StreamEvent {
+	RecreateStream,
+	Drop
+}
+
+
+
+/// keeps an AAudio stream playing the mixer, and opens a new one
+/// when the device disconnects, like when headphones are plugged
+pub struct Backend {
+
+	join: Option<std::thread::JoinHandle<()>>,
+	sender: Sender<StreamEvent>
+
+}
+
+impl Backend {
+
+	/// the output has `channels` channels, the mixer converts every
+	/// sound to them
+	pub fn start (
+		mixer: Arc<Mutex<Mixer>>,
+		performance_mode: PerformanceMode,
+		sharing_mode: SharingMode,
+		channels: u16
+	) -> Result<Self, &'static str> {
+
+		let (sender, receiver) = mpsc::channel();
+
+		// the first stream is opened here, so a device without
+		// aaudio is reported to the caller
+		let stream = Stream::open(&mixer, performance_mode, sharing_mode, channels, sender.clone())?;
+
+		let join = {
+			let sender = sender.clone();
+			std::thread::spawn(move || {
+				let mut stream = Some(stream);
+				while let Ok(event) = receiver.recv() {
+					match event {
+						StreamEvent::RecreateStream => {
+							log::debug!("recreating aaudio stream");
+							drop(stream.take());
+							match Stream::open(&mixer, performance_mode, sharing_mode, channels, sender.clone()) {
+								Ok(x) => stream = Some(x),
+								Err(err) => {
+									log::error!("recreating aaudio stream failed: {}", err);
+									return;
+								}
+							}
+						},
+						StreamEvent::Drop => return
+					}
+				}
+			})
+		};
+
+		Ok(Self {
+			join: Some(join),
+			sender
+		})
+
+	}
+
+}
+
+impl Drop for Backend {
+
+	fn drop (&mut self) {
+
+		self.sender.send(StreamEvent::Drop).unwrap();
+		self.join.take().unwrap().join().unwrap();
+
+	}
+
+}
+
+
+
+/// shared with the callbacks of an input stream
+struct InputData {
+	api: &'static Api,
+	/// set once the stream is open and its format known, before it
+	/// starts
+	writer: Option<TapWriter>,
+	channels: usize
+}
+
+
+
+extern "C" fn input_callback (_: *mut AAudioStream, user_data: *mut c_void, audio_data: *mut c_void, frames: i32) -> i32 {
+	// SAFETY: `user_data` is the `InputData` of the stream, freed after it is closed, and
+	// `audio_data` has `frames` frames of the i16 format the stream was opened with
+	let data = unsafe { &*(user_data as *const InputData) };
+	let buffer = unsafe { std::slice::from_raw_parts(audio_data as *const i16, frames as usize * data.channels) };
+	if let Some(writer) = &data.writer {
+		writer.write(buffer);
+	}
+	CALLBACK_RESULT_CONTINUE
+}
+
+
+extern "C" fn input_error_callback (_: *mut AAudioStream, user_data: *mut c_void, error: i32) {
+	// SAFETY: same as in `input_callback`
+	let data = unsafe { &*(user_data as *const InputData) };
+	log::error!("aaudio input stream error: {}", result_text(data.api, error));
+}
+
+
+
+/// records the default input device through an AAudio stream
+///
+/// unlike the output, a disconnected input is not reopened, as the
+/// new device may record in another format. the input stops recording
+pub struct InputBackend {
+	api: &'static Api,
+	stream: *mut AAudioStream,
+	data: *mut InputData
+}
+
+// same as for `Stream`
+unsafe impl Send for InputBackend {}
+
+impl InputBackend {
+
+	/// also returns if the device processes the voice
+	pub fn start (config: InputConfig) -> Result<(Self, Tap, bool), &'static str> {
+
+		let api = api().ok_or("aaudio is not available, it needs android 8.0")?;
+		let preset = api.AAudioStreamBuilder_setInputPreset.filter(|_| config.processed());
+		let data = Box::into_raw(Box::new(InputData { api, writer: None, channels: 0 }));
+
+		let mut builder = std::ptr::null_mut();
+		let mut stream = std::ptr::null_mut();
+		// SAFETY: the builder is used only between its creation and deletion, and `data` lives
+		// until the stream is closed by `Drop`
+		let result = unsafe {
+			let result = (api.AAudio_createStreamBuilder)(&mut builder);
+			if result != OK {
+				drop(Box::from_raw(data));
+				log::error!("creating aaudio stream builder failed: {}", result_text(api, result));
+				return Err("aaudio is not available");
+			}
+			(api.AAudioStreamBuilder_setDirection)(builder, DIRECTION_INPUT);
+			(api.AAudioStreamBuilder_setFormat)(builder, FORMAT_PCM_I16);
+			// asked, the device may record in another format, which
+			// the input converts
+			(api.AAudioStreamBuilder_setChannelCount)(builder, config.channels.max(1) as i32);
+			(api.AAudioStreamBuilder_setSampleRate)(builder, config.sample_rate as i32);
+			(api.AAudioStreamBuilder_setSharingMode)(builder, SHARING_MODE_SHARED);
+			if let Some(set_input_preset) = preset {
+				set_input_preset(builder, INPUT_PRESET_VOICE_COMMUNICATION);
+			}
+			(api.AAudioStreamBuilder_setDataCallback)(builder, input_callback, data as *mut c_void);
+			(api.AAudioStreamBuilder_setErrorCallback)(builder, input_error_callback, data as *mut c_void);
+			let result = (api.AAudioStreamBuilder_openStream)(builder, &mut stream);
+			(api.AAudioStreamBuilder_delete)(builder);
+			result
+		};
+		if result != OK {
+			// SAFETY: no stream was opened, so nothing else uses `data`
+			drop(unsafe { Box::from_raw(data) });
+			log::error!("opening aaudio input stream failed: {}", result_text(api, result));
+			return Err("failed to open aaudio input stream");
+		}
+		let this = Self { api, stream, data };
+
+		// SAFETY: `stream` is open until `Drop`, and the callbacks don't
+		// run before it is started, so `data` can be written
+		unsafe {
+			let sample_rate = (api.AAudioStream_getSampleRate)(stream);
+			let channels = (api.AAudioStream_getChannelCount)(stream);
+			let (writer, ring) = input::ring(config.buffer, channels as u16, sample_rate as u32);
+			(*data).writer = Some(writer);
+			(*data).channels = channels as usize;
+
+			log::info!("opened aaudio input stream: {}Hz, {} channels", sample_rate, channels);
+
+			let result = (api.AAudioStream_requestStart)(stream);
+			if result != OK {
+				log::error!("starting aaudio input stream failed: {}", result_text(api, result));
+				return Err("failed to start aaudio input stream");
+			}
+			Ok((this, ring, preset.is_some()))
+		}
+
+	}
+
+}
+
+impl Drop for InputBackend {
+	fn drop (&mut self) {
+		// SAFETY: closing stops the callbacks, so `data` is not used after it
+		unsafe {
+			(self.api.AAudioStream_close)(self.stream);
+			drop(Box::from_raw(self.data));
+		}
+	}
+}
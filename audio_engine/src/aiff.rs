@@ -0,0 +1,413 @@
+
+
+
+//! An AIFF and AIFF-C decoder.
+//!
+//! The samples are read as they are mixed, like a [`RawPcmDecoder`] at the offset of the `SSND`
+//! chunk. AIFF-C is read when it is uncompressed, in floats, or in a-law or µ-law, and the
+//! sustain loop of the `INST` chunk becomes the loop region.
+
+
+
+use std::io::{ self, Read, Seek, SeekFrom };
+
+use crate::mixer::SoundSource;
+use crate::pcm::{ PcmEncoding, PcmFormat, RawPcmDecoder };
+
+
+
+/// the longest chunk other than `SSND` that is read, bigger ones are
+/// skipped
+const MAX_CHUNK: usize = 1 << 16;
+
+
+
+fn invalid (message: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+
+/// Aiff File Decoder
+pub struct AiffDecoder <T: Seek + Read + Send + 'static> {
+
+	inner: RawPcmDecoder<T>,
+	loop_region: Option<(u64, u64)>
+
+}
+
+impl <T: Seek + Read + Send + 'static> AiffDecoder<T> {
+
+
+	/// Create a new aiff file decoder
+	///
+	/// the sustain loop of the file, if any, is its loop region, see
+	/// [`Sound::set_loop_region`](crate::Sound::set_loop_region)
+	pub fn new (mut data: T) -> io::Result<Self> {
+		let mut header = [0; 12];
+		data.read_exact(&mut header)?;
+		let compressed = match &header[8..12] {
+			_ if &header[0..4] != b"FORM" => return Err(invalid("not an aiff file")),
+			b"AIFF" => false,
+			b"AIFC" => true,
+			_ => return Err(invalid("not an aiff file"))
+		};
+
+		let mut format = None;
+		let mut frames = 0;
+		let mut sound = None;
+		let mut markers = vec![];
+		let mut sustain = None;
+		let mut chunk = [0; 8];
+		while data.read_exact(&mut chunk).is_ok() {
+			let id = &chunk[0..4];
+			// chunks are padded to an even length
+			let len = u32_at(&chunk, 4) as u64;
+			let padded = len + len % 2;
+			if id == b"SSND" {
+				let mut offset = [0; 8];
+				data.read_exact(&mut offset)?;
+				// the samples can start after some padding
+				let offset = u32_at(&offset, 0) as u64;
+				let start = data.stream_position()? + offset;
+				sound = Some((start, len.saturating_sub(8 + offset)));
+				data.seek(SeekFrom::Current(padded as i64 - 8))?;
+			} else if matches!(id, b"COMM" | b"MARK" | b"INST") && len as usize <= MAX_CHUNK {
+				let mut body = vec![0; padded as usize];
+				data.read_exact(&mut body)?;
+				if id == b"COMM" {
+					format = Some(read_format(&body, compressed)?);
+					frames = u32_at(&body, 2) as u64;
+				} else if id == b"MARK" {
+					markers = read_markers(&body);
+				} else if body.len() >= 14 && u16_at(&body, 8) != 0 {
+					// the play mode, then the markers of the start and the end
+					sustain = Some((u16_at(&body, 10), u16_at(&body, 12)));
+				}
+			} else {
+				data.seek(SeekFrom::Current(padded as i64))?;
+			}
+		}
+
+		let format = format.ok_or(invalid("no COMM chunk"))?;
+		let (start, len) = sound.ok_or(invalid("no SSND chunk"))?;
+		data.seek(SeekFrom::Start(start))?;
+		let marker = |id| markers.iter().find(|x: &&(u16, u64)| x.0 == id).map(|x| x.1);
+		Ok(Self {
+			inner: RawPcmDecoder::with_len(data, format, len.min(frames * format.frame_bytes() as u64))?,
+			loop_region: sustain
+				.and_then(|(start, end)| Some((marker(start)?, marker(end)?)))
+				.filter(|(start, end)| start < end)
+		})
+	}
+
+
+}
+
+#[cfg(all(target_os = "android", feature = "android-assets"))]
+impl AiffDecoder<crate::asset::Asset> {
+
+
+	/// Create a new aiff file decoder from an asset of the APK
+	///
+	/// `path` is relative to the assets folder, see
+	/// [`set_asset_manager`](crate::set_asset_manager)
+	pub fn from_asset (path: &str) -> io::Result<Self> {
+		Self::new(crate::asset::Asset::open(path)?)
+	}
+
+
+}
+
+impl <T: Seek + Read + Send + 'static> SoundSource for AiffDecoder<T> {
+
+
+	fn reset (&mut self) {
+		self.inner.reset()
+	}
+
+
+	fn channels (&self) -> u16 {
+		self.inner.channels()
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.inner.sample_rate()
+	}
+
+
+	fn total_frames (&self) -> Option<u64> {
+		self.inner.total_frames()
+	}
+
+
+	/// sample accurate
+	fn seek (&mut self, frame: u64) -> bool {
+		self.inner.seek(frame)
+	}
+
+
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		self.loop_region
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		self.inner.write_samples(buffer)
+	}
+
+
+}
+
+
+
+/// the format of a `COMM` chunk
+fn read_format (body: &[u8], compressed: bool) -> io::Result<PcmFormat> {
+	if body.len() < 18 || (compressed && body.len() < 22) {
+		return Err(invalid("COMM chunk too short"));
+	}
+	let channels = u16_at(body, 0);
+	let bits = u16_at(body, 6);
+	let sample_rate = extended_at(body, 8);
+	let int = match bits {
+		1..=8 => PcmEncoding::I8,
+		9..=16 => PcmEncoding::I16,
+		17..=24 => PcmEncoding::I24,
+		_ => PcmEncoding::I32
+	};
+	let compression = if compressed { &body[18..22] } else { b"NONE" };
+	let (encoding, big_endian) = match compression {
+		b"NONE" | b"twos" => (int, true),
+		b"sowt" => (int, false),
+		b"raw " if bits <= 8 => (PcmEncoding::U8, true),
+		b"in24" => (PcmEncoding::I24, true),
+		b"in32" => (PcmEncoding::I32, true),
+		b"fl32" | b"FL32" => (PcmEncoding::F32, true),
+		b"fl64" | b"FL64" => (PcmEncoding::F64, true),
+		b"alaw" | b"ALAW" => (PcmEncoding::ALaw, true),
+		b"ulaw" | b"ULAW" => (PcmEncoding::MuLaw, true),
+		_ => return Err(invalid("unsupported aiff compression"))
+	};
+	if channels == 0 || sample_rate == 0 || bits == 0 || bits > 64 {
+		return Err(invalid("invalid aiff format"));
+	}
+	Ok(PcmFormat::new(encoding, channels, sample_rate).big_endian(big_endian))
+}
+
+
+
+/// the ids and frames of the markers of a `MARK` chunk
+fn read_markers (body: &[u8]) -> Vec<(u16, u64)> {
+	let mut markers = vec![];
+	let mut index = 2;
+	for _ in 0..u16_at(body, 0) {
+		if index + 7 > body.len() {
+			break;
+		}
+		markers.push((u16_at(body, index), u32_at(body, index + 2) as u64));
+		// the name is a pascal string, padded to an even length
+		let name = body[index + 6] as usize + 1;
+		index += 6 + name + name % 2;
+	}
+	markers
+}
+
+
+
+fn u16_at (bytes: &[u8], index: usize) -> u16 {
+	bytes.get(index..index + 2).map_or(0, |x| u16::from_be_bytes(x.try_into().unwrap()))
+}
+
+
+
+fn u32_at (bytes: &[u8], index: usize) -> u32 {
+	bytes.get(index..index + 4).map_or(0, |x| u32::from_be_bytes(x.try_into().unwrap()))
+}
+
+
+
+/// the 80 bit float of the sample rate, rounded
+pub (crate) fn extended_at (bytes: &[u8], index: usize) -> u32 {
+	let exponent = (u16_at(bytes, index) & 0x7FFF) as i32;
+	let mantissa = (u32_at(bytes, index + 2) as u64) << 32 | u32_at(bytes, index + 6) as u64;
+	(mantissa as f64 * 2f64.powi(exponent - 16383 - 63)).round() as u32
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::io::Cursor;
+
+	use super::*;
+
+
+	/// the 80 bit float of `rate`
+	fn extended (rate: f64) -> [u8; 10] {
+		let exponent = rate.log2().floor() as i32;
+		let mantissa = (rate / 2f64.powi(exponent - 63)) as u64;
+		let mut bytes = [0; 10];
+		bytes[..2].copy_from_slice(&((exponent + 16383) as u16).to_be_bytes());
+		bytes[2..].copy_from_slice(&mantissa.to_be_bytes());
+		bytes
+	}
+
+
+	fn comm (channels: u16, frames: u32, bits: u16, rate: [u8; 10], compression: Option<&[u8; 4]>) -> Vec<u8> {
+		let mut body = vec![];
+		body.extend_from_slice(&channels.to_be_bytes());
+		body.extend_from_slice(&frames.to_be_bytes());
+		body.extend_from_slice(&bits.to_be_bytes());
+		body.extend_from_slice(&rate);
+		if let Some(compression) = compression {
+			body.extend_from_slice(compression);
+			// an empty pascal string for the name
+			body.extend_from_slice(&[0, 0]);
+		}
+		body
+	}
+
+
+	fn ssnd (samples: &[u8]) -> Vec<u8> {
+		let mut body = vec![0; 8];
+		body.extend_from_slice(samples);
+		body
+	}
+
+
+	fn aiff (kind: &[u8; 4], chunks: &[(&[u8; 4], Vec<u8>)]) -> io::Result<AiffDecoder<Cursor<Vec<u8>>>> {
+		let mut body = kind.to_vec();
+		for (id, data) in chunks {
+			body.extend_from_slice(*id);
+			body.extend_from_slice(&(data.len() as u32).to_be_bytes());
+			body.extend_from_slice(data);
+			if data.len() % 2 == 1 {
+				body.push(0);
+			}
+		}
+		let mut file = b"FORM".to_vec();
+		file.extend_from_slice(&(body.len() as u32).to_be_bytes());
+		file.extend_from_slice(&body);
+		AiffDecoder::new(Cursor::new(file))
+	}
+
+
+	fn read_all (decoder: &mut impl SoundSource) -> Vec<i16> {
+		let mut samples = vec![];
+		let mut buffer = [0; 4];
+		loop {
+			let len = decoder.write_samples(&mut buffer);
+			samples.extend_from_slice(&buffer[..len]);
+			if len < buffer.len() {
+				return samples;
+			}
+		}
+	}
+
+
+	#[test]
+	fn sample_rate () {
+		// 44100 and 48000 as the encoders write them
+		assert_eq!(extended_at(&[0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0], 0), 44100);
+		assert_eq!(extended_at(&[0x40, 0x0E, 0xBB, 0x80, 0, 0, 0, 0, 0, 0], 0), 48000);
+		for rate in [8000, 11025, 22050, 96000, 192000] {
+			assert_eq!(extended_at(&extended(rate as f64), 0), rate);
+		}
+		// rounded to the closest rate
+		assert_eq!(extended_at(&extended(44099.6), 0), 44100);
+		assert_eq!(extended_at(&[0; 10], 0), 0);
+	}
+
+
+	#[test]
+	fn zero_sample_rate () {
+		let chunks = [(b"COMM", comm(1, 1, 16, [0; 10], None)), (b"SSND", ssnd(&[0, 1]))];
+		assert!(aiff(b"AIFF", &chunks).is_err());
+	}
+
+
+	#[test]
+	fn big_endian_pcm () {
+		let chunks = [(b"COMM", comm(2, 2, 16, extended(44100.0), None)), (b"SSND", ssnd(&[0x12, 0x34, 0xFF, 0xFE, 0x80, 0x00, 0x7F, 0xFF]))];
+		let mut decoder = aiff(b"AIFF", &chunks).unwrap();
+		assert_eq!((decoder.channels(), decoder.sample_rate(), decoder.total_frames()), (2, 44100, Some(2)));
+		assert_eq!(read_all(&mut decoder), [0x1234, -2, i16::MIN, i16::MAX]);
+	}
+
+
+	#[test]
+	fn compressions () {
+		let cases = [
+			(b"NONE", 16, vec![0x12, 0x34, 0x80, 0x00], [0x1234, i16::MIN]),
+			(b"twos", 8, vec![0x12, 0x80], [0x1200, i16::MIN]),
+			(b"sowt", 16, vec![0x34, 0x12, 0x00, 0x80], [0x1234, i16::MIN]),
+			(b"raw ", 8, vec![0x80, 0x00], [0, i16::MIN]),
+			(b"in24", 24, vec![0x12, 0x34, 0x56, 0x80, 0x00, 0x00], [0x1234, i16::MIN]),
+			(b"in32", 32, vec![0x12, 0x34, 0x56, 0x78, 0x80, 0, 0, 0], [0x1234, i16::MIN]),
+			(b"fl32", 32, [0.5f32, -1.0].iter().flat_map(|x| x.to_be_bytes()).collect(), [16383, i16::MIN]),
+			(b"fl64", 64, [0.5f64, -1.0].iter().flat_map(|x| x.to_be_bytes()).collect(), [16383, i16::MIN]),
+			(b"ulaw", 8, vec![0xFF, 0x80], [0, 32124])
+		];
+		for (compression, bits, samples, expected) in cases {
+			let chunks = [(b"COMM", comm(1, 2, bits, extended(22050.0), Some(compression))), (b"SSND", ssnd(&samples))];
+			let mut decoder = aiff(b"AIFC", &chunks).unwrap();
+			assert_eq!(read_all(&mut decoder), expected, "{}", String::from_utf8_lossy(compression));
+		}
+		let chunks = [(b"COMM", comm(1, 2, 8, extended(22050.0), Some(b"alaw"))), (b"SSND", ssnd(&[0xD5, 0x2A]))];
+		assert_eq!(read_all(&mut aiff(b"AIFC", &chunks).unwrap()), [8, -32256]);
+		let chunks = [(b"COMM", comm(1, 2, 16, extended(22050.0), Some(b"ima4"))), (b"SSND", ssnd(&[0; 4]))];
+		assert!(aiff(b"AIFC", &chunks).is_err());
+	}
+
+
+	/// a `MARK` chunk of `(id, frame, name)`
+	fn mark (markers: &[(u16, u32, &str)]) -> Vec<u8> {
+		let mut body = (markers.len() as u16).to_be_bytes().to_vec();
+		for (id, frame, name) in markers {
+			body.extend_from_slice(&id.to_be_bytes());
+			body.extend_from_slice(&frame.to_be_bytes());
+			body.push(name.len() as u8);
+			body.extend_from_slice(name.as_bytes());
+			if name.len() % 2 == 0 {
+				body.push(0);
+			}
+		}
+		body
+	}
+
+
+	/// an `INST` chunk with the sustain loop between the markers
+	fn inst (play_mode: u16, start: u16, end: u16) -> Vec<u8> {
+		let mut body = vec![60, 0, 0, 127, 0, 127, 0, 0];
+		body.extend_from_slice(&play_mode.to_be_bytes());
+		body.extend_from_slice(&start.to_be_bytes());
+		body.extend_from_slice(&end.to_be_bytes());
+		// the release loop
+		body.extend_from_slice(&[0; 6]);
+		body
+	}
+
+
+	#[test]
+	fn sustain_loop () {
+		let samples = ssnd(&[0; 40]);
+		let markers = mark(&[(1, 4, "start"), (7, 15, "end"), (3, 8, "odd")]);
+		let chunks = [(b"COMM", comm(1, 20, 16, extended(8000.0), None)), (b"MARK", markers.clone()), (b"INST", inst(1, 1, 7)), (b"SSND", samples.clone())];
+		assert_eq!(aiff(b"AIFF", &chunks).unwrap().loop_region(), Some((4, 15)));
+
+		// the markers of the loop can come after it
+		let chunks = [(b"COMM", comm(1, 20, 16, extended(8000.0), None)), (b"INST", inst(2, 3, 7)), (b"SSND", samples.clone()), (b"MARK", markers.clone())];
+		assert_eq!(aiff(b"AIFF", &chunks).unwrap().loop_region(), Some((8, 15)));
+
+		// no loop when it doesn't play, with a missing marker, or when
+		// it ends before it starts
+		for inst in [inst(0, 1, 7), inst(1, 1, 9), inst(1, 3, 1)] {
+			let chunks = [(b"COMM", comm(1, 20, 16, extended(8000.0), None)), (b"MARK", markers.clone()), (b"INST", inst), (b"SSND", samples.clone())];
+			assert_eq!(aiff(b"AIFF", &chunks).unwrap().loop_region(), None);
+		}
+	}
+
+
+}
@@ -0,0 +1,122 @@
+
+
+
+//! First order ambisonics, where the placed sounds are mixed into a sound field around the
+//! listener.
+//!
+//! Every placed sound is encoded into the four channels of B-format from its direction in the
+//! world. The field is then turned with the listener, and decoded to the speakers, or to
+//! headphones through six virtual speakers rendered with an [`Hrtf`].
+
+
+
+use crate::hrtf::{ Binaural, Hrtf };
+use crate::spatial::{ ListenerState, dot };
+
+
+
+/// the virtual speakers of the binaural decoding: front, back, left,
+/// right, top and bottom, as front, left and up
+const SPEAKERS: [[f32; 3]; 6] = [
+	[1.0, 0.0, 0.0],
+	[-1.0, 0.0, 0.0],
+	[0.0, 1.0, 0.0],
+	[0.0, -1.0, 0.0],
+	[0.0, 0.0, 1.0],
+	[0.0, 0.0, -1.0]
+];
+
+
+
+/// the sound field of the placed sounds, for one buffer
+pub (crate) struct Ambisonics {
+
+	/// W, X, Y and Z of every frame, along the axes of the world
+	field: Vec<f32>,
+	/// the binaural state of each virtual speaker
+	speakers: Vec<Binaural>,
+	/// the feed of one speaker, as 2 channels
+	scratch: Vec<f32>
+
+}
+
+impl Ambisonics {
+
+
+	pub fn new () -> Self {
+		Self {
+			field: vec![],
+			speakers: SPEAKERS.iter().map(|_| Binaural::new()).collect(),
+			scratch: vec![]
+		}
+	}
+
+
+	/// start an empty field of `frames`
+	pub fn clear (&mut self, frames: usize) {
+		self.field.clear();
+		self.field.resize(frames * 4, 0.0);
+	}
+
+
+	/// add the sound in `samples` from `direction`, a unit vector of
+	/// the world. its channels are mixed to mono
+	pub fn encode (&mut self, samples: &[f32], channels: u16, direction: [f32; 3]) {
+		for (frame, field) in samples.chunks_exact(channels as usize).zip(self.field.chunks_exact_mut(4)) {
+			let x = frame.iter().sum::<f32>() / channels as f32;
+			field[0] += x;
+			field[1] += x * direction[0];
+			field[2] += x * direction[1];
+			field[3] += x * direction[2];
+		}
+	}
+
+
+	/// turn the field with `listener`, and add it to `mix`
+	///
+	/// a stereo output is decoded with `hrtf` when there is one, with
+	/// two cardioids to the sides in the front left and right channels
+	/// otherwise. a mono output only gets the omnidirectional part
+	pub fn decode (&mut self, listener: &ListenerState, hrtf: Option<&Hrtf>, mix: &mut [f32], channels: u16) {
+		let (forward, right, up) = listener.basis();
+		let left = [-right[0], -right[1], -right[2]];
+		// X to the front, Y to the left and Z to the top of the listener
+		for field in self.field.chunks_exact_mut(4) {
+			let world = [field[1], field[2], field[3]];
+			field[1] = dot(world, forward);
+			field[2] = dot(world, left);
+			field[3] = dot(world, up);
+		}
+
+		match (hrtf, channels) {
+			(Some(hrtf), 2) => {
+				for (speaker, binaural) in SPEAKERS.iter().zip(self.speakers.iter_mut()) {
+					// a basic decoder, the feeds of a sound add up to it
+					self.scratch.clear();
+					for field in self.field.chunks_exact(4) {
+						let feed = (field[0] + 3.0 * (speaker[0] * field[1] + speaker[1] * field[2] + speaker[2] * field[3])) / 6.0;
+						self.scratch.extend([feed, feed]);
+					}
+					// the hrtf takes right, up and front
+					binaural.process(hrtf, &mut self.scratch, [-speaker[1], speaker[2], speaker[0]]);
+					for (b, x) in mix.iter_mut().zip(&self.scratch) {
+						*b += x;
+					}
+				}
+			},
+			(_, 2..) => {
+				for (frame, field) in mix.chunks_exact_mut(channels as usize).zip(self.field.chunks_exact(4)) {
+					frame[0] += (field[0] + field[2]) * 0.5;
+					frame[1] += (field[0] - field[2]) * 0.5;
+				}
+			},
+			_ => {
+				for (frame, field) in mix.chunks_exact_mut(channels as usize).zip(self.field.chunks_exact(4)) {
+					frame.iter_mut().for_each(|x| *x += field[0]);
+				}
+			}
+		}
+	}
+
+
+}
@@ -0,0 +1,131 @@
+
+
+
+//! Reading sounds from the assets of an Android APK.
+//!
+//! Assets are not files, so they are read through the NDK's AAssetManager. The manager is
+//! owned by the activity, so the game has to hand it over once with [`set_asset_manager`].
+
+
+
+use std::ffi::{ c_char, c_int, c_void, CString };
+use std::io::{ self, Read, Seek, SeekFrom };
+use std::ptr;
+use std::sync::atomic::{ AtomicPtr, Ordering };
+
+
+
+/// AASSET_MODE_RANDOM, the sounds seek
+const MODE_RANDOM: c_int = 1;
+
+const SEEK_SET: c_int = 0;
+const SEEK_CUR: c_int = 1;
+const SEEK_END: c_int = 2;
+
+
+
+#[repr(C)]
+struct AAssetManager {
+	_private: [u8; 0]
+}
+
+#[repr(C)]
+struct AAsset {
+	_private: [u8; 0]
+}
+
+#[link(name = "android")]
+extern "C" {
+	fn AAssetManager_open (manager: *mut AAssetManager, filename: *const c_char, mode: c_int) -> *mut AAsset;
+	fn AAsset_read (asset: *mut AAsset, buffer: *mut c_void, count: usize) -> c_int;
+	fn AAsset_seek64 (asset: *mut AAsset, offset: i64, whence: c_int) -> i64;
+	fn AAsset_close (asset: *mut AAsset);
+}
+
+
+
+static ASSET_MANAGER: AtomicPtr<AAssetManager> = AtomicPtr::new(ptr::null_mut());
+
+
+
+/// set the AAssetManager that [`Asset::open`] reads from
+///
+/// with android-activity this is
+/// `app.asset_manager().ptr().as_ptr() as *mut c_void`
+///
+/// # Safety
+///
+/// `manager` must be a valid `AAssetManager*` for as long as
+/// assets are opened, usually the lifetime of the activity
+pub unsafe fn set_asset_manager (manager: *mut c_void) {
+	ASSET_MANAGER.store(manager as *mut AAssetManager, Ordering::Release);
+}
+
+
+
+/// an asset of the APK, opened for reading
+///
+/// implements `Read` and `Seek`, so it can be given to any decoder
+pub struct Asset {
+	asset: *mut AAsset
+}
+
+// an AAsset can be used from any thread, as long as it is not used
+// from two threads at once, which `&mut self` already ensures
+unsafe impl Send for Asset {}
+
+impl Asset {
+
+
+	/// open the asset at `path`, relative to the assets folder
+	pub fn open (path: &str) -> io::Result<Self> {
+		let manager = ASSET_MANAGER.load(Ordering::Acquire);
+		if manager.is_null() {
+			return Err(io::Error::other("set_asset_manager was not called"));
+		}
+		let path = CString::new(path).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+		// SAFETY: `manager` is valid by the contract of `set_asset_manager`
+		let asset = unsafe { AAssetManager_open(manager, path.as_ptr(), MODE_RANDOM) };
+		if asset.is_null() {
+			return Err(io::Error::new(io::ErrorKind::NotFound, "asset not found"));
+		}
+		Ok(Self { asset })
+	}
+
+
+}
+
+impl Read for Asset {
+	fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		// SAFETY: `asset` is open until drop, and `buf` is valid for its length
+		let len = unsafe { AAsset_read(self.asset, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+		if len < 0 {
+			return Err(io::Error::other("error while reading asset"));
+		}
+		Ok(len as usize)
+	}
+}
+
+impl Seek for Asset {
+	fn seek (&mut self, pos: SeekFrom) -> io::Result<u64> {
+		let (offset, whence) = match pos {
+			SeekFrom::Start(x) => (x as i64, SEEK_SET),
+			SeekFrom::Current(x) => (x, SEEK_CUR),
+			SeekFrom::End(x) => (x, SEEK_END)
+		};
+		// SAFETY: `asset` is open until drop
+		let pos = unsafe { AAsset_seek64(self.asset, offset, whence) };
+		if pos < 0 {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek in asset"));
+		}
+		Ok(pos as u64)
+	}
+}
+
+impl Drop for Asset {
+	fn drop (&mut self) {
+		// SAFETY: `asset` was opened by `AAssetManager_open`, and is not used after this
+		unsafe { AAsset_close(self.asset) };
+	}
+}
@@ -0,0 +1,511 @@
+
+
+
+//! Sound banks, events described in a JSON file so they can be tweaked without building the game.
+//!
+//! Every file of a bank is decoded once when it is loaded, even when many events use it. The
+//! JSON reader only knows what a bank needs: it reads any JSON, but numbers are `f64` and the
+//! keys an event doesn't use are ignored.
+
+
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{ Path, PathBuf };
+
+use crate::aiff::AiffDecoder;
+use crate::mixer::GroupId;
+use crate::random::RandomSound;
+use crate::reader::SeekBufReader;
+use crate::sound_data::SoundData;
+use crate::spatial::{ Attenuation, AttenuationModel };
+use crate::wav::WavDecoder;
+
+
+
+/// how deep arrays and objects are nested before the bank is
+/// rejected, so a broken file can't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+
+
+/// an event of a bank, played with
+/// [`AudioEngine::post_event`](crate::AudioEngine::post_event)
+pub (crate) struct BankEvent {
+	pub sound: RandomSound,
+	pub group: Option<GroupId>,
+	pub looping: bool,
+	pub attenuation: Option<Attenuation>
+}
+
+
+
+/// the events of a bank
+pub (crate) type Events = Vec<(String, BankEvent)>;
+
+
+
+/// read the events of the bank at `path`, the groups are found or
+/// made by `group`
+///
+/// also returns the files that were decoded
+pub (crate) fn load (path: &Path, mut group: impl FnMut(&str) -> GroupId) -> Result<(Events, Vec<PathBuf>), &'static str> {
+	let text = std::fs::read_to_string(path).map_err(|_| "the bank can't be read")?;
+	let json = Parser { bytes: text.as_bytes(), index: 0 }.parse().ok_or("the bank is not valid json")?;
+	let Some(Json::Object(events)) = json.get("events") else {
+		return Err("the bank has no events");
+	};
+	// the files are relative to the bank
+	let directory = path.parent().unwrap_or(Path::new(""));
+	let mut decoded: HashMap<String, SoundData> = HashMap::new();
+	let mut files = vec![];
+
+	let mut bank = vec![];
+	for (name, event) in events {
+		let sources = match (event.get("sources"), event.get("source")) {
+			(Some(Json::Array(x)), _) => x.iter().collect(),
+			(_, Some(source)) => vec![source],
+			_ => vec![]
+		};
+		if sources.is_empty() {
+			return Err("an event of the bank has no sources");
+		}
+		let mut sound = RandomSound::new();
+		for source in sources {
+			// a file, or an object with a file and a weight
+			let (file, weight) = match source {
+				Json::String(file) => (file, 1.0),
+				_ => (
+					source.get("file").and_then(Json::as_str).ok_or("a source of the bank has no file")?,
+					source.get("weight").and_then(Json::as_f32).unwrap_or(1.0)
+				)
+			};
+			let data = match decoded.get(file) {
+				Some(data) => data.clone(),
+				None => {
+					let file_path = directory.join(file);
+					let data = decode(&file_path)?;
+					decoded.insert(file.clone(), data.clone());
+					files.push(file_path);
+					data
+				}
+			};
+			sound = sound.add(data, weight);
+		}
+		if let Some((min, max)) = event.get("volume").and_then(Json::as_range) {
+			sound = sound.volume_range(min, max);
+		}
+		if let Some((min, max)) = event.get("pitch").and_then(Json::as_range) {
+			sound = sound.pitch_range(min, max);
+		}
+		let attenuation = match event.get("spatial") {
+			Some(spatial) => Some(read_attenuation(spatial)?),
+			None => None
+		};
+		bank.push((name.clone(), BankEvent {
+			sound,
+			group: event.get("group").and_then(Json::as_str).map(|x| group(x)),
+			looping: event.get("loop").and_then(Json::as_bool).unwrap_or(false),
+			attenuation
+		}));
+	}
+	Ok((bank, files))
+}
+
+
+fn read_attenuation (spatial: &Json) -> Result<Attenuation, &'static str> {
+	let default = Attenuation::default();
+	let rolloff = spatial.get("rolloff").and_then(Json::as_f32).unwrap_or(1.0);
+	let model = match spatial.get("model").and_then(Json::as_str).map(|x| x.as_str()) {
+		None => default.model,
+		Some("linear") => AttenuationModel::Linear,
+		Some("inverse") => AttenuationModel::Inverse { rolloff },
+		Some("exponential") => AttenuationModel::Exponential { rolloff },
+		Some(_) => return Err("an attenuation model of the bank is not linear, inverse or exponential")
+	};
+	Ok(Attenuation {
+		model,
+		min_distance: spatial.get("min_distance").and_then(Json::as_f32).unwrap_or(default.min_distance),
+		max_distance: spatial.get("max_distance").and_then(Json::as_f32).unwrap_or(default.max_distance)
+	})
+}
+
+
+/// decode a file of the bank, by its extension
+fn decode (path: &Path) -> Result<SoundData, &'static str> {
+	let open = || File::open(path).and_then(SeekBufReader::new).map_err(|_| "a file of the bank can't be opened");
+	let invalid = "a file of the bank can't be decoded";
+	match path.extension().and_then(|x| x.to_str()) {
+		Some("wav") => WavDecoder::new(open()?).map(SoundData::decode).map_err(|_| invalid),
+		Some("aif" | "aiff" | "aifc") => AiffDecoder::new(open()?).map(SoundData::decode).map_err(|_| invalid),
+		#[cfg(feature = "ogg")]
+		Some("ogg") => crate::ogg::OggDecoder::new(open()?).map(SoundData::decode).map_err(|_| invalid),
+		#[cfg(feature = "flac")]
+		Some("flac") => crate::flac::FlacDecoder::new(open()?).map(SoundData::decode).map_err(|_| invalid),
+		#[cfg(feature = "symphonia")]
+		Some(extension) => {
+			crate::universal::SymphoniaDecoder::with_extension(open()?, extension).map(SoundData::decode).map_err(|_| invalid)
+		},
+		_ => Err("a file of the bank is not in a format that is enabled")
+	}
+}
+
+
+
+#[derive(Debug, PartialEq)]
+enum Json {
+	Null,
+	Bool(bool),
+	Number(f64),
+	String(String),
+	Array(Vec<Json>),
+	/// in the order of the file
+	Object(Vec<(String, Json)>)
+}
+
+impl Json {
+
+
+	fn get (&self, key: &str) -> Option<&Json> {
+		match self {
+			Json::Object(x) => x.iter().find(|x| x.0 == key).map(|x| &x.1),
+			_ => None
+		}
+	}
+
+
+	fn as_str (&self) -> Option<&String> {
+		match self {
+			Json::String(x) => Some(x),
+			_ => None
+		}
+	}
+
+
+	fn as_f32 (&self) -> Option<f32> {
+		match self {
+			Json::Number(x) => Some(*x as f32),
+			_ => None
+		}
+	}
+
+
+	fn as_bool (&self) -> Option<bool> {
+		match self {
+			Json::Bool(x) => Some(*x),
+			_ => None
+		}
+	}
+
+
+	/// `[min, max]`, or a number for both
+	fn as_range (&self) -> Option<(f32, f32)> {
+		match self {
+			Json::Array(x) if x.len() == 2 => Some((x[0].as_f32()?, x[1].as_f32()?)),
+			_ => self.as_f32().map(|x| (x, x))
+		}
+	}
+
+
+}
+
+
+
+/// reads json from `bytes`, `None` if it is not valid
+struct Parser<'a> {
+	bytes: &'a [u8],
+	index: usize
+}
+
+impl Parser<'_> {
+
+
+	fn parse (mut self) -> Option<Json> {
+		let value = self.value(0)?;
+		self.skip_whitespace();
+		(self.index == self.bytes.len()).then_some(value)
+	}
+
+
+	fn skip_whitespace (&mut self) {
+		while self.bytes.get(self.index).is_some_and(|x| x.is_ascii_whitespace()) {
+			self.index += 1;
+		}
+	}
+
+
+	/// skip the whitespace and take `byte`, if it is next
+	fn eat (&mut self, byte: u8) -> bool {
+		self.skip_whitespace();
+		let found = self.bytes.get(self.index) == Some(&byte);
+		if found {
+			self.index += 1;
+		}
+		found
+	}
+
+
+	fn literal (&mut self, literal: &str, value: Json) -> Option<Json> {
+		let found = self.bytes[self.index..].starts_with(literal.as_bytes());
+		self.index += literal.len();
+		found.then_some(value)
+	}
+
+
+	fn value (&mut self, depth: usize) -> Option<Json> {
+		if depth > MAX_DEPTH {
+			return None;
+		}
+		self.skip_whitespace();
+		match *self.bytes.get(self.index)? {
+			b'n' => self.literal("null", Json::Null),
+			b't' => self.literal("true", Json::Bool(true)),
+			b'f' => self.literal("false", Json::Bool(false)),
+			b'"' => self.string().map(Json::String),
+			b'[' => {
+				self.index += 1;
+				let mut array = vec![];
+				if !self.eat(b']') {
+					loop {
+						array.push(self.value(depth + 1)?);
+						if self.eat(b']') {
+							break;
+						}
+						if !self.eat(b',') {
+							return None;
+						}
+					}
+				}
+				Some(Json::Array(array))
+			},
+			b'{' => {
+				self.index += 1;
+				let mut object = vec![];
+				if !self.eat(b'}') {
+					loop {
+						self.skip_whitespace();
+						let key = self.string()?;
+						if !self.eat(b':') {
+							return None;
+						}
+						object.push((key, self.value(depth + 1)?));
+						if self.eat(b'}') {
+							break;
+						}
+						if !self.eat(b',') {
+							return None;
+						}
+					}
+				}
+				Some(Json::Object(object))
+			},
+			_ => {
+				let start = self.index;
+				while self.bytes.get(self.index).is_some_and(|x| matches!(x, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')) {
+					self.index += 1;
+				}
+				std::str::from_utf8(&self.bytes[start..self.index]).ok()?.parse().ok().map(Json::Number)
+			}
+		}
+	}
+
+
+	fn string (&mut self) -> Option<String> {
+		if self.bytes.get(self.index) != Some(&b'"') {
+			return None;
+		}
+		self.index += 1;
+		let mut string = vec![];
+		loop {
+			let byte = *self.bytes.get(self.index)?;
+			self.index += 1;
+			match byte {
+				b'"' => break,
+				b'\\' => {
+					let escaped = *self.bytes.get(self.index)?;
+					self.index += 1;
+					let x = match escaped {
+						b'"' | b'\\' | b'/' => escaped as char,
+						b'b' => '\u{8}',
+						b'f' => '\u{c}',
+						b'n' => '\n',
+						b'r' => '\r',
+						b't' => '\t',
+						b'u' => {
+							let hex = self.bytes.get(self.index..self.index + 4)?;
+							if !hex.iter().all(u8::is_ascii_hexdigit) {
+								return None;
+							}
+							let hex = std::str::from_utf8(hex).ok()?;
+							self.index += 4;
+							// the halves of a surrogate pair are not joined
+							char::from_u32(u32::from_str_radix(hex, 16).ok()?).unwrap_or(char::REPLACEMENT_CHARACTER)
+						},
+						_ => return None
+					};
+					string.extend_from_slice(x.encode_utf8(&mut [0; 4]).as_bytes());
+				},
+				_ => string.push(byte)
+			}
+		}
+		String::from_utf8(string).ok()
+	}
+
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::path::PathBuf;
+
+	use crate::mixer::{ Mixer, SampleRate };
+	use crate::spatial::{ Attenuation, AttenuationModel };
+	use super::{ Json, MAX_DEPTH, Parser, load, read_attenuation };
+
+
+	/// `text` read as json
+	fn parse (text: &str) -> Option<Json> {
+		Parser { bytes: text.as_bytes(), index: 0 }.parse()
+	}
+
+
+	/// a directory of its own for the files of a test
+	fn directory (name: &str) -> PathBuf {
+		let directory = std::env::temp_dir().join(format!("audio_engine_bank_{}_{}", name, std::process::id()));
+		std::fs::create_dir_all(&directory).unwrap();
+		directory
+	}
+
+
+	/// load the bank `json` of the test `name`, with a short wav file
+	/// as `beep.wav` next to it
+	fn load_bank (name: &str, json: &str) -> Result<super::Events, &'static str> {
+		let directory = directory(name);
+		let spec = hound::WavSpec { channels: 1, sample_rate: 8000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+		let mut writer = hound::WavWriter::create(directory.join("beep.wav"), spec).unwrap();
+		for i in 0..16 {
+			writer.write_sample(i as i16 * 100).unwrap();
+		}
+		writer.finalize().unwrap();
+		let path = directory.join("bank.json");
+		std::fs::write(&path, json).unwrap();
+		let mut mixer = Mixer::new(2, SampleRate(48000));
+		let result = load(&path, |x| mixer.find_group(x).unwrap_or_else(|| mixer.add_group(x, None)));
+		std::fs::remove_dir_all(directory).unwrap();
+		result.map(|x| x.0)
+	}
+
+
+	#[test]
+	fn values () {
+		let json = parse(r#" { "a": [ null, true, false, "x" ], "b": {}, "c": [] } "#).unwrap();
+		assert_eq!(json, Json::Object(vec![
+			("a".to_owned(), Json::Array(vec![Json::Null, Json::Bool(true), Json::Bool(false), Json::String("x".to_owned())])),
+			("b".to_owned(), Json::Object(vec![])),
+			("c".to_owned(), Json::Array(vec![]))
+		]));
+		// the first of the same key is found
+		assert_eq!(parse(r#"{ "a": 1, "a": 2 }"#).unwrap().get("a"), Some(&Json::Number(1.0)));
+	}
+
+
+	#[test]
+	fn numbers () {
+		for (text, x) in [("0", 0.0), ("-1.5", -1.5), ("1e3", 1000.0), ("2.5E-2", 0.025), ("12", 12.0)] {
+			assert_eq!(parse(text), Some(Json::Number(x)), "{}", text);
+		}
+		for text in ["", "-", "1.2.3", "1e", "0x10", "inf", "NaN", "--1"] {
+			assert_eq!(parse(text), None, "{}", text);
+		}
+	}
+
+
+	#[test]
+	fn strings () {
+		let string = |text| match parse(text) {
+			Some(Json::String(x)) => Some(x),
+			_ => None
+		};
+		assert_eq!(string(r#""a\"b\\c\/d\n\t\r\b\f""#).as_deref(), Some("a\"b\\c/d\n\t\r\u{8}\u{c}"));
+		assert_eq!(string(r#""\u00e9\u20AC""#).as_deref(), Some("é€"));
+		// not escaped, as utf-8
+		assert_eq!(string("\"é\"").as_deref(), Some("é"));
+		// the halves of a surrogate pair are not joined
+		assert_eq!(string(r#""\ud83d\ude00""#).as_deref(), Some("\u{fffd}\u{fffd}"));
+		for text in [r#""\u12""#, r#""\uzzzz""#, r#""\u+123""#, r#""\x""#, r#""open"#, r#""\"#] {
+			assert_eq!(string(text), None, "{}", text);
+		}
+	}
+
+
+	#[test]
+	fn invalid () {
+		for text in ["{} x", "1 2", "[1,]", "[1 2]", r#"{"a":1,}"#, r#"{"a" 1}"#, "{1: 2}", "nul", "truex", "[", "}"] {
+			assert_eq!(parse(text), None, "{}", text);
+		}
+	}
+
+
+	#[test]
+	fn depth () {
+		let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+		assert!(parse(&nested(MAX_DEPTH + 1)).is_some());
+		assert!(parse(&nested(MAX_DEPTH + 2)).is_none());
+		// deep enough to overflow the stack without the limit
+		assert!(parse(&nested(1_000_000)).is_none());
+	}
+
+
+	#[test]
+	fn attenuation () {
+		let attenuation = |text| read_attenuation(&parse(text).unwrap());
+		assert_eq!(attenuation("{}"), Ok(Attenuation::default()));
+		assert_eq!(attenuation(r#"{ "model": "linear", "min_distance": 2, "max_distance": 20 }"#), Ok(Attenuation {
+			model: AttenuationModel::Linear,
+			min_distance: 2.0,
+			max_distance: 20.0
+		}));
+		assert_eq!(attenuation(r#"{ "model": "exponential", "rolloff": 0.5 }"#).unwrap().model, AttenuationModel::Exponential { rolloff: 0.5 });
+		assert_eq!(attenuation(r#"{ "rolloff": 2 }"#).unwrap().model, AttenuationModel::Inverse { rolloff: 1.0 });
+		assert!(attenuation(r#"{ "model": "cubic" }"#).is_err());
+	}
+
+
+	#[test]
+	fn events () {
+		let events = load_bank("events", r#"{ "events": {
+			"jump": { "sources": [ "beep.wav", { "file": "beep.wav", "weight": 0.5 } ], "group": "sfx", "volume": [ 0.5, 1 ] },
+			"music": { "source": "beep.wav", "loop": true, "group": "music", "pitch": 2 },
+			"step": { "source": "beep.wav", "group": "sfx", "spatial": { "model": "linear" } }
+		} }"#).unwrap();
+		let names: Vec<_> = events.iter().map(|x| x.0.as_str()).collect();
+		assert_eq!(names, ["jump", "music", "step"]);
+		assert_eq!(events[0].1.group, events[2].1.group);
+		assert_ne!(events[0].1.group, events[1].1.group);
+		assert!(events[1].1.looping && !events[0].1.looping);
+		assert_eq!(events[2].1.attenuation.as_ref().map(|x| &x.model), Some(&AttenuationModel::Linear));
+		assert!(events[0].1.attenuation.is_none());
+	}
+
+
+	#[test]
+	fn errors () {
+		let error = |name, json| load_bank(name, json).err();
+		assert_eq!(error("json", "{ events: {} }"), Some("the bank is not valid json"));
+		assert_eq!(error("no_events", r#"{ "sounds": {} }"#), Some("the bank has no events"));
+		assert_eq!(error("no_sources", r#"{ "events": { "a": { "sources": [] } } }"#), Some("an event of the bank has no sources"));
+		assert_eq!(error("no_file", r#"{ "events": { "a": { "source": { "weight": 1 } } } }"#), Some("a source of the bank has no file"));
+		assert_eq!(error("missing", r#"{ "events": { "a": { "source": "missing.wav" } } }"#), Some("a file of the bank can't be opened"));
+		assert_eq!(
+			error("model", r#"{ "events": { "a": { "source": "beep.wav", "spatial": { "model": "cubic" } } } }"#),
+			Some("an attenuation model of the bank is not linear, inverse or exponential")
+		);
+		let missing = std::env::temp_dir().join("audio_engine_bank_that_does_not_exist.json");
+		assert_eq!(load(&missing, |_| unreachable!()).err(), Some("the bank can't be read"));
+	}
+
+
+}
@@ -0,0 +1,354 @@
+
+
+
+//! Beeps, morse code and telephone tones.
+//!
+//! A [`Beeper`] plays a pattern of beeps and pauses, written by hand or from morse code, and
+//! [`Dtmf`] the two tones of the keys of a telephone. Both are mono, end after their pattern, and
+//! fade each tone in and out over a couple of milliseconds so they don't click.
+
+
+
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+use crate::mixer::SoundSource;
+
+
+
+/// the sample rate of the patterns, unless set otherwise
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// the amplitude of the patterns, unless set otherwise, -6 dB
+const DEFAULT_AMPLITUDE: f32 = 0.5;
+
+/// in seconds, how long a tone fades in and out
+const FADE: f64 = 0.002;
+
+/// in seconds, a dot at 20 words per minute
+const MORSE_DOT: f64 = 0.06;
+
+/// the pitch of morse code, unless set otherwise
+const MORSE_FREQUENCY: f32 = 600.0;
+
+/// the rows and columns of the telephone keypad, and their tones
+const DTMF_KEYS: [&[u8; 4]; 4] = [b"123A", b"456B", b"789C", b"*0#D"];
+const DTMF_ROWS: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_COLUMNS: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+
+
+
+/// a tone of up to two frequencies, or a pause when there are none
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+	frequencies: [f32; 2],
+	/// in seconds
+	duration: f64
+}
+
+
+
+/// the segments of a pattern, and where they are played
+#[derive(Debug, Clone)]
+struct Pattern {
+
+	segments: Vec<Segment>,
+	amplitude: f32,
+	sample_rate: u32,
+	/// the segment playing, and the frame in it
+	index: usize,
+	frame: u64
+
+}
+
+impl Pattern {
+
+
+	fn new () -> Self {
+		Self {
+			segments: vec![],
+			amplitude: DEFAULT_AMPLITUDE,
+			sample_rate: DEFAULT_SAMPLE_RATE,
+			index: 0,
+			frame: 0
+		}
+	}
+
+
+	fn push (&mut self, frequencies: [f32; 2], duration: f64) {
+		self.segments.push(Segment { frequencies, duration });
+	}
+
+
+	fn frames (&self, segment: &Segment) -> u64 {
+		(segment.duration * self.sample_rate as f64).round() as u64
+	}
+
+
+	fn write (&mut self, buffer: &mut [i16]) -> usize {
+		let rate = self.sample_rate as f64;
+		let fade = FADE * rate;
+		let scale = self.amplitude.clamp(0.0, 1.0) * i16::MAX as f32;
+		let mut written = 0;
+		while written < buffer.len() {
+			let Some(&segment) = self.segments.get(self.index) else {
+				break;
+			};
+			let frames = self.frames(&segment);
+			let len = ((frames - self.frame.min(frames)) as usize).min(buffer.len() - written);
+			for x in buffer[written..written + len].iter_mut() {
+				// from the start of the segment, so seeking lands on the
+				// same samples
+				let time = self.frame as f64 / rate;
+				let gain = (self.frame as f64 / fade).min((frames - self.frame) as f64 / fade).min(1.0);
+				let [a, b] = segment.frequencies;
+				let tone = match (a > 0.0, b > 0.0) {
+					(true, true) => ((TAU * a as f64 * time).sin() + (TAU * b as f64 * time).sin()) * 0.5,
+					(true, false) => (TAU * a as f64 * time).sin(),
+					_ => 0.0
+				};
+				*x = ((tone * gain) as f32 * scale) as i16;
+				self.frame += 1;
+			}
+			written += len;
+			if self.frame >= frames {
+				self.index += 1;
+				self.frame = 0;
+			}
+		}
+		written
+	}
+
+
+	fn total_frames (&self) -> u64 {
+		self.segments.iter().map(|x| self.frames(x)).sum()
+	}
+
+
+	fn seek (&mut self, mut frame: u64) {
+		self.index = 0;
+		while let Some(segment) = self.segments.get(self.index) {
+			let frames = self.frames(segment);
+			if frame < frames {
+				break;
+			}
+			frame -= frames;
+			self.index += 1;
+		}
+		self.frame = if self.index < self.segments.len() { frame } else { 0 };
+	}
+
+
+}
+
+
+
+/// implement the builders and [`SoundSource`] for a type playing a
+/// `pattern`
+macro_rules! pattern {
+	($name:ident) => {
+
+		impl $name {
+
+			/// the peak of the sound, `1.0` is full scale
+			pub fn amplitude (mut self, amplitude: f32) -> Self {
+				self.pattern.amplitude = amplitude;
+				self
+			}
+
+			/// 48kHz by default
+			pub fn sample_rate (mut self, sample_rate: u32) -> Self {
+				self.pattern.sample_rate = sample_rate.max(1);
+				self
+			}
+
+			/// how long the whole pattern plays
+			pub fn duration (&self) -> Duration {
+				Duration::from_secs_f64(self.pattern.segments.iter().map(|x| x.duration).sum())
+			}
+
+		}
+
+		impl SoundSource for $name {
+
+			fn channels (&self) -> u16 {
+				1
+			}
+
+			fn sample_rate (&self) -> u32 {
+				self.pattern.sample_rate
+			}
+
+			fn reset (&mut self) {
+				self.pattern.seek(0);
+			}
+
+			fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+				self.pattern.write(buffer)
+			}
+
+			fn total_frames (&self) -> Option<u64> {
+				Some(self.pattern.total_frames())
+			}
+
+			fn seek (&mut self, frame: u64) -> bool {
+				self.pattern.seek(frame);
+				true
+			}
+
+		}
+
+	};
+}
+
+
+
+/// a pattern of beeps and pauses
+///
+/// ```
+/// # use std::time::Duration;
+/// # use audio_engine::Beeper;
+/// let cue = Beeper::new(880.0).beep(Duration::from_millis(100)).pause(Duration::from_millis(50)).beep(Duration::from_millis(100));
+/// let sos = Beeper::morse("SOS");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Beeper {
+	frequency: f32,
+	pattern: Pattern
+}
+
+impl Beeper {
+
+
+	/// no beep yet, they are added at `frequency` Hz
+	pub fn new (frequency: f32) -> Self {
+		Self { frequency, pattern: Pattern::new() }
+	}
+
+
+	/// `text` in morse code, at 600Hz and 20 words per minute
+	///
+	/// letters, digits and the common punctuation are played, spaces
+	/// separate words, and other characters are skipped
+	pub fn morse (text: &str) -> Self {
+		let mut beeper = Self::new(MORSE_FREQUENCY);
+		let dot = Duration::from_secs_f64(MORSE_DOT);
+		let mut gap = None;
+		for c in text.chars() {
+			if c.is_whitespace() {
+				// the gap between words is 7 dots
+				gap = gap.map(|_| 7);
+				continue;
+			}
+			let Some(code) = morse(c) else {
+				continue;
+			};
+			if let Some(dots) = gap {
+				beeper = beeper.pause(dot * dots);
+			}
+			for (i, symbol) in code.bytes().enumerate() {
+				if i > 0 {
+					beeper = beeper.pause(dot);
+				}
+				beeper = beeper.beep(if symbol == b'-' { dot * 3 } else { dot });
+			}
+			// the gap between letters is 3 dots
+			gap = Some(3);
+		}
+		beeper
+	}
+
+
+	/// add a beep
+	pub fn beep (mut self, duration: Duration) -> Self {
+		self.pattern.push([self.frequency, 0.0], duration.as_secs_f64());
+		self
+	}
+
+
+	/// add a silence
+	pub fn pause (mut self, duration: Duration) -> Self {
+		self.pattern.push([0.0; 2], duration.as_secs_f64());
+		self
+	}
+
+
+	/// the frequency of the beeps added from now on
+	pub fn frequency (mut self, frequency: f32) -> Self {
+		self.frequency = frequency;
+		self
+	}
+
+
+}
+
+pattern!(Beeper);
+
+
+
+/// the dual tones of telephone keys
+#[derive(Debug, Clone)]
+pub struct Dtmf {
+	pattern: Pattern
+}
+
+impl Dtmf {
+
+
+	/// the tone of a key, for 200ms
+	///
+	/// the keys are the digits, `*`, `#`, and `A` to `D`. `None` for any
+	/// other character
+	pub fn digit (key: char) -> Option<Self> {
+		let mut pattern = Pattern::new();
+		pattern.push(dtmf(key)?, 0.2);
+		Some(Self { pattern })
+	}
+
+
+	/// the tones of every key of `number`, for 100ms each with 100ms
+	/// between them, like a phone dialing. other characters, like
+	/// spaces or dashes, are skipped
+	pub fn dial (number: &str) -> Self {
+		let mut pattern = Pattern::new();
+		for frequencies in number.chars().filter_map(dtmf) {
+			if !pattern.segments.is_empty() {
+				pattern.push([0.0; 2], 0.1);
+			}
+			pattern.push(frequencies, 0.1);
+		}
+		Self { pattern }
+	}
+
+
+}
+
+pattern!(Dtmf);
+
+
+
+/// the two frequencies of a telephone key
+fn dtmf (key: char) -> Option<[f32; 2]> {
+	let key = key.to_ascii_uppercase() as u8;
+	DTMF_KEYS.iter()
+		.enumerate()
+		.find_map(|(row, keys)| keys.iter().position(|&x| x == key).map(|column| [DTMF_ROWS[row], DTMF_COLUMNS[column]]))
+}
+
+
+
+/// the dots and dashes of a character
+fn morse (c: char) -> Option<&'static str> {
+	Some(match c.to_ascii_uppercase() {
+		'A' => ".-", 'B' => "-...", 'C' => "-.-.", 'D' => "-..", 'E' => ".", 'F' => "..-.",
+		'G' => "--.", 'H' => "....", 'I' => "..", 'J' => ".---", 'K' => "-.-", 'L' => ".-..",
+		'M' => "--", 'N' => "-.", 'O' => "---", 'P' => ".--.", 'Q' => "--.-", 'R' => ".-.",
+		'S' => "...", 'T' => "-", 'U' => "..-", 'V' => "...-", 'W' => ".--", 'X' => "-..-",
+		'Y' => "-.--", 'Z' => "--..",
+		'0' => "-----", '1' => ".----", '2' => "..---", '3' => "...--", '4' => "....-",
+		'5' => ".....", '6' => "-....", '7' => "--...", '8' => "---..", '9' => "----.",
+		'.' => ".-.-.-", ',' => "--..--", '?' => "..--..", '/' => "-..-.", '=' => "-...-",
+		'@' => ".--.-.", '-' => "-....-", '!' => "-.-.--", '\'' => ".----.",
+		_ => return None
+	})
+}
@@ -0,0 +1,293 @@
+
+
+
+//! Biquad filters, from the RBJ audio EQ cookbook.
+//!
+//! The cutoff, Q and gain can be changed while the sound plays, through the [`BiquadControls`] of
+//! the filter, which is how occlusion or an underwater sound are done. The cutoff can also be
+//! animated over time on the audio thread.
+
+
+
+use std::f32::consts::{ FRAC_1_SQRT_2, PI };
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::time::Duration;
+
+use crate::easing::{ Easing, Ramp };
+use crate::effect::Effect;
+
+
+
+/// frames filtered with the same coefficients while the cutoff is
+/// animated
+const ANIMATION_BLOCK_FRAMES: usize = 32;
+
+
+
+/// the response of a [`Biquad`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+	/// keeps what is under the cutoff, for muffled sounds
+	LowPass,
+	/// keeps what is over the cutoff, for thin sounds like radios
+	HighPass,
+	/// keeps what is around the cutoff, the Q sets how wide
+	BandPass,
+	/// removes what is around the cutoff, the Q sets how wide
+	Notch,
+	/// boosts or cuts around the cutoff by the gain, for equalizers
+	Peak,
+	/// boosts or cuts under the cutoff by the gain
+	LowShelf,
+	/// boosts or cuts over the cutoff by the gain
+	HighShelf
+}
+
+
+
+/// the parameters shared with the controls, as the bits of `f32`s
+struct Params {
+	cutoff: AtomicU32,
+	q: AtomicU32,
+	/// in dB, only used by the peak and shelf filters
+	gain: AtomicU32,
+	/// the target, duration and easing of a new animation of the
+	/// cutoff, taken by the filter
+	animation: Mutex<Option<(f32, Duration, Easing)>>
+}
+
+
+
+/// a second order filter, as an [`Effect`]
+///
+/// every channel is filtered on its own
+pub struct Biquad {
+
+	kind: FilterKind,
+	params: Arc<Params>,
+	/// the cutoff, Q, gain and sample rate of `coefficients`
+	current: (f32, f32, f32, u32),
+	/// b0, b1, b2, a1, a2, divided by a0
+	coefficients: [f32; 5],
+	/// x1, x2, y1, y2 of each channel
+	state: Vec<[f32; 4]>,
+	/// the animated cutoff, and the last value it stored in `params`
+	animation: Option<(Ramp, f32)>
+
+}
+
+impl Biquad {
+
+
+	/// a filter of `kind`, at `cutoff` Hz
+	///
+	/// a `q` of `0.707` is flat around the cutoff for the low and high
+	/// pass, higher values make it resonate
+	pub fn new (kind: FilterKind, cutoff: f32, q: f32) -> Self {
+		Self::with_gain(kind, cutoff, q, 0.0)
+	}
+
+
+	/// a filter of `kind`, at `cutoff` Hz, that boosts or cuts by
+	/// `gain` dB. only the peak and shelf filters have a gain
+	pub fn with_gain (kind: FilterKind, cutoff: f32, q: f32, gain: f32) -> Self {
+		Self {
+			kind,
+			params: Arc::new(Params {
+				cutoff: AtomicU32::new(cutoff.to_bits()),
+				q: AtomicU32::new(q.to_bits()),
+				gain: AtomicU32::new(gain.to_bits()),
+				animation: Mutex::new(None)
+			}),
+			current: (f32::NAN, f32::NAN, f32::NAN, 0),
+			coefficients: [1.0, 0.0, 0.0, 0.0, 0.0],
+			state: vec![],
+			animation: None
+		}
+	}
+
+
+	pub fn low_pass (cutoff: f32) -> Self {
+		Self::new(FilterKind::LowPass, cutoff, FRAC_1_SQRT_2)
+	}
+
+
+	pub fn high_pass (cutoff: f32) -> Self {
+		Self::new(FilterKind::HighPass, cutoff, FRAC_1_SQRT_2)
+	}
+
+
+	/// change the filter after it was given to a sound
+	pub fn controls (&self) -> BiquadControls {
+		BiquadControls { params: self.params.clone() }
+	}
+
+
+	fn update_coefficients (&mut self, sample_rate: u32) {
+		let cutoff = f32::from_bits(self.params.cutoff.load(Ordering::Relaxed));
+		let q = f32::from_bits(self.params.q.load(Ordering::Relaxed));
+		let gain = f32::from_bits(self.params.gain.load(Ordering::Relaxed));
+		if self.current == (cutoff, q, gain, sample_rate) {
+			return;
+		}
+		self.current = (cutoff, q, gain, sample_rate);
+
+		// kept under nyquist, where the formulas break down
+		let cutoff = cutoff.clamp(1.0, sample_rate as f32 * 0.49);
+		let w0 = 2.0 * PI * cutoff / sample_rate as f32;
+		let (sin, cos) = w0.sin_cos();
+		let alpha = sin / (2.0 * q.max(0.01));
+
+		let a = 10f32.powf(gain / 40.0);
+		let shelf = 2.0 * a.sqrt() * alpha;
+
+		let [b0, b1, b2, a0, a1, a2] = match self.kind {
+			FilterKind::LowPass => [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+			FilterKind::HighPass => [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+			FilterKind::BandPass => [alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+			FilterKind::Notch => [1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+			FilterKind::Peak => [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+			FilterKind::LowShelf => [
+				a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+				2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+				a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+				(a + 1.0) + (a - 1.0) * cos + shelf,
+				-2.0 * ((a - 1.0) + (a + 1.0) * cos),
+				(a + 1.0) + (a - 1.0) * cos - shelf
+			],
+			FilterKind::HighShelf => [
+				a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+				-2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+				a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+				(a + 1.0) - (a - 1.0) * cos + shelf,
+				2.0 * ((a - 1.0) - (a + 1.0) * cos),
+				(a + 1.0) - (a - 1.0) * cos - shelf
+			]
+		};
+		self.coefficients = [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0];
+	}
+
+
+	/// start an animation sent by the controls, and store the cutoff
+	/// of the current one. it stops when the controls set the cutoff
+	///
+	/// the audio thread never waits on the controls, a new animation
+	/// is taken on a later buffer if they hold the lock
+	fn animate (&mut self, sample_rate: u32) -> bool {
+		if let Some((target, duration, easing)) = self.params.animation.try_lock().ok().and_then(|mut x| x.take()) {
+			let cutoff = f32::from_bits(self.params.cutoff.load(Ordering::Relaxed));
+			let mut ramp = Ramp::new(cutoff);
+			ramp.set_eased(target, (duration.as_secs_f64() * sample_rate as f64).round() as u32, easing);
+			self.animation = Some((ramp, cutoff));
+		}
+		let Some((ramp, stored)) = self.animation.as_mut() else {
+			return false;
+		};
+		if self.params.cutoff.load(Ordering::Relaxed) != stored.to_bits() {
+			self.animation = None;
+			return false;
+		}
+		self.params.cutoff.store(ramp.value.to_bits(), Ordering::Relaxed);
+		*stored = ramp.value;
+		if ramp.is_done() {
+			self.animation = None;
+		}
+		true
+	}
+
+
+	fn filter (&mut self, frames: &mut [f32], channels: u16) {
+		let [b0, b1, b2, a1, a2] = self.coefficients;
+		for frame in frames.chunks_exact_mut(channels as usize) {
+			for (x, state) in frame.iter_mut().zip(self.state.iter_mut()) {
+				let [x1, x2, y1, y2] = *state;
+				let y = b0 * *x + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2;
+				*state = [*x, x1, y, y1];
+				*x = y;
+			}
+		}
+	}
+
+
+}
+
+impl Effect for Biquad {
+
+	fn process (&mut self, frames: &mut [f32], channels: u16, sample_rate: u32) {
+		if self.state.len() != channels as usize {
+			self.state = vec![[0.0; 4]; channels as usize];
+		}
+
+		// while animated, the coefficients follow the cutoff every
+		// few frames, otherwise the whole buffer uses the same
+		let mut rest = frames;
+		while !rest.is_empty() {
+			let animated = self.animate(sample_rate);
+			self.update_coefficients(sample_rate);
+			let len = if animated { rest.len().min(ANIMATION_BLOCK_FRAMES * channels as usize) } else { rest.len() };
+			let (block, later) = rest.split_at_mut(len);
+			self.filter(block, channels);
+			if let Some((ramp, _)) = self.animation.as_mut() {
+				ramp.skip((len / channels as usize) as u32);
+			}
+			rest = later;
+		}
+	}
+
+}
+
+
+
+/// changes the cutoff, Q and gain of a [`Biquad`] from any thread
+///
+/// the changes apply from the next buffer that is mixed
+#[derive(Clone)]
+pub struct BiquadControls {
+	params: Arc<Params>
+}
+
+impl BiquadControls {
+
+
+	pub fn set_cutoff (&self, cutoff: f32) {
+		self.params.cutoff.store(cutoff.to_bits(), Ordering::Relaxed);
+	}
+
+
+	/// move the cutoff to `target` over `duration`, along `easing`
+	///
+	/// done on the audio thread, a few frames at a time. setting the
+	/// cutoff stops the animation
+	pub fn animate_cutoff (&self, target: f32, duration: Duration, easing: Easing) {
+		*self.params.animation.lock().unwrap() = Some((target, duration, easing));
+	}
+
+
+	pub fn set_q (&self, q: f32) {
+		self.params.q.store(q.to_bits(), Ordering::Relaxed);
+	}
+
+
+	/// in dB, for the peak and shelf filters
+	pub fn set_gain (&self, gain: f32) {
+		self.params.gain.store(gain.to_bits(), Ordering::Relaxed);
+	}
+
+
+	pub fn cutoff (&self) -> f32 {
+		f32::from_bits(self.params.cutoff.load(Ordering::Relaxed))
+	}
+
+
+	pub fn q (&self) -> f32 {
+		f32::from_bits(self.params.q.load(Ordering::Relaxed))
+	}
+
+
+	pub fn gain (&self) -> f32 {
+		f32::from_bits(self.params.gain.load(Ordering::Relaxed))
+	}
+
+
+}
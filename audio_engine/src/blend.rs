@@ -0,0 +1,233 @@
+
+
+
+//! Layers crossfaded by a game parameter, like the rpm of an engine or the wind of an ambience.
+//!
+//! Each layer is heard fully at its own value of the parameter, and fades into its neighbours
+//! between them with equal power, so the loudness holds through the blend. Every layer loops,
+//! and can also be played faster or slower with the parameter, which is how engine recordings
+//! are made to rev.
+
+
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU32, Ordering };
+
+use crate::mixer::SoundSource;
+use crate::sound_data::SoundData;
+
+
+
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+
+
+/// a looping layer
+struct Layer {
+	data: SoundData,
+	/// the value of the parameter where it is heard fully
+	center: f32,
+	/// in frames of the samples
+	position: f64,
+	/// the gain at the end of the last buffer, the next one moves
+	/// from it
+	gain: f32
+}
+
+
+
+/// layers of [`SoundData`]s crossfaded by a parameter, as a
+/// [`SoundSource`]
+///
+/// it never ends. the parameter is changed while it plays with its
+/// [`controls`](BlendSound::controls)
+///
+/// ```
+/// # use std::time::Duration;
+/// # use audio_engine::{ AudioEngine, BlendSound, SineWave, SoundSourceExt };
+/// # let (engine, _backend) = AudioEngine::offline(2, 48000);
+/// # let layer = |x| engine.load(SineWave::new(x).take_duration(Duration::from_millis(100)));
+/// # let (idle, low, high) = (layer(40.0), layer(120.0), layer(300.0));
+/// let engine_sound = BlendSound::new()
+///     .layer(idle, 800.0)
+///     .layer(low, 2500.0)
+///     .layer(high, 6000.0)
+///     .follow_pitch(true);
+/// let rpm = engine_sound.controls();
+/// engine.new_sound(engine_sound, |x| x)?.play();
+/// rpm.set_parameter(3200.0);
+/// # Ok::<(), audio_engine::Error>(())
+/// ```
+pub struct BlendSound {
+
+	layers: Vec<Layer>,
+	follow_pitch: bool,
+	/// the bits of the `f32` parameter, shared with the controls
+	parameter: Arc<AtomicU32>,
+	mix: Vec<f32>
+
+}
+
+impl BlendSound {
+
+
+	pub fn new () -> Self {
+		Self {
+			layers: vec![],
+			follow_pitch: false,
+			parameter: Arc::new(AtomicU32::new(0.0f32.to_bits())),
+			mix: vec![]
+		}
+	}
+
+
+	/// a layer heard fully when the parameter is `center`
+	///
+	/// the first layer sets the channels and sample rate of the
+	/// sound, the others are played at them
+	pub fn layer (mut self, data: SoundData, center: f32) -> Self {
+		let index = self.layers.partition_point(|x| x.center <= center);
+		self.layers.insert(index, Layer { data, center, position: 0.0, gain: 0.0 });
+		self
+	}
+
+
+	/// play each layer at the speed of the parameter over its center,
+	/// so a layer recorded at 2500 rpm plays faster at 3000, `false`
+	/// by default
+	pub fn follow_pitch (mut self, follow_pitch: bool) -> Self {
+		self.follow_pitch = follow_pitch;
+		self
+	}
+
+
+	/// the value of the parameter it starts with, `0.0` by default
+	pub fn parameter (self, parameter: f32) -> Self {
+		self.controls().set_parameter(parameter);
+		self
+	}
+
+
+	/// change the parameter while it plays
+	pub fn controls (&self) -> BlendControls {
+		BlendControls { parameter: self.parameter.clone() }
+	}
+
+
+	/// the gain of the layer at `index`, at `parameter`
+	fn gain (&self, index: usize, parameter: f32) -> f32 {
+		let center = self.layers[index].center;
+		let neighbour = if parameter < center {
+			index.checked_sub(1)
+		} else {
+			Some(index + 1).filter(|&x| x < self.layers.len())
+		};
+		let Some(neighbour) = neighbour.map(|x| self.layers[x].center).filter(|&x| x != center) else {
+			// under the first layer or over the last one
+			return 1.0;
+		};
+		let t = ((parameter - center) / (neighbour - center)).max(0.0);
+		if t >= 1.0 {
+			// past the neighbour, which is heard fully
+			return 0.0;
+		}
+		(t * std::f32::consts::FRAC_PI_2).cos()
+	}
+
+
+}
+
+impl Default for BlendSound {
+	fn default () -> Self {
+		Self::new()
+	}
+}
+
+impl SoundSource for BlendSound {
+
+	fn channels (&self) -> u16 {
+		self.layers.first().map_or(2, |x| x.data.channels())
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.layers.first().map_or(DEFAULT_SAMPLE_RATE, |x| x.data.sample_rate())
+	}
+
+	fn reset (&mut self) {
+		for layer in self.layers.iter_mut() {
+			layer.position = 0.0;
+		}
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let channels = self.channels().max(1) as usize;
+		let sample_rate = self.sample_rate();
+		let parameter = f32::from_bits(self.parameter.load(Ordering::Relaxed));
+		let frames = buffer.len() / channels;
+
+		self.mix.clear();
+		self.mix.resize(buffer.len(), 0.0);
+		for i in 0..self.layers.len() {
+			let target = self.gain(i, parameter);
+			let follow_pitch = self.follow_pitch;
+			let layer = &mut self.layers[i];
+			let (start, total) = (layer.gain, layer.data.frames() as f64);
+			layer.gain = target;
+			if (start == 0.0 && target == 0.0) || total == 0.0 {
+				continue;
+			}
+			let speed = match follow_pitch {
+				true if layer.center > 0.0 => (parameter / layer.center).clamp(0.25, 4.0),
+				_ => 1.0
+			};
+			let step = speed as f64 * layer.data.sample_rate() as f64 / sample_rate as f64;
+			let layer_channels = layer.data.channels().max(1) as usize;
+			let samples = layer.data.samples();
+			// the gain moves over the buffer, so a change doesn't click
+			for f in 0..frames {
+				let gain = start + (target - start) * f as f32 / frames as f32;
+				let i = layer.position as usize;
+				let t = (layer.position - i as f64) as f32;
+				let j = (i + 1) % total as usize;
+				for c in 0..channels {
+					// a mono layer is heard on every channel
+					let from = c % layer_channels;
+					let a = samples[i * layer_channels + from] as f32;
+					let b = samples[j * layer_channels + from] as f32;
+					self.mix[f * channels + c] += (a + (b - a) * t) * gain;
+				}
+				layer.position = (layer.position + step) % total;
+			}
+		}
+
+		for (x, y) in buffer.iter_mut().zip(self.mix.iter()) {
+			*x = y.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+		}
+		buffer.len()
+	}
+
+}
+
+
+
+/// changes the parameter of a [`BlendSound`] from any thread, from
+/// the next mixed buffer
+#[derive(Clone)]
+pub struct BlendControls {
+	parameter: Arc<AtomicU32>
+}
+
+impl BlendControls {
+
+
+	pub fn set_parameter (&self, parameter: f32) {
+		self.parameter.store(parameter.to_bits(), Ordering::Relaxed);
+	}
+
+
+	pub fn parameter (&self) -> f32 {
+		f32::from_bits(self.parameter.load(Ordering::Relaxed))
+	}
+
+
+}
@@ -0,0 +1,151 @@
+
+
+
+//! Sources played one after the other, as a single source.
+//!
+//! The next source starts on the frame after the last one ended, in the same buffer, so an intro
+//! flows into its loop and lines of dialogue recorded apart are heard as one. The sources are
+//! converted to the channels and sample rate of the first one when they are added.
+
+
+
+use crate::converter::{ self, ResampleQuality };
+use crate::error::Error;
+use crate::mixer::SoundSource;
+
+
+
+/// sources played back to back, as a [`SoundSource`]
+///
+/// it ends when the last source ends. it can seek when the length
+/// of every source before the frame is known
+///
+/// ```ignore
+/// let music = ChainSource::new(OggDecoder::new(File::open("intro.ogg")?)?)
+///     .then(OggDecoder::new(File::open("loop.ogg")?)?)?
+///     .loop_last(true);
+/// let mut sound = engine.new_sound(StreamingDecoder::new(music), |x| x)?;
+/// sound.set_loop(true);
+/// sound.play();
+/// ```
+pub struct ChainSource {
+
+	parts: Vec<Box<dyn SoundSource + Send>>,
+	/// the part that plays
+	current: usize,
+	loop_last: bool
+
+}
+
+impl ChainSource {
+
+
+	/// a chain starting with `first`, at its channels and sample rate
+	pub fn new <T: SoundSource + Send + 'static> (first: T) -> Self {
+		Self {
+			parts: vec![Box::new(first)],
+			current: 0,
+			loop_last: false
+		}
+	}
+
+
+	/// play `source` after the sources already in the chain
+	///
+	/// fails if `source` can't be converted to the channels of the
+	/// first source
+	pub fn then <T: SoundSource + Send + 'static> (mut self, source: T) -> Result<Self, Error> {
+		let source = converter::convert(Box::new(source), self.channels(), self.sample_rate(), ResampleQuality::default())?;
+		self.parts.push(source);
+		Ok(self)
+	}
+
+
+	/// make the last source the loop region of the chain, so a
+	/// looping sound plays the sources before it once, `false` by
+	/// default
+	///
+	/// the lengths of all the sources must be known, see
+	/// [`Sound::set_loop_region`](crate::Sound::set_loop_region)
+	pub fn loop_last (mut self, loop_last: bool) -> Self {
+		self.loop_last = loop_last;
+		self
+	}
+
+
+	/// the frame where the part at `index` starts, if the parts before
+	/// it have known lengths
+	fn start_of (&self, index: usize) -> Option<u64> {
+		self.parts[..index].iter().map(|x| x.total_frames()).sum()
+	}
+
+
+}
+
+impl SoundSource for ChainSource {
+
+	fn channels (&self) -> u16 {
+		self.parts[0].channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.parts[0].sample_rate()
+	}
+
+	fn reset (&mut self) {
+		for part in self.parts.iter_mut().take(self.current + 1) {
+			part.reset();
+		}
+		self.current = 0;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let mut len = 0;
+		while len < buffer.len() && self.current < self.parts.len() {
+			len += self.parts[self.current].write_samples(&mut buffer[len..]);
+			if len < buffer.len() {
+				// the part ended, the next one fills the rest
+				self.current += 1;
+			}
+		}
+		len
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		self.start_of(self.parts.len())
+	}
+
+	fn seek (&mut self, frame: u64) -> bool {
+		let mut start = 0;
+		let mut index = self.parts.len() - 1;
+		for (i, part) in self.parts.iter().enumerate().take(self.parts.len() - 1) {
+			let Some(len) = part.total_frames() else {
+				return false;
+			};
+			if frame < start + len {
+				index = i;
+				break;
+			}
+			start += len;
+		}
+		if !self.parts[index].seek(frame - start) {
+			return false;
+		}
+		// the parts after it play from their start again
+		for part in self.parts.iter_mut().take(self.current + 1).skip(index + 1) {
+			part.reset();
+		}
+		self.current = index;
+		true
+	}
+
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		if !self.loop_last {
+			return None;
+		}
+		let start = self.start_of(self.parts.len() - 1)?;
+		let end = start + self.parts.last()?.total_frames()?;
+		Some((start, end)).filter(|(start, end)| start < end)
+	}
+
+}
@@ -0,0 +1,122 @@
+
+
+
+//! Routing of the channels of a source to the channels of the output, like stereo music also in
+//! the rear of a 5.1 output or a cue only heard on the left.
+
+
+
+use crate::mixer::{ Direction, InstanceLimit, SoundSource };
+
+
+
+/// plays the channels of `T` on chosen channels of the output, each
+/// at its own gain, as a [`SoundSource`]
+///
+/// output channels without a route are silent, and the routes to the
+/// same channel add up. the engine already converts between the
+/// [`ChannelLayout`](crate::ChannelLayout)s, this is for any other
+/// routing
+///
+/// ```
+/// # use audio_engine::{ AudioEngine, ChannelMap, Constant };
+/// # let (engine, _backend) = AudioEngine::offline(6, 48000);
+/// # let music = Constant::new(0.5).channels(2);
+/// // stereo music on a 5.1 output, also in the rear channels at half
+/// let music = ChannelMap::new(music, 6)
+///     .route(0, 0, 1.0)
+///     .route(1, 1, 1.0)
+///     .route(0, 4, 0.5)
+///     .route(1, 5, 0.5);
+/// engine.new_sound(music, |x| x)?.play();
+/// # Ok::<(), audio_engine::Error>(())
+/// ```
+pub struct ChannelMap<T: SoundSource> {
+
+	inner: T,
+	channels: u16,
+	/// the channel of the source, the channel of the output, and the
+	/// gain
+	routes: Vec<(u16, u16, f32)>,
+	/// the samples read from the source
+	buffer: Vec<i16>
+
+}
+
+impl<T: SoundSource> ChannelMap<T> {
+
+
+	/// `inner` with `channels` channels and no routes
+	pub fn new (inner: T, channels: u16) -> Self {
+		Self { inner, channels: channels.max(1), routes: vec![], buffer: vec![] }
+	}
+
+
+	/// play channel `from` of the source on channel `to` of the
+	/// output at `gain`, routes out of either layout are ignored
+	pub fn route (mut self, from: u16, to: u16, gain: f32) -> Self {
+		if from < self.inner.channels() && to < self.channels {
+			self.routes.push((from, to, gain));
+		}
+		self
+	}
+
+
+}
+
+impl<T: SoundSource> SoundSource for ChannelMap<T> {
+
+	fn channels (&self) -> u16 {
+		self.channels
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.inner.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.inner.reset()
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let (from_channels, to_channels) = (self.inner.channels().max(1) as usize, self.channels as usize);
+		let frames = buffer.len() / to_channels;
+		// only reallocates when the buffer grows
+		self.buffer.resize(frames * from_channels, 0);
+		let frames = self.inner.write_samples(&mut self.buffer) / from_channels;
+
+		for (f, frame) in buffer.chunks_exact_mut(to_channels).take(frames).enumerate() {
+			let from = &self.buffer[f * from_channels..(f + 1) * from_channels];
+			for (c, x) in frame.iter_mut().enumerate() {
+				let sum: f32 = self.routes
+					.iter()
+					.filter(|route| route.1 as usize == c)
+					.map(|route| from[route.0 as usize] as f32 * route.2)
+					.sum();
+				*x = sum.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+			}
+		}
+		frames * to_channels
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		self.inner.total_frames()
+	}
+
+	fn seek (&mut self, frame: u64) -> bool {
+		self.inner.seek(frame)
+	}
+
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		self.inner.loop_region()
+	}
+
+	fn set_direction (&mut self, direction: Direction) -> bool {
+		self.inner.set_direction(direction)
+	}
+
+	fn instance_limit (&self) -> Option<InstanceLimit> {
+		self.inner.instance_limit()
+	}
+
+}
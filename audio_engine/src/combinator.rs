@@ -0,0 +1,675 @@
+
+
+
+//! Adapters that change a source before it's given to the engine, chained like iterators.
+//!
+//! A long recording can be trimmed with [`skip_duration`](SoundSourceExt::skip_duration) and
+//! [`take_duration`](SoundSourceExt::take_duration), started later with
+//! [`delay`](SoundSourceExt::delay), played a few times with
+//! [`repeat_n`](SoundSourceExt::repeat_n), mixed with another source, or made louder or quieter,
+//! all as a single source that seeks and knows its length when the source it wraps does.
+
+
+
+use std::time::Duration;
+
+use crate::converter::{ self, ResampleQuality };
+use crate::error::Error;
+use crate::mixer::{ Direction, InstanceLimit, SoundSource };
+
+
+
+/// `duration` in frames at `sample_rate`
+fn frames_of (duration: Duration, sample_rate: u32) -> u64 {
+	(duration.as_secs_f64() * sample_rate as f64).round() as u64
+}
+
+
+
+/// the adapters of every [`SoundSource`]
+///
+/// ```ignore
+/// // the second to the fourth second of the take, twice, at half
+/// // the volume, after a quarter of a second of silence
+/// let line = WavDecoder::new(File::open("takes.wav")?)?
+///     .skip_duration(Duration::from_secs(2))
+///     .take_duration(Duration::from_secs(2))
+///     .repeat_n(2)
+///     .amplify(0.5)
+///     .delay(Duration::from_millis(250));
+/// engine.new_sound(line, |x| x)?.play();
+/// ```
+pub trait SoundSourceExt: SoundSource + Sized {
+
+	/// end after `duration`
+	fn take_duration (self, duration: Duration) -> TakeDuration<Self> {
+		let frames = frames_of(duration, self.sample_rate());
+		TakeDuration { inner: self, frames, position: 0 }
+	}
+
+	/// start `duration` into the source
+	///
+	/// the source seeks there if it can, or the frames before are
+	/// decoded and thrown away
+	fn skip_duration (self, duration: Duration) -> Skip<Self> {
+		let frames = frames_of(duration, self.sample_rate());
+		Skip { inner: self, frames, skipped: false }
+	}
+
+	/// play `duration` of silence before the source
+	fn delay (self, duration: Duration) -> Delayed<Self> {
+		let frames = frames_of(duration, self.sample_rate());
+		Delayed { inner: self, frames, position: 0 }
+	}
+
+	/// play the source `count` times, one after the other
+	fn repeat_n (self, count: u32) -> RepeatN<Self> {
+		RepeatN { inner: self, count, played: 0 }
+	}
+
+	/// play `other` at the same time, converted to the channels and
+	/// sample rate of the source
+	///
+	/// it ends when both have ended. fails if `other` can't be
+	/// converted to the channels of the source
+	fn mix <T: SoundSource + Send + 'static> (self, other: T) -> Result<Mix<Self>, Error> {
+		let other = converter::convert(Box::new(other), self.channels(), self.sample_rate(), ResampleQuality::default())?;
+		Ok(Mix { inner: self, other, buffer: vec![], position: 0 })
+	}
+
+	/// multiply the samples by `gain`, see [`db_to_gain`](crate::db_to_gain)
+	fn amplify (self, gain: f32) -> Amplify<Self> {
+		Amplify { inner: self, gain }
+	}
+
+}
+
+impl<T: SoundSource> SoundSourceExt for T {}
+
+
+
+/// the start of a source, see [`SoundSourceExt::take_duration`]
+pub struct TakeDuration<T: SoundSource> {
+	inner: T,
+	frames: u64,
+	position: u64
+}
+
+impl<T: SoundSource> SoundSource for TakeDuration<T> {
+
+	fn channels (&self) -> u16 {
+		self.inner.channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.inner.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.inner.reset();
+		self.position = 0;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let channels = self.channels().max(1) as usize;
+		let left = (self.frames - self.position).min((buffer.len() / channels) as u64) as usize;
+		let frames = self.inner.write_samples(&mut buffer[..left * channels]) / channels;
+		if frames < left {
+			// the source ended first
+			self.position = self.frames;
+		} else {
+			self.position += frames as u64;
+		}
+		frames * channels
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		Some(self.inner.total_frames()?.min(self.frames))
+	}
+
+	fn seek (&mut self, frame: u64) -> bool {
+		if frame >= self.frames {
+			self.position = self.frames;
+			return true;
+		}
+		if !self.inner.seek(frame) {
+			return false;
+		}
+		self.position = frame;
+		true
+	}
+
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		let (start, end) = self.inner.loop_region()?;
+		Some((start, end.min(self.frames))).filter(|(start, end)| start < end)
+	}
+
+	fn instance_limit (&self) -> Option<InstanceLimit> {
+		self.inner.instance_limit()
+	}
+
+}
+
+
+
+/// a source without its start, see [`SoundSourceExt::skip_duration`]
+pub struct Skip<T: SoundSource> {
+	inner: T,
+	frames: u64,
+	/// the source is past the skipped frames
+	skipped: bool
+}
+
+impl<T: SoundSource> Skip<T> {
+
+	/// move the source past the skipped frames, decoding them in
+	/// `buffer` if it can't seek
+	fn skip (&mut self, buffer: &mut [i16]) {
+		self.skipped = true;
+		if self.inner.seek(self.frames) {
+			return;
+		}
+		let channels = self.channels().max(1) as usize;
+		let mut left = self.frames as usize;
+		while left > 0 {
+			let len = (buffer.len() / channels).min(left) * channels;
+			if len == 0 || self.inner.write_samples(&mut buffer[..len]) < len {
+				break;
+			}
+			left -= len / channels;
+		}
+	}
+
+}
+
+impl<T: SoundSource> SoundSource for Skip<T> {
+
+	fn channels (&self) -> u16 {
+		self.inner.channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.inner.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.inner.reset();
+		self.skipped = false;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		if !self.skipped {
+			self.skip(buffer);
+		}
+		self.inner.write_samples(buffer)
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		Some(self.inner.total_frames()?.saturating_sub(self.frames))
+	}
+
+	fn seek (&mut self, frame: u64) -> bool {
+		if !self.inner.seek(frame.saturating_add(self.frames)) {
+			return false;
+		}
+		self.skipped = true;
+		true
+	}
+
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		let (start, end) = self.inner.loop_region()?;
+		Some((start.saturating_sub(self.frames), end.saturating_sub(self.frames))).filter(|(start, end)| start < end)
+	}
+
+	fn instance_limit (&self) -> Option<InstanceLimit> {
+		self.inner.instance_limit()
+	}
+
+}
+
+
+
+/// a source after some silence, see [`SoundSourceExt::delay`]
+pub struct Delayed<T: SoundSource> {
+	inner: T,
+	frames: u64,
+	/// the frames of silence played
+	position: u64
+}
+
+impl<T: SoundSource> SoundSource for Delayed<T> {
+
+	fn channels (&self) -> u16 {
+		self.inner.channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.inner.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.inner.reset();
+		self.position = 0;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let channels = self.channels().max(1) as usize;
+		let silence = (self.frames - self.position).min((buffer.len() / channels) as u64) as usize;
+		buffer[..silence * channels].fill(0);
+		self.position += silence as u64;
+		silence * channels + self.inner.write_samples(&mut buffer[silence * channels..])
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		Some(self.inner.total_frames()? + self.frames)
+	}
+
+	fn seek (&mut self, frame: u64) -> bool {
+		if frame < self.frames {
+			self.inner.reset();
+			self.position = frame;
+			return true;
+		}
+		if !self.inner.seek(frame - self.frames) {
+			return false;
+		}
+		self.position = self.frames;
+		true
+	}
+
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		let (start, end) = self.inner.loop_region()?;
+		Some((start + self.frames, end + self.frames))
+	}
+
+	fn instance_limit (&self) -> Option<InstanceLimit> {
+		self.inner.instance_limit()
+	}
+
+}
+
+
+
+/// a source played a few times, see [`SoundSourceExt::repeat_n`]
+pub struct RepeatN<T: SoundSource> {
+	inner: T,
+	count: u32,
+	/// the times the source has ended
+	played: u32
+}
+
+impl<T: SoundSource> SoundSource for RepeatN<T> {
+
+	fn channels (&self) -> u16 {
+		self.inner.channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.inner.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.inner.reset();
+		self.played = 0;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let mut len = 0;
+		// the source was reset in this buffer, and would repeat
+		// forever in it if it's empty
+		let mut reset = false;
+		while len < buffer.len() && self.played < self.count {
+			let written = self.inner.write_samples(&mut buffer[len..]);
+			len += written;
+			if len < buffer.len() {
+				if written == 0 && reset {
+					self.played = self.count;
+					break;
+				}
+				self.played += 1;
+				if self.played < self.count {
+					self.inner.reset();
+					reset = true;
+				}
+			}
+		}
+		len
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		Some(self.inner.total_frames()? * self.count as u64)
+	}
+
+	fn seek (&mut self, frame: u64) -> bool {
+		let Some(len) = self.inner.total_frames().filter(|&x| x > 0) else {
+			return false;
+		};
+		let played = (frame / len).min(self.count as u64) as u32;
+		if played == self.count {
+			self.played = played;
+			return true;
+		}
+		if !self.inner.seek(frame % len) {
+			return false;
+		}
+		self.played = played;
+		true
+	}
+
+	fn instance_limit (&self) -> Option<InstanceLimit> {
+		self.inner.instance_limit()
+	}
+
+}
+
+
+
+/// two sources played at once, see [`SoundSourceExt::mix`]
+pub struct Mix<T: SoundSource> {
+	inner: T,
+	other: Box<dyn SoundSource + Send>,
+	/// the samples of `other`
+	buffer: Vec<i16>,
+	/// the frames played, to seek `inner` back if `other` can't seek
+	position: u64
+}
+
+impl<T: SoundSource> SoundSource for Mix<T> {
+
+	fn channels (&self) -> u16 {
+		self.inner.channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.inner.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.inner.reset();
+		self.other.reset();
+		self.position = 0;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let len = self.inner.write_samples(buffer);
+		buffer[len..].fill(0);
+		// only reallocates when the buffer grows
+		self.buffer.resize(buffer.len(), 0);
+		let other = self.other.write_samples(&mut self.buffer);
+		for (x, y) in buffer.iter_mut().zip(self.buffer[..other].iter()) {
+			*x = x.saturating_add(*y);
+		}
+		let len = len.max(other);
+		self.position += (len / self.channels().max(1) as usize) as u64;
+		len
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		Some(self.inner.total_frames()?.max(self.other.total_frames()?))
+	}
+
+	/// both sources must be able to seek
+	fn seek (&mut self, frame: u64) -> bool {
+		if !self.inner.seek(frame) {
+			return false;
+		}
+		if !self.other.seek(frame) {
+			// back where it was, so both stay in sync
+			self.inner.seek(self.position);
+			return false;
+		}
+		self.position = frame;
+		true
+	}
+
+	fn instance_limit (&self) -> Option<InstanceLimit> {
+		self.inner.instance_limit()
+	}
+
+}
+
+
+
+/// a louder or quieter source, see [`SoundSourceExt::amplify`]
+pub struct Amplify<T: SoundSource> {
+	inner: T,
+	gain: f32
+}
+
+impl<T: SoundSource> SoundSource for Amplify<T> {
+
+	fn channels (&self) -> u16 {
+		self.inner.channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.inner.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.inner.reset()
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let len = self.inner.write_samples(buffer);
+		for x in buffer[..len].iter_mut() {
+			*x = (*x as f32 * self.gain).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+		}
+		len
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		self.inner.total_frames()
+	}
+
+	fn seek (&mut self, frame: u64) -> bool {
+		self.inner.seek(frame)
+	}
+
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		self.inner.loop_region()
+	}
+
+	fn set_direction (&mut self, direction: Direction) -> bool {
+		self.inner.set_direction(direction)
+	}
+
+	fn instance_limit (&self) -> Option<InstanceLimit> {
+		self.inner.instance_limit()
+	}
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::time::Duration;
+
+	use crate::Constant;
+	use crate::mixer::SoundSource;
+	use super::SoundSourceExt;
+
+
+	/// a mono source of `len` frames at 1000 Hz, where each sample is
+	/// its frame, so a millisecond is a frame
+	struct Counter {
+		position: u64,
+		len: u64,
+		seekable: bool
+	}
+
+	impl Counter {
+
+		fn new (len: u64) -> Self {
+			Self { position: 0, len, seekable: true }
+		}
+
+		fn unseekable (len: u64) -> Self {
+			Self { position: 0, len, seekable: false }
+		}
+
+	}
+
+	impl SoundSource for Counter {
+
+		fn channels (&self) -> u16 {
+			1
+		}
+
+		fn sample_rate (&self) -> u32 {
+			1000
+		}
+
+		fn reset (&mut self) {
+			self.position = 0;
+		}
+
+		fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+			let len = (self.len - self.position).min(buffer.len() as u64) as usize;
+			for (i, x) in buffer[..len].iter_mut().enumerate() {
+				*x = (self.position + i as u64) as i16;
+			}
+			self.position += len as u64;
+			len
+		}
+
+		fn total_frames (&self) -> Option<u64> {
+			Some(self.len)
+		}
+
+		fn seek (&mut self, frame: u64) -> bool {
+			if self.seekable {
+				self.position = frame.min(self.len);
+			}
+			self.seekable
+		}
+
+	}
+
+
+	/// the samples until the end, in small buffers
+	fn read_all (source: &mut impl SoundSource) -> Vec<i16> {
+		read(source, usize::MAX)
+	}
+
+
+	/// up to `max` samples, in small buffers
+	fn read (source: &mut impl SoundSource, max: usize) -> Vec<i16> {
+		let mut samples = vec![];
+		let mut buffer = [0; 2];
+		while samples.len() < max {
+			let len = source.write_samples(&mut buffer);
+			samples.extend_from_slice(&buffer[..len]);
+			if len < buffer.len() {
+				break;
+			}
+		}
+		samples
+	}
+
+
+	fn ms (x: u64) -> Duration {
+		Duration::from_millis(x)
+	}
+
+
+	#[test]
+	fn take_duration () {
+		let mut source = Counter::new(10).take_duration(ms(5));
+		assert_eq!(source.total_frames(), Some(5));
+		assert_eq!(read_all(&mut source), [0, 1, 2, 3, 4]);
+		assert!(source.seek(3));
+		assert_eq!(read_all(&mut source), [3, 4]);
+		assert!(source.seek(7));
+		assert_eq!(read_all(&mut source), []);
+		source.reset();
+		assert_eq!(read_all(&mut source), [0, 1, 2, 3, 4]);
+		// a shorter source ends first
+		let mut source = Counter::new(3).take_duration(ms(5));
+		assert_eq!(source.total_frames(), Some(3));
+		assert_eq!(read_all(&mut source), [0, 1, 2]);
+	}
+
+
+	#[test]
+	fn skip_duration () {
+		for inner in [Counter::new(8), Counter::unseekable(8)] {
+			let mut source = inner.skip_duration(ms(5));
+			assert_eq!(source.total_frames(), Some(3));
+			assert_eq!(read_all(&mut source), [5, 6, 7]);
+			source.reset();
+			assert_eq!(read_all(&mut source), [5, 6, 7]);
+		}
+		let mut source = Counter::new(8).skip_duration(ms(5));
+		assert!(source.seek(1));
+		assert_eq!(read_all(&mut source), [6, 7]);
+		assert!(!Counter::unseekable(8).skip_duration(ms(5)).seek(1));
+	}
+
+
+	#[test]
+	fn delay () {
+		let mut source = Counter::new(3).delay(ms(3));
+		assert_eq!(source.total_frames(), Some(6));
+		assert_eq!(read_all(&mut source), [0, 0, 0, 0, 1, 2]);
+		assert!(source.seek(2));
+		assert_eq!(read_all(&mut source), [0, 0, 1, 2]);
+		assert!(source.seek(4));
+		assert_eq!(read_all(&mut source), [1, 2]);
+	}
+
+
+	#[test]
+	fn repeat_n () {
+		let mut source = Counter::new(3).repeat_n(3);
+		assert_eq!(source.total_frames(), Some(9));
+		assert_eq!(read_all(&mut source), [0, 1, 2, 0, 1, 2, 0, 1, 2]);
+		assert!(source.seek(4));
+		assert_eq!(read_all(&mut source), [1, 2, 0, 1, 2]);
+		assert!(source.seek(9));
+		assert_eq!(read_all(&mut source), []);
+		// an empty source doesn't repeat forever
+		assert_eq!(read_all(&mut Counter::new(0).repeat_n(u32::MAX)), []);
+		assert_eq!(read_all(&mut Counter::new(2).repeat_n(0)), []);
+	}
+
+
+	#[test]
+	fn mix () {
+		let mut source = Counter::new(3).mix(Counter::new(5)).unwrap();
+		assert_eq!(source.total_frames(), Some(5));
+		assert_eq!(read_all(&mut source), [0, 2, 4, 3, 4]);
+		assert!(source.seek(1));
+		assert_eq!(read_all(&mut source), [2, 4, 3, 4]);
+	}
+
+
+	#[test]
+	fn mix_seek_keeps_the_sources_in_sync () {
+		let mut source = Counter::new(6).mix(Counter::unseekable(6)).unwrap();
+		assert_eq!(read(&mut source, 2), [0, 2]);
+		assert!(!source.seek(4));
+		assert_eq!(read_all(&mut source), [4, 6, 8, 10]);
+		// the other way around, nothing moves
+		let mut source = Counter::unseekable(6).mix(Counter::new(6)).unwrap();
+		assert_eq!(read(&mut source, 2), [0, 2]);
+		assert!(!source.seek(4));
+		assert_eq!(read_all(&mut source), [4, 6, 8, 10]);
+	}
+
+
+	#[test]
+	fn amplify () {
+		let mut source = Counter::new(4).amplify(-2.5);
+		assert_eq!(read_all(&mut source), [0, -2, -5, -7]);
+		assert!(source.seek(2));
+		assert_eq!(read_all(&mut source), [-5, -7]);
+		// clipped to full scale
+		let mut source = Constant::new(0.75).sample_rate(1000).duration(ms(2)).amplify(2.0);
+		assert_eq!(read_all(&mut source), [i16::MAX, i16::MAX]);
+	}
+
+
+}
@@ -0,0 +1,119 @@
+
+
+
+//! A compressor, that turns down what goes over a threshold.
+
+
+
+use std::time::Duration;
+
+use crate::effect::Effect;
+
+
+
+/// full scale, in the units of the mix
+const FULL_SCALE: f32 = i16::MAX as f32;
+
+
+
+/// settings of a [`Compressor`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressorConfig {
+
+	/// the level where the compression starts, `1.0` is full scale
+	pub threshold: f32,
+	/// how much the level over the threshold is divided by
+	pub ratio: f32,
+	/// how fast the compression starts when the level goes over
+	pub attack: Duration,
+	/// how fast it stops when the level goes back under
+	pub release: Duration,
+	/// gain applied after the compression, to make up for it
+	pub makeup: f32
+
+}
+
+impl Default for CompressorConfig {
+	fn default () -> Self {
+		Self {
+			// -12 dB
+			threshold: 0.25,
+			ratio: 4.0,
+			attack: Duration::from_millis(10),
+			release: Duration::from_millis(100),
+			makeup: 1.0
+		}
+	}
+}
+
+
+
+/// per sample smoothing that takes about `time`
+fn coefficient (time: Duration, sample_rate: u32) -> f32 {
+	let frames = time.as_secs_f32() * sample_rate as f32;
+	if frames > 0.0 { 1.0 - (-1.0 / frames).exp() } else { 1.0 }
+}
+
+
+
+/// a compressor, as an [`Effect`]
+///
+/// the level is the peak of every channel, so they are compressed
+/// together
+pub struct Compressor {
+
+	config: CompressorConfig,
+	/// the followed level, in full scale
+	envelope: f32,
+	/// smoothing of `envelope`, for `sample_rate`
+	attack: f32,
+	release: f32,
+	sample_rate: u32
+
+}
+
+impl Compressor {
+
+
+	pub fn new (config: CompressorConfig) -> Self {
+		Self {
+			config,
+			envelope: 0.0,
+			attack: 1.0,
+			release: 1.0,
+			sample_rate: 0
+		}
+	}
+
+
+}
+
+impl Effect for Compressor {
+
+	fn process (&mut self, frames: &mut [f32], channels: u16, sample_rate: u32) {
+		if self.sample_rate != sample_rate {
+			self.attack = coefficient(self.config.attack, sample_rate);
+			self.release = coefficient(self.config.release, sample_rate);
+			self.sample_rate = sample_rate;
+		}
+
+		let threshold = self.config.threshold.max(1e-6);
+		let slope = 1.0 - 1.0 / self.config.ratio.max(1.0);
+		for frame in frames.chunks_exact_mut(channels as usize) {
+			let peak = frame.iter().fold(0.0f32, |peak, x| peak.max(x.abs())) / FULL_SCALE;
+			let smoothing = if peak > self.envelope { self.attack } else { self.release };
+			self.envelope += (peak - self.envelope) * smoothing;
+
+			// the gain in dB is the level over the threshold, times the slope
+			let gain = if self.envelope > threshold {
+				(threshold / self.envelope).powf(slope)
+			} else {
+				1.0
+			};
+			for x in frame.iter_mut() {
+				*x *= gain * self.config.makeup;
+			}
+		}
+	}
+
+}
@@ -2,12 +2,35 @@
 
 
 
-use crate::mixer::SoundSource;
+use crate::error::Error;
+use crate::layout;
+use crate::mixer::{Direction, InstanceLimit, SoundSource};
 
+use std::sync::OnceLock;
 use std::vec;
 
 
 
+/// Convert a boxed source to `channels` and `sample_rate`, like the engine converts its sounds.
+///
+/// The sample rate is converted with `quality`, and the channels are mixed down or up like in
+/// [`layout::convert`], which returns an `Err` for counts without a layout.
+pub(crate) fn convert(
+	source: Box<dyn SoundSource + Send>,
+	channels: u16,
+	sample_rate: u32,
+	quality: ResampleQuality,
+) -> Result<Box<dyn SoundSource + Send>, Error> {
+	let source: Box<dyn SoundSource + Send> = if source.sample_rate() != sample_rate {
+		Box::new(SampleRateConverter::new(source, sample_rate, quality))
+	} else {
+		source
+	};
+	layout::convert(source, channels)
+}
+
+
+
 /// Convert a SoundSource to a diferent number of channels.
 ///
 /// This struct is able to convert from 1 channel to many (by duplicating the signal), or from many
@@ -35,6 +58,21 @@ impl<T: SoundSource> SoundSource for ChannelConverter<T> {
 	fn reset(&mut self) {
 		self.inner.reset()
 	}
+	fn total_frames(&self) -> Option<u64> {
+		self.inner.total_frames()
+	}
+	fn seek(&mut self, frame: u64) -> bool {
+		self.inner.seek(frame)
+	}
+	fn loop_region(&self) -> Option<(u64, u64)> {
+		self.inner.loop_region()
+	}
+	fn set_direction(&mut self, direction: Direction) -> bool {
+		self.inner.set_direction(direction)
+	}
+	fn instance_limit(&self) -> Option<InstanceLimit> {
+		self.inner.instance_limit()
+	}
 	fn write_samples(&mut self, buffer: &mut [i16]) -> usize {
 		if self.inner.channels() == 1 {
 			let len = buffer.len() / self.channels as usize;
@@ -65,51 +103,108 @@ impl<T: SoundSource> SoundSource for ChannelConverter<T> {
 	}
 }
 
-/// Do a sample rate convertion using linear interpolation.
+/// How precisely the sample rates of the sounds are converted, and their speed changed.
+///
+/// Every preset interpolates with a Kaiser windowed sinc, the longer ones alias less but cost
+/// more. Sounds played at their own rate and speed are not resampled, and cost nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResampleQuality {
+	/// 8 taps, for many pitched voices on slow devices.
+	Fast,
+	/// 16 taps, inaudible aliasing for most sounds.
+	#[default]
+	Good,
+	/// 32 taps, for music.
+	Best,
+}
+
+/// A windowed sinc, sampled finely enough to be interpolated linearly.
+struct Kernel {
+	/// Half the number of taps, in input frames.
+	half_width: usize,
+	/// The passband, as a fraction of the nyquist frequency of the input.
+	cutoff: f64,
+	/// Samples per input frame in `table`.
+	resolution: usize,
+	/// How many positions between two input frames get their own weights, see `Resampler`.
+	phases: usize,
+	/// The kernel from 0 to `half_width`, it is symmetric, with the slope to the next sample. it
+	/// ends with a zero, that the samples past the end are clamped to.
+	table: Box<[(f32, f32)]>,
+}
+impl Kernel {
+	fn new(half_width: usize, beta: f64, cutoff: f64, resolution: usize, phases: usize) -> Self {
+		// the zeroth order modified bessel function, for the kaiser window
+		fn bessel(x: f64) -> f64 {
+			let (mut sum, mut term, mut k) = (1.0, 1.0, 1.0);
+			while term > sum * 1e-12 {
+				term *= (x / (2.0 * k)) * (x / (2.0 * k));
+				sum += term;
+				k += 1.0;
+			}
+			sum
+		}
+		let samples: Vec<f32> = (0..=half_width * resolution)
+			.map(|i| {
+				let x = i as f64 / resolution as f64;
+				let sinc = if i == 0 {
+					1.0
+				} else {
+					(std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+				};
+				let window = 1.0 - (x / half_width as f64).powi(2);
+				(sinc * bessel(beta * window.max(0.0).sqrt()) / bessel(beta)) as f32
+			})
+			.chain([0.0, 0.0])
+			.collect();
+		let table = samples.windows(2).map(|x| (x[0], x[1] - x[0])).collect();
+		Self {
+			half_width,
+			cutoff,
+			resolution,
+			phases,
+			table,
+		}
+	}
+
+	/// The kernel of `quality`, built the first time it is used.
+	fn get(quality: ResampleQuality) -> &'static Kernel {
+		static KERNELS: [OnceLock<Kernel>; 3] = [OnceLock::new(), OnceLock::new(), OnceLock::new()];
+		let (index, half_width, beta, cutoff, resolution, phases) = match quality {
+			ResampleQuality::Fast => (0, 4, 5.0, 0.80, 128, 32),
+			ResampleQuality::Good => (1, 8, 7.0, 0.88, 512, 64),
+			ResampleQuality::Best => (2, 16, 9.0, 0.94, 1024, 128),
+		};
+		KERNELS[index].get_or_init(|| Kernel::new(half_width, beta, cutoff, resolution, phases))
+	}
+
+	/// The kernel at `x` samples of `table` from its center, `x` is positive.
+	fn at(&self, x: f32) -> f32 {
+		let i = x as usize;
+		let (value, slope) = self.table[i.min(self.table.len() - 1)];
+		value + slope * (x - i as f32)
+	}
+}
+
+/// Convert the sample rate of a SoundSource, with a [`Resampler`] at a fixed ratio.
 pub struct SampleRateConverter<T: SoundSource> {
 	inner: T,
 	/// The output sample_rate
 	output_sample_rate: u32,
-	/// a buffer contained a `in_len` of input samples, that will be completelly converted in
-	/// `out_len` of ouput samples.
-	in_buffer: Box<[i16]>,
-	out_len: usize,
-	/// The current length of valid samples in `in_buffer`.
-	len: usize,
-	/// The index of the next sample to be generated in the `out_buffer`. `out_buffer` don't exist
-	/// in fact, and it samples are directly outputed in `write_samples`.
-	iter: usize,
+	resampler: Resampler,
 }
 impl<T: SoundSource> SampleRateConverter<T> {
 	/// Create a new SampleRateConverter.
 	///
 	/// This will convert from the sample rate of `inner`, outputing with the given `sample_rate`.
-	pub fn new(inner: T, output_sample_rate: u32) -> Self {
-		use gcd::Gcd;
-
-		// divide the input sample_rate and the ouput sample_rate by its gcd, to find to smallest
-		// pair of input/output buffers that can be fully converted between.
-		let gcd = inner.sample_rate().gcd(output_sample_rate) as usize;
-		let in_len = inner.sample_rate() as usize / gcd * inner.channels() as usize;
-		let out_len = output_sample_rate as usize / gcd * inner.channels() as usize;
-
-		let channels = inner.channels() as usize;
-
-		// in_buffer also contains the first sample of the next buffer.
-		let in_buffer = vec![0; in_len + channels].into_boxed_slice();
-
-		let mut this = Self {
-			len: in_buffer.len() - 1,
-			in_buffer,
-			iter: out_len,
-			out_len,
+	pub fn new(inner: T, output_sample_rate: u32, quality: ResampleQuality) -> Self {
+		let mut resampler = Resampler::new(inner.channels(), quality);
+		resampler.set_ratio(inner.sample_rate() as f64 / output_sample_rate as f64);
+		Self {
 			inner,
 			output_sample_rate,
-		};
-
-		this.reset();
-
-		this
+			resampler,
+		}
 	}
 }
 impl<T: SoundSource> SoundSource for SampleRateConverter<T> {
@@ -121,59 +216,305 @@ impl<T: SoundSource> SoundSource for SampleRateConverter<T> {
 	}
 	fn reset(&mut self) {
 		self.inner.reset();
-
-		let channels = self.inner.channels() as usize;
-		self.len = self.inner.write_samples(&mut self.in_buffer[..]) - channels;
-		self.iter = 0;
+		self.resampler.reset();
+	}
+	fn total_frames(&self) -> Option<u64> {
+		let total = self.inner.total_frames()? as u128 * self.output_sample_rate as u128;
+		Some((total / self.inner.sample_rate() as u128) as u64)
+	}
+	fn seek(&mut self, frame: u64) -> bool {
+		// the fraction of an input frame is lost, so this is only precise
+		// to the input sample rate.
+		let frame = frame as u128 * self.inner.sample_rate() as u128;
+		let frame = (frame / self.output_sample_rate as u128) as u64;
+		if !self.inner.seek(frame) {
+			return false;
+		}
+		self.resampler.seek(frame);
+		true
+	}
+	fn loop_region(&self) -> Option<(u64, u64)> {
+		let (start, end) = self.inner.loop_region()?;
+		let scale = |frame: u64| {
+			(frame as u128 * self.output_sample_rate as u128 / self.inner.sample_rate() as u128) as u64
+		};
+		Some((scale(start), scale(end)))
+	}
+	fn set_direction(&mut self, direction: Direction) -> bool {
+		// the frames already read are interpolated in the new direction,
+		// so the position must be set again with a seek
+		self.inner.set_direction(direction)
+	}
+	fn instance_limit(&self) -> Option<InstanceLimit> {
+		self.inner.instance_limit()
 	}
 	fn write_samples(&mut self, buffer: &mut [i16]) -> usize {
-		let channels = self.inner.channels() as usize;
+		self.resampler.write_samples(&mut self.inner, buffer)
+	}
+}
 
-		if self.output_sample_rate == self.inner.sample_rate() {
-			return self.inner.write_samples(buffer);
+
+
+/// Resample by a ratio that can change at any time, with a windowed sinc.
+///
+/// Unlike the other converters, this doesn't own its SoundSource. The mixer drives it with the
+/// source of each sound, so the ratio can change without rebuilding the chain of converters.
+/// Until the speed is changed from 1.0, samples are passed through untouched.
+///
+/// When played faster, the kernel is stretched by the speed, up to `MAX_STRETCH`, so the
+/// frequencies over the output nyquist are filtered out instead of aliased. The kernel sees the
+/// first frame before the start of the source, and the last one after its end, so a looped sound
+/// doesn't dip at its seam.
+pub struct Resampler {
+	/// how many input frames are consumed for each output frame
+	speed: f64,
+	channels: usize,
+	quality: ResampleQuality,
+	kernel: &'static Kernel,
+	/// how much `weights` is stretched, 0.0 before it is built
+	stretch: f64,
+	/// the taps go from `index + 1 - reach` to `index + reach`
+	reach: usize,
+	/// the `2 * reach` weights of each of the `kernel.phases + 1` positions between two frames,
+	/// each row adding up to 1.0
+	weights: Vec<f32>,
+	/// input frames read from the source, the ones the kernel still reaches and the ones read
+	/// ahead. it starts with `HISTORY` copies of the first frame
+	frames: Vec<i16>,
+	/// the frame of `frames` the output is at
+	index: usize,
+	/// position between the frame `index` (0.0) and the next one (1.0)
+	t: f64,
+	/// the source returned less than asked, so it has no more frames
+	input_ended: bool,
+	/// the first frame was copied before it
+	primed: bool,
+	/// the last frame was already output
+	finished: bool,
+	/// if false, samples are passed through
+	active: bool,
+	/// frames of the source played since the last reset
+	position: u64,
+}
+impl Resampler {
+	/// how many frames are read from the source at a time
+	const CHUNK_FRAMES: usize = 256;
+	/// how many frames behind `index` are kept, more than the kernel reaches at `MAX_STRETCH`
+	const HISTORY: usize = 128;
+	/// how much the kernel is stretched at most, when played faster
+	const MAX_STRETCH: f64 = 4.0;
+	/// the stretch is rounded up to a multiple of this, so a speed that moves a little doesn't
+	/// rebuild the weights
+	const STRETCH_STEP: f64 = 1.0 / 16.0;
+
+	/// Create a new Resampler for a source with the given number of `channels`.
+	pub fn new(channels: u16, quality: ResampleQuality) -> Self {
+		let mut this = Self {
+			speed: 1.0,
+			channels: channels as usize,
+			quality,
+			kernel: Kernel::get(quality),
+			stretch: 0.0,
+			reach: 0,
+			weights: vec![],
+			frames: Vec::with_capacity(
+				(Self::HISTORY * 2 + Self::CHUNK_FRAMES * 2) * channels as usize,
+			),
+			index: 0,
+			t: 0.0,
+			input_ended: false,
+			primed: false,
+			finished: false,
+			active: false,
+			position: 0,
+		};
+		this.seek(0);
+		this
+	}
+
+	/// Change the number of channels of the source. This reallocates, and loses the frames that
+	/// were read ahead.
+	pub fn set_channels(&mut self, channels: u16) {
+		let (speed, position) = (self.speed, self.position);
+		*self = Self::new(channels, self.quality);
+		self.set_ratio(speed);
+		self.position = position;
+		self.active = speed != 1.0;
+	}
+
+	/// Change the kernel, the frames already read are kept.
+	pub fn set_quality(&mut self, quality: ResampleQuality) {
+		self.quality = quality;
+		self.kernel = Kernel::get(quality);
+		self.stretch = 0.0;
+	}
+
+	/// How many frames of the source were played since the last reset. Frames that were read
+	/// ahead, but not played yet, are not counted.
+	pub fn position(&self) -> u64 {
+		self.position
+	}
+
+	/// Set the playback speed, 2.0 plays twice as fast, one octave higher.
+	pub fn set_speed(&mut self, speed: f32) {
+		self.set_ratio(speed as f64);
+	}
+
+	/// The same as `set_speed`, precise enough for a fixed ratio of sample rates.
+	fn set_ratio(&mut self, speed: f64) {
+		// a speed of 0 would never consume input, and never end
+		self.speed = speed.max(1.0 / 1024.0);
+		self.active |= self.speed != 1.0;
+		// built here when the speed doesn't change, so the audio thread doesn't have to
+		if self.active && self.stretch == 0.0 {
+			self.build_weights();
 		}
+	}
 
-		let mut i = 0;
-		while i < buffer.len() {
-			let in_len = self.in_buffer.len() - channels;
-			fn div_up(a: usize, b: usize) -> usize {
-				a / b + (a % b != 0) as usize
-			}
-			let curr_out_len = div_up(self.out_len * self.len, in_len) / channels * channels;
+	/// Build `weights` for the current speed, if they were built for another.
+	fn build_weights(&mut self) {
+		// played faster, the cutoff is lowered under the nyquist of the output
+		let stretch = self.speed.clamp(1.0, Self::MAX_STRETCH);
+		let stretch = (stretch / Self::STRETCH_STEP).ceil() * Self::STRETCH_STEP;
+		if stretch == self.stretch {
+			return;
+		}
+		self.stretch = stretch;
 
-			// if next sample is out of bounds, reset in_buffer
-			if self.iter >= curr_out_len {
-				// if self.len is smaller than in_len, the inner sound already finished.
-				if self.len < in_len {
-					return i;
-				}
+		let kernel = self.kernel;
+		let cutoff = kernel.cutoff / stretch;
+		self.reach = ((kernel.half_width as f64 / cutoff).ceil() as usize).min(Self::HISTORY);
+		let taps = self.reach * 2;
+		// the distance between two taps, in samples of the table
+		let step = cutoff * kernel.resolution as f64;
+		self.weights.clear();
+		for phase in 0..=kernel.phases {
+			let t = phase as f64 / kernel.phases as f64;
+			let first = (1.0 - self.reach as f64 - t) * step;
+			let row = self.weights.len();
+			self.weights
+				.extend((0..taps).map(|k| kernel.at((first + k as f64 * step).abs() as f32)));
+			let total: f32 = self.weights[row..].iter().sum();
+			self.weights[row..].iter_mut().for_each(|x| *x /= total);
+		}
+	}
+
+	/// Forget the interpolation state. Must be called together with the `reset` of the source.
+	pub fn reset(&mut self) {
+		self.seek(0);
+	}
 
-				// the last sample of the last buffer is the start sample of this buffer.
-				self.in_buffer.copy_within(self.len.., 0);
+	/// Forget the interpolation state. Must be called after the source was moved to `position`.
+	pub fn seek(&mut self, position: u64) {
+		self.frames.clear();
+		self.frames.resize(Self::HISTORY * self.channels, 0);
+		self.index = Self::HISTORY;
+		self.t = 0.0;
+		self.input_ended = false;
+		self.primed = false;
+		self.finished = false;
+		self.active = self.speed != 1.0;
+		self.position = position;
+	}
 
-				self.len = self.inner.write_samples(&mut self.in_buffer[channels..]);
-				self.iter = 0;
-			}
+	/// Read from `inner` until `frames` has the frame `frame`, or `inner` has no more frames.
+	fn fill(&mut self, inner: &mut dyn SoundSource, frame: usize) {
+		while !self.input_ended && self.frames.len() / self.channels <= frame {
+			let len = self.frames.len();
+			self.frames
+				.resize(len + Self::CHUNK_FRAMES * self.channels, 0);
+			let read = inner.write_samples(&mut self.frames[len..]) / self.channels;
+			self.frames.truncate(len + read * self.channels);
+			self.input_ended = read < Self::CHUNK_FRAMES;
+		}
+	}
+
+	/// Write the samples of `inner`, resampled by the current speed, to `buffer`.
+	///
+	/// Works like [`SoundSource::write_samples`].
+	pub fn write_samples(&mut self, inner: &mut dyn SoundSource, buffer: &mut [i16]) -> usize {
+		if !self.active {
+			let len = inner.write_samples(buffer);
+			self.position += (len / self.channels) as u64;
+			return len;
+		}
+		if inner.channels() as usize != self.channels {
+			self.set_channels(inner.channels());
+		}
+		if self.finished {
+			return 0;
+		}
+		self.build_weights();
 
-			// j is the float position in in_buffer.
-			let j = ((self.iter / channels) * in_len) as f32 / self.out_len as f32;
+		let (channels, reach, phases) = (self.channels, self.reach, self.kernel.phases);
+		let mut len = 0;
+		for frame in buffer.chunks_exact_mut(channels) {
+			self.fill(inner, self.index);
+			if self.index >= self.frames.len() / channels {
+				self.finished = true;
+				break;
+			}
+			if !self.primed {
+				let start = self.index * channels;
+				for i in 0..start {
+					self.frames[i] = self.frames[start + i % channels];
+				}
+				self.primed = true;
+			}
+			self.fill(inner, self.index + reach);
 
-			let t = j.fract();
-			let j = j as usize * channels;
+			// the output is interpolated between the weights of two phases
+			let phase = self.t * phases as f64;
+			let row = (phase as usize).min(phases - 1);
+			let f = (phase - row as f64) as f32;
+			let a = &self.weights[row * reach * 2..(row + 1) * reach * 2];
+			let b = &self.weights[(row + 1) * reach * 2..(row + 2) * reach * 2];
 
-			for c in 0..channels {
-				// interpolate by t, curr and next sample
-				buffer[i + c] = (self.in_buffer[j + c] as f32 * (1.0 - t)
-					+ self.in_buffer[j + c + channels] as f32 * t)
-					as i16;
+			// the taps past the end of the source get the last frame
+			let first = self.index + 1 - reach;
+			let taps = (self.frames.len() / channels - first).min(reach * 2);
+			let rest = if taps < reach * 2 {
+				let (a, b): (f32, f32) = (a[taps..].iter().sum(), b[taps..].iter().sum());
+				a + (b - a) * f
+			} else {
+				0.0
+			};
+			let (start, last) = (first * channels, self.frames.len() - channels);
+			for (c, out) in frame.iter_mut().enumerate() {
+				let (mut sum_a, mut sum_b) = (0.0, 0.0);
+				for ((x, a), b) in self.frames[start + c..]
+					.iter()
+					.step_by(channels)
+					.zip(&a[..taps])
+					.zip(&b[..taps])
+				{
+					sum_a += *x as f32 * a;
+					sum_b += *x as f32 * b;
+				}
+				let sum = sum_a + (sum_b - sum_a) * f + self.frames[last + c] as f32 * rest;
+				*out = sum.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
 			}
+			len += channels;
 
-			self.iter += channels;
-			i += channels;
+			self.t += self.speed;
+			while self.t >= 1.0 {
+				self.t -= 1.0;
+				self.fill(inner, self.index + 1);
+				if self.index + 1 >= self.frames.len() / channels {
+					self.finished = true;
+					return len;
+				}
+				self.index += 1;
+				self.position += 1;
+			}
 		}
 
-		buffer.len()
+		// forget the frames the kernel doesn't reach anymore
+		if self.index > Self::HISTORY + Self::CHUNK_FRAMES {
+			let old = self.index - Self::HISTORY;
+			self.frames.drain(..old * channels);
+			self.index -= old;
+		}
+		len
 	}
 }
-
-
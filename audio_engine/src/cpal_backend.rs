@@ -0,0 +1,401 @@
+
+
+
+//! Output and input through cpal, for desktop platforms and as the fallback on android.
+
+
+
+use cpal::{
+	SampleRate,
+	StreamError,
+	traits::{ DeviceTrait, HostTrait, StreamTrait }
+};
+
+use std::sync::{ Arc, Mutex };
+
+use crate::input::{ self, InputConfig };
+use crate::mixer;
+use crate::mixer::{ Mixer, SoundSource };
+use crate::tap::{ Tap, TapWriter };
+
+
+
+struct StreamEventLoop {
+	mixer: Arc<Mutex<Mixer>>,
+	/// the channels asked for, `None` for what the os mixes at
+	channels: Option<u16>,
+	stream: Option<cpal::platform::Stream>
+}
+
+impl StreamEventLoop {
+
+	fn run (
+		&mut self,
+		event_channel: std::sync::mpsc::Sender<StreamEvent>,
+		stream_evemt_reciever: std::sync::mpsc::Receiver<StreamEvent>
+	) {
+
+		// trigger first device creation
+		event_channel.send(StreamEvent::RecreateStream).unwrap();
+
+		let mut handled = false;
+		let error_callback = move |err| {
+			log::error!("stream error: {}", err);
+			if !handled {
+				// https://github.com/Rodrigodd/audio-engine/blob/3d0da3711b5cc78e7192d616ebb1d4069920707d/src/engine.rs#L35
+				// the stream could have send multiple errors which has been confirmed on android
+				// (an error before the stream closes, and an error after it closes)
+				handled = true;
+				event_channel.send(StreamEvent::RecreateStream).unwrap()
+			}
+		};
+
+		while let Ok(event) = stream_evemt_reciever.recv() {
+			match event {
+				StreamEvent::RecreateStream => {
+					log::debug!("recreating audio device");
+
+					// https://github.com/Rodrigodd/audio-engine/blob/3d0da3711b5cc78e7192d616ebb1d4069920707d/src/engine.rs#L47
+					// Droping the stream is unsound in android, see:
+					// https://github.com/katyo/oboe-rs/issues/41
+					#[cfg(target_os = "android")]
+					std::mem::forget(self.stream.take());
+
+					#[cfg(not(target_os = "android"))]
+					drop(self.stream.take());
+
+					let stream = create_device(&self.mixer, self.channels, error_callback.clone());
+					let stream = match stream {
+						Ok(x) => x,
+						Err(x) => {
+							log::error!("creating audio device failed: {}", x);
+							return;
+						}
+					};
+					self.stream = Some(stream);
+				},
+				StreamEvent::Drop => return
+			}
+		}
+
+	}
+
+}
+
+
+
+enum StreamEvent {
+	RecreateStream,
+	Drop
+}
+
+
+
+pub struct Backend {
+
+	join: Option<std::thread::JoinHandle<()>>,
+	sender: std::sync::mpsc::Sender<StreamEvent>
+
+}
+
+impl Backend {
+
+	/// the output has `channels` channels when the device has a config
+	/// for them
+	pub fn start (mixer: Arc<Mutex<Mixer>>, channels: Option<u16>) -> Result<Self, &'static str> {
+
+		let (sender, receiver) = std::sync::mpsc::channel::<StreamEvent>();
+
+		let join = {
+			let sender = sender.clone();
+			std::thread::spawn( move || {
+				log::debug!("starting thread");
+				StreamEventLoop { mixer, channels, stream: None }.run(sender, receiver)
+			})
+		};
+
+		Ok(Self {
+			join: Some(join),
+			sender
+		})
+
+	}
+
+}
+
+impl Drop for Backend {
+
+	fn drop (&mut self) {
+
+		self.sender.send(StreamEvent::Drop).unwrap();
+		self.join.take().unwrap().join().unwrap();
+
+	}
+
+}
+
+
+
+fn create_device (
+	mixer: &Arc<Mutex<Mixer>>,
+	channels: Option<u16>,
+	error_callback: impl FnMut(StreamError) + Send + Clone + 'static
+) -> Result<cpal::Stream, &'static str> {
+
+	let host = cpal::default_host();
+	let device = host
+					.default_output_device()
+					.ok_or("no output device available")?;
+	let mut supported_configs_range = device
+										.supported_output_configs()
+										.map_err(|_| "error while querying formats")?
+										.map(|x| {
+											let sample_rate = SampleRate(48000);
+											if x.min_sample_rate() <= sample_rate && sample_rate <= x.max_sample_rate() {
+												return x.with_sample_rate(sample_rate);
+											}
+
+											let sample_rate = SampleRate(44100);
+											if x.min_sample_rate() <= sample_rate && sample_rate <= x.max_sample_rate() {
+												return x.with_sample_rate(sample_rate);
+											}
+
+											x.with_max_sample_rate()
+										})
+										.collect::<Vec<_>>();
+
+	supported_configs_range.sort_unstable_by(|a, b| {
+		let key = |x: &cpal::SupportedStreamConfig| {
+			(
+				Some(x.channels()) == channels,
+				x.sample_rate().0 == 48000,
+				x.sample_rate().0 == 44100,
+				x.channels() == 2,
+				x.channels() == 1,
+				x.sample_format() == cpal::SampleFormat::I16,
+				x.sample_rate().0
+			)
+		};
+		key(a).cmp(&key(b))
+	});
+
+	// the default config is what the os mixes at, so it is tried
+	// first, it avoids resampling twice. unless it has other channels
+	// than the ones asked for
+	match device.default_output_config() {
+		Ok(config) if channels.is_none_or(|x| x == config.channels()) => supported_configs_range.push(config),
+		Ok(_) => (),
+		Err(err) => log::debug!("no default output config: {}", err)
+	}
+
+	if log::max_level() >= log::LevelFilter::Trace {
+		for config in &supported_configs_range {
+			log::trace!("config {:?}", config);
+		}
+	}
+
+	let stream = loop {
+		let config = if let Some(config) = supported_configs_range.pop() {
+			config
+		} else {
+			return Err("no supported config");
+		};
+		let sample_format = config.sample_format();
+		let config = config.config();
+		mixer
+			.lock()
+			.unwrap()
+			.set_config(config.channels, mixer::SampleRate(config.sample_rate.0));
+
+		let stream = {
+			use cpal::SampleFormat::*;
+			match sample_format {
+				I16 => stream::<i16, _>(mixer, error_callback.clone(), &device, &config),
+				U16 => stream::<u16, _>(mixer, error_callback.clone(), &device, &config),
+				F32 => stream_f32(mixer, error_callback.clone(), &device, &config)
+			}
+		};
+
+		let stream = match stream {
+			Ok(x) => {
+				log::info!("created {:?} stream with config {:?}", sample_format, config);
+				x
+			},
+			Err(e) => {
+				log::error!("failed to create stream with config {:?}: {:?}", config, e);
+				continue;
+			}
+		};
+
+		stream.play().unwrap();
+		break stream;
+	};
+
+	Ok(stream)
+
+}
+
+
+
+fn stream <T: cpal::Sample, E: FnMut(StreamError) + Send + 'static> (
+	mixer: &Arc<Mutex<Mixer>>,
+	error_callback: E,
+	device: &cpal::Device,
+	config: &cpal::StreamConfig
+) -> Result<cpal::Stream, cpal::BuildStreamError> {
+
+	let mixer = mixer.clone();
+	let mut input_buffer = Vec::new();
+	device.build_output_stream(
+		config,
+		move |output_buffer: &mut [T], _| {
+			input_buffer.clear();
+			input_buffer.resize(output_buffer.len(), 0);
+			mixer.lock().unwrap().write_samples(&mut input_buffer);
+			// write sample to output buffer
+			output_buffer
+				.iter_mut()
+				.zip(input_buffer.iter())
+				.for_each(|(a, b)| *a = T::from(b));
+		},
+		error_callback
+	)
+
+}
+
+
+
+/// like `stream`, without going through i16, so the mix keeps its
+/// headroom until the device
+fn stream_f32 <E: FnMut(StreamError) + Send + 'static> (
+	mixer: &Arc<Mutex<Mixer>>,
+	error_callback: E,
+	device: &cpal::Device,
+	config: &cpal::StreamConfig
+) -> Result<cpal::Stream, cpal::BuildStreamError> {
+
+	let mixer = mixer.clone();
+	device.build_output_stream(
+		config,
+		move |output_buffer: &mut [f32], _| {
+			mixer.lock().unwrap().write_samples_f32(output_buffer);
+		},
+		error_callback
+	)
+
+}
+
+
+
+/// records the default input device through cpal
+///
+/// the stream lives on its own thread, as cpal streams can't be sent
+/// to other threads on every platform
+pub struct InputBackend {
+	sender: std::sync::mpsc::Sender<()>
+}
+
+impl InputBackend {
+
+	pub fn start (config: InputConfig) -> Result<(Self, Tap), &'static str> {
+
+		let (sender, receiver) = std::sync::mpsc::channel::<()>();
+		let (ring_sender, ring_receiver) = std::sync::mpsc::channel();
+
+		std::thread::spawn(move || {
+			let stream = match create_input(config) {
+				Ok((stream, ring)) => {
+					let _ = ring_sender.send(Ok(ring));
+					stream
+				},
+				Err(err) => {
+					let _ = ring_sender.send(Err(err));
+					return;
+				}
+			};
+			// recording until the input is dropped
+			let _ = receiver.recv();
+
+			#[cfg(target_os = "android")]
+			std::mem::forget(stream);
+
+			#[cfg(not(target_os = "android"))]
+			drop(stream);
+		});
+
+		let ring = ring_receiver.recv().map_err(|_| "the cpal input thread stopped")??;
+		Ok((Self { sender }, ring))
+
+	}
+
+}
+
+impl Drop for InputBackend {
+
+	fn drop (&mut self) {
+
+		// the thread is not joined, a monitor may be dropped on the
+		// audio thread
+		let _ = self.sender.send(());
+
+	}
+
+}
+
+
+
+fn create_input (config: InputConfig) -> Result<(cpal::Stream, Tap), &'static str> {
+
+	let host = cpal::default_host();
+	let device = host
+					.default_input_device()
+					.ok_or("no input device available")?;
+	let supported = device
+					.default_input_config()
+					.map_err(|_| "error while querying input formats")?;
+
+	let sample_format = supported.sample_format();
+	let stream_config = supported.config();
+	let (writer, ring) = input::ring(config.buffer, stream_config.channels, stream_config.sample_rate.0);
+
+	let error_callback = |err| log::error!("input stream error: {}", err);
+	let stream = {
+		use cpal::SampleFormat::*;
+		match sample_format {
+			I16 => input_stream::<i16>(writer, error_callback, &device, &stream_config),
+			U16 => input_stream::<u16>(writer, error_callback, &device, &stream_config),
+			F32 => input_stream::<f32>(writer, error_callback, &device, &stream_config)
+		}
+	};
+	let stream = stream.map_err(|err| {
+		log::error!("failed to create input stream with config {:?}: {:?}", stream_config, err);
+		"failed to create input stream"
+	})?;
+
+	log::info!("created {:?} input stream with config {:?}", sample_format, stream_config);
+	stream.play().map_err(|_| "failed to start input stream")?;
+	Ok((stream, ring))
+
+}
+
+
+
+fn input_stream <T: cpal::Sample> (
+	writer: TapWriter,
+	error_callback: impl FnMut(StreamError) + Send + 'static,
+	device: &cpal::Device,
+	config: &cpal::StreamConfig
+) -> Result<cpal::Stream, cpal::BuildStreamError> {
+
+	let mut buffer = Vec::new();
+	device.build_input_stream(
+		config,
+		move |input_buffer: &[T], _| {
+			buffer.clear();
+			buffer.extend(input_buffer.iter().map(|x| x.to_i16()));
+			writer.write(&buffer);
+		},
+		error_callback
+	)
+
+}
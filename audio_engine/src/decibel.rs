@@ -0,0 +1,36 @@
+
+
+
+//! Volumes in decibels, the way sound designers think of them.
+//!
+//! `0.0` dB is a gain of `1.0`, and every 6 dB is about twice or half the amplitude. The
+//! conversions clamp to a floor, under which the volume is silence, and to a ceiling, so a typo
+//! like `60.0` for `-60.0` doesn't blow up the mix.
+
+
+
+/// the quietest volume, at or under it the gain is `0.0`
+pub const MIN_DB: f32 = -96.0;
+
+/// the loudest volume, a gain of about 16
+pub const MAX_DB: f32 = 24.0;
+
+
+
+/// the gain of `db`, `0.0` at or under [`MIN_DB`]
+pub fn db_to_gain (db: f32) -> f32 {
+	if db.is_nan() || db <= MIN_DB {
+		return 0.0;
+	}
+	10f32.powf(db.min(MAX_DB) / 20.0)
+}
+
+
+
+/// the volume of `gain` in dB, [`MIN_DB`] for silence
+pub fn gain_to_db (gain: f32) -> f32 {
+	if gain.is_nan() || gain <= 0.0 {
+		return MIN_DB;
+	}
+	(20.0 * gain.log10()).clamp(MIN_DB, MAX_DB)
+}
@@ -0,0 +1,197 @@
+
+
+
+//! An echo, with feedback, that can follow the tempo of the music.
+
+
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::time::Duration;
+
+use crate::effect::Effect;
+
+
+
+/// the longest echo, the delay line is allocated for it
+const MAX_DELAY: Duration = Duration::from_secs(4);
+
+
+
+/// how long until the echo of a [`Delay`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DelayTime {
+	Fixed(Duration),
+	/// `beats` at `bpm`, so the echoes stay on the beat of the music
+	Beats {
+		bpm: f32,
+		beats: f32
+	}
+}
+
+impl DelayTime {
+
+	fn seconds (&self) -> f32 {
+		match *self {
+			DelayTime::Fixed(time) => time.as_secs_f32(),
+			DelayTime::Beats { bpm, beats } => 60.0 / bpm.max(1.0) * beats
+		}
+	}
+
+}
+
+
+
+/// settings of a [`Delay`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DelayConfig {
+
+	/// up to 4 seconds
+	pub time: DelayTime,
+	/// how much of each echo comes back, under `1.0` so it dies out
+	pub feedback: f32,
+	/// gain of the echoes
+	pub wet: f32,
+	/// gain of the original sound
+	pub dry: f32
+
+}
+
+impl Default for DelayConfig {
+	fn default () -> Self {
+		Self {
+			time: DelayTime::Fixed(Duration::from_millis(250)),
+			feedback: 0.4,
+			wet: 0.5,
+			dry: 1.0
+		}
+	}
+}
+
+
+
+/// the parameters shared with the controls, as the bits of `f32`s
+struct Params {
+	seconds: AtomicU32,
+	feedback: AtomicU32,
+	wet: AtomicU32,
+	dry: AtomicU32
+}
+
+fn load (x: &AtomicU32) -> f32 {
+	f32::from_bits(x.load(Ordering::Relaxed))
+}
+
+fn store (x: &AtomicU32, value: f32) {
+	x.store(value.to_bits(), Ordering::Relaxed);
+}
+
+
+
+/// a delay line, as an [`Effect`]
+///
+/// works on a sound or on a group. every channel echoes on its own
+pub struct Delay {
+
+	params: Arc<Params>,
+	/// interleaved, made on the first call for `MAX_DELAY`
+	line: Vec<f32>,
+	/// the frame of `line` written next
+	position: usize,
+	channels: usize,
+	sample_rate: u32
+
+}
+
+impl Delay {
+
+
+	pub fn new (config: DelayConfig) -> Self {
+		Self {
+			params: Arc::new(Params {
+				seconds: AtomicU32::new(config.time.seconds().to_bits()),
+				feedback: AtomicU32::new(config.feedback.to_bits()),
+				wet: AtomicU32::new(config.wet.to_bits()),
+				dry: AtomicU32::new(config.dry.to_bits())
+			}),
+			line: vec![],
+			position: 0,
+			channels: 0,
+			sample_rate: 0
+		}
+	}
+
+
+	/// change the delay after it was given to a sound or group
+	pub fn controls (&self) -> DelayControls {
+		DelayControls { params: self.params.clone() }
+	}
+
+
+}
+
+impl Effect for Delay {
+
+	fn process (&mut self, frames: &mut [f32], channels: u16, sample_rate: u32) {
+		let channels = channels as usize;
+		if self.channels != channels || self.sample_rate != sample_rate {
+			let length = (MAX_DELAY.as_secs_f32() * sample_rate as f32) as usize;
+			self.line = vec![0.0; length * channels];
+			self.position = 0;
+			self.channels = channels;
+			self.sample_rate = sample_rate;
+		}
+
+		let length = self.line.len() / channels;
+		let delay = ((load(&self.params.seconds) * sample_rate as f32) as usize).clamp(1, length);
+		let feedback = load(&self.params.feedback);
+		let wet = load(&self.params.wet);
+		let dry = load(&self.params.dry);
+		for frame in frames.chunks_exact_mut(channels) {
+			let read = (self.position + length - delay) % length;
+			for (c, x) in frame.iter_mut().enumerate() {
+				let delayed = self.line[read * channels + c];
+				self.line[self.position * channels + c] = *x + delayed * feedback;
+				*x = *x * dry + delayed * wet;
+			}
+			self.position = (self.position + 1) % length;
+		}
+	}
+
+}
+
+
+
+/// changes a [`Delay`] from any thread
+///
+/// the changes apply from the next buffer that is mixed
+#[derive(Clone)]
+pub struct DelayControls {
+	params: Arc<Params>
+}
+
+impl DelayControls {
+
+
+	/// a new tempo for a synced delay is set with `DelayTime::Beats`
+	pub fn set_time (&self, time: DelayTime) {
+		store(&self.params.seconds, time.seconds());
+	}
+
+
+	pub fn set_feedback (&self, feedback: f32) {
+		store(&self.params.feedback, feedback);
+	}
+
+
+	pub fn set_wet (&self, wet: f32) {
+		store(&self.params.wet, wet);
+	}
+
+
+	pub fn set_dry (&self, dry: f32) {
+		store(&self.params.dry, dry);
+	}
+
+
+}
@@ -0,0 +1,231 @@
+
+
+
+//! Nonlinear effects, for retro and lo-fi sounds.
+//!
+//! An [`Overdrive`] saturates the sound smoothly, a [`Bitcrusher`] lowers its bit depth and
+//! sample rate, and a [`Waveshaper`] maps it through any curve.
+
+
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU32, Ordering };
+
+use crate::effect::Effect;
+
+
+
+/// full scale, in the units of the mix
+const FULL_SCALE: f32 = i16::MAX as f32;
+
+/// points in the table of a [`Waveshaper`]
+const TABLE_SIZE: usize = 1024;
+
+
+
+/// a soft clipping distortion, as an [`Effect`]
+pub struct Overdrive {
+	/// how hard the sound is pushed into the curve
+	drive: f32,
+	/// gain applied after the curve
+	level: f32
+}
+
+impl Overdrive {
+
+
+	/// a `drive` of `1.0` barely changes the sound, `10.0` is heavy
+	pub fn new (drive: f32) -> Self {
+		Self { drive: drive.max(0.01), level: 1.0 }
+	}
+
+
+	/// the gain after the distortion, which makes the sound louder
+	pub fn level (mut self, level: f32) -> Self {
+		self.level = level;
+		self
+	}
+
+
+}
+
+impl Effect for Overdrive {
+
+	fn process (&mut self, frames: &mut [f32], _: u16, _: u32) {
+		// divided by tanh(drive) so full scale stays full scale
+		let scale = self.level / self.drive.tanh();
+		for x in frames.iter_mut() {
+			*x = (*x / FULL_SCALE * self.drive).tanh() * scale * FULL_SCALE;
+		}
+	}
+
+}
+
+
+
+/// the parameters shared with the controls
+struct Params {
+	bits: AtomicU32,
+	hold: AtomicU32
+}
+
+
+
+/// lowers the bit depth and the sample rate, as an [`Effect`]
+pub struct Bitcrusher {
+
+	params: Arc<Params>,
+	/// the held frame of each channel
+	held: Vec<f32>,
+	/// frames left until the next one is held
+	countdown: u32
+
+}
+
+impl Bitcrusher {
+
+
+	/// keeps `bits` bits of every sample, from 1 to 16, and holds every
+	/// sample for `hold` frames, which divides the sample rate
+	pub fn new (bits: u32, hold: u32) -> Self {
+		let params = Arc::new(Params {
+			bits: AtomicU32::new(bits),
+			hold: AtomicU32::new(hold)
+		});
+		Self { params, held: vec![], countdown: 0 }
+	}
+
+
+	/// change the bitcrusher after it was given to a sound
+	pub fn controls (&self) -> BitcrusherControls {
+		BitcrusherControls { params: self.params.clone() }
+	}
+
+
+}
+
+impl Effect for Bitcrusher {
+
+	fn process (&mut self, frames: &mut [f32], channels: u16, _: u32) {
+		let bits = self.params.bits.load(Ordering::Relaxed).clamp(1, 16);
+		let hold = self.params.hold.load(Ordering::Relaxed).max(1);
+		if self.held.len() != channels as usize {
+			self.held = vec![0.0; channels as usize];
+			self.countdown = 0;
+		}
+
+		// the size of one step, with `bits` bits over the range of an i16
+		let step = (1u32 << (16 - bits)) as f32;
+		for frame in frames.chunks_exact_mut(channels as usize) {
+			if self.countdown == 0 {
+				for (held, x) in self.held.iter_mut().zip(frame.iter()) {
+					*held = (*x / step).round() * step;
+				}
+				self.countdown = hold;
+			}
+			self.countdown -= 1;
+			frame.copy_from_slice(&self.held);
+		}
+	}
+
+}
+
+
+
+/// changes the bit depth and the hold of a [`Bitcrusher`] from any
+/// thread
+#[derive(Clone)]
+pub struct BitcrusherControls {
+	params: Arc<Params>
+}
+
+impl BitcrusherControls {
+
+
+	pub fn set_bits (&self, bits: u32) {
+		self.params.bits.store(bits, Ordering::Relaxed);
+	}
+
+
+	pub fn set_hold (&self, hold: u32) {
+		self.params.hold.store(hold, Ordering::Relaxed);
+	}
+
+
+	pub fn bits (&self) -> u32 {
+		self.params.bits.load(Ordering::Relaxed)
+	}
+
+
+	pub fn hold (&self) -> u32 {
+		self.params.hold.load(Ordering::Relaxed)
+	}
+
+
+}
+
+
+
+/// maps every sample through a curve, as an [`Effect`]
+///
+/// the curve is sampled into a table once, so it can be as slow as
+/// needed
+pub struct Waveshaper {
+	table: Vec<f32>
+}
+
+impl Waveshaper {
+
+
+	/// `curve` maps the range `-1.0..=1.0` to itself, where `1.0` is
+	/// full scale
+	pub fn new (curve: impl Fn(f32) -> f32) -> Self {
+		let table = (0..TABLE_SIZE)
+			.map(|i| curve(i as f32 / (TABLE_SIZE - 1) as f32 * 2.0 - 1.0))
+			.collect();
+		Self { table }
+	}
+
+
+	/// a curve given as points, spread evenly over `-1.0..=1.0`
+	///
+	/// with less than two points the curve is a straight line, which
+	/// leaves the sound as it is
+	pub fn from_points (points: &[f32]) -> Self {
+		if points.len() < 2 {
+			return Self { table: vec![-1.0, 1.0] };
+		}
+		Self { table: points.to_vec() }
+	}
+
+
+	/// folds the sound back over itself past the threshold, for
+	/// metallic sounds
+	pub fn fold (threshold: f32) -> Self {
+		let threshold = threshold.clamp(0.01, 1.0);
+		Self::new(|x| {
+			let period = 4.0 * threshold;
+			// a triangle wave of the input, between -threshold and threshold
+			let x = (x + threshold).rem_euclid(period);
+			(if x < 2.0 * threshold { x - threshold } else { 3.0 * threshold - x }) / threshold
+		})
+	}
+
+
+}
+
+impl Effect for Waveshaper {
+
+	fn process (&mut self, frames: &mut [f32], _: u16, _: u32) {
+		let last = (self.table.len() - 1) as f32;
+		for x in frames.iter_mut() {
+			// linear interpolation between the two nearest points
+			let position = ((*x / FULL_SCALE).clamp(-1.0, 1.0) + 1.0) / 2.0 * last;
+			let index = (position as usize).min(self.table.len() - 2);
+			let fraction = position - index as f32;
+			let y = self.table[index] + (self.table[index + 1] - self.table[index]) * fraction;
+			*x = y * FULL_SCALE;
+		}
+	}
+
+}
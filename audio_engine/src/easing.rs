@@ -0,0 +1,118 @@
+
+
+
+//! Easing curves, for volume, pan, speed and filter changes that take some time.
+
+
+
+/// the shape of a change over time, see
+/// [`Sound::animate_volume`](crate::Sound::animate_volume)
+///
+/// `In` curves start slow, `Out` curves end slow, and `InOut` curves
+/// do both. the exponential curves suit volumes and frequencies,
+/// which are heard in ratios
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Easing {
+	#[default]
+	Linear,
+	QuadIn,
+	QuadOut,
+	QuadInOut,
+	CubicIn,
+	CubicOut,
+	CubicInOut,
+	SineInOut,
+	ExpIn,
+	ExpOut
+}
+
+impl Easing {
+
+	/// how far along the change is, for `t` from `0.0` to `1.0` of
+	/// its duration
+	pub fn apply (self, t: f32) -> f32 {
+		let t = t.clamp(0.0, 1.0);
+		match self {
+			Easing::Linear => t,
+			Easing::QuadIn => t * t,
+			Easing::QuadOut => t * (2.0 - t),
+			Easing::QuadInOut => if t < 0.5 { 2.0 * t * t } else { 1.0 - 2.0 * (1.0 - t) * (1.0 - t) },
+			Easing::CubicIn => t * t * t,
+			Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+			Easing::CubicInOut => if t < 0.5 { 4.0 * t * t * t } else { 1.0 - 4.0 * (1.0 - t).powi(3) },
+			Easing::SineInOut => 0.5 - 0.5 * (std::f32::consts::PI * t).cos(),
+			// 2^(10(t - 1)), moved so it starts at 0 and ends at 1
+			Easing::ExpIn => if t == 0.0 { 0.0 } else { (2f32.powf(10.0 * (t - 1.0)) - 1.0 / 1024.0) * 1024.0 / 1023.0 },
+			Easing::ExpOut => if t == 1.0 { 1.0 } else { (1.0 - 2f32.powf(-10.0 * t)) * 1024.0 / 1023.0 }
+		}
+	}
+
+}
+
+
+
+/// a value that moves to a target along an [`Easing`], one frame at
+/// a time
+#[derive(Debug, Clone, Copy)]
+pub (crate) struct Ramp {
+	pub value: f32,
+	pub target: f32,
+	/// the value when the change started
+	start: f32,
+	easing: Easing,
+	/// frames of the whole change
+	frames: u32,
+	/// frames left until `value` reaches `target`
+	remaining: u32
+}
+
+impl Ramp {
+
+	pub fn new (value: f32) -> Self {
+		Self { value, target: value, start: value, easing: Easing::Linear, frames: 0, remaining: 0 }
+	}
+
+	/// move linearly from the current value to `target` in `frames`
+	/// frames
+	pub fn set (&mut self, target: f32, frames: u32) {
+		self.set_eased(target, frames, Easing::Linear);
+	}
+
+	/// move from the current value to `target` in `frames` frames,
+	/// along `easing`
+	pub fn set_eased (&mut self, target: f32, frames: u32, easing: Easing) {
+		self.start = self.value;
+		self.target = target;
+		self.easing = easing;
+		self.frames = frames;
+		self.remaining = frames;
+		if frames == 0 {
+			self.value = target;
+		}
+	}
+
+	/// the value of the next frame
+	pub fn next (&mut self) -> f32 {
+		let value = self.value;
+		if self.remaining > 0 {
+			self.skip(1);
+		}
+		value
+	}
+
+	pub fn is_done (&self) -> bool {
+		self.remaining == 0
+	}
+
+	/// advance `frames` frames at once
+	pub fn skip (&mut self, frames: u32) {
+		self.remaining = self.remaining.saturating_sub(frames);
+		self.value = if self.remaining == 0 {
+			self.target
+		} else {
+			let t = (self.frames - self.remaining) as f32 / self.frames as f32;
+			self.start + (self.target - self.start) * self.easing.apply(t)
+		};
+	}
+
+}
@@ -0,0 +1,51 @@
+
+
+
+//! Effects that change the samples of a sound or a group before they are mixed.
+
+
+
+use std::sync::atomic::{ AtomicU32, Ordering };
+
+
+
+/// processes the samples of a sound, one buffer at a time
+///
+/// any `FnMut(f32) -> f32` closure is an effect that maps every
+/// sample on its own. implement this for effects that keep state
+/// between samples, like filters and delays
+pub trait Effect: Send {
+
+	/// change `frames` in place
+	///
+	/// the samples are interleaved, with `channels` per frame, and in
+	/// the range of an i16. the buffer is the part of the sound that
+	/// is mixed next, its length changes between calls
+	fn process (&mut self, frames: &mut [f32], channels: u16, sample_rate: u32);
+
+}
+
+impl<F: FnMut(f32) -> f32 + Send> Effect for F {
+	fn process (&mut self, frames: &mut [f32], _: u16, _: u32) {
+		for x in frames.iter_mut() {
+			*x = self(*x);
+		}
+	}
+}
+
+
+
+/// identifies an effect in the chain of a sound or a group, see
+/// [`Sound::add_effect`](crate::Sound::add_effect)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EffectId(u32);
+
+impl EffectId {
+
+	/// a new id, never given before
+	pub (crate) fn next () -> Self {
+		static NEXT: AtomicU32 = AtomicU32::new(0);
+		Self(NEXT.fetch_add(1, Ordering::Relaxed))
+	}
+
+}
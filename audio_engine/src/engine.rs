@@ -1,230 +1,799 @@
 
 
 
-use cpal::{
-	SampleRate,
-	StreamError,
-	traits::{ DeviceTrait, HostTrait, StreamTrait }
-};
-
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{ Arc, Mutex };
+use std::time::Duration;
 
+use crate::bank::{ self, BankEvent };
+use crate::combinator::SoundSourceExt;
 use crate::mixer;
-use crate::mixer::{ Mixer, Sound, SoundSource };
-use crate::converter::{ ChannelConverter, SampleRateConverter };
+use crate::mixer::{ Command, EngineTime, Group, GroupId, Mixer, Sound, SoundEvent, SoundId, SoundSource, StealPolicy };
+use crate::queue::Queue;
+use crate::sound_data::SoundData;
+use crate::converter::{ self, ResampleQuality };
+use crate::effect::{ Effect, EffectId };
+use crate::error::Error;
+use crate::hrtf::Hrtf;
+use crate::input::{ self, AudioInput, InputConfig };
+use crate::layout::ChannelLayout;
+use crate::limiter::LimiterConfig;
+use crate::meter::{ Levels, Meter };
+use crate::monitor::{ Monitor, MonitorControls };
+use crate::offline::OfflineBackend;
+use crate::random::RandomSound;
+use crate::recorder::Recording;
+use crate::replay::Replay;
+use crate::sequence::SequenceSound;
+use crate::snapshot::Snapshot;
+use crate::spatial::Listener;
+use crate::tap::{ self, Tap };
+use crate::transport::Transport;
 
 
 
-use backend::Backend;
+/// The main struct of the crate
+///
+/// This holds all existing sounds and the output stream of the backend
+pub struct AudioEngine {
 
-mod backend {
+	mixer: Arc<Mutex<Mixer>>,
+	commands: Arc<Queue<Command>>,
+	events: Arc<Queue<SoundEvent>>,
+	meter: Arc<Meter>,
+	/// the events of the loaded banks, by name
+	bank: Arc<Mutex<HashMap<String, BankEvent>>>,
+	/// only kept to be dropped with the engine
+	_backend: Box<dyn Send>
 
+}
 
-	use super::create_device;
-	use crate::mixer::Mixer;
-	use std::sync::{ Arc, Mutex };
+impl AudioEngine {
 
 
-	struct StreamEventLoop {
-		mixer: Arc<Mutex<Mixer>>,
-		stream: Option<cpal::platform::Stream>
+	/// tries to create a new Audio Engine
+	///
+	/// the backend will spawn a new thread where the sound samples
+	/// will be sampled, mixed and outputed to the output stream
+	pub fn new () -> Result<Self, Error> {
+		AudioEngineBuilder::new().build()
 	}
 
-	impl StreamEventLoop {
 
-		fn run (
-			&mut self,
-			event_channel: std::sync::mpsc::Sender<StreamEvent>,
-			stream_evemt_reciever: std::sync::mpsc::Receiver<StreamEvent>
-		) {
+	/// configure the output stream before creating the engine
+	pub fn builder () -> AudioEngineBuilder {
+		AudioEngineBuilder::new()
+	}
 
-			// trigger first device creation
-			event_channel.send(StreamEvent::RecreateStream).unwrap();
 
-			let mut handled = false;
-			let error_callback = move |err| {
-				log::error!("stream error: {}", err);
-				if !handled {
-					// https://github.com/Rodrigodd/audio-engine/blob/3d0da3711b5cc78e7192d616ebb1d4069920707d/src/engine.rs#L35
-					// the stream could have send multiple errors which has been confirmed on android
-					// (an error before the stream closes, and an error after it closes)
-					handled = true;
-					event_channel.send(StreamEvent::RecreateStream).unwrap()
-				}
-			};
+	/// create an engine without an output device
+	///
+	/// nothing is mixed until the returned backend is advanced, with
+	/// the same output every run. for tests without an audio device
+	pub fn offline (channels: u16, sample_rate: u32) -> (Self, OfflineBackend) {
+		let mixer = Arc::new(Mutex::new(Mixer::new(channels, mixer::SampleRate(sample_rate))));
+		let commands = mixer.lock().unwrap().commands();
+		let events = mixer.lock().unwrap().events();
+		let meter = mixer.lock().unwrap().meter();
+
+		let engine = Self {
+			mixer: mixer.clone(),
+			commands,
+			events,
+			meter,
+			bank: Arc::new(Mutex::new(HashMap::new())),
+			_backend: Box::new(())
+		};
+		(engine, OfflineBackend::new(mixer))
+	}
 
-			while let Ok(event) = stream_evemt_reciever.recv() {
-				match event {
-					StreamEvent::RecreateStream => {
-						log::debug!("recreating audio device");
 
-						// https://github.com/Rodrigodd/audio-engine/blob/3d0da3711b5cc78e7192d616ebb1d4069920707d/src/engine.rs#L47
-						// Droping the stream is unsound in android, see:
-						// https://github.com/katyo/oboe-rs/issues/41
-						#[cfg(target_os = "android")]
-						std::mem::forget(self.stream.take());
+	/// mix the next `duration` into a buffer, as fast as possible
+	///
+	/// meant for engines created with [`AudioEngine::offline`], to
+	/// bake sounds or compare them with a recording. with a device,
+	/// the rendered part is not played by it
+	pub fn render_offline (&self, duration: Duration) -> Vec<i16> {
+		crate::offline::render(&self.mixer, duration)
+	}
 
-						#[cfg(not(target_os = "android"))]
-						drop(self.stream.take());
 
-						let stream = create_device(&self.mixer, error_callback.clone());
-						let stream = match stream {
-							Ok(x) => x,
-							Err(x) => {
-								log::error!("creating audio device failed: {}", x);
-								return;
-							}
-						};
-						self.stream = Some(stream);
-					},
-					StreamEvent::Drop => return
-				}
-			}
+	/// the frame the next mixed buffer starts on, to schedule sounds
+	/// with [`Sound::play_at`]
+	///
+	/// it moves a whole buffer at a time, and a buffer is heard after
+	/// the latency of the device. so a sound played at `now()` starts
+	/// with the next buffer, at the earliest
+	pub fn now (&self) -> EngineTime {
+		let mixer = self.mixer.lock().unwrap();
+		EngineTime { frame: mixer.clock(), sample_rate: mixer.sample_rate() }
+	}
 
-		}
 
+	/// the sample rate that is currently being outputed to the device
+	pub fn sample_rate(&self) -> u32 {
+		self.mixer.lock().unwrap().sample_rate()
 	}
 
 
+	/// the sample rate of the current output device
+	///
+	/// may change when device changes
+	pub fn channels (&self) -> u16 {
+		self.mixer.lock().unwrap().channels()
+	}
+
 
-	enum StreamEvent {
-		RecreateStream,
-		Drop
+	/// set how long volume changes of playing sounds take
+	///
+	/// defaults to 10ms, long enough to avoid the clicks of abrupt
+	/// changes. `Duration::ZERO` applies changes right away
+	pub fn set_volume_smoothing (&self, duration: Duration) {
+		self.mixer.lock().unwrap().set_volume_smoothing(duration);
 	}
 
 
+	/// how precisely the sounds are resampled, to the rate of the
+	/// output and when their speed or pitch changes, see
+	/// [`ResampleQuality`]
+	///
+	/// the speed of every sound follows it at once, but the sounds
+	/// already created keep the quality of their rate conversion
+	pub fn set_resample_quality (&self, quality: ResampleQuality) {
+		self.mixer.lock().unwrap().set_resample_quality(quality);
+	}
+
 
-	pub struct Backend {
+	/// set the volume of the whole mix
+	///
+	/// applied after every sound is mixed, and smoothed like the
+	/// volume of each sound
+	pub fn set_master_volume (&self, volume: f32) {
+		mixer::send(&self.mixer, &self.commands, Command::SetMasterVolume(volume));
+	}
 
-		join: Option<std::thread::JoinHandle<()>>,
-		sender: std::sync::mpsc::Sender<StreamEvent>
 
+	/// set the volume of the whole mix in dB, like
+	/// [`Sound::set_volume_db`]
+	pub fn set_master_volume_db (&self, db: f32) {
+		self.set_master_volume(crate::db_to_gain(db));
 	}
 
-	impl Backend {
 
-		pub (super) fn start (mixer: Arc<Mutex<Mixer>>) -> Result<Self, &'static str> {
+	/// silence the whole mix, or bring it back to the master volume
+	pub fn set_muted (&self, muted: bool) {
+		mixer::send(&self.mixer, &self.commands, Command::SetMuted(muted));
+	}
 
-			let (sender, receiver) = std::sync::mpsc::channel::<StreamEvent>();
 
-			let join = {
-				let sender = sender.clone();
-				std::thread::spawn( move || {
-					log::debug!("starting thread");
-					StreamEventLoop { mixer, stream: None }.run(sender, receiver)
-				})
-			};
+	/// configure the limiter of the output, or turn it off with `None`
+	///
+	/// the limiter turns the mix down before it would clip, so many
+	/// loud sounds at once don't distort. it is on by default, with
+	/// [`LimiterConfig::default`], and delays the output by 1ms
+	pub fn set_limiter (&self, config: Option<LimiterConfig>) {
+		mixer::send(&self.mixer, &self.commands, Command::SetLimiter(config));
+	}
 
-			Ok(Self {
-				join: Some(join),
-				sender
-			})
 
+	/// add `effect` at the end of the chain of the whole mix
+	///
+	/// the master effects come after every group, and before the
+	/// master volume and the limiter
+	pub fn add_master_effect (&self, effect: impl Effect + 'static) -> EffectId {
+		let id = EffectId::next();
+		mixer::send(&self.mixer, &self.commands, Command::AddMasterEffect(id, Box::new(effect)));
+		id
+	}
+
+
+	/// remove an effect added with [`AudioEngine::add_master_effect`]
+	pub fn remove_master_effect (&self, effect: EffectId) {
+		mixer::send(&self.mixer, &self.commands, Command::RemoveMasterEffect(effect));
+	}
+
+
+	pub fn clear_master_effects (&self) {
+		mixer::send(&self.mixer, &self.commands, Command::ClearMasterEffects);
+	}
+
+
+	/// add noise before rounding the output to 16 bits
+	///
+	/// this turns the distortion of quiet sounds and fades into a low
+	/// hiss, which is less audible. off by default
+	pub fn set_dither (&self, dither: bool) {
+		mixer::send(&self.mixer, &self.commands, Command::SetDither(dither));
+	}
+
+
+	/// play the same mix on every speaker
+	///
+	/// for players who hear with one ear or use a single speaker, and
+	/// to check that nothing cancels out when the channels are summed
+	pub fn set_mono (&self, mono: bool) {
+		mixer::send(&self.mixer, &self.commands, Command::SetMono(mono));
+	}
+
+
+	/// play at most `max` sounds at once, or any number with `None`
+	///
+	/// a sound that starts over the limit stops one that plays, with
+	/// a short fade, as `policy` chooses, or the least important ones
+	/// are made virtual. this bounds the work of the mixer. see
+	/// [`SoundData::max_instances`] for a limit on each sample
+	pub fn set_max_voices (&self, max: Option<u32>, policy: StealPolicy) {
+		mixer::send(&self.mixer, &self.commands, Command::SetMaxVoices(max.map(|x| (x, policy))));
+	}
+
+
+	/// take the events of every sound since the last call, oldest
+	/// first
+	///
+	/// call this regularly, like once per game frame. events are kept
+	/// only up to a limit, after that new ones are lost. sounds keep
+	/// sending events after their handle is dropped
+	pub fn events (&self) -> impl Iterator<Item = SoundEvent> + '_ {
+		std::iter::from_fn(move || self.events.pop())
+	}
+
+
+	/// record the output to a wav file at `path`, until the returned
+	/// recording is stopped
+	///
+	/// the file keeps the channels and sample rate the engine had
+	/// when the recording started
+	pub fn record (&self, path: impl AsRef<Path>) -> Result<Recording, Error> {
+		let mut mixer = self.mixer.lock().unwrap();
+		let (channels, sample_rate) = (mixer.channels(), mixer.sample_rate());
+		// a second of audio, the file is written far more often
+		let (writer, reader) = tap::tap(sample_rate as usize * channels as usize, channels, sample_rate);
+		let recording = Recording::start(path.as_ref(), reader)?;
+		mixer.add_tap(writer);
+		Ok(recording)
+	}
+
+
+	/// keep the last `length` of the output, to save instant replays
+	/// with [`AudioEngine::save_last`]
+	///
+	/// the output is kept in memory, about 11MB per minute in stereo
+	/// at 48kHz, in the channels and sample rate the engine had when
+	/// this was called. what was kept before is lost, and
+	/// `Duration::ZERO` stops keeping it
+	pub fn enable_replay (&self, length: Duration) {
+		let mut mixer = self.mixer.lock().unwrap();
+		let replay = (!length.is_zero()).then(|| Arc::new(Replay::new(length, mixer.channels(), mixer.sample_rate())));
+		mixer.set_replay(replay);
+	}
+
+
+	/// write the last `duration` of the output to a wav file at `path`
+	///
+	/// at most the length given to [`AudioEngine::enable_replay`] is
+	/// saved, and less if the engine didn't play that long yet. fails
+	/// if the replay isn't enabled
+	pub fn save_last (&self, duration: Duration, path: impl AsRef<Path>) -> Result<(), Error> {
+		let replay = self.mixer.lock().unwrap().replay().ok_or(Error::ReplayDisabled)?;
+		Ok(replay.save(duration, path.as_ref())?)
+	}
+
+
+	/// start recording the default input device, like the microphone
+	///
+	/// the samples are read from the returned input, converted to the
+	/// channels and sample rate of `config`. it tries the backends in
+	/// the same order as [`AudioBackend::Auto`], and records until it
+	/// is dropped. the input is not mixed with the output, see
+	/// [`AudioEngine::open_monitor`] for that
+	pub fn open_input (&self, config: InputConfig) -> Result<AudioInput, Error> {
+		input::start(config, self.meter.clone()).map_err(Error::Backend)
+	}
+
+
+	/// play the default input device through the mixer as it is
+	/// recorded, like a karaoke mic
+	///
+	/// the returned sound is already playing, its volume is the gain
+	/// of the mic and effects like a reverb can be added to it. the
+	/// latency is how much is kept ahead of what is played, and can be
+	/// changed with the controls. the device is recorded in mono, at
+	/// the sample rate of the engine, until the sound is dropped
+	pub fn open_monitor (&self, latency: Duration) -> Result<(Sound, MonitorControls), Error> {
+		let sample_rate = self.mixer.lock().unwrap().sample_rate();
+		let config = InputConfig { channels: 1, sample_rate, ..InputConfig::default() };
+		let input = input::start(config, self.meter.clone()).map_err(Error::Backend)?;
+		let monitor = Monitor::new(input).latency(latency);
+		let controls = monitor.controls();
+		let mut sound = self.new_sound(monitor, |x| x)?;
+		sound.play();
+		Ok((sound, controls))
+	}
+
+
+	/// how loud the output was in the last mixed buffer, after the
+	/// master volume
+	pub fn master_levels (&self) -> Levels {
+		self.meter.levels()
+	}
+
+
+	/// get copies of the output, to draw it
+	///
+	/// the tap holds up to `capacity` of audio, in the channels and
+	/// sample rate the engine had when the tap was created
+	pub fn tap (&self, capacity: Duration) -> Tap {
+		let mut mixer = self.mixer.lock().unwrap();
+		let (channels, sample_rate) = (mixer.channels(), mixer.sample_rate());
+		let samples = mixer::SampleRate(sample_rate).frames(capacity) as usize * channels as usize;
+		let (writer, reader) = tap::tap(samples, channels, sample_rate);
+		mixer.add_tap(writer);
+		reader
+	}
+
+
+	/// create a new top level group
+	///
+	/// see [`Group::create_subgroup`] for nested groups
+	pub fn create_group (&self, name: &str) -> Group {
+		let id = self.mixer.lock().unwrap().add_group(name, None);
+		Group {
+			mixer: self.mixer.clone(),
+			commands: self.commands.clone(),
+			id
 		}
+	}
+
 
+	/// get a handle to the first group created with `name`
+	pub fn group (&self, name: &str) -> Option<Group> {
+		let id = self.mixer.lock().unwrap().find_group(name)?;
+		Some(Group {
+			mixer: self.mixer.clone(),
+			commands: self.commands.clone(),
+			id
+		})
+	}
+
+
+	/// turn `group` down while a sound of `by` plays, like the music
+	/// under a voice
+	///
+	/// `amount` is how much it is turned down, from `0.0` to `1.0`
+	/// for silent. it goes down over `attack` when a sound of `by`
+	/// starts, and back up over `release` after the last one ends.
+	/// fails if a group is of another engine
+	pub fn duck (&self, group: &Group, by: &Group, amount: f32, attack: Duration, release: Duration) -> Result<(), Error> {
+		let (group, by) = (self.group_id(group)?, self.group_id(by)?);
+		mixer::send(&self.mixer, &self.commands, Command::Duck(group, by, amount, attack, release));
+		Ok(())
 	}
 
-	impl Drop for Backend {
 
-		fn drop (&mut self) {
+	/// stop a duck started with [`AudioEngine::duck`]
+	pub fn stop_ducking (&self, group: &Group, by: &Group) -> Result<(), Error> {
+		let (group, by) = (self.group_id(group)?, self.group_id(by)?);
+		mixer::send(&self.mixer, &self.commands, Command::StopDucking(group, by));
+		Ok(())
+	}
 
-			self.sender.send(StreamEvent::Drop).unwrap();
-			self.join.take().unwrap().join().unwrap();
 
+	/// fade `from` out while `to` fades in over `duration`, starting on
+	/// the same frame, like the music of two scenes
+	///
+	/// `from` is stopped once it is silent. `to` starts playing, or is
+	/// raised from its current fade if it already plays. fails if a
+	/// sound is of another engine
+	pub fn crossfade (&self, from: &mut Sound, to: &mut Sound, duration: Duration) -> Result<(), Error> {
+		if !Arc::ptr_eq(&from.mixer, &self.mixer) || !Arc::ptr_eq(&to.mixer, &self.mixer) {
+			return Err(Error::InvalidSound);
 		}
+		mixer::send(&self.mixer, &self.commands, Command::Crossfade(from.id, to.id, duration));
+		Ok(())
+	}
 
+
+	/// pause every sound where it is, but the ones of the groups of
+	/// `except` and their subgroups, like gameplay behind a pause menu
+	///
+	/// works like [`Group::pause`]: the sounds keep their own playing
+	/// state, and sounds that start before [`AudioEngine::resume_all`]
+	/// wait for it too. fails if a group doesn't exist in this engine
+	pub fn pause_all (&self, except: &[GroupId]) -> Result<(), Error> {
+		let mixer = self.mixer.lock().unwrap();
+		if !except.iter().all(|&x| mixer.has_group(x)) {
+			return Err(Error::InvalidGroup);
+		}
+		// `send` locks the mixer when the queue is full
+		drop(mixer);
+		mixer::send(&self.mixer, &self.commands, Command::PauseAll(except.to_vec()));
+		Ok(())
 	}
 
 
-}
+	/// continue the sounds paused by [`AudioEngine::pause_all`]
+	pub fn resume_all (&self) {
+		mixer::send(&self.mixer, &self.commands, Command::ResumeAll);
+	}
 
 
+	/// pause every sound tagged with `tag` where it is, see
+	/// [`Sound::set_tag`]
+	///
+	/// all of them pause on the same frame, like the sounds of a scene
+	/// behind a menu
+	pub fn pause_tagged (&self, tag: &str) {
+		mixer::send(&self.mixer, &self.commands, Command::PauseTagged(mixer::name_key(tag)));
+	}
 
-/// The main struct of the crate
-///
-/// This holds all existing Wav Sources and `cpal::platform::Stream`
-pub struct AudioEngine {
 
-	mixer: Arc<Mutex<Mixer>>,
-	_backend: Backend
+	/// continue the sounds tagged with `tag` that are paused
+	pub fn resume_tagged (&self, tag: &str) {
+		mixer::send(&self.mixer, &self.commands, Command::ResumeTagged(mixer::name_key(tag)));
+	}
 
-}
 
-impl AudioEngine {
+	/// stop every sound tagged with `tag`, like
+	/// [`Sound::stop`]
+	pub fn stop_tagged (&self, tag: &str) {
+		mixer::send(&self.mixer, &self.commands, Command::StopTagged(mixer::name_key(tag)));
+	}
 
 
-	/// tries to create a new Audio Engine
+	/// set the volume of every sound tagged with `tag`, like
+	/// [`Sound::set_volume`]
+	pub fn set_volume_tagged (&self, tag: &str, volume: f32) {
+		mixer::send(&self.mixer, &self.commands, Command::SetVolumeTagged(mixer::name_key(tag), volume));
+	}
+
+
+	/// set the game parameter `name`, which every sound and group mapped
+	/// to it follows, see [`Sound::map_parameter`](crate::Sound::map_parameter)
 	///
-	/// `cpal` will spawn a new thread where the sound samples will
-	/// be sampled, mixed and outputed to the output stream
-	pub fn new () -> Result<Self, &'static str> {
-		let mixer = Arc::new(Mutex::new(Mixer::new(2, mixer::SampleRate(48000)))); // 48k sample rate
-		let backend = Backend::start(mixer.clone())?;
+	/// only changes are sent to the mixer, there is no need to set it
+	/// every frame of the game
+	pub fn set_parameter (&self, name: &str, value: f32) {
+		mixer::send(&self.mixer, &self.commands, Command::SetParameter(mixer::name_key(name), value));
+	}
 
-		Ok(Self {
-			mixer,
-			_backend: backend
-		})
+
+	/// keep `snapshot` under `name`, replacing the last one with this
+	/// name, see [`AudioEngine::transition_to_snapshot`]
+	///
+	/// fails if a group of the snapshot doesn't exist in this engine
+	pub fn add_snapshot (&self, name: &str, snapshot: Snapshot) -> Result<(), Error> {
+		let mut mixer = self.mixer.lock().unwrap();
+		let groups = snapshot.volumes.iter().chain(snapshot.low_passes.iter()).map(|x| x.0);
+		if !groups.into_iter().all(|x| mixer.has_group(x)) {
+			return Err(Error::InvalidGroup);
+		}
+		mixer.add_snapshot(name, snapshot);
+		Ok(())
 	}
 
 
-	/// the sample rate that is currently being outputed to the device
-	pub fn sample_rate(&self) -> u32 {
-		self.mixer.lock().unwrap().sample_rate()
+	/// blend the mix to the snapshot added as `name` over `duration`,
+	/// like a muffle under a pause menu or underwater
+	///
+	/// every group of the snapshot starts moving on the same frame.
+	/// fails if there is no snapshot with this name
+	pub fn transition_to_snapshot (&self, name: &str, duration: Duration) -> Result<(), Error> {
+		let index = self.mixer.lock().unwrap().find_snapshot(name).ok_or(Error::NotFound("snapshot"))?;
+		mixer::send(&self.mixer, &self.commands, Command::TransitionToSnapshot(index, duration));
+		Ok(())
 	}
 
 
-	/// the sample rate of the current output device
+	/// the group of the reverb shared by every sound, created on the
+	/// first call
 	///
-	/// may change when device changes
-	pub fn channels (&self) -> u16 {
-		self.mixer.lock().unwrap().channels()
+	/// sounds are sent to it with [`Sound::set_reverb_send`]. it only
+	/// outputs the reverberated sound, with the default
+	/// [`ReverbConfig`](crate::ReverbConfig). use
+	/// [`Group::clear_effects`] and [`Group::add_effect`] to change it
+	pub fn reverb (&self) -> Group {
+		let id = self.mixer.lock().unwrap().reverb_group();
+		Group {
+			mixer: self.mixer.clone(),
+			commands: self.commands.clone(),
+			id
+		}
+	}
+
+
+	/// render the placed sounds for headphones with `hrtf`, or pan
+	/// them with `None`
+	///
+	/// only used on a stereo output. turn it on when headphones are
+	/// plugged in, which android tells through `AudioManager`, as it
+	/// sounds wrong on speakers
+	pub fn set_hrtf (&self, hrtf: Option<Hrtf>) {
+		let sample_rate = self.mixer.lock().unwrap().sample_rate.0;
+		let hrtf = hrtf.map(|x| Box::new(x.resample(sample_rate)));
+		mixer::send(&self.mixer, &self.commands, Command::SetHrtf(hrtf));
+	}
+
+
+	/// mix the placed sounds in a first order ambisonic sound field,
+	/// turned with the listener, instead of placing them one by one
+	///
+	/// the field is decoded with the hrtf when one is set, see
+	/// [`AudioEngine::set_hrtf`]. placed sounds skip the effects of
+	/// their group, their sends still work
+	pub fn set_ambisonics (&self, ambisonics: bool) {
+		mixer::send(&self.mixer, &self.commands, Command::SetAmbisonics(ambisonics));
+	}
+
+
+	/// the listener of the sounds placed with
+	/// [`Sound::set_position`](crate::Sound::set_position)
+	pub fn listener (&self) -> Listener {
+		Listener {
+			mixer: self.mixer.clone(),
+			commands: self.commands.clone()
+		}
+	}
+
+
+	/// the tempo and time signature of the music, to start sounds on
+	/// the beat with [`Sound::play_quantized`]
+	pub fn transport (&self) -> Transport {
+		Transport {
+			mixer: self.mixer.clone(),
+			commands: self.commands.clone()
+		}
 	}
 
 
 	/// create a new sound
 	///
-	/// Return a `Err` if the number of channels doesn't match the
-	/// output number of channels. If the output number of channels
-	/// of `source` is 1, `source` will be automatic wrapped in a
-	/// [`ChannelConverter`]
+	/// a source with another layout than the output is mixed down or
+	/// up to it, like 5.1 on stereo, see [`ChannelLayout`]. Return an
+	/// [`Error::Channels`] if the number of channels doesn't match the
+	/// output, one of them has no layout and neither is 1
 	///
 	/// if the `sample_rate` of `source` mismatch the output
-	/// `sample_rate`, `source` will be wrapped in a
-	/// [`SampleRateConverter`]
+	/// `sample_rate`, `source` will be resampled, see
+	/// [`AudioEngine::set_resample_quality`]
 	pub fn new_sound <T: SoundSource + Send + 'static> (
 		&self,
 		source: T,
 		effect: impl FnMut(f32) -> f32 + 'static + std::marker::Send
-	) -> Result<Sound, &'static str> {
+	) -> Result<Sound, Error> {
+		self.add_sound(source, effect, None)
+	}
+
+
+	/// create a new sound inside `group`
+	///
+	/// same as [`AudioEngine::new_sound`] otherwise, and also fails
+	/// if the group is of another engine
+	pub fn new_sound_in_group <T: SoundSource + Send + 'static> (
+		&self,
+		group: &Group,
+		source: T,
+		effect: impl FnMut(f32) -> f32 + 'static + std::marker::Send
+	) -> Result<Sound, Error> {
+		self.add_sound(source, effect, Some(self.group_id(group)?))
+	}
+
+
+	/// create a new sound of the part of `source` from `start` to
+	/// `end`, so a long recording can hold many sounds
+	///
+	/// the sound starts, resets and seeks inside the part, and its
+	/// length is the length of the part. the source seeks to `start`
+	/// when it first plays, or decodes up to it if it can't seek.
+	/// same as [`AudioEngine::new_sound`] otherwise
+	///
+	/// ```ignore
+	/// let footsteps = engine.load(WavDecoder::new(File::open("footsteps.wav")?)?);
+	/// let step = engine.new_sound_with_region(footsteps.source(), Duration::from_millis(1200), Duration::from_millis(1450), |x| x)?;
+	/// ```
+	pub fn new_sound_with_region <T: SoundSource + Send + 'static> (
+		&self,
+		source: T,
+		start: Duration,
+		end: Duration,
+		effect: impl FnMut(f32) -> f32 + 'static + std::marker::Send
+	) -> Result<Sound, Error> {
+		let source = source.skip_duration(start).take_duration(end.saturating_sub(start));
+		self.add_sound(source, effect, None)
+	}
+
+
+	/// decode the whole `source` once, to be played many times
+	///
+	/// see [`SoundData::decode`]
+	pub fn load <T: SoundSource> (&self, source: T) -> SoundData {
+		SoundData::decode(source)
+	}
+
+
+	/// play `data` without a handle, like [`AudioEngine::play_oneshot`]
+	///
+	/// the samples are not copied, so this is cheap to call for
+	/// every shot of a short effect. use `data.source()` with
+	/// [`AudioEngine::new_sound`] to get a handle
+	pub fn play (&self, data: &SoundData) -> Result<SoundId, Error> {
+		self.play_oneshot(data.source())
+	}
+
+
+	/// load the events of the bank at `path`, to play them with
+	/// [`AudioEngine::post_event`]
+	///
+	/// a bank is a JSON file, its files are relative to it. an event
+	/// needs its `source`, or `sources` to pick one at random by their
+	/// weights, see [`RandomSound`]. the rest can be left out: the
+	/// ranges are a number or `[min, max]`, the pitch in semitones,
+	/// the group is made if it doesn't exist, and `spatial` is the
+	/// [`Attenuation`](crate::Attenuation) of the sound
+	///
+	/// ```json
+	/// { "events": {
+	///     "player/jump": {
+	///         "sources": [ "jump1.wav", { "file": "jump2.wav", "weight": 0.5 } ],
+	///         "group": "sfx", "volume": [ 0.8, 1.0 ], "pitch": [ -1.0, 1.0 ],
+	///         "loop": false,
+	///         "spatial": { "model": "inverse", "rolloff": 1.0, "min_distance": 1.0, "max_distance": 50.0 }
+	///     }
+	/// } }
+	/// ```
+	///
+	/// every file is decoded before this returns, the ogg and flac
+	/// files need their features. an event replaces the one with the
+	/// same name of an earlier bank. with the `hot-reload` feature,
+	/// the bank is loaded again while it or its files change
+	pub fn load_bank (&self, path: impl AsRef<Path>) -> Result<(), Error> {
+		let path = path.as_ref();
+		let group = |name: &str| self.group(name).unwrap_or_else(|| self.create_group(name)).id;
+		let (events, files) = bank::load(path, group).map_err(Error::Bank)?;
+		#[cfg(feature = "hot-reload")]
+		let names = events.iter().map(|x| x.0.clone()).collect();
+		// before watching, so a reload can't be overwritten by these
+		self.bank.lock().unwrap().extend(events);
+		#[cfg(feature = "hot-reload")]
+		crate::hot_reload::watch(path.to_owned(), files, names, &self.bank, &self.mixer);
+		#[cfg(not(feature = "hot-reload"))]
+		drop(files);
+		Ok(())
+	}
+
+
+	/// play the event `name` of a loaded bank, see
+	/// [`AudioEngine::load_bank`]
+	///
+	/// the sound plays until it ends once the handle is dropped, so
+	/// the handle of a looping event must be kept to stop it. fails if
+	/// no bank has the event
+	pub fn post_event (&self, name: &str) -> Result<Sound, Error> {
+		self.start_event(name, None)
+	}
+
+
+	/// play the event `name` placed at `position`, like
+	/// [`AudioEngine::post_event`]
+	pub fn post_event_at (&self, name: &str, position: [f32; 3]) -> Result<Sound, Error> {
+		self.start_event(name, Some(position))
+	}
+
+
+	fn start_event (&self, name: &str, position: Option<[f32; 3]>) -> Result<Sound, Error> {
+		let mut bank = self.bank.lock().unwrap();
+		let event = bank.get_mut(name).ok_or(Error::NotFound("event"))?;
+		let (data, volume, speed) = event.sound.pick().ok_or(Error::Empty)?;
+		let mut sound = self.add_sound(data.source(), |x| x, event.group)?;
+		sound.set_volume(volume);
+		sound.set_speed(speed);
+		sound.set_loop(event.looping);
+		if let Some(attenuation) = event.attenuation.clone() {
+			sound.set_attenuation(attenuation);
+		}
+		if position.is_some() {
+			sound.set_position(position);
+		}
+		sound.play();
+		Ok(sound)
+	}
+
+
+	/// play one of the variations of `random`, picked with its volume
+	/// and pitch, without a handle, like [`AudioEngine::play`]
+	///
+	/// fails if it has no variations
+	pub fn play_random (&self, random: &mut RandomSound) -> Result<SoundId, Error> {
+		let (data, volume, speed) = random.pick().ok_or(Error::Empty)?;
+		self.play_oneshot_with(data.source(), volume, 0.0, speed)
+	}
+
+
+	/// play the next step of `sequence` without a handle, like
+	/// [`AudioEngine::play`]
+	///
+	/// fails once a sequence that doesn't loop played its last step.
+	/// use [`SequenceSound::next_step`] to get a handle
+	pub fn play_sequence (&self, sequence: &mut SequenceSound) -> Result<SoundId, Error> {
+		let data = sequence.next_step().ok_or(Error::Empty)?;
+		self.play(&data)
+	}
+
+
+	/// create a sound of one of the variations of `random`, with its
+	/// volume and pitch set, to place or play it later
+	///
+	/// same as [`AudioEngine::new_sound`] otherwise
+	pub fn new_random_sound (&self, random: &mut RandomSound) -> Result<Sound, Error> {
+		let (data, volume, speed) = random.pick().ok_or(Error::Empty)?;
+		let mut sound = self.add_sound(data.source(), |x| x, None)?;
+		sound.set_volume(volume);
+		sound.set_speed(speed);
+		Ok(sound)
+	}
+
+
+	/// the sound of `id` still exists, a one-shot is freed once it
+	/// ends, after the event of its end
+	///
+	/// commands sent to a freed sound do nothing, this is to assert
+	/// against stale ids
+	pub fn is_valid (&self, id: SoundId) -> bool {
+		self.mixer.lock().unwrap().is_valid(id)
+	}
+
+
+	/// play a sound without a handle, freed once it ends
+	///
+	/// the returned id can be matched with [`AudioEngine::events`].
+	/// the source is converted like in [`AudioEngine::new_sound`]
+	pub fn play_oneshot <T: SoundSource + Send + 'static> (&self, source: T) -> Result<SoundId, Error> {
+		self.play_oneshot_with(source, 1.0, 0.0, 1.0)
+	}
+
+
+	/// like [`AudioEngine::play_oneshot`], with the volume, pan and
+	/// speed of the sound, see [`Sound::set_volume`], [`Sound::set_pan`]
+	/// and [`Sound::set_speed`]
+	pub fn play_oneshot_with <T: SoundSource + Send + 'static> (
+		&self,
+		source: T,
+		volume: f32,
+		pan: f32,
+		speed: f32
+	) -> Result<SoundId, Error> {
+		let mut sound = self.add_sound(source, |x| x, None)?;
+		sound.set_volume(volume);
+		sound.set_pan(pan);
+		sound.set_speed(speed);
+		sound.play();
+		// dropping the handle frees the sound once it ends
+		Ok(sound.id)
+	}
+
+
+	fn add_sound <T: SoundSource + Send + 'static> (
+		&self,
+		source: T,
+		effect: impl FnMut(f32) -> f32 + 'static + std::marker::Send,
+		group: Option<GroupId>
+	) -> Result<Sound, Error> {
 		let mut mixer = self.mixer.lock().unwrap();
 
-		let sound: Box<dyn SoundSource + Send> = if source.sample_rate() != mixer.sample_rate.0 {
-			if source.channels() == mixer.channels {
-				Box::new(SampleRateConverter::new(source, mixer.sample_rate.0))
-			} else if mixer.channels == 1 || source.channels() == 1 {
-				Box::new(ChannelConverter::new(
-					SampleRateConverter::new(source, mixer.sample_rate.0),
-					mixer.channels
-				))
-			} else {
-				return Err("Number of channels do not match the output, and neither are 1");
-			}
-		} else if source.channels() == mixer.channels {
-			Box::new(source)
-		} else if mixer.channels == 1 || source.channels() == 1 {
-			Box::new(ChannelConverter::new(source, mixer.channels))
-		} else {
-			return Err("Number of channels do not match the output, and is not 1");
-		};
+		let mono = source.channels() == 1;
+		let sound = converter::convert(Box::new(source), mixer.channels, mixer.sample_rate.0, mixer.resample_quality())?;
 
-		let id = mixer.add_sound(sound, effect);
+		let (id, shared) = mixer.add_sound(sound, mono, effect);
+		mixer.set_group(id, group);
 		drop(mixer);
 
-		Ok(Sound {
-			mixer: self.mixer.clone(),
-			id
-		})
+		Ok(Sound::new(self.mixer.clone(), self.commands.clone(), shared, id))
+	}
+
+
+	/// the id of `group`, if it is a group of this engine
+	fn group_id (&self, group: &Group) -> Result<GroupId, Error> {
+		Arc::ptr_eq(&group.mixer, &self.mixer).then_some(group.id).ok_or(Error::InvalidGroup)
 	}
 
 
@@ -232,120 +801,194 @@ impl AudioEngine {
 
 
 
-fn create_device (
-	mixer: &Arc<Mutex<Mixer>>,
-	error_callback: impl FnMut(StreamError) + Send + Clone + 'static
-) -> Result<cpal::Stream, &'static str> {
-
-	let host = cpal::default_host();
-	let device = host
-					.default_output_device()
-					.ok_or("no output device available")?;
-	let mut supported_configs_range = device
-										.supported_output_configs()
-										.map_err(|_| "error while querying formats")?
-										.map(|x| {
-											let sample_rate = SampleRate(48000);
-											if x.min_sample_rate() <= sample_rate && sample_rate <= x.max_sample_rate() {
-												return x.with_sample_rate(sample_rate);
-											}
-
-											let sample_rate = SampleRate(44100);
-											if x.min_sample_rate() <= sample_rate && sample_rate <= x.max_sample_rate() {
-												return x.with_sample_rate(sample_rate);
-											}
-
-											x.with_max_sample_rate()
-										})
-										.collect::<Vec<_>>();
-
-	supported_configs_range.sort_unstable_by(|a, b| {
-		let key = |x: &cpal::SupportedStreamConfig| {
-			(
-				x.sample_rate().0 == 48000,
-				x.sample_rate().0 == 441000,
-				x.channels() == 2,
-				x.channels() == 1,
-				x.sample_format() == cpal::SampleFormat::I16,
-				x.sample_rate().0
-			)
-		};
-		key(a).cmp(&key(b))
-	});
+/// how the output stream trades latency for power use
+///
+/// only followed by the aaudio backend, see [`AudioEngineBuilder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerformanceMode {
+	/// let the device decide
+	None,
+	/// small buffers, for rhythm games and other tight timing
+	LowLatency,
+	/// large buffers, for music players and alike
+	PowerSaving
+}
 
-	if log::max_level() >= log::LevelFilter::Trace {
-		for config in &supported_configs_range {
-			log::trace!("config {:?}", config);
-		}
-	}
 
-	let stream = loop {
-		let config = if let Some(config) = supported_configs_range.pop() {
-			config
-		} else {
-			return Err("no supported config");
-		};
-		let sample_format = config.sample_format();
-		let config = config.config();
-		mixer
-			.lock()
-			.unwrap()
-			.set_config(config.channels, mixer::SampleRate(config.sample_rate.0));
-
-		let stream = {
-			use cpal::SampleFormat::*;
-			match sample_format {
-				I16 => stream::<i16, _>(mixer, error_callback.clone(), &device, &config),
-				U16 => stream::<u16, _>(mixer, error_callback.clone(), &device, &config),
-				F32 => stream::<f32, _>(mixer, error_callback.clone(), &device, &config)
-			}
-		};
 
-		let stream = match stream {
-			Ok(x) => {
-				log::info!("created {:?} stream with config {:?}", sample_format, config);
-				x
-			},
-			Err(e) => {
-				log::error!("failed to create stream with config {:?}: {:?}", config, e);
-				continue;
-			}
-		};
+/// if other apps can play to the device at the same time
+///
+/// only followed by the aaudio backend, see [`AudioEngineBuilder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharingMode {
+	Shared,
+	/// the lowest latency, when the device allows it. falls back to
+	/// shared otherwise
+	Exclusive
+}
 
-		stream.play().unwrap();
-		break stream;
-	};
 
-	Ok(stream)
 
+/// where the output of the engine goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioBackend {
+	/// the best backend that works on this device: AAudio, then
+	/// OpenSL ES, then cpal, skipping the ones that are not enabled
+	Auto,
+	/// android 8.0 and later, needs the `aaudio` feature
+	AAudio,
+	/// any android version, needs the `opensles` feature
+	OpenSlEs,
+	Cpal
 }
 
 
 
-fn stream <T: cpal::Sample, E: FnMut(StreamError) + Send + 'static> (
-	mixer: &Arc<Mutex<Mixer>>,
-	error_callback: E,
-	device: &cpal::Device,
-	config: &cpal::StreamConfig
-) -> Result<cpal::Stream, cpal::BuildStreamError> {
-
-	let mixer = mixer.clone();
-	let mut input_buffer = Vec::new();
-	device.build_output_stream(
-		config,
-		move |output_buffer: &mut [T], _| {
-			input_buffer.clear();
-			input_buffer.resize(output_buffer.len(), 0);
-			mixer.lock().unwrap().write_samples(&mut input_buffer);
-			// write sample to output buffer
-			output_buffer
-				.iter_mut()
-				.zip(input_buffer.iter())
-				.for_each(|(a, b)| *a = T::from(b));
-		},
-		error_callback
-	)
+/// configure an [`AudioEngine`]
+///
+/// the performance and sharing modes are only followed by the
+/// aaudio backend
+pub struct AudioEngineBuilder {
+
+	backend: AudioBackend,
+	performance_mode: PerformanceMode,
+	sharing_mode: SharingMode,
+	channel_layout: Option<ChannelLayout>
 
 }
 
+impl AudioEngineBuilder {
+
+
+	/// the default config, the same as [`AudioEngine::new`]
+	pub fn new () -> Self {
+		Self {
+			backend: AudioBackend::Auto,
+			performance_mode: PerformanceMode::None,
+			sharing_mode: SharingMode::Shared,
+			channel_layout: None
+		}
+	}
+
+
+	/// force a backend, instead of picking one at runtime
+	///
+	/// [`AudioEngineBuilder::build`] fails if it doesn't work
+	pub fn backend (mut self, backend: AudioBackend) -> Self {
+		self.backend = backend;
+		self
+	}
+
+
+	pub fn performance_mode (mut self, mode: PerformanceMode) -> Self {
+		self.performance_mode = mode;
+		self
+	}
+
+
+	pub fn sharing_mode (mut self, mode: SharingMode) -> Self {
+		self.sharing_mode = mode;
+		self
+	}
+
+
+	/// the speakers to open the output with, like 5.1 on a tv box with
+	/// an hdmi receiver
+	///
+	/// by default the output is stereo, or what the os mixes at with
+	/// cpal. the device can still give other channels, see
+	/// [`AudioEngine::channels`], and every sound is converted to them
+	pub fn channel_layout (mut self, layout: ChannelLayout) -> Self {
+		self.channel_layout = Some(layout);
+		self
+	}
+
+
+	/// tries to create the engine and start its output stream
+	///
+	/// fails with [`Error::Backend`] if no backend could be started
+	pub fn build (self) -> Result<AudioEngine, Error> {
+		let mixer = Arc::new(Mutex::new(Mixer::new(2, mixer::SampleRate(48000)))); // 48k sample rate
+		let commands = mixer.lock().unwrap().commands();
+		let events = mixer.lock().unwrap().events();
+		let meter = mixer.lock().unwrap().meter();
+
+		let backend = self.start_backend(&mixer).map_err(Error::Backend)?;
+
+		Ok(AudioEngine {
+			mixer,
+			commands,
+			events,
+			meter,
+			bank: Arc::new(Mutex::new(HashMap::new())),
+			_backend: backend
+		})
+	}
+
+
+	fn start_backend (&self, mixer: &Arc<Mutex<Mixer>>) -> Result<Box<dyn Send>, &'static str> {
+		match self.backend {
+			AudioBackend::Auto => self.start_aaudio(mixer)
+				.or_else(|err| {
+					log::debug!("{}, trying opensl es", err);
+					self.start_opensles(mixer)
+				})
+				.or_else(|err| {
+					log::debug!("{}, trying cpal", err);
+					self.start_cpal(mixer)
+				}),
+			AudioBackend::AAudio => self.start_aaudio(mixer),
+			AudioBackend::OpenSlEs => self.start_opensles(mixer),
+			AudioBackend::Cpal => self.start_cpal(mixer)
+		}
+	}
+
+
+	#[cfg(all(target_os = "android", feature = "aaudio"))]
+	fn start_aaudio (&self, mixer: &Arc<Mutex<Mixer>>) -> Result<Box<dyn Send>, &'static str> {
+		Ok(Box::new(crate::aaudio::Backend::start(
+			mixer.clone(),
+			self.performance_mode,
+			self.sharing_mode,
+			self.channel_layout.map_or(2, ChannelLayout::channels)
+		)?))
+	}
+
 
+	#[cfg(not(all(target_os = "android", feature = "aaudio")))]
+	fn start_aaudio (&self, _: &Arc<Mutex<Mixer>>) -> Result<Box<dyn Send>, &'static str> {
+		Err("the aaudio backend is not enabled")
+	}
+
+
+	#[cfg(all(target_os = "android", feature = "opensles"))]
+	fn start_opensles (&self, mixer: &Arc<Mutex<Mixer>>) -> Result<Box<dyn Send>, &'static str> {
+		Ok(Box::new(crate::opensles::Backend::start(mixer.clone(), self.channel_layout.unwrap_or(ChannelLayout::Stereo))?))
+	}
+
+
+	#[cfg(not(all(target_os = "android", feature = "opensles")))]
+	fn start_opensles (&self, _: &Arc<Mutex<Mixer>>) -> Result<Box<dyn Send>, &'static str> {
+		Err("the opensl es backend is not enabled")
+	}
+
+
+	#[cfg(feature = "cpal")]
+	fn start_cpal (&self, mixer: &Arc<Mutex<Mixer>>) -> Result<Box<dyn Send>, &'static str> {
+		Ok(Box::new(crate::cpal_backend::Backend::start(mixer.clone(), self.channel_layout.map(ChannelLayout::channels))?))
+	}
+
+
+	#[cfg(not(feature = "cpal"))]
+	fn start_cpal (&self, _: &Arc<Mutex<Mixer>>) -> Result<Box<dyn Send>, &'static str> {
+		Err("the cpal backend is not enabled")
+	}
+
+
+}
+
+impl Default for AudioEngineBuilder {
+	fn default () -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,122 @@
+
+
+
+//! Attack, decay, sustain and release envelopes for the volume of a sound.
+
+
+
+use std::time::Duration;
+
+use crate::easing::Ramp;
+
+
+
+/// an ADSR envelope, see [`Sound::set_envelope`](crate::Sound::set_envelope)
+///
+/// when the sound starts, the volume rises from silence to full in
+/// `attack`, then falls to `sustain` in `decay`, and stays there
+/// until [`Sound::release`](crate::Sound::release), after which it
+/// falls to silence in `release` and the sound stops
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+
+	pub attack: Duration,
+	pub decay: Duration,
+	/// the volume held after the decay, from `0.0` to `1.0`
+	pub sustain: f32,
+	pub release: Duration
+
+}
+
+impl Default for Envelope {
+	/// short enough to only remove the clicks at the start and end
+	fn default () -> Self {
+		Self {
+			attack: Duration::from_millis(5),
+			decay: Duration::ZERO,
+			sustain: 1.0,
+			release: Duration::from_millis(20)
+		}
+	}
+}
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+	Attack,
+	Decay,
+	Sustain,
+	Release
+}
+
+
+
+/// an [`Envelope`] being played
+pub (crate) struct EnvelopeState {
+
+	envelope: Envelope,
+	stage: Stage,
+	gain: Ramp,
+	/// frames of the decay, at the sample rate given to `start`
+	decay: u32
+
+}
+
+impl EnvelopeState {
+
+
+	pub fn new (envelope: Envelope) -> Self {
+		Self {
+			envelope,
+			stage: Stage::Sustain,
+			gain: Ramp::new(envelope.sustain),
+			decay: 0
+		}
+	}
+
+
+	/// start the attack, from silence
+	pub fn start (&mut self, sample_rate: u32) {
+		self.stage = Stage::Attack;
+		self.gain = Ramp::new(0.0);
+		self.gain.set(1.0, frames(self.envelope.attack, sample_rate));
+		self.decay = frames(self.envelope.decay, sample_rate);
+	}
+
+
+	/// start the release, from the current gain
+	pub fn release (&mut self, sample_rate: u32) {
+		self.stage = Stage::Release;
+		self.gain.set(0.0, frames(self.envelope.release, sample_rate));
+	}
+
+
+	/// the release ended, the sound is silent
+	pub fn is_released (&self) -> bool {
+		self.stage == Stage::Release && self.gain.is_done()
+	}
+
+
+	/// the gain of the next frame
+	pub fn next (&mut self) -> f32 {
+		let gain = self.gain.next();
+		match self.stage {
+			Stage::Attack if self.gain.is_done() => {
+				self.stage = Stage::Decay;
+				self.gain.set(self.envelope.sustain, self.decay);
+			},
+			Stage::Decay if self.gain.is_done() => self.stage = Stage::Sustain,
+			_ => ()
+		}
+		gain
+	}
+
+
+}
+
+
+
+fn frames (duration: Duration, sample_rate: u32) -> u32 {
+	(duration.as_secs_f64() * sample_rate as f64).round() as u32
+}
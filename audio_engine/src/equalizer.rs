@@ -0,0 +1,112 @@
+
+
+
+//! A parametric equalizer, made of up to eight biquad bands in series.
+
+
+
+use log::warn;
+
+use crate::biquad::{ Biquad, BiquadControls, FilterKind };
+use crate::effect::Effect;
+
+
+
+/// the most bands an [`Equalizer`] can have
+pub const MAX_BANDS: usize = 8;
+
+
+
+/// one band of an [`Equalizer`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqBand {
+
+	/// usually `Peak`, `LowShelf` or `HighShelf`
+	pub kind: FilterKind,
+	/// the center or corner of the band, in Hz
+	pub frequency: f32,
+	/// how wide the band is, higher is narrower
+	pub q: f32,
+	/// in dB, positive boosts and negative cuts
+	pub gain: f32
+
+}
+
+impl EqBand {
+
+
+	pub fn peak (frequency: f32, q: f32, gain: f32) -> Self {
+		Self { kind: FilterKind::Peak, frequency, q, gain }
+	}
+
+
+	pub fn low_shelf (frequency: f32, gain: f32) -> Self {
+		Self { kind: FilterKind::LowShelf, frequency, q: std::f32::consts::FRAC_1_SQRT_2, gain }
+	}
+
+
+	pub fn high_shelf (frequency: f32, gain: f32) -> Self {
+		Self { kind: FilterKind::HighShelf, frequency, q: std::f32::consts::FRAC_1_SQRT_2, gain }
+	}
+
+
+}
+
+
+
+/// a multi band equalizer, as an [`Effect`]
+///
+/// meant for the master output and groups, to master the music or
+/// tune the sound for a device
+pub struct Equalizer {
+	bands: Vec<Biquad>
+}
+
+impl Equalizer {
+
+
+	/// an equalizer with `bands`, applied in order
+	///
+	/// the bands past the first [`MAX_BANDS`] are ignored
+	pub fn new (bands: &[EqBand]) -> Self {
+		if bands.len() > MAX_BANDS {
+			warn!("an equalizer has at most {} bands, {} were given", MAX_BANDS, bands.len());
+		}
+		Self {
+			bands: bands.iter()
+				.take(MAX_BANDS)
+				.map(|band| Biquad::with_gain(band.kind, band.frequency, band.q, band.gain))
+				.collect()
+		}
+	}
+
+
+	/// a low shelf at 100Hz, a peak at 1kHz and a high shelf at 8kHz,
+	/// all flat
+	pub fn three_band () -> Self {
+		Self::new(&[
+			EqBand::low_shelf(100.0, 0.0),
+			EqBand::peak(1000.0, 1.0, 0.0),
+			EqBand::high_shelf(8000.0, 0.0)
+		])
+	}
+
+
+	/// change the band at `index` after the equalizer was given to a
+	/// group
+	pub fn band (&self, index: usize) -> Option<BiquadControls> {
+		self.bands.get(index).map(|band| band.controls())
+	}
+
+
+}
+
+impl Effect for Equalizer {
+
+	fn process (&mut self, frames: &mut [f32], channels: u16, sample_rate: u32) {
+		for band in self.bands.iter_mut() {
+			band.process(frames, channels, sample_rate);
+		}
+	}
+
+}
@@ -0,0 +1,62 @@
+
+
+
+//! The error of everything in the crate that can fail.
+//!
+//! The decoders keep the errors of their formats, which convert into this one, so `?` works on
+//! both in a function that returns an [`Error`].
+
+
+
+use std::io;
+
+
+
+/// what went wrong, see the variants
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	/// no output or input stream could be opened or started
+	#[error("audio backend: {0}")]
+	Backend(&'static str),
+	/// a [`Sound`](crate::Sound) of another engine
+	#[error("the sound is not in this engine")]
+	InvalidSound,
+	/// a group of another engine
+	#[error("the group is not in this engine")]
+	InvalidGroup,
+	/// a source with other channels than the output, when one of them
+	/// has no [`ChannelLayout`](crate::ChannelLayout) and neither is
+	/// mono
+	#[error("the channels of the source can't be converted to the output")]
+	Channels,
+	/// no snapshot or event with this name, the `&str` says which
+	#[error("no {0} with this name")]
+	NotFound(&'static str),
+	/// a random sound without variations, or a sequence without steps
+	/// left
+	#[error("there is nothing to play")]
+	Empty,
+	/// [`AudioEngine::save_last`](crate::AudioEngine::save_last)
+	/// without [`AudioEngine::enable_replay`](crate::AudioEngine::enable_replay)
+	#[error("the replay is not enabled")]
+	ReplayDisabled,
+	/// too many changes are waiting for the audio thread, like many
+	/// music changes in one game frame
+	#[error("too many changes are waiting")]
+	QueueFull,
+	/// a sound bank that can't be loaded, see
+	/// [`AudioEngine::load_bank`](crate::AudioEngine::load_bank)
+	#[error("sound bank: {0}")]
+	Bank(&'static str),
+	#[error(transparent)]
+	Io(#[from] io::Error),
+	/// a wav file that can't be read or written
+	#[error(transparent)]
+	Wav(#[from] hound::Error),
+	#[cfg(feature = "ogg")]
+	#[error(transparent)]
+	Vorbis(#[from] lewton::VorbisError),
+	#[cfg(feature = "symphonia")]
+	#[error(transparent)]
+	Symphonia(#[from] symphonia::core::errors::Error)
+}
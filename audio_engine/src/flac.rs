@@ -0,0 +1,999 @@
+
+
+
+//! A streaming FLAC decoder.
+//!
+//! Only what is needed to feed the mixer is implemented: the STREAMINFO and SEEKTABLE metadata
+//! blocks, and every subframe type. CRCs are read but not checked.
+
+
+
+use log::error;
+
+use std::io::{ self, Read, Seek, SeekFrom };
+
+use crate::mixer::SoundSource;
+
+
+
+fn invalid (message: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+
+/// reads a byte stream bit by bit, most significant bit first
+struct BitReader<T: Read> {
+
+	inner: T,
+	buffer: Box<[u8]>,
+	/// range of `buffer` that was not consumed yet
+	pos: usize,
+	len: usize,
+	/// bits read from `buffer` but not consumed yet, right aligned
+	acc: u64,
+	bits: u32,
+	/// how many bytes were moved from `buffer` to `acc`
+	consumed: u64
+
+}
+
+impl<T: Read> BitReader<T> {
+
+
+	fn new (inner: T) -> Self {
+		Self {
+			inner,
+			buffer: vec![0; 4096].into_boxed_slice(),
+			pos: 0,
+			len: 0,
+			acc: 0,
+			bits: 0,
+			consumed: 0
+		}
+	}
+
+
+	/// forget every buffered byte, after the inner reader was seeked
+	fn clear (&mut self) {
+		self.pos = 0;
+		self.len = 0;
+		self.acc = 0;
+		self.bits = 0;
+		self.consumed = 0;
+	}
+
+
+	/// the position of the next unread byte, relative to where the
+	/// reader started or was last cleared
+	fn byte_position (&self) -> u64 {
+		self.consumed - (self.bits / 8) as u64
+	}
+
+
+	/// true if there is no byte left to read, and all bits were consumed
+	fn at_end (&mut self) -> io::Result<bool> {
+		Ok(self.bits == 0 && !self.fill()?)
+	}
+
+
+	/// make sure `buffer` has unread bytes, returns false at the end
+	/// of the stream
+	fn fill (&mut self) -> io::Result<bool> {
+		if self.pos == self.len {
+			self.pos = 0;
+			self.len = loop {
+				match self.inner.read(&mut self.buffer) {
+					Ok(x) => break x,
+					Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+					Err(err) => return Err(err)
+				}
+			};
+		}
+		Ok(self.pos < self.len)
+	}
+
+
+	/// read `n` bits as an unsigned number, `n` must be at most 32
+	fn read_bits (&mut self, n: u32) -> io::Result<u32> {
+		debug_assert!(n <= 32);
+		while self.bits < n {
+			if !self.fill()? {
+				return Err(io::ErrorKind::UnexpectedEof.into());
+			}
+			self.acc = (self.acc << 8) | self.buffer[self.pos] as u64;
+			self.pos += 1;
+			self.bits += 8;
+			self.consumed += 1;
+		}
+		self.bits -= n;
+		Ok(((self.acc >> self.bits) & ((1u64 << n) - 1)) as u32)
+	}
+
+
+	/// read `n` bits as a two's complement number
+	fn read_signed (&mut self, n: u32) -> io::Result<i32> {
+		if n == 0 {
+			return Ok(0);
+		}
+		if n > 32 {
+			return Err(invalid("unsupported flac sample size"));
+		}
+		let x = self.read_bits(n)?;
+		Ok(((x << (32 - n)) as i32) >> (32 - n))
+	}
+
+
+	/// count the number of 0 bits before the next 1 bit
+	fn read_unary (&mut self) -> io::Result<u32> {
+		let mut n = 0;
+		while self.read_bits(1)? == 0 {
+			n += 1;
+		}
+		Ok(n)
+	}
+
+
+	/// skip the bits left in the current byte
+	fn align (&mut self) {
+		self.bits -= self.bits % 8;
+	}
+
+
+}
+
+
+
+/// FLAC File Decoder
+///
+/// frames are decoded as they are needed, so the whole file is
+/// never decoded up front
+pub struct FlacDecoder <T: Seek + Read + Send + 'static> {
+
+	reader: BitReader<T>,
+	channels: u16,
+	sample_rate: u32,
+	/// bits per sample declared in STREAMINFO
+	bits_per_sample: u32,
+	/// block size of streams with a fixed blocking strategy
+	block_size: u32,
+	/// total number of frames (samples per channel), 0 if unknown
+	total_frames: u64,
+	/// position of the first audio frame in the byte stream
+	first_frame: u64,
+	/// (frame number, byte offset from `first_frame`) pairs of the
+	/// SEEKTABLE block
+	seek_points: Vec<(u64, u64)>,
+	/// decoded samples of the current frame, one `Vec` per channel
+	samples: Vec<Vec<i32>>,
+	/// the last decoded frame, interleaved and converted to i16
+	buffer: Vec<i16>,
+	/// how many samples of `buffer` were already written out
+	index: usize,
+	/// the frame number right after the last decoded frame
+	position: u64,
+	/// the stream reached its end or failed to decode
+	done: bool
+
+}
+
+impl <T: Seek + Read + Send + 'static> FlacDecoder<T> {
+
+
+	/// Create a new flac file decoder
+	pub fn new (mut data: T) -> io::Result<Self> {
+		let start = data.stream_position()?;
+		let mut reader = BitReader::new(data);
+
+		if reader.read_bits(32)? != u32::from_be_bytes(*b"fLaC") {
+			return Err(invalid("not a flac stream"));
+		}
+
+		let mut stream_info = None;
+		let mut seek_points = vec![];
+		loop {
+			let last = reader.read_bits(1)? == 1;
+			let kind = reader.read_bits(7)?;
+			let len = reader.read_bits(24)?;
+			match kind {
+				// STREAMINFO
+				0 => {
+					let _min_block_size = reader.read_bits(16)?;
+					let block_size = reader.read_bits(16)?;
+					let _frame_sizes = (reader.read_bits(24)?, reader.read_bits(24)?);
+					let sample_rate = reader.read_bits(20)?;
+					let channels = reader.read_bits(3)? + 1;
+					let bits_per_sample = reader.read_bits(5)? + 1;
+					let total_frames = (reader.read_bits(4)? as u64) << 32 | reader.read_bits(32)? as u64;
+					for _ in 0..4 {
+						// md5 signature
+						reader.read_bits(32)?;
+					}
+					stream_info = Some((block_size, sample_rate, channels, bits_per_sample, total_frames));
+				},
+				// SEEKTABLE
+				3 => {
+					for _ in 0..len / 18 {
+						let frame = (reader.read_bits(32)? as u64) << 32 | reader.read_bits(32)? as u64;
+						let offset = (reader.read_bits(32)? as u64) << 32 | reader.read_bits(32)? as u64;
+						reader.read_bits(16)?;
+						// skip placeholder points
+						if frame != u64::MAX {
+							seek_points.push((frame, offset));
+						}
+					}
+					for _ in 0..len % 18 {
+						reader.read_bits(8)?;
+					}
+				},
+				_ => {
+					for _ in 0..len {
+						reader.read_bits(8)?;
+					}
+				}
+			}
+			if last {
+				break;
+			}
+		}
+
+		let (block_size, sample_rate, channels, bits_per_sample, total_frames) =
+			stream_info.ok_or_else(|| invalid("missing flac STREAMINFO block"))?;
+		// the 3 bits of the channels always give 1 to 8 of them
+		if sample_rate == 0 {
+			return Err(invalid("flac sample rate of 0"));
+		}
+		if !(4..=32).contains(&bits_per_sample) {
+			return Err(invalid("flac bits per sample not between 4 and 32"));
+		}
+
+		Ok(Self {
+			first_frame: start + reader.byte_position(),
+			reader,
+			channels: channels as u16,
+			sample_rate,
+			bits_per_sample,
+			block_size,
+			total_frames,
+			seek_points,
+			samples: vec![vec![]; channels as usize],
+			buffer: vec![],
+			index: 0,
+			position: 0,
+			done: false
+		})
+	}
+
+
+	/// move to the given frame (sample per channel)
+	///
+	/// jumps to the closest SEEKTABLE point before `frame`, if any,
+	/// and decodes from there to be sample accurate
+	pub fn seek (&mut self, frame: u64) -> io::Result<()> {
+		let (point_frame, offset) = self.seek_points
+			.iter()
+			.rev()
+			.find(|x| x.0 <= frame)
+			.copied()
+			.unwrap_or((0, 0));
+
+		let pos = self.first_frame + offset;
+		self.reader.inner.seek(SeekFrom::Start(pos))?;
+		self.reader.clear();
+		self.buffer.clear();
+		self.index = 0;
+		self.position = point_frame;
+		self.done = false;
+
+		loop {
+			let first = self.position;
+			if !self.next_frame() {
+				self.done = true;
+				return Ok(());
+			}
+			if frame < self.position {
+				self.index = (frame.saturating_sub(first)) as usize * self.channels as usize;
+				return Ok(());
+			}
+		}
+	}
+
+
+	/// decode the next frame into `buffer`
+	///
+	/// return false if there is nothing more to decode
+	fn next_frame (&mut self) -> bool {
+		if self.total_frames != 0 && self.position >= self.total_frames {
+			return false;
+		}
+		match self.decode_frame() {
+			Ok(x) => x,
+			Err(err) => {
+				// same as the wav decoder, an error ends the sound
+				error!("error while decoding flac: {}", err);
+				false
+			}
+		}
+	}
+
+
+	fn decode_frame (&mut self) -> io::Result<bool> {
+
+		let r = &mut self.reader;
+		if r.at_end()? {
+			return Ok(false);
+		}
+
+		// 14 bits of sync code, and 1 reserved bit
+		if r.read_bits(15)? != 0x7ffc {
+			return Err(invalid("lost flac frame sync"));
+		}
+		let variable_block_size = r.read_bits(1)? == 1;
+		let block_size_code = r.read_bits(4)?;
+		let sample_rate_code = r.read_bits(4)?;
+		let channel_assignment = r.read_bits(4)?;
+		let sample_size_code = r.read_bits(3)?;
+		r.read_bits(1)?;
+
+		let number = read_utf8_number(r)?;
+
+		let block_size = match block_size_code {
+			0 => return Err(invalid("reserved flac block size")),
+			1 => 192,
+			2..=5 => 576 << (block_size_code - 2),
+			6 => r.read_bits(8)? + 1,
+			7 => r.read_bits(16)? + 1,
+			_ => 256 << (block_size_code - 8)
+		} as usize;
+
+		// the sample rate always comes from STREAMINFO, changing it
+		// mid stream would not make sense for the mixer
+		match sample_rate_code {
+			12 => { r.read_bits(8)?; },
+			13 | 14 => { r.read_bits(16)?; },
+			15 => return Err(invalid("invalid flac sample rate")),
+			_ => {}
+		}
+
+		let bits_per_sample = match sample_size_code {
+			0 => self.bits_per_sample,
+			1 => 8,
+			2 => 12,
+			4 => 16,
+			5 => 20,
+			6 => 24,
+			7 => 32,
+			_ => return Err(invalid("reserved flac sample size"))
+		};
+
+		// crc-8
+		r.read_bits(8)?;
+
+		let channels = match channel_assignment {
+			0..=7 => channel_assignment + 1,
+			8..=10 => 2,
+			_ => return Err(invalid("reserved flac channel assignment"))
+		};
+		if channels != self.channels as u32 {
+			return Err(invalid("flac frame has a different number of channels"));
+		}
+
+		for (c, samples) in self.samples.iter_mut().enumerate() {
+			// the side channel has one extra bit
+			let extra = match (channel_assignment, c) {
+				(8, 1) | (9, 0) | (10, 1) => 1,
+				_ => 0
+			};
+			samples.resize(block_size, 0);
+			decode_subframe(r, samples, bits_per_sample + extra)?;
+		}
+
+		r.align();
+		// crc-16
+		r.read_bits(16)?;
+
+		if channel_assignment >= 8 {
+			let (a, b) = self.samples.split_at_mut(1);
+			for (a, b) in a[0].iter_mut().zip(b[0].iter_mut()) {
+				let (left, right) = match channel_assignment {
+					// left/side
+					8 => (*a, a.wrapping_sub(*b)),
+					// side/right
+					9 => (a.wrapping_add(*b), *b),
+					// mid/side
+					_ => {
+						let mid = ((*a as i64) << 1) | (*b & 1) as i64;
+						(((mid + *b as i64) >> 1) as i32, ((mid - *b as i64) >> 1) as i32)
+					}
+				};
+				*a = left;
+				*b = right;
+			}
+		}
+
+		self.buffer.clear();
+		for i in 0..block_size {
+			for samples in self.samples.iter() {
+				let x = samples[i];
+				self.buffer.push(if bits_per_sample > 16 {
+					(x >> (bits_per_sample - 16)) as i16
+				} else {
+					(x << (16 - bits_per_sample)) as i16
+				});
+			}
+		}
+		self.index = 0;
+
+		self.position = if variable_block_size {
+			number
+		} else {
+			number * self.block_size as u64
+		} + block_size as u64;
+
+		Ok(true)
+
+	}
+
+
+}
+
+impl <T: Seek + Read + Send + 'static> SoundSource for FlacDecoder<T> {
+
+
+	fn reset (&mut self) {
+		if let Err(err) = self.seek(0) {
+			error!("error while seeking flac: {}", err);
+			self.done = true;
+		}
+	}
+
+
+	fn channels (&self) -> u16 {
+		self.channels
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	/// known if the encoder wrote it in STREAMINFO
+	fn total_frames (&self) -> Option<u64> {
+		(self.total_frames != 0).then_some(self.total_frames)
+	}
+
+
+	/// sample accurate, see [`FlacDecoder::seek`]
+	fn seek (&mut self, frame: u64) -> bool {
+		if let Err(err) = FlacDecoder::seek(self, frame) {
+			error!("error while seeking flac: {}", err);
+			self.done = true;
+		}
+		true
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+
+		let mut len = 0;
+		while len < buffer.len() && !self.done {
+			if self.index == self.buffer.len() && !self.next_frame() {
+				self.done = true;
+				break;
+			}
+			let n = (self.buffer.len() - self.index).min(buffer.len() - len);
+			buffer[len..len + n].copy_from_slice(&self.buffer[self.index..self.index + n]);
+			self.index += n;
+			len += n;
+		}
+		len
+
+	}
+
+
+}
+
+
+
+/// the frame or sample number of a frame header, coded like UTF-8
+fn read_utf8_number <T: Read> (r: &mut BitReader<T>) -> io::Result<u64> {
+	let first = r.read_bits(8)?;
+	let extra = (!(first << 24)).leading_zeros();
+	let (mut number, extra) = match extra {
+		0 => return Ok(first as u64),
+		2..=7 => ((first & (0x7f >> extra)) as u64, extra - 1),
+		_ => return Err(invalid("invalid flac frame number"))
+	};
+	for _ in 0..extra {
+		number = (number << 6) | (r.read_bits(8)? & 0x3f) as u64;
+	}
+	Ok(number)
+}
+
+
+
+fn decode_subframe <T: Read> (r: &mut BitReader<T>, out: &mut [i32], bits_per_sample: u32) -> io::Result<()> {
+
+	r.read_bits(1)?;
+	let kind = r.read_bits(6)?;
+	let wasted = if r.read_bits(1)? == 1 { r.read_unary()? + 1 } else { 0 };
+	if wasted >= bits_per_sample {
+		return Err(invalid("invalid flac wasted bits"));
+	}
+	let bits_per_sample = bits_per_sample - wasted;
+
+	match kind {
+		// constant
+		0 => {
+			let x = r.read_signed(bits_per_sample)?;
+			out.fill(x);
+		},
+		// verbatim
+		1 => {
+			for x in out.iter_mut() {
+				*x = r.read_signed(bits_per_sample)?;
+			}
+		},
+		// fixed predictor
+		8..=12 => {
+			let order = (kind - 8) as usize;
+			if order > out.len() {
+				return Err(invalid("flac predictor order bigger than block"));
+			}
+			for x in out[..order].iter_mut() {
+				*x = r.read_signed(bits_per_sample)?;
+			}
+			decode_residual(r, order, out)?;
+			for i in order..out.len() {
+				let prediction = match order {
+					0 => 0,
+					1 => out[i - 1] as i64,
+					2 => 2 * out[i - 1] as i64 - out[i - 2] as i64,
+					3 => 3 * out[i - 1] as i64 - 3 * out[i - 2] as i64 + out[i - 3] as i64,
+					_ => 4 * out[i - 1] as i64 - 6 * out[i - 2] as i64 + 4 * out[i - 3] as i64 - out[i - 4] as i64
+				};
+				out[i] = (out[i] as i64 + prediction) as i32;
+			}
+		},
+		// linear predictor
+		32..=63 => {
+			let order = (kind - 31) as usize;
+			if order > out.len() {
+				return Err(invalid("flac predictor order bigger than block"));
+			}
+			for x in out[..order].iter_mut() {
+				*x = r.read_signed(bits_per_sample)?;
+			}
+			let precision = r.read_bits(4)? + 1;
+			if precision == 16 {
+				return Err(invalid("invalid flac coefficient precision"));
+			}
+			let shift = r.read_signed(5)?;
+			if shift < 0 {
+				return Err(invalid("negative flac coefficient shift"));
+			}
+			let mut coefficients = [0i64; 32];
+			for c in coefficients[..order].iter_mut() {
+				*c = r.read_signed(precision)? as i64;
+			}
+			decode_residual(r, order, out)?;
+			for i in order..out.len() {
+				let prediction: i64 = coefficients[..order]
+					.iter()
+					.zip(out[i - order..i].iter().rev())
+					.map(|(c, x)| c * *x as i64)
+					.sum();
+				out[i] = (out[i] as i64 + (prediction >> shift)) as i32;
+			}
+		},
+		_ => return Err(invalid("reserved flac subframe type"))
+	}
+
+	if wasted > 0 {
+		for x in out.iter_mut() {
+			*x <<= wasted;
+		}
+	}
+
+	Ok(())
+
+}
+
+
+
+/// decode the rice coded residual into `out[order..]`
+fn decode_residual <T: Read> (r: &mut BitReader<T>, order: usize, out: &mut [i32]) -> io::Result<()> {
+
+	let parameter_bits = match r.read_bits(2)? {
+		0 => 4,
+		1 => 5,
+		_ => return Err(invalid("reserved flac residual coding method"))
+	};
+	let escape = (1 << parameter_bits) - 1;
+
+	let partition_order = r.read_bits(4)?;
+	let partition_size = out.len() >> partition_order;
+	if partition_size < order || (partition_size << partition_order) != out.len() {
+		return Err(invalid("invalid flac partition order"));
+	}
+
+	let mut i = order;
+	for p in 0..1 << partition_order {
+		let end = (p + 1) * partition_size;
+		let parameter = r.read_bits(parameter_bits)?;
+		if parameter == escape {
+			let bits = r.read_bits(5)?;
+			for x in out[i..end].iter_mut() {
+				*x = r.read_signed(bits)?;
+			}
+		} else {
+			for x in out[i..end].iter_mut() {
+				let high = r.read_unary()?;
+				let low = r.read_bits(parameter)?;
+				let folded = high.wrapping_shl(parameter) | low;
+				*x = (folded >> 1) as i32 ^ -((folded & 1) as i32);
+			}
+		}
+		i = end;
+	}
+
+	Ok(())
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::io::Cursor;
+
+	use super::*;
+
+
+	/// writes a byte stream bit by bit, most significant bit first
+	#[derive(Default)]
+	struct BitWriter {
+		bytes: Vec<u8>,
+		bits: u32
+	}
+
+	impl BitWriter {
+
+		fn bits (&mut self, value: u64, n: u32) {
+			for i in (0..n).rev() {
+				if self.bits.is_multiple_of(8) {
+					self.bytes.push(0);
+				}
+				let bit = (value >> i) as u8 & 1;
+				*self.bytes.last_mut().unwrap() |= bit << (7 - self.bits % 8);
+				self.bits += 1;
+			}
+		}
+
+		fn signed (&mut self, value: i32, n: u32) {
+			self.bits(value as u64 & ((1 << n) - 1), n);
+		}
+
+		fn rice (&mut self, value: i32, parameter: u32) {
+			let folded = if value >= 0 { (value as u32) << 1 } else { ((-value as u32) << 1) - 1 };
+			for _ in 0..folded >> parameter {
+				self.bits(0, 1);
+			}
+			self.bits(1, 1);
+			self.bits(folded as u64 & ((1 << parameter) - 1), parameter);
+		}
+
+		fn align (&mut self) {
+			self.bits += (8 - self.bits % 8) % 8;
+		}
+
+	}
+
+
+	/// a subframe, with the samples it decodes to
+	enum Subframe {
+		Constant(i32),
+		Verbatim(Vec<i32>),
+		/// the order and the samples, the residual in one partition with
+		/// a rice parameter of 2
+		Fixed(usize, Vec<i32>),
+		/// the coefficients, their shift and the samples
+		Lpc(Vec<i32>, u32, Vec<i32>),
+		/// a fixed predictor of order 0, the residual in two partitions,
+		/// the second escaped to verbatim with 7 bits per sample
+		Escaped(Vec<i32>)
+	}
+
+	impl Subframe {
+
+		fn samples (&self, len: usize) -> Vec<i32> {
+			match self {
+				Subframe::Constant(x) => vec![*x; len],
+				Subframe::Verbatim(x) | Subframe::Fixed(_, x) | Subframe::Lpc(_, _, x) | Subframe::Escaped(x) => x.clone()
+			}
+		}
+
+		fn write (&self, w: &mut BitWriter, bits: u32) {
+			w.bits(0, 1);
+			match self {
+				Subframe::Constant(x) => {
+					w.bits(0, 7);
+					w.signed(*x, bits);
+				},
+				Subframe::Verbatim(samples) => {
+					w.bits(1 << 1, 7);
+					for x in samples {
+						w.signed(*x, bits);
+					}
+				},
+				Subframe::Fixed(order, samples) => {
+					w.bits((8 + *order as u64) << 1, 7);
+					for x in &samples[..*order] {
+						w.signed(*x, bits);
+					}
+					let residual = (*order..samples.len()).map(|i| {
+						let x = |j: usize| samples[i - j] as i64;
+						let prediction = match order {
+							0 => 0,
+							1 => x(1),
+							2 => 2 * x(1) - x(2),
+							3 => 3 * x(1) - 3 * x(2) + x(3),
+							_ => 4 * x(1) - 6 * x(2) + 4 * x(3) - x(4)
+						};
+						(samples[i] as i64 - prediction) as i32
+					});
+					w.bits(0, 2);
+					w.bits(0, 4);
+					w.bits(2, 4);
+					for x in residual {
+						w.rice(x, 2);
+					}
+				},
+				Subframe::Lpc(coefficients, shift, samples) => {
+					let order = coefficients.len();
+					w.bits((31 + order as u64) << 1, 7);
+					for x in &samples[..order] {
+						w.signed(*x, bits);
+					}
+					// 12 bits of precision
+					w.bits(11, 4);
+					w.signed(*shift as i32, 5);
+					for c in coefficients {
+						w.signed(*c, 12);
+					}
+					// in 2 partitions, with a 5 bits parameter
+					w.bits(1, 2);
+					w.bits(1, 4);
+					for (p, range) in [order..samples.len() / 2, samples.len() / 2..samples.len()].into_iter().enumerate() {
+						w.bits(3 + p as u64, 5);
+						for i in range {
+							let prediction: i64 = coefficients.iter().enumerate().map(|(j, c)| *c as i64 * samples[i - j - 1] as i64).sum();
+							w.rice((samples[i] as i64 - (prediction >> shift)) as i32, 3 + p as u32);
+						}
+					}
+				},
+				Subframe::Escaped(samples) => {
+					w.bits(8 << 1, 7);
+					w.bits(0, 2);
+					w.bits(1, 4);
+					let half = samples.len() / 2;
+					w.bits(1, 4);
+					for x in &samples[..half] {
+						w.rice(*x, 1);
+					}
+					w.bits(15, 4);
+					w.bits(7, 5);
+					for x in &samples[half..] {
+						w.signed(*x, 7);
+					}
+				}
+			}
+		}
+
+	}
+
+
+	/// a frame of `len` samples per channel
+	fn frame (w: &mut BitWriter, number: u64, channel_assignment: u64, len: usize, subframes: &[Subframe]) {
+		w.bits(0x7ffc, 15);
+		w.bits(0, 1);
+		// the block size after the number, the rate and size of STREAMINFO
+		w.bits(7, 4);
+		w.bits(0, 4);
+		w.bits(channel_assignment, 4);
+		w.bits(0, 3);
+		w.bits(0, 1);
+		w.bits(number, 8);
+		w.bits(len as u64 - 1, 16);
+		w.bits(0, 8);
+		for (c, subframe) in subframes.iter().enumerate() {
+			let side = matches!((channel_assignment, c), (8, 1) | (9, 0) | (10, 1));
+			subframe.write(w, 16 + side as u32);
+		}
+		w.align();
+		w.bits(0, 16);
+	}
+
+
+	/// the start of a stream, with its STREAMINFO block
+	fn stream_info (w: &mut BitWriter, last: bool, channels: u32, block_size: u32, sample_rate: u32, bits: u32, total: u64) {
+		w.bits(u32::from_be_bytes(*b"fLaC") as u64, 32);
+		w.bits(last as u64, 1);
+		w.bits(0, 7);
+		w.bits(34, 24);
+		w.bits(block_size as u64, 16);
+		w.bits(block_size as u64, 16);
+		w.bits(0, 48);
+		w.bits(sample_rate as u64, 20);
+		w.bits(channels as u64 - 1, 3);
+		w.bits(bits as u64 - 1, 5);
+		w.bits(total, 36);
+		// the md5
+		w.bits(0, 64);
+		w.bits(0, 64);
+	}
+
+
+	/// a stream of 16 bits with frames of `block_size`, and a seek
+	/// table of `(frame, byte offset of the frame)`
+	fn flac (channels: u32, block_size: u32, total: u64, seek_points: &[(u64, u64)], frames: &[u8]) -> FlacDecoder<Cursor<Vec<u8>>> {
+		let mut w = BitWriter::default();
+		stream_info(&mut w, seek_points.is_empty(), channels, block_size, 44100, 16, total);
+		if !seek_points.is_empty() {
+			w.bits(1, 1);
+			w.bits(3, 7);
+			w.bits(18 * seek_points.len() as u64 + 18, 24);
+			for (frame, offset) in seek_points {
+				w.bits(*frame, 64);
+				w.bits(*offset, 64);
+				w.bits(block_size as u64, 16);
+			}
+			// a placeholder
+			w.bits(u64::MAX, 64);
+			w.bits(0, 64);
+			w.bits(0, 16);
+		}
+		w.bytes.extend_from_slice(frames);
+		FlacDecoder::new(Cursor::new(w.bytes)).unwrap()
+	}
+
+
+	fn read_all (decoder: &mut impl SoundSource) -> Vec<i16> {
+		let mut samples = vec![];
+		let mut buffer = [0; 6];
+		loop {
+			let len = decoder.write_samples(&mut buffer);
+			samples.extend_from_slice(&buffer[..len]);
+			if len < buffer.len() {
+				return samples;
+			}
+		}
+	}
+
+
+	fn mono (subframe: Subframe) -> Vec<i16> {
+		let mut w = BitWriter::default();
+		let expected = subframe.samples(8);
+		frame(&mut w, 0, 0, 8, &[subframe]);
+		let mut decoder = flac(1, 8, expected.len() as u64, &[], &w.bytes);
+		let samples = read_all(&mut decoder);
+		assert_eq!(samples, expected.iter().map(|&x| x as i16).collect::<Vec<_>>());
+		samples
+	}
+
+
+	#[test]
+	fn invalid_stream_info () {
+		for (sample_rate, bits) in [(0, 16), (44100, 3), (44100, 1)] {
+			let mut w = BitWriter::default();
+			stream_info(&mut w, true, 2, 8, sample_rate, bits, 0);
+			assert!(FlacDecoder::new(Cursor::new(w.bytes)).is_err(), "{} Hz, {} bits", sample_rate, bits);
+		}
+		let mut w = BitWriter::default();
+		stream_info(&mut w, true, 8, 8, 1, 32, 0);
+		let decoder = FlacDecoder::new(Cursor::new(w.bytes)).unwrap();
+		assert_eq!((decoder.channels(), decoder.sample_rate()), (8, 1));
+	}
+
+
+	const RAMP: [i32; 8] = [100, 250, 420, 610, 820, 1000, 1150, 1260];
+
+
+	#[test]
+	fn constant () {
+		assert_eq!(mono(Subframe::Constant(-1234)), [-1234; 8]);
+	}
+
+
+	#[test]
+	fn verbatim () {
+		mono(Subframe::Verbatim(vec![0, 1, -1, i16::MAX as i32, i16::MIN as i32, 7, -300, 42]));
+	}
+
+
+	#[test]
+	fn fixed () {
+		for order in 0..=4 {
+			mono(Subframe::Fixed(order, RAMP.to_vec()));
+		}
+	}
+
+
+	#[test]
+	fn lpc () {
+		mono(Subframe::Lpc(vec![1024, -512], 9, RAMP.to_vec()));
+		mono(Subframe::Lpc(vec![1500, 700, -220], 11, RAMP.to_vec()));
+	}
+
+
+	#[test]
+	fn rice_escape () {
+		mono(Subframe::Escaped(vec![3, -2, 0, 1, 63, -64, 0, -1]));
+	}
+
+
+	fn stereo (channel_assignment: u64, left: &[i32], right: &[i32]) {
+		let (a, b): (Vec<i32>, Vec<i32>) = match channel_assignment {
+			1 => (left.to_vec(), right.to_vec()),
+			8 => (left.to_vec(), left.iter().zip(right).map(|(l, r)| l - r).collect()),
+			9 => (left.iter().zip(right).map(|(l, r)| l - r).collect(), right.to_vec()),
+			_ => left.iter().zip(right).map(|(l, r)| ((l + r) >> 1, l - r)).unzip()
+		};
+		let mut w = BitWriter::default();
+		frame(&mut w, 0, channel_assignment, left.len(), &[Subframe::Verbatim(a), Subframe::Verbatim(b)]);
+		let mut decoder = flac(2, left.len() as u32, left.len() as u64, &[], &w.bytes);
+		let expected: Vec<i16> = left.iter().zip(right).flat_map(|(&l, &r)| [l as i16, r as i16]).collect();
+		assert_eq!(read_all(&mut decoder), expected, "channel assignment {}", channel_assignment);
+	}
+
+
+	#[test]
+	fn stereo_decorrelation () {
+		// the side of the extremes needs 17 bits, and odd sides lose a
+		// bit of the mid
+		let left = [0, 1, -1, 32767, -32768, 1000, 3, -7];
+		let right = [0, 0, 2, -32768, 32767, -1000, 4, 8];
+		for channel_assignment in [1, 8, 9, 10] {
+			stereo(channel_assignment, &left, &right);
+		}
+	}
+
+
+	/// 4 frames of 8, the last one of 4
+	fn frames () -> (Vec<u8>, Vec<u64>, Vec<i16>) {
+		let mut w = BitWriter::default();
+		let mut offsets = vec![];
+		let mut expected = vec![];
+		for number in 0..4 {
+			let samples: Vec<i32> = (0..if number == 3 { 4 } else { 8 }).map(|i| number as i32 * 100 + i).collect();
+			expected.extend(samples.iter().map(|&x| x as i16));
+			offsets.push(w.bytes.len() as u64);
+			frame(&mut w, number, 0, samples.len(), &[Subframe::Fixed(1, samples)]);
+		}
+		(w.bytes, offsets, expected)
+	}
+
+
+	#[test]
+	fn seek () {
+		let (bytes, offsets, expected) = frames();
+		let tables = [vec![], vec![(0, offsets[0]), (16, offsets[2])]];
+		for seek_points in tables {
+			let mut decoder = flac(1, 8, 28, &seek_points, &bytes);
+			assert_eq!(decoder.total_frames(), Some(28));
+			for frame in [0, 5, 8, 15, 16, 17, 27] {
+				assert!(SoundSource::seek(&mut decoder, frame));
+				assert_eq!(read_all(&mut decoder), expected[frame as usize..], "from {}", frame);
+			}
+			// past the end
+			assert!(SoundSource::seek(&mut decoder, 28));
+			assert_eq!(read_all(&mut decoder), []);
+			decoder.reset();
+			assert_eq!(read_all(&mut decoder), expected);
+		}
+	}
+
+
+}
@@ -0,0 +1,331 @@
+
+
+
+//! Granular playback of decoded samples, for scrubbing and stretched textures.
+//!
+//! Short grains are read from the samples around a playhead, each faded in and out with a Hann
+//! window, and overlapped. The playhead moves at its own speed, or stays where it is set, so the
+//! sound can be stretched, frozen or scrubbed without changing its pitch. The grains start at a
+//! steady rate, each from a random place close to the playhead, so they don't comb-filter.
+
+
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, AtomicU32, Ordering };
+use std::time::Duration;
+
+use crate::mixer::SoundSource;
+use crate::sound_data::SoundData;
+
+
+
+/// more grains at once are not started
+const MAX_GRAINS: usize = 64;
+
+
+
+/// the parameters shared with the controls, as the bits of `f32`s
+struct Params {
+	/// in seconds, set by the controls, read once `moved` is set
+	position: AtomicU32,
+	moved: AtomicBool,
+	/// in seconds, where the playhead is, set by the source
+	playhead: AtomicU32,
+	grain_seconds: AtomicU32,
+	density: AtomicU32,
+	pitch: AtomicU32,
+	spread_seconds: AtomicU32,
+	speed: AtomicU32
+}
+
+fn load (x: &AtomicU32) -> f32 {
+	f32::from_bits(x.load(Ordering::Relaxed))
+}
+
+fn store (x: &AtomicU32, value: f32) {
+	x.store(value.to_bits(), Ordering::Relaxed);
+}
+
+
+
+/// a grain being played
+struct Grain {
+	/// the frame of the samples the grain started at
+	start: f64,
+	/// frames of the samples read for each output frame
+	step: f64,
+	/// output frames played, and the frames the grain lasts
+	age: usize,
+	length: usize
+}
+
+
+
+/// plays a [`SoundData`] in short overlapped grains, as a
+/// [`SoundSource`]
+///
+/// it never ends, the playhead wraps around the samples. a speed of
+/// `0.0` freezes the playhead, so it can be moved by a tape-scrub ui
+/// with [`GranularControls::set_position`]. a low speed stretches
+/// the sound into an ambient texture
+///
+/// ```
+/// # use std::time::Duration;
+/// # use audio_engine::{ AudioEngine, Granular, SineWave, SoundSourceExt };
+/// # let (engine, _backend) = AudioEngine::offline(2, 48000);
+/// # let data = engine.load(SineWave::new(440.0).take_duration(Duration::from_secs(1)));
+/// let granular = Granular::new(data).speed(0.25).grain_size(Duration::from_millis(200));
+/// let controls = granular.controls();
+/// engine.new_sound(granular, |x| x)?.play();
+/// controls.set_pitch(-12.0);
+/// # Ok::<(), audio_engine::Error>(())
+/// ```
+pub struct Granular {
+
+	data: SoundData,
+	params: Arc<Params>,
+	grains: Vec<Grain>,
+	/// in frames of the samples
+	playhead: f64,
+	/// output frames until the next grain starts
+	until_next: f64,
+	/// the grains are mixed here
+	mix: Vec<f32>,
+	rng: u32
+
+}
+
+impl Granular {
+
+
+	/// grains of `100ms`, 20 a second, at the speed and pitch of the
+	/// samples
+	pub fn new (data: SoundData) -> Self {
+		Self {
+			data,
+			params: Arc::new(Params {
+				position: AtomicU32::new(0.0f32.to_bits()),
+				moved: AtomicBool::new(false),
+				playhead: AtomicU32::new(0.0f32.to_bits()),
+				grain_seconds: AtomicU32::new(0.1f32.to_bits()),
+				density: AtomicU32::new(20.0f32.to_bits()),
+				pitch: AtomicU32::new(0.0f32.to_bits()),
+				spread_seconds: AtomicU32::new(0.01f32.to_bits()),
+				speed: AtomicU32::new(1.0f32.to_bits())
+			}),
+			grains: Vec::with_capacity(MAX_GRAINS),
+			playhead: 0.0,
+			until_next: 0.0,
+			mix: vec![],
+			rng: 0x9E37_79B9
+		}
+	}
+
+
+	/// where the playhead starts
+	pub fn position (self, position: Duration) -> Self {
+		self.controls().set_position(position);
+		self
+	}
+
+
+	pub fn grain_size (self, size: Duration) -> Self {
+		self.controls().set_grain_size(size);
+		self
+	}
+
+
+	pub fn density (self, grains_per_second: f32) -> Self {
+		self.controls().set_density(grains_per_second);
+		self
+	}
+
+
+	pub fn pitch (self, semitones: f32) -> Self {
+		self.controls().set_pitch(semitones);
+		self
+	}
+
+
+	pub fn spread (self, spread: Duration) -> Self {
+		self.controls().set_spread(spread);
+		self
+	}
+
+
+	pub fn speed (self, speed: f32) -> Self {
+		self.controls().set_speed(speed);
+		self
+	}
+
+
+	/// move the playhead and change the grains while it plays
+	pub fn controls (&self) -> GranularControls {
+		GranularControls { params: self.params.clone() }
+	}
+
+
+	/// a random number from `-1.0` to `1.0`
+	fn random (&mut self) -> f64 {
+		self.rng ^= self.rng << 13;
+		self.rng ^= self.rng >> 17;
+		self.rng ^= self.rng << 5;
+		self.rng as f64 / u32::MAX as f64 * 2.0 - 1.0
+	}
+
+
+	fn start_grain (&mut self, frames: f64, sample_rate: f64) {
+		if self.grains.len() == MAX_GRAINS {
+			return;
+		}
+		let spread = load(&self.params.spread_seconds).max(0.0) as f64 * sample_rate;
+		let start = (self.playhead + self.random() * spread).rem_euclid(frames);
+		let length = (load(&self.params.grain_seconds) as f64 * sample_rate).max(2.0) as usize;
+		let step = 2f64.powf(load(&self.params.pitch) as f64 / 12.0);
+		self.grains.push(Grain { start, step, age: 0, length });
+	}
+
+
+}
+
+impl SoundSource for Granular {
+
+	fn channels (&self) -> u16 {
+		self.data.channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.data.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.grains.clear();
+		self.playhead = 0.0;
+		self.until_next = 0.0;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let channels = self.data.channels().max(1) as usize;
+		let sample_rate = self.data.sample_rate() as f64;
+		let frames = self.data.frames() as f64;
+		if frames == 0.0 {
+			buffer.fill(0);
+			return buffer.len();
+		}
+		if self.params.moved.swap(false, Ordering::Relaxed) {
+			self.playhead = (load(&self.params.position) as f64 * sample_rate).clamp(0.0, frames - 1.0);
+		}
+
+		let density = load(&self.params.density).max(0.0) as f64;
+		let speed = load(&self.params.speed) as f64;
+		let interval = if density > 0.0 { sample_rate / density } else { f64::INFINITY };
+		// a higher density is taken at once
+		self.until_next = self.until_next.min(interval);
+		// the hann windows of the grains add up to half their overlap
+		let overlap = density * load(&self.params.grain_seconds).max(0.0) as f64;
+		let gain = 1.0 / (overlap * 0.5).max(1.0) as f32;
+
+		self.mix.clear();
+		self.mix.resize(buffer.len(), 0.0);
+		let output_frames = buffer.len() / channels;
+		let mut frame = 0;
+		while frame < output_frames {
+			// the grains are mixed up to the next one that starts
+			if self.until_next <= 0.0 {
+				self.start_grain(frames, sample_rate);
+				self.until_next += interval;
+			}
+			let end = frame + self.until_next.ceil().clamp(1.0, (output_frames - frame) as f64) as usize;
+			let samples = self.data.samples();
+			for grain in self.grains.iter_mut() {
+				let len = (grain.length - grain.age).min(end - frame);
+				for f in 0..len {
+					let age = grain.age + f;
+					let window = 0.5 - 0.5 * (std::f32::consts::TAU * age as f32 / grain.length as f32).cos();
+					let position = (grain.start + age as f64 * grain.step).rem_euclid(frames);
+					let i = position as usize;
+					let t = (position - i as f64) as f32;
+					let j = (i + 1) % frames as usize;
+					for c in 0..channels {
+						let a = samples[i * channels + c] as f32;
+						let b = samples[j * channels + c] as f32;
+						self.mix[(frame + f) * channels + c] += (a + (b - a) * t) * window;
+					}
+				}
+				grain.age += len;
+			}
+			self.grains.retain(|x| x.age < x.length);
+			self.until_next -= (end - frame) as f64;
+			self.playhead = (self.playhead + (end - frame) as f64 * speed).rem_euclid(frames);
+			frame = end;
+		}
+
+		for (x, y) in buffer.iter_mut().zip(self.mix.iter()) {
+			*x = (y * gain).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+		}
+		store(&self.params.playhead, (self.playhead / sample_rate) as f32);
+		buffer.len()
+	}
+
+}
+
+
+
+/// changes a [`Granular`] from any thread, from the next mixed
+/// buffer
+#[derive(Clone)]
+pub struct GranularControls {
+	params: Arc<Params>
+}
+
+impl GranularControls {
+
+
+	/// move the playhead, for scrubbing
+	pub fn set_position (&self, position: Duration) {
+		store(&self.params.position, position.as_secs_f32());
+		self.params.moved.store(true, Ordering::Relaxed);
+	}
+
+
+	/// where the playhead is, as of the last mixed buffer
+	pub fn position (&self) -> Duration {
+		Duration::from_secs_f32(load(&self.params.playhead).max(0.0))
+	}
+
+
+	/// how long each grain lasts, `100ms` by default. longer grains
+	/// sound closer to the samples, shorter ones more granular
+	pub fn set_grain_size (&self, size: Duration) {
+		store(&self.params.grain_seconds, size.as_secs_f32());
+	}
+
+
+	/// how many grains start each second, `20` by default
+	pub fn set_density (&self, grains_per_second: f32) {
+		store(&self.params.density, grains_per_second);
+	}
+
+
+	/// in semitones, `0.0` by default. changes the pitch of the
+	/// grains, not how fast the playhead moves
+	pub fn set_pitch (&self, semitones: f32) {
+		store(&self.params.pitch, semitones);
+	}
+
+
+	/// how far from the playhead a grain may start, at random, `10ms`
+	/// by default
+	pub fn set_spread (&self, spread: Duration) {
+		store(&self.params.spread_seconds, spread.as_secs_f32());
+	}
+
+
+	/// how fast the playhead moves, `1.0` by default. `0.0` freezes
+	/// it, a negative speed moves it backward
+	pub fn set_speed (&self, speed: f32) {
+		store(&self.params.speed, speed);
+	}
+
+
+}
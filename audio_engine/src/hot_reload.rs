@@ -0,0 +1,196 @@
+
+
+
+//! Reloading of the sound banks while their files change, to tune a mix without restarting.
+//!
+//! A thread looks at when the banks and their files were last modified, a few times a second,
+//! which also sees files pushed with `adb push`. A bank that fails to reload keeps its events,
+//! and is tried again on its next change.
+
+
+
+use log::{ error, info };
+
+use std::collections::HashMap;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Mutex, Once, Weak };
+use std::time::{ Duration, SystemTime };
+
+use crate::bank::{ self, BankEvent };
+use crate::mixer::Mixer;
+
+
+
+/// how often the files are looked at
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+
+
+/// a loaded bank, with the engine it was loaded in
+struct Watched {
+	path: PathBuf,
+	/// the bank and its files, with when they were last modified
+	files: Vec<(PathBuf, Option<SystemTime>)>,
+	/// the names of its events, without the ones a bank loaded later
+	/// in the same engine replaced
+	events: Vec<String>,
+	bank: Weak<Mutex<HashMap<String, BankEvent>>>,
+	mixer: Weak<Mutex<Mixer>>
+}
+
+impl Watched {
+
+
+	fn modified (&self) -> bool {
+		self.files.iter().any(|(path, time)| modified(path) != *time)
+	}
+
+
+	fn reload (&mut self) {
+		let (Some(bank), Some(mixer)) = (self.bank.upgrade(), self.mixer.upgrade()) else {
+			return;
+		};
+		let group = |name: &str| {
+			let mut mixer = mixer.lock().unwrap();
+			mixer.find_group(name).unwrap_or_else(|| mixer.add_group(name, None))
+		};
+		match bank::load(&self.path, group) {
+			Ok((events, files)) => {
+				let mut bank = bank.lock().unwrap();
+				for name in self.events.drain(..) {
+					bank.remove(&name);
+				}
+				self.events = events.iter().map(|x| x.0.clone()).collect();
+				bank.extend(events);
+				self.files = watched_files(self.path.clone(), files);
+				info!("reloaded the sound bank {}", self.path.display());
+			},
+			Err(err) => {
+				error!("error while reloading the sound bank {}: {}", self.path.display(), err);
+				// not tried again until it changes again
+				for (path, time) in self.files.iter_mut() {
+					*time = modified(path);
+				}
+			}
+		}
+	}
+
+
+}
+
+
+
+static WATCHED: Mutex<Vec<Watched>> = Mutex::new(Vec::new());
+
+static STARTED: Once = Once::new();
+
+
+
+/// reload the bank at `path` while it or `files` change, until the
+/// engine is dropped
+pub (crate) fn watch (
+	path: PathBuf,
+	files: Vec<PathBuf>,
+	events: Vec<String>,
+	bank: &Arc<Mutex<HashMap<String, BankEvent>>>,
+	mixer: &Arc<Mutex<Mixer>>
+) {
+	let mut watched = WATCHED.lock().unwrap();
+	// loading the same bank again reloads it
+	watched.retain(|x| x.path != path || !Weak::ptr_eq(&x.bank, &Arc::downgrade(bank)));
+	watched.push(Watched {
+		files: watched_files(path.clone(), files),
+		path,
+		events,
+		bank: Arc::downgrade(bank),
+		mixer: Arc::downgrade(mixer)
+	});
+	let last = watched.len() - 1;
+	disown(&mut watched, last);
+	STARTED.call_once(|| {
+		std::thread::spawn(poll);
+	});
+}
+
+
+fn poll () {
+	loop {
+		std::thread::sleep(POLL_INTERVAL);
+		let mut watched = WATCHED.lock().unwrap();
+		watched.retain(|x| x.bank.strong_count() > 0);
+		for i in 0..watched.len() {
+			if watched[i].modified() {
+				watched[i].reload();
+				disown(&mut watched, i);
+			}
+		}
+	}
+}
+
+
+/// the banks loaded in the same engine as `watched[owner]` no longer
+/// own its events, so they don't remove them when they reload
+fn disown (watched: &mut [Watched], owner: usize) {
+	let events = std::mem::take(&mut watched[owner].events);
+	let bank = watched[owner].bank.clone();
+	for x in watched.iter_mut().filter(|x| Weak::ptr_eq(&x.bank, &bank)) {
+		x.events.retain(|name| !events.contains(name));
+	}
+	watched[owner].events = events;
+}
+
+
+fn watched_files (path: PathBuf, files: Vec<PathBuf>) -> Vec<(PathBuf, Option<SystemTime>)> {
+	std::iter::once(path).chain(files).map(|x| {
+		let time = modified(&x);
+		(x, time)
+	}).collect()
+}
+
+
+fn modified (path: &Path) -> Option<SystemTime> {
+	std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::collections::HashMap;
+	use std::path::PathBuf;
+	use std::sync::{ Arc, Mutex, Weak };
+
+	use crate::bank::BankEvent;
+	use super::{ Watched, disown };
+
+
+	/// the bank at `path` with `events`, loaded in the engine of `bank`
+	fn watched (path: &str, events: &[&str], bank: &Arc<Mutex<HashMap<String, BankEvent>>>) -> Watched {
+		Watched {
+			path: PathBuf::from(path),
+			files: vec![],
+			events: events.iter().map(|x| x.to_string()).collect(),
+			bank: Arc::downgrade(bank),
+			mixer: Weak::new()
+		}
+	}
+
+
+	#[test]
+	fn later_banks_own_their_events () {
+		let (bank, other) = (Arc::default(), Arc::default());
+		let mut banks = [
+			watched("a.json", &["jump", "step", "door"], &bank),
+			watched("b.json", &["jump", "door"], &other),
+			watched("c.json", &["step", "music"], &bank)
+		];
+		disown(&mut banks, 2);
+		assert_eq!(banks[0].events, ["jump", "door"]);
+		// another engine keeps its events
+		assert_eq!(banks[1].events, ["jump", "door"]);
+		assert_eq!(banks[2].events, ["step", "music"]);
+	}
+
+
+}
@@ -0,0 +1,272 @@
+
+
+
+//! Binaural rendering of placed sounds, for headphones.
+//!
+//! Every placed sound is filtered with the head related impulse responses of its direction,
+//! one for each ear, which gives the delay and the shadow of the head between the ears. The
+//! responses are switched with a crossfade over a buffer when the sound moves.
+
+
+
+use std::f32::consts::FRAC_PI_2;
+
+
+
+/// radius of the head, in meters
+const HEAD_RADIUS: f32 = 0.0875;
+
+/// speed of sound, in meters per second
+const SPEED_OF_SOUND: f32 = 343.0;
+
+
+
+/// the impulse responses of both ears for one direction
+#[derive(Debug, Clone, PartialEq)]
+pub struct HrtfResponse {
+
+	/// in degrees, `0.0` is in front and `90.0` on the right
+	pub azimuth: f32,
+	/// in degrees, `90.0` is above
+	pub elevation: f32,
+	pub left: Vec<f32>,
+	pub right: Vec<f32>
+
+}
+
+
+
+/// a set of [`HrtfResponse`]s all around the head, see
+/// [`AudioEngine::set_hrtf`](crate::AudioEngine::set_hrtf)
+///
+/// measured responses, like the ones of a SOFA file, can be given
+/// with [`Hrtf::new`]. [`Hrtf::spherical_head`] is a built in model
+/// that needs no data
+#[derive(Debug, Clone)]
+pub struct Hrtf {
+
+	sample_rate: u32,
+	/// every response is padded to the same length
+	responses: Vec<HrtfResponse>,
+	/// the unit vector of each response, right, up and front
+	directions: Vec<[f32; 3]>,
+	length: usize
+
+}
+
+impl Hrtf {
+
+
+	/// a set of `responses` recorded at `sample_rate`
+	///
+	/// panics if there are no responses
+	pub fn new (sample_rate: u32, mut responses: Vec<HrtfResponse>) -> Self {
+		assert!(!responses.is_empty(), "an hrtf needs at least one response");
+		let length = responses.iter().map(|x| x.left.len().max(x.right.len())).max().unwrap_or(0).max(1);
+		for response in responses.iter_mut() {
+			response.left.resize(length, 0.0);
+			response.right.resize(length, 0.0);
+		}
+		let directions = responses.iter().map(|x| direction(x.azimuth, x.elevation)).collect();
+		Self { sample_rate, responses, directions, length }
+	}
+
+
+	/// a model of a round head, with the delay between the ears and the
+	/// shadow of the head on the far ear
+	///
+	/// it has no pinna, so sounds in front and behind sound alike, but
+	/// it places sounds on the sides much better than panning
+	pub fn spherical_head (sample_rate: u32) -> Self {
+		let length = (sample_rate as f32 * 0.0015) as usize + 16;
+		let mut responses = vec![];
+		for elevation in (-40..=90).step_by(10) {
+			for azimuth in (-180..180).step_by(10) {
+				let (azimuth, elevation) = (azimuth as f32, elevation as f32);
+				let source = direction(azimuth, elevation);
+				responses.push(HrtfResponse {
+					azimuth,
+					elevation,
+					left: sphere_response(source, [-1.0, 0.0, 0.0], sample_rate, length),
+					right: sphere_response(source, [1.0, 0.0, 0.0], sample_rate, length)
+				});
+				// there is only one direction straight up
+				if elevation == 90.0 {
+					break;
+				}
+			}
+		}
+		Self::new(sample_rate, responses)
+	}
+
+
+	pub fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	/// the same responses at `sample_rate`, with linear interpolation
+	pub fn resample (&self, sample_rate: u32) -> Self {
+		if sample_rate == self.sample_rate {
+			return self.clone();
+		}
+		let ratio = self.sample_rate as f32 / sample_rate as f32;
+		let length = ((self.length as f32 / ratio).ceil() as usize).max(1);
+		let resample = |x: &[f32]| -> Vec<f32> {
+			(0..length).map(|i| {
+				let position = i as f32 * ratio;
+				let index = position as usize;
+				let fraction = position - index as f32;
+				let a = x.get(index).copied().unwrap_or(0.0);
+				let b = x.get(index + 1).copied().unwrap_or(0.0);
+				// scaled so the gain stays the same
+				(a + (b - a) * fraction) * ratio.min(1.0)
+			}).collect()
+		};
+		let responses = self.responses.iter().map(|x| HrtfResponse {
+			azimuth: x.azimuth,
+			elevation: x.elevation,
+			left: resample(&x.left),
+			right: resample(&x.right)
+		}).collect();
+		Self::new(sample_rate, responses)
+	}
+
+
+	/// the response that is the closest to `direction`, given as right,
+	/// up and front
+	pub (crate) fn nearest (&self, direction: [f32; 3]) -> usize {
+		let mut best = (0, f32::MIN);
+		for (i, x) in self.directions.iter().enumerate() {
+			let dot = x[0] * direction[0] + x[1] * direction[1] + x[2] * direction[2];
+			if dot > best.1 {
+				best = (i, dot);
+			}
+		}
+		best.0
+	}
+
+
+}
+
+
+
+/// the unit vector of a direction, right, up and front
+fn direction (azimuth: f32, elevation: f32) -> [f32; 3] {
+	let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+	[azimuth.sin() * elevation.cos(), elevation.sin(), azimuth.cos() * elevation.cos()]
+}
+
+
+
+/// the response of the ear at `ear` on a round head to a sound from
+/// `source`, with the head shadow filter of Brown and Duda
+fn sphere_response (source: [f32; 3], ear: [f32; 3], sample_rate: u32, length: usize) -> Vec<f32> {
+	let dot = source[0] * ear[0] + source[1] * ear[1] + source[2] * ear[2];
+	// the angle between the sound and the ear
+	let angle = dot.clamp(-1.0, 1.0).acos();
+
+	// the sound goes around the head to reach the far ear
+	let delay = if angle < FRAC_PI_2 {
+		HEAD_RADIUS / SPEED_OF_SOUND * (1.0 - angle.cos())
+	} else {
+		HEAD_RADIUS / SPEED_OF_SOUND * (angle - FRAC_PI_2 + 1.0)
+	};
+	let delay = delay * sample_rate as f32;
+
+	// a shelf that boosts the near ear and cuts the far one, lowest at
+	// 150 degrees from the ear
+	let alpha = 1.05 + 0.95 * (angle * 180.0 / 150.0).cos();
+	let tau = HEAD_RADIUS / (2.0 * SPEED_OF_SOUND);
+	let k = 2.0 * sample_rate as f32;
+	let b0 = (1.0 + alpha * tau * k) / (1.0 + tau * k);
+	let b1 = (1.0 - alpha * tau * k) / (1.0 + tau * k);
+	let a1 = (1.0 - tau * k) / (1.0 + tau * k);
+
+	let whole = delay as usize;
+	let fraction = delay - whole as f32;
+	let mut response = vec![0.0; length];
+	let (mut x1, mut y1) = (0.0, 0.0);
+	for (i, y) in response.iter_mut().enumerate() {
+		// the impulse, split between two samples for the fraction
+		let x = if i == whole { 1.0 - fraction } else if i == whole + 1 { fraction } else { 0.0 };
+		*y = b0 * x + b1 * x1 - a1 * y1;
+		x1 = x;
+		y1 = *y;
+	}
+	response
+}
+
+
+
+/// the state of the binaural rendering of one sound
+pub (crate) struct Binaural {
+
+	/// the last inputs, twice so the newest ones are always in one slice
+	history: Vec<f32>,
+	/// where the next input goes in `history`
+	position: usize,
+	/// the response used in the last buffer
+	response: Option<usize>
+
+}
+
+impl Binaural {
+
+
+	pub fn new () -> Self {
+		Self { history: vec![], position: 0, response: None }
+	}
+
+
+	/// turn the 2 channel `frames` into what each ear hears from
+	/// `direction`, given as right, up and front
+	///
+	/// both channels are mixed to mono first
+	pub fn process (&mut self, hrtf: &Hrtf, frames: &mut [f32], direction: [f32; 3]) {
+		let length = hrtf.length;
+		if self.history.len() != length * 2 {
+			self.history = vec![0.0; length * 2];
+			self.position = 0;
+		}
+		let next = hrtf.nearest(direction);
+		let previous = self.response.unwrap_or(next);
+		self.response = Some(next);
+
+		let count = frames.len() / 2;
+		for (f, frame) in frames.chunks_exact_mut(2).enumerate() {
+			let x = (frame[0] + frame[1]) * 0.5;
+			self.history[self.position] = x;
+			self.history[self.position + length] = x;
+			// oldest first
+			let window = &self.history[self.position + 1..self.position + 1 + length];
+			self.position = (self.position + 1) % length;
+
+			let (left, right) = convolve(&hrtf.responses[next], window);
+			let (left, right) = if previous == next {
+				(left, right)
+			} else {
+				let t = f as f32 / count as f32;
+				let (old_left, old_right) = convolve(&hrtf.responses[previous], window);
+				(old_left + (left - old_left) * t, old_right + (right - old_right) * t)
+			};
+			frame[0] = left;
+			frame[1] = right;
+		}
+	}
+
+
+}
+
+
+
+/// both ears of `response` for the last inputs in `window`, oldest first
+fn convolve (response: &HrtfResponse, window: &[f32]) -> (f32, f32) {
+	let mut left = 0.0;
+	let mut right = 0.0;
+	for ((x, l), r) in window.iter().rev().zip(&response.left).zip(&response.right) {
+		left += x * l;
+		right += x * r;
+	}
+	(left, right)
+}
@@ -0,0 +1,656 @@
+
+
+
+//! Sounds streamed over HTTP, like internet radios or music downloaded as it plays.
+//!
+//! The stream is read and decoded on the thread of a [`StreamingDecoder`], so a slow network only
+//! drains the prefetch. A dropped connection is opened again, from where it was for a download
+//! with a known length, or from the live edge for a radio. SHOUTcast and Icecast metadata is
+//! taken out of the stream, and its title is kept in the [`HttpStatus`].
+//!
+//! Only `http://` is supported, there is no TLS.
+
+
+
+use log::{ debug, warn };
+use symphonia::core::probe::Hint;
+
+use std::io::{ self, BufRead, BufReader, Read, Seek, SeekFrom, Write };
+use std::net::{ TcpStream, ToSocketAddrs };
+use std::sync::{ Arc, Mutex, PoisonError };
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::thread;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::mixer::SoundSource;
+use crate::queue::Queue;
+use crate::streaming::StreamingDecoder;
+use crate::universal::SymphoniaDecoder;
+
+
+
+/// decoded ahead by [`HttpStreamSource::connect`], more than for
+/// a file since the network is slower and less steady
+const DEFAULT_PREFETCH: Duration = Duration::from_secs(4);
+
+/// how long a connection or a read may take before the connection
+/// is opened again
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// how many times in a row a dropped connection is opened again,
+/// waiting twice as long every time from `RECONNECT_DELAY`
+const MAX_RECONNECTS: u32 = 5;
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+const MAX_REDIRECTS: u32 = 5;
+
+/// the longest header line, and how many lines there can be
+const MAX_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/// the buffer level under which a [`HttpEvent::BufferLow`] is sent
+const LOW_LEVEL: f32 = 0.25;
+
+/// events kept until they are read
+const EVENT_CAPACITY: usize = 64;
+
+
+
+/// something that happened to a [`HttpStreamSource`], read with
+/// [`HttpStatus::events`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpEvent {
+	/// the connection dropped and is being opened again
+	Reconnecting,
+	/// the connection was opened again
+	Reconnected,
+	/// the connection couldn't be opened again, the sound ends
+	Failed,
+	/// the buffer ran dry, the sound plays silence until it fills
+	Buffering,
+	/// the buffer filled again after [`HttpEvent::Buffering`]
+	Buffered,
+	/// the buffer went under a quarter, a sign of a slow network.
+	/// it's sent again after the buffer is back over a half
+	BufferLow,
+	/// the title the radio sent, like `Artist - Song`
+	Title(String)
+}
+
+
+
+/// state shared by the stream and its [`HttpStatus`]es
+struct Status {
+	/// the buffer level, as the bits of a `f32`
+	level: AtomicU32,
+	name: Option<String>,
+	title: Mutex<Option<String>>,
+	events: Queue<HttpEvent>
+}
+
+
+
+/// the state of a [`HttpStreamSource`], which can be kept after the
+/// source is moved into the engine
+#[derive(Clone)]
+pub struct HttpStatus(Arc<Status>);
+
+impl HttpStatus {
+
+
+	/// how full the buffer is, from `0.0` to `1.0`
+	pub fn buffer_level (&self) -> f32 {
+		f32::from_bits(self.0.level.load(Ordering::Relaxed))
+	}
+
+
+	/// the name of the radio, from the `icy-name` header
+	pub fn name (&self) -> Option<&str> {
+		self.0.name.as_deref()
+	}
+
+
+	/// the last title the radio sent
+	pub fn title (&self) -> Option<String> {
+		self.0.title.lock().unwrap_or_else(PoisonError::into_inner).clone()
+	}
+
+
+	/// take the events since the last call, oldest first
+	///
+	/// events are kept only up to a limit, after that new ones are lost
+	pub fn events (&self) -> impl Iterator<Item = HttpEvent> + '_ {
+		std::iter::from_fn(move || self.0.events.pop())
+	}
+
+
+}
+
+
+
+/// where a stream is read from
+#[derive(Clone)]
+struct Url {
+	host: String,
+	port: u16,
+	path: String
+}
+
+impl Url {
+
+
+	fn parse (url: &str) -> io::Result<Self> {
+		let invalid = |message| io::Error::new(io::ErrorKind::InvalidInput, message);
+		if url.starts_with("https://") {
+			return Err(invalid("https is not supported"));
+		}
+		let rest = url.strip_prefix("http://").ok_or(invalid("not a http url"))?;
+		let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+		let (host, port) = match authority.rsplit_once(':') {
+			Some((host, port)) => (host, port.parse().map_err(|_| invalid("invalid port in the url"))?),
+			None => (authority, 80)
+		};
+		if host.is_empty() {
+			return Err(invalid("no host in the url"));
+		}
+		Ok(Self {
+			host: host.to_owned(),
+			port,
+			path: if path.is_empty() { "/".to_owned() } else { path.to_owned() }
+		})
+	}
+
+
+	/// a `Location` header, which can be relative to `self`
+	fn join (&self, location: &str) -> io::Result<Self> {
+		if location.contains("://") {
+			return Self::parse(location);
+		}
+		let path = if location.starts_with('/') {
+			location.to_owned()
+		} else {
+			format!("{}/{}", &self.path[..self.path.rfind('/').unwrap_or(0)], location)
+		};
+		Ok(Self { path, ..self.clone() })
+	}
+
+
+}
+
+
+
+/// the body of an answer, after its headers
+struct Response {
+	body: BufReader<TcpStream>,
+	/// the length of the whole file, if known
+	length: Option<u64>,
+	/// the server took the range, so the body starts at the offset
+	partial: bool,
+	/// the bytes of audio between two metadata blocks
+	metaint: Option<usize>,
+	/// sent by radios, which play forever
+	live: bool,
+	name: Option<String>,
+	content_type: Option<String>
+}
+
+
+
+/// send a request for `url` from `offset`, following redirects
+fn request (url: &Url, offset: u64) -> io::Result<Response> {
+	let mut url = url.clone();
+	for _ in 0..=MAX_REDIRECTS {
+		let address = (url.host.as_str(), url.port)
+			.to_socket_addrs()?
+			.next()
+			.ok_or(io::Error::new(io::ErrorKind::NotFound, "the host has no address"))?;
+		let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+		stream.set_read_timeout(Some(TIMEOUT))?;
+		stream.set_write_timeout(Some(TIMEOUT))?;
+		// http 1.0, so the body is never chunked
+		let range = if offset > 0 { format!("Range: bytes={}-\r\n", offset) } else { String::new() };
+		let request = format!(
+			"GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: audio_engine\r\nIcy-MetaData: 1\r\n{}Connection: close\r\n\r\n",
+			url.path, url.host, range
+		);
+		stream.write_all(request.as_bytes())?;
+
+		let mut body = BufReader::new(stream);
+		let status = read_line(&mut body)?;
+		// shoutcast answers `ICY 200 OK`
+		let code: u16 = status.split_whitespace().nth(1).and_then(|x| x.parse().ok()).unwrap_or(0);
+		let mut response = Response {
+			body,
+			length: None,
+			partial: code == 206,
+			metaint: None,
+			live: false,
+			name: None,
+			content_type: None
+		};
+		let mut location = None;
+		for _ in 0..MAX_HEADERS {
+			let line = read_line(&mut response.body)?;
+			if line.is_empty() {
+				break;
+			}
+			let Some((key, value)) = line.split_once(':') else {
+				continue;
+			};
+			let (key, value) = (key.trim().to_ascii_lowercase(), value.trim());
+			match key.as_str() {
+				"location" => location = Some(value.to_owned()),
+				"content-length" => response.length = value.parse().ok(),
+				"content-type" => response.content_type = Some(value.to_owned()),
+				"icy-metaint" => response.metaint = value.parse().ok().filter(|&x| x > 0),
+				"icy-name" => response.name = Some(value.to_owned()),
+				_ => ()
+			}
+			response.live |= key.starts_with("icy-") || key.starts_with("ice-");
+		}
+		match code {
+			200 | 206 => {
+				// the length of what is left, for a range
+				if response.partial {
+					response.length = response.length.map(|x| x + offset);
+				}
+				response.live &= response.length.is_none();
+				return Ok(response);
+			},
+			301 | 302 | 303 | 307 | 308 => {
+				let location = location.ok_or(io::Error::new(io::ErrorKind::InvalidData, "redirect without a location"))?;
+				url = url.join(&location)?;
+			},
+			_ => return Err(io::Error::other(format!("the server answered {}", status)))
+		}
+	}
+	Err(io::Error::other("too many redirects"))
+}
+
+
+
+/// a line of the headers, without its end
+fn read_line (reader: &mut BufReader<TcpStream>) -> io::Result<String> {
+	let mut line = vec![];
+	reader.by_ref().take(MAX_LINE as u64).read_until(b'\n', &mut line)?;
+	if !line.ends_with(b"\n") {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "the headers ended early"));
+	}
+	Ok(String::from_utf8_lossy(&line).trim_end().to_owned())
+}
+
+
+
+/// read a metadata block, which starts with its length in 16 bytes,
+/// and take the title out of it
+fn read_title (reader: &mut impl Read) -> io::Result<Option<String>> {
+	let mut len = [0];
+	reader.read_exact(&mut len)?;
+	let mut meta = vec![0; len[0] as usize * 16];
+	reader.read_exact(&mut meta)?;
+
+	// like `StreamTitle='Artist - Song';StreamUrl='';`
+	let meta = String::from_utf8_lossy(&meta);
+	let title = meta
+		.split_once("StreamTitle='")
+		.map(|(_, rest)| rest.split_once("';").map_or(rest, |x| x.0))
+		.map(|x| x.trim_end_matches('\0').to_owned());
+	Ok(title)
+}
+
+
+
+/// the bytes of audio of a stream, without its metadata, opened
+/// again when it drops
+struct HttpReader {
+	url: Url,
+	response: Option<Response>,
+	/// the bytes of audio read since the start of the file
+	position: u64,
+	/// the bytes of audio until the next metadata block
+	until_meta: usize,
+	/// of the first response, the next ones can't be trusted since a
+	/// range can fail
+	length: Option<u64>,
+	live: bool,
+	status: Arc<Status>
+}
+
+impl HttpReader {
+
+
+	/// open the connection again from `position`, waiting longer
+	/// after every failure
+	fn reconnect (&mut self) -> io::Result<()> {
+		self.response = None;
+		// a new response starts with audio, and none may open
+		self.until_meta = usize::MAX;
+		let _ = self.status.events.push(HttpEvent::Reconnecting);
+		let mut delay = RECONNECT_DELAY;
+		let mut result = Err(io::Error::other("no reconnection was tried"));
+		for _ in 0..MAX_RECONNECTS {
+			thread::sleep(delay);
+			delay *= 2;
+			result = self.open();
+			match &result {
+				Ok(()) => break,
+				Err(err) => debug!("reconnecting to the http stream failed: {}", err)
+			}
+		}
+		let _ = self.status.events.push(if result.is_ok() { HttpEvent::Reconnected } else { HttpEvent::Failed });
+		result
+	}
+
+
+	/// open the connection at `position`
+	fn open (&mut self) -> io::Result<()> {
+		// a radio can't go back, it goes on from its live edge
+		let offset = if self.live { 0 } else { self.position };
+		let mut response = request(&self.url, offset)?;
+		if !response.partial && offset > 0 {
+			// the server ignored the range
+			io::copy(&mut response.body.by_ref().take(offset), &mut io::sink())?;
+		}
+		self.until_meta = response.metaint.unwrap_or(usize::MAX);
+		self.response = Some(response);
+		Ok(())
+	}
+
+
+	/// read the metadata block at the current position
+	fn read_meta (&mut self) -> io::Result<()> {
+		let response = self.response.as_mut().ok_or(io::ErrorKind::NotConnected)?;
+		let title = read_title(&mut response.body)?;
+		self.until_meta = response.metaint.unwrap_or(usize::MAX);
+		if let Some(title) = title {
+			let mut current = self.status.title.lock().unwrap_or_else(PoisonError::into_inner);
+			if current.as_ref() != Some(&title) {
+				*current = Some(title.clone());
+				let _ = self.status.events.push(HttpEvent::Title(title));
+			}
+		}
+		Ok(())
+	}
+
+
+	fn read_audio (&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+		if self.until_meta == 0 {
+			self.read_meta()?;
+		}
+		let response = self.response.as_mut().ok_or(io::ErrorKind::NotConnected)?;
+		let len = buffer.len().min(self.until_meta);
+		let len = response.body.read(&mut buffer[..len])?;
+		// a radio never ends, and a download ends at its length
+		if len == 0 && self.length.map_or(self.live, |x| self.position < x) {
+			return Err(io::ErrorKind::UnexpectedEof.into());
+		}
+		if self.until_meta != usize::MAX {
+			self.until_meta -= len;
+		}
+		self.position += len as u64;
+		Ok(len)
+	}
+
+
+}
+
+impl Read for HttpReader {
+
+	fn read (&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+		if buffer.is_empty() {
+			return Ok(0);
+		}
+		match self.read_audio(buffer) {
+			Ok(len) => Ok(len),
+			Err(err) if err.kind() == io::ErrorKind::Interrupted => Err(err),
+			Err(err) => {
+				warn!("the http stream dropped: {}", err);
+				match self.reconnect() {
+					Ok(()) => self.read_audio(buffer),
+					// the sound ends
+					Err(_) => Ok(0)
+				}
+			}
+		}
+	}
+
+}
+
+impl Seek for HttpReader {
+
+	/// only a download of a known length can seek, by opening the
+	/// connection again at the new position
+	fn seek (&mut self, position: SeekFrom) -> io::Result<u64> {
+		let target = match (position, self.length) {
+			(SeekFrom::Current(0), _) => return Ok(self.position),
+			(SeekFrom::Start(x), Some(_)) => x,
+			(SeekFrom::Current(x), Some(_)) => self.position.saturating_add_signed(x),
+			(SeekFrom::End(x), Some(length)) => length.saturating_add_signed(x),
+			_ => return Err(io::Error::new(io::ErrorKind::Unsupported, "a live http stream can't seek"))
+		};
+		if target != self.position {
+			self.position = target;
+			self.open()?;
+		}
+		Ok(self.position)
+	}
+
+}
+
+
+
+/// Http Stream Source
+///
+/// plays a stream from a `http://` url, decoded with symphonia, with
+/// a [`HttpStatus`] to show its buffer, its title and its events
+///
+/// ```ignore
+/// let radio = HttpStreamSource::connect("http://radio.example.com:8000/stream")?;
+/// let status = radio.status();
+/// engine.new_sound(radio, |x| x)?.play();
+/// // every frame
+/// for event in status.events() {
+///     if let HttpEvent::Title(title) = event {
+///         show_title(&title);
+///     }
+/// }
+/// ```
+pub struct HttpStreamSource {
+
+	inner: StreamingDecoder,
+	status: HttpStatus,
+	/// the last state that was sent as an event
+	buffering: bool,
+	low: bool
+
+}
+
+impl HttpStreamSource {
+
+
+	/// connect to `url` and start streaming it, four seconds ahead
+	///
+	/// blocks until the format of the stream is known
+	pub fn connect (url: &str) -> Result<Self, Error> {
+		Self::with_prefetch(url, DEFAULT_PREFETCH)
+	}
+
+
+	/// connect to `url` and start streaming it, `prefetch` ahead
+	pub fn with_prefetch (url: &str, prefetch: Duration) -> Result<Self, Error> {
+		let url = Url::parse(url)?;
+		let response = request(&url, 0)?;
+		let status = Arc::new(Status {
+			level: AtomicU32::new(0),
+			name: response.name.clone(),
+			title: Mutex::new(None),
+			events: Queue::with_capacity(EVENT_CAPACITY)
+		});
+		let mut hint = Hint::new();
+		if let Some(content_type) = &response.content_type {
+			hint.mime_type(content_type.split(';').next().unwrap_or_default().trim());
+		}
+		let reader = HttpReader {
+			url,
+			until_meta: response.metaint.unwrap_or(usize::MAX),
+			length: response.length,
+			live: response.live,
+			response: Some(response),
+			position: 0,
+			status: status.clone()
+		};
+		let decoder = SymphoniaDecoder::probe(reader, hint)?;
+		Ok(Self {
+			inner: StreamingDecoder::with_prefetch(decoder, prefetch),
+			status: HttpStatus(status),
+			// the stream starts by buffering, without an event
+			buffering: true,
+			low: false
+		})
+	}
+
+
+	/// the state of the stream, shared with the source
+	pub fn status (&self) -> HttpStatus {
+		self.status.clone()
+	}
+
+
+}
+
+impl SoundSource for HttpStreamSource {
+
+
+	fn reset (&mut self) {
+		self.inner.reset()
+	}
+
+
+	fn channels (&self) -> u16 {
+		self.inner.channels()
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.inner.sample_rate()
+	}
+
+
+	fn total_frames (&self) -> Option<u64> {
+		self.inner.total_frames()
+	}
+
+
+	/// only for a download of a known length, a radio keeps playing
+	fn seek (&mut self, frame: u64) -> bool {
+		self.inner.seek(frame)
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let len = self.inner.write_samples(buffer);
+
+		let status = &self.status.0;
+		let level = self.inner.buffered();
+		status.level.store(level.to_bits(), Ordering::Relaxed);
+		// the queue doesn't block or allocate, so this is fine on the
+		// audio thread
+		let buffering = self.inner.is_buffering();
+		if buffering != self.buffering {
+			self.buffering = buffering;
+			let _ = status.events.push(if buffering { HttpEvent::Buffering } else { HttpEvent::Buffered });
+		}
+		let low = !buffering && level < LOW_LEVEL;
+		if low && !self.low {
+			let _ = status.events.push(HttpEvent::BufferLow);
+		}
+		self.low = low || (self.low && level < LOW_LEVEL * 2.0);
+		len
+	}
+
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::io::Cursor;
+
+	use super::{ Url, read_title };
+
+
+	/// `meta` as a metadata block, padded to 16 bytes, then audio
+	fn block (meta: &str) -> Cursor<Vec<u8>> {
+		let len = meta.len().div_ceil(16);
+		let mut data = vec![len as u8];
+		data.extend(meta.as_bytes());
+		data.resize(1 + len * 16, 0);
+		data.extend(b"audio");
+		Cursor::new(data)
+	}
+
+
+	#[test]
+	fn parse () {
+		let url = Url::parse("http://radio.example.com:8000/live/stream.mp3").unwrap();
+		assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("radio.example.com", 8000, "/live/stream.mp3"));
+		let url = Url::parse("http://example.com").unwrap();
+		assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("example.com", 80, "/"));
+		let url = Url::parse("http://example.com/a?b=c:d").unwrap();
+		assert_eq!((url.port, url.path.as_str()), (80, "/a?b=c:d"));
+	}
+
+
+	#[test]
+	fn parse_errors () {
+		assert!(Url::parse("https://example.com/").is_err());
+		assert!(Url::parse("ftp://example.com/").is_err());
+		assert!(Url::parse("example.com/").is_err());
+		assert!(Url::parse("http:///stream").is_err());
+		assert!(Url::parse("http://:80/stream").is_err());
+		assert!(Url::parse("http://example.com:port/").is_err());
+		assert!(Url::parse("http://example.com:70000/").is_err());
+	}
+
+
+	#[test]
+	fn join () {
+		let url = Url::parse("http://example.com:8000/music/a.ogg").unwrap();
+		let joined = url.join("/b.ogg").unwrap();
+		assert_eq!((joined.host.as_str(), joined.port, joined.path.as_str()), ("example.com", 8000, "/b.ogg"));
+		assert_eq!(url.join("c.ogg").unwrap().path, "/music/c.ogg");
+		let joined = url.join("http://mirror.example.com/d.ogg").unwrap();
+		assert_eq!((joined.host.as_str(), joined.port, joined.path.as_str()), ("mirror.example.com", 80, "/d.ogg"));
+		assert!(url.join("https://mirror.example.com/d.ogg").is_err());
+	}
+
+
+	#[test]
+	fn title () {
+		let mut reader = block("StreamTitle='Artist - Song';StreamUrl='';");
+		assert_eq!(read_title(&mut reader).unwrap().as_deref(), Some("Artist - Song"));
+		// the audio after the block is left to read
+		assert_eq!(&reader.get_ref()[reader.position() as usize..], b"audio");
+		// without the end of the field, up to the padding
+		let mut reader = block("StreamTitle='Song");
+		assert_eq!(read_title(&mut reader).unwrap().as_deref(), Some("Song"));
+	}
+
+
+	#[test]
+	fn empty_and_short_blocks () {
+		let mut reader = Cursor::new(vec![0, b'a']);
+		assert_eq!(read_title(&mut reader).unwrap(), None);
+		assert_eq!(reader.position(), 1);
+		let mut reader = block("StreamUrl='http://example.com';");
+		assert_eq!(read_title(&mut reader).unwrap(), None);
+		// a block cut short by the connection
+		assert!(read_title(&mut Cursor::new(vec![2, 0, 0])).is_err());
+		assert!(read_title(&mut Cursor::new(vec![])).is_err());
+	}
+
+
+}
@@ -0,0 +1,321 @@
+
+
+
+//! Audio captured from the microphone, read outside of the audio thread.
+//!
+//! The backend writes what the device records to a lock-free ring, at the rate and channels
+//! of the device, and [`AudioInput::read`] converts it to the ones that were asked for. Like a
+//! [`Tap`](crate::Tap), a reader that falls behind loses whole buffers.
+//!
+//! Echo cancellation, noise suppression and gain control are asked of Android, which records
+//! with its voice communication preset. Elsewhere the engine does them itself, roughly.
+
+
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::meter::Meter;
+use crate::tap::{ self, Tap, TapWriter };
+use crate::voice::VoiceProcessor;
+
+
+
+/// how many input frames are read from the ring at a time
+const CHUNK_FRAMES: usize = 256;
+
+
+
+/// how to capture, see [`AudioEngine::open_input`](crate::AudioEngine::open_input)
+///
+/// on android, the app needs the `RECORD_AUDIO` permission, granted
+/// before the input is opened
+///
+/// asking for any of the voice processing records with the voice
+/// communication preset on android 9.0 and later with AAudio, and
+/// on every version with OpenSL ES. the device then does all three,
+/// as well as it can. without it, the engine processes the samples
+/// as they are read: the echo is only suppressed, the mic being
+/// turned down while the output is louder than it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputConfig {
+
+	/// the channels read, whatever the device records with
+	pub channels: u16,
+	/// the sample rate read, whatever the device records at
+	pub sample_rate: u32,
+	/// how much audio is kept until it is read
+	pub buffer: Duration,
+	/// remove the output of the speaker that the mic picks up
+	pub echo_cancellation: bool,
+	/// turn down the background noise between words
+	pub noise_suppression: bool,
+	/// keep the voice at the same level
+	pub gain_control: bool
+
+}
+
+impl InputConfig {
+
+	/// any voice processing was asked for
+	pub (crate) fn processed (&self) -> bool {
+		self.echo_cancellation || self.noise_suppression || self.gain_control
+	}
+
+}
+
+impl Default for InputConfig {
+	/// mono at 48kHz, like voice chat, with a second of buffer and
+	/// no processing
+	fn default () -> Self {
+		Self {
+			channels: 1,
+			sample_rate: 48000,
+			buffer: Duration::from_secs(1),
+			echo_cancellation: false,
+			noise_suppression: false,
+			gain_control: false
+		}
+	}
+}
+
+
+
+/// the ring a backend writes the captured samples to, for a device
+/// recording `channels` at `sample_rate`
+#[cfg_attr(not(any(feature = "cpal", all(target_os = "android", any(feature = "aaudio", feature = "opensles")))), allow(dead_code))]
+pub (crate) fn ring (buffer: Duration, channels: u16, sample_rate: u32) -> (TapWriter, Tap) {
+	let samples = (buffer.as_secs_f64() * sample_rate as f64) as usize * channels as usize;
+	tap::tap(samples, channels, sample_rate)
+}
+
+
+
+/// the audio captured from an input device, see
+/// [`AudioEngine::open_input`](crate::AudioEngine::open_input)
+///
+/// read it regularly, the backend drops whole buffers once it is
+/// full. the device stops recording once this is dropped
+pub struct AudioInput {
+
+	/// what the device recorded
+	ring: Tap,
+	channels: u16,
+	sample_rate: u32,
+	/// input frames consumed per output frame
+	step: f64,
+	/// the two input frames the output is between, in the output
+	/// channels, and where between them
+	current: Vec<f32>,
+	next: Vec<f32>,
+	t: f64,
+	/// input read ahead from the ring
+	input: Vec<i16>,
+	input_pos: usize,
+	input_len: usize,
+	/// `current` and `next` hold frames of the device
+	primed: bool,
+	/// done by the engine, when the device doesn't
+	processor: Option<VoiceProcessor>,
+	/// only kept to be dropped with the input
+	_backend: Box<dyn Send>
+
+}
+
+impl AudioInput {
+
+
+	fn new (ring: Tap, config: InputConfig, processor: Option<VoiceProcessor>, backend: Box<dyn Send>) -> Self {
+		let channels = config.channels.max(1);
+		let sample_rate = config.sample_rate.max(1);
+		Self {
+			step: ring.sample_rate() as f64 / sample_rate as f64,
+			input: vec![0; CHUNK_FRAMES * ring.channels() as usize],
+			ring,
+			channels,
+			sample_rate,
+			current: vec![0.0; channels as usize],
+			next: vec![0.0; channels as usize],
+			t: 0.0,
+			input_pos: 0,
+			input_len: 0,
+			primed: false,
+			processor,
+			_backend: backend
+		}
+	}
+
+
+	pub fn channels (&self) -> u16 {
+		self.channels
+	}
+
+
+	pub fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	/// the sample rate the device records at, the samples are
+	/// converted from it
+	pub fn device_sample_rate (&self) -> u32 {
+		self.ring.sample_rate()
+	}
+
+
+	/// how many frames the device recorded that were lost because the
+	/// reader was too slow
+	pub fn dropped (&self) -> u64 {
+		self.ring.dropped()
+	}
+
+
+	/// about how many frames can be read now, in the sample rate that
+	/// was asked for
+	pub (crate) fn available (&self) -> usize {
+		let device = self.ring.available() / self.ring.channels() as usize + (self.input_len - self.input_pos);
+		(device as f64 / self.step) as usize
+	}
+
+
+	/// move the oldest captured samples to `buffer`, interleaved,
+	/// returns how many
+	///
+	/// only whole frames are written, and nothing when the device
+	/// didn't record more since the last call
+	pub fn read (&mut self, buffer: &mut [i16]) -> usize {
+		let len = self.convert(buffer);
+		if let Some(processor) = self.processor.as_mut() {
+			processor.process(&mut buffer[..len]);
+		}
+		len
+	}
+
+
+	/// `read` before the processing
+	fn convert (&mut self, buffer: &mut [i16]) -> usize {
+		if !self.primed {
+			if !self.pull() || !self.pull() {
+				return 0;
+			}
+			self.primed = true;
+		}
+
+		let mut len = 0;
+		for frame in buffer.chunks_exact_mut(self.channels as usize) {
+			while self.t >= 1.0 {
+				if !self.pull() {
+					return len;
+				}
+				self.t -= 1.0;
+			}
+			let t = self.t as f32;
+			for ((x, a), b) in frame.iter_mut().zip(self.current.iter()).zip(self.next.iter()) {
+				*x = (a * (1.0 - t) + b * t).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+			}
+			len += self.channels as usize;
+			self.t += self.step;
+		}
+		len
+	}
+
+
+	/// move `next` to `current`, and read the next frame of the device
+	/// into `next` in the output channels. false if there is none yet
+	fn pull (&mut self) -> bool {
+		let device = self.ring.channels() as usize;
+		if self.input_pos == self.input_len {
+			self.input_len = self.ring.read(&mut self.input) / device;
+			self.input_pos = 0;
+			if self.input_len == 0 {
+				return false;
+			}
+		}
+		std::mem::swap(&mut self.current, &mut self.next);
+
+		let frame = &self.input[self.input_pos * device..(self.input_pos + 1) * device];
+		self.input_pos += 1;
+		if device == self.channels as usize {
+			for (x, &y) in self.next.iter_mut().zip(frame) {
+				*x = y as f32;
+			}
+		} else if self.channels == 1 {
+			self.next[0] = frame.iter().map(|&x| x as f32).sum::<f32>() / device as f32;
+		} else {
+			// a mono device is copied to every channel, otherwise the
+			// channels wrap around the ones the device has
+			for (i, x) in self.next.iter_mut().enumerate() {
+				*x = frame[i % device] as f32;
+			}
+		}
+		true
+	}
+
+
+}
+
+
+
+/// open the default input device with the first backend that works,
+/// in the same order as the output
+///
+/// `meter` measures the mix, for the echo suppression of the engine
+pub (crate) fn start (config: InputConfig, meter: Arc<Meter>) -> Result<AudioInput, &'static str> {
+	let (backend, ring, processed) = start_aaudio(config)
+		.or_else(|err| {
+			log::debug!("{}, trying opensl es input", err);
+			start_opensles(config)
+		})
+		.or_else(|err| {
+			log::debug!("{}, trying cpal input", err);
+			start_cpal(config)
+		})?;
+
+	let processor = (config.processed() && !processed).then(|| {
+		log::info!("the input device doesn't process the voice, the engine does");
+		let echo = config.echo_cancellation.then_some(meter);
+		VoiceProcessor::new(config.noise_suppression, config.gain_control, echo, config.channels, config.sample_rate)
+	});
+	Ok(AudioInput::new(ring, config, processor, backend))
+}
+
+
+
+#[cfg(all(target_os = "android", feature = "aaudio"))]
+fn start_aaudio (config: InputConfig) -> Result<(Box<dyn Send>, Tap, bool), &'static str> {
+	let (backend, ring, processed) = crate::aaudio::InputBackend::start(config)?;
+	Ok((Box::new(backend), ring, processed))
+}
+
+
+#[cfg(not(all(target_os = "android", feature = "aaudio")))]
+fn start_aaudio (_: InputConfig) -> Result<(Box<dyn Send>, Tap, bool), &'static str> {
+	Err("the aaudio backend is not enabled")
+}
+
+
+#[cfg(all(target_os = "android", feature = "opensles"))]
+fn start_opensles (config: InputConfig) -> Result<(Box<dyn Send>, Tap, bool), &'static str> {
+	let (backend, ring, processed) = crate::opensles::InputBackend::start(config)?;
+	Ok((Box::new(backend), ring, processed))
+}
+
+
+#[cfg(not(all(target_os = "android", feature = "opensles")))]
+fn start_opensles (_: InputConfig) -> Result<(Box<dyn Send>, Tap, bool), &'static str> {
+	Err("the opensl es backend is not enabled")
+}
+
+
+#[cfg(feature = "cpal")]
+fn start_cpal (config: InputConfig) -> Result<(Box<dyn Send>, Tap, bool), &'static str> {
+	// cpal has no voice processing
+	let (backend, ring) = crate::cpal_backend::InputBackend::start(config)?;
+	Ok((Box::new(backend), ring, false))
+}
+
+
+#[cfg(not(feature = "cpal"))]
+fn start_cpal (_: InputConfig) -> Result<(Box<dyn Send>, Tap, bool), &'static str> {
+	Err("the cpal backend is not enabled")
+}
@@ -0,0 +1,179 @@
+
+
+
+//! Speaker layouts of sources and outputs, and how one is played on another.
+//!
+//! A source with another layout than the output is mixed down or up to it: 5.1 on stereo folds
+//! the center and the surrounds into the front speakers, and stereo on 5.1 plays on the front
+//! speakers only. A mono source plays on the front left and right like on stereo, so it can still
+//! be panned. The LFE is never made from other channels, and is dropped when the output has none.
+
+
+
+use crate::channel_map::ChannelMap;
+use crate::converter::ChannelConverter;
+use crate::error::Error;
+use crate::mixer::SoundSource;
+
+
+
+/// the gain of a speaker folded into two others, -3 dB
+const FOLD: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// the bits of the speakers in a wave channel mask, also used by
+/// opensl es
+const FRONT_LEFT: u32 = 0x1;
+const FRONT_RIGHT: u32 = 0x2;
+const FRONT_CENTER: u32 = 0x4;
+const LOW_FREQUENCY: u32 = 0x8;
+const BACK_LEFT: u32 = 0x10;
+const BACK_RIGHT: u32 = 0x20;
+const SIDE_LEFT: u32 = 0x200;
+const SIDE_RIGHT: u32 = 0x400;
+
+
+
+/// the speakers of a source or of the output, in the order of the
+/// wave format and of android
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+	Mono,
+	/// left, right
+	Stereo,
+	/// front left and right, back left and right
+	Quad,
+	/// front left, right and center, lfe, back left and right
+	Surround51,
+	/// the same as 5.1, then side left and right
+	Surround71
+}
+
+impl ChannelLayout {
+
+
+	/// the usual layout of `channels` channels, `None` for counts
+	/// without one
+	pub fn from_channels (channels: u16) -> Option<Self> {
+		match channels {
+			1 => Some(Self::Mono),
+			2 => Some(Self::Stereo),
+			4 => Some(Self::Quad),
+			6 => Some(Self::Surround51),
+			8 => Some(Self::Surround71),
+			_ => None
+		}
+	}
+
+
+	pub fn channels (self) -> u16 {
+		self.speakers().len() as u16
+	}
+
+
+	/// the speakers as a wave channel mask, like `0x3F` for 5.1
+	pub fn mask (self) -> u32 {
+		self.speakers().iter().fold(0, |mask, x| mask | x)
+	}
+
+
+	fn speakers (self) -> &'static [u32] {
+		match self {
+			Self::Mono => &[FRONT_CENTER],
+			Self::Stereo => &[FRONT_LEFT, FRONT_RIGHT],
+			Self::Quad => &[FRONT_LEFT, FRONT_RIGHT, BACK_LEFT, BACK_RIGHT],
+			Self::Surround51 => &[FRONT_LEFT, FRONT_RIGHT, FRONT_CENTER, LOW_FREQUENCY, BACK_LEFT, BACK_RIGHT],
+			Self::Surround71 => &[FRONT_LEFT, FRONT_RIGHT, FRONT_CENTER, LOW_FREQUENCY, BACK_LEFT, BACK_RIGHT, SIDE_LEFT, SIDE_RIGHT]
+		}
+	}
+
+
+	/// how each channel of `self` plays on `to`, as the routes of a
+	/// [`ChannelMap`]
+	///
+	/// the downmixes are the ones of ITU-R BS.775, without lowering
+	/// the front, so a loud 5.1 source can clip on stereo
+	pub fn matrix (self, to: ChannelLayout) -> Vec<(u16, u16, f32)> {
+		let mut routes = vec![];
+		for (from, &speaker) in self.speakers().iter().enumerate() {
+			for (channel, gain) in to.gains(speaker, self == Self::Mono) {
+				routes.push((from as u16, channel, gain));
+			}
+		}
+		routes
+	}
+
+
+	/// the channels of `self` a `speaker` plays on, and their gains
+	fn gains (self, speaker: u32, mono: bool) -> Vec<(u16, f32)> {
+		let channel = |speaker| self.speakers().iter().position(|&x| x == speaker).map(|x| x as u16);
+
+		if self == Self::Mono {
+			// the average of the left and right of the stereo downmix
+			return match speaker {
+				LOW_FREQUENCY => vec![],
+				FRONT_CENTER => vec![(0, FOLD)],
+				FRONT_LEFT | FRONT_RIGHT => vec![(0, 0.5)],
+				_ => vec![(0, FOLD * 0.5)]
+			};
+		}
+		// a mono source is heard on both front speakers, like on stereo
+		if mono {
+			return vec![(0, 1.0), (1, 1.0)];
+		}
+		if let Some(channel) = channel(speaker) {
+			return vec![(channel, 1.0)];
+		}
+		// a missing surround goes to the other surround of its side, or
+		// else to the front of its side
+		let fallback = match speaker {
+			BACK_LEFT => channel(SIDE_LEFT),
+			BACK_RIGHT => channel(SIDE_RIGHT),
+			SIDE_LEFT => channel(BACK_LEFT),
+			SIDE_RIGHT => channel(BACK_RIGHT),
+			_ => None
+		};
+		match (fallback, speaker) {
+			(Some(channel), _) => vec![(channel, 1.0)],
+			(None, FRONT_CENTER) => vec![(0, FOLD), (1, FOLD)],
+			(None, BACK_LEFT | SIDE_LEFT) => vec![(0, FOLD)],
+			(None, BACK_RIGHT | SIDE_RIGHT) => vec![(1, FOLD)],
+			_ => vec![]
+		}
+	}
+
+
+}
+
+
+
+/// `source` played on `channels` channels, mixed down or up when both
+/// have a layout
+///
+/// fails with [`Error::Channels`] when one of them has no layout and
+/// neither is mono
+pub (crate) fn convert (source: Box<dyn SoundSource + Send>, channels: u16) -> Result<Box<dyn SoundSource + Send>, Error> {
+	let layouts = ChannelLayout::from_channels(source.channels()).zip(ChannelLayout::from_channels(channels));
+	if source.channels() != channels && layouts.is_none() && source.channels() != 1 && channels != 1 {
+		return Err(Error::Channels);
+	}
+	Ok(remix(source, channels))
+}
+
+
+
+/// the same as [`convert`], but a source that can't be converted is
+/// played silent
+pub (crate) fn remix (source: Box<dyn SoundSource + Send>, channels: u16) -> Box<dyn SoundSource + Send> {
+	if source.channels() == channels {
+		return source;
+	}
+	match (ChannelLayout::from_channels(source.channels()), ChannelLayout::from_channels(channels)) {
+		(Some(from), Some(to)) => Box::new(
+			from.matrix(to)
+				.into_iter()
+				.fold(ChannelMap::new(source, channels), |map, (from, to, gain)| map.route(from, to, gain))
+		),
+		_ if source.channels() == 1 || channels == 1 => Box::new(ChannelConverter::new(source, channels)),
+		_ => Box::new(ChannelMap::new(source, channels))
+	}
+}
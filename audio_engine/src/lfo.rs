@@ -0,0 +1,308 @@
+
+
+
+//! Slow oscillators: tremolo and vibrato effects, and an [`Lfo`] that moves the volume, pan
+//! or pitch of a sound.
+
+
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU32, Ordering };
+
+use crate::effect::Effect;
+
+
+
+/// the delay line of a [`Vibrato`], which bounds its depth at slow
+/// rates
+const MAX_DELAY: f32 = 0.05;
+
+
+
+/// the parameters shared with the controls, as the bits of `f32`s
+struct Params {
+	rate: AtomicU32,
+	depth: AtomicU32
+}
+
+impl Params {
+
+	fn new (rate: f32, depth: f32) -> Arc<Self> {
+		Arc::new(Self {
+			rate: AtomicU32::new(rate.to_bits()),
+			depth: AtomicU32::new(depth.to_bits())
+		})
+	}
+
+	fn rate (&self) -> f32 {
+		f32::from_bits(self.rate.load(Ordering::Relaxed))
+	}
+
+	fn depth (&self) -> f32 {
+		f32::from_bits(self.depth.load(Ordering::Relaxed))
+	}
+
+}
+
+
+
+/// moves the volume up and down, as an [`Effect`]
+pub struct Tremolo {
+	params: Arc<Params>,
+	/// of the oscillator, from `0.0` to `1.0`
+	phase: f32
+}
+
+impl Tremolo {
+
+
+	/// `rate` in Hz, a `depth` of `1.0` goes down to silence and `0.0`
+	/// does nothing
+	pub fn new (rate: f32, depth: f32) -> Self {
+		Self { params: Params::new(rate, depth), phase: 0.0 }
+	}
+
+
+	/// change the rate and depth after it was given to a sound or group
+	pub fn controls (&self) -> LfoControls {
+		LfoControls { params: self.params.clone() }
+	}
+
+
+}
+
+impl Effect for Tremolo {
+
+	fn process (&mut self, frames: &mut [f32], channels: u16, sample_rate: u32) {
+		let rate = self.params.rate() / sample_rate as f32;
+		let depth = self.params.depth().clamp(0.0, 1.0);
+		for frame in frames.chunks_exact_mut(channels as usize) {
+			// full volume at the start of each turn
+			let gain = 1.0 - depth * (0.5 - 0.5 * (2.0 * PI * self.phase).cos());
+			for x in frame.iter_mut() {
+				*x *= gain;
+			}
+			self.phase = (self.phase + rate).fract();
+		}
+	}
+
+}
+
+
+
+/// moves the pitch up and down, as an [`Effect`]
+///
+/// done with a delay line that is swept back and forth, so the sound
+/// comes a few milliseconds late
+pub struct Vibrato {
+
+	params: Arc<Params>,
+	/// interleaved, made on the first call for `MAX_DELAY`
+	line: Vec<f32>,
+	/// the frame of `line` written next
+	position: usize,
+	/// of the oscillator, from `0.0` to `1.0`
+	phase: f32,
+	channels: usize,
+	sample_rate: u32
+
+}
+
+impl Vibrato {
+
+
+	/// `rate` in Hz, `depth` in semitones either way
+	pub fn new (rate: f32, depth: f32) -> Self {
+		Self {
+			params: Params::new(rate, depth),
+			line: vec![],
+			position: 0,
+			phase: 0.0,
+			channels: 0,
+			sample_rate: 0
+		}
+	}
+
+
+	/// change the rate and depth after it was given to a sound or group
+	pub fn controls (&self) -> LfoControls {
+		LfoControls { params: self.params.clone() }
+	}
+
+
+}
+
+impl Effect for Vibrato {
+
+	fn process (&mut self, frames: &mut [f32], channels: u16, sample_rate: u32) {
+		let channels = channels as usize;
+		if self.channels != channels || self.sample_rate != sample_rate {
+			let length = (MAX_DELAY * sample_rate as f32) as usize + 2;
+			self.line = vec![0.0; length * channels];
+			self.position = 0;
+			self.channels = channels;
+			self.sample_rate = sample_rate;
+		}
+
+		let length = self.line.len() / channels;
+		let rate = self.params.rate().max(0.01);
+		// the pitch follows the slope of the delay, so a sine sweep of
+		// `sweep` seconds moves it by up to 2π * rate * sweep
+		let ratio = 2f32.powf(self.params.depth().abs() / 12.0) - 1.0;
+		let sweep = (ratio / (2.0 * PI * rate) * sample_rate as f32).min((length - 3) as f32 / 2.0);
+		let step = rate / sample_rate as f32;
+		for frame in frames.chunks_exact_mut(channels) {
+			let delay = 1.0 + sweep * (1.0 + (2.0 * PI * self.phase).sin());
+
+			// linear interpolation between the two nearest frames
+			let whole = delay as usize;
+			let fraction = delay - whole as f32;
+			let a = (self.position + length - whole) % length;
+			let b = (self.position + length - whole - 1) % length;
+			for (c, x) in frame.iter_mut().enumerate() {
+				self.line[self.position * channels + c] = *x;
+				let a = self.line[a * channels + c];
+				let b = self.line[b * channels + c];
+				*x = a + (b - a) * fraction;
+			}
+			self.position = (self.position + 1) % length;
+			self.phase = (self.phase + step).fract();
+		}
+	}
+
+}
+
+
+
+/// the shape of an [`Lfo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+	Sine,
+	Triangle,
+	Square,
+	/// a new random value every cycle, held until the next one
+	Random
+}
+
+
+
+/// what an [`Lfo`] moves, see [`Sound::set_lfo`](crate::Sound::set_lfo)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoTarget {
+	/// the depth is how far down the volume goes, from `0.0` to `1.0`
+	Volume,
+	/// the depth is how far the pan goes either way
+	Pan,
+	/// the depth is in semitones either way
+	Pitch
+}
+
+
+
+/// an oscillator that moves a parameter of a sound
+///
+/// runs on the audio thread, frame by frame for the volume and the
+/// pan, and a few frames at a time for the pitch
+pub struct Lfo {
+
+	waveform: Waveform,
+	params: Arc<Params>,
+	/// from `0.0` to `1.0`
+	phase: f32,
+	/// the held value of `Waveform::Random`
+	random: f32,
+	rng: u32
+
+}
+
+impl Lfo {
+
+
+	/// `rate` in Hz, see [`LfoTarget`] for the `depth`
+	pub fn new (waveform: Waveform, rate: f32, depth: f32) -> Self {
+		Self {
+			waveform,
+			params: Params::new(rate, depth),
+			phase: 0.0,
+			random: 0.0,
+			rng: 0x2545_F491
+		}
+	}
+
+
+	/// change the rate and depth after it was given to a sound
+	pub fn controls (&self) -> LfoControls {
+		LfoControls { params: self.params.clone() }
+	}
+
+
+	/// the current value of the waveform, between `-1.0` and `1.0`
+	pub (crate) fn value (&self) -> f32 {
+		match self.waveform {
+			Waveform::Sine => (2.0 * PI * self.phase).sin(),
+			// starts at 0 going up, like the sine
+			Waveform::Triangle => 4.0 * ((self.phase + 0.75).fract() - 0.5).abs() - 1.0,
+			Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+			Waveform::Random => self.random
+		}
+	}
+
+
+	pub (crate) fn depth (&self) -> f32 {
+		self.params.depth()
+	}
+
+
+	/// move `frames` frames forward
+	pub (crate) fn advance (&mut self, frames: u32, sample_rate: u32) {
+		let phase = self.phase + self.params.rate() * frames as f32 / sample_rate.max(1) as f32;
+		if phase >= 1.0 {
+			self.rng ^= self.rng << 13;
+			self.rng ^= self.rng >> 17;
+			self.rng ^= self.rng << 5;
+			self.random = self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0;
+		}
+		self.phase = phase.fract();
+	}
+
+
+}
+
+
+
+/// changes a [`Tremolo`], a [`Vibrato`] or an [`Lfo`] from any thread
+///
+/// the changes apply from the next buffer that is mixed
+#[derive(Clone)]
+pub struct LfoControls {
+	params: Arc<Params>
+}
+
+impl LfoControls {
+
+
+	/// in Hz
+	pub fn set_rate (&self, rate: f32) {
+		self.params.rate.store(rate.to_bits(), Ordering::Relaxed);
+	}
+
+
+	/// from `0.0` to `1.0` for a tremolo, in semitones for a vibrato,
+	/// and depending on the target for an [`Lfo`]
+	pub fn set_depth (&self, depth: f32) {
+		self.params.depth.store(depth.to_bits(), Ordering::Relaxed);
+	}
+
+
+	pub fn rate (&self) -> f32 {
+		self.params.rate()
+	}
+
+
+	pub fn depth (&self) -> f32 {
+		self.params.depth()
+	}
+
+
+}
@@ -4,13 +4,219 @@
 mod wav;
 pub use wav::WavDecoder;
 
+#[cfg(feature = "ogg")]
+mod ogg;
+#[cfg(feature = "ogg")]
+pub use ogg::OggDecoder;
+
+#[cfg(feature = "flac")]
+mod flac;
+#[cfg(feature = "flac")]
+pub use flac::FlacDecoder;
+
+#[cfg(feature = "opus")]
+mod opus;
+#[cfg(feature = "opus")]
+pub use opus::{ OpusDecoder, OpusPacketDecoder };
+
+#[cfg(feature = "tracker")]
+mod tracker;
+#[cfg(feature = "tracker")]
+pub use tracker::{ ModControls, ModDecoder };
+
+#[cfg(feature = "symphonia")]
+mod universal;
+#[cfg(feature = "symphonia")]
+pub use universal::SymphoniaDecoder;
+
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "http")]
+pub use http::{ HttpEvent, HttpStatus, HttpStreamSource };
+
+#[cfg(all(target_os = "android", feature = "android-assets"))]
+mod asset;
+#[cfg(all(target_os = "android", feature = "android-assets"))]
+pub use asset::{ Asset, set_asset_manager };
+
+mod aiff;
+pub use aiff::AiffDecoder;
+
+mod ambisonics;
+
+mod bank;
+
+mod beeper;
+pub use beeper::{ Beeper, Dtmf };
+
+mod biquad;
+pub use biquad::{ Biquad, BiquadControls, FilterKind };
+
+mod blend;
+pub use blend::{ BlendControls, BlendSound };
+
+mod chain;
+pub use chain::ChainSource;
+
+mod channel_map;
+pub use channel_map::ChannelMap;
+
+mod combinator;
+pub use combinator::{ Amplify, Delayed, Mix, RepeatN, Skip, SoundSourceExt, TakeDuration };
+
+mod compressor;
+pub use compressor::{ Compressor, CompressorConfig };
+
+mod decibel;
+pub use decibel::{ MAX_DB, MIN_DB, db_to_gain, gain_to_db };
+
+mod delay;
+pub use delay::{ Delay, DelayConfig, DelayControls, DelayTime };
+
+mod distortion;
+pub use distortion::{ Bitcrusher, BitcrusherControls, Overdrive, Waveshaper };
+
+mod easing;
+pub use easing::Easing;
+
+mod effect;
+pub use effect::{ Effect, EffectId };
+
 mod engine;
-pub use engine::AudioEngine;
+pub use engine::{ AudioBackend, AudioEngine, AudioEngineBuilder, PerformanceMode, SharingMode };
+
+#[cfg(all(target_os = "android", feature = "aaudio"))]
+mod aaudio;
+#[cfg(all(target_os = "android", feature = "opensles"))]
+mod opensles;
+#[cfg(feature = "cpal")]
+mod cpal_backend;
+
+mod envelope;
+pub use envelope::Envelope;
+
+mod error;
+pub use error::Error;
+
+mod equalizer;
+pub use equalizer::{ EqBand, Equalizer, MAX_BANDS };
+
+mod granular;
+pub use granular::{ Granular, GranularControls };
+
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+
+mod hrtf;
+pub use hrtf::{ Hrtf, HrtfResponse };
+
+mod input;
+pub use input::{ AudioInput, InputConfig };
+
+mod lfo;
+pub use lfo::{ Lfo, LfoControls, LfoTarget, Tremolo, Vibrato, Waveform };
+
+mod layout;
+pub use layout::ChannelLayout;
+
+mod limiter;
+pub use limiter::LimiterConfig;
+
+mod metadata;
+pub use metadata::{ AudioMetadata, CoverArt };
+
+mod meter;
+pub use meter::Levels;
+
+mod midi;
+pub use midi::{ MidiFile, Sequencer, SequencerControls };
+
+mod modulation;
+pub use modulation::{ Modulation, ModulationConfig, ModulationControls };
+
+mod monitor;
+pub use monitor::{ Monitor, MonitorControls };
+
+mod music;
+pub use music::{ Music, MusicControls, MusicTrack, Transition };
+
+mod occlusion;
+
+mod oscillator;
+pub use oscillator::{ Constant, FnSource, PinkNoise, SawWave, Silence, SineWave, SquareWave, TriangleWave, WhiteNoise };
+
+mod parameter;
+pub use parameter::ParameterTarget;
+
+mod pcm;
+pub use pcm::{ PcmEncoding, PcmFormat, RawPcmDecoder, RawPcmSource };
+
+mod offline;
+pub use offline::OfflineBackend;
+
+mod playlist;
+pub use playlist::{ MusicQueue, MusicQueueControls, Repeat };
+
+mod random;
+pub use random::RandomSound;
+
+mod reader;
+pub use reader::SeekBufReader;
+#[cfg(feature = "mmap")]
+pub use reader::MappedFile;
+
+mod recorder;
+pub use recorder::Recording;
+
+mod replay;
+
+mod synth;
+pub use synth::{ Synth, SynthControls, SynthSample };
+
+mod tap;
+pub use tap::Tap;
+
+mod transport;
+pub use transport::{ Quantize, Transport };
+
+mod voice;
+pub use voice::{ VoiceDetector, VoiceEvent };
 
 mod converter;
+pub use converter::ResampleQuality;
 
 mod mixer;
+pub use mixer::{ Direction, EngineTime, Finished, Group, GroupId, InstanceLimit, PlaybackState, Sound, SoundEvent, SoundId, SoundSource, StealPolicy, WeakSound };
+
+mod queue;
+
+mod reverb;
+pub use reverb::{ Reverb, ReverbConfig };
+
+mod sequence;
+pub use sequence::SequenceSound;
+
+mod snapshot;
+pub use snapshot::Snapshot;
+
+mod soundfont;
+pub use soundfont::SoundFont;
+
+mod sound_data;
+pub use sound_data::{ SoundData, SoundDataSource };
+
+mod spatial;
+pub use spatial::{ Attenuation, AttenuationModel, Listener };
+
+mod stereo;
+pub use stereo::{ StereoWidth, StereoWidthControls };
+
+mod streaming;
+pub use streaming::StreamingDecoder;
+
+mod stretch;
 
+#[cfg(feature = "cpal")]
 pub use cpal;
 
 
@@ -0,0 +1,109 @@
+
+
+
+//! A look-ahead limiter for the master output.
+//!
+//! The mix is delayed by a millisecond, so the gain can go down before a peak reaches the
+//! output instead of clipping it. After the peak, the gain comes back up over the release time.
+
+
+
+use std::time::Duration;
+
+
+
+/// how far ahead the limiter looks, this is also the added latency
+const LOOKAHEAD: Duration = Duration::from_millis(1);
+
+/// full scale, in the units of the mix
+const FULL_SCALE: f32 = i16::MAX as f32;
+
+
+
+/// settings of the master limiter, see
+/// [`AudioEngine::set_limiter`](crate::AudioEngine::set_limiter)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterConfig {
+
+	/// the level the output is kept under, `1.0` is full scale
+	pub threshold: f32,
+	/// how long the gain takes to come back after a peak
+	pub release: Duration
+
+}
+
+impl Default for LimiterConfig {
+	fn default () -> Self {
+		Self {
+			// -1 dB
+			threshold: 0.89,
+			release: Duration::from_millis(100)
+		}
+	}
+}
+
+
+
+pub struct Limiter {
+
+	threshold: f32,
+	channels: usize,
+	/// the last `lookahead` frames of input, interleaved
+	delay: Vec<f32>,
+	/// the gain each frame of `delay` needs to stay under the threshold
+	targets: Vec<f32>,
+	/// the next frame of `delay` to read and overwrite
+	position: usize,
+	gain: f32,
+	/// per frame smoothing of the gain going down and up
+	attack: f32,
+	release: f32
+
+}
+
+impl Limiter {
+
+
+	pub fn new (config: LimiterConfig, channels: u16, sample_rate: u32) -> Self {
+		let lookahead = (LOOKAHEAD.as_secs_f32() * sample_rate as f32).max(1.0) as usize;
+		let release = config.release.as_secs_f32() * sample_rate as f32;
+		Self {
+			threshold: config.threshold.max(0.0) * FULL_SCALE,
+			channels: channels as usize,
+			delay: vec![0.0; lookahead * channels as usize],
+			targets: vec![1.0; lookahead],
+			position: 0,
+			gain: 1.0,
+			// settles within the lookahead, the rest is clipped
+			attack: 1.0 - (-5.0 / lookahead as f32).exp(),
+			release: if release > 0.0 { 1.0 - (-1.0 / release).exp() } else { 1.0 }
+		}
+	}
+
+
+	/// limit the interleaved `mix` in place, delaying it by the lookahead
+	pub fn process (&mut self, mix: &mut [f32]) {
+		let lookahead = self.targets.len();
+		for frame in mix.chunks_exact_mut(self.channels) {
+			let peak = frame.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+			let outgoing = self.targets[self.position];
+			self.targets[self.position] = if peak > self.threshold { self.threshold / peak } else { 1.0 };
+
+			// the lowest gain of every frame from the one going out to
+			// the one coming in
+			let target = self.targets.iter().fold(outgoing, |a, b| a.min(*b));
+			let smoothing = if target < self.gain { self.attack } else { self.release };
+			self.gain += (target - self.gain) * smoothing;
+
+			let delay = &mut self.delay[self.position * self.channels..][..self.channels];
+			for (x, delayed) in frame.iter_mut().zip(delay.iter_mut()) {
+				let output = (*delayed * self.gain).clamp(-self.threshold, self.threshold);
+				*delayed = *x;
+				*x = output;
+			}
+			self.position = (self.position + 1) % lookahead;
+		}
+	}
+
+
+}
@@ -0,0 +1,836 @@
+
+
+
+//! Tags of music files, read without decoding them.
+//!
+//! ID3v2 tags are read at the start of MP3 files and in the `id3 ` chunks of WAV and AIFF files,
+//! RIFF INFO lists in WAV files, the text chunks of AIFF files, and Vorbis comments in FLAC and
+//! Ogg files. The duration comes from the headers, so it's missing for MP3 files without a
+//! `TLEN` frame.
+
+
+
+use std::io::{ self, Read, Seek, SeekFrom };
+use std::time::Duration;
+
+use crate::aiff::extended_at;
+
+
+
+/// the biggest tag or chunk that is read, like one with a big cover
+const MAX_TAG: usize = 1 << 24;
+
+/// the picture type of a front cover, in id3 and flac
+const FRONT_COVER: u8 = 3;
+
+
+
+fn invalid (message: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+
+/// a picture of a file, like its cover
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverArt {
+	/// like `image/jpeg`, as the file says
+	pub mime_type: String,
+	pub data: Vec<u8>
+}
+
+
+
+/// the tags of a music file
+///
+/// ```ignore
+/// let metadata = AudioMetadata::from_reader(BufReader::new(File::open("song.flac")?))?;
+/// println!("{} by {}", metadata.title.unwrap_or_default(), metadata.artist.unwrap_or_default());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudioMetadata {
+	pub title: Option<String>,
+	pub artist: Option<String>,
+	pub album: Option<String>,
+	pub duration: Option<Duration>,
+	/// the front cover, or else the first picture
+	pub cover_art: Option<CoverArt>,
+	/// every text tag with the key of the file, like `TIT2` in id3,
+	/// `TITLE` in vorbis comments or `INAM` in riff info
+	pub tags: Vec<(String, String)>
+}
+
+impl AudioMetadata {
+
+
+	/// read the tags of a wav, aiff, flac, ogg or mp3 file, from where
+	/// `data` is
+	///
+	/// the file is detected by its first bytes, and a file without
+	/// tags gives an empty [`AudioMetadata`]
+	pub fn from_reader <T: Read + Seek> (mut data: T) -> io::Result<Self> {
+		let mut metadata = Self::default();
+		let mut header = [0; 12];
+		data.read_exact(&mut header)?;
+		match (&header[0..4], &header[8..12]) {
+			(b"RIFF", b"WAVE") => metadata.read_wav(&mut data)?,
+			(b"FORM", b"AIFF" | b"AIFC") => metadata.read_aiff(&mut data)?,
+			(b"fLaC", _) => {
+				data.seek(SeekFrom::Current(-8))?;
+				metadata.read_flac(&mut data)?;
+			},
+			(b"OggS", _) => {
+				data.seek(SeekFrom::Current(-12))?;
+				metadata.read_ogg(&mut data)?;
+			},
+			_ if &header[0..3] == b"ID3" => {
+				data.seek(SeekFrom::Current(-12))?;
+				let mut header = [0; 10];
+				data.read_exact(&mut header)?;
+				let len = syncsafe(&header[6..10]) as usize;
+				// the rest of the file is audio, so it's not read
+				let mut tag = read_vec(&mut data, len)?;
+				metadata.read_id3(&header, &mut tag);
+			},
+			_ => return Err(invalid("not a wav, aiff, flac, ogg or mp3 file"))
+		}
+		Ok(metadata)
+	}
+
+
+	/// add a text tag, also as the title, artist or album when `key`
+	/// is one of them
+	fn add (&mut self, key: &str, value: String) {
+		if value.is_empty() {
+			return;
+		}
+		let field = match key.to_ascii_uppercase().as_str() {
+			"TITLE" | "TIT2" | "TT2" | "INAM" | "NAME" => Some(&mut self.title),
+			"ARTIST" | "TPE1" | "TP1" | "IART" | "AUTH" => Some(&mut self.artist),
+			"ALBUM" | "TALB" | "TAL" | "IPRD" => Some(&mut self.album),
+			_ => None
+		};
+		if let Some(field) = field {
+			field.get_or_insert_with(|| value.clone());
+		}
+		self.tags.push((key.to_owned(), value));
+	}
+
+
+	/// keep `picture` if it's the first one or the front cover
+	fn add_picture (&mut self, kind: u8, picture: CoverArt) {
+		if self.cover_art.is_none() || kind == FRONT_COVER {
+			self.cover_art = Some(picture);
+		}
+	}
+
+
+	/// the chunks of a wav file, after its header
+	fn read_wav <T: Read + Seek> (&mut self, data: &mut T) -> io::Result<()> {
+		let mut byte_rate = 0;
+		let mut sample_rate = 0;
+		let mut data_len = None;
+		let mut frames = None;
+		for_each_chunk(data, false, |id, len, data| {
+			match id {
+				b"data" => data_len = Some(len),
+				b"fmt " | b"fact" | b"LIST" | b"id3 " | b"ID3 " if len <= MAX_TAG as u64 => {
+					let body = read_vec(data, len as usize)?;
+					match id {
+						b"fmt " => {
+							sample_rate = u32_le(&body, 4);
+							byte_rate = u32_le(&body, 8);
+						},
+						b"fact" => frames = Some(u32_le(&body, 0)),
+						b"LIST" if body.starts_with(b"INFO") => self.read_info(&body[4..]),
+						b"LIST" => (),
+						_ => self.read_id3_chunk(body)
+					}
+					return Ok(true);
+				},
+				_ => ()
+			}
+			Ok(false)
+		})?;
+		// the fact chunk has the frames of compressed formats
+		self.duration = match (frames, data_len) {
+			(Some(frames), _) if sample_rate > 0 => Some(Duration::from_secs_f64(frames as f64 / sample_rate as f64)),
+			(_, Some(len)) if byte_rate > 0 => Some(Duration::from_secs_f64(len as f64 / byte_rate as f64)),
+			_ => None
+		};
+		Ok(())
+	}
+
+
+	/// the sub chunks of a riff `INFO` list
+	fn read_info (&mut self, mut body: &[u8]) {
+		while body.len() >= 8 {
+			let len = u32_le(body, 4) as usize;
+			let Some(value) = len.checked_add(8).and_then(|end| body.get(8..end)) else {
+				break;
+			};
+			let key = String::from_utf8_lossy(&body[0..4]).into_owned();
+			self.add(&key, String::from_utf8_lossy(value).trim_end_matches('\0').to_owned());
+			body = body.get(8 + len + len % 2..).unwrap_or_default();
+		}
+	}
+
+
+	/// the chunks of an aiff file, after its header
+	fn read_aiff <T: Read + Seek> (&mut self, data: &mut T) -> io::Result<()> {
+		for_each_chunk(data, true, |id, len, data| {
+			if !matches!(id, b"COMM" | b"NAME" | b"AUTH" | b"(c) " | b"ANNO" | b"ID3 ") || len > MAX_TAG as u64 {
+				return Ok(false);
+			}
+			let body = read_vec(data, len as usize)?;
+			match id {
+				b"COMM" => {
+					let frames = u32::from_be_bytes(body.get(2..6).and_then(|x| x.try_into().ok()).unwrap_or_default());
+					let rate = extended_at(&body, 8);
+					if rate > 0 {
+						self.duration = Some(Duration::from_secs_f64(frames as f64 / rate as f64));
+					}
+				},
+				b"ID3 " => self.read_id3_chunk(body),
+				_ => self.add(&String::from_utf8_lossy(id), String::from_utf8_lossy(&body).trim_end_matches('\0').to_owned())
+			}
+			Ok(true)
+		})
+	}
+
+
+	/// the metadata blocks of a flac file, after its `fLaC`
+	fn read_flac <T: Read + Seek> (&mut self, data: &mut T) -> io::Result<()> {
+		loop {
+			let mut header = [0; 4];
+			data.read_exact(&mut header)?;
+			let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+			match header[0] & 0x7F {
+				// streaminfo, the sample rate is 20 bits at 10 and the
+				// frames 36 bits at 13
+				0 => {
+					let body = read_vec(data, len)?;
+					if body.len() >= 18 {
+						let rate = (body[10] as u32) << 12 | (body[11] as u32) << 4 | (body[12] as u32) >> 4;
+						let frames = ((body[13] & 0xF) as u64) << 32 | u32::from_be_bytes(body[14..18].try_into().unwrap()) as u64;
+						if rate > 0 && frames > 0 {
+							self.duration = Some(Duration::from_secs_f64(frames as f64 / rate as f64));
+						}
+					}
+				},
+				4 => self.read_vorbis_comment(&read_vec(data, len)?),
+				6 => {
+					if let Some((kind, picture)) = read_flac_picture(&read_vec(data, len)?) {
+						self.add_picture(kind, picture);
+					}
+				},
+				_ => {
+					data.seek(SeekFrom::Current(len as i64))?;
+				}
+			}
+			// the last block
+			if header[0] & 0x80 != 0 {
+				return Ok(());
+			}
+		}
+	}
+
+
+	/// the comment header of an ogg vorbis or opus file, and its length
+	/// from the granule of the last page
+	fn read_ogg <T: Read + Seek> (&mut self, data: &mut T) -> io::Result<()> {
+		let start = data.stream_position()?;
+		let mut pages = OggPackets { data: &mut *data, serial: None, packet: vec![] };
+		let ident = pages.next_packet()?;
+		let comment = pages.next_packet()?;
+
+		// the granule of opus is at 48 kHz, after a pre skip
+		let (rate, pre_skip, comment) = if ident.starts_with(b"\x01vorbis") {
+			(u32_le(&ident, 12), 0, comment.strip_prefix(b"\x03vorbis"))
+		} else if ident.starts_with(b"OpusHead") && ident.len() >= 12 {
+			(48000, u16::from_le_bytes([ident[10], ident[11]]) as u64, comment.strip_prefix(b"OpusTags"))
+		} else {
+			(0, 0, None)
+		};
+		if let Some(comment) = comment {
+			self.read_vorbis_comment(comment);
+		}
+
+		let serial = pages.serial;
+		let end = data.seek(SeekFrom::End(0))?;
+		let tail = end.saturating_sub(start).min(1 << 16);
+		data.seek(SeekFrom::Start(end - tail))?;
+		let tail = read_vec(data, tail as usize)?;
+		let granule = (0..tail.len().saturating_sub(27))
+			.rev()
+			.filter(|&i| &tail[i..i + 4] == b"OggS" && Some(u32_le(&tail, i + 14)) == serial)
+			.map(|i| u64::from_le_bytes(tail[i + 6..i + 14].try_into().unwrap()))
+			.find(|&x| x != u64::MAX);
+		if let Some(granule) = granule.filter(|_| rate > 0) {
+			self.duration = Some(Duration::from_secs_f64(granule.saturating_sub(pre_skip) as f64 / rate as f64));
+		}
+		Ok(())
+	}
+
+
+	/// a vorbis comment, without the header of its packet
+	fn read_vorbis_comment (&mut self, body: &[u8]) {
+		// the vendor string, then the number of comments
+		let Some(mut index) = (u32_le(body, 0) as usize).checked_add(8) else {
+			return;
+		};
+		for _ in 0..u32_le(body, index - 4) {
+			let len = u32_le(body, index) as usize;
+			let start = index + 4;
+			// the lengths can overflow a 32 bits usize
+			let Some(comment) = start.checked_add(len).and_then(|end| body.get(start..end)) else {
+				break;
+			};
+			index = start + len;
+			let comment = String::from_utf8_lossy(comment);
+			let Some((key, value)) = comment.split_once('=') else {
+				continue;
+			};
+			if key.eq_ignore_ascii_case("METADATA_BLOCK_PICTURE") {
+				if let Some((kind, picture)) = base64(value).as_deref().and_then(read_flac_picture) {
+					self.add_picture(kind, picture);
+				}
+			} else {
+				self.add(key, value.to_owned());
+			}
+		}
+	}
+
+
+	/// an id3 tag of a wav or aiff chunk
+	fn read_id3_chunk (&mut self, mut body: Vec<u8>) {
+		if body.len() >= 10 && body.starts_with(b"ID3") {
+			let header: [u8; 10] = body[..10].try_into().unwrap();
+			body.drain(..10);
+			self.read_id3(&header, &mut body);
+		}
+	}
+
+
+	/// the frames of an id3v2 tag, after its `header`
+	fn read_id3 (&mut self, header: &[u8; 10], tag: &mut Vec<u8>) {
+		let version = header[3];
+		let flags = header[5];
+		if !(2..=4).contains(&version) {
+			return;
+		}
+		// every 0xFF 0x00 was a 0xFF, so no byte looks like a sync
+		if flags & 0x80 != 0 {
+			let mut previous = 0;
+			tag.retain(|&x| {
+				let keep = !(previous == 0xFF && x == 0);
+				previous = x;
+				keep
+			});
+		}
+		let mut index = 0;
+		if flags & 0x40 != 0 && version >= 3 {
+			let size = tag.get(0..4).unwrap_or_default();
+			// the size includes itself only in v4
+			index = if version == 4 {
+				syncsafe(size) as usize
+			} else {
+				(u32::from_be_bytes(size.try_into().unwrap_or_default()) as usize).saturating_add(4)
+			};
+		}
+
+		let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+		while index.saturating_add(header_len) <= tag.len() && tag[index] != 0 {
+			let id = String::from_utf8_lossy(&tag[index..index + id_len]).into_owned();
+			let size = &tag[index + id_len..index + header_len];
+			let len = match version {
+				2 => u32::from_be_bytes([0, size[0], size[1], size[2]]),
+				3 => u32::from_be_bytes(size[0..4].try_into().unwrap()),
+				_ => syncsafe(&size[0..4])
+			} as usize;
+			index += header_len;
+			let Some(body) = index.checked_add(len).and_then(|end| tag.get(index..end)) else {
+				break;
+			};
+			index += len;
+			if body.is_empty() {
+				continue;
+			}
+			// text frames, but not the user defined one
+			if id.starts_with('T') && id != "TXXX" && id != "TXX" {
+				let text = text(body[0], &body[1..]);
+				if (id == "TLEN" || id == "TLE") && self.duration.is_none() {
+					self.duration = text.trim().parse().ok().map(Duration::from_millis);
+				}
+				self.add(&id, text);
+			} else if id == "APIC" || id == "PIC" {
+				if let Some((kind, picture)) = read_id3_picture(body, version) {
+					self.add_picture(kind, picture);
+				}
+			}
+		}
+	}
+
+
+}
+
+
+
+/// call `f` with the id, the length and the reader of every chunk
+/// of a riff or iff file, after its header
+///
+/// `f` returns if it read the chunk, else it's skipped
+fn for_each_chunk <T: Read + Seek> (
+	data: &mut T,
+	big_endian: bool,
+	mut f: impl FnMut(&[u8; 4], u64, &mut T) -> io::Result<bool>
+) -> io::Result<()> {
+	let mut chunk = [0; 8];
+	while data.read_exact(&mut chunk).is_ok() {
+		let id: [u8; 4] = chunk[0..4].try_into().unwrap();
+		let len = chunk[4..8].try_into().unwrap();
+		let len = if big_endian { u32::from_be_bytes(len) } else { u32::from_le_bytes(len) } as u64;
+		// chunks are padded to an even length
+		let skip = if f(&id, len, data)? { len % 2 } else { len + len % 2 };
+		data.seek(SeekFrom::Current(skip as i64))?;
+	}
+	Ok(())
+}
+
+
+
+/// reads the packets of the first logical stream of an ogg file
+struct OggPackets <'a, T: Read> {
+	data: &'a mut T,
+	serial: Option<u32>,
+	/// the segments of the packet being read
+	packet: Vec<u8>
+}
+
+impl <T: Read> OggPackets<'_, T> {
+
+
+	fn next_packet (&mut self) -> io::Result<Vec<u8>> {
+		loop {
+			let mut header = [0; 27];
+			self.data.read_exact(&mut header)?;
+			if &header[0..4] != b"OggS" {
+				return Err(invalid("invalid ogg page"));
+			}
+			let mut segments = vec![0; header[26] as usize];
+			self.data.read_exact(&mut segments)?;
+			let body = read_vec(self.data, segments.iter().map(|&x| x as usize).sum())?;
+			let serial = u32_le(&header, 14);
+			if *self.serial.get_or_insert(serial) != serial {
+				continue;
+			}
+			let mut index = 0;
+			let mut done = None;
+			for (i, &len) in segments.iter().enumerate() {
+				if done.is_none() {
+					self.packet.extend_from_slice(&body[index..index + len as usize]);
+				}
+				index += len as usize;
+				// a packet ends with a segment shorter than 255
+				if len < 255 && done.is_none() {
+					done = Some(i);
+				}
+			}
+			if self.packet.len() > MAX_TAG {
+				return Err(invalid("ogg packet too big"));
+			}
+			// only the first packet of a page is kept, the next ones are
+			// the audio after the headers or a setup header
+			if done.is_some() {
+				return Ok(std::mem::take(&mut self.packet));
+			}
+		}
+	}
+
+
+}
+
+
+
+/// the type and the picture of an `APIC` or `PIC` frame
+fn read_id3_picture (body: &[u8], version: u8) -> Option<(u8, CoverArt)> {
+	let encoding = body[0];
+	let (mime_type, rest) = if version == 2 {
+		// a format like `JPG` instead of a mime type
+		let format = String::from_utf8_lossy(body.get(1..4)?).to_ascii_lowercase();
+		(format!("image/{}", if format == "jpg" { "jpeg" } else { &format }), body.get(4..)?)
+	} else {
+		let end = body[1..].iter().position(|&x| x == 0)? + 1;
+		(String::from_utf8_lossy(&body[1..end]).into_owned(), body.get(end + 1..)?)
+	};
+	let kind = *rest.first()?;
+	let (_, data) = split_terminated(encoding, &rest[1..]);
+	Some((kind, CoverArt { mime_type, data: data.to_vec() }))
+}
+
+
+
+/// the type and the picture of a flac picture block
+fn read_flac_picture (body: &[u8]) -> Option<(u8, CoverArt)> {
+	let u32_at = |index: usize| body.get(index..index.checked_add(4)?).map(|x| u32::from_be_bytes(x.try_into().unwrap()) as usize);
+	let kind = u32_at(0)?;
+	let description = u32_at(4)?.checked_add(8)?;
+	let mime_type = String::from_utf8_lossy(body.get(8..description)?).into_owned();
+	// then the width, height, depth and colors
+	let data = u32_at(description)?.checked_add(description + 20)?;
+	let end = u32_at(data)?.checked_add(data + 4)?;
+	let data = body.get(data + 4..end)?.to_vec();
+	Some((kind.min(u8::MAX as usize) as u8, CoverArt { mime_type, data }))
+}
+
+
+
+/// the text of an id3 frame in `encoding`, with its values joined
+fn text (encoding: u8, bytes: &[u8]) -> String {
+	let text = match encoding {
+		0 => bytes.iter().map(|&x| x as char).collect(),
+		1 | 2 => {
+			let big_endian = encoding == 2 || bytes.starts_with(&[0xFE, 0xFF]);
+			let units: Vec<u16> = bytes
+				.chunks_exact(2)
+				.map(|x| if big_endian { u16::from_be_bytes([x[0], x[1]]) } else { u16::from_le_bytes([x[0], x[1]]) })
+				.filter(|&x| x != 0xFEFF)
+				.collect();
+			String::from_utf16_lossy(&units)
+		},
+		_ => String::from_utf8_lossy(bytes).into_owned()
+	};
+	// id3v2.4 separates the values with nulls
+	text.split('\0').filter(|x| !x.is_empty()).collect::<Vec<_>>().join("; ")
+}
+
+
+
+/// split a null terminated text in `encoding` from what follows it
+fn split_terminated (encoding: u8, bytes: &[u8]) -> (&[u8], &[u8]) {
+	let end = if encoding == 1 || encoding == 2 {
+		bytes.chunks_exact(2).position(|x| x == [0, 0]).map(|x| (x * 2, 2))
+	} else {
+		bytes.iter().position(|&x| x == 0).map(|x| (x, 1))
+	};
+	match end {
+		Some((end, len)) => (&bytes[..end], &bytes[end + len..]),
+		None => (bytes, &[])
+	}
+}
+
+
+
+/// decode standard base64, `None` if it's not
+fn base64 (text: &str) -> Option<Vec<u8>> {
+	let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+	let mut acc = 0u32;
+	let mut bits = 0;
+	for x in text.bytes().filter(|&x| x != b'=' && !x.is_ascii_whitespace()) {
+		let value = match x {
+			b'A'..=b'Z' => x - b'A',
+			b'a'..=b'z' => x - b'a' + 26,
+			b'0'..=b'9' => x - b'0' + 52,
+			b'+' => 62,
+			b'/' => 63,
+			_ => return None
+		};
+		acc = acc << 6 | value as u32;
+		bits += 6;
+		if bits >= 8 {
+			bits -= 8;
+			bytes.push((acc >> bits) as u8);
+		}
+	}
+	Some(bytes)
+}
+
+
+
+/// read `len` bytes, without trusting `len` for the allocation
+fn read_vec <T: Read> (data: &mut T, len: usize) -> io::Result<Vec<u8>> {
+	let mut bytes = vec![];
+	data.take(len.min(MAX_TAG) as u64).read_to_end(&mut bytes)?;
+	if bytes.len() < len.min(MAX_TAG) {
+		return Err(io::ErrorKind::UnexpectedEof.into());
+	}
+	Ok(bytes)
+}
+
+
+
+/// the size of an id3 tag, 7 bits per byte
+fn syncsafe (bytes: &[u8]) -> u32 {
+	bytes.iter().take(4).fold(0, |acc, &x| acc << 7 | (x & 0x7F) as u32)
+}
+
+
+
+fn u32_le (bytes: &[u8], index: usize) -> u32 {
+	bytes.get(index..index.saturating_add(4)).map_or(0, |x| u32::from_le_bytes(x.try_into().unwrap()))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::io::Cursor;
+	use std::time::Duration;
+
+	use super::{ AudioMetadata, CoverArt, base64, read_flac_picture };
+
+
+	/// an id3 frame of `version`, with the size of `body`
+	fn frame (version: u8, id: &str, body: &[u8]) -> Vec<u8> {
+		let len = body.len() as u32;
+		let mut frame = id.as_bytes().to_vec();
+		match version {
+			2 => frame.extend_from_slice(&len.to_be_bytes()[1..]),
+			3 => frame.extend_from_slice(&len.to_be_bytes()),
+			_ => frame.extend([len >> 21, len >> 14, len >> 7, len].map(|x| (x & 0x7F) as u8))
+		}
+		if version > 2 {
+			frame.extend([0, 0]);
+		}
+		frame.extend_from_slice(body);
+		frame
+	}
+
+
+	/// the latin-1 text frame `id` of `version`
+	fn text (version: u8, id: &str, text: &str) -> Vec<u8> {
+		frame(version, id, &[&[0], text.as_bytes()].concat())
+	}
+
+
+	/// the metadata of an id3 tag of `version`, with `flags`
+	fn id3 (version: u8, flags: u8, tag: &[u8]) -> AudioMetadata {
+		let len = tag.len() as u32;
+		let mut header = [b'I', b'D', b'3', version, 0, flags, 0, 0, 0, 0];
+		header[6..].copy_from_slice(&[len >> 21, len >> 14, len >> 7, len].map(|x| (x & 0x7F) as u8));
+		let mut metadata = AudioMetadata::default();
+		metadata.read_id3(&header, &mut tag.to_vec());
+		metadata
+	}
+
+
+	/// a flac picture block
+	fn flac_picture (kind: u32, mime_type: &str, data: &[u8]) -> Vec<u8> {
+		let mut block = kind.to_be_bytes().to_vec();
+		block.extend((mime_type.len() as u32).to_be_bytes());
+		block.extend(mime_type.as_bytes());
+		block.extend(5u32.to_be_bytes());
+		block.extend(b"cover");
+		block.extend([0; 16]);
+		block.extend((data.len() as u32).to_be_bytes());
+		block.extend(data);
+		block
+	}
+
+
+	/// a vorbis comment of `comments`, after a vendor string
+	fn vorbis_comment (comments: &[&[u8]]) -> Vec<u8> {
+		let mut body = 6u32.to_le_bytes().to_vec();
+		body.extend(b"vendor");
+		body.extend((comments.len() as u32).to_le_bytes());
+		for comment in comments {
+			body.extend((comment.len() as u32).to_le_bytes());
+			body.extend(*comment);
+		}
+		body
+	}
+
+
+	#[test]
+	fn id3_versions () {
+		for version in 3..=4 {
+			let tag = [
+				text(version, "TIT2", "Song"),
+				text(version, "TPE1", "Artist"),
+				text(version, "TLEN", "90500"),
+				frame(version, "TXXX", b"\0key\0value"),
+				// padding
+				vec![0; 16]
+			].concat();
+			let metadata = id3(version, 0, &tag);
+			assert_eq!(metadata.title.as_deref(), Some("Song"));
+			assert_eq!(metadata.artist.as_deref(), Some("Artist"));
+			assert_eq!(metadata.duration, Some(Duration::from_millis(90500)));
+			assert_eq!(metadata.tags.len(), 3);
+		}
+		let metadata = id3(2, 0, &[text(2, "TT2", "Old"), text(2, "TAL", "Album")].concat());
+		assert_eq!((metadata.title.as_deref(), metadata.album.as_deref()), (Some("Old"), Some("Album")));
+		// no other version is read
+		assert_eq!(id3(5, 0, &text(4, "TIT2", "Song")), AudioMetadata::default());
+	}
+
+
+	#[test]
+	fn id3_sizes () {
+		// a size of 200 is syncsafe in v4, and a plain number in v3
+		let long = "x".repeat(199);
+		assert_eq!(id3(4, 0, &text(4, "TIT2", &long)).title.as_deref(), Some(long.as_str()));
+		assert_eq!(id3(3, 0, &text(3, "TIT2", &long)).title.as_deref(), Some(long.as_str()));
+		// a frame past the end is not read
+		let mut tag = text(3, "TIT2", "Song");
+		tag[7] = 0xFF;
+		assert_eq!(id3(3, 0, &tag).title, None);
+	}
+
+
+	#[test]
+	fn id3_encodings () {
+		let utf16: Vec<u8> = [0xFEFF].into_iter().chain("Café".encode_utf16()).flat_map(u16::to_le_bytes).collect();
+		let utf16_be: Vec<u8> = "Café".encode_utf16().flat_map(u16::to_be_bytes).collect();
+		let tag = [
+			frame(4, "TIT2", &[&[1], &utf16[..]].concat()),
+			frame(4, "TPE1", &[&[2], &utf16_be[..]].concat()),
+			frame(4, "TALB", &[&[3], "Café".as_bytes()].concat()),
+			frame(4, "TCON", b"\0Rock\0Pop")
+		].concat();
+		let metadata = id3(4, 0, &tag);
+		assert_eq!(metadata.title.as_deref(), Some("Café"));
+		assert_eq!(metadata.artist.as_deref(), Some("Café"));
+		assert_eq!(metadata.album.as_deref(), Some("Café"));
+		assert_eq!(metadata.tags[3], ("TCON".to_owned(), "Rock; Pop".to_owned()));
+	}
+
+
+	#[test]
+	fn id3_unsynchronisation () {
+		// the size of the frame is the size once the tag is restored
+		let tag = [&b"TIT2"[..], &[0, 0, 0, 4, 0, 0], &[0, b'a', 0xFF, 0x00, b'b']].concat();
+		assert_eq!(id3(3, 0x80, &tag).title.as_deref(), Some("a\u{ff}b"));
+	}
+
+
+	#[test]
+	fn id3_extended_header () {
+		// v3 doesn't count the size itself, v4 does and is syncsafe
+		let v3 = [&[0, 0, 0, 6][..], &[0; 6], &text(3, "TIT2", "Song")].concat();
+		assert_eq!(id3(3, 0x40, &v3).title.as_deref(), Some("Song"));
+		let v4 = [&[0, 0, 0, 6][..], &[1, 0], &text(4, "TIT2", "Song")].concat();
+		assert_eq!(id3(4, 0x40, &v4).title.as_deref(), Some("Song"));
+		// a size past the tag
+		let broken = [&[0xFF, 0xFF, 0xFF, 0xFF][..], &text(3, "TIT2", "Song")].concat();
+		assert_eq!(id3(3, 0x40, &broken).title, None);
+	}
+
+
+	#[test]
+	fn id3_pictures () {
+		let apic = frame(3, "APIC", &[&[0][..], b"image/png\0", &[4], b"back\0", &[1, 2, 3]].concat());
+		let front = frame(3, "APIC", &[&[0][..], b"image/jpeg\0", &[3], b"\0", &[4, 5]].concat());
+		// the first picture, until the front cover
+		assert_eq!(id3(3, 0, &apic).cover_art, Some(CoverArt { mime_type: "image/png".to_owned(), data: vec![1, 2, 3] }));
+		assert_eq!(id3(3, 0, &[apic, front].concat()).cover_art, Some(CoverArt { mime_type: "image/jpeg".to_owned(), data: vec![4, 5] }));
+		let pic = frame(2, "PIC", &[&[0][..], b"JPG", &[3], b"\0", &[6]].concat());
+		assert_eq!(id3(2, 0, &pic).cover_art, Some(CoverArt { mime_type: "image/jpeg".to_owned(), data: vec![6] }));
+	}
+
+
+	#[test]
+	fn mp3_file () {
+		let tag = text(4, "TIT2", "Song");
+		let mut file = b"ID3\x04\x00\x00\x00\x00\x00".to_vec();
+		file.push(tag.len() as u8);
+		file.extend(tag);
+		// the audio
+		file.extend([0xFF, 0xFB, 0x90, 0x00]);
+		let metadata = AudioMetadata::from_reader(Cursor::new(file)).unwrap();
+		assert_eq!(metadata.title.as_deref(), Some("Song"));
+		assert!(AudioMetadata::from_reader(Cursor::new(vec![0; 16])).is_err());
+	}
+
+
+	#[test]
+	fn vorbis_comments () {
+		let picture = flac_picture(3, "image/png", &[9, 8, 7]);
+		let picture = format!("METADATA_BLOCK_PICTURE={}", encode(&picture));
+		let body = vorbis_comment(&[b"TITLE=Song", b"artist=A=B", b"no separator", b"ALBUM=", picture.as_bytes()]);
+		let mut metadata = AudioMetadata::default();
+		metadata.read_vorbis_comment(&body);
+		assert_eq!(metadata.title.as_deref(), Some("Song"));
+		// split at the first `=`, and the keys in any case
+		assert_eq!(metadata.artist.as_deref(), Some("A=B"));
+		assert_eq!(metadata.album, None);
+		assert_eq!(metadata.tags.len(), 2);
+		assert_eq!(metadata.cover_art, Some(CoverArt { mime_type: "image/png".to_owned(), data: vec![9, 8, 7] }));
+	}
+
+
+	#[test]
+	fn broken_vorbis_comments () {
+		// more comments than there are, one cut short
+		let mut body = vorbis_comment(&[b"TITLE=Song", b"ARTIST=Artist"]);
+		body[10] = 9;
+		body.truncate(body.len() - 3);
+		let mut metadata = AudioMetadata::default();
+		metadata.read_vorbis_comment(&body);
+		assert_eq!((metadata.title.as_deref(), metadata.artist), (Some("Song"), None));
+		// lengths that overflow
+		for body in [vec![0xFF; 8], [&[0, 0, 0, 0, 1, 0, 0, 0][..], &[0xFF; 4]].concat(), vec![]] {
+			let mut metadata = AudioMetadata::default();
+			metadata.read_vorbis_comment(&body);
+			assert_eq!(metadata, AudioMetadata::default());
+		}
+	}
+
+
+	#[test]
+	fn riff_info () {
+		// the chunks are padded to an even length, and a length past
+		// the list stops it
+		let body = [&b"INAM"[..], &[5, 0, 0, 0], b"Song\0\0", b"IART", &[2, 0, 0, 0], b"Me", b"IPRD", &[0xFF; 4], b"Album"].concat();
+		let mut metadata = AudioMetadata::default();
+		metadata.read_info(&body);
+		assert_eq!((metadata.title.as_deref(), metadata.artist.as_deref(), metadata.album), (Some("Song"), Some("Me"), None));
+	}
+
+
+	#[test]
+	fn flac_pictures () {
+		let block = flac_picture(3, "image/jpeg", &[1, 2, 3, 4]);
+		assert_eq!(read_flac_picture(&block), Some((3, CoverArt { mime_type: "image/jpeg".to_owned(), data: vec![1, 2, 3, 4] })));
+		assert_eq!(read_flac_picture(&flac_picture(300, "", &[])).map(|x| x.0), Some(u8::MAX));
+		// cut short, and with lengths past the block
+		assert_eq!(read_flac_picture(&block[..block.len() - 1]), None);
+		let mut big = block.clone();
+		big[4..8].copy_from_slice(&[0xFF; 4]);
+		assert_eq!(read_flac_picture(&big), None);
+		let data = block.len() - 8;
+		big = block.clone();
+		big[data..data + 4].copy_from_slice(&[0xFF; 4]);
+		assert_eq!(read_flac_picture(&big), None);
+	}
+
+
+	/// `bytes` in standard base64, with padding
+	fn encode (bytes: &[u8]) -> String {
+		const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+		let mut text = String::new();
+		for chunk in bytes.chunks(3) {
+			let x = u32::from_be_bytes([0, chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)]);
+			for i in 0..4 {
+				text.push(if i <= chunk.len() { ALPHABET[(x >> (18 - 6 * i)) as usize & 63] as char } else { '=' });
+			}
+		}
+		text
+	}
+
+
+	#[test]
+	fn base64_decoding () {
+		assert_eq!(base64("TWFu").unwrap(), b"Man");
+		assert_eq!(base64("TWE=").unwrap(), b"Ma");
+		assert_eq!(base64("TQ==").unwrap(), b"M");
+		assert_eq!(base64("TW\nFu TQ").unwrap(), b"ManM");
+		assert_eq!(base64("").unwrap(), b"");
+		assert_eq!(base64("TW@u"), None);
+		let bytes: Vec<u8> = (0..=255).collect();
+		assert_eq!(base64(&encode(&bytes)).unwrap(), bytes);
+	}
+
+
+}
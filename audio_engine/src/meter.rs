@@ -0,0 +1,113 @@
+
+
+
+//! Peak and RMS levels, measured by the audio thread.
+
+
+
+use std::sync::atomic::{ AtomicU32, Ordering };
+
+use crate::decibel::gain_to_db;
+
+
+
+/// how loud some audio was during the last mixed buffer
+///
+/// `1.0` is full scale, the peak goes over it when the mix clips
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Levels {
+
+	/// the loudest sample
+	pub peak: f32,
+	/// the root mean square of the samples
+	pub rms: f32
+
+}
+
+impl Levels {
+
+
+	/// the peak in dB of full scale, `0.0` is full scale
+	pub fn peak_db (&self) -> f32 {
+		gain_to_db(self.peak)
+	}
+
+
+	/// the rms in dB of full scale
+	pub fn rms_db (&self) -> f32 {
+		gain_to_db(self.rms)
+	}
+
+
+}
+
+
+
+/// levels written by the audio thread, and read without locking
+pub struct Meter {
+	/// the bits of the `f32`s
+	peak: AtomicU32,
+	rms: AtomicU32
+}
+
+impl Meter {
+
+
+	pub fn new () -> Self {
+		Self {
+			peak: AtomicU32::new(0),
+			rms: AtomicU32::new(0)
+		}
+	}
+
+
+	pub fn levels (&self) -> Levels {
+		Levels {
+			peak: f32::from_bits(self.peak.load(Ordering::Relaxed)),
+			rms: f32::from_bits(self.rms.load(Ordering::Relaxed))
+		}
+	}
+
+
+	pub fn store (&self, levels: Levels) {
+		self.peak.store(levels.peak.to_bits(), Ordering::Relaxed);
+		self.rms.store(levels.rms.to_bits(), Ordering::Relaxed);
+	}
+
+
+}
+
+impl Default for Meter {
+	fn default () -> Self {
+		Self::new()
+	}
+}
+
+
+
+/// the levels of the samples of one buffer, as they are mixed
+#[derive(Default)]
+pub struct Measure {
+	peak: f32,
+	sum: f32,
+	count: usize
+}
+
+impl Measure {
+
+
+	pub fn add (&mut self, sample: f32) {
+		let x = sample / i16::MAX as f32;
+		self.peak = self.peak.max(x.abs());
+		self.sum += x * x;
+		self.count += 1;
+	}
+
+
+	pub fn levels (&self) -> Levels {
+		let rms = if self.count == 0 { 0.0 } else { (self.sum / self.count as f32).sqrt() };
+		Levels { peak: self.peak, rms }
+	}
+
+
+}
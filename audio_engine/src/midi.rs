@@ -0,0 +1,633 @@
+
+
+
+//! Standard MIDI files, played through a [`SoundFont`] by a [`Sequencer`].
+//!
+//! Every track of a file is merged into one list of events sorted by time. The sequencer plays
+//! the notes, program and bank changes, pitch bends, and the volume, expression and sustain
+//! controllers of the 16 channels, with channel 10 playing the drum kits. The tempo and the
+//! transposition can be changed while it plays, through its [`SequencerControls`].
+
+
+
+use std::io::{ self, Read };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::time::Duration;
+
+use crate::mixer::SoundSource;
+use crate::soundfont::{ DRUM_BANK, SoundFont };
+use crate::synth::Synth;
+
+
+
+/// the sample rate of the sequencer, unless set otherwise
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// frames played between the events that are due, which sets how
+/// late an event can be heard
+const EVENT_BLOCK_FRAMES: usize = 64;
+
+/// microseconds per quarter note, 120 beats per minute
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// the channel of the drums, channel 10 counting from 1
+const DRUM_CHANNEL: usize = 9;
+
+/// in semitones, the range of a full pitch bend
+const BEND_RANGE: f32 = 2.0;
+
+
+
+fn invalid (message: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+
+/// how the ticks of a file are counted
+#[derive(Debug, Clone, Copy)]
+enum Division {
+	/// ticks per quarter note, which lasts as long as the tempo says
+	Quarter(u16),
+	/// ticks per second, for files synced to video
+	Seconds(f64)
+}
+
+
+
+#[derive(Debug, Clone, Copy)]
+enum MidiEvent {
+	/// microseconds per quarter note
+	Tempo(u32),
+	/// a channel message, the status and up to two data bytes
+	Message([u8; 3])
+}
+
+
+
+/// a parsed `.mid` file, see [`Sequencer`]
+#[derive(Debug, Clone)]
+pub struct MidiFile {
+
+	division: Division,
+	/// the tick of each event, in order
+	events: Arc<[(u64, MidiEvent)]>
+
+}
+
+impl MidiFile {
+
+
+	/// read a whole standard midi file, of format 0 or 1
+	///
+	/// format 2 files, of independent sequences, are played as if
+	/// they were format 1
+	pub fn new (mut data: impl Read) -> io::Result<Self> {
+		let mut bytes = vec![];
+		data.read_to_end(&mut bytes)?;
+		let mut reader = Reader { data: &bytes, pos: 0 };
+
+		if reader.take(4)? != b"MThd" {
+			return Err(invalid("not a midi file"));
+		}
+		let len = reader.u32()? as usize;
+		let header = reader.take(len)?;
+		if header.len() < 6 {
+			return Err(invalid("midi header too short"));
+		}
+		let tracks = u16::from_be_bytes([header[2], header[3]]);
+		let division = match u16::from_be_bytes([header[4], header[5]]) {
+			x if x & 0x8000 == 0 => Division::Quarter(x.max(1)),
+			x => {
+				// frames per second as a negative byte, and ticks per frame
+				let fps = match ((x >> 8) as i8).unsigned_abs() {
+					24 => 24.0,
+					25 => 25.0,
+					29 => 29.97,
+					30 => 30.0,
+					_ => return Err(invalid("midi frame rate not 24, 25, 29 or 30"))
+				};
+				Division::Seconds((fps * (x & 0xff) as f64).max(1.0))
+			}
+		};
+
+		let mut events = vec![];
+		for _ in 0..tracks {
+			if reader.at_end() {
+				break;
+			}
+			let id = reader.take(4)?;
+			let len = reader.u32()? as usize;
+			let track = reader.take(len)?;
+			if id == b"MTrk" {
+				read_track(track, &mut events)?;
+			}
+		}
+		// stable, so events at the same tick keep the order of the
+		// tracks
+		events.sort_by_key(|(tick, _)| *tick);
+
+		Ok(Self { division, events: events.into() })
+	}
+
+
+	/// how long the file plays at its own tempo, not counting the
+	/// release of the last notes
+	pub fn duration (&self) -> Duration {
+		let mut time = 0.0;
+		let (mut tick, mut tempo) = (0, DEFAULT_TEMPO);
+		for &(at, event) in self.events.iter() {
+			time += seconds_per_tick(self.division, tempo) * (at - tick) as f64;
+			tick = at;
+			if let MidiEvent::Tempo(x) = event {
+				tempo = x;
+			}
+		}
+		Duration::from_secs_f64(time)
+	}
+
+
+}
+
+
+
+/// reads the big endian numbers of a midi file
+struct Reader<'a> {
+	data: &'a [u8],
+	pos: usize
+}
+
+impl<'a> Reader<'a> {
+
+	fn at_end (&self) -> bool {
+		self.pos >= self.data.len()
+	}
+
+	fn take (&mut self, len: usize) -> io::Result<&'a [u8]> {
+		let data = self.data.get(self.pos..self.pos.saturating_add(len)).ok_or(io::ErrorKind::UnexpectedEof)?;
+		self.pos += len;
+		Ok(data)
+	}
+
+	fn u8 (&mut self) -> io::Result<u8> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn u32 (&mut self) -> io::Result<u32> {
+		let x = self.take(4)?;
+		Ok(u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+	}
+
+	/// a variable length number, 7 bits per byte
+	fn var (&mut self) -> io::Result<u32> {
+		let mut value = 0u32;
+		for _ in 0..4 {
+			let x = self.u8()?;
+			value = (value << 7) | (x & 0x7f) as u32;
+			if x & 0x80 == 0 {
+				return Ok(value);
+			}
+		}
+		Err(invalid("midi number too long"))
+	}
+
+}
+
+
+
+/// add the events of `track` to `events`
+fn read_track (track: &[u8], events: &mut Vec<(u64, MidiEvent)>) -> io::Result<()> {
+	let mut reader = Reader { data: track, pos: 0 };
+	let mut tick = 0u64;
+	let mut running = None;
+	while !reader.at_end() {
+		tick += reader.var()? as u64;
+		let mut status = reader.u8()?;
+		match status {
+			0xff => {
+				let kind = reader.u8()?;
+				let len = reader.var()? as usize;
+				let data = reader.take(len)?;
+				match kind {
+					0x51 if len == 3 => {
+						let tempo = u32::from_be_bytes([0, data[0], data[1], data[2]]);
+						events.push((tick, MidiEvent::Tempo(tempo.max(1))));
+					},
+					// the end of the track
+					0x2f => break,
+					_ => ()
+				}
+			},
+			0xf0 | 0xf7 => {
+				let len = reader.var()? as usize;
+				reader.take(len)?;
+			},
+			_ => {
+				// a data byte first reuses the last status
+				let first = if status & 0x80 == 0 {
+					let data = status;
+					status = running.ok_or_else(|| invalid("midi data without a status"))?;
+					data
+				} else {
+					running = Some(status);
+					reader.u8()?
+				};
+				let second = match status & 0xf0 {
+					0xc0 | 0xd0 => 0,
+					_ => reader.u8()?
+				};
+				events.push((tick, MidiEvent::Message([status, first & 0x7f, second & 0x7f])));
+			}
+		}
+	}
+	Ok(())
+}
+
+
+
+fn seconds_per_tick (division: Division, tempo: u32) -> f64 {
+	match division {
+		Division::Quarter(ticks) => tempo as f64 / 1e6 / ticks as f64,
+		Division::Seconds(ticks) => 1.0 / ticks
+	}
+}
+
+
+
+/// the state of one of the 16 channels
+struct Channel {
+
+	synth: Synth,
+	bank: u16,
+	volume: f32,
+	expression: f32,
+	/// the note each key started, once transposed
+	notes: [u8; 128]
+
+}
+
+impl Channel {
+
+	fn new (font: &SoundFont, drums: bool, sample_rate: u32) -> Self {
+		let bank = if drums { DRUM_BANK } else { 0 };
+		Self {
+			synth: Synth::from_samples(font.preset(bank, 0)).sample_rate(sample_rate),
+			bank,
+			volume: 100.0 / 127.0,
+			expression: 1.0,
+			notes: [0; 128]
+		}
+	}
+
+}
+
+
+
+/// the tempo and transposition shared with the controls
+struct Params {
+	/// the bits of an `f32`
+	tempo: AtomicU32,
+	/// the bits of an `i32`
+	transpose: AtomicU32
+}
+
+
+
+/// plays a [`MidiFile`] with the instruments of a [`SoundFont`], as a
+/// [`SoundSource`]
+///
+/// it is mono, and ends once the last event is played and the notes
+/// are released. looping the sound plays the file again. it can't seek
+///
+/// ```ignore
+/// let font = SoundFont::new(File::open("music.sf2")?)?;
+/// let sequencer = Sequencer::new(MidiFile::new(File::open("theme.mid")?)?, &font);
+/// let controls = sequencer.controls();
+/// let sound = engine.play(sequencer);
+/// controls.set_tempo(1.5);
+/// ```
+pub struct Sequencer {
+
+	midi: MidiFile,
+	font: SoundFont,
+	channels: Vec<Channel>,
+	params: Arc<Params>,
+	/// the next event to play
+	next: usize,
+	/// where the sequencer is, in ticks of the file
+	tick: f64,
+	/// microseconds per quarter note, from the file
+	tempo: u32,
+	amplitude: f32,
+	sample_rate: u32
+
+}
+
+impl Sequencer {
+
+
+	pub fn new (midi: MidiFile, font: &SoundFont) -> Self {
+		Self {
+			midi,
+			channels: (0..16).map(|i| Channel::new(font, i == DRUM_CHANNEL, DEFAULT_SAMPLE_RATE)).collect(),
+			font: font.clone(),
+			params: Arc::new(Params {
+				tempo: AtomicU32::new(1f32.to_bits()),
+				transpose: AtomicU32::new(0)
+			}),
+			next: 0,
+			tick: 0.0,
+			tempo: DEFAULT_TEMPO,
+			amplitude: 0.5,
+			sample_rate: DEFAULT_SAMPLE_RATE
+		}
+	}
+
+
+	/// the gain of the channels at full volume, `0.5` by default
+	pub fn amplitude (mut self, amplitude: f32) -> Self {
+		self.amplitude = amplitude;
+		self
+	}
+
+
+	/// 48kHz by default
+	pub fn sample_rate (mut self, sample_rate: u32) -> Self {
+		self.sample_rate = sample_rate.max(1);
+		self.channels = (0..16).map(|i| Channel::new(&self.font, i == DRUM_CHANNEL, self.sample_rate)).collect();
+		self
+	}
+
+
+	/// change the tempo and transposition after the sequencer was
+	/// given to a sound
+	pub fn controls (&self) -> SequencerControls {
+		SequencerControls { params: self.params.clone() }
+	}
+
+
+	fn play (&mut self, message: [u8; 3]) {
+		let [status, first, second] = message;
+		let index = (status & 0x0f) as usize;
+		let channel = &mut self.channels[index];
+		match status & 0xf0 {
+			0x90 if second > 0 => {
+				let transpose = if index == DRUM_CHANNEL { 0 } else { self.params.transpose.load(Ordering::Relaxed) as i32 };
+				let note = (first as i32 + transpose).clamp(0, 127) as u8;
+				channel.notes[first as usize] = note;
+				channel.synth.note_on(note, second);
+			},
+			// a note on without velocity is a note off
+			0x80 | 0x90 => channel.synth.note_off(Some(channel.notes[first as usize])),
+			0xb0 => match first {
+				0 => channel.bank = if index == DRUM_CHANNEL { DRUM_BANK } else { second as u16 },
+				7 => channel.volume = second as f32 / 127.0,
+				11 => channel.expression = second as f32 / 127.0,
+				64 => channel.synth.set_sustain(second >= 64),
+				120 => channel.synth.stop(),
+				121 => {
+					channel.expression = 1.0;
+					channel.synth.set_sustain(false);
+					channel.synth.set_bend(0.0);
+				},
+				123 => channel.synth.note_off(None),
+				_ => ()
+			},
+			0xc0 => {
+				channel.synth.set_samples(self.font.preset(channel.bank, first));
+			},
+			0xe0 => {
+				let bend = (((second as i32) << 7 | first as i32) - 8192) as f32 / 8192.0;
+				channel.synth.set_bend(bend * BEND_RANGE);
+			},
+			_ => ()
+		}
+	}
+
+
+	/// play the events that are due
+	fn process_events (&mut self) {
+		while let Some(&(tick, event)) = self.midi.events.get(self.next) {
+			if tick as f64 > self.tick {
+				break;
+			}
+			match event {
+				MidiEvent::Tempo(tempo) => self.tempo = tempo,
+				MidiEvent::Message(message) => self.play(message)
+			}
+			self.next += 1;
+		}
+	}
+
+
+}
+
+impl SoundSource for Sequencer {
+
+
+	fn channels (&self) -> u16 {
+		1
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	/// play the file again from the start
+	fn reset (&mut self) {
+		self.next = 0;
+		self.tick = 0.0;
+		self.tempo = DEFAULT_TEMPO;
+		for (i, channel) in self.channels.iter_mut().enumerate() {
+			channel.synth.stop();
+			channel.bank = if i == DRUM_CHANNEL { DRUM_BANK } else { 0 };
+			channel.volume = 100.0 / 127.0;
+			channel.expression = 1.0;
+			channel.synth.set_samples(self.font.preset(channel.bank, 0));
+		}
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let mut mix = [0.0f32; EVENT_BLOCK_FRAMES];
+		let mut written = 0;
+		for chunk in buffer.chunks_mut(EVENT_BLOCK_FRAMES) {
+			self.process_events();
+			if self.next >= self.midi.events.len() && self.channels.iter().all(|x| x.synth.is_silent()) {
+				break;
+			}
+
+			let mix = &mut mix[..chunk.len()];
+			mix.fill(0.0);
+			for channel in self.channels.iter_mut() {
+				channel.synth.set_amplitude(self.amplitude * channel.volume * channel.expression);
+				channel.synth.render(mix);
+			}
+			for (x, &y) in chunk.iter_mut().zip(mix.iter()) {
+				*x = (y * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+			}
+			written += chunk.len();
+
+			let tempo = f32::from_bits(self.params.tempo.load(Ordering::Relaxed)).max(0.01) as f64;
+			let seconds = chunk.len() as f64 / self.sample_rate as f64 * tempo;
+			self.tick += seconds / seconds_per_tick(self.midi.division, self.tempo);
+		}
+		written
+	}
+
+
+}
+
+
+
+/// changes the tempo and transposition of a [`Sequencer`] from any
+/// thread
+///
+/// the changes apply from the next buffer that is mixed
+#[derive(Clone)]
+pub struct SequencerControls {
+	params: Arc<Params>
+}
+
+impl SequencerControls {
+
+
+	/// how fast the file plays, `1.0` is its own tempo
+	pub fn set_tempo (&self, tempo: f32) {
+		self.params.tempo.store(tempo.to_bits(), Ordering::Relaxed);
+	}
+
+
+	/// in semitones, applied to the notes that start from now on. the
+	/// drums are not transposed
+	pub fn set_transpose (&self, semitones: i32) {
+		self.params.transpose.store(semitones as u32, Ordering::Relaxed);
+	}
+
+
+	pub fn tempo (&self) -> f32 {
+		f32::from_bits(self.params.tempo.load(Ordering::Relaxed))
+	}
+
+
+	pub fn transpose (&self) -> i32 {
+		self.params.transpose.load(Ordering::Relaxed) as i32
+	}
+
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::time::Duration;
+
+	use super::{ Division, MidiEvent, MidiFile, read_track };
+
+
+	/// the events of `track`, as ticks and messages, tempos as a
+	/// status of 0
+	fn events (track: &[u8]) -> Vec<(u64, [u8; 3])> {
+		let mut events = vec![];
+		read_track(track, &mut events).unwrap();
+		events.into_iter()
+			.map(|(tick, event)| match event {
+				MidiEvent::Tempo(x) => (tick, [0, (x >> 16) as u8, (x >> 8) as u8]),
+				MidiEvent::Message(x) => (tick, x)
+			})
+			.collect()
+	}
+
+
+	/// a format 0 file with `division` and one track
+	fn midi (division: u16, track: &[u8]) -> Vec<u8> {
+		let mut data = b"MThd".to_vec();
+		data.extend(6u32.to_be_bytes());
+		data.extend([0, 0, 0, 1]);
+		data.extend(division.to_be_bytes());
+		data.extend(b"MTrk");
+		data.extend((track.len() as u32).to_be_bytes());
+		data.extend(track);
+		data
+	}
+
+
+	#[test]
+	fn running_status () {
+		let track = [
+			0x00, 0x90, 60, 100,
+			// the same status, then one with a single data byte
+			0x10, 64, 90,
+			0x81, 0x00, 0xc2, 5,
+			0x05, 7,
+			0x00, 0xff, 0x2f, 0x00
+		];
+		assert_eq!(events(&track), [
+			(0, [0x90, 60, 100]),
+			(16, [0x90, 64, 90]),
+			(144, [0xc2, 5, 0]),
+			(149, [0xc2, 7, 0])
+		]);
+	}
+
+
+	#[test]
+	fn data_without_a_status () {
+		let mut events = vec![];
+		assert!(read_track(&[0x00, 60, 100], &mut events).is_err());
+	}
+
+
+	#[test]
+	fn tempo () {
+		let track = [
+			// a sysex and a text event are skipped
+			0x00, 0xf0, 0x02, 0x7e, 0xf7,
+			0x00, 0xff, 0x01, 0x02, b'h', b'i',
+			0x00, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40,
+			0x60, 0x80, 60, 0,
+			// nothing is read past the end of the track
+			0x00, 0xff, 0x2f, 0x00,
+			0x00, 0x90, 60, 100
+		];
+		assert_eq!(events(&track), [(0, [0, 0x0f, 0x42]), (96, [0x80, 60, 0])]);
+		// one second per quarter note, and 96 ticks per quarter note
+		let file = MidiFile::new(&midi(96, &track)[..]).unwrap();
+		assert_eq!(file.duration(), Duration::from_secs(1));
+	}
+
+
+	#[test]
+	fn quarter_division () {
+		// the default tempo, 120 beats per minute
+		let file = MidiFile::new(&midi(480, &[0x81, 0x70, 0x80, 60, 0])[..]).unwrap();
+		assert!(matches!(file.division, Division::Quarter(480)));
+		assert_eq!(file.duration(), Duration::from_millis(250));
+	}
+
+
+	#[test]
+	fn seconds_division () {
+		// 25 frames per second of 40 ticks, for 1000 ticks per second
+		let file = MidiFile::new(&midi(0xe728, &[0x83, 0x74, 0x80, 60, 0])[..]).unwrap();
+		assert!(matches!(file.division, Division::Seconds(x) if x == 1000.0));
+		assert_eq!(file.duration(), Duration::from_millis(500));
+		let file = MidiFile::new(&midi(0xe350, &[])[..]).unwrap();
+		assert!(matches!(file.division, Division::Seconds(x) if (x - 29.97 * 80.0).abs() < 1e-9));
+	}
+
+
+	#[test]
+	fn invalid_frame_rate () {
+		// -128 frames per second, which can't be negated as an i8
+		assert!(MidiFile::new(&midi(0x8028, &[])[..]).is_err());
+		assert!(MidiFile::new(&midi(0xe028, &[])[..]).is_err());
+	}
+
+
+}
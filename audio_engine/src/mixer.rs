@@ -1,428 +1,3771 @@
 
 
 
-use crate::converter;
-
+use crate::ambisonics::Ambisonics;
+use crate::biquad::{ Biquad, BiquadControls };
+use crate::converter::{ self, ResampleQuality };
+use crate::decibel::db_to_gain;
+use crate::easing::{ Easing, Ramp };
+use crate::effect::{ Effect, EffectId };
+use crate::envelope::{ Envelope, EnvelopeState };
+use crate::error::Error;
+use crate::hrtf::{ Binaural, Hrtf };
+use crate::layout;
+use crate::lfo::{ Lfo, LfoTarget };
+use crate::limiter::{ Limiter, LimiterConfig };
+use crate::occlusion::Occlusion;
+use crate::parameter::{ self, ParameterMap, ParameterTarget };
+use crate::reverb::{ Reverb, ReverbConfig };
+use crate::snapshot::Snapshot;
+use crate::spatial::{ Attenuation, Emitter, ListenerState };
+use crate::stretch::Stretcher;
+use crate::stereo;
+use crate::meter::{ Levels, Measure, Meter };
+use crate::queue::Queue;
+use crate::replay::Replay;
+use crate::tap::TapWriter;
+use crate::transport::{ Quantize, TransportState };
+
+use log::warn;
+
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::{
 	Arc,
 	Mutex,
-	atomic::{ AtomicU64, Ordering }
+	Weak,
+	atomic::{ AtomicBool, AtomicU8, AtomicU32, AtomicU64, Ordering }
 };
+use std::task::{ Context, Poll, Waker };
+use std::time::Duration;
 
 
 
-type SoundId = u64;
+/// identifies a sound inside the mixer
+///
+/// `index` is the slot where the sound lives and `generation` is
+/// bumped every time that slot is freed, so an id of a removed
+/// sound never matches the sound that reuses its slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SoundId {
+	index: u32,
+	generation: u32
+}
 
 
 
-fn next_id() -> SoundId {
-	static GLOBAL_COUNT: AtomicU64 = AtomicU64::new(0);
-	GLOBAL_COUNT.fetch_add(1, Ordering::Relaxed)
-}
+/// identifies a group of sounds inside the mixer
+///
+/// groups are never removed, so this is just their index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GroupId(u32);
 
 
 
-/// the number of samples processed per second for a single channel of audio
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct SampleRate(pub u32);
+/// how many commands can wait in the queue before `Sound` falls
+/// back to locking the mixer
+const COMMAND_QUEUE_CAPACITY: usize = 1024;
 
+/// how many events can wait to be read before new ones are lost
+const EVENT_QUEUE_CAPACITY: usize = 1024;
 
+/// default time a volume change takes, long enough to not click
+const DEFAULT_VOLUME_SMOOTHING: Duration = Duration::from_millis(10);
 
-/// represents a sound in the audio engine. if this is dropped,
-/// the sound will continue to play until it ends.
-pub struct Sound {
+/// the level of a sidechain where ducking starts, -40 dB
+const DUCK_THRESHOLD: f32 = 0.01;
 
-	pub mixer: Arc<Mutex<Mixer>>,
-	pub id: SoundId
+/// frames written at the same speed while the speed of a sound is
+/// animated
+const SPEED_BLOCK_FRAMES: usize = 64;
 
-}
+/// how fast a stolen sound fades out, so it doesn't click
+const STEAL_FADE: Duration = Duration::from_millis(10);
 
-impl Sound {
+/// the priority of a new sound
+const DEFAULT_PRIORITY: u8 = 128;
 
+/// a sound quieter than this, -80 dB, is made virtual
+const INAUDIBLE_GAIN: f32 = 1e-4;
 
-	/// starts or continue to play the sound
-	///
-	/// if the sound was paused ot stopped, it will start playing
-	/// again. otherwise, does nothing
-	pub fn play (&mut self) {
-		self.mixer.lock().unwrap().play(self.id);
-	}
+/// the cutoff of the low pass of a group before a snapshot moves it
+const OPEN_CUTOFF: f32 = 20000.0;
 
 
-	/// pause the sound
-	///
-	/// if the sound is playing, it will pause. if play is called,
-	/// this sound will continue from where it was before pause.
-	/// if the sound is not playing, doesn nothing.
-	pub fn pause (&mut self) {
-		self.mixer.lock().unwrap().pause(self.id);
-	}
 
+/// the number of samples processed per second for a single channel of audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SampleRate(pub u32);
 
-	/// stop the sound
-	///
-	/// if the sound is playing, it will pause and reset the song.
-	/// when play is called, this sound will start from beggining.
-	/// even if the sound is not playing, it will reset the sound.
-	pub fn stop (&mut self) {
-		self.mixer.lock().unwrap().stop(self.id);
+impl SampleRate {
+
+	/// how many frames (samples per channel) last `duration`
+	pub fn frames (&self, duration: Duration) -> u32 {
+		(duration.as_secs_f64() * self.0 as f64).round() as u32
 	}
 
+}
 
-	/// reset the sound to the start
-	///
-	/// the behaviour is the same being the sound playing or not
-	pub fn reset (&mut self) {
-		self.mixer.lock().unwrap().reset(self.id);
-	}
 
 
-	/// set the volume of the sound
-	pub fn set_volume(&mut self, volume: f32) {
-		self.mixer.lock().unwrap().set_volume(self.id, volume);
-	}
+/// a moment of the output of the engine, counted in frames since it
+/// was created, see [`AudioEngine::now`](crate::AudioEngine::now)
+///
+/// a duration can be added to it, to schedule a sound with
+/// [`Sound::play_at`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EngineTime {
 
+	pub frame: u64,
+	/// the sample rate the frames are counted at
+	pub sample_rate: u32
 
-	/// set if the sound will repeat every time it reaches the end
-	pub fn set_loop (&mut self, looping: bool) {
-		self.mixer.lock().unwrap().set_loop(self.id, looping);
-	}
+}
 
+impl EngineTime {
 
-	/// update sound effect
-	pub fn effect (&mut self, effect: impl FnMut(f32) -> f32 + 'static + std::marker::Send) {
-		self.mixer.lock().unwrap().update_effect(self.id, effect);
+	/// how long the engine had played at this time
+	pub fn as_duration (&self) -> Duration {
+		frames_to_duration(self.frame, self.sample_rate.max(1))
 	}
 
-
 }
 
-impl Drop for Sound {
-	fn drop (&mut self) {
-		self.mixer.lock().unwrap().drop_sound(self.id);
+impl std::ops::Add<Duration> for EngineTime {
+	type Output = Self;
+	fn add (self, duration: Duration) -> Self {
+		Self { frame: self.frame + duration_to_frames(duration, self.sample_rate), ..self }
 	}
 }
 
 
 
-/// a source of sound samples
-///
-/// sound samples of each channel must be interleaved
-pub trait SoundSource {
+fn frames_to_duration (frames: u64, sample_rate: u32) -> Duration {
+	Duration::from_secs_f64(frames as f64 / sample_rate as f64)
+}
 
-	/// return the number of channels
-	fn channels (&self) -> u16;
 
-	/// return the sample rate
-	fn sample_rate (&self) -> u32;
+fn duration_to_frames (duration: Duration, sample_rate: u32) -> u64 {
+	(duration.as_secs_f64() * sample_rate as f64).round() as u64
+}
 
-	/// start the sound from the beggining
-	fn reset (&mut self);
 
-	/// write the samples to `buffer`
-	///
-	/// return how many samples was written. if it returns a value
-	/// less than the length of `buffer`, this indicates that the
-	/// sound has ended.
-	///
-	/// the `buffer` length and the returned length should always be
-	/// a multiple of [`self.channels()`](SoundSource::channels).
-	fn write_samples (&mut self, buffer: &mut [i16]) -> usize;
+/// the fnv-1a hash of the name of a tag or a parameter, so the
+/// audio thread compares numbers instead of strings
+pub (crate) fn name_key (name: &str) -> u64 {
+	name.bytes().fold(0xCBF2_9CE4_8422_2325, |hash, x| (hash ^ x as u64).wrapping_mul(0x100_0000_01B3))
+}
+
+
+
+/// represents a sound in the audio engine. if this is dropped,
+/// the sound will continue to play until it ends.
+///
+/// every control call is pushed to the mixer's command queue, so
+/// it never waits on the audio thread. the handle can be cloned and
+/// shared between threads, every clone controls the same sound, and
+/// the sound is freed once the last one is dropped
+///
+/// when the queue is full, like when the audio thread is not
+/// running, a call locks the mixer and applies the queued commands
+/// itself, so it can wait on a buffer being mixed
+#[derive(Clone)]
+pub struct Sound {
+
+	pub (crate) mixer: Arc<Mutex<Mixer>>,
+	pub (crate) commands: Arc<Queue<Command>>,
+	pub (crate) shared: Arc<SoundShared>,
+	pub id: SoundId,
+	pub (crate) owner: Arc<SoundOwner>
 
 }
 
-impl<T: SoundSource + ?Sized> SoundSource for Box<T> {
+impl Sound {
 
-	fn channels (&self) -> u16 {
-		(**self).channels()
-	}
 
-	fn sample_rate (&self) -> u32 {
-		(**self).sample_rate()
+	pub (crate) fn new (mixer: Arc<Mutex<Mixer>>, commands: Arc<Queue<Command>>, shared: Arc<SoundShared>, id: SoundId) -> Self {
+		let owner = Arc::new(SoundOwner { mixer: mixer.clone(), commands: commands.clone(), id });
+		Self { mixer, commands, shared, id, owner }
 	}
 
-	fn reset (&mut self) {
-		(**self).reset()
-	}
 
-	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
-		(**self).write_samples(buffer)
+	/// the sound still exists in the mixer, which it does as long as
+	/// a handle to it is alive, so this is for asserting against bugs
+	///
+	/// locks the mixer, see [`AudioEngine::is_valid`](crate::AudioEngine::is_valid)
+	/// for ids without a handle
+	pub fn is_valid (&self) -> bool {
+		self.mixer.lock().unwrap().is_valid(self.id)
 	}
 
-}
 
+	/// a handle that doesn't keep the sound alive, see [`WeakSound`]
+	pub fn downgrade (&self) -> WeakSound {
+		WeakSound {
+			mixer: self.mixer.clone(),
+			commands: self.commands.clone(),
+			shared: self.shared.clone(),
+			id: self.id,
+			owner: Arc::downgrade(&self.owner)
+		}
+	}
 
-struct SoundInner {
 
-	id: SoundId,
-	data: Box<dyn SoundSource + Send>,
-	volume: f32,
-	looping: bool,
-	drop: bool,
-	effect: Box<dyn FnMut(f32) -> f32 + Send>
+	/// how many frames (samples per channel) of the sound were played
+	/// since its start, at the output sample rate
+	pub fn position_frames (&self) -> u64 {
+		self.shared.position.load(Ordering::Relaxed)
+	}
 
-}
 
-impl SoundInner {
+	/// how much of the sound was played since its start
+	pub fn position (&self) -> Duration {
+		frames_to_duration(self.position_frames(), self.shared.sample_rate.load(Ordering::Relaxed))
+	}
 
-	fn new (data: Box<dyn SoundSource + Send>, effect: impl FnMut(f32) -> f32 + 'static + std::marker::Send) -> Self {
-		Self {
-			id: next_id(),
-			data,
-			volume: 1.0,
-			looping: false,
-			drop: false,
-			effect: Box::new(effect)
+
+	/// the length of the sound in frames (samples per channel), at the
+	/// output sample rate, if its source knows it
+	pub fn duration_frames (&self) -> Option<u64> {
+		match self.shared.total_frames.load(Ordering::Relaxed) {
+			u64::MAX => None,
+			x => Some(x)
 		}
 	}
 
-}
 
+	/// the length of the sound, if its source knows it
+	pub fn duration (&self) -> Option<Duration> {
+		let sample_rate = self.shared.sample_rate.load(Ordering::Relaxed);
+		self.duration_frames().map(|x| frames_to_duration(x, sample_rate))
+	}
 
 
-/// keep track of each Sound, and mix their output together
-pub struct Mixer {
+	/// what the sound is doing
+	///
+	/// commands are applied by the audio thread, so this changes
+	/// shortly after a call like [`Sound::play`], not right away
+	pub fn state (&self) -> PlaybackState {
+		PlaybackState::from_u8(self.shared.state.load(Ordering::Relaxed))
+	}
 
-	sounds: Vec<SoundInner>,
-	playing: usize,
-	pub channels: u16,
-	pub sample_rate: SampleRate
 
-}
+	/// how loud the sound was in the last mixed buffer, after its
+	/// volume, pan and group
+	pub fn levels (&self) -> Levels {
+		self.shared.levels.levels()
+	}
 
-impl Mixer {
 
+	/// the sound is playing, see [`Sound::state`]
+	pub fn is_playing (&self) -> bool {
+		self.state() == PlaybackState::Playing
+	}
 
-	pub fn new (channels: u16, sample_rate: SampleRate) -> Self {
-		Self {
-			sounds: vec![],
-			playing: 0,
-			channels,
-			sample_rate
+
+	/// a future that resolves the next time the sound reaches its
+	/// end or is stopped, after this call
+	///
+	/// ```ignore
+	/// sound.play();
+	/// sound.finished().await;
+	/// ```
+	///
+	/// pausing doesn't count. if the sound isn't played again, the
+	/// future never resolves
+	pub fn finished (&self) -> Finished {
+		Finished {
+			shared: self.shared.clone(),
+			ends: self.shared.ends.load(Ordering::SeqCst)
 		}
 	}
 
 
-	/// change the number of channels and the sample rate
+	/// starts or continue to play the sound
 	///
-	/// this will also keep all currently playing sounds and convert
-	/// them to the new config if necessary
-	pub fn set_config (&mut self, channels: u16, sample_rate: SampleRate) {
-
-		struct Nop;
-		#[rustfmt::skip]
-		impl SoundSource for Nop {
-			fn channels (&self) -> u16 { 0 }
-			fn sample_rate (&self) -> u32 { 0 }
-			fn reset (&mut self) { }
-			fn write_samples (&mut self, _: &mut [i16]) -> usize { 0 }
-		}
+	/// if the sound was paused ot stopped, it will start playing
+	/// again. otherwise, does nothing
+	pub fn play (&mut self) {
+		self.send(Command::Play(self.id));
+	}
 
-		let not_changed = self.channels == channels && self.sample_rate == sample_rate;
-		if not_changed {
-			return;
-		}
-		if !self.sounds.is_empty() {
-			for sound in self.sounds.iter_mut() {
-				// https://github.com/Rodrigodd/audio-engine/blob/3d0da3711b5cc78e7192d616ebb1d4069920707d/src/lib.rs#L200
-				// Beware !! read the link
-				if sound.data.channels() != channels {
-					let inner = std::mem::replace(&mut sound.data, Box::new(Nop));
-					sound.data = Box::new(converter::ChannelConverter::new(inner, channels));
-				}
-				if sound.data.sample_rate() != sample_rate.0 {
-					let inner = std::mem::replace(&mut sound.data, Box::new(Nop));
-					sound.data = Box::new(converter::SampleRateConverter::new(inner, sample_rate.0));
-				}
-			}
-		}
-		self.channels = channels;
-		self.sample_rate = sample_rate;
 
+	/// start the sound on the exact frame of `time`, for rhythm games
+	///
+	/// a time that already passed starts it on the next buffer. a
+	/// sound that is already playing is not moved. playing, pausing
+	/// or stopping the sound cancels it
+	pub fn play_at (&mut self, time: EngineTime) {
+		self.send(Command::PlayAt(self.id, time.frame));
 	}
 
 
-	pub fn add_sound (&mut self, sound: Box<dyn SoundSource + Send>, effect: impl FnMut(f32) -> f32 + 'static + std::marker::Send) -> SoundId {
-		let sound_inner = SoundInner::new(sound, effect);
-		let id = sound_inner.id;
-		self.sounds.push(sound_inner);
-		id
+	/// start the sound `delay` after the next mixed buffer starts,
+	/// on the exact frame
+	///
+	/// same as [`Sound::play_at`] otherwise
+	pub fn play_after (&mut self, delay: Duration) {
+		self.send(Command::PlayAfter(self.id, delay));
 	}
 
 
-	/// if the sound was paused ot stopped, it will start playing
-	/// again. otherwise, does nothing
-	pub fn play (&mut self, id: SoundId) {
-		for i in (self.playing..self.sounds.len()).rev() {
-			if self.sounds[i].id == id {
-				self.sounds.swap(self.playing, i);
-				self.playing += 1;
-				break;
-			}
-		}
+	/// start the sound on the next beat or bar of the
+	/// [`Transport`](crate::Transport), so music enters in time
+	///
+	/// right away if the next buffer starts on it. same as
+	/// [`Sound::play_at`] otherwise
+	pub fn play_quantized (&mut self, quantize: Quantize) {
+		self.send(Command::PlayQuantized(self.id, quantize));
 	}
 
 
+	/// pause the sound
+	///
 	/// if the sound is playing, it will pause. if play is called,
-	/// this sound will continue from where it was when pause.
-	/// if the sound is not playing, does nothing
-	pub fn pause (&mut self, id: SoundId) {
-		for i in (0..self.playing).rev() {
-			if self.sounds[i].id == id {
-				self.playing -= 1;
-				self.sounds.swap(self.playing, i);
-				break;
-			}
-		}
+	/// this sound will continue from where it was before pause.
+	/// if the sound is not playing, doesn nothing.
+	pub fn pause (&mut self) {
+		self.send(Command::Pause(self.id));
 	}
 
 
+	/// stop the sound
+	///
 	/// if the sound is playing, it will pause and reset the song.
-	/// when play is called this sound will start from the beggining
-	/// even if the sound is not playing, it will reset the sound to
-	/// the start
-	pub fn stop (&mut self, id: SoundId) {
-		for i in (0..self.sounds.len()).rev() {
-			if self.sounds[i].id == id {
-				self.sounds[i].data.reset();
-				if i < self.playing {
-					self.playing -= 1;
-					self.sounds.swap(self.playing, i);
-				}
-				break;
-			}
-		}
+	/// when play is called, this sound will start from beggining.
+	/// even if the sound is not playing, it will reset the sound.
+	pub fn stop (&mut self) {
+		self.send(Command::Stop(self.id));
 	}
 
 
-	/// this reset the sound to the start, the sound being played
-	/// or not
-	pub fn reset (&mut self, id: SoundId) {
-		for i in (0..self.sounds.len()).rev() {
-			if self.sounds[i].id == id {
-				self.sounds[i].data.reset();
-				break;
-			}
-		}
+	/// start playing the sound, raising its volume from silence
+	/// over `duration`
+	///
+	/// if the sound is already playing (or fading out), the volume
+	/// is raised from where it is
+	pub fn fade_in (&mut self, duration: Duration) {
+		self.send(Command::FadeIn(self.id, duration));
 	}
 
 
-	/// set the volume of the sound
-	pub fn set_volume (&mut self, id: SoundId, volume: f32) {
-		for i in (0..self.sounds.len()).rev() {
-			if self.sounds[i].id == id {
-				self.sounds[i].volume = volume;
-				break;
-			}
-		}
+	/// lower the volume of the sound to silence over `duration`,
+	/// then pause it
+	///
+	/// when play is called, the sound continues at full volume
+	pub fn fade_out (&mut self, duration: Duration) {
+		self.send(Command::FadeOut(self.id, duration, FadeEnd::Pause));
 	}
 
 
-	/// set if the sound will repeat ever time it reach the end
-	pub fn set_loop (&mut self, id: SoundId, looping: bool) {
-		for i in (0..self.sounds.len()).rev() {
-			if self.sounds[i].id == id {
-				self.sounds[i].looping = looping;
-				break;
-			}
-		}
+	/// lower the volume of the sound to silence over `duration`,
+	/// then stop it
+	pub fn stop_with_fade (&mut self, duration: Duration) {
+		self.send(Command::FadeOut(self.id, duration, FadeEnd::Stop));
 	}
 
 
-	/// mark the sound to be dropped after it reaches the end
-	pub fn drop_sound (&mut self, id: SoundId) {
-		for i in (0..self.sounds.len()).rev() {
-			if self.sounds[i].id == id {
-				self.sounds[i].drop = true;
-				break;
-			}
-		}
+	/// shape the volume of the sound with `envelope` every time it
+	/// starts, or stop using one
+	///
+	/// the attack starts when the sound is played from the start, not
+	/// when it continues after a pause
+	pub fn set_envelope (&mut self, envelope: Option<Envelope>) {
+		self.send(Command::SetEnvelope(self.id, envelope));
 	}
 
 
-	/// update sound effect
-	pub fn update_effect (&mut self, id: SoundId, effect: impl FnMut(f32) -> f32 + 'static + std::marker::Send) {
-		for i in (0..self.sounds.len()).rev() {
-			if self.sounds[i].id == id {
-				self.sounds[i].effect = Box::new(effect);
-				break;
-			}
-		}
+	/// start the release of the envelope, then stop the sound
+	///
+	/// stops the sound right away if it has no envelope
+	pub fn release (&mut self) {
+		self.send(Command::Release(self.id));
 	}
 
 
-}
+	/// reset the sound to the start
+	///
+	/// the behaviour is the same being the sound playing or not
+	pub fn reset (&mut self) {
+		self.send(Command::Reset(self.id));
+	}
 
-impl SoundSource for Mixer {
+
+	/// move the sound to `position` from its start
+	///
+	/// wav and flac sounds seek to the exact frame, other compressed
+	/// formats may land a little before it. does nothing if the
+	/// source can't seek, see [`SoundSource::seek`]
+	pub fn seek_to (&mut self, position: Duration) {
+		self.send(Command::SeekTo(self.id, position));
+	}
 
 
-	fn channels (&self) -> u16 {
-		self.channels
+	/// move the sound forward by `offset` from where it is
+	pub fn seek_by (&mut self, offset: Duration) {
+		self.send(Command::SeekBy(self.id, offset));
 	}
 
 
-	fn sample_rate (&self) -> u32 {
-		self.sample_rate.0
+	/// set the volume of the sound
+	///
+	/// while playing, the volume moves to the new value smoothly, see
+	/// [`AudioEngine::set_volume_smoothing`](crate::AudioEngine::set_volume_smoothing)
+	pub fn set_volume(&mut self, volume: f32) {
+		self.send(Command::SetVolume(self.id, volume));
 	}
 
 
-	fn reset (&mut self) {}
+	/// set the volume of the sound in dB, `0.0` is the full volume and
+	/// [`MIN_DB`](crate::MIN_DB) is silent, see [`db_to_gain`](crate::db_to_gain)
+	pub fn set_volume_db (&mut self, db: f32) {
+		self.set_volume(db_to_gain(db));
+	}
 
 
-	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+	/// set the position of the sound between the left (-1.0) and
+	/// the right (1.0) speakers
+	///
+	/// mono sounds use a constant-power law, stereo sounds are
+	/// balanced by lowering the opposite channel. has no effect if
+	/// the output is mono
+	pub fn set_pan (&mut self, pan: f32) {
+		self.send(Command::SetPan(self.id, pan));
+	}
 
-		if self.playing == 0 {
-			for b in buffer.iter_mut() {
-				*b = 0;
-			}
-			return buffer.len();
-		}
 
-		let mut buf = vec![0; buffer.len()];
-		let mut s = 0;
-		while s < self.playing {
-			let mut len = 0;
-			loop {
-				len += self.sounds[s].data.write_samples(&mut buf[len..]);
-				if len < buffer.len() {
-					self.sounds[s].data.reset();
-					if self.sounds[s].looping {
-						continue;
-					}
-				}
+	/// the gain of each channel of the output, in its order, like
+	/// `&[1.0, 0.0]` to only play on the left. channels past the end
+	/// are at `1.0`
+	///
+	/// applied on top of the volume and pan, and smoothed like the
+	/// volume. see [`ChannelMap`](crate::ChannelMap) to route the
+	/// channels of the source instead
+	pub fn set_channel_gains (&mut self, gains: &[f32]) {
+		self.send(Command::SetChannelGains(self.id, gains.to_vec()));
+	}
+
+
+	/// set the playback speed of the sound
+	///
+	/// the sound is resampled on the fly, so this changes both the
+	/// pitch and the tempo. 2.0 plays one octave higher
+	pub fn set_speed (&mut self, speed: f32) {
+		self.send(Command::SetSpeed(self.id, speed));
+	}
+
+
+	/// move the volume to `target` over `duration`, along `easing`
+	///
+	/// the change is done on the audio thread, frame by frame, and
+	/// only moves while the sound plays. a later volume change
+	/// replaces it
+	pub fn animate_volume (&mut self, target: f32, duration: Duration, easing: Easing) {
+		self.send(Command::AnimateVolume(self.id, target, duration, easing));
+	}
+
+
+	/// move the volume to `target` dB, like [`Sound::animate_volume`]
+	///
+	/// the gain moves along `easing`, not the dB, so a fade out with
+	/// `Easing::Linear` drops slowly and then fast, as most fades should
+	pub fn animate_volume_db (&mut self, target: f32, duration: Duration, easing: Easing) {
+		self.animate_volume(db_to_gain(target), duration, easing);
+	}
+
+
+	/// move the pan to `target` over `duration`, along `easing`
+	pub fn animate_pan (&mut self, target: f32, duration: Duration, easing: Easing) {
+		self.send(Command::AnimatePan(self.id, target, duration, easing));
+	}
+
+
+	/// move the speed, and so the pitch, to `target` over `duration`,
+	/// along `easing`
+	pub fn animate_speed (&mut self, target: f32, duration: Duration, easing: Easing) {
+		self.send(Command::AnimateSpeed(self.id, target, duration, easing));
+	}
+
+
+	/// place the sound at `position` around the listener, or make it
+	/// play the same wherever the listener is with `None`
+	///
+	/// a placed sound gets quieter with its distance and is panned to
+	/// its side, on top of its own volume and pan. see
+	/// [`AudioEngine::listener`](crate::AudioEngine::listener)
+	pub fn set_position (&mut self, position: Option<[f32; 3]>) {
+		self.send(Command::SetPosition(self.id, position));
+	}
+
+
+	/// muffle the sound as if it was behind a wall, from `0.0`, not
+	/// occluded, to `1.0`, fully occluded
+	///
+	/// lowers the cutoff of a low pass and the volume together. meant
+	/// to be set from the raycasts of the game, changes are smoothed
+	/// like the volume
+	pub fn set_occlusion (&mut self, occlusion: f32) {
+		self.send(Command::SetOcclusion(self.id, occlusion));
+	}
+
+
+	/// the speed of a placed sound, in units per second, for its
+	/// doppler shift
+	///
+	/// does nothing until the sound is placed, see
+	/// [`Listener::set_doppler_factor`](crate::Listener::set_doppler_factor)
+	pub fn set_velocity (&mut self, velocity: [f32; 3]) {
+		self.send(Command::SetVelocity(self.id, velocity));
+	}
+
+
+	/// how the sound gets quieter with its distance to the listener,
+	/// once it is placed with [`Sound::set_position`]
+	pub fn set_attenuation (&mut self, attenuation: Attenuation) {
+		self.send(Command::SetAttenuation(self.id, attenuation));
+	}
+
+
+	/// move the volume, pan or pitch of the sound with `lfo`, or stop
+	/// moving it
+	///
+	/// each target has its own lfo, setting one replaces the last.
+	/// the lfo moves around the value set by the other calls
+	pub fn set_lfo (&mut self, target: LfoTarget, lfo: Option<Lfo>) {
+		self.send(Command::SetLfo(self.id, target, lfo));
+	}
+
+
+	/// set if the sound will repeat every time it reaches the end
+	pub fn set_loop (&mut self, looping: bool) {
+		self.send(Command::SetLoop(self.id, looping));
+	}
+
+
+	/// change how fast the sound plays without changing its pitch,
+	/// `0.5` is half as fast, from `0.25` to `4.0`
+	///
+	/// unlike [`Sound::set_speed`], which changes both, like a tape.
+	/// both can be used together. it is made for music and voices, a
+	/// few milliseconds of attack may be smeared
+	pub fn set_tempo (&mut self, tempo: f32) {
+		self.send(Command::SetSoundTempo(self.id, tempo));
+	}
+
+
+	/// shift the pitch of the sound without changing how fast it
+	/// plays, from `-12.0` to `12.0` semitones
+	///
+	/// for key changes and voice effects. it is played faster or
+	/// slower, and stretched back to its tempo, see
+	/// [`Sound::set_tempo`]
+	pub fn set_pitch_semitones (&mut self, semitones: f32) {
+		self.send(Command::SetPitch(self.id, semitones));
+	}
+
+
+	/// play the sound forward, or backward from where it is
+	///
+	/// a sound that is stopped or finished plays backward from its
+	/// end. only sources decoded in memory can play backward, like a
+	/// [`SoundData`](crate::SoundData), others keep playing forward.
+	/// the loop region is only used forward
+	pub fn set_direction (&mut self, direction: Direction) {
+		self.send(Command::SetDirection(self.id, direction));
+	}
+
+
+	/// when the sound loops, play it backward from its end, then
+	/// forward from its start, in turn
+	///
+	/// every turn is a [`SoundEvent::Looped`]. needs a source that can
+	/// play backward, see [`Sound::set_direction`]
+	pub fn set_ping_pong (&mut self, ping_pong: bool) {
+		self.send(Command::SetPingPong(self.id, ping_pong));
+	}
+
+
+	/// how much the sound matters when too many play, `128` by
+	/// default, higher is more important
+	///
+	/// a new sound only steals one with the same or a lower priority,
+	/// the lowest first. with [`StealPolicy::Virtualize`], the sounds
+	/// with the highest priority, then the loudest, are the ones heard
+	pub fn set_priority (&mut self, priority: u8) {
+		self.send(Command::SetPriority(self.id, priority));
+	}
+
+
+	/// mark the sound with `tag`, replacing its last one, so it can be
+	/// paused, stopped or turned down with the other sounds of the
+	/// tag, see [`AudioEngine::pause_tagged`](crate::AudioEngine::pause_tagged)
+	pub fn set_tag (&mut self, tag: &str) {
+		self.send(Command::SetTag(self.id, Some(name_key(tag))));
+	}
+
+
+	/// remove the tag of the sound
+	pub fn clear_tag (&mut self) {
+		self.send(Command::SetTag(self.id, None));
+	}
+
+
+	/// follow the game parameter `name` with `target`, through `curve`,
+	/// values of the target at values of the parameter in order of the
+	/// parameter. replaces the last curve of the parameter and target,
+	/// and an empty curve stops following it
+	///
+	/// ```
+	/// # use audio_engine::{ AudioEngine, ParameterTarget, SineWave };
+	/// # let (engine, _backend) = AudioEngine::offline(2, 48000);
+	/// # let mut music = engine.new_sound(SineWave::new(220.0), |x| x)?;
+	/// // quieter and darker while the fight is calm
+	/// music.map_parameter("tension", ParameterTarget::Volume, &[(0.0, 0.4), (1.0, 1.0)]);
+	/// music.map_parameter("tension", ParameterTarget::Cutoff, &[(0.0, 1200.0), (0.6, 20000.0)]);
+	/// engine.set_parameter("tension", 0.7);
+	/// # Ok::<(), audio_engine::Error>(())
+	/// ```
+	pub fn map_parameter (&mut self, name: &str, target: ParameterTarget, curve: &[(f32, f32)]) {
+		let map = ParameterMap { key: name_key(name), target, curve: curve.to_vec() };
+		self.send(Command::MapParameter(self.id, map));
+	}
+
+
+	/// repeat the sound `count` times, so it plays `count + 1` times,
+	/// then let it end
+	///
+	/// every repeat is a [`SoundEvent::Looped`]. the count starts again
+	/// every time the sound plays from the start. with a loop region,
+	/// the region repeats instead, and the sound plays on to its end.
+	/// [`Sound::set_loop`] repeats it forever again
+	pub fn set_loop_count (&mut self, count: u32) {
+		self.send(Command::SetLoopCount(self.id, count));
+	}
+
+
+	/// play the sound from the start, then repeat it from `start` to
+	/// `end`, like music with an intro
+	///
+	/// wav files with a loop in their `smpl` or `cue` chunk get it
+	/// when they are created. the jump back is seamless on sources
+	/// that seek to the exact frame, like [`WavDecoder`](crate::WavDecoder)
+	/// or a [`SoundData`](crate::SoundData). every jump is a
+	/// [`SoundEvent::Looped`]. an `end` past the end of the sound is
+	/// the end of the sound
+	pub fn set_loop_region (&mut self, start: Duration, end: Duration) {
+		self.send(Command::SetLoopRegion(self.id, Some((start, end))));
+	}
+
+
+	/// stop repeating the loop region, the sound plays on to its end
+	/// from where it is, like into an outro
+	pub fn clear_loop_region (&mut self) {
+		self.send(Command::SetLoopRegion(self.id, None));
+	}
+
+
+	/// update sound effect
+	pub fn effect (&mut self, effect: impl FnMut(f32) -> f32 + 'static + std::marker::Send) {
+		self.set_effect(effect);
+	}
+
+
+	/// replace every effect of the sound with `effect`, see [`Effect`]
+	pub fn set_effect (&mut self, effect: impl Effect + 'static) {
+		self.send(Command::SetEffect(self.id, Box::new(effect)));
+	}
+
+
+	/// add `effect` at the end of the chain of the sound
+	///
+	/// the effects are applied in the order they were added, each one
+	/// to the output of the one before
+	pub fn add_effect (&mut self, effect: impl Effect + 'static) -> EffectId {
+		let id = EffectId::next();
+		self.send(Command::AddEffect(self.id, id, Box::new(effect)));
+		id
+	}
+
+
+	/// remove an effect added with [`Sound::add_effect`]
+	pub fn remove_effect (&mut self, effect: EffectId) {
+		self.send(Command::RemoveEffect(self.id, effect));
+	}
+
+
+	/// remove every effect of the sound
+	pub fn clear_effects (&mut self) {
+		self.send(Command::ClearEffects(self.id));
+	}
+
+
+	/// also play the sound on `group`, at `level` of its volume
+	///
+	/// the sound keeps playing on its own group. this is how many
+	/// sounds share one effect, like a reverb. a level of `0.0`
+	/// removes the send. fails if the group is of another engine
+	pub fn set_send (&mut self, group: &Group, level: f32) -> Result<(), Error> {
+		if !Arc::ptr_eq(&self.mixer, &group.mixer) {
+			return Err(Error::InvalidGroup);
+		}
+		self.send(Command::SetSend(self.id, group.id, level));
+		Ok(())
+	}
+
+
+	/// send the sound to the shared reverb, see
+	/// [`AudioEngine::reverb`](crate::AudioEngine::reverb)
+	///
+	/// the first send locks the mixer, to create the reverb
+	pub fn set_reverb_send (&mut self, level: f32) {
+		let group = self.mixer.lock().unwrap().reverb_group();
+		self.send(Command::SetSend(self.id, group, level));
+	}
+
+
+	fn send (&self, command: Command) {
+		send(&self.mixer, &self.commands, command);
+	}
+
+
+}
+
+/// shared by the clones of a [`Sound`], frees the sound when the
+/// last one is dropped
+pub struct SoundOwner {
+	mixer: Arc<Mutex<Mixer>>,
+	commands: Arc<Queue<Command>>,
+	id: SoundId
+}
+
+impl Drop for SoundOwner {
+	fn drop (&mut self) {
+		send(&self.mixer, &self.commands, Command::Drop(self.id));
+	}
+}
+
+
+
+/// a handle to a sound that doesn't keep it alive, made with
+/// [`Sound::downgrade`]
+///
+/// for systems that control a sound owned by another one, like a
+/// footstep system and the sound of a character. once every
+/// [`Sound`] of it is dropped, it can't control the sound, even if
+/// the sound still plays to its end
+#[derive(Clone)]
+pub struct WeakSound {
+	mixer: Arc<Mutex<Mixer>>,
+	commands: Arc<Queue<Command>>,
+	shared: Arc<SoundShared>,
+	id: SoundId,
+	owner: Weak<SoundOwner>
+}
+
+impl WeakSound {
+
+
+	/// a handle to the sound, if one of its [`Sound`]s is still alive
+	pub fn upgrade (&self) -> Option<Sound> {
+		Some(Sound {
+			owner: self.owner.upgrade()?,
+			mixer: self.mixer.clone(),
+			commands: self.commands.clone(),
+			shared: self.shared.clone(),
+			id: self.id
+		})
+	}
+
+
+	pub fn id (&self) -> SoundId {
+		self.id
+	}
+
+
+	/// the state of the sound, also after it was freed
+	pub fn state (&self) -> PlaybackState {
+		PlaybackState::from_u8(self.shared.state.load(Ordering::Relaxed))
+	}
+
+
+}
+
+
+
+/// a group of sounds, like a mix bus
+///
+/// the volume, mute and pause of a group apply to every sound in it
+/// and to its subgroups, so the volumes of nested groups multiply.
+/// groups live as long as the engine
+pub struct Group {
+
+	pub (crate) mixer: Arc<Mutex<Mixer>>,
+	pub (crate) commands: Arc<Queue<Command>>,
+	pub id: GroupId
+
+}
+
+impl Group {
+
+
+	/// create a group inside this one
+	pub fn create_subgroup (&self, name: &str) -> Group {
+		let id = self.mixer.lock().unwrap().add_group(name, Some(self.id));
+		Group {
+			mixer: self.mixer.clone(),
+			commands: self.commands.clone(),
+			id
+		}
+	}
+
+
+	/// set the volume of the group
+	///
+	/// smoothed like the volume of a sound
+	pub fn set_volume (&mut self, volume: f32) {
+		send(&self.mixer, &self.commands, Command::SetGroupVolume(self.id, volume));
+	}
+
+
+	/// set the volume of the group in dB, like [`Sound::set_volume_db`]
+	pub fn set_volume_db (&mut self, db: f32) {
+		self.set_volume(db_to_gain(db));
+	}
+
+
+	/// silence the group, without changing its volume
+	pub fn set_muted (&mut self, muted: bool) {
+		send(&self.mixer, &self.commands, Command::SetGroupMuted(self.id, muted));
+	}
+
+
+	/// follow the game parameter `name` with `target`, like
+	/// [`Sound::map_parameter`]
+	///
+	/// the pitch changes every sound of the group, and the cutoff moves
+	/// the low pass that the snapshots also move
+	pub fn map_parameter (&mut self, name: &str, target: ParameterTarget, curve: &[(f32, f32)]) {
+		let map = ParameterMap { key: name_key(name), target, curve: curve.to_vec() };
+		send(&self.mixer, &self.commands, Command::MapGroupParameter(self.id, map));
+	}
+
+
+	/// pause every sound of the group where it is
+	///
+	/// the sounds keep their own playing state, so a sound that was
+	/// paused before stays paused after `resume`
+	pub fn pause (&mut self) {
+		send(&self.mixer, &self.commands, Command::SetGroupPaused(self.id, true));
+	}
+
+
+	/// continue the sounds paused by [`Group::pause`]
+	pub fn resume (&mut self) {
+		send(&self.mixer, &self.commands, Command::SetGroupPaused(self.id, false));
+	}
+
+
+	/// add `effect` at the end of the chain of the group
+	///
+	/// a group with effects is mixed on its own, with its sounds,
+	/// subgroups and sends, and then goes through the effects
+	pub fn add_effect (&mut self, effect: impl Effect + 'static) -> EffectId {
+		let id = EffectId::next();
+		send(&self.mixer, &self.commands, Command::AddGroupEffect(self.id, id, Box::new(effect)));
+		id
+	}
+
+
+	/// remove an effect added with [`Group::add_effect`]
+	pub fn remove_effect (&mut self, effect: EffectId) {
+		send(&self.mixer, &self.commands, Command::RemoveGroupEffect(self.id, effect));
+	}
+
+
+	/// remove every effect of the group
+	pub fn clear_effects (&mut self) {
+		send(&self.mixer, &self.commands, Command::ClearGroupEffects(self.id));
+	}
+
+
+}
+
+
+
+/// push a command to the mixer
+///
+/// if the queue is full (the audio thread is probably not running),
+/// the mixer is locked and the queue is drained here, so no command
+/// is lost and the order is kept. this is logged once, with a
+/// running audio thread it means the commands come faster than the
+/// buffers
+pub fn send (mixer: &Mutex<Mixer>, commands: &Queue<Command>, command: Command) {
+	static WARNED: AtomicBool = AtomicBool::new(false);
+	if let Err(command) = commands.push(command) {
+		if !WARNED.swap(true, Ordering::Relaxed) {
+			warn!("the command queue is full, the mixer is locked to apply the commands");
+		}
+		let mut mixer = mixer.lock().unwrap();
+		mixer.process_commands();
+		mixer.apply(command);
+	}
+}
+
+
+
+/// a change to a sound or to the whole mix, sent from a [`Sound`]
+/// or the engine to the [`Mixer`]
+pub enum Command {
+	Play(SoundId),
+	PlayAt(SoundId, u64),
+	PlayAfter(SoundId, Duration),
+	PlayQuantized(SoundId, Quantize),
+	Pause(SoundId),
+	Stop(SoundId),
+	FadeIn(SoundId, Duration),
+	FadeOut(SoundId, Duration, FadeEnd),
+	Crossfade(SoundId, SoundId, Duration),
+	SetEnvelope(SoundId, Option<Envelope>),
+	Release(SoundId),
+	Reset(SoundId),
+	SeekTo(SoundId, Duration),
+	SeekBy(SoundId, Duration),
+	SetVolume(SoundId, f32),
+	SetPan(SoundId, f32),
+	SetChannelGains(SoundId, Vec<f32>),
+	SetSpeed(SoundId, f32),
+	SetSoundTempo(SoundId, f32),
+	SetPitch(SoundId, f32),
+	AnimateVolume(SoundId, f32, Duration, Easing),
+	AnimatePan(SoundId, f32, Duration, Easing),
+	AnimateSpeed(SoundId, f32, Duration, Easing),
+	SetLfo(SoundId, LfoTarget, Option<Lfo>),
+	SetPosition(SoundId, Option<[f32; 3]>),
+	SetAttenuation(SoundId, Attenuation),
+	SetVelocity(SoundId, [f32; 3]),
+	SetOcclusion(SoundId, f32),
+	SetListenerPosition([f32; 3]),
+	SetListenerVelocity([f32; 3]),
+	SetDopplerFactor(f32),
+	SetHrtf(Option<Box<Hrtf>>),
+	SetAmbisonics(bool),
+	SetListenerOrientation([f32; 3], [f32; 3]),
+	SetLoop(SoundId, bool),
+	SetLoopCount(SoundId, u32),
+	SetDirection(SoundId, Direction),
+	SetPingPong(SoundId, bool),
+	SetPriority(SoundId, u8),
+	SetTag(SoundId, Option<u64>),
+	PauseTagged(u64),
+	ResumeTagged(u64),
+	StopTagged(u64),
+	SetVolumeTagged(u64, f32),
+	MapParameter(SoundId, ParameterMap),
+	SetLoopRegion(SoundId, Option<(Duration, Duration)>),
+	SetEffect(SoundId, Box<dyn Effect>),
+	AddEffect(SoundId, EffectId, Box<dyn Effect>),
+	RemoveEffect(SoundId, EffectId),
+	ClearEffects(SoundId),
+	Drop(SoundId),
+	SetMasterVolume(f32),
+	SetMuted(bool),
+	SetDither(bool),
+	SetMono(bool),
+	SetMaxVoices(Option<(u32, StealPolicy)>),
+	PauseAll(Vec<GroupId>),
+	ResumeAll,
+	SetLimiter(Option<LimiterConfig>),
+	TransitionToSnapshot(usize, Duration),
+	SetParameter(u64, f32),
+	SetGroupVolume(GroupId, f32),
+	SetGroupMuted(GroupId, bool),
+	SetGroupPaused(GroupId, bool),
+	AddGroupEffect(GroupId, EffectId, Box<dyn Effect>),
+	RemoveGroupEffect(GroupId, EffectId),
+	ClearGroupEffects(GroupId),
+	MapGroupParameter(GroupId, ParameterMap),
+	SetSend(SoundId, GroupId, f32),
+	AddMasterEffect(EffectId, Box<dyn Effect>),
+	RemoveMasterEffect(EffectId),
+	ClearMasterEffects,
+	Duck(GroupId, GroupId, f32, Duration, Duration),
+	StopDucking(GroupId, GroupId),
+	SetTempo(f32),
+	SetTimeSignature(u32, u32),
+	RestartTransport
+}
+
+
+
+/// something that happened to a sound while it was played, read
+/// with [`AudioEngine::events`](crate::AudioEngine::events)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundEvent {
+	/// a sound that is not looping reached its end
+	Ended(SoundId),
+	/// a looping sound, or its loop region, reached its end and
+	/// started again
+	Looped(SoundId)
+}
+
+
+
+/// which way a sound plays, see [`Sound::set_direction`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+	Forward,
+	Reverse
+}
+
+
+
+/// which sound is stopped when too many play, see
+/// [`SoundData::max_instances`](crate::SoundData::max_instances) and
+/// [`AudioEngine::set_max_voices`](crate::AudioEngine::set_max_voices)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StealPolicy {
+	/// the one that started first
+	Oldest,
+	/// the one that was quietest in the last buffer
+	Quietest,
+	/// none, the new sound doesn't start
+	RejectNew,
+	/// none, the least important sounds over the limit are virtual:
+	/// they move on without being decoded or heard, and are heard
+	/// again once there is room
+	///
+	/// sounds too quiet to be heard, by their volume, distance,
+	/// occlusion or group, are virtual whatever the policy. a sound
+	/// with an envelope, a fade, or that plays backward, is never
+	/// virtual, nor one from a source without a length
+	Virtualize
+}
+
+
+
+/// how many sounds of the same samples play at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstanceLimit {
+	/// the samples, by address
+	pub (crate) key: usize,
+	pub max: u32,
+	pub policy: StealPolicy
+}
+
+
+
+/// what happens to a sound once a fade out finishes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FadeEnd {
+	Pause,
+	Stop
+}
+
+
+
+/// what a sound is doing, see [`Sound::state`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+	/// also while its group is paused
+	Playing,
+	Paused,
+	/// stopped, or never played yet
+	Stopped,
+	/// reached its end without looping
+	Finished
+}
+
+impl PlaybackState {
+
+	fn from_u8 (x: u8) -> Self {
+		match x {
+			0 => Self::Playing,
+			1 => Self::Paused,
+			2 => Self::Stopped,
+			_ => Self::Finished
+		}
+	}
+
+}
+
+
+
+/// state of a sound written by the audio thread, and read by its
+/// [`Sound`] handle without locking
+pub struct SoundShared {
+
+	/// frames of the sound played since its start
+	position: AtomicU64,
+	/// length of the sound in frames, `u64::MAX` if unknown
+	total_frames: AtomicU64,
+	/// sample rate of `position` and `total_frames`
+	sample_rate: AtomicU32,
+	/// a `PlaybackState`
+	state: AtomicU8,
+	/// how many times the sound ended or was stopped
+	ends: AtomicU32,
+	/// tasks waiting on a [`Finished`] future
+	wakers: Mutex<Vec<Waker>>,
+	/// the levels of the last buffer, zero when not playing
+	levels: Meter
+
+}
+
+impl SoundShared {
+
+	fn new (total_frames: Option<u64>, sample_rate: u32) -> Self {
+		Self {
+			position: AtomicU64::new(0),
+			total_frames: AtomicU64::new(total_frames.unwrap_or(u64::MAX)),
+			sample_rate: AtomicU32::new(sample_rate),
+			state: AtomicU8::new(PlaybackState::Stopped as u8),
+			ends: AtomicU32::new(0),
+			wakers: Mutex::new(vec![]),
+			levels: Meter::new()
+		}
+	}
+
+
+	/// resolve the [`Finished`] futures of the sound
+	///
+	/// called from the audio thread, so this never waits on the
+	/// lock. if the lock is taken, it is by a future that checks
+	/// `ends` again after adding its waker
+	fn end (&self) {
+		self.ends.fetch_add(1, Ordering::SeqCst);
+		if let Ok(mut wakers) = self.wakers.try_lock() {
+			for waker in wakers.drain(..) {
+				waker.wake();
+			}
+		}
+	}
+
+}
+
+
+
+/// resolves when a sound ends, see [`Sound::finished`]
+pub struct Finished {
+	shared: Arc<SoundShared>,
+	/// `SoundShared::ends` when the future was created
+	ends: u32
+}
+
+impl Future for Finished {
+
+	type Output = ();
+
+	fn poll (self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+		let ended = || self.shared.ends.load(Ordering::SeqCst) != self.ends;
+		if ended() {
+			return Poll::Ready(());
+		}
+		{
+			let mut wakers = self.shared.wakers.lock().unwrap();
+			if !wakers.iter().any(|x| x.will_wake(cx.waker())) {
+				wakers.push(cx.waker().clone());
+			}
+		}
+		// the sound may have ended while the waker was added
+		if ended() { Poll::Ready(()) } else { Poll::Pending }
+	}
+
+}
+
+
+
+/// a source of sound samples
+///
+/// sound samples of each channel must be interleaved
+pub trait SoundSource {
+
+	/// return the number of channels
+	fn channels (&self) -> u16;
+
+	/// return the sample rate
+	fn sample_rate (&self) -> u32;
+
+	/// start the sound from the beggining
+	fn reset (&mut self);
+
+	/// write the samples to `buffer`
+	///
+	/// return how many samples was written. if it returns a value
+	/// less than the length of `buffer`, this indicates that the
+	/// sound has ended.
+	///
+	/// the `buffer` length and the returned length should always be
+	/// a multiple of [`self.channels()`](SoundSource::channels).
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize;
+
+	/// return the length of the sound in frames (samples per
+	/// channel), if it is known
+	fn total_frames (&self) -> Option<u64> {
+		None
+	}
+
+	/// move to `frame` frames from the start of the sound
+	///
+	/// return false if the source can't seek, in which case nothing
+	/// changes. compressed sources may land on the closest frame
+	/// they can start decoding from. if seeking fails for any other
+	/// reason, the sound should end, like on a decoding error
+	fn seek (&mut self, _frame: u64) -> bool {
+		false
+	}
+
+	/// return the frames the sound loops between after its intro, if
+	/// the source knows them, as a start and an exclusive end
+	///
+	/// see [`Sound::set_loop_region`]
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		None
+	}
+
+	/// play the sound forward or backward from where it is
+	///
+	/// return false if the source can't play backward, in which case
+	/// nothing changes. backward, [`reset`](SoundSource::reset) moves
+	/// to the end, and [`seek`](SoundSource::seek) plays the frames
+	/// before `frame`
+	fn set_direction (&mut self, _direction: Direction) -> bool {
+		false
+	}
+
+	/// return how many sounds of the same samples may play at once,
+	/// see [`SoundData::max_instances`](crate::SoundData::max_instances)
+	fn instance_limit (&self) -> Option<InstanceLimit> {
+		None
+	}
+
+}
+
+impl<T: SoundSource + ?Sized> SoundSource for Box<T> {
+
+	fn channels (&self) -> u16 {
+		(**self).channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		(**self).sample_rate()
+	}
+
+	fn reset (&mut self) {
+		(**self).reset()
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		(**self).write_samples(buffer)
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		(**self).total_frames()
+	}
+
+	fn seek (&mut self, frame: u64) -> bool {
+		(**self).seek(frame)
+	}
+
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		(**self).loop_region()
+	}
+
+	fn set_direction (&mut self, direction: Direction) -> bool {
+		(**self).set_direction(direction)
+	}
+
+	fn instance_limit (&self) -> Option<InstanceLimit> {
+		(**self).instance_limit()
+	}
+
+}
+
+
+
+/// the speed and loop region of a sound, read by its stretcher
+struct Resampled<'a>(&'a mut SoundInner);
+
+impl SoundSource for Resampled<'_> {
+
+	fn channels (&self) -> u16 {
+		self.0.data.channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.0.data.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.0.reset();
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		self.0.write_resampled(buffer)
+	}
+
+}
+
+
+
+/// the part of a sound that repeats after its intro
+struct LoopRegion {
+	start: u64,
+	end: u64,
+	/// the frame of the source that is read next
+	read: u64,
+	/// frames that were played again, taken off the position
+	looped: u64,
+	/// times it went back to `start` since the last buffer
+	loops: u32
+}
+
+impl LoopRegion {
+
+	fn new (start: u64, end: u64, read: u64) -> Option<Self> {
+		(end > start).then_some(Self { start, end, read, looped: 0, loops: 0 })
+	}
+
+}
+
+
+
+/// reads a source through its loop region, so the resampler
+/// interpolates across the jump back like across any other frame
+struct RegionSource<'a> {
+	data: &'a mut (dyn SoundSource + Send),
+	region: &'a mut LoopRegion,
+	/// the repeats left, see `SoundInner::loops_left`
+	loops_left: &'a mut Option<u32>
+}
+
+impl SoundSource for RegionSource<'_> {
+
+	fn channels (&self) -> u16 {
+		self.data.channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.data.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.data.reset();
+		self.region.read = 0;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let channels = self.data.channels().max(1) as usize;
+		let region = &mut *self.region;
+		let mut len = 0;
+		let mut jumped = false;
+		while len < buffer.len() {
+			// past the end of the region, after a seek or the last
+			// repeat, it plays on to the end of the sound
+			let inside = region.read < region.end && *self.loops_left != Some(0);
+			let mut asked = buffer.len() - len;
+			if inside {
+				asked = (region.end - region.read).min((asked / channels) as u64) as usize * channels;
+			}
+			let written = self.data.write_samples(&mut buffer[len..len + asked]);
+			len += written;
+			region.read += (written / channels) as u64;
+			if !inside || (written == 0 && jumped) {
+				break;
+			}
+			jumped = false;
+			// the sound may end before the region does
+			if written < asked || region.read >= region.end {
+				if !self.data.seek(region.start) {
+					break;
+				}
+				region.looped += region.read - region.start;
+				region.read = region.start;
+				region.loops += 1;
+				*self.loops_left = self.loops_left.map(|x| x - 1);
+				jumped = true;
+			}
+		}
+		len
+	}
+
+}
+
+
+struct SoundInner {
+
+	data: Box<dyn SoundSource + Send>,
+	shared: Arc<SoundShared>,
+	/// changes the playback speed of `data`
+	resampler: converter::Resampler,
+	/// the source was mono before being converted to the output
+	/// channels, which changes how it is panned
+	mono: bool,
+	/// smoothed, so changes don't click
+	volume: Ramp,
+	/// gain of the current fade, 1.0 when not fading
+	fade: Ramp,
+	/// set while fading out
+	fade_end: Option<FadeEnd>,
+	envelope: Option<EnvelopeState>,
+	pan: Ramp,
+	/// the gain of each output channel, empty until they are set
+	channel_gains: Vec<Ramp>,
+	/// given to `resampler` every few frames while it moves
+	speed: Ramp,
+	/// made the first time the tempo changes from `1.0`, and kept
+	/// until the sound moves at that tempo again
+	stretcher: Option<Box<Stretcher>>,
+	tempo: f32,
+	/// in semitones, played faster by the resampler and stretched back
+	pitch: f32,
+	/// at most one for each target
+	lfos: Vec<(LfoTarget, Lfo)>,
+	/// set for sounds placed around the listener
+	emitter: Option<Emitter>,
+	attenuation: Attenuation,
+	/// made the first time the sound is occluded
+	occlusion: Option<Occlusion>,
+	/// the game parameters the sound follows, with what they give it
+	parameter_maps: Vec<ParameterMap>,
+	parameter_gain: Ramp,
+	parameter_speed: f32,
+	/// made the first time a parameter moves its cutoff
+	low_pass: Option<Biquad>,
+	/// the speed the parameters give its groups, set every buffer
+	group_speed: f32,
+	group: Option<GroupId>,
+	looping: bool,
+	/// the repeats of a sound looped a number of times, and the
+	/// repeats left, `None` for forever
+	loop_count: Option<u32>,
+	loops_left: Option<u32>,
+	loop_region: Option<LoopRegion>,
+	/// the direction set on the sound, a ping-pong loop turns it
+	direction: Direction,
+	ping_pong: bool,
+	/// set while the sound plays backward, the position it started
+	/// from, which the resampler counts up from
+	reversed_at: Option<u64>,
+	drop: bool,
+	/// applied in order, the ids are only set for effects added by
+	/// `Sound::add_effect`
+	effects: Vec<(Option<EffectId>, Box<dyn Effect>)>,
+	/// groups that also get the sound, with the level
+	sends: Vec<(GroupId, f32)>,
+	/// position of this sound in `Mixer::playing`, if it is playing
+	playing: Option<usize>,
+	instance_limit: Option<InstanceLimit>,
+	/// the clock when the sound last started playing
+	started: u64,
+	priority: u8,
+	/// the key of the tag, see `name_key`
+	tag: Option<u64>,
+	/// set while the sound is virtual, the frame of the source it is
+	/// at, which moves on without the source being read
+	virtual_at: Option<f64>,
+	/// frames of silence before the sound starts, in the next buffer
+	offset: usize
+
+}
+
+impl SoundInner {
+
+	fn new (data: Box<dyn SoundSource + Send>, mono: bool, effect: impl Effect + 'static, quality: ResampleQuality) -> Self {
+		let loop_region = data.loop_region().and_then(|(start, end)| LoopRegion::new(start, end, 0));
+		let instance_limit = data.instance_limit();
+		Self {
+			shared: Arc::new(SoundShared::new(data.total_frames(), data.sample_rate())),
+			resampler: converter::Resampler::new(data.channels(), quality),
+			data,
+			mono,
+			volume: Ramp::new(1.0),
+			fade: Ramp::new(1.0),
+			fade_end: None,
+			envelope: None,
+			pan: Ramp::new(0.0),
+			channel_gains: vec![],
+			speed: Ramp::new(1.0),
+			stretcher: None,
+			tempo: 1.0,
+			pitch: 0.0,
+			lfos: vec![],
+			emitter: None,
+			attenuation: Attenuation::default(),
+			occlusion: None,
+			parameter_maps: vec![],
+			parameter_gain: Ramp::new(1.0),
+			parameter_speed: 1.0,
+			low_pass: None,
+			group_speed: 1.0,
+			group: None,
+			looping: false,
+			loop_count: None,
+			loops_left: None,
+			loop_region,
+			direction: Direction::Forward,
+			ping_pong: false,
+			reversed_at: None,
+			drop: false,
+			effects: vec![(None, Box::new(effect))],
+			sends: vec![],
+			playing: None,
+			instance_limit,
+			started: 0,
+			priority: DEFAULT_PRIORITY,
+			tag: None,
+			virtual_at: None,
+			offset: 0
+		}
+	}
+
+
+	/// start the sound from the beggining
+	fn reset (&mut self) {
+		self.virtual_at = None;
+		self.data.reset();
+		self.resampler.reset();
+		self.reset_stretcher();
+		if let Some(region) = self.loop_region.as_mut() {
+			region.read = 0;
+			region.looped = 0;
+		}
+		if self.reversed_at.is_some() {
+			let end = self.data.total_frames().unwrap_or(0);
+			self.resampler.seek(end);
+			self.reversed_at = Some(end);
+		}
+		self.update_position();
+	}
+
+
+	/// move the sound to `frame`, if its source can seek
+	fn seek (&mut self, frame: u64) {
+		let frame = match self.data.total_frames() {
+			Some(total) => frame.min(total),
+			None => frame
+		};
+		if self.virtual_at.is_some() {
+			self.virtual_at = Some(frame as f64);
+			self.update_position();
+			return;
+		}
+		if self.data.seek(frame) {
+			self.resampler.seek(frame);
+			self.reset_stretcher();
+			if let Some(region) = self.loop_region.as_mut() {
+				region.read = frame;
+				region.looped = 0;
+			}
+			if self.reversed_at.is_some() {
+				self.reversed_at = Some(frame);
+			}
+			self.update_position();
+		} else {
+			warn!("seek on a sound source that can't seek");
+		}
+	}
+
+
+	/// the frame of the source that is playing, in its loop region
+	/// once it looped
+	fn position (&self) -> u64 {
+		if let Some(frame) = self.virtual_at {
+			return frame as u64;
+		}
+		// the stretcher reads ahead of what plays, at the speed
+		let lag = self.stretcher.as_ref().map_or(0, |x| (x.lag() as f32 * self.speed.value * self.pitch_ratio()) as u64);
+		let played = self.resampler.position().saturating_sub(lag);
+		if let Some(start) = self.reversed_at {
+			return (2 * start).saturating_sub(played);
+		}
+		played - self.loop_region.as_ref().map_or(0, |x| x.looped.min(played))
+	}
+
+
+	/// play the source backward or forward from the position, return
+	/// false if it can't
+	fn set_reversed (&mut self, reversed: bool) -> bool {
+		if self.reversed_at.is_some() == reversed {
+			return true;
+		}
+		let direction = if reversed { Direction::Reverse } else { Direction::Forward };
+		// the frames read ahead by the resampler are read again
+		let position = self.position();
+		if !self.data.set_direction(direction) {
+			return false;
+		}
+		self.data.seek(position);
+		self.resampler.seek(position);
+		if let Some(region) = self.loop_region.as_mut() {
+			region.read = position;
+			region.looped = 0;
+		}
+		self.reversed_at = reversed.then_some(position);
+		true
+	}
+
+
+	/// how much faster the resampler plays for the pitch shift
+	fn pitch_ratio (&self) -> f32 {
+		2f32.powf(self.pitch / 12.0)
+	}
+
+
+	/// the tempo of the stretcher, with the speed of the pitch shift
+	/// taken out
+	fn stretch (&self) -> f32 {
+		self.tempo / self.pitch_ratio()
+	}
+
+
+	/// stretch the sound by `stretch`, made the first time it isn't
+	/// `1.0`
+	fn update_stretcher (&mut self, sample_rate: u32) {
+		let stretch = self.stretch();
+		if stretch != 1.0 {
+			let channels = self.data.channels();
+			let stretcher = self.stretcher.get_or_insert_with(|| Box::new(Stretcher::new(channels, sample_rate)));
+			stretcher.set_tempo(stretch);
+		} else if let Some(stretcher) = self.stretcher.as_mut() {
+			// the frames it read ahead are still played
+			stretcher.set_tempo(1.0);
+		}
+	}
+
+
+	/// forget what the stretcher read after the source moved, or stop
+	/// stretching at a tempo of `1.0`
+	fn reset_stretcher (&mut self) {
+		if self.stretch() == 1.0 {
+			self.stretcher = None;
+		} else if let Some(stretcher) = self.stretcher.as_mut() {
+			stretcher.reset();
+		}
+	}
+
+
+	/// the most gain the sound may have over the next buffer, without
+	/// its envelope and lfos
+	fn audibility (&self, group: f32) -> f32 {
+		let most = |x: &Ramp| x.value.max(x.target);
+		let distance = self.emitter.as_ref().map_or(1.0, |x| most(&x.gain));
+		let occlusion = self.occlusion.as_ref().map_or(1.0, |x| most(&x.gain));
+		most(&self.volume) * most(&self.fade) * most(&self.parameter_gain) * distance * occlusion * group
+	}
+
+
+	/// the sound can move on without its source being read: it plays
+	/// forward, the length of the source is known, and nothing waits
+	/// on its samples
+	fn can_virtualize (&self) -> bool {
+		self.data.total_frames().is_some_and(|x| x > 0)
+			&& self.reversed_at.is_none()
+			&& !self.ping_pong
+			&& self.envelope.is_none()
+			&& self.fade_end.is_none()
+			&& self.offset == 0
+	}
+
+
+	/// stop reading the source, the position moves on with
+	/// `advance_virtual`
+	fn virtualize (&mut self) {
+		if self.virtual_at.is_none() {
+			self.virtual_at = Some(self.position() as f64);
+			self.shared.levels.store(Levels::default());
+		}
+	}
+
+
+	/// read the source again, from where the virtual sound is
+	fn materialize (&mut self) {
+		if let Some(frame) = self.virtual_at.take() {
+			self.seek(frame as u64);
+		}
+	}
+
+
+	/// move a virtual sound on by `frames` frames of the output, as
+	/// if it played, return how many times it looped, or `None` if
+	/// it reaches its end, which it has to play to stop or loop
+	fn advance_virtual (&mut self, frames: usize, sample_rate: u32) -> Option<u32> {
+		let at = self.virtual_at?;
+		let total = self.data.total_frames()? as f64;
+		let mut position = at + frames as f64 * (self.speed.value * self.tape_speed() * self.tempo) as f64;
+		let mut loops = 0;
+		let mut loops_left = self.loops_left;
+		loop {
+			let repeats = loops_left != Some(0);
+			let (start, end) = match self.loop_region.as_ref() {
+				Some(region) if at < region.end as f64 && repeats => (region.start as f64, region.end as f64),
+				_ if self.looping && repeats => (0.0, total),
+				_ => break
+			};
+			if position < end {
 				break;
 			}
+			position = start + (position - end);
+			loops += 1;
+			loops_left = loops_left.map(|x| x - 1);
+		}
+		if position >= total {
+			return None;
+		}
+
+		// the ramps and lfos move on as if it played
+		let frames = frames as u32;
+		for ramp in [&mut self.volume, &mut self.fade, &mut self.pan, &mut self.speed, &mut self.parameter_gain] {
+			ramp.skip(frames);
+		}
+		for ramp in self.channel_gains.iter_mut() {
+			ramp.skip(frames);
+		}
+		if let Some(emitter) = self.emitter.as_mut() {
+			emitter.gain.skip(frames);
+			emitter.pan.skip(frames);
+		}
+		if let Some(occlusion) = self.occlusion.as_mut() {
+			occlusion.gain.skip(frames);
+		}
+		for (_, lfo) in self.lfos.iter_mut() {
+			lfo.advance(frames, sample_rate);
+		}
+		self.virtual_at = Some(position);
+		self.loops_left = loops_left;
+		Some(loops)
+	}
+
+
+	/// publish the position to the handle
+	fn update_position (&self) {
+		self.shared.position.store(self.position(), Ordering::Relaxed);
+	}
+
+
+	/// publish the playback state to the handle
+	fn set_state (&self, state: PlaybackState) {
+		self.shared.state.store(state as u8, Ordering::Relaxed);
+	}
+
+
+	fn state (&self) -> PlaybackState {
+		PlaybackState::from_u8(self.shared.state.load(Ordering::Relaxed))
+	}
+
+
+	/// go back to full volume, forgetting any fade in progress
+	fn cancel_fade (&mut self) {
+		self.fade = Ramp::new(1.0);
+		self.fade_end = None;
+	}
+
+
+	/// write the samples of the sound, at its current speed and tempo
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let Some(mut stretcher) = self.stretcher.take() else {
+			return self.write_resampled(buffer);
+		};
+		let len = stretcher.write_samples(&mut Resampled(self), buffer);
+		self.stretcher = Some(stretcher);
+		len
+	}
+
+
+	/// write the samples of the sound, at its current speed
+	///
+	/// while the speed moves, the buffer is written in blocks of
+	/// `SPEED_BLOCK_FRAMES`, each at the speed of its start. the
+	/// doppler shift of a placed sound is added on top
+	fn write_resampled (&mut self, buffer: &mut [i16]) -> usize {
+		let sample_rate = self.data.sample_rate();
+		let tape = self.tape_speed();
+		// the stretcher takes the shift of the pitch out of the tempo
+		let pitch = self.pitch_ratio();
+		let mut region;
+		let data: &mut dyn SoundSource = match self.loop_region.as_mut().filter(|_| self.reversed_at.is_none()) {
+			Some(loop_region) => {
+				region = RegionSource { data: &mut *self.data, region: loop_region, loops_left: &mut self.loops_left };
+				&mut region
+			},
+			None => &mut *self.data
+		};
+		let mut lfo = self.lfos.iter_mut().find(|x| x.0 == LfoTarget::Pitch).map(|x| &mut x.1);
+		if self.speed.is_done() && lfo.is_none() {
+			self.resampler.set_speed(self.speed.value * tape * pitch);
+			return self.resampler.write_samples(data, buffer);
+		}
+		let channels = data.channels().max(1) as usize;
+		let mut len = 0;
+		while len < buffer.len() && (!self.speed.is_done() || lfo.is_some()) {
+			let end = (len + SPEED_BLOCK_FRAMES * channels).min(buffer.len());
+			let semitones = lfo.as_ref().map_or(0.0, |x| x.value() * x.depth());
+			self.resampler.set_speed(self.speed.value * tape * pitch * 2f32.powf(semitones / 12.0));
+			let written = self.resampler.write_samples(data, &mut buffer[len..end]);
+			self.speed.skip((written / channels) as u32);
+			if let Some(lfo) = lfo.as_mut() {
+				lfo.advance((written / channels) as u32, sample_rate);
+			}
+			len += written;
+			if len < end {
+				return len;
+			}
+		}
+		self.resampler.set_speed(self.speed.value * tape * pitch);
+		len + self.resampler.write_samples(data, &mut buffer[len..])
+	}
+
+
+	/// the speed of the doppler shift and the parameters, which
+	/// change the pitch with it, like a tape
+	fn tape_speed (&self) -> f32 {
+		let doppler = self.emitter.as_ref().map_or(1.0, |x| x.doppler);
+		doppler * self.parameter_speed * self.group_speed
+	}
+
+
+	/// move what the parameters give the sound to their values, over
+	/// `smoothing`
+	fn follow_parameters (&mut self, parameters: &[(u64, f32)], smoothing: Duration, sample_rate: SampleRate) {
+		let mapped = parameter::mapped(&self.parameter_maps, parameters);
+		self.parameter_gain.set(mapped.gain, sample_rate.frames(smoothing));
+		self.parameter_speed = mapped.speed;
+		match (mapped.cutoff, self.low_pass.as_ref()) {
+			(Some(cutoff), Some(filter)) => filter.controls().animate_cutoff(cutoff, smoothing, Easing::Linear),
+			(Some(cutoff), None) => self.low_pass = Some(Biquad::low_pass(cutoff)),
+			// kept open, for when a parameter moves it again
+			(None, Some(filter)) => filter.controls().animate_cutoff(OPEN_CUTOFF, smoothing, Easing::Linear),
+			(None, None) => {}
+		}
+	}
+
+
+	/// the lfo of `target`, if there is one
+	fn lfo (&mut self, target: LfoTarget) -> Option<&mut Lfo> {
+		self.lfos.iter_mut().find(|x| x.0 == target).map(|x| &mut x.1)
+	}
+
+
+	/// the gains of the left and right channels for `pan`
+	///
+	/// both gains are 1.0 at the center, so a centered sound plays
+	/// like it did without panning
+	fn pan_gains (&self, pan: f32) -> (f32, f32) {
+		if pan == 0.0 {
+			return (1.0, 1.0);
+		}
+		// 0 is fully left, pi/2 is fully right
+		let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+		let left = angle.cos() * std::f32::consts::SQRT_2;
+		let right = angle.sin() * std::f32::consts::SQRT_2;
+		if self.mono {
+			(left, right)
+		} else {
+			// a stereo source already has its own image, the channel
+			// being panned to is never boosted
+			(left.min(1.0), right.min(1.0))
+		}
+	}
+
+}
+
+
+
+/// a slot of the mixer's sound slab
+struct Slot {
+	generation: u32,
+	sound: Option<SoundInner>
+}
+
+
+
+struct GroupInner {
+
+	name: String,
+	/// always created before this group, so it has a lower index
+	parent: Option<GroupId>,
+	volume: f32,
+	muted: bool,
+	paused: bool,
+	/// moves smoothly to `volume`, or to 0 when muted
+	gain: Ramp,
+	/// gain of the group, multiplied by the gains of its parents, at
+	/// the start and at the end of the current buffer
+	total_gain: (f32, f32),
+	/// this group or one of its parents is paused
+	total_paused: bool,
+	/// when not empty, the group is mixed in `buffer` and goes
+	/// through these
+	effects: Vec<(EffectId, Box<dyn Effect>)>,
+	buffer: Vec<f32>,
+	/// gain of the ducks on this group, at the start and at the end
+	/// of the current buffer
+	ducking: (f32, f32),
+	/// the filter of the snapshots and parameters, in `effects`, made
+	/// the first time one sets its cutoff
+	low_pass: Option<(EffectId, BiquadControls)>,
+	/// the game parameters the group follows, with what they give it
+	parameter_maps: Vec<ParameterMap>,
+	parameter_gain: f32,
+	parameter_speed: f32,
+	/// the speed of the parameters of this group and its parents
+	total_speed: f32
+
+}
+
+
+
+/// a group turned down while another one plays
+struct Duck {
+
+	group: GroupId,
+	sidechain: GroupId,
+	/// how much the group is turned down, `1.0` is silent
+	amount: f32,
+	attack: Duration,
+	release: Duration,
+	gain: f32,
+	/// the loudest sound of the sidechain in the last buffer
+	level: f32
+
+}
+
+
+
+fn update_group_gain (group: &mut GroupInner, frames: u32) {
+	let target = if group.muted { 0.0 } else { group.volume * group.parameter_gain };
+	group.gain.set(target, frames);
+}
+
+
+
+/// the controls of the low pass of `group`, made again if the
+/// effects were cleared
+fn group_low_pass (group: &mut GroupInner) -> BiquadControls {
+	let effects = &group.effects;
+	match group.low_pass.as_ref().filter(|x| effects.iter().any(|y| y.0 == x.0)) {
+		Some((_, controls)) => controls.clone(),
+		None => {
+			let filter = Biquad::low_pass(OPEN_CUTOFF);
+			let controls = filter.controls();
+			let effect_id = EffectId::next();
+			group.effects.push((effect_id, Box::new(filter)));
+			group.low_pass = Some((effect_id, controls.clone()));
+			controls
+		}
+	}
+}
+
+
+
+/// the group with effects that `group` is mixed into, itself or
+/// one of its parents. `None` is the master mix
+fn bus_of (groups: &[GroupInner], mut group: Option<GroupId>) -> Option<GroupId> {
+	while let Some(id) = group {
+		let inner = &groups[id.0 as usize];
+		if !inner.effects.is_empty() {
+			return Some(id);
+		}
+		group = inner.parent;
+	}
+	None
+}
+
+
+
+/// `group` is `ancestor` or inside it
+fn is_in (groups: &[GroupInner], mut group: Option<GroupId>, ancestor: GroupId) -> bool {
+	while let Some(id) = group {
+		if id == ancestor {
+			return true;
+		}
+		group = groups[id.0 as usize].parent;
+	}
+	false
+}
+
+
+
+/// the buffer of `bus`
+fn bus_buffer <'a> (groups: &'a mut [GroupInner], mix: &'a mut [f32], bus: Option<GroupId>) -> &'a mut [f32] {
+	match bus {
+		Some(id) => &mut groups[id.0 as usize].buffer,
+		None => mix
+	}
+}
+
+
+
+/// find the sound of `id`, if it still exists
+fn find (sounds: &mut [Slot], id: SoundId) -> Option<&mut SoundInner> {
+	let slot = sounds.get_mut(id.index as usize)?;
+	if slot.generation != id.generation {
+		return None;
+	}
+	slot.sound.as_mut()
+}
+
+
+
+/// copy `buffer` to every tap, forgetting the closed ones
+fn write_taps (taps: &mut Vec<TapWriter>, replay: Option<&Replay>, buffer: &[i16]) {
+	taps.retain(|tap| !tap.is_closed());
+	for tap in taps.iter() {
+		tap.write(buffer);
+	}
+	if let Some(replay) = replay {
+		replay.write(buffer);
+	}
+}
+
+
+
+/// triangular noise between -1 and 1, the difference of two uniform
+/// values of a xorshift generator
+fn tpdf (state: &mut u32) -> f32 {
+	let mut uniform = || {
+		*state ^= *state << 13;
+		*state ^= *state >> 17;
+		*state ^= *state << 5;
+		*state as f32 / u32::MAX as f32
+	};
+	uniform() - uniform()
+}
+
+
+
+/// keep track of each Sound, and mix their output together
+pub struct Mixer {
+
+	/// every sound, indexed by `SoundId::index`
+	sounds: Vec<Slot>,
+	/// indices of the free slots in `sounds`
+	free: Vec<u32>,
+	/// indices of the sounds being played, in mixing order
+	playing: Vec<u32>,
+	/// sounds started later, with the frame they start on
+	scheduled: Vec<(u64, SoundId)>,
+	/// frames mixed since the mixer was created
+	clock: u64,
+	/// the most sounds that play at once
+	max_voices: Option<(usize, StealPolicy)>,
+	/// the playing sounds with their priority and audibility, and the
+	/// sounds heard of each instance limit, kept for
+	/// `update_virtual` to avoid allocating
+	ranked: Vec<(u32, u8, f32)>,
+	instances: Vec<(usize, u32)>,
+	/// set while every sound is paused, but the ones of these groups
+	paused_all: Option<Vec<GroupId>>,
+	/// by name, see `AudioEngine::add_snapshot`
+	snapshots: Vec<(String, Snapshot)>,
+	/// the values of the game parameters that were set, by the key
+	/// of their name
+	parameters: Vec<(u64, f32)>,
+	transport: TransportState,
+	groups: Vec<GroupInner>,
+	commands: Arc<Queue<Command>>,
+	/// written by the audio thread, read by the engine
+	events: Arc<Queue<SoundEvent>>,
+	/// how long a volume change takes
+	volume_smoothing: Duration,
+	/// of the sounds added from now on, and of the speed of every sound
+	resample_quality: ResampleQuality,
+	master_volume: f32,
+	muted: bool,
+	/// gain applied to the whole mix, moves smoothly to
+	/// `master_volume`, or to 0 when muted
+	master: Ramp,
+	/// scratch space where each sound writes its samples before
+	/// being mixed, kept between callbacks to avoid allocating
+	buffer: Vec<i16>,
+	/// the samples of `buffer` as f32, given to the effect
+	samples: Vec<f32>,
+	/// the sum of the sounds, kept in f32 so loud overlaps only
+	/// clip once, at the output
+	mix: Vec<f32>,
+	/// add noise before rounding the mix to i16
+	dither: bool,
+	/// state of the dither noise
+	rng: u32,
+	/// fold the output down to mono
+	mono: bool,
+	limiter: Option<Limiter>,
+	limiter_config: Option<LimiterConfig>,
+	/// the group of the shared reverb, once a sound sends to it
+	reverb: Option<GroupId>,
+	ducks: Vec<Duck>,
+	listener: ListenerState,
+	/// renders the placed sounds for headphones, on a stereo output
+	hrtf: Option<Box<Hrtf>>,
+	/// when set, the placed sounds are mixed in a sound field
+	ambisonics: Option<Ambisonics>,
+	/// applied to the whole mix, before the master gain
+	effects: Vec<(EffectId, Box<dyn Effect>)>,
+	/// get a copy of every mixed buffer
+	taps: Vec<TapWriter>,
+	/// the last seconds of the output, when enabled
+	replay: Option<Arc<Replay>>,
+	/// the levels of the output
+	meter: Arc<Meter>,
+	pub channels: u16,
+	pub sample_rate: SampleRate
+
+}
+
+impl Mixer {
+
+
+	pub fn new (channels: u16, sample_rate: SampleRate) -> Self {
+		Self {
+			sounds: vec![],
+			free: vec![],
+			playing: vec![],
+			scheduled: vec![],
+			clock: 0,
+			max_voices: None,
+			ranked: vec![],
+			instances: vec![],
+			paused_all: None,
+			snapshots: vec![],
+			parameters: vec![],
+			transport: TransportState::new(),
+			groups: vec![],
+			commands: Arc::new(Queue::with_capacity(COMMAND_QUEUE_CAPACITY)),
+			events: Arc::new(Queue::with_capacity(EVENT_QUEUE_CAPACITY)),
+			volume_smoothing: DEFAULT_VOLUME_SMOOTHING,
+			resample_quality: ResampleQuality::default(),
+			master_volume: 1.0,
+			muted: false,
+			master: Ramp::new(1.0),
+			buffer: vec![],
+			samples: vec![],
+			mix: vec![],
+			dither: false,
+			rng: 0x9E37_79B9,
+			mono: false,
+			limiter: Some(Limiter::new(LimiterConfig::default(), channels, sample_rate.0)),
+			limiter_config: Some(LimiterConfig::default()),
+			reverb: None,
+			ducks: vec![],
+			listener: ListenerState::default(),
+			hrtf: None,
+			ambisonics: None,
+			effects: vec![],
+			taps: vec![],
+			replay: None,
+			meter: Arc::new(Meter::new()),
+			channels,
+			sample_rate
+		}
+	}
+
+
+	/// change the number of channels and the sample rate
+	///
+	/// this will also keep all currently playing sounds and convert
+	/// them to the new config if necessary
+	pub fn set_config (&mut self, channels: u16, sample_rate: SampleRate) {
+
+		struct Nop;
+		#[rustfmt::skip]
+		impl SoundSource for Nop {
+			fn channels (&self) -> u16 { 0 }
+			fn sample_rate (&self) -> u32 { 0 }
+			fn reset (&mut self) { }
+			fn write_samples (&mut self, _: &mut [i16]) -> usize { 0 }
+		}
+
+		let not_changed = self.channels == channels && self.sample_rate == sample_rate;
+		if not_changed {
+			return;
+		}
+		for sound in self.sounds.iter_mut().filter_map(|x| x.sound.as_mut()) {
+			// https://github.com/Rodrigodd/audio-engine/blob/3d0da3711b5cc78e7192d616ebb1d4069920707d/src/lib.rs#L200
+			// Beware !! read the link
+			if sound.data.channels() != channels {
+				let inner = std::mem::replace(&mut sound.data, Box::new(Nop));
+				sound.data = layout::remix(inner, channels);
+				sound.resampler.set_channels(channels);
+			}
+			if sound.data.sample_rate() != sample_rate.0 {
+				let inner = std::mem::replace(&mut sound.data, Box::new(Nop));
+				sound.data = Box::new(converter::SampleRateConverter::new(inner, sample_rate.0, self.resample_quality));
+			}
+			let total_frames = sound.data.total_frames().unwrap_or(u64::MAX);
+			sound.shared.total_frames.store(total_frames, Ordering::Relaxed);
+			sound.shared.sample_rate.store(sample_rate.0, Ordering::Relaxed);
+		}
+		self.channels = channels;
+		self.sample_rate = sample_rate;
+		self.set_limiter(self.limiter_config);
+
+	}
+
+
+	/// the queue where [`Sound`] pushes its commands
+	pub fn commands (&self) -> Arc<Queue<Command>> {
+		self.commands.clone()
+	}
+
+
+	/// the queue where the mixer pushes [`SoundEvent`]s
+	pub fn events (&self) -> Arc<Queue<SoundEvent>> {
+		self.events.clone()
+	}
+
+
+	/// the levels of the output, after the master volume
+	pub fn meter (&self) -> Arc<Meter> {
+		self.meter.clone()
+	}
+
+
+	/// copy the output to `tap`, until its reader is dropped
+	pub fn add_tap (&mut self, tap: TapWriter) {
+		self.taps.push(tap);
+	}
+
+
+	pub fn set_replay (&mut self, replay: Option<Arc<Replay>>) {
+		self.replay = replay;
+	}
+
+
+	pub fn replay (&self) -> Option<Arc<Replay>> {
+		self.replay.clone()
+	}
+
+
+	/// the frame the next mixed buffer starts on
+	pub fn clock (&self) -> u64 {
+		self.clock
+	}
+
+
+	pub fn transport (&self) -> &TransportState {
+		&self.transport
+	}
+
+
+	/// apply every command waiting in the queue
+	pub fn process_commands (&mut self) {
+		while let Some(command) = self.commands.pop() {
+			self.apply(command);
+		}
+	}
+
+
+	/// apply a single command
+	pub fn apply (&mut self, command: Command) {
+		match command {
+			Command::Play(id) => {
+				self.unschedule(id);
+				self.play(id);
+			},
+			Command::PlayAt(id, frame) => self.play_at(id, frame),
+			Command::PlayAfter(id, delay) => self.play_at(id, self.clock + self.sample_rate.frames(delay) as u64),
+			Command::PlayQuantized(id, quantize) => self.play_at(id, self.transport.next(quantize, self.clock, self.sample_rate.0)),
+			Command::Pause(id) => {
+				self.unschedule(id);
+				self.pause(id);
+			},
+			Command::Stop(id) => {
+				self.unschedule(id);
+				self.stop(id);
+			},
+			Command::FadeIn(id, duration) => self.fade_in(id, duration),
+			Command::FadeOut(id, duration, end) => self.fade_out(id, duration, end),
+			Command::Crossfade(from, to, duration) => {
+				// both are applied before the next buffer is mixed, so the
+				// fades start on the same frame
+				self.unschedule(from);
+				self.fade_out(from, duration, FadeEnd::Stop);
+				self.unschedule(to);
+				self.fade_in(to, duration);
+			},
+			Command::SetEnvelope(id, envelope) => self.set_envelope(id, envelope),
+			Command::Release(id) => self.release(id),
+			Command::Reset(id) => self.reset(id),
+			Command::SeekTo(id, position) => self.seek_to(id, position),
+			Command::SeekBy(id, offset) => self.seek_by(id, offset),
+			Command::SetVolume(id, volume) => self.set_volume(id, volume),
+			Command::SetPan(id, pan) => self.set_pan(id, pan),
+			Command::SetChannelGains(id, gains) => self.set_channel_gains(id, gains),
+			Command::SetSpeed(id, speed) => self.set_speed(id, speed),
+			Command::SetSoundTempo(id, tempo) => self.set_sound_tempo(id, tempo),
+			Command::SetPitch(id, semitones) => self.set_pitch(id, semitones),
+			Command::AnimateVolume(id, target, duration, easing) => self.animate_volume(id, target, duration, easing),
+			Command::AnimatePan(id, target, duration, easing) => self.animate_pan(id, target, duration, easing),
+			Command::AnimateSpeed(id, target, duration, easing) => self.animate_speed(id, target, duration, easing),
+			Command::SetLfo(id, target, lfo) => self.set_lfo(id, target, lfo),
+			Command::SetPosition(id, position) => self.set_position(id, position),
+			Command::SetAttenuation(id, attenuation) => self.set_attenuation(id, attenuation),
+			Command::SetVelocity(id, velocity) => self.set_velocity(id, velocity),
+			Command::SetOcclusion(id, occlusion) => self.set_occlusion(id, occlusion),
+			Command::SetListenerPosition(position) => self.listener.position = position,
+			Command::SetListenerVelocity(velocity) => self.listener.velocity = velocity,
+			Command::SetDopplerFactor(factor) => self.listener.doppler_factor = factor,
+			Command::SetHrtf(hrtf) => self.hrtf = hrtf,
+			Command::SetAmbisonics(on) => self.ambisonics = on.then(Ambisonics::new),
+			Command::SetListenerOrientation(forward, up) => {
+				self.listener.forward = forward;
+				self.listener.up = up;
+			},
+			Command::SetLoop(id, looping) => self.set_loop(id, looping),
+			Command::SetLoopCount(id, count) => self.set_loop_count(id, count),
+			Command::SetDirection(id, direction) => self.set_direction(id, direction),
+			Command::SetPingPong(id, ping_pong) => {
+				if let Some(sound) = find(&mut self.sounds, id) {
+					sound.ping_pong = ping_pong;
+				}
+			},
+			Command::SetPriority(id, priority) => {
+				if let Some(sound) = find(&mut self.sounds, id) {
+					sound.priority = priority;
+				}
+			},
+			Command::SetTag(id, tag) => {
+				if let Some(sound) = find(&mut self.sounds, id) {
+					sound.tag = tag;
+				}
+			},
+			Command::PauseTagged(tag) => self.for_tagged(tag, |mixer, id| {
+				mixer.unschedule(id);
+				mixer.pause(id);
+			}),
+			Command::ResumeTagged(tag) => self.for_tagged(tag, |mixer, id| {
+				if find(&mut mixer.sounds, id).is_some_and(|x| x.state() == PlaybackState::Paused) {
+					mixer.play(id);
+				}
+			}),
+			Command::StopTagged(tag) => self.for_tagged(tag, |mixer, id| {
+				mixer.unschedule(id);
+				mixer.stop(id);
+			}),
+			Command::SetVolumeTagged(tag, volume) => self.for_tagged(tag, |mixer, id| mixer.set_volume(id, volume)),
+			Command::MapParameter(id, map) => self.map_parameter(id, map),
+			Command::SetLoopRegion(id, region) => self.set_loop_region(id, region),
+			Command::SetEffect(id, effect) => self.update_effect(id, effect),
+			Command::AddEffect(id, effect_id, effect) => self.add_effect(id, effect_id, effect),
+			Command::RemoveEffect(id, effect_id) => self.remove_effect(id, effect_id),
+			Command::ClearEffects(id) => self.clear_effects(id),
+			Command::AddGroupEffect(id, effect_id, effect) => self.add_group_effect(id, effect_id, effect),
+			Command::RemoveGroupEffect(id, effect_id) => self.remove_group_effect(id, effect_id),
+			Command::ClearGroupEffects(id) => self.clear_group_effects(id),
+			Command::MapGroupParameter(id, map) => self.map_group_parameter(id, map),
+			Command::SetSend(id, group, level) => self.set_send(id, group, level),
+			Command::AddMasterEffect(id, effect) => self.effects.push((id, effect)),
+			Command::RemoveMasterEffect(id) => self.effects.retain(|x| x.0 != id),
+			Command::ClearMasterEffects => self.effects.clear(),
+			Command::Duck(group, sidechain, amount, attack, release) => self.duck(group, sidechain, amount, attack, release),
+			Command::StopDucking(group, sidechain) => self.stop_ducking(group, sidechain),
+			Command::SetTempo(bpm) => self.transport.set_tempo(bpm, self.clock, self.sample_rate.0),
+			Command::SetTimeSignature(beats, unit) => {
+				self.transport.beats_per_bar = beats.max(1);
+				self.transport.beat_unit = unit.max(1);
+			},
+			Command::RestartTransport => self.transport.restart(self.clock),
+			Command::Drop(id) => self.drop_sound(id),
+			Command::SetMasterVolume(volume) => self.set_master_volume(volume),
+			Command::SetMuted(muted) => self.set_muted(muted),
+			Command::SetDither(dither) => self.set_dither(dither),
+			Command::SetMono(mono) => self.set_mono(mono),
+			Command::SetMaxVoices(max) => self.max_voices = max.map(|(max, policy)| (max as usize, policy)),
+			Command::PauseAll(except) => self.paused_all = Some(except),
+			Command::TransitionToSnapshot(index, duration) => self.transition_to_snapshot(index, duration),
+			Command::SetParameter(key, value) => self.set_parameter(key, value),
+			Command::ResumeAll => self.paused_all = None,
+			Command::SetLimiter(config) => self.set_limiter(config),
+			Command::SetGroupVolume(id, volume) => self.set_group_volume(id, volume),
+			Command::SetGroupMuted(id, muted) => self.set_group_muted(id, muted),
+			Command::SetGroupPaused(id, paused) => self.groups[id.0 as usize].paused = paused
+		}
+	}
+
+
+	/// add a sound already converted to the output config
+	///
+	/// `mono` tells if the source had a single channel before
+	/// being converted
+	pub fn add_sound (&mut self, sound: Box<dyn SoundSource + Send>, mono: bool, effect: impl Effect + 'static) -> (SoundId, Arc<SoundShared>) {
+		let sound = SoundInner::new(sound, mono, effect, self.resample_quality);
+		let shared = sound.shared.clone();
+		let sound = Some(sound);
+		let id = match self.free.pop() {
+			Some(index) => {
+				let slot = &mut self.sounds[index as usize];
+				slot.sound = sound;
+				SoundId { index, generation: slot.generation }
+			},
+			None => {
+				self.sounds.push(Slot { generation: 0, sound });
+				SoundId { index: self.sounds.len() as u32 - 1, generation: 0 }
+			}
+		};
+		// reserve here, so the audio thread never needs to allocate
+		// when a sound starts playing or is freed
+		self.playing.reserve(self.sounds.len() - self.playing.len());
+		self.free.reserve(self.sounds.len() - self.free.len());
+		(id, shared)
+	}
+
+
+	/// create a new group, inside `parent` if given
+	pub fn add_group (&mut self, name: &str, parent: Option<GroupId>) -> GroupId {
+		self.groups.push(GroupInner {
+			name: name.to_owned(),
+			parent,
+			volume: 1.0,
+			muted: false,
+			paused: false,
+			gain: Ramp::new(1.0),
+			total_gain: (1.0, 1.0),
+			total_paused: false,
+			effects: vec![],
+			buffer: vec![],
+			ducking: (1.0, 1.0),
+			low_pass: None,
+			parameter_maps: vec![],
+			parameter_gain: 1.0,
+			parameter_speed: 1.0,
+			total_speed: 1.0
+		});
+		GroupId(self.groups.len() as u32 - 1)
+	}
+
+
+	pub fn has_group (&self, id: GroupId) -> bool {
+		(id.0 as usize) < self.groups.len()
+	}
+
+
+	/// the first group created with this name
+	pub fn find_group (&self, name: &str) -> Option<GroupId> {
+		self.groups
+			.iter()
+			.position(|x| x.name == name)
+			.map(|x| GroupId(x as u32))
+	}
+
+
+	/// add a snapshot, or replace the one with the same name
+	pub fn add_snapshot (&mut self, name: &str, snapshot: Snapshot) {
+		match self.snapshots.iter_mut().find(|x| x.0 == name) {
+			Some(x) => x.1 = snapshot,
+			None => self.snapshots.push((name.to_owned(), snapshot))
+		}
+	}
+
+
+	pub fn find_snapshot (&self, name: &str) -> Option<usize> {
+		self.snapshots.iter().position(|x| x.0 == name)
+	}
+
+
+	/// move the groups of the snapshot at `index` to its settings
+	/// over `duration`
+	fn transition_to_snapshot (&mut self, index: usize, duration: Duration) {
+		let frames = self.sample_rate.frames(duration);
+		let snapshot = &self.snapshots[index].1;
+		for &(id, volume) in &snapshot.volumes {
+			let group = &mut self.groups[id.0 as usize];
+			group.volume = volume;
+			update_group_gain(group, frames);
+		}
+		for &(id, cutoff) in &snapshot.low_passes {
+			let controls = group_low_pass(&mut self.groups[id.0 as usize]);
+			// frequencies are heard in ratios, so the cutoff moves
+			// fast where it is high
+			let easing = if cutoff < controls.cutoff() { Easing::ExpOut } else { Easing::ExpIn };
+			controls.animate_cutoff(cutoff, duration, easing);
+		}
+	}
+
+
+	/// set the game parameter of `key`, and move the sounds and groups
+	/// that follow it
+	fn set_parameter (&mut self, key: u64, value: f32) {
+		match self.parameters.iter_mut().find(|x| x.0 == key) {
+			Some(x) => x.1 = value,
+			None => self.parameters.push((key, value))
+		}
+		let follows = |maps: &[ParameterMap]| maps.iter().any(|x| x.key == key);
+		for slot in self.sounds.iter_mut() {
+			if let Some(sound) = slot.sound.as_mut().filter(|x| follows(&x.parameter_maps)) {
+				sound.follow_parameters(&self.parameters, self.volume_smoothing, self.sample_rate);
+			}
+		}
+		for i in 0..self.groups.len() {
+			if follows(&self.groups[i].parameter_maps) {
+				self.group_follow_parameters(GroupId(i as u32), false);
+			}
+		}
+	}
+
+
+	fn map_parameter (&mut self, id: SoundId, map: ParameterMap) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			parameter::insert(&mut sound.parameter_maps, map);
+			sound.follow_parameters(&self.parameters, self.volume_smoothing, self.sample_rate);
+		}
+	}
+
+
+	fn map_group_parameter (&mut self, id: GroupId, map: ParameterMap) {
+		let maps = &mut self.groups[id.0 as usize].parameter_maps;
+		let cutoff = |maps: &[ParameterMap]| maps.iter().any(|x| x.target == ParameterTarget::Cutoff);
+		let had_cutoff = cutoff(maps);
+		parameter::insert(maps, map);
+		// the low pass opens once no parameter moves it
+		let open = had_cutoff && !cutoff(maps);
+		self.group_follow_parameters(id, open);
+	}
+
+
+	/// move what the parameters give the group to their values, and
+	/// open its low pass if `open` and no parameter moves it
+	fn group_follow_parameters (&mut self, id: GroupId, open: bool) {
+		let frames = self.sample_rate.frames(self.volume_smoothing);
+		let group = &mut self.groups[id.0 as usize];
+		let mapped = parameter::mapped(&group.parameter_maps, &self.parameters);
+		group.parameter_gain = mapped.gain;
+		group.parameter_speed = mapped.speed;
+		update_group_gain(group, frames);
+		// the snapshots move the low pass of a group that no parameter
+		// moves, so it is only opened when asked
+		let cutoff = mapped.cutoff.or(open.then_some(OPEN_CUTOFF));
+		if let Some(cutoff) = cutoff {
+			group_low_pass(group).animate_cutoff(cutoff, self.volume_smoothing, Easing::Linear);
+		}
+	}
+
+
+	/// move a sound to a group, or out of any group
+	pub fn set_group (&mut self, id: SoundId, group: Option<GroupId>) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.group = group;
+		}
+	}
+
+
+	pub fn set_group_volume (&mut self, id: GroupId, volume: f32) {
+		let group = &mut self.groups[id.0 as usize];
+		group.volume = volume;
+		update_group_gain(group, self.sample_rate.frames(self.volume_smoothing));
+	}
+
+
+	pub fn set_group_muted (&mut self, id: GroupId, muted: bool) {
+		let group = &mut self.groups[id.0 as usize];
+		group.muted = muted;
+		update_group_gain(group, self.sample_rate.frames(self.volume_smoothing));
+	}
+
+
+	/// advance the gain of every group by `frames`, and find the
+	/// total gain and pause of each one
+	fn update_groups (&mut self, frames: u32) {
+		for i in 0..self.groups.len() {
+			let (parent_gain, parent_paused, parent_speed) = match self.groups[i].parent {
+				Some(parent) => {
+					let parent = &self.groups[parent.0 as usize];
+					(parent.total_gain, parent.total_paused, parent.total_speed)
+				},
+				None => ((1.0, 1.0), false, 1.0)
+			};
+			let group = &mut self.groups[i];
+			let start = group.gain.value;
+			group.gain.skip(frames);
+			group.total_gain = (
+				start * group.ducking.0 * parent_gain.0,
+				group.gain.value * group.ducking.1 * parent_gain.1
+			);
+			group.total_paused = group.paused || parent_paused;
+			group.total_speed = group.parameter_speed * parent_speed;
+		}
+	}
+
+
+	/// if the sound was paused ot stopped, it will start playing
+	/// again. otherwise, does nothing
+	pub fn play (&mut self, id: SoundId) {
+		let sample_rate = self.sample_rate.0;
+		let idle = find(&mut self.sounds, id).is_some_and(|x| x.playing.is_none());
+		if idle && !self.make_room(id) {
+			// it ends right away, for whoever waits on it
+			if let Some(sound) = find(&mut self.sounds, id) {
+				sound.set_state(PlaybackState::Stopped);
+				sound.shared.end();
+			}
+			return;
+		}
+		let clock = self.clock;
+		if let Some(sound) = find(&mut self.sounds, id) {
+			if sound.playing.is_none() {
+				sound.started = clock;
+				// a paused sound continues where its envelope was
+				let paused = sound.state() == PlaybackState::Paused;
+				if let Some(envelope) = sound.envelope.as_mut().filter(|_| !paused) {
+					envelope.start(sample_rate);
+				}
+				if !paused {
+					sound.loops_left = sound.loop_count;
+				}
+				sound.playing = Some(self.playing.len());
+				self.playing.push(id.index);
+			}
+			sound.set_state(PlaybackState::Playing);
+		}
+	}
+
+
+	/// call `f` with every sound tagged with `tag`
+	fn for_tagged (&mut self, tag: u64, mut f: impl FnMut(&mut Self, SoundId)) {
+		for index in 0..self.sounds.len() {
+			let slot = &self.sounds[index];
+			if slot.sound.as_ref().is_some_and(|x| x.tag == Some(tag)) {
+				let id = SoundId { index: index as u32, generation: slot.generation };
+				f(self, id);
+			}
+		}
+	}
+
+
+	/// stop the sounds that the limits of `id` steal, before it starts,
+	/// return false if it can't start
+	fn make_room (&mut self, id: SoundId) -> bool {
+		let Some(sound) = find(&mut self.sounds, id) else {
+			return true;
+		};
+		let (limit, priority) = (sound.instance_limit, sound.priority);
+		if let Some(limit) = limit {
+			let same = |x: &SoundInner| x.instance_limit.is_some_and(|x| x.key == limit.key);
+			if !self.steal(limit.max as usize, limit.policy, priority, same) {
+				return false;
+			}
+		}
+		match self.max_voices {
+			Some((max, policy)) => self.steal(max, policy, priority, |_| true),
+			None => true
+		}
+	}
+
+
+	/// fade out one of the playing sounds that match `filter`, if
+	/// `max` of them already play, return false if no sound
+	/// can be stolen by `policy`
+	///
+	/// the sounds that fade out to stop don't count. only sounds with
+	/// the same or a lower `priority` are stolen, the lowest first
+	fn steal (&mut self, max: usize, policy: StealPolicy, priority: u8, filter: impl Fn(&SoundInner) -> bool) -> bool {
+		// the sounds over the limit are made virtual in `update_virtual`
+		if policy == StealPolicy::Virtualize {
+			return true;
+		}
+		let mut count = 0;
+		let mut victim: Option<(u32, u8, f64)> = None;
+		for &index in &self.playing {
+			let sound = self.sounds[index as usize].sound.as_ref().unwrap();
+			if sound.fade_end == Some(FadeEnd::Stop) || !filter(sound) {
+				continue;
+			}
+			count += 1;
+			let score = match policy {
+				_ if sound.priority > priority => continue,
+				StealPolicy::Oldest => sound.started as f64,
+				StealPolicy::Quietest => sound.shared.levels.levels().rms as f64,
+				StealPolicy::RejectNew | StealPolicy::Virtualize => continue
+			};
+			if victim.is_none_or(|x| (sound.priority, score) < (x.1, x.2)) {
+				victim = Some((index, sound.priority, score));
+			}
+		}
+		if count < max {
+			return true;
+		}
+		let Some((index, ..)) = victim else {
+			return false;
+		};
+		let id = SoundId { index, generation: self.sounds[index as usize].generation };
+		self.fade_out(id, STEAL_FADE, FadeEnd::Stop);
+		true
+	}
+
+
+	/// start the sound when the output reaches `frame`, or on the next
+	/// buffer if it already did
+	pub fn play_at (&mut self, id: SoundId, frame: u64) {
+		self.unschedule(id);
+		if frame <= self.clock {
+			self.play(id);
+		} else if find(&mut self.sounds, id).is_some() {
+			self.scheduled.push((frame, id));
+		}
+	}
+
+
+	fn unschedule (&mut self, id: SoundId) {
+		self.scheduled.retain(|x| x.1 != id);
+	}
+
+
+	/// start the scheduled sounds that start in the next `frames`,
+	/// after silence until their frame
+	fn start_scheduled (&mut self, frames: u64) {
+		let end = self.clock + frames;
+		let mut i = 0;
+		while i < self.scheduled.len() {
+			let (frame, id) = self.scheduled[i];
+			if frame >= end {
+				i += 1;
+				continue;
+			}
+			self.scheduled.swap_remove(i);
+			let idle = find(&mut self.sounds, id).is_some_and(|x| x.playing.is_none());
+			self.play(id);
+			if let Some(sound) = find(&mut self.sounds, id).filter(|_| idle) {
+				sound.offset = frame.saturating_sub(self.clock) as usize;
+			}
+		}
+	}
+
+
+	/// if the sound is playing, it will pause. if play is called,
+	/// this sound will continue from where it was when pause.
+	/// if the sound is not playing, does nothing
+	pub fn pause (&mut self, id: SoundId) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.cancel_fade();
+			if let Some(position) = sound.playing.take() {
+				sound.set_state(PlaybackState::Paused);
+				self.remove_playing(position);
+			}
+		}
+	}
+
+
+	/// if the sound is playing, it will pause and reset the song.
+	/// when play is called this sound will start from the beggining
+	/// even if the sound is not playing, it will reset the sound to
+	/// the start
+	pub fn stop (&mut self, id: SoundId) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.reset();
+			sound.cancel_fade();
+			sound.set_state(PlaybackState::Stopped);
+			sound.shared.end();
+			if let Some(position) = sound.playing.take() {
+				self.remove_playing(position);
+			}
+		}
+	}
+
+
+	/// start playing the sound, raising its volume from silence, or
+	/// from its current fade if it is already playing
+	pub fn fade_in (&mut self, id: SoundId, duration: Duration) {
+		let frames = self.sample_rate.frames(duration);
+		if let Some(sound) = find(&mut self.sounds, id) {
+			if sound.playing.is_none() {
+				sound.fade = Ramp::new(0.0);
+			}
+			sound.fade.set(1.0, frames);
+			sound.fade_end = None;
+		}
+		self.play(id);
+	}
+
+
+	/// shape the volume of the sound with `envelope` from the next
+	/// time it starts
+	pub fn set_envelope (&mut self, id: SoundId, envelope: Option<Envelope>) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.envelope = envelope.map(EnvelopeState::new);
+		}
+	}
+
+
+	/// start the release of the envelope of a playing sound, which
+	/// then stops. a sound without an envelope stops right away
+	pub fn release (&mut self, id: SoundId) {
+		let sample_rate = self.sample_rate.0;
+		if let Some(sound) = find(&mut self.sounds, id) {
+			if let (Some(envelope), Some(_)) = (sound.envelope.as_mut(), sound.playing) {
+				envelope.release(sample_rate);
+				return;
+			}
+		}
+		self.stop(id);
+	}
+
+
+	/// lower the volume of a playing sound to silence, then pause
+	/// or stop it. does nothing if the sound is not playing
+	pub fn fade_out (&mut self, id: SoundId, duration: Duration, end: FadeEnd) {
+		let frames = self.sample_rate.frames(duration);
+		if let Some(sound) = find(&mut self.sounds, id) {
+			if sound.playing.is_some() {
+				sound.fade.set(0.0, frames);
+				sound.fade_end = Some(end);
+			} else if end == FadeEnd::Stop {
+				sound.reset();
+				sound.set_state(PlaybackState::Stopped);
+				sound.shared.end();
+			}
+		}
+	}
+
+
+	/// this reset the sound to the start, the sound being played
+	/// or not
+	pub fn reset (&mut self, id: SoundId) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.reset();
+			// it is back at the start, ready to play again
+			if sound.state() == PlaybackState::Finished {
+				sound.set_state(PlaybackState::Stopped);
+			}
+		}
+	}
+
+
+	/// move the sound to `position` from its start, the sound being
+	/// played or not
+	pub fn seek_to (&mut self, id: SoundId, position: Duration) {
+		let frame = duration_to_frames(position, self.sample_rate.0);
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.seek(frame);
+		}
+	}
+
+
+	/// move the sound forward by `offset` from its current position
+	pub fn seek_by (&mut self, id: SoundId, offset: Duration) {
+		let offset = duration_to_frames(offset, self.sample_rate.0);
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.seek(sound.position() + offset);
+		}
+	}
+
+
+	/// set how long volume changes take to reach their value
+	///
+	/// applies to the changes made after this call
+	pub fn set_volume_smoothing (&mut self, duration: Duration) {
+		self.volume_smoothing = duration;
+	}
+
+
+	/// set the volume of the whole mix
+	pub fn set_master_volume (&mut self, volume: f32) {
+		self.master_volume = volume;
+		self.update_master();
+	}
+
+
+	/// resample the speed of every sound with `quality`, and the sample
+	/// rate of the sounds added from now on
+	pub fn set_resample_quality (&mut self, quality: ResampleQuality) {
+		self.resample_quality = quality;
+		for sound in self.sounds.iter_mut().filter_map(|x| x.sound.as_mut()) {
+			sound.resampler.set_quality(quality);
+		}
+	}
+
+
+	pub fn resample_quality (&self) -> ResampleQuality {
+		self.resample_quality
+	}
+
+
+	/// silence the whole mix, without changing the master volume
+	pub fn set_muted (&mut self, muted: bool) {
+		self.muted = muted;
+		self.update_master();
+	}
+
+
+	/// dither the i16 output with triangular noise, off by default
+	pub fn set_dither (&mut self, dither: bool) {
+		self.dither = dither;
+	}
+
+
+	/// play every channel of the output as the average of all of them
+	pub fn set_mono (&mut self, mono: bool) {
+		self.mono = mono;
+	}
+
+
+	/// limit the output with `config`, or stop limiting it
+	pub fn set_limiter (&mut self, config: Option<LimiterConfig>) {
+		self.limiter_config = config;
+		self.limiter = config.map(|config| Limiter::new(config, self.channels, self.sample_rate.0));
+	}
+
+
+	fn update_master (&mut self) {
+		let target = if self.muted { 0.0 } else { self.master_volume };
+		self.master.set(target, self.sample_rate.frames(self.volume_smoothing));
+	}
+
+
+	/// set the volume of the sound
+	///
+	/// a playing sound moves to the new volume smoothly, a sound
+	/// that is not playing changes right away
+	pub fn set_volume (&mut self, id: SoundId, volume: f32) {
+		let frames = self.sample_rate.frames(self.volume_smoothing);
+		if let Some(sound) = find(&mut self.sounds, id) {
+			let frames = if sound.playing.is_some() { frames } else { 0 };
+			sound.volume.set(volume, frames);
+		}
+	}
+
+
+	/// set the position of the sound between the left (-1.0) and
+	/// the right (1.0) speakers
+	pub fn set_pan (&mut self, id: SoundId, pan: f32) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.pan.set(pan, 0);
+		}
+	}
+
+
+	/// set the gain of each output channel of the sound, the ones
+	/// past the end of `gains` move back to `1.0`
+	pub fn set_channel_gains (&mut self, id: SoundId, gains: Vec<f32>) {
+		let frames = self.sample_rate.frames(self.volume_smoothing);
+		if let Some(sound) = find(&mut self.sounds, id) {
+			let frames = if sound.playing.is_some() { frames } else { 0 };
+			let len = gains.len().max(sound.channel_gains.len());
+			sound.channel_gains.resize_with(len, || Ramp::new(1.0));
+			for (i, gain) in sound.channel_gains.iter_mut().enumerate() {
+				gain.set(gains.get(i).copied().unwrap_or(1.0), frames);
+			}
+		}
+	}
+
+
+	/// set the playback speed of the sound
+	pub fn set_speed (&mut self, id: SoundId, speed: f32) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.speed.set(speed, 0);
+			sound.resampler.set_speed(speed);
+		}
+	}
+
+
+	/// place the sound around the listener, or stop placing it
+	pub fn set_position (&mut self, id: SoundId, position: Option<[f32; 3]>) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			match (sound.emitter.as_mut(), position) {
+				(Some(emitter), Some(position)) => emitter.position = position,
+				(_, position) => sound.emitter = position.map(Emitter::new)
+			}
+		}
+	}
+
+
+	pub fn set_velocity (&mut self, id: SoundId, velocity: [f32; 3]) {
+		if let Some(emitter) = find(&mut self.sounds, id).and_then(|x| x.emitter.as_mut()) {
+			emitter.velocity = velocity;
+		}
+	}
+
+
+	pub fn set_occlusion (&mut self, id: SoundId, occlusion: f32) {
+		let frames = self.sample_rate.frames(self.volume_smoothing);
+		if let Some(sound) = find(&mut self.sounds, id) {
+			let frames = if sound.playing.is_some() { frames } else { 0 };
+			sound.occlusion.get_or_insert_with(Occlusion::new).set(occlusion, frames);
+		}
+	}
+
+
+	pub fn set_attenuation (&mut self, id: SoundId, attenuation: Attenuation) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.attenuation = attenuation;
+		}
+	}
+
+
+	/// move a parameter of the sound with `lfo`, replacing the last
+	/// lfo of `target`
+	pub fn set_lfo (&mut self, id: SoundId, target: LfoTarget, lfo: Option<Lfo>) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.lfos.retain(|x| x.0 != target);
+			if let Some(lfo) = lfo {
+				sound.lfos.push((target, lfo));
+			}
+			if target == LfoTarget::Pitch {
+				sound.resampler.set_speed(sound.speed.value);
+			}
+		}
+	}
+
+
+	/// move the volume of the sound to `target` along `easing`
+	pub fn animate_volume (&mut self, id: SoundId, target: f32, duration: Duration, easing: Easing) {
+		let frames = self.sample_rate.frames(duration);
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.volume.set_eased(target, frames, easing);
+		}
+	}
+
+
+	/// move the pan of the sound to `target` along `easing`
+	pub fn animate_pan (&mut self, id: SoundId, target: f32, duration: Duration, easing: Easing) {
+		let frames = self.sample_rate.frames(duration);
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.pan.set_eased(target, frames, easing);
+		}
+	}
+
+
+	/// move the speed of the sound to `target` along `easing`
+	pub fn animate_speed (&mut self, id: SoundId, target: f32, duration: Duration, easing: Easing) {
+		let frames = self.sample_rate.frames(duration);
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.speed.set_eased(target, frames, easing);
+		}
+	}
+
+
+	/// set if the sound will repeat ever time it reach the end
+	pub fn set_loop (&mut self, id: SoundId, looping: bool) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.looping = looping;
+			sound.loop_count = None;
+			sound.loops_left = None;
+		}
+	}
+
+
+	/// stretch the sound to `tempo` from the next buffer
+	pub fn set_sound_tempo (&mut self, id: SoundId, tempo: f32) {
+		let sample_rate = self.sample_rate.0;
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.tempo = tempo.clamp(0.25, 4.0);
+			sound.update_stretcher(sample_rate);
+		}
+	}
+
+
+	/// shift the pitch of the sound from the next buffer, the stretcher
+	/// keeps its tempo
+	pub fn set_pitch (&mut self, id: SoundId, semitones: f32) {
+		let sample_rate = self.sample_rate.0;
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.pitch = semitones.clamp(-12.0, 12.0);
+			sound.update_stretcher(sample_rate);
+		}
+	}
+
+
+	/// play the sound forward or backward, a stopped sound plays
+	/// backward from its end
+	pub fn set_direction (&mut self, id: SoundId, direction: Direction) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.direction = direction;
+			if !sound.set_reversed(direction == Direction::Reverse) {
+				warn!("reverse playback on a sound source that can't play backward");
+				return;
+			}
+			if sound.playing.is_none() && sound.state() != PlaybackState::Paused {
+				sound.reset();
+			}
+		}
+	}
+
+
+	/// repeat the sound `count` times each time it plays from the
+	/// start, then let it end
+	pub fn set_loop_count (&mut self, id: SoundId, count: u32) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.looping = true;
+			sound.loop_count = Some(count);
+			sound.loops_left = Some(count);
+		}
+	}
+
+
+	/// repeat the sound between two positions after its intro, or stop
+	/// repeating it with `None`
+	pub fn set_loop_region (&mut self, id: SoundId, region: Option<(Duration, Duration)>) {
+		let Some(sound) = find(&mut self.sounds, id) else {
+			return;
+		};
+		let Some((start, end)) = region else {
+			sound.loop_region = None;
+			return;
+		};
+		let sample_rate = sound.data.sample_rate();
+		let total = sound.data.total_frames().unwrap_or(u64::MAX);
+		let end = duration_to_frames(end, sample_rate).min(total);
+		let start = duration_to_frames(start, sample_rate);
+		// the frames read ahead by the resampler are read again, so
+		// the region knows where the source is
+		let position = sound.position();
+		if !sound.data.seek(position) {
+			warn!("loop region on a sound source that can't seek");
+			return;
+		}
+		sound.resampler.seek(position);
+		sound.loop_region = LoopRegion::new(start, end, position);
+	}
+
+
+	/// the sound of `id` was not freed, ids of freed sounds never
+	/// match the sounds that reuse their slot
+	pub fn is_valid (&self, id: SoundId) -> bool {
+		self.sounds.get(id.index as usize).is_some_and(|x| x.generation == id.generation && x.sound.is_some())
+	}
+
+
+	/// mark the sound to be dropped after it reaches the end
+	///
+	/// a sound that is not playing can never be played again, so it
+	/// is freed right away
+	pub fn drop_sound (&mut self, id: SoundId) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.drop = true;
+			if sound.playing.is_none() {
+				self.free_sound(id.index);
+			}
+		}
+	}
+
+
+	/// update sound effect
+	pub fn update_effect (&mut self, id: SoundId, effect: Box<dyn Effect>) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.effects.clear();
+			sound.effects.push((None, effect));
+		}
+	}
+
+
+	pub fn add_effect (&mut self, id: SoundId, effect_id: EffectId, effect: Box<dyn Effect>) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.effects.push((Some(effect_id), effect));
+		}
+	}
+
+
+	pub fn remove_effect (&mut self, id: SoundId, effect_id: EffectId) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.effects.retain(|(id, _)| *id != Some(effect_id));
+		}
+	}
+
+
+	pub fn clear_effects (&mut self, id: SoundId) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.effects.clear();
+		}
+	}
+
+
+	pub fn add_group_effect (&mut self, id: GroupId, effect_id: EffectId, effect: Box<dyn Effect>) {
+		self.groups[id.0 as usize].effects.push((effect_id, effect));
+	}
+
+
+	pub fn remove_group_effect (&mut self, id: GroupId, effect_id: EffectId) {
+		self.groups[id.0 as usize].effects.retain(|(id, _)| *id != effect_id);
+	}
+
+
+	pub fn clear_group_effects (&mut self, id: GroupId) {
+		self.groups[id.0 as usize].effects.clear();
+	}
+
+
+	/// send the sound of `id` to `group` too, or stop if `level` is 0
+	pub fn set_send (&mut self, id: SoundId, group: GroupId, level: f32) {
+		if let Some(sound) = find(&mut self.sounds, id) {
+			sound.sends.retain(|(g, _)| *g != group);
+			if level != 0.0 {
+				sound.sends.push((group, level));
+			}
+		}
+	}
+
+
+	/// turn `group` down by `amount` while a sound of `sidechain`
+	/// plays, replacing the last duck between them
+	pub fn duck (&mut self, group: GroupId, sidechain: GroupId, amount: f32, attack: Duration, release: Duration) {
+		self.stop_ducking(group, sidechain);
+		self.ducks.push(Duck {
+			group,
+			sidechain,
+			amount: amount.clamp(0.0, 1.0),
+			attack,
+			release,
+			gain: 1.0,
+			level: 0.0
+		});
+	}
+
+
+	/// the group comes back up right away
+	pub fn stop_ducking (&mut self, group: GroupId, sidechain: GroupId) {
+		self.ducks.retain(|x| x.group != group || x.sidechain != sidechain);
+	}
+
+
+	/// follow the sidechains of the last buffer, for the next one
+	fn update_ducks (&mut self, frames: u32) {
+		for group in self.groups.iter_mut() {
+			group.ducking = (group.ducking.1, 1.0);
+		}
+		for duck in self.ducks.iter_mut() {
+			let (target, time) = if duck.level > DUCK_THRESHOLD {
+				(1.0 - duck.amount, duck.attack)
+			} else {
+				(1.0, duck.release)
+			};
+			let time = time.as_secs_f32() * self.sample_rate.0 as f32;
+			let smoothing = if time > 0.0 { 1.0 - (-(frames as f32) / time).exp() } else { 1.0 };
+			duck.gain += (target - duck.gain) * smoothing;
+			duck.level = 0.0;
+			self.groups[duck.group.0 as usize].ducking.1 *= duck.gain;
+		}
+	}
+
+
+	/// the group of the shared reverb, created on the first call
+	pub fn reverb_group (&mut self) -> GroupId {
+		if let Some(group) = self.reverb {
+			return group;
+		}
+		let group = self.add_group("reverb", None);
+		let reverb = Reverb::new(ReverbConfig { dry: 0.0, ..ReverbConfig::default() });
+		self.add_group_effect(group, EffectId::next(), Box::new(reverb));
+		self.reverb = Some(group);
+		group
+	}
+
+
+	/// remove the sound at `position` from the playing list, moving
+	/// the last playing sound to its place
+	fn remove_playing (&mut self, position: usize) {
+		let index = self.playing.swap_remove(position);
+		if let Some(sound) = self.sounds[index as usize].sound.as_ref() {
+			sound.shared.levels.store(Levels::default());
+		}
+		if let Some(&index) = self.playing.get(position) {
+			if let Some(sound) = self.sounds[index as usize].sound.as_mut() {
+				sound.playing = Some(position);
+			}
+		}
+	}
 
-			if (self.sounds[s].volume - 1.0).abs() < 1.0 / i16::max_value() as f32 {
-				for i in 0..len {
-					buffer[i] = buffer[i].saturating_add((self.sounds[s].effect)(buf[i] as f32) as i16);
+
+	/// drop the sound in the slot `index`, and make the slot
+	/// available for new sounds
+	fn free_sound (&mut self, index: u32) {
+		let slot = &mut self.sounds[index as usize];
+		slot.sound = None;
+		slot.generation = slot.generation.wrapping_add(1);
+		self.free.push(index);
+	}
+
+
+	/// make the playing sounds that can't be heard, or that are over
+	/// a limit with `StealPolicy::Virtualize`, virtual, and the others
+	/// real again
+	fn update_virtual (&mut self) {
+		self.ranked.clear();
+		for &index in &self.playing {
+			let sound = self.sounds[index as usize].sound.as_ref().unwrap();
+			let group = sound.group.map_or(1.0, |x| self.groups[x.0 as usize].total_gain.1);
+			self.ranked.push((index, sound.priority, sound.audibility(group)));
+		}
+		// the most important, then the loudest, are heard first
+		self.ranked.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(b.2.total_cmp(&a.2)));
+
+		let cap = self.max_voices.filter(|x| x.1 == StealPolicy::Virtualize).map(|x| x.0);
+		let mut voices = 0;
+		self.instances.clear();
+		for &(index, _, audibility) in &self.ranked {
+			let sound = self.sounds[index as usize].sound.as_mut().unwrap();
+			let limit = sound.instance_limit.filter(|x| x.policy == StealPolicy::Virtualize);
+			let instances = limit.map(|limit| match self.instances.iter().position(|x| x.0 == limit.key) {
+				Some(i) => i,
+				None => {
+					self.instances.push((limit.key, 0));
+					self.instances.len() - 1
+				}
+			});
+			// the sounds that can't be virtual are heard over the limits
+			let heard = !sound.can_virtualize() || (
+				audibility >= INAUDIBLE_GAIN
+				&& cap.is_none_or(|x| voices < x)
+				&& limit.zip(instances).is_none_or(|(limit, i)| self.instances[i].1 < limit.max)
+			);
+			if heard {
+				voices += 1;
+				if let Some(i) = instances {
+					self.instances[i].1 += 1;
 				}
+				sound.materialize();
 			} else {
-				for i in 0..len {
-					buffer[i] = buffer[i].saturating_add(((self.sounds[s].effect)(buf[i] as f32) * self.sounds[s].volume) as i16);
+				sound.virtualize();
+			}
+		}
+	}
+
+
+	/// write the mix as f32, for devices that take it
+	///
+	/// full scale is `1.0`, and nothing is clipped
+	pub fn write_samples_f32 (&mut self, buffer: &mut [f32]) -> usize {
+		self.mix(buffer.len());
+		for (b, x) in buffer.iter_mut().zip(&self.mix) {
+			*b = *x / i16::MAX as f32;
+		}
+		// the taps are read as i16
+		self.buffer.resize(buffer.len(), 0);
+		for (b, x) in self.buffer.iter_mut().zip(&self.mix) {
+			*b = x.round() as i16;
+		}
+		write_taps(&mut self.taps, self.replay.as_deref(), &self.buffer);
+		buffer.len()
+	}
+
+
+	/// mix `length` samples of every playing sound into `self.mix`
+	fn mix (&mut self, length: usize) {
+
+		self.process_commands();
+
+		let frame_count = length / self.channels as usize;
+		self.start_scheduled(frame_count as u64);
+		self.clock += frame_count as u64;
+		self.update_ducks(frame_count as u32);
+		self.update_groups(frame_count as u32);
+
+		self.mix.clear();
+		self.mix.resize(length, 0.0);
+		for group in self.groups.iter_mut().filter(|x| !x.effects.is_empty()) {
+			group.buffer.clear();
+			group.buffer.resize(length, 0.0);
+		}
+		if let Some(ambisonics) = self.ambisonics.as_mut() {
+			ambisonics.clear(frame_count);
+		}
+		// the effects of the buses may still be ringing
+		if self.playing.is_empty() && self.effects.is_empty() && self.groups.iter().all(|x| x.effects.is_empty()) {
+			self.master.skip(frame_count as u32);
+			// the limiter still has the end of the last sounds
+			self.finish_mix();
+			return;
+		}
+
+		// only reallocates when the device buffer size changes
+		if self.buffer.len() != length {
+			self.buffer.resize(length, 0);
+		}
+		self.update_virtual();
+		let mut p = 0;
+		while p < self.playing.len() {
+			let index = self.playing[p];
+			let slot = &mut self.sounds[index as usize];
+			let id = SoundId { index, generation: slot.generation };
+			let sound = slot.sound.as_mut().unwrap();
+
+			let excepted = |except: &Vec<GroupId>| except.iter().any(|x| is_in(&self.groups, sound.group, *x));
+			if self.paused_all.as_ref().is_some_and(|x| !excepted(x)) {
+				sound.shared.levels.store(Levels::default());
+				p += 1;
+				continue;
+			}
+
+			// the group gain is interpolated over the buffer
+			let (group_start, group_end) = match sound.group {
+				Some(group) => {
+					let group = &self.groups[group.0 as usize];
+					if group.total_paused {
+						sound.shared.levels.store(Levels::default());
+						p += 1;
+						continue;
+					}
+					group.total_gain
+				},
+				None => (1.0, 1.0)
+			};
+			let group_step = (group_end - group_start) / frame_count as f32;
+			sound.group_speed = sound.group.map_or(1.0, |x| self.groups[x.0 as usize].total_speed);
+
+			// before writing, the doppler shift changes the speed
+			// the ambisonics decode with the hrtf, not every sound
+			let binaural = self.hrtf.is_some() && self.channels == 2 && self.ambisonics.is_none();
+			let panned = !binaural && self.ambisonics.is_none();
+			if let Some(emitter) = sound.emitter.as_mut() {
+				emitter.update(&self.listener, &sound.attenuation, panned, frame_count as u32);
+			}
+
+			// a virtual sound moves on without being read or mixed,
+			// until it reaches its end
+			if sound.virtual_at.is_some() {
+				match sound.advance_virtual(frame_count, self.sample_rate.0) {
+					Some(loops) => {
+						for _ in 0..loops {
+							let _ = self.events.push(SoundEvent::Looped(id));
+						}
+						sound.update_position();
+						p += 1;
+						continue;
+					},
+					None => sound.materialize()
+				}
+			}
+
+			// a scheduled sound starts within the buffer
+			let offset = std::mem::take(&mut sound.offset).min(frame_count);
+			self.buffer[..offset * self.channels as usize].fill(0);
+			let mut len = offset * self.channels as usize;
+			let mut turned = false;
+			loop {
+				let written = sound.write_samples(&mut self.buffer[len..]);
+				len += written;
+				if len < length {
+					let repeat = sound.looping && sound.loops_left != Some(0);
+					// a ping-pong loop turns around at the end instead of
+					// starting again, unless there is nothing to play
+					let turn = repeat && sound.ping_pong && !(turned && written == 0);
+					if turn && sound.set_reversed(sound.reversed_at.is_none()) {
+						turned = true;
+						sound.loops_left = sound.loops_left.map(|x| x - 1);
+						let _ = self.events.push(SoundEvent::Looped(id));
+						continue;
+					}
+					if sound.ping_pong {
+						sound.set_reversed(sound.direction == Direction::Reverse);
+					}
+					sound.reset();
+					if repeat {
+						sound.loops_left = sound.loops_left.map(|x| x - 1);
+						// nobody reading the events is not a reason to
+						// stop the sound, so a full queue is ignored
+						let _ = self.events.push(SoundEvent::Looped(id));
+						continue;
+					}
+				}
+				break;
+			}
+			if let Some(region) = sound.loop_region.as_mut() {
+				for _ in 0..std::mem::take(&mut region.loops) {
+					let _ = self.events.push(SoundEvent::Looped(id));
 				}
 			}
 
-			if len < buffer.len() {
-				if self.sounds[s].drop {
-					let _ = self.sounds.swap_remove(s);
+			self.samples.clear();
+			self.samples.extend(self.buffer[..len].iter().map(|x| *x as f32));
+			for (_, effect) in sound.effects.iter_mut() {
+				effect.process(&mut self.samples, self.channels, self.sample_rate.0);
+			}
+			if let Some(filter) = sound.low_pass.as_mut() {
+				filter.process(&mut self.samples, self.channels, self.sample_rate.0);
+			}
+			if let Some(occlusion) = sound.occlusion.as_mut() {
+				occlusion.process(&mut self.samples, self.channels, self.sample_rate.0);
+			}
+			if let (Some(hrtf), Some(emitter), true) = (self.hrtf.as_ref(), sound.emitter.as_mut(), binaural) {
+				let direction = emitter.direction;
+				emitter.binaural.get_or_insert_with(Binaural::new).process(hrtf, &mut self.samples, direction);
+			}
+
+			// only the front left and right channels are panned
+			let stereo = self.channels >= 2;
+			let spatial_pan = sound.emitter.as_ref().map_or(0.0, |x| x.pan.value);
+			let (mut left, mut right) = if stereo { sound.pan_gains(sound.pan.value + spatial_pan) } else { (1.0, 1.0) };
+			let channels = self.channels as usize;
+			let mut measure = Measure::default();
+			let sample_rate = self.sample_rate.0;
+			let panning = sound.lfos.iter().any(|x| x.0 == LfoTarget::Pan)
+				|| sound.emitter.as_ref().is_some_and(|x| !x.pan.is_done());
+			for (f, samples) in self.samples.chunks_exact_mut(channels).enumerate().skip(offset) {
+				let (distance, spatial_pan) = match sound.emitter.as_mut() {
+					Some(emitter) => (emitter.gain.next(), emitter.pan.next()),
+					None => (1.0, 0.0)
+				};
+				if !sound.pan.is_done() || panning {
+					let pan = sound.pan.next() + spatial_pan;
+					let pan = match sound.lfo(LfoTarget::Pan) {
+						Some(lfo) => {
+							let offset = lfo.value() * lfo.depth();
+							lfo.advance(1, sample_rate);
+							pan + offset
+						},
+						None => pan
+					};
+					if stereo {
+						(left, right) = sound.pan_gains(pan);
+					}
 				}
-				self.playing -= 1;
-				if self.playing > 0 && self.playing < self.sounds.len() {
-					self.sounds.swap(s, self.playing);
-				} else {
-					break;
+				let tremolo = match sound.lfo(LfoTarget::Volume) {
+					Some(lfo) => {
+						// from full volume down to 1 - depth
+						let gain = 1.0 - (1.0 - lfo.value()) * 0.5 * lfo.depth().clamp(0.0, 1.0);
+						lfo.advance(1, sample_rate);
+						gain
+					},
+					None => 1.0
+				};
+				let group = group_start + group_step * f as f32;
+				let envelope = sound.envelope.as_mut().map_or(1.0, |x| x.next());
+				let occlusion = sound.occlusion.as_mut().map_or(1.0, |x| x.gain.next());
+				let parameters = sound.parameter_gain.next();
+				let gain = sound.volume.next() * sound.fade.next() * parameters * envelope * tremolo * distance * occlusion * group;
+				for (c, x) in samples.iter_mut().enumerate() {
+					let gain = match c {
+						0 => gain * left,
+						1 => gain * right,
+						_ => gain
+					};
+					let gain = sound.channel_gains.get_mut(c).map_or(gain, |x| gain * x.next());
+					*x *= gain;
+					measure.add(*x);
+				}
+			}
+			let levels = measure.levels();
+			sound.shared.levels.store(levels);
+			for duck in self.ducks.iter_mut() {
+				if is_in(&self.groups, sound.group, duck.sidechain) {
+					duck.level = duck.level.max(levels.peak);
+				}
+			}
+
+			// placed sounds go in the sound field, which skips the
+			// effects of their groups
+			match (self.ambisonics.as_mut(), sound.emitter.as_ref()) {
+				(Some(ambisonics), Some(emitter)) => ambisonics.encode(&self.samples, self.channels, emitter.world_direction),
+				_ => {
+					let bus = bus_of(&self.groups, sound.group);
+					for (b, x) in bus_buffer(&mut self.groups, &mut self.mix, bus).iter_mut().zip(&self.samples) {
+						*b += x;
+					}
+				}
+			}
+			for (group, level) in sound.sends.iter() {
+				let bus = bus_of(&self.groups, Some(*group));
+				for (b, x) in bus_buffer(&mut self.groups, &mut self.mix, bus).iter_mut().zip(&self.samples) {
+					*b += x * level;
+				}
+			}
+
+			sound.update_position();
+
+			let ended = len < length;
+			let released = sound.envelope.as_ref().is_some_and(|x| x.is_released());
+			let faded = sound.fade_end.is_some() && sound.fade.is_done();
+			if (faded || released) && !ended && (released || sound.fade_end == Some(FadeEnd::Stop)) {
+				sound.reset();
+			}
+			if ended || faded || released {
+				// the sound ended or faded out, the last playing sound
+				// takes its place
+				sound.playing = None;
+				if ended {
+					let _ = self.events.push(SoundEvent::Ended(id));
+				}
+				let state = match sound.fade_end {
+					_ if ended => PlaybackState::Finished,
+					_ if released => PlaybackState::Stopped,
+					Some(FadeEnd::Pause) => PlaybackState::Paused,
+					_ => PlaybackState::Stopped
+				};
+				sound.set_state(state);
+				if state != PlaybackState::Paused {
+					sound.shared.end();
+				}
+				sound.cancel_fade();
+				let drop = sound.drop;
+				self.remove_playing(p);
+				if drop {
+					self.free_sound(index);
 				}
 			} else {
-				s += 1;
+				p += 1;
 			}
 		}
 
-		buffer.len()
+		// a subgroup always comes after its parent, so going backwards
+		// every bus is done before the bus it is mixed into
+		for i in (0..self.groups.len()).rev() {
+			if self.groups[i].effects.is_empty() {
+				continue;
+			}
+			let mut buffer = std::mem::take(&mut self.groups[i].buffer);
+			for (_, effect) in self.groups[i].effects.iter_mut() {
+				effect.process(&mut buffer, self.channels, self.sample_rate.0);
+			}
+			let bus = bus_of(&self.groups, self.groups[i].parent);
+			for (b, x) in bus_buffer(&mut self.groups, &mut self.mix, bus).iter_mut().zip(&buffer) {
+				*b += x;
+			}
+			self.groups[i].buffer = buffer;
+		}
+
+		if let Some(ambisonics) = self.ambisonics.as_mut() {
+			ambisonics.decode(&self.listener, self.hrtf.as_deref(), &mut self.mix, self.channels);
+		}
+
+		for (_, effect) in self.effects.iter_mut() {
+			effect.process(&mut self.mix, self.channels, self.sample_rate.0);
+		}
+
+		// the master gain is applied after every sound was mixed
+		if !self.master.is_done() || self.master.value != 1.0 {
+			for frame in self.mix.chunks_exact_mut(self.channels as usize) {
+				let gain = self.master.next();
+				for b in frame.iter_mut() {
+					*b *= gain;
+				}
+			}
+		}
+
+		self.finish_mix();
+
+	}
+
+
+	/// limit and measure the mix
+	fn finish_mix (&mut self) {
+		if self.mono {
+			stereo::to_mono(&mut self.mix, self.channels);
+		}
+		if let Some(limiter) = self.limiter.as_mut() {
+			limiter.process(&mut self.mix);
+		}
+		let mut measure = Measure::default();
+		for x in &self.mix {
+			measure.add(*x);
+		}
+		self.meter.store(measure.levels());
+	}
+
+
+}
+
+impl SoundSource for Mixer {
+
+
+	fn channels (&self) -> u16 {
+		self.channels
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate.0
+	}
+
+
+	fn reset (&mut self) {}
 
+
+	/// write the mix, converted to i16
+	///
+	/// samples over full scale are clipped, and dithered first when
+	/// dithering is on
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		self.mix(buffer.len());
+		let mut rng = self.rng;
+		for (b, x) in buffer.iter_mut().zip(&self.mix) {
+			let x = if self.dither { *x + tpdf(&mut rng) } else { *x };
+			// `as` saturates to the i16 range
+			*b = x.round() as i16;
+		}
+		self.rng = rng;
+		write_taps(&mut self.taps, self.replay.as_deref(), buffer);
+		buffer.len()
 	}
 
 
 }
 
 
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::sync::atomic::Ordering;
+	use std::time::Duration;
+
+	use crate::{ AudioEngine, Constant, PlaybackState };
+	use super::{ Mixer, SampleRate, SoundId };
+
+
+	/// add an endless sound to `mixer`
+	fn add (mixer: &mut Mixer) -> SoundId {
+		mixer.add_sound(Box::new(Constant::new(0.5).channels(2)), false, |x| x).0
+	}
+
+
+	#[test]
+	fn freed_slot_bumps_the_generation () {
+		let mut mixer = Mixer::new(2, SampleRate(48000));
+		let first = add(&mut mixer);
+		mixer.drop_sound(first);
+		let second = add(&mut mixer);
+		assert_eq!(second.index, first.index);
+		assert_eq!(second.generation, first.generation + 1);
+		assert_ne!(second, first);
+		// a slot is reused only once it's freed
+		let third = add(&mut mixer);
+		assert_ne!(third.index, second.index);
+	}
+
+
+	#[test]
+	fn stale_id_matches_no_sound () {
+		let mut mixer = Mixer::new(2, SampleRate(48000));
+		let stale = add(&mut mixer);
+		mixer.drop_sound(stale);
+		let (id, shared) = mixer.add_sound(Box::new(Constant::new(0.5).channels(2)), false, |x| x);
+		assert!(!mixer.is_valid(stale));
+		assert!(mixer.is_valid(id));
+		// commands with the old id leave the new sound alone
+		mixer.play(stale);
+		mixer.drop_sound(stale);
+		assert!(mixer.is_valid(id));
+		assert_ne!(PlaybackState::from_u8(shared.state.load(Ordering::Relaxed)), PlaybackState::Playing);
+		mixer.play(id);
+		assert_eq!(PlaybackState::from_u8(shared.state.load(Ordering::Relaxed)), PlaybackState::Playing);
+	}
+
+
+	#[test]
+	fn invalid_after_drop () {
+		let (engine, backend) = AudioEngine::offline(2, 1000);
+		let sound = engine.new_sound(Constant::new(0.5).channels(2), |x| x).unwrap();
+		let id = sound.id;
+		assert!(sound.is_valid());
+		drop(sound);
+		// the drop is applied with the next buffer
+		assert!(engine.is_valid(id));
+		backend.advance(1);
+		assert!(!engine.is_valid(id));
+	}
+
+
+	#[test]
+	fn playing_sound_is_freed_at_its_end () {
+		let (engine, backend) = AudioEngine::offline(2, 1000);
+		let source = Constant::new(0.5).channels(2).sample_rate(1000).duration(Duration::from_millis(10));
+		let mut sound = engine.new_sound(source, |x| x).unwrap();
+		let id = sound.id;
+		sound.play();
+		drop(sound);
+		backend.advance(5);
+		assert!(engine.is_valid(id));
+		backend.advance(10);
+		assert!(!engine.is_valid(id));
+	}
+
+
+}
@@ -0,0 +1,246 @@
+
+
+
+//! Chorus and flanger, delays that are moved back and forth by a slow oscillator.
+
+
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::time::Duration;
+
+use crate::effect::Effect;
+
+
+
+/// the longest delay, with the depth added, the delay line is
+/// allocated for it
+const MAX_DELAY: Duration = Duration::from_millis(50);
+
+
+
+/// settings of a [`Modulation`]
+///
+/// see [`ModulationConfig::chorus`] and [`ModulationConfig::flanger`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModulationConfig {
+
+	/// the delay in the middle of the sweep
+	pub delay: Duration,
+	/// how far the delay goes either way of `delay`
+	pub depth: Duration,
+	/// speed of the oscillator, in Hz
+	pub rate: f32,
+	/// how much of the delayed sound comes back, between `-1.0` and
+	/// `1.0`, makes the flanger ring
+	pub feedback: f32,
+	/// gain of the delayed sound
+	pub wet: f32,
+	/// gain of the original sound
+	pub dry: f32
+
+}
+
+impl ModulationConfig {
+
+
+	/// a longer delay with no feedback, sounds like more than one voice
+	pub fn chorus () -> Self {
+		Self {
+			delay: Duration::from_millis(20),
+			depth: Duration::from_millis(5),
+			rate: 0.8,
+			feedback: 0.0,
+			wet: 0.5,
+			dry: 1.0
+		}
+	}
+
+
+	/// a short delay with feedback, the sweeping jet sound
+	pub fn flanger () -> Self {
+		Self {
+			delay: Duration::from_millis(3),
+			depth: Duration::from_millis(2),
+			rate: 0.25,
+			feedback: 0.6,
+			wet: 0.7,
+			dry: 1.0
+		}
+	}
+
+
+}
+
+impl Default for ModulationConfig {
+	fn default () -> Self {
+		Self::chorus()
+	}
+}
+
+
+
+/// the parameters shared with the controls, as the bits of `f32`s
+struct Params {
+	rate: AtomicU32,
+	depth: AtomicU32,
+	feedback: AtomicU32,
+	wet: AtomicU32,
+	dry: AtomicU32
+}
+
+fn load (x: &AtomicU32) -> f32 {
+	f32::from_bits(x.load(Ordering::Relaxed))
+}
+
+fn store (x: &AtomicU32, value: f32) {
+	x.store(value.to_bits(), Ordering::Relaxed);
+}
+
+
+
+/// a modulated delay line, as an [`Effect`]
+///
+/// works on a sound or on a group. the channels are swept a little
+/// apart from each other, which widens stereo sounds
+pub struct Modulation {
+
+	params: Arc<Params>,
+	/// the delay in the middle of the sweep, in seconds
+	delay: f32,
+	/// interleaved, made on the first call for `MAX_DELAY`
+	line: Vec<f32>,
+	/// the frame of `line` written next
+	position: usize,
+	/// of the oscillator, from `0.0` to `1.0`
+	phase: f32,
+	channels: usize,
+	sample_rate: u32
+
+}
+
+impl Modulation {
+
+
+	pub fn new (config: ModulationConfig) -> Self {
+		Self {
+			params: Arc::new(Params {
+				rate: AtomicU32::new(config.rate.to_bits()),
+				depth: AtomicU32::new(config.depth.as_secs_f32().to_bits()),
+				feedback: AtomicU32::new(config.feedback.to_bits()),
+				wet: AtomicU32::new(config.wet.to_bits()),
+				dry: AtomicU32::new(config.dry.to_bits())
+			}),
+			delay: config.delay.as_secs_f32(),
+			line: vec![],
+			position: 0,
+			phase: 0.0,
+			channels: 0,
+			sample_rate: 0
+		}
+	}
+
+
+	pub fn chorus () -> Self {
+		Self::new(ModulationConfig::chorus())
+	}
+
+
+	pub fn flanger () -> Self {
+		Self::new(ModulationConfig::flanger())
+	}
+
+
+	/// change the modulation after it was given to a sound or group
+	pub fn controls (&self) -> ModulationControls {
+		ModulationControls { params: self.params.clone() }
+	}
+
+
+}
+
+impl Effect for Modulation {
+
+	fn process (&mut self, frames: &mut [f32], channels: u16, sample_rate: u32) {
+		let channels = channels as usize;
+		if self.channels != channels || self.sample_rate != sample_rate {
+			let length = (MAX_DELAY.as_secs_f32() * sample_rate as f32) as usize + 2;
+			self.line = vec![0.0; length * channels];
+			self.position = 0;
+			self.channels = channels;
+			self.sample_rate = sample_rate;
+		}
+
+		let length = self.line.len() / channels;
+		let max = (length - 2) as f32;
+		let rate = load(&self.params.rate) / sample_rate as f32;
+		let delay = self.delay * sample_rate as f32;
+		let depth = load(&self.params.depth) * sample_rate as f32;
+		let feedback = load(&self.params.feedback).clamp(-0.95, 0.95);
+		let wet = load(&self.params.wet);
+		let dry = load(&self.params.dry);
+		for frame in frames.chunks_exact_mut(channels) {
+			for (c, x) in frame.iter_mut().enumerate() {
+				// every channel is a quarter of a turn after the one before
+				let phase = self.phase + c as f32 * 0.25;
+				let delay = (delay + depth * (2.0 * PI * phase).sin()).clamp(1.0, max);
+
+				// linear interpolation between the two nearest frames
+				let whole = delay as usize;
+				let fraction = delay - whole as f32;
+				let a = self.line[((self.position + length - whole) % length) * channels + c];
+				let b = self.line[((self.position + length - whole - 1) % length) * channels + c];
+				let delayed = a + (b - a) * fraction;
+
+				self.line[self.position * channels + c] = *x + delayed * feedback;
+				*x = *x * dry + delayed * wet;
+			}
+			self.position = (self.position + 1) % length;
+			self.phase = (self.phase + rate).fract();
+		}
+	}
+
+}
+
+
+
+/// changes a [`Modulation`] from any thread
+///
+/// the changes apply from the next buffer that is mixed
+#[derive(Clone)]
+pub struct ModulationControls {
+	params: Arc<Params>
+}
+
+impl ModulationControls {
+
+
+	/// in Hz
+	pub fn set_rate (&self, rate: f32) {
+		store(&self.params.rate, rate);
+	}
+
+
+	/// the delay stays under 50ms with the depth added
+	pub fn set_depth (&self, depth: Duration) {
+		store(&self.params.depth, depth.as_secs_f32());
+	}
+
+
+	pub fn set_feedback (&self, feedback: f32) {
+		store(&self.params.feedback, feedback);
+	}
+
+
+	pub fn set_wet (&self, wet: f32) {
+		store(&self.params.wet, wet);
+	}
+
+
+	pub fn set_dry (&self, dry: f32) {
+		store(&self.params.dry, dry);
+	}
+
+
+}
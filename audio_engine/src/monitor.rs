@@ -0,0 +1,163 @@
+
+
+
+//! The microphone played through the mixer, for karaoke and the like.
+//!
+//! A [`Monitor`] reads an [`AudioInput`] on the audio thread, so the voice is mixed like any
+//! other sound, with its volume, pan and effects. It keeps a little audio ahead, the latency,
+//! so the input and output callbacks don't have to line up: it waits for it to fill again when
+//! the input runs dry, and drops the oldest audio when the input runs ahead.
+
+
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::time::Duration;
+
+use crate::input::AudioInput;
+use crate::mixer::SoundSource;
+
+
+
+/// the latency of a new monitor
+const DEFAULT_LATENCY: Duration = Duration::from_millis(20);
+
+/// frames dropped at a time when the input runs ahead
+const SKIP_FRAMES: usize = 256;
+
+
+
+/// plays an [`AudioInput`] as it is captured, as a [`SoundSource`]
+///
+/// it never ends, and is silent while it waits for the input. see
+/// [`AudioEngine::open_monitor`](crate::AudioEngine::open_monitor)
+pub struct Monitor {
+
+	input: AudioInput,
+	/// the latency in seconds, as the bits of an `f32`
+	latency: Arc<AtomicU32>,
+	/// the latency the buffered audio was last trimmed to, in frames
+	target: usize,
+	/// silent until the input holds the latency
+	buffering: bool
+
+}
+
+impl Monitor {
+
+
+	pub fn new (input: AudioInput) -> Self {
+		Self {
+			input,
+			latency: Arc::new(AtomicU32::new(DEFAULT_LATENCY.as_secs_f32().to_bits())),
+			target: 0,
+			buffering: true
+		}
+	}
+
+
+	/// how much audio is kept ahead of what is played, `20ms` by
+	/// default. more is safer against dropouts, but the voice is
+	/// heard later
+	pub fn latency (self, latency: Duration) -> Self {
+		self.latency.store(latency.as_secs_f32().to_bits(), Ordering::Relaxed);
+		self
+	}
+
+
+	/// change the latency while it plays
+	pub fn controls (&self) -> MonitorControls {
+		MonitorControls { latency: self.latency.clone() }
+	}
+
+
+	/// drop captured audio until `frames` are left
+	fn trim (&mut self, frames: usize) {
+		let channels = self.input.channels() as usize;
+		let mut scratch = [0; SKIP_FRAMES];
+		let chunk = SKIP_FRAMES / channels * channels;
+		let mut skip = self.input.available().saturating_sub(frames) * channels;
+		while skip > 0 {
+			let len = self.input.read(&mut scratch[..skip.min(chunk)]);
+			if len == 0 {
+				break;
+			}
+			skip -= len.min(skip);
+		}
+	}
+
+
+}
+
+impl SoundSource for Monitor {
+
+	fn channels (&self) -> u16 {
+		self.input.channels()
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.input.sample_rate()
+	}
+
+	fn reset (&mut self) {
+		self.buffering = true;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let channels = self.input.channels() as usize;
+		let frames = buffer.len() / channels;
+		let latency = f32::from_bits(self.latency.load(Ordering::Relaxed)).max(0.0);
+		let target = (latency as f64 * self.input.sample_rate() as f64) as usize;
+
+		// a lower latency is taken at once, a higher one once the
+		// input catches up
+		if target < self.target {
+			self.trim(target + frames);
+		}
+		self.target = target;
+
+		let available = self.input.available();
+		if self.buffering {
+			if available < target + frames {
+				buffer.fill(0);
+				return buffer.len();
+			}
+			self.buffering = false;
+		} else if available > target + frames + target.max(frames) {
+			// the input is faster than the output, or came back from a
+			// stall with more than was asked for
+			self.trim(target + frames);
+		}
+
+		let len = self.input.read(buffer);
+		if len < buffer.len() {
+			buffer[len..].fill(0);
+			self.buffering = true;
+		}
+		buffer.len()
+	}
+
+}
+
+
+
+/// changes the latency of a [`Monitor`] from any thread
+#[derive(Clone)]
+pub struct MonitorControls {
+	latency: Arc<AtomicU32>
+}
+
+impl MonitorControls {
+
+
+	pub fn set_latency (&self, latency: Duration) {
+		self.latency.store(latency.as_secs_f32().to_bits(), Ordering::Relaxed);
+	}
+
+
+	pub fn latency (&self) -> Duration {
+		Duration::from_secs_f32(f32::from_bits(self.latency.load(Ordering::Relaxed)).max(0.0))
+	}
+
+
+}
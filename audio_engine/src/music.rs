@@ -0,0 +1,601 @@
+
+
+
+//! Interactive music, made of stems that follow the intensity of the game.
+//!
+//! A [`MusicTrack`] is a set of stems played together, each heard once the intensity is at
+//! its level, so a fight adds drums and brass to the calm layers. Tracks are switched with a
+//! [`Transition`], which can wait for the next beat or bar of the playing track, crossfade, or
+//! play a stinger in between. Everything is mixed on the audio thread, so the changes land on
+//! the exact frame.
+
+
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU32, Ordering };
+use std::time::Duration;
+
+use crate::converter::{ self, ResampleQuality };
+use crate::easing::Ramp;
+use crate::error::Error;
+use crate::mixer::SoundSource;
+use crate::queue::Queue;
+use crate::transport::Quantize;
+
+
+
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// how long a stem takes to come in or out when the intensity
+/// changes
+const LAYER_FADE: Duration = Duration::from_millis(500);
+
+/// the capacity of the queue of the controls
+const EVENT_QUEUE_CAPACITY: usize = 32;
+
+
+
+/// stems played together, with the tempo they are written in
+///
+/// the stems loop on their own, so they should have the same length
+pub struct MusicTrack {
+
+	stems: Vec<(Box<dyn SoundSource + Send>, f32)>,
+	bpm: f32,
+	beats_per_bar: u32,
+	looped: bool
+
+}
+
+impl MusicTrack {
+
+
+	/// an empty track at `bpm`, with `beats_per_bar` beats a bar
+	pub fn new (bpm: f32, beats_per_bar: u32) -> Self {
+		Self {
+			stems: vec![],
+			bpm,
+			beats_per_bar: beats_per_bar.max(1),
+			looped: true
+		}
+	}
+
+
+	/// add a stem that is heard once the intensity is at `level` or
+	/// above, `0.0` being always heard
+	pub fn stem <T: SoundSource + Send + 'static> (mut self, source: T, level: f32) -> Self {
+		self.stems.push((Box::new(source), level));
+		self
+	}
+
+
+	/// the track loops by default, otherwise it ends with its
+	/// longest stem
+	pub fn looped (mut self, looped: bool) -> Self {
+		self.looped = looped;
+		self
+	}
+
+
+}
+
+
+
+/// how a [`MusicControls::play`] or [`MusicControls::stop`] goes
+/// from the playing track to the next
+pub struct Transition {
+
+	fade: Duration,
+	quantize: Option<Quantize>,
+	stinger: Option<Box<dyn SoundSource + Send>>
+
+}
+
+impl Transition {
+
+
+	/// switch right away
+	pub fn cut () -> Self {
+		Self::crossfade(Duration::ZERO)
+	}
+
+
+	/// fade the playing track out while the next fades in
+	pub fn crossfade (fade: Duration) -> Self {
+		Self { fade, quantize: None, stinger: None }
+	}
+
+
+	/// wait for the next beat or bar of the playing track
+	pub fn on (mut self, quantize: Quantize) -> Self {
+		self.quantize = Some(quantize);
+		self
+	}
+
+
+	/// play `stinger` when the transition starts, and the next track
+	/// once it ends
+	pub fn stinger <T: SoundSource + Send + 'static> (mut self, stinger: T) -> Self {
+		self.stinger = Some(Box::new(stinger));
+		self
+	}
+
+
+}
+
+
+
+/// a transition, as the audio thread takes it
+struct Change {
+	/// `None` stops the music
+	track: Option<Track>,
+	fade: u32,
+	quantize: Option<Quantize>,
+	stinger: Option<Box<dyn SoundSource + Send>>
+}
+
+enum MusicEvent {
+	Change(Change),
+	Stinger(Box<dyn SoundSource + Send>, Option<Quantize>)
+}
+
+
+
+struct Stem {
+	source: Box<dyn SoundSource + Send>,
+	level: f32,
+	gain: Ramp,
+	ended: bool
+}
+
+/// a track being played
+struct Track {
+
+	stems: Vec<Stem>,
+	bpm: f32,
+	beats_per_bar: u32,
+	looped: bool,
+	/// frames played
+	position: u64,
+	/// the gain of the crossfade
+	fade: Ramp
+
+}
+
+impl Track {
+
+
+	/// the frames until the next `quantize` of the track
+	fn frames_to (&self, quantize: Quantize, sample_rate: u32) -> u64 {
+		let beats = match quantize {
+			Quantize::NextBeat => 1.0,
+			Quantize::NextBar => self.beats_per_bar as f64,
+			Quantize::NextBeats(beats) => beats.max(1e-3) as f64
+		};
+		let grid = beats * 60.0 / self.bpm.max(1e-3) as f64 * sample_rate as f64;
+		let next = (self.position as f64 / grid).ceil() * grid;
+		(next.round() as u64).saturating_sub(self.position)
+	}
+
+
+	/// fade the stems to `intensity`, over `frames`
+	fn set_intensity (&mut self, intensity: f32, frames: u32) {
+		for stem in self.stems.iter_mut() {
+			let gain = if intensity >= stem.level { 1.0 } else { 0.0 };
+			if gain != stem.gain.target {
+				stem.gain.set(gain, frames);
+			}
+		}
+	}
+
+
+	/// add the next `mix.len()` samples of the stems to `mix`
+	fn render (&mut self, mix: &mut [f32], track: &mut [f32], scratch: &mut [i16], channels: usize) {
+		track.fill(0.0);
+		for stem in self.stems.iter_mut().filter(|x| !x.ended) {
+			let mut len = 0;
+			// an empty stem writes nothing right after it is reset
+			let mut restarted = false;
+			while len < scratch.len() {
+				let written = stem.source.write_samples(&mut scratch[len..]);
+				len += written;
+				if len == scratch.len() {
+					break;
+				}
+				if !self.looped || (restarted && written == 0) {
+					stem.ended = !self.looped;
+					break;
+				}
+				stem.source.reset();
+				restarted = true;
+			}
+			scratch[len..].fill(0);
+			for (frame, samples) in track.chunks_exact_mut(channels).zip(scratch.chunks_exact(channels)) {
+				let gain = stem.gain.next();
+				for (x, y) in frame.iter_mut().zip(samples) {
+					*x += *y as f32 * gain;
+				}
+			}
+		}
+		for (frame, samples) in mix.chunks_exact_mut(channels).zip(track.chunks_exact(channels)) {
+			let gain = self.fade.next();
+			for (x, y) in frame.iter_mut().zip(samples) {
+				*x += y * gain;
+			}
+		}
+		self.position += (mix.len() / channels) as u64;
+	}
+
+
+	fn is_done (&self) -> bool {
+		self.stems.iter().all(|x| x.ended) || (self.fade.is_done() && self.fade.value == 0.0)
+	}
+
+
+}
+
+
+
+/// the interactive music player, as a [`SoundSource`]
+///
+/// it never ends, and is silent until a track is played with its
+/// [`controls`](Music::controls)
+///
+/// ```
+/// # use audio_engine::{ AudioEngine, Music, MusicTrack, SawWave, SineWave, Transition };
+/// # let (engine, _backend) = AudioEngine::offline(2, 48000);
+/// # let (calm, drums) = (SineWave::new(220.0), SawWave::new(55.0));
+/// let music = Music::new();
+/// let controls = music.controls();
+/// engine.new_sound(music, |x| x)?.play();
+/// controls.play(MusicTrack::new(120.0, 4).stem(calm, 0.0).stem(drums, 0.5), Transition::cut())?;
+/// controls.set_intensity(1.0);
+/// # Ok::<(), audio_engine::Error>(())
+/// ```
+pub struct Music {
+
+	channels: u16,
+	sample_rate: u32,
+	intensity: Arc<AtomicU32>,
+	events: Arc<Queue<MusicEvent>>,
+	/// the intensity the stems were last faded to
+	current_intensity: f32,
+	current: Option<Track>,
+	/// tracks fading out
+	fading: Vec<Track>,
+	/// a transition waiting for its beat, with the frame it starts on
+	pending: Option<(u64, Change)>,
+	/// stingers waiting for their beat
+	scheduled: Vec<(u64, Box<dyn SoundSource + Send>)>,
+	stingers: Vec<Box<dyn SoundSource + Send>>,
+	/// the stinger of a transition, and the track and fade started
+	/// once it ends
+	bridge: Option<(Box<dyn SoundSource + Send>, Option<Track>, u32)>,
+	/// frames played
+	clock: u64,
+	mix: Vec<f32>,
+	track: Vec<f32>,
+	scratch: Vec<i16>
+
+}
+
+impl Music {
+
+
+	/// stereo, at 48kHz
+	pub fn new () -> Self {
+		Self {
+			channels: 2,
+			sample_rate: DEFAULT_SAMPLE_RATE,
+			intensity: Arc::new(AtomicU32::new(0f32.to_bits())),
+			events: Arc::new(Queue::with_capacity(EVENT_QUEUE_CAPACITY)),
+			current_intensity: 0.0,
+			current: None,
+			fading: vec![],
+			pending: None,
+			scheduled: vec![],
+			stingers: vec![],
+			bridge: None,
+			clock: 0,
+			mix: vec![],
+			track: vec![],
+			scratch: vec![]
+		}
+	}
+
+
+	pub fn channels (mut self, channels: u16) -> Self {
+		self.channels = channels.max(1);
+		self
+	}
+
+
+	pub fn sample_rate (mut self, sample_rate: u32) -> Self {
+		self.sample_rate = sample_rate;
+		self
+	}
+
+
+	/// play tracks and change the intensity while it plays
+	///
+	/// the stems are converted to the channels and sample rate the
+	/// music has when the controls are made
+	pub fn controls (&self) -> MusicControls {
+		MusicControls {
+			intensity: self.intensity.clone(),
+			events: self.events.clone(),
+			channels: self.channels,
+			sample_rate: self.sample_rate
+		}
+	}
+
+
+	/// the frames until `quantize` of the playing track, or none when
+	/// nothing plays
+	fn frames_to (&self, quantize: Option<Quantize>) -> u64 {
+		match (quantize, self.current.as_ref()) {
+			(Some(quantize), Some(track)) => track.frames_to(quantize, self.sample_rate),
+			_ => 0
+		}
+	}
+
+
+	fn process_events (&mut self) {
+		while let Some(event) = self.events.pop() {
+			match event {
+				MusicEvent::Change(change) => {
+					let at = self.clock + self.frames_to(change.quantize);
+					self.pending = Some((at, change));
+				},
+				MusicEvent::Stinger(stinger, quantize) => {
+					let at = self.clock + self.frames_to(quantize);
+					self.scheduled.push((at, stinger));
+				}
+			}
+		}
+
+		let intensity = f32::from_bits(self.intensity.load(Ordering::Relaxed));
+		if intensity != self.current_intensity {
+			self.current_intensity = intensity;
+			let frames = (LAYER_FADE.as_secs_f64() * self.sample_rate as f64) as u32;
+			if let Some(track) = self.current.as_mut() {
+				track.set_intensity(intensity, frames);
+			}
+		}
+	}
+
+
+	/// the first frame something happens on, before `end`
+	fn next_event (&self, end: u64) -> u64 {
+		let pending = self.pending.as_ref().map(|x| x.0);
+		self.scheduled.iter().map(|x| x.0).chain(pending).fold(end, u64::min).max(self.clock)
+	}
+
+
+	/// start what is due at the current frame
+	fn fire_events (&mut self) {
+		let mut i = 0;
+		while i < self.scheduled.len() {
+			if self.scheduled[i].0 <= self.clock {
+				let (_, stinger) = self.scheduled.swap_remove(i);
+				self.stingers.push(stinger);
+			} else {
+				i += 1;
+			}
+		}
+
+		if self.pending.as_ref().is_some_and(|x| x.0 <= self.clock) {
+			let (_, change) = self.pending.take().unwrap();
+			self.fade_out(change.fade);
+			match change.stinger {
+				Some(stinger) => self.bridge = Some((stinger, change.track, change.fade)),
+				None => self.start(change.track, change.fade)
+			}
+		}
+	}
+
+
+	fn fade_out (&mut self, fade: u32) {
+		if let Some(mut track) = self.current.take() {
+			if fade > 0 {
+				track.fade.set(0.0, fade);
+				self.fading.push(track);
+			}
+		}
+	}
+
+
+	fn start (&mut self, track: Option<Track>, fade: u32) {
+		self.current = track.map(|mut track| {
+			track.set_intensity(self.current_intensity, 0);
+			track.fade = Ramp::new(if fade > 0 { 0.0 } else { 1.0 });
+			track.fade.set(1.0, fade);
+			track
+		});
+	}
+
+
+	/// mix the samples from `start` to `end`, nothing is due during
+	/// them but the end of the stinger of a transition
+	fn render (&mut self, start: usize, end: usize) {
+		let channels = self.channels as usize;
+		let (mut mix, mut track, mut scratch) = (std::mem::take(&mut self.mix), std::mem::take(&mut self.track), std::mem::take(&mut self.scratch));
+		let (mix_part, track_part, scratch_part) = (&mut mix[start..end], &mut track[start..end], &mut scratch[start..end]);
+
+		for playing in self.current.iter_mut().chain(self.fading.iter_mut()) {
+			playing.render(mix_part, track_part, scratch_part, channels);
+		}
+
+		let mut i = 0;
+		while i < self.stingers.len() {
+			let len = self.stingers[i].write_samples(scratch_part);
+			for (x, y) in mix_part.iter_mut().zip(&scratch_part[..len]) {
+				*x += *y as f32;
+			}
+			if len < scratch_part.len() {
+				self.stingers.swap_remove(i);
+			} else {
+				i += 1;
+			}
+		}
+
+		if let Some((stinger, _, _)) = self.bridge.as_mut() {
+			let len = stinger.write_samples(scratch_part);
+			for (x, y) in mix_part.iter_mut().zip(&scratch_part[..len]) {
+				*x += *y as f32;
+			}
+			if len < scratch_part.len() {
+				// the next track starts right after the stinger
+				let (_, next, fade) = self.bridge.take().unwrap();
+				self.start(next, fade);
+				if let Some(playing) = self.current.as_mut() {
+					playing.render(&mut mix_part[len..], &mut track_part[len..], &mut scratch_part[len..], channels);
+				}
+			}
+		}
+
+		if self.current.as_ref().is_some_and(|x| x.is_done()) {
+			self.current = None;
+		}
+		self.fading.retain(|x| !x.is_done());
+		self.clock += ((end - start) / channels) as u64;
+		(self.mix, self.track, self.scratch) = (mix, track, scratch);
+	}
+
+
+}
+
+impl Default for Music {
+	fn default () -> Self {
+		Self::new()
+	}
+}
+
+impl SoundSource for Music {
+
+	fn channels (&self) -> u16 {
+		self.channels
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn reset (&mut self) {}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		self.process_events();
+
+		let channels = self.channels as usize;
+		let frames = buffer.len() / channels;
+		self.mix.clear();
+		self.mix.resize(frames * channels, 0.0);
+		self.track.resize(frames * channels, 0.0);
+		self.scratch.resize(frames * channels, 0);
+
+		// split where a transition or a stinger starts
+		let end = self.clock + frames as u64;
+		let mut start = 0;
+		while start < frames {
+			self.fire_events();
+			let next = (self.next_event(end) - self.clock) as usize;
+			let next = if next == 0 { frames } else { start + next };
+			self.render(start * channels, next * channels);
+			start = next;
+		}
+
+		for (b, x) in buffer.iter_mut().zip(&self.mix) {
+			*b = x.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+		}
+		buffer.len()
+	}
+
+}
+
+
+
+/// plays tracks on a [`Music`] from any thread
+///
+/// the changes apply from the next mixed buffer
+#[derive(Clone)]
+pub struct MusicControls {
+	intensity: Arc<AtomicU32>,
+	events: Arc<Queue<MusicEvent>>,
+	channels: u16,
+	sample_rate: u32
+}
+
+impl MusicControls {
+
+
+	/// switch to `track` along `transition`
+	///
+	/// fails if a stem can't be converted to the channels of the
+	/// music, or if too many changes are waiting
+	pub fn play (&self, track: MusicTrack, transition: Transition) -> Result<(), Error> {
+		let stems = track.stems
+			.into_iter()
+			.map(|(source, level)| Ok(Stem {
+				source: self.convert(source)?,
+				level,
+				gain: Ramp::new(0.0),
+				ended: false
+			}))
+			.collect::<Result<Vec<_>, Error>>()?;
+		let track = Track {
+			stems,
+			bpm: track.bpm,
+			beats_per_bar: track.beats_per_bar,
+			looped: track.looped,
+			position: 0,
+			fade: Ramp::new(1.0)
+		};
+		self.change(Some(track), transition)
+	}
+
+
+	/// fade out along `transition`, which may end on a stinger
+	pub fn stop (&self, transition: Transition) -> Result<(), Error> {
+		self.change(None, transition)
+	}
+
+
+	/// play `stinger` over the music, on the next `quantize` of the
+	/// track if any
+	pub fn stinger <T: SoundSource + Send + 'static> (&self, stinger: T, quantize: Option<Quantize>) -> Result<(), Error> {
+		let stinger = self.convert(Box::new(stinger))?;
+		self.events.push(MusicEvent::Stinger(stinger, quantize)).map_err(|_| Error::QueueFull)
+	}
+
+
+	/// from `0.0`, the stems of a higher level fade in and the ones
+	/// above it fade out
+	pub fn set_intensity (&self, intensity: f32) {
+		self.intensity.store(intensity.to_bits(), Ordering::Relaxed);
+	}
+
+
+	pub fn intensity (&self) -> f32 {
+		f32::from_bits(self.intensity.load(Ordering::Relaxed))
+	}
+
+
+	fn change (&self, track: Option<Track>, transition: Transition) -> Result<(), Error> {
+		let stinger = transition.stinger.map(|x| self.convert(x)).transpose()?;
+		let change = Change {
+			track,
+			fade: (transition.fade.as_secs_f64() * self.sample_rate as f64) as u32,
+			quantize: transition.quantize,
+			stinger
+		};
+		self.events.push(MusicEvent::Change(change)).map_err(|_| Error::QueueFull)
+	}
+
+
+	/// `source` in the channels and sample rate of the music
+	fn convert (&self, source: Box<dyn SoundSource + Send>) -> Result<Box<dyn SoundSource + Send>, Error> {
+		converter::convert(source, self.channels, self.sample_rate, ResampleQuality::default())
+	}
+
+
+}
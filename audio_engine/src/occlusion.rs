@@ -0,0 +1,65 @@
+
+
+
+//! Muffling of sounds behind walls, from how occluded they are.
+
+
+
+use crate::biquad::{ Biquad, BiquadControls };
+use crate::easing::Ramp;
+use crate::effect::Effect;
+
+
+
+/// the cutoff of the low pass when the sound isn't occluded
+const OPEN_CUTOFF: f32 = 20000.0;
+
+/// the cutoff when the sound is fully occluded
+const OCCLUDED_CUTOFF: f32 = 500.0;
+
+/// the gain when the sound is fully occluded, -12 dB
+const OCCLUDED_GAIN: f32 = 0.25;
+
+
+
+/// the low pass and the gain of an occluded sound
+pub (crate) struct Occlusion {
+
+	filter: Biquad,
+	controls: BiquadControls,
+	/// multiplied into the gain of the sound, frame by frame
+	pub gain: Ramp,
+	amount: f32
+
+}
+
+impl Occlusion {
+
+
+	pub fn new () -> Self {
+		let filter = Biquad::low_pass(OPEN_CUTOFF);
+		let controls = filter.controls();
+		Self { filter, controls, gain: Ramp::new(1.0), amount: 0.0 }
+	}
+
+
+	/// `amount` from `0.0`, not occluded, to `1.0`, behind a thick
+	/// wall. the gain moves there over `frames`
+	pub fn set (&mut self, amount: f32, frames: u32) {
+		self.amount = amount.clamp(0.0, 1.0);
+		// the cutoff falls by the same ratio for every step, which is
+		// heard as even
+		self.controls.set_cutoff(OPEN_CUTOFF * (OCCLUDED_CUTOFF / OPEN_CUTOFF).powf(self.amount));
+		self.gain.set(1.0 + (OCCLUDED_GAIN - 1.0) * self.amount, frames);
+	}
+
+
+	/// filter `frames`, unless the sound is not occluded
+	pub fn process (&mut self, frames: &mut [f32], channels: u16, sample_rate: u32) {
+		if self.amount > 0.0 {
+			self.filter.process(frames, channels, sample_rate);
+		}
+	}
+
+
+}
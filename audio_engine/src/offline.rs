@@ -0,0 +1,354 @@
+
+
+
+//! Mixing without an output device.
+//!
+//! The caller decides when time passes, so the output only depends on what was played and
+//! how far the backend was advanced. This makes sounds and sources testable in CI.
+
+
+
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+
+use crate::mixer::{ self, Mixer, SoundSource };
+
+
+
+/// how many frames [`render`] mixes at a time, like a device
+/// callback would
+const RENDER_CHUNK_FRAMES: usize = 1024;
+
+
+
+/// the output of an engine created with [`AudioEngine::offline`]
+///
+/// every call mixes the next frames, like the callback of a device
+/// would. commands sent to the engine before a call are applied at
+/// its start
+///
+/// [`AudioEngine::offline`]: crate::AudioEngine::offline
+pub struct OfflineBackend {
+	mixer: Arc<Mutex<Mixer>>
+}
+
+impl OfflineBackend {
+
+
+	pub (crate) fn new (mixer: Arc<Mutex<Mixer>>) -> Self {
+		Self { mixer }
+	}
+
+
+	pub fn channels (&self) -> u16 {
+		self.mixer.lock().unwrap().channels()
+	}
+
+
+	pub fn sample_rate (&self) -> u32 {
+		self.mixer.lock().unwrap().sample_rate()
+	}
+
+
+	/// mix the next `frames` frames, interleaved
+	pub fn advance (&self, frames: usize) -> Vec<i16> {
+		let mut buffer = vec![0; frames * self.channels() as usize];
+		self.advance_into(&mut buffer);
+		buffer
+	}
+
+
+	/// mix the next frames into `buffer`, overwriting it
+	///
+	/// the length of `buffer` should be a multiple of the channels
+	pub fn advance_into (&self, buffer: &mut [i16]) {
+		buffer.fill(0);
+		self.mixer.lock().unwrap().write_samples(buffer);
+	}
+
+
+	/// mix the next `duration`, as fast as possible
+	pub fn render (&self, duration: Duration) -> Vec<i16> {
+		render(&self.mixer, duration)
+	}
+
+
+}
+
+
+
+/// mix the next `duration` of `mixer` into a new buffer
+///
+/// the lock is taken for every chunk, so a long render does not
+/// block the engine
+pub (crate) fn render (mixer: &Mutex<Mixer>, duration: Duration) -> Vec<i16> {
+	let (channels, frames) = {
+		let mixer = mixer.lock().unwrap();
+		(mixer.channels() as usize, mixer::SampleRate(mixer.sample_rate()).frames(duration) as usize)
+	};
+	let mut buffer = vec![0; frames * channels];
+	for chunk in buffer.chunks_mut(RENDER_CHUNK_FRAMES * channels) {
+		mixer.lock().unwrap().write_samples(chunk);
+	}
+	buffer
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::time::Duration;
+
+	use crate::{ AudioEngine, Constant, FnSource, LimiterConfig, OfflineBackend, PlaybackState, Silence };
+
+
+	/// half of full scale, as a [`Constant`] of `0.5` writes it
+	const HALF: i16 = 16383;
+
+
+	/// an engine without the limiter or the volume smoothing, so the
+	/// output is the sources as they are mixed
+	fn engine (channels: u16, sample_rate: u32) -> (AudioEngine, OfflineBackend) {
+		let (engine, backend) = AudioEngine::offline(channels, sample_rate);
+		engine.set_limiter(None);
+		engine.set_volume_smoothing(Duration::ZERO);
+		(engine, backend)
+	}
+
+
+	/// a ramp of one thousandth of full scale per frame
+	fn ramp () -> FnSource<impl FnMut(u64) -> f32> {
+		FnSource::new(|i| i as f32 / 1000.0).sample_rate(1000)
+	}
+
+
+	/// frame `i` of [`ramp`]
+	fn ramp_at (i: u64) -> i16 {
+		(i as f32 / 1000.0 * i16::MAX as f32) as i16
+	}
+
+
+	#[test]
+	fn silent_until_played () {
+		let (engine, backend) = engine(2, 48000);
+		let mut sound = engine.new_sound(Constant::new(0.5).channels(2), |x| x).unwrap();
+		assert!(backend.advance(64).iter().all(|&x| x == 0));
+		sound.play();
+		assert!(backend.advance(64).iter().all(|&x| x == HALF));
+		assert_eq!(sound.position_frames(), 64);
+		assert_eq!(sound.state(), PlaybackState::Playing);
+	}
+
+
+	#[test]
+	fn pause_keeps_the_position () {
+		let (engine, backend) = engine(1, 1000);
+		let mut sound = engine.new_sound(ramp(), |x| x).unwrap();
+		sound.play();
+		backend.advance(10);
+		sound.pause();
+		assert!(backend.advance(10).iter().all(|&x| x == 0));
+		assert_eq!(sound.position_frames(), 10);
+		assert_eq!(sound.state(), PlaybackState::Paused);
+		sound.play();
+		assert_eq!(backend.advance(1)[0], ramp_at(10));
+	}
+
+
+	#[test]
+	fn stop_goes_back_to_the_start () {
+		let (engine, backend) = engine(1, 1000);
+		let mut sound = engine.new_sound(ramp(), |x| x).unwrap();
+		sound.play();
+		let first = backend.advance(10);
+		sound.stop();
+		assert!(backend.advance(10).iter().all(|&x| x == 0));
+		assert_eq!(sound.position_frames(), 0);
+		assert_eq!(sound.state(), PlaybackState::Stopped);
+		sound.play();
+		assert_eq!(backend.advance(10), first);
+	}
+
+
+	#[test]
+	fn ends_with_the_source () {
+		let (engine, backend) = engine(1, 1000);
+		let mut sound = engine.new_sound(Constant::new(0.5).sample_rate(1000).duration(Duration::from_millis(10)), |x| x).unwrap();
+		sound.play();
+		let output = backend.advance(20);
+		assert!(output[..10].iter().all(|&x| x == HALF));
+		assert!(output[10..].iter().all(|&x| x == 0));
+		assert_eq!(sound.state(), PlaybackState::Finished);
+	}
+
+
+	#[test]
+	fn set_volume () {
+		let (engine, backend) = engine(2, 48000);
+		let mut sound = engine.new_sound(Constant::new(0.5).channels(2), |x| x).unwrap();
+		sound.set_volume(0.5);
+		sound.play();
+		assert!(backend.advance(64).iter().all(|&x| x == HALF / 2 + 1));
+		sound.set_volume(0.0);
+		assert!(backend.advance(64).iter().all(|&x| x == 0));
+	}
+
+
+	#[test]
+	fn volume_is_smoothed () {
+		let (engine, backend) = AudioEngine::offline(1, 48000);
+		engine.set_limiter(None);
+		let mut sound = engine.new_sound(Constant::new(0.5), |x| x).unwrap();
+		sound.play();
+		backend.advance(64);
+		sound.set_volume(0.0);
+		let output = backend.advance(4800);
+		// it moves down over the 10ms, without jumping
+		assert!(output.windows(2).all(|x| x[1] <= x[0] && x[0] - x[1] < 100));
+		assert!(output[0] > HALF / 2);
+		assert_eq!(*output.last().unwrap(), 0);
+	}
+
+
+	#[test]
+	fn fade_in () {
+		let (engine, backend) = engine(1, 48000);
+		let mut sound = engine.new_sound(Constant::new(0.5), |x| x).unwrap();
+		sound.fade_in(Duration::from_millis(1));
+		let output = backend.advance(96);
+		assert_eq!(output[0], 0);
+		assert!(output[..48].windows(2).all(|x| x[1] > x[0]));
+		assert!(output[48..].iter().all(|&x| x == HALF));
+		assert_eq!(sound.state(), PlaybackState::Playing);
+	}
+
+
+	#[test]
+	fn fade_out_pauses () {
+		let (engine, backend) = engine(1, 48000);
+		let mut sound = engine.new_sound(Constant::new(0.5), |x| x).unwrap();
+		sound.play();
+		backend.advance(64);
+		sound.fade_out(Duration::from_millis(1));
+		let output = backend.advance(96);
+		assert_eq!(output[0], HALF);
+		assert!(output[..48].windows(2).all(|x| x[1] < x[0]));
+		assert!(output[48..].iter().all(|&x| x == 0));
+		assert_eq!(sound.state(), PlaybackState::Paused);
+		// and plays at full volume again
+		sound.play();
+		assert!(backend.advance(64).iter().all(|&x| x == HALF));
+	}
+
+
+	#[test]
+	fn loop_region () {
+		let (engine, backend) = engine(1, 1000);
+		let mut sound = engine.new_sound(ramp(), |x| x).unwrap();
+		sound.set_loop_region(Duration::from_millis(2), Duration::from_millis(5));
+		sound.play();
+		let expected: Vec<i16> = [0, 1, 2, 3, 4, 2, 3, 4, 2, 3, 4, 2].into_iter().map(ramp_at).collect();
+		assert_eq!(backend.advance(12), expected);
+
+		// the sound plays on past the region once it is cleared
+		sound.clear_loop_region();
+		let expected: Vec<i16> = [3, 4, 5, 6].into_iter().map(ramp_at).collect();
+		assert_eq!(backend.advance(4), expected);
+	}
+
+
+	#[test]
+	fn looping_sound () {
+		let (engine, backend) = engine(1, 1000);
+		let mut sound = engine.new_sound(ramp().duration(Duration::from_millis(4)), |x| x).unwrap();
+		sound.set_loop(true);
+		sound.play();
+		let expected: Vec<i16> = [0, 1, 2, 3, 0, 1, 2, 3, 0, 1].into_iter().map(ramp_at).collect();
+		assert_eq!(backend.advance(10), expected);
+		assert_eq!(sound.state(), PlaybackState::Playing);
+	}
+
+
+
+	#[test]
+	fn pan_mono () {
+		let (engine, backend) = engine(2, 48000);
+		let mut sound = engine.new_sound(Constant::new(0.5), |x| x).unwrap();
+		sound.play();
+		let center = backend.advance(1);
+		assert_eq!(center[0], center[1]);
+		// constant power, so louder on one side than both at the center
+		sound.set_pan(-1.0);
+		let left = backend.advance(1);
+		assert_eq!(left[1], 0);
+		assert_eq!(left[0], (HALF as f32 * std::f32::consts::SQRT_2).round() as i16);
+		sound.set_pan(1.0);
+		assert_eq!(backend.advance(1), [0, left[0]]);
+	}
+
+
+	#[test]
+	fn pan_stereo () {
+		let (engine, backend) = engine(2, 48000);
+		let mut sound = engine.new_sound(Constant::new(0.5).channels(2), |x| x).unwrap();
+		sound.set_pan(-1.0);
+		sound.play();
+		// the left channel stays, the right one is lowered
+		assert_eq!(backend.advance(1), [HALF, 0]);
+		sound.set_pan(0.5);
+		let right = backend.advance(1);
+		assert!(right[0] > 0 && right[0] < HALF);
+		assert_eq!(right[1], HALF);
+	}
+
+
+	#[test]
+	fn sounds_add_up () {
+		let (engine, backend) = engine(1, 1000);
+		let mut a = engine.new_sound(Constant::new(0.25).sample_rate(1000), |x| x).unwrap();
+		let mut b = engine.new_sound(ramp(), |x| x).unwrap();
+		a.play();
+		b.play();
+		let quarter = (0.25 * i16::MAX as f32) as i16;
+		let expected: Vec<i16> = (0..8).map(|i| quarter + ramp_at(i)).collect();
+		assert_eq!(backend.advance(8), expected);
+		// and clip without the limiter
+		let mut c = engine.new_sound(Constant::new(1.0).sample_rate(1000), |x| x).unwrap();
+		c.play();
+		assert!(backend.advance(8).iter().all(|&x| x == i16::MAX));
+	}
+
+
+	#[test]
+	fn limiter () {
+		let (engine, backend) = AudioEngine::offline(2, 48000);
+		let threshold = (LimiterConfig::default().threshold * i16::MAX as f32).round() as i16;
+		let mut a = engine.new_sound(Constant::new(0.8).channels(2), |x| x).unwrap();
+		let mut b = engine.new_sound(Constant::new(0.8).channels(2), |x| x).unwrap();
+		a.play();
+		b.play();
+		let output = backend.advance(4800);
+		assert!(output.iter().all(|&x| x <= threshold));
+		// it is held at the threshold, not turned down further
+		assert!(output[2000..].iter().all(|&x| x == threshold));
+	}
+
+
+	#[test]
+	fn silence_and_durations () {
+		let (engine, backend) = engine(2, 48000);
+		let mut silence = engine.new_sound(Silence::new().channels(2).duration(Duration::from_millis(1)), |x| x).unwrap();
+		let mut constant = engine.new_sound(Constant::new(0.5).channels(2).duration(Duration::from_millis(2)), |x| x).unwrap();
+		assert_eq!(silence.duration_frames(), Some(48));
+		assert_eq!(constant.duration_frames(), Some(96));
+		silence.play();
+		constant.play();
+		let output = backend.advance(128);
+		assert!(output[..192].iter().all(|&x| x == HALF));
+		assert!(output[192..].iter().all(|&x| x == 0));
+		assert_eq!(silence.state(), PlaybackState::Finished);
+	}
+
+
+}
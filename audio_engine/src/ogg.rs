@@ -0,0 +1,139 @@
+
+
+
+use lewton::inside_ogg::OggStreamReader;
+use log::error;
+
+use std::io::{ Read, Seek, SeekFrom };
+
+use crate::mixer::SoundSource;
+
+
+
+/// Ogg Vorbis File Decoder
+///
+/// packets are decoded as they are needed, so the whole file is
+/// never decoded up front
+pub struct OggDecoder <T: Seek + Read + Send + 'static> {
+
+	/// only `None` while `reset` is rebuilding it
+	reader: Option<OggStreamReader<T>>,
+	channels: u16,
+	sample_rate: u32,
+	/// the last decoded packet, interleaved
+	buffer: Vec<i16>,
+	/// how many samples of `buffer` were already written out
+	index: usize,
+	/// the stream reached its end or failed to decode
+	done: bool
+
+}
+
+impl <T: Seek + Read + Send + 'static> OggDecoder<T> {
+
+
+	/// Create a new ogg vorbis file decoder
+	pub fn new (data: T) -> Result<Self, lewton::VorbisError> {
+		let reader = OggStreamReader::new(data)?;
+		Ok(Self {
+			channels: reader.ident_hdr.audio_channels as u16,
+			sample_rate: reader.ident_hdr.audio_sample_rate,
+			reader: Some(reader),
+			buffer: vec![],
+			index: 0,
+			done: false
+		})
+	}
+
+
+	/// decode the next packet into `buffer`
+	///
+	/// return false if there is nothing more to decode
+	fn next_packet (&mut self) -> bool {
+		let reader = self.reader.as_mut().unwrap();
+		loop {
+			match reader.read_dec_packet_itl() {
+				// packets may be empty, like the first one of the stream
+				Ok(Some(packet)) if packet.is_empty() => continue,
+				Ok(Some(packet)) => {
+					self.buffer = packet;
+					self.index = 0;
+					return true;
+				},
+				Ok(None) => return false,
+				Err(err) => {
+					// same as the wav decoder, an error ends the sound
+					error!("error while decoding ogg: {}", err);
+					return false;
+				}
+			}
+		}
+	}
+
+
+}
+
+impl <T: Seek + Read + Send + 'static> SoundSource for OggDecoder<T> {
+
+
+	fn reset (&mut self) {
+		// seeking to granule 0 may land on the header pages, so the
+		// stream is read again from the start instead
+		let mut data = self.reader.take().unwrap().into_inner().into_inner();
+		data.seek(SeekFrom::Start(0)).unwrap();
+		self.reader = Some(OggStreamReader::new(data).unwrap());
+		self.buffer.clear();
+		self.index = 0;
+		self.done = false;
+	}
+
+
+	/// with a page granularity, so it may land a few thousand
+	/// frames before `frame`
+	fn seek (&mut self, frame: u64) -> bool {
+		if frame == 0 {
+			self.reset();
+			return true;
+		}
+		let reader = self.reader.as_mut().unwrap();
+		if let Err(err) = reader.seek_absgp_pg(frame) {
+			error!("error while seeking ogg: {}", err);
+			self.done = true;
+			return true;
+		}
+		self.buffer.clear();
+		self.index = 0;
+		self.done = false;
+		true
+	}
+
+
+	fn channels (&self) -> u16 {
+		self.channels
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+
+		let mut len = 0;
+		while len < buffer.len() && !self.done {
+			if self.index == self.buffer.len() && !self.next_packet() {
+				self.done = true;
+				break;
+			}
+			let n = (self.buffer.len() - self.index).min(buffer.len() - len);
+			buffer[len..len + n].copy_from_slice(&self.buffer[self.index..self.index + n]);
+			self.index += n;
+			len += n;
+		}
+		len
+
+	}
+
+
+}
@@ -0,0 +1,592 @@
+
+
+
+//! OpenSL ES output and input, for the Android versions before AAudio.
+//!
+//! OpenSL ES is on every Android version, but it has more latency than AAudio. The player is
+//! fed from a queue of two buffers, each refilled by the mixer when the other starts playing,
+//! and the recorder fills a queue of two buffers the same way. Android allows a single engine
+//! object, which the player and the recorder share.
+
+
+
+use std::ffi::{ c_char, c_void, CStr };
+use std::ptr;
+use std::sync::{ Arc, Mutex, Weak };
+
+use crate::input::{ self, InputConfig };
+use crate::layout::ChannelLayout;
+use crate::mixer::{ self, Mixer, SoundSource };
+use crate::tap::{ Tap, TapWriter };
+
+
+
+type SLresult = u32;
+type SLboolean = u32;
+type SLInterfaceID = *const c_void;
+type SLObjectItf = *const *const ObjectVtable;
+type SLEngineItf = *const *const EngineVtable;
+type SLPlayItf = *const *const PlayVtable;
+type SLRecordItf = *const *const RecordVtable;
+type SLAndroidSimpleBufferQueueItf = *const *const BufferQueueVtable;
+type SLAndroidConfigurationItf = *const *const ConfigurationVtable;
+type BufferQueueCallback = extern "C" fn (SLAndroidSimpleBufferQueueItf, *mut c_void);
+
+const SL_RESULT_SUCCESS: SLresult = 0;
+const SL_BOOLEAN_FALSE: SLboolean = 0;
+const SL_BOOLEAN_TRUE: SLboolean = 1;
+const SL_PLAYSTATE_PLAYING: u32 = 3;
+const SL_RECORDSTATE_RECORDING: u32 = 3;
+const SL_DATAFORMAT_PCM: u32 = 2;
+const SL_DATALOCATOR_IODEVICE: u32 = 3;
+const SL_DATALOCATOR_OUTPUTMIX: u32 = 4;
+const SL_IODEVICE_AUDIOINPUT: u32 = 1;
+const SL_DEFAULTDEVICEID_AUDIOINPUT: u32 = 0xFFFFFFFF;
+const SL_DATALOCATOR_ANDROIDSIMPLEBUFFERQUEUE: u32 = 0x800007BD;
+const SL_SPEAKER_FRONT_LEFT: u32 = 1;
+const SL_SPEAKER_FRONT_RIGHT: u32 = 2;
+const SL_SPEAKER_FRONT_CENTER: u32 = 4;
+const SL_BYTEORDER_LITTLEENDIAN: u32 = 2;
+const SL_ANDROID_KEY_RECORDING_PRESET: &CStr = c"androidRecordingPreset";
+/// echo cancellation, noise suppression and gain control, as the
+/// device does them
+const SL_ANDROID_RECORDING_PRESET_VOICE_COMMUNICATION: u32 = 4;
+
+/// OpenSL ES can't tell the rate of the device, this is the most
+/// common one, anything else is resampled by android
+const SAMPLE_RATE: u32 = 48000;
+/// 10ms at `SAMPLE_RATE`
+const BUFFER_FRAMES: usize = 480;
+
+
+
+// only the functions that are used are typed, the others are kept as
+// pointers so the layout matches the headers
+
+#[repr(C)]
+struct ObjectVtable {
+	realize: unsafe extern "C" fn (SLObjectItf, SLboolean) -> SLresult,
+	_resume: *const c_void,
+	_get_state: *const c_void,
+	get_interface: unsafe extern "C" fn (SLObjectItf, SLInterfaceID, *mut c_void) -> SLresult,
+	_register_callback: *const c_void,
+	_abort_async_operation: *const c_void,
+	destroy: unsafe extern "C" fn (SLObjectItf)
+}
+
+#[repr(C)]
+struct EngineVtable {
+	_create_led_device: *const c_void,
+	_create_vibra_device: *const c_void,
+	create_audio_player: unsafe extern "C" fn (
+		SLEngineItf,
+		*mut SLObjectItf,
+		*mut DataSource,
+		*mut DataSink,
+		u32,
+		*const SLInterfaceID,
+		*const SLboolean
+	) -> SLresult,
+	create_audio_recorder: unsafe extern "C" fn (
+		SLEngineItf,
+		*mut SLObjectItf,
+		*mut DataSource,
+		*mut DataSink,
+		u32,
+		*const SLInterfaceID,
+		*const SLboolean
+	) -> SLresult,
+	_create_midi_player: *const c_void,
+	_create_listener: *const c_void,
+	_create_3d_group: *const c_void,
+	create_output_mix: unsafe extern "C" fn (SLEngineItf, *mut SLObjectItf, u32, *const SLInterfaceID, *const SLboolean) -> SLresult
+}
+
+#[repr(C)]
+struct PlayVtable {
+	set_play_state: unsafe extern "C" fn (SLPlayItf, u32) -> SLresult
+}
+
+#[repr(C)]
+struct RecordVtable {
+	set_record_state: unsafe extern "C" fn (SLRecordItf, u32) -> SLresult
+}
+
+#[repr(C)]
+struct BufferQueueVtable {
+	enqueue: unsafe extern "C" fn (SLAndroidSimpleBufferQueueItf, *const c_void, u32) -> SLresult,
+	_clear: *const c_void,
+	_get_state: *const c_void,
+	register_callback: unsafe extern "C" fn (SLAndroidSimpleBufferQueueItf, BufferQueueCallback, *mut c_void) -> SLresult
+}
+
+#[repr(C)]
+struct ConfigurationVtable {
+	set_configuration: unsafe extern "C" fn (SLAndroidConfigurationItf, *const c_char, *const c_void, u32) -> SLresult
+}
+
+#[repr(C)]
+struct BufferQueueLocator {
+	locator_type: u32,
+	num_buffers: u32
+}
+
+#[repr(C)]
+struct PcmFormat {
+	format_type: u32,
+	num_channels: u32,
+	/// in milliHertz
+	samples_per_sec: u32,
+	bits_per_sample: u32,
+	container_size: u32,
+	channel_mask: u32,
+	endianness: u32
+}
+
+#[repr(C)]
+struct OutputMixLocator {
+	locator_type: u32,
+	output_mix: SLObjectItf
+}
+
+#[repr(C)]
+struct IoDeviceLocator {
+	locator_type: u32,
+	device_type: u32,
+	device_id: u32,
+	device: SLObjectItf
+}
+
+#[repr(C)]
+struct DataSource {
+	locator: *mut c_void,
+	format: *mut c_void
+}
+
+#[repr(C)]
+struct DataSink {
+	locator: *mut c_void,
+	format: *mut c_void
+}
+
+#[link(name = "OpenSLES")]
+extern "C" {
+	static SL_IID_ENGINE: SLInterfaceID;
+	static SL_IID_PLAY: SLInterfaceID;
+	static SL_IID_RECORD: SLInterfaceID;
+	static SL_IID_ANDROIDSIMPLEBUFFERQUEUE: SLInterfaceID;
+	static SL_IID_ANDROIDCONFIGURATION: SLInterfaceID;
+
+	fn slCreateEngine (
+		engine: *mut SLObjectItf,
+		num_options: u32,
+		options: *const c_void,
+		num_interfaces: u32,
+		interface_ids: *const SLInterfaceID,
+		interface_required: *const SLboolean
+	) -> SLresult;
+}
+
+
+
+fn check (result: SLresult, err: &'static str) -> Result<(), &'static str> {
+	if result == SL_RESULT_SUCCESS {
+		Ok(())
+	} else {
+		log::error!("{}: opensl es error {}", err, result);
+		Err(err)
+	}
+}
+
+
+
+/// the engine object, shared by the player and the recorder
+struct Engine {
+	object: SLObjectItf,
+	interface: SLEngineItf
+}
+
+// OpenSL ES objects can be used from any thread
+unsafe impl Send for Engine {}
+unsafe impl Sync for Engine {}
+
+impl Engine {
+
+	/// the engine, created if neither the player nor the recorder
+	/// holds it
+	fn get () -> Result<Arc<Engine>, &'static str> {
+		static ENGINE: Mutex<Weak<Engine>> = Mutex::new(Weak::new());
+		let mut shared = ENGINE.lock().unwrap();
+		if let Some(engine) = shared.upgrade() {
+			return Ok(engine);
+		}
+
+		let mut engine = Engine { object: ptr::null(), interface: ptr::null() };
+		// on error, `Drop` destroys what was created so far
+		// SAFETY: the engine is realized before its interface is used
+		unsafe {
+			check(slCreateEngine(&mut engine.object, 0, ptr::null(), 0, ptr::null(), ptr::null()), "failed to create opensl es engine")?;
+			check(((**engine.object).realize)(engine.object, SL_BOOLEAN_FALSE), "failed to realize opensl es engine")?;
+			check(
+				((**engine.object).get_interface)(engine.object, SL_IID_ENGINE, &mut engine.interface as *mut _ as *mut c_void),
+				"failed to get opensl es engine interface"
+			)?;
+		}
+		let engine = Arc::new(engine);
+		*shared = Arc::downgrade(&engine);
+		Ok(engine)
+	}
+
+}
+
+impl Drop for Engine {
+	fn drop (&mut self) {
+		// SAFETY: the objects created from the engine hold it, so they
+		// are all destroyed already
+		if !self.object.is_null() {
+			unsafe { ((**self.object).destroy)(self.object) };
+		}
+	}
+}
+
+
+
+/// owned by the buffer queue callback, while the player exists
+struct CallbackData {
+	mixer: Arc<Mutex<Mixer>>,
+	queue: SLAndroidSimpleBufferQueueItf,
+	buffers: [Vec<i16>; 2],
+	/// the buffer to fill next
+	next: usize
+}
+
+impl CallbackData {
+
+	/// mix the next buffer and queue it after the one playing
+	fn enqueue (&mut self) {
+		let buffer = &mut self.buffers[self.next];
+		buffer.fill(0);
+		if let Ok(mut mixer) = self.mixer.lock() {
+			mixer.write_samples(buffer);
+		}
+		let len = (buffer.len() * std::mem::size_of::<i16>()) as u32;
+		// SAFETY: the buffer is not touched until the queue returns it,
+		// when this callback fills the other one
+		unsafe { ((**self.queue).enqueue)(self.queue, buffer.as_ptr() as *const c_void, len) };
+		self.next = 1 - self.next;
+	}
+
+}
+
+extern "C" fn buffer_callback (_: SLAndroidSimpleBufferQueueItf, context: *mut c_void) {
+	// SAFETY: `context` is the `CallbackData` of the player, and the
+	// callbacks of a player never run at the same time
+	let data = unsafe { &mut *(context as *mut CallbackData) };
+	data.enqueue();
+}
+
+
+
+/// plays the mixer through an OpenSL ES audio player
+///
+/// android routes the player to new devices on its own, so unlike
+/// the other backends there is no stream to recreate
+pub struct Backend {
+
+	engine: Option<Arc<Engine>>,
+	output_mix: SLObjectItf,
+	player: SLObjectItf,
+	data: *mut CallbackData
+
+}
+
+// the objects are only destroyed by `Drop`, and OpenSL ES objects can
+// be used from any thread
+unsafe impl Send for Backend {}
+
+impl Backend {
+
+
+	/// the output has the speakers of `layout`, the mixer converts every
+	/// sound to them
+	pub fn start (mixer: Arc<Mutex<Mixer>>, layout: ChannelLayout) -> Result<Self, &'static str> {
+
+		let channels = layout.channels() as usize;
+		mixer.lock().unwrap().set_config(channels as u16, mixer::SampleRate(SAMPLE_RATE));
+
+		let mut this = Self {
+			engine: None,
+			output_mix: ptr::null(),
+			player: ptr::null(),
+			data: ptr::null_mut()
+		};
+		// on error, `Drop` destroys what was created so far
+		// SAFETY: every object is realized before its interfaces are used
+		unsafe {
+			let engine = Engine::get()?;
+			this.engine = Some(engine.clone());
+			let engine = engine.interface;
+
+			check(((**engine).create_output_mix)(engine, &mut this.output_mix, 0, ptr::null(), ptr::null()), "failed to create opensl es output mix")?;
+			check(((**this.output_mix).realize)(this.output_mix, SL_BOOLEAN_FALSE), "failed to realize opensl es output mix")?;
+
+			let mut queue_locator = BufferQueueLocator {
+				locator_type: SL_DATALOCATOR_ANDROIDSIMPLEBUFFERQUEUE,
+				num_buffers: 2
+			};
+			let mut format = PcmFormat {
+				format_type: SL_DATAFORMAT_PCM,
+				num_channels: channels as u32,
+				samples_per_sec: SAMPLE_RATE * 1000,
+				bits_per_sample: 16,
+				container_size: 16,
+				// the speakers of opensl es are the ones of the wave format
+				channel_mask: layout.mask(),
+				endianness: SL_BYTEORDER_LITTLEENDIAN
+			};
+			let mut source = DataSource {
+				locator: &mut queue_locator as *mut _ as *mut c_void,
+				format: &mut format as *mut _ as *mut c_void
+			};
+			let mut mix_locator = OutputMixLocator {
+				locator_type: SL_DATALOCATOR_OUTPUTMIX,
+				output_mix: this.output_mix
+			};
+			let mut sink = DataSink {
+				locator: &mut mix_locator as *mut _ as *mut c_void,
+				format: ptr::null_mut()
+			};
+			let ids = [SL_IID_ANDROIDSIMPLEBUFFERQUEUE];
+			let required = [SL_BOOLEAN_TRUE];
+			check(
+				((**engine).create_audio_player)(engine, &mut this.player, &mut source, &mut sink, 1, ids.as_ptr(), required.as_ptr()),
+				"failed to create opensl es audio player"
+			)?;
+			check(((**this.player).realize)(this.player, SL_BOOLEAN_FALSE), "failed to realize opensl es audio player")?;
+
+			let mut play: SLPlayItf = ptr::null();
+			check(
+				((**this.player).get_interface)(this.player, SL_IID_PLAY, &mut play as *mut _ as *mut c_void),
+				"failed to get opensl es play interface"
+			)?;
+			let mut queue: SLAndroidSimpleBufferQueueItf = ptr::null();
+			check(
+				((**this.player).get_interface)(this.player, SL_IID_ANDROIDSIMPLEBUFFERQUEUE, &mut queue as *mut _ as *mut c_void),
+				"failed to get opensl es buffer queue interface"
+			)?;
+
+			this.data = Box::into_raw(Box::new(CallbackData {
+				mixer,
+				queue,
+				buffers: [vec![0; BUFFER_FRAMES * channels], vec![0; BUFFER_FRAMES * channels]],
+				next: 0
+			}));
+			check(((**queue).register_callback)(queue, buffer_callback, this.data as *mut c_void), "failed to register opensl es callback")?;
+
+			// the callback only runs once a buffer finishes, so the
+			// queue is filled before playing
+			(*this.data).enqueue();
+			(*this.data).enqueue();
+			check(((**play).set_play_state)(play, SL_PLAYSTATE_PLAYING), "failed to start opensl es audio player")?;
+		}
+
+		log::info!("created opensl es audio player: {}Hz, {} channels", SAMPLE_RATE, channels);
+		Ok(this)
+
+	}
+
+
+}
+
+impl Drop for Backend {
+	fn drop (&mut self) {
+		// SAFETY: destroying the player waits for its callback to
+		// return, so `data` is freed after every use
+		unsafe {
+			for object in [self.player, self.output_mix] {
+				if !object.is_null() {
+					((**object).destroy)(object);
+				}
+			}
+			if !self.data.is_null() {
+				drop(Box::from_raw(self.data));
+			}
+		}
+		// after the objects created from it
+		self.engine.take();
+	}
+}
+
+
+
+/// owned by the buffer queue callback, while the recorder exists
+struct RecorderData {
+	writer: TapWriter,
+	queue: SLAndroidSimpleBufferQueueItf,
+	buffers: [Vec<i16>; 2],
+	/// the buffer filled next
+	next: usize
+}
+
+impl RecorderData {
+
+	/// queue the next buffer to be filled
+	fn enqueue (&mut self) {
+		let buffer = &mut self.buffers[self.next];
+		let len = (buffer.len() * std::mem::size_of::<i16>()) as u32;
+		// SAFETY: the buffer is not touched until the queue returns it
+		unsafe { ((**self.queue).enqueue)(self.queue, buffer.as_mut_ptr() as *const c_void, len) };
+		self.next = 1 - self.next;
+	}
+
+}
+
+extern "C" fn record_callback (_: SLAndroidSimpleBufferQueueItf, context: *mut c_void) {
+	// SAFETY: `context` is the `RecorderData` of the recorder, and the
+	// callbacks of a recorder never run at the same time
+	let data = unsafe { &mut *(context as *mut RecorderData) };
+	// the buffers are filled in the order they were queued, the one
+	// that is full was queued two buffers ago, which is the next one
+	data.writer.write(&data.buffers[data.next]);
+	data.enqueue();
+}
+
+
+
+/// records the default input device through an OpenSL ES audio
+/// recorder
+pub struct InputBackend {
+
+	engine: Option<Arc<Engine>>,
+	recorder: SLObjectItf,
+	data: *mut RecorderData
+
+}
+
+// same as for `Backend`
+unsafe impl Send for InputBackend {}
+
+impl InputBackend {
+
+
+	/// records in the channels and rate of `config`, android
+	/// converts from the device. also returns if the device processes
+	/// the voice
+	pub fn start (config: InputConfig) -> Result<(Self, Tap, bool), &'static str> {
+
+		// mono or stereo, the input converts to anything else
+		let channels = config.channels.clamp(1, 2) as usize;
+		let sample_rate = config.sample_rate;
+
+		let mut this = Self {
+			engine: None,
+			recorder: ptr::null(),
+			data: ptr::null_mut()
+		};
+		let (writer, ring) = input::ring(config.buffer, channels as u16, sample_rate);
+		let mut processed = false;
+		// on error, `Drop` destroys what was created so far
+		// SAFETY: every object is realized before its interfaces are used
+		unsafe {
+			let engine = Engine::get()?;
+			this.engine = Some(engine.clone());
+			let engine = engine.interface;
+
+			let mut device_locator = IoDeviceLocator {
+				locator_type: SL_DATALOCATOR_IODEVICE,
+				device_type: SL_IODEVICE_AUDIOINPUT,
+				device_id: SL_DEFAULTDEVICEID_AUDIOINPUT,
+				device: ptr::null()
+			};
+			let mut source = DataSource {
+				locator: &mut device_locator as *mut _ as *mut c_void,
+				format: ptr::null_mut()
+			};
+			let mut queue_locator = BufferQueueLocator {
+				locator_type: SL_DATALOCATOR_ANDROIDSIMPLEBUFFERQUEUE,
+				num_buffers: 2
+			};
+			let mut format = PcmFormat {
+				format_type: SL_DATAFORMAT_PCM,
+				num_channels: channels as u32,
+				samples_per_sec: sample_rate * 1000,
+				bits_per_sample: 16,
+				container_size: 16,
+				channel_mask: if channels == 1 { SL_SPEAKER_FRONT_CENTER } else { SL_SPEAKER_FRONT_LEFT | SL_SPEAKER_FRONT_RIGHT },
+				endianness: SL_BYTEORDER_LITTLEENDIAN
+			};
+			let mut sink = DataSink {
+				locator: &mut queue_locator as *mut _ as *mut c_void,
+				format: &mut format as *mut _ as *mut c_void
+			};
+			let ids = [SL_IID_ANDROIDSIMPLEBUFFERQUEUE, SL_IID_ANDROIDCONFIGURATION];
+			let required = [SL_BOOLEAN_TRUE, SL_BOOLEAN_FALSE];
+			check(
+				((**engine).create_audio_recorder)(engine, &mut this.recorder, &mut source, &mut sink, 2, ids.as_ptr(), required.as_ptr()),
+				"failed to create opensl es audio recorder"
+			)?;
+
+			// the preset is set before the recorder is realized
+			let mut configuration: SLAndroidConfigurationItf = ptr::null();
+			if config.processed()
+				&& ((**this.recorder).get_interface)(this.recorder, SL_IID_ANDROIDCONFIGURATION, &mut configuration as *mut _ as *mut c_void) == SL_RESULT_SUCCESS
+			{
+				let preset = SL_ANDROID_RECORDING_PRESET_VOICE_COMMUNICATION;
+				processed = ((**configuration).set_configuration)(
+					configuration,
+					SL_ANDROID_KEY_RECORDING_PRESET.as_ptr(),
+					&preset as *const u32 as *const c_void,
+					std::mem::size_of::<u32>() as u32
+				) == SL_RESULT_SUCCESS;
+			}
+			check(((**this.recorder).realize)(this.recorder, SL_BOOLEAN_FALSE), "failed to realize opensl es audio recorder")?;
+
+			let mut record: SLRecordItf = ptr::null();
+			check(
+				((**this.recorder).get_interface)(this.recorder, SL_IID_RECORD, &mut record as *mut _ as *mut c_void),
+				"failed to get opensl es record interface"
+			)?;
+			let mut queue: SLAndroidSimpleBufferQueueItf = ptr::null();
+			check(
+				((**this.recorder).get_interface)(this.recorder, SL_IID_ANDROIDSIMPLEBUFFERQUEUE, &mut queue as *mut _ as *mut c_void),
+				"failed to get opensl es buffer queue interface"
+			)?;
+
+			// 10ms buffers
+			let frames = (sample_rate as usize / 100).max(1);
+			this.data = Box::into_raw(Box::new(RecorderData {
+				writer,
+				queue,
+				buffers: [vec![0; frames * channels], vec![0; frames * channels]],
+				next: 0
+			}));
+			check(((**queue).register_callback)(queue, record_callback, this.data as *mut c_void), "failed to register opensl es callback")?;
+
+			(*this.data).enqueue();
+			(*this.data).enqueue();
+			check(((**record).set_record_state)(record, SL_RECORDSTATE_RECORDING), "failed to start opensl es audio recorder")?;
+		}
+
+		log::info!("created opensl es audio recorder: {}Hz, {} channels", sample_rate, channels);
+		Ok((this, ring, processed))
+
+	}
+
+
+}
+
+impl Drop for InputBackend {
+	fn drop (&mut self) {
+		// SAFETY: destroying the recorder waits for its callback to
+		// return, so `data` is freed after every use
+		unsafe {
+			if !self.recorder.is_null() {
+				((**self.recorder).destroy)(self.recorder);
+			}
+			if !self.data.is_null() {
+				drop(Box::from_raw(self.data));
+			}
+		}
+		self.engine.take();
+	}
+}
@@ -0,0 +1,251 @@
+
+
+
+//! Ogg Opus streams.
+//!
+//! This handles the Ogg Opus container: the identification header, pre-skip, output gain and
+//! end trimming. Decoding the packets themselves is left to an [`OpusPacketDecoder`], usually a
+//! binding to libopus, since a SILK/CELT implementation is out of scope for this crate.
+
+
+
+use log::error;
+use ogg::reading::PacketReader;
+
+use std::io::{ self, Read, Seek, SeekFrom };
+
+use crate::mixer::SoundSource;
+
+
+
+/// Opus always decodes at 48kHz, whatever the input sample rate was
+const OPUS_SAMPLE_RATE: u32 = 48000;
+
+/// the longest opus packet is 120ms
+const MAX_PACKET_FRAMES: usize = OPUS_SAMPLE_RATE as usize * 120 / 1000;
+
+
+
+fn invalid <E: Into<Box<dyn std::error::Error + Send + Sync>>> (err: E) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+
+
+/// decodes single opus packets
+///
+/// implement this over an opus codec, like a libopus binding, to
+/// use it with [`OpusDecoder`]
+pub trait OpusPacketDecoder: Send {
+
+	/// decode `packet` into `output`, interleaved, at 48kHz
+	///
+	/// `output` has room for the longest possible packet. return
+	/// how many frames (samples per channel) were written
+	fn decode (&mut self, packet: &[u8], output: &mut [i16]) -> io::Result<usize>;
+
+	/// forget any state, the next packet is the first of the stream
+	fn reset (&mut self);
+
+}
+
+
+
+/// Ogg Opus File Decoder
+///
+/// packets are decoded as they are needed, so the whole file is
+/// never decoded up front
+pub struct OpusDecoder <T: Seek + Read + Send + 'static, D: OpusPacketDecoder> {
+
+	reader: PacketReader<T>,
+	decoder: D,
+	channels: u16,
+	/// frames to discard at the start of the stream
+	pre_skip: usize,
+	/// linear gain from the header, applied to every sample
+	gain: f32,
+	/// frames still to be discarded, counting down from `pre_skip`
+	skip: usize,
+	/// frames decoded so far, counting the pre-skip
+	position: u64,
+	/// the last decoded packet, interleaved
+	buffer: Vec<i16>,
+	/// range of `buffer` that was not written out yet
+	index: usize,
+	len: usize,
+	/// the stream reached its end or failed to decode
+	done: bool
+
+}
+
+impl <T: Seek + Read + Send + 'static, D: OpusPacketDecoder> OpusDecoder<T, D> {
+
+
+	/// Create a new ogg opus file decoder
+	///
+	/// `decoder` is called with the number of channels of the
+	/// stream and should return a decoder for that many channels
+	pub fn new (data: T, decoder: impl FnOnce(u16) -> D) -> io::Result<Self> {
+		let mut reader = PacketReader::new(data);
+		let (channels, pre_skip, gain) = read_headers(&mut reader)?;
+
+		let mut this = Self {
+			reader,
+			decoder: decoder(channels),
+			channels,
+			pre_skip,
+			gain,
+			skip: pre_skip,
+			position: 0,
+			buffer: vec![0; MAX_PACKET_FRAMES * channels as usize],
+			index: 0,
+			len: 0,
+			done: false
+		};
+		this.decoder.reset();
+		Ok(this)
+	}
+
+
+	/// decode the next packet into `buffer`
+	///
+	/// return false if there is nothing more to decode
+	fn next_packet (&mut self) -> bool {
+		match self.decode_packet() {
+			Ok(x) => x,
+			Err(err) => {
+				// same as the wav decoder, an error ends the sound
+				error!("error while decoding opus: {}", err);
+				false
+			}
+		}
+	}
+
+
+	fn decode_packet (&mut self) -> io::Result<bool> {
+
+		let channels = self.channels as usize;
+		loop {
+			let packet = match self.reader.read_packet().map_err(invalid)? {
+				Some(x) => x,
+				None => return Ok(false)
+			};
+
+			let frames = self.decoder.decode(&packet.data, &mut self.buffer)?.min(MAX_PACKET_FRAMES);
+			let start = self.position;
+			self.position += frames as u64;
+
+			// the granule position of the last page tells where the
+			// audio really ends, the last packet may be padded
+			let mut end = frames;
+			if packet.last_in_stream() {
+				end = packet.absgp_page().saturating_sub(start).min(frames as u64) as usize;
+			}
+
+			let skip = self.skip.min(end);
+			self.skip -= skip;
+			if skip == end {
+				if packet.last_in_stream() {
+					return Ok(false);
+				}
+				continue;
+			}
+
+			self.index = skip * channels;
+			self.len = end * channels;
+			if self.gain != 1.0 {
+				for x in self.buffer[self.index..self.len].iter_mut() {
+					*x = (*x as f32 * self.gain) as i16;
+				}
+			}
+			return Ok(true);
+		}
+
+	}
+
+
+}
+
+impl <T: Seek + Read + Send + 'static, D: OpusPacketDecoder> SoundSource for OpusDecoder<T, D> {
+
+
+	fn reset (&mut self) {
+		let result = self.reader
+			.seek_bytes(SeekFrom::Start(0))
+			.and_then(|_| read_headers(&mut self.reader));
+		if let Err(err) = result {
+			error!("error while seeking opus: {}", err);
+			self.done = true;
+			return;
+		}
+		self.decoder.reset();
+		self.skip = self.pre_skip;
+		self.position = 0;
+		self.index = 0;
+		self.len = 0;
+		self.done = false;
+	}
+
+
+	fn channels (&self) -> u16 {
+		self.channels
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		OPUS_SAMPLE_RATE
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+
+		let mut len = 0;
+		while len < buffer.len() && !self.done {
+			if self.index == self.len && !self.next_packet() {
+				self.done = true;
+				break;
+			}
+			let n = (self.len - self.index).min(buffer.len() - len);
+			buffer[len..len + n].copy_from_slice(&self.buffer[self.index..self.index + n]);
+			self.index += n;
+			len += n;
+		}
+		len
+
+	}
+
+
+}
+
+
+
+/// read the OpusHead and OpusTags packets
+///
+/// return the number of channels, the pre-skip and the output gain
+fn read_headers <T: Seek + Read> (reader: &mut PacketReader<T>) -> io::Result<(u16, usize, f32)> {
+
+	let head = reader.read_packet_expected().map_err(invalid)?.data;
+	if head.len() < 19 || &head[0..8] != b"OpusHead" {
+		return Err(invalid("not an opus stream"));
+	}
+	// only the major version is breaking
+	if head[8] >> 4 != 0 {
+		return Err(invalid("unsupported opus version"));
+	}
+	let channels = head[9] as u16;
+	if channels == 0 {
+		return Err(invalid("opus stream without channels"));
+	}
+	let pre_skip = u16::from_le_bytes([head[10], head[11]]) as usize;
+	// Q7.8 in dB
+	let gain = i16::from_le_bytes([head[16], head[17]]);
+	let gain = 10f32.powf(gain as f32 / (20.0 * 256.0));
+
+	let tags = reader.read_packet_expected().map_err(invalid)?.data;
+	if tags.len() < 8 || &tags[0..8] != b"OpusTags" {
+		return Err(invalid("missing OpusTags header"));
+	}
+
+	Ok((channels, pre_skip, gain))
+
+}
@@ -0,0 +1,620 @@
+
+
+
+//! Sources that generate sounds: tones of the basic waveforms, noise, silence, a constant and
+//! any closure.
+//!
+//! The square, saw and triangle are band limited, with polyBLEP, so high notes don't alias.
+//! The tones and the noises are mono and never end.
+
+
+
+use std::time::Duration;
+
+use crate::mixer::SoundSource;
+
+
+
+/// the sample rate of the generators, unless set otherwise
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// the amplitude of the generators, unless set otherwise, -6 dB
+const DEFAULT_AMPLITUDE: f32 = 0.5;
+
+
+
+/// the correction of a step at phase `0.0`, for the `t` of a sample
+/// advancing by `dt` per sample
+fn poly_blep (t: f32, dt: f32) -> f32 {
+	if t < dt {
+		let t = t / dt;
+		t + t - t * t - 1.0
+	} else if t > 1.0 - dt {
+		let t = (t - 1.0) / dt;
+		t * t + t + t + 1.0
+	} else {
+		0.0
+	}
+}
+
+
+
+/// implement the builders and [`SoundSource`] for a generator with an
+/// `amplitude`, a `sample_rate` and a `next` method, that gives the
+/// next sample between `-1.0` and `1.0`, and a `seek` method
+macro_rules! generator {
+	($name:ident) => {
+
+		impl $name {
+
+			/// the peak of the sound, `1.0` is full scale
+			pub fn amplitude (mut self, amplitude: f32) -> Self {
+				self.amplitude = amplitude;
+				self
+			}
+
+			/// 48kHz by default
+			pub fn sample_rate (mut self, sample_rate: u32) -> Self {
+				self.sample_rate = sample_rate.max(1);
+				self
+			}
+
+		}
+
+		impl SoundSource for $name {
+
+			fn channels (&self) -> u16 {
+				1
+			}
+
+			fn sample_rate (&self) -> u32 {
+				self.sample_rate
+			}
+
+			fn reset (&mut self) {
+				self.seek_to(0);
+			}
+
+			/// never ends
+			fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+				let scale = self.amplitude.clamp(0.0, 1.0) * i16::MAX as f32;
+				for x in buffer.iter_mut() {
+					*x = (self.next() * scale) as i16;
+				}
+				buffer.len()
+			}
+
+			fn seek (&mut self, frame: u64) -> bool {
+				self.seek_to(frame);
+				true
+			}
+
+		}
+
+	};
+}
+
+
+
+/// the phase of a tone, from `0.0` to `1.0`
+#[derive(Debug, Clone, Copy)]
+struct Phase {
+	frequency: f32,
+	value: f32
+}
+
+impl Phase {
+
+	/// the phase of this sample, and how much it moves per sample
+	fn next (&mut self, sample_rate: u32) -> (f32, f32) {
+		let value = self.value;
+		let dt = (self.frequency / sample_rate as f32).clamp(0.0, 0.5);
+		self.value = (self.value + dt).fract();
+		(value, dt)
+	}
+
+	fn seek (&mut self, frame: u64, sample_rate: u32) {
+		self.value = ((frame as f64 * self.frequency as f64 / sample_rate as f64) % 1.0) as f32;
+	}
+
+}
+
+
+
+/// a sine tone
+pub struct SineWave {
+	phase: Phase,
+	amplitude: f32,
+	sample_rate: u32
+}
+
+impl SineWave {
+
+	/// a tone at `frequency` Hz
+	pub fn new (frequency: f32) -> Self {
+		Self {
+			phase: Phase { frequency, value: 0.0 },
+			amplitude: DEFAULT_AMPLITUDE,
+			sample_rate: DEFAULT_SAMPLE_RATE
+		}
+	}
+
+	fn next (&mut self) -> f32 {
+		let (t, _) = self.phase.next(self.sample_rate);
+		(std::f32::consts::TAU * t).sin()
+	}
+
+	fn seek_to (&mut self, frame: u64) {
+		self.phase.seek(frame, self.sample_rate);
+	}
+
+}
+
+generator!(SineWave);
+
+
+
+/// a band limited square tone
+pub struct SquareWave {
+	phase: Phase,
+	amplitude: f32,
+	sample_rate: u32
+}
+
+impl SquareWave {
+
+	/// a tone at `frequency` Hz
+	pub fn new (frequency: f32) -> Self {
+		Self {
+			phase: Phase { frequency, value: 0.0 },
+			amplitude: DEFAULT_AMPLITUDE,
+			sample_rate: DEFAULT_SAMPLE_RATE
+		}
+	}
+
+	fn next (&mut self) -> f32 {
+		let (t, dt) = self.phase.next(self.sample_rate);
+		square(t, dt)
+	}
+
+	fn seek_to (&mut self, frame: u64) {
+		self.phase.seek(frame, self.sample_rate);
+	}
+
+}
+
+generator!(SquareWave);
+
+
+
+/// a band limited square, up for the first half of the phase
+fn square (t: f32, dt: f32) -> f32 {
+	let naive = if t < 0.5 { 1.0 } else { -1.0 };
+	naive + poly_blep(t, dt) - poly_blep((t + 0.5).fract(), dt)
+}
+
+
+
+/// a band limited saw tone, going up
+pub struct SawWave {
+	phase: Phase,
+	amplitude: f32,
+	sample_rate: u32
+}
+
+impl SawWave {
+
+	/// a tone at `frequency` Hz
+	pub fn new (frequency: f32) -> Self {
+		Self {
+			phase: Phase { frequency, value: 0.0 },
+			amplitude: DEFAULT_AMPLITUDE,
+			sample_rate: DEFAULT_SAMPLE_RATE
+		}
+	}
+
+	fn next (&mut self) -> f32 {
+		let (t, dt) = self.phase.next(self.sample_rate);
+		// the saw falls at the end of the phase, while the correction
+		// is for a step up
+		2.0 * t - 1.0 - poly_blep(t, dt)
+	}
+
+	fn seek_to (&mut self, frame: u64) {
+		self.phase.seek(frame, self.sample_rate);
+	}
+
+}
+
+generator!(SawWave);
+
+
+
+/// a band limited triangle tone
+///
+/// made by integrating a band limited square, which starts it at its
+/// bottom
+pub struct TriangleWave {
+	phase: Phase,
+	/// the integral of the square
+	value: f32,
+	amplitude: f32,
+	sample_rate: u32
+}
+
+impl TriangleWave {
+
+	/// a tone at `frequency` Hz
+	pub fn new (frequency: f32) -> Self {
+		Self {
+			phase: Phase { frequency, value: 0.0 },
+			value: -1.0,
+			amplitude: DEFAULT_AMPLITUDE,
+			sample_rate: DEFAULT_SAMPLE_RATE
+		}
+	}
+
+	fn next (&mut self) -> f32 {
+		let (t, dt) = self.phase.next(self.sample_rate);
+		// the square moves the triangle by 2 over half a period, the
+		// small leak keeps it from drifting away from the center
+		self.value = self.value * 0.9995 + 4.0 * dt * square(t, dt);
+		self.value.clamp(-1.0, 1.0)
+	}
+
+	fn seek_to (&mut self, frame: u64) {
+		self.phase.seek(frame, self.sample_rate);
+		let t = self.phase.value;
+		self.value = if t < 0.5 { 4.0 * t - 1.0 } else { 3.0 - 4.0 * t };
+	}
+
+}
+
+generator!(TriangleWave);
+
+
+
+/// the next value of a xorshift generator, between `-1.0` and `1.0`
+fn white (state: &mut u32) -> f32 {
+	*state ^= *state << 13;
+	*state ^= *state >> 17;
+	*state ^= *state << 5;
+	*state as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+
+
+/// white noise, the same power at every frequency
+pub struct WhiteNoise {
+	state: u32,
+	amplitude: f32,
+	sample_rate: u32
+}
+
+impl WhiteNoise {
+
+	pub fn new () -> Self {
+		Self { state: 0x1234_5678, amplitude: DEFAULT_AMPLITUDE, sample_rate: DEFAULT_SAMPLE_RATE }
+	}
+
+	fn next (&mut self) -> f32 {
+		white(&mut self.state)
+	}
+
+	/// noise has no position, it just continues
+	fn seek_to (&mut self, _: u64) {}
+
+}
+
+impl Default for WhiteNoise {
+	fn default () -> Self {
+		Self::new()
+	}
+}
+
+generator!(WhiteNoise);
+
+
+
+/// pink noise, with less power in the highs, -3 dB per octave
+///
+/// sounds more even than white noise, like rain or a waterfall
+pub struct PinkNoise {
+	state: u32,
+	/// the filters of Paul Kellett's method
+	filters: [f32; 7],
+	amplitude: f32,
+	sample_rate: u32
+}
+
+impl PinkNoise {
+
+	pub fn new () -> Self {
+		Self { state: 0x1234_5678, filters: [0.0; 7], amplitude: DEFAULT_AMPLITUDE, sample_rate: DEFAULT_SAMPLE_RATE }
+	}
+
+	fn next (&mut self) -> f32 {
+		let white = white(&mut self.state);
+		let b = &mut self.filters;
+		b[0] = 0.99886 * b[0] + white * 0.0555179;
+		b[1] = 0.99332 * b[1] + white * 0.0750759;
+		b[2] = 0.96900 * b[2] + white * 0.153852;
+		b[3] = 0.86650 * b[3] + white * 0.3104856;
+		b[4] = 0.55000 * b[4] + white * 0.5329522;
+		b[5] = -0.7616 * b[5] - white * 0.0168980;
+		let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+		b[6] = white * 0.115926;
+		// the usual scale of the method, which keeps nearly every peak
+		// under full scale
+		(pink * 0.11).clamp(-1.0, 1.0)
+	}
+
+	/// noise has no position, it just continues
+	fn seek_to (&mut self, _: u64) {}
+
+}
+
+impl Default for PinkNoise {
+	fn default () -> Self {
+		Self::new()
+	}
+}
+
+generator!(PinkNoise);
+
+
+
+/// how many samples of a generator were written, and how many it has
+#[derive(Debug, Clone, Copy)]
+struct Length {
+	position: u64,
+	/// in samples, `None` never ends
+	total: Option<u64>,
+	channels: u16,
+	sample_rate: u32
+}
+
+impl Length {
+
+	fn new () -> Self {
+		Self { position: 0, total: None, channels: 1, sample_rate: DEFAULT_SAMPLE_RATE }
+	}
+
+	fn set_duration (&mut self, duration: Duration) {
+		let frames = (duration.as_secs_f64() * self.sample_rate as f64).round() as u64;
+		self.total = Some(frames * self.channels as u64);
+	}
+
+	/// how many of `len` samples can be written
+	fn take (&mut self, len: usize) -> usize {
+		let len = match self.total {
+			Some(total) => (total.saturating_sub(self.position) as usize).min(len),
+			None => len
+		};
+		self.position += len as u64;
+		len
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		self.total.map(|x| x / self.channels as u64)
+	}
+
+	fn seek (&mut self, frame: u64) {
+		let position = frame.saturating_mul(self.channels as u64);
+		self.position = self.total.map_or(position, |x| position.min(x));
+	}
+
+}
+
+
+
+/// silence, for as long as needed
+pub struct Silence {
+	length: Length
+}
+
+impl Silence {
+
+	/// mono, at 48kHz, that never ends
+	pub fn new () -> Self {
+		Self { length: Length::new() }
+	}
+
+	pub fn channels (mut self, channels: u16) -> Self {
+		self.length.channels = channels.max(1);
+		self
+	}
+
+	pub fn sample_rate (mut self, sample_rate: u32) -> Self {
+		self.length.sample_rate = sample_rate.max(1);
+		self
+	}
+
+	/// end after `duration`, set after the channels and sample rate
+	pub fn duration (mut self, duration: Duration) -> Self {
+		self.length.set_duration(duration);
+		self
+	}
+
+}
+
+impl Default for Silence {
+	fn default () -> Self {
+		Self::new()
+	}
+}
+
+impl SoundSource for Silence {
+
+	fn channels (&self) -> u16 {
+		self.length.channels
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.length.sample_rate
+	}
+
+	fn reset (&mut self) {
+		self.length.position = 0;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let len = self.length.take(buffer.len());
+		buffer[..len].fill(0);
+		len
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		self.length.total_frames()
+	}
+
+	fn seek (&mut self, frame: u64) -> bool {
+		self.length.seek(frame);
+		true
+	}
+
+}
+
+
+
+/// the same sample, over and over
+///
+/// a DC offset, inaudible, but what the mixer does to it is easy to
+/// check
+pub struct Constant {
+	value: i16,
+	length: Length
+}
+
+impl Constant {
+
+	/// `value` from `-1.0` to `1.0`, mono, at 48kHz, that never ends
+	pub fn new (value: f32) -> Self {
+		Self { value: (value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16, length: Length::new() }
+	}
+
+	pub fn channels (mut self, channels: u16) -> Self {
+		self.length.channels = channels.max(1);
+		self
+	}
+
+	pub fn sample_rate (mut self, sample_rate: u32) -> Self {
+		self.length.sample_rate = sample_rate.max(1);
+		self
+	}
+
+	/// end after `duration`, set after the channels and sample rate
+	pub fn duration (mut self, duration: Duration) -> Self {
+		self.length.set_duration(duration);
+		self
+	}
+
+}
+
+impl SoundSource for Constant {
+
+	fn channels (&self) -> u16 {
+		self.length.channels
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.length.sample_rate
+	}
+
+	fn reset (&mut self) {
+		self.length.position = 0;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let len = self.length.take(buffer.len());
+		buffer[..len].fill(self.value);
+		len
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		self.length.total_frames()
+	}
+
+	fn seek (&mut self, frame: u64) -> bool {
+		self.length.seek(frame);
+		true
+	}
+
+}
+
+
+
+/// a source that calls a closure for every sample
+///
+/// the closure gets the index of the sample since the start, counted
+/// over every channel, and gives it from `-1.0` to `1.0`
+///
+/// ```
+/// # use audio_engine::FnSource;
+/// // a 440Hz sine, the long way
+/// let source = FnSource::new(|i| (i as f32 * 440.0 / 48000.0 * std::f32::consts::TAU).sin());
+/// ```
+pub struct FnSource <F: FnMut(u64) -> f32> {
+	function: F,
+	length: Length
+}
+
+impl<F: FnMut(u64) -> f32> FnSource<F> {
+
+	/// mono, at 48kHz, that never ends
+	pub fn new (function: F) -> Self {
+		Self { function, length: Length::new() }
+	}
+
+	pub fn channels (mut self, channels: u16) -> Self {
+		self.length.channels = channels.max(1);
+		self
+	}
+
+	pub fn sample_rate (mut self, sample_rate: u32) -> Self {
+		self.length.sample_rate = sample_rate.max(1);
+		self
+	}
+
+	/// end after `duration`, set after the channels and sample rate
+	pub fn duration (mut self, duration: Duration) -> Self {
+		self.length.set_duration(duration);
+		self
+	}
+
+}
+
+impl<F: FnMut(u64) -> f32> SoundSource for FnSource<F> {
+
+	fn channels (&self) -> u16 {
+		self.length.channels
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.length.sample_rate
+	}
+
+	fn reset (&mut self) {
+		self.length.position = 0;
+	}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let start = self.length.position;
+		let len = self.length.take(buffer.len());
+		for (i, x) in buffer[..len].iter_mut().enumerate() {
+			*x = ((self.function)(start + i as u64).clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+		}
+		len
+	}
+
+	fn total_frames (&self) -> Option<u64> {
+		self.length.total_frames()
+	}
+
+	/// the closure is called with the indexes from `frame` on
+	fn seek (&mut self, frame: u64) -> bool {
+		self.length.seek(frame);
+		true
+	}
+
+}
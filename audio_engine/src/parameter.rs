@@ -0,0 +1,77 @@
+
+
+
+//! Game parameters, named values like the tension of a fight or the speed of a car, that sounds
+//! and groups follow through curves.
+//!
+//! A parameter is set once with [`AudioEngine::set_parameter`](crate::AudioEngine::set_parameter)
+//! and every sound and group mapped to it follows, smoothed like a change of volume. A parameter
+//! that was never set is `0.0`.
+
+
+
+use crate::spatial::curve;
+
+
+
+/// what a parameter moves, see [`Sound::map_parameter`](crate::Sound::map_parameter)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterTarget {
+	/// the curve gives a gain, on top of the volume
+	Volume,
+	/// the curve gives semitones, played faster or slower like a tape
+	Pitch,
+	/// the curve gives the cutoff of a low pass, in hertz
+	Cutoff
+}
+
+
+
+/// a parameter followed by a sound or a group
+pub struct ParameterMap {
+	/// the key of the name of the parameter, see `name_key`
+	pub key: u64,
+	pub target: ParameterTarget,
+	/// values of the target at values of the parameter, in order of
+	/// the parameter
+	pub curve: Vec<(f32, f32)>
+}
+
+
+
+/// what the parameters give a sound or a group
+pub (crate) struct Mapped {
+	pub gain: f32,
+	pub speed: f32,
+	/// `None` when no parameter moves the cutoff
+	pub cutoff: Option<f32>
+}
+
+
+
+/// the targets of `maps` at `parameters`. the gains and speeds of
+/// many maps multiply, and the lowest cutoff is kept
+pub (crate) fn mapped (maps: &[ParameterMap], parameters: &[(u64, f32)]) -> Mapped {
+	let mut mapped = Mapped { gain: 1.0, speed: 1.0, cutoff: None };
+	for map in maps {
+		let parameter = parameters.iter().find(|x| x.0 == map.key).map_or(0.0, |x| x.1);
+		let value = curve(&map.curve, parameter);
+		match map.target {
+			ParameterTarget::Volume => mapped.gain *= value.max(0.0),
+			ParameterTarget::Pitch => mapped.speed *= 2f32.powf(value / 12.0),
+			ParameterTarget::Cutoff => mapped.cutoff = Some(mapped.cutoff.map_or(value, |x| x.min(value)))
+		}
+	}
+	mapped
+}
+
+
+
+/// add `map` in place of the one of the same parameter and
+/// target, an empty curve only removes it
+pub (crate) fn insert (maps: &mut Vec<ParameterMap>, map: ParameterMap) {
+	maps.retain(|x| x.key != map.key || x.target != map.target);
+	if !map.curve.is_empty() {
+		maps.push(map);
+	}
+}
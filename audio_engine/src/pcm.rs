@@ -0,0 +1,342 @@
+
+
+
+//! Sources from samples decoded or generated outside of the engine.
+//!
+//! A [`RawPcmSource`] plays interleaved 16 bit samples from a buffer, or pulls them from an
+//! iterator as they are mixed, so audio from another decoder doesn't need its own
+//! [`SoundSource`]. A [`RawPcmDecoder`] streams headerless samples from a file, in the
+//! [`PcmFormat`] it is told.
+
+
+
+use log::error;
+
+use std::io::{ self, Read, Seek, SeekFrom };
+
+use crate::mixer::SoundSource;
+use crate::wav::{ alaw_to_i16, f32_to_i16, mulaw_to_i16 };
+
+
+
+/// where the samples of a [`RawPcmSource`] come from
+enum Samples {
+	Buffer {
+		samples: Vec<i16>,
+		/// index of the next sample to write
+		position: usize
+	},
+	Iter(Box<dyn Iterator<Item = i16> + Send>)
+}
+
+
+
+/// plays interleaved 16 bit samples
+pub struct RawPcmSource {
+
+	samples: Samples,
+	channels: u16,
+	sample_rate: u32
+
+}
+
+impl RawPcmSource {
+
+
+	/// play the interleaved `samples`
+	///
+	/// a last frame missing samples of some channels is not played.
+	/// to share the samples between sounds, use a
+	/// [`SoundData`](crate::SoundData)
+	pub fn new (samples: Vec<i16>, channels: u16, sample_rate: u32) -> Self {
+		let channels = channels.max(1);
+		let mut samples = samples;
+		samples.truncate(samples.len() / channels as usize * channels as usize);
+		Self {
+			samples: Samples::Buffer { samples, position: 0 },
+			channels,
+			sample_rate
+		}
+	}
+
+
+	/// play the interleaved samples of `iter`, pulled on the audio
+	/// thread as they are mixed, until it ends
+	///
+	/// an iterator can't go back, so the source can't seek, and
+	/// resetting it carries on where it was. it should be quick to
+	/// advance, as it is called for every sample
+	pub fn from_iter (iter: impl Iterator<Item = i16> + Send + 'static, channels: u16, sample_rate: u32) -> Self {
+		Self {
+			samples: Samples::Iter(Box::new(iter)),
+			channels: channels.max(1),
+			sample_rate
+		}
+	}
+
+
+}
+
+impl SoundSource for RawPcmSource {
+
+
+	fn channels (&self) -> u16 {
+		self.channels
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	fn reset (&mut self) {
+		if let Samples::Buffer { position, .. } = &mut self.samples {
+			*position = 0;
+		}
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		match &mut self.samples {
+			Samples::Buffer { samples, position } => {
+				let samples = &samples[*position..];
+				let len = samples.len().min(buffer.len());
+				buffer[..len].copy_from_slice(&samples[..len]);
+				*position += len;
+				len
+			}
+			Samples::Iter(iter) => {
+				let mut len = 0;
+				for (x, sample) in buffer.iter_mut().zip(iter) {
+					*x = sample;
+					len += 1;
+				}
+				// only whole frames are played
+				len / self.channels as usize * self.channels as usize
+			}
+		}
+	}
+
+
+	fn total_frames (&self) -> Option<u64> {
+		match &self.samples {
+			Samples::Buffer { samples, .. } => Some((samples.len() / self.channels as usize) as u64),
+			Samples::Iter(_) => None
+		}
+	}
+
+
+	/// sample accurate from a buffer, not supported from an iterator
+	fn seek (&mut self, frame: u64) -> bool {
+		match &mut self.samples {
+			Samples::Buffer { samples, position } => {
+				let target = frame.saturating_mul(self.channels as u64);
+				*position = target.min(samples.len() as u64) as usize;
+				true
+			}
+			Samples::Iter(_) => false
+		}
+	}
+
+
+}
+
+
+
+/// how the samples of a [`PcmFormat`] are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmEncoding {
+	/// unsigned, silence is `128`
+	U8,
+	I8,
+	I16,
+	/// packed in 3 bytes
+	I24,
+	I32,
+	/// from `-1.0` to `1.0`
+	F32,
+	F64,
+	/// g.711 a-law
+	ALaw,
+	/// g.711 µ-law
+	MuLaw
+}
+
+impl PcmEncoding {
+
+
+	/// the size of a sample in bytes
+	pub fn bytes (self) -> usize {
+		match self {
+			Self::U8 | Self::I8 | Self::ALaw | Self::MuLaw => 1,
+			Self::I16 => 2,
+			Self::I24 => 3,
+			Self::I32 | Self::F32 => 4,
+			Self::F64 => 8
+		}
+	}
+
+
+	/// the sample of `bytes`, in little endian
+	fn decode (self, bytes: &[u8]) -> i16 {
+		match self {
+			Self::U8 => (bytes[0] as i16 - 128) << 8,
+			Self::I8 => (bytes[0] as i8 as i16) << 8,
+			// the top 16 bits
+			Self::I16 | Self::I24 | Self::I32 => i16::from_le_bytes([bytes[bytes.len() - 2], bytes[bytes.len() - 1]]),
+			Self::F32 => f32_to_i16(f32::from_le_bytes(bytes.try_into().unwrap())),
+			Self::F64 => f32_to_i16(f64::from_le_bytes(bytes.try_into().unwrap()) as f32),
+			Self::ALaw => alaw_to_i16(bytes[0]),
+			Self::MuLaw => mulaw_to_i16(bytes[0])
+		}
+	}
+
+
+}
+
+
+
+/// the layout of headerless samples, for a [`RawPcmDecoder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmFormat {
+
+	encoding: PcmEncoding,
+	channels: u16,
+	sample_rate: u32,
+	big_endian: bool
+
+}
+
+impl PcmFormat {
+
+
+	/// interleaved samples of `encoding`, in little endian
+	pub fn new (encoding: PcmEncoding, channels: u16, sample_rate: u32) -> Self {
+		Self { encoding, channels: channels.max(1), sample_rate, big_endian: false }
+	}
+
+
+	/// if the samples are in big endian, like the ones of aiff files
+	pub fn big_endian (mut self, big_endian: bool) -> Self {
+		self.big_endian = big_endian;
+		self
+	}
+
+
+	pub (crate) fn frame_bytes (&self) -> usize {
+		self.encoding.bytes() * self.channels as usize
+	}
+
+
+}
+
+
+
+/// streams headerless samples from a file, like the `.raw` or `.pcm`
+/// assets of old games
+///
+/// ```ignore
+/// let format = PcmFormat::new(PcmEncoding::I16, 2, 22050);
+/// let music = RawPcmDecoder::new(BufReader::new(File::open("music.raw")?), format)?;
+/// ```
+pub struct RawPcmDecoder <T: Seek + Read + Send + 'static> {
+
+	data: T,
+	format: PcmFormat,
+	/// where the samples start in `data`
+	start: u64,
+	frames: u64,
+	/// the next frame to read
+	frame: u64,
+	/// bytes read from `data`, kept to avoid allocating
+	bytes: Vec<u8>
+
+}
+
+impl <T: Seek + Read + Send + 'static> RawPcmDecoder<T> {
+
+
+	/// the samples of `data` from where it is to its end, so a header
+	/// can be skipped by seeking past it first
+	///
+	/// a last frame missing samples of some channels is not played
+	pub fn new (mut data: T, format: PcmFormat) -> io::Result<Self> {
+		let start = data.stream_position()?;
+		let end = data.seek(SeekFrom::End(0))?;
+		data.seek(SeekFrom::Start(start))?;
+		Self::with_len(data, format, end.saturating_sub(start))
+	}
+
+
+	/// the `len` bytes of samples of `data` from where it is
+	pub (crate) fn with_len (mut data: T, format: PcmFormat, len: u64) -> io::Result<Self> {
+		Ok(Self {
+			start: data.stream_position()?,
+			data,
+			format,
+			frames: len / format.frame_bytes() as u64,
+			frame: 0,
+			bytes: vec![]
+		})
+	}
+
+
+}
+
+impl <T: Seek + Read + Send + 'static> SoundSource for RawPcmDecoder<T> {
+
+
+	fn channels (&self) -> u16 {
+		self.format.channels
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.format.sample_rate
+	}
+
+
+	fn reset (&mut self) {
+		self.seek(0);
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let sample_bytes = self.format.encoding.bytes();
+		let channels = self.format.channels as usize;
+		let frames = ((buffer.len() / channels) as u64).min(self.frames - self.frame) as usize;
+		self.bytes.resize(frames * channels * sample_bytes, 0);
+		if let Err(err) = self.data.read_exact(&mut self.bytes) {
+			error!("error while reading pcm: {}", err);
+			return 0;
+		}
+		self.frame += frames as u64;
+
+		for (x, sample) in buffer.iter_mut().zip(self.bytes.chunks_exact_mut(sample_bytes)) {
+			if self.format.big_endian {
+				sample.reverse();
+			}
+			*x = self.format.encoding.decode(sample);
+		}
+		frames * channels
+	}
+
+
+	fn total_frames (&self) -> Option<u64> {
+		Some(self.frames)
+	}
+
+
+	/// sample accurate
+	fn seek (&mut self, frame: u64) -> bool {
+		let frame = frame.min(self.frames);
+		match self.data.seek(SeekFrom::Start(self.start + frame * self.format.frame_bytes() as u64)) {
+			Ok(_) => self.frame = frame,
+			Err(err) => error!("error while seeking pcm: {}", err)
+		}
+		true
+	}
+
+
+}
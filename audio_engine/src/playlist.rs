@@ -0,0 +1,356 @@
+
+
+
+//! A queue of songs played one after the other, for music players.
+//!
+//! The songs are played by a single source, so the next one starts on the frame after the last
+//! one ended, without a gap. The beginning of every song is decoded when it is queued, on the
+//! thread that queues it, so the audio thread never waits on a decoder that is just starting.
+
+
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU64, Ordering };
+
+use crate::converter::{ self, ResampleQuality };
+use crate::error::Error;
+use crate::mixer::SoundSource;
+use crate::queue::Queue;
+
+
+
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// how much of a song is decoded when it is queued
+const HEAD_FRAMES: usize = 8192;
+
+/// the capacity of the queue of the controls
+const EVENT_QUEUE_CAPACITY: usize = 64;
+
+/// stored in `playing` when nothing plays
+const NONE: u64 = u64::MAX;
+
+
+
+/// what a [`MusicQueue`] plays once a song ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+	/// the next song, until there are none
+	Off,
+	/// the same song again
+	One,
+	/// the next song, and the whole queue again after the last one
+	All
+}
+
+
+
+/// a queued song, with its beginning decoded
+struct Entry {
+	id: u64,
+	source: Box<dyn SoundSource + Send>,
+	head: Vec<i16>,
+	/// the next sample of `head`
+	position: usize,
+	/// the head is the whole song
+	whole: bool
+}
+
+impl Entry {
+
+
+	fn new (id: u64, mut source: Box<dyn SoundSource + Send>) -> Self {
+		let mut head = vec![0; HEAD_FRAMES * source.channels() as usize];
+		let len = source.write_samples(&mut head);
+		let whole = len < head.len();
+		head.truncate(len);
+		Self { id, source, head, position: 0, whole }
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let len = (self.head.len() - self.position).min(buffer.len());
+		buffer[..len].copy_from_slice(&self.head[self.position..self.position + len]);
+		self.position += len;
+		if len == buffer.len() || self.whole {
+			return len;
+		}
+		len + self.source.write_samples(&mut buffer[len..])
+	}
+
+
+	/// go back to the start, after the head if the source can seek
+	fn rewind (&mut self) {
+		self.position = 0;
+		if self.whole {
+			return;
+		}
+		self.source.reset();
+		let frames = (self.head.len() / self.source.channels() as usize) as u64;
+		if !self.source.seek(frames) {
+			self.head.clear();
+		}
+	}
+
+
+}
+
+
+
+enum QueueEvent {
+	Enqueue(Entry),
+	Skip,
+	Clear,
+	SetShuffle(bool),
+	SetRepeat(Repeat)
+}
+
+
+
+/// songs played one after the other without gaps, as a
+/// [`SoundSource`]
+///
+/// it never ends, and is silent while the queue is empty, so songs
+/// can be queued at any time with its [`controls`](MusicQueue::controls)
+///
+/// ```ignore
+/// let queue = MusicQueue::new();
+/// let controls = queue.controls();
+/// engine.new_sound(queue, |x| x)?.play();
+/// controls.enqueue(OggDecoder::new(first)?)?;
+/// controls.enqueue(OggDecoder::new(second)?)?;
+/// controls.set_repeat(Repeat::All);
+/// ```
+pub struct MusicQueue {
+
+	channels: u16,
+	sample_rate: u32,
+	events: Arc<Queue<QueueEvent>>,
+	/// the id of the song playing, shared with the controls
+	playing: Arc<AtomicU64>,
+	/// the id of the next queued song, shared with the controls
+	next_id: Arc<AtomicU64>,
+	current: Option<Entry>,
+	upcoming: VecDeque<Entry>,
+	/// the songs that were played, queued again with `Repeat::All`
+	played: Vec<Entry>,
+	shuffle: bool,
+	repeat: Repeat,
+	rng: u32
+
+}
+
+impl MusicQueue {
+
+
+	/// stereo, at 48kHz
+	pub fn new () -> Self {
+		Self {
+			channels: 2,
+			sample_rate: DEFAULT_SAMPLE_RATE,
+			events: Arc::new(Queue::with_capacity(EVENT_QUEUE_CAPACITY)),
+			playing: Arc::new(AtomicU64::new(NONE)),
+			next_id: Arc::new(AtomicU64::new(0)),
+			current: None,
+			upcoming: VecDeque::new(),
+			played: vec![],
+			shuffle: false,
+			repeat: Repeat::Off,
+			rng: 0x6D2B_79F5
+		}
+	}
+
+
+	pub fn channels (mut self, channels: u16) -> Self {
+		self.channels = channels.max(1);
+		self
+	}
+
+
+	pub fn sample_rate (mut self, sample_rate: u32) -> Self {
+		self.sample_rate = sample_rate;
+		self
+	}
+
+
+	/// queue and skip songs while it plays
+	///
+	/// the songs are converted to the channels and sample rate the
+	/// queue has when the controls are made
+	pub fn controls (&self) -> MusicQueueControls {
+		MusicQueueControls {
+			events: self.events.clone(),
+			playing: self.playing.clone(),
+			next_id: self.next_id.clone(),
+			channels: self.channels,
+			sample_rate: self.sample_rate
+		}
+	}
+
+
+	fn process_events (&mut self) {
+		while let Some(event) = self.events.pop() {
+			match event {
+				QueueEvent::Enqueue(entry) => self.upcoming.push_back(entry),
+				QueueEvent::Skip => self.next(false),
+				QueueEvent::Clear => {
+					self.current = None;
+					self.upcoming.clear();
+					self.played.clear();
+				},
+				QueueEvent::SetShuffle(shuffle) => self.shuffle = shuffle,
+				QueueEvent::SetRepeat(repeat) => self.repeat = repeat
+			}
+		}
+		self.publish();
+	}
+
+
+	/// move on from the current song, once it ended or is skipped
+	fn next (&mut self, ended: bool) {
+		if let Some(mut entry) = self.current.take() {
+			if ended && self.repeat == Repeat::One {
+				entry.rewind();
+				self.current = Some(entry);
+				return;
+			}
+			if self.repeat == Repeat::All {
+				entry.rewind();
+				self.played.push(entry);
+			}
+		}
+		if self.upcoming.is_empty() && self.repeat == Repeat::All {
+			self.upcoming.extend(self.played.drain(..));
+		}
+		let index = if self.shuffle && !self.upcoming.is_empty() {
+			self.rng ^= self.rng << 13;
+			self.rng ^= self.rng >> 17;
+			self.rng ^= self.rng << 5;
+			self.rng as usize % self.upcoming.len()
+		} else {
+			0
+		};
+		self.current = self.upcoming.remove(index);
+	}
+
+
+	fn publish (&self) {
+		self.playing.store(self.current.as_ref().map_or(NONE, |x| x.id), Ordering::Relaxed);
+	}
+
+
+}
+
+impl Default for MusicQueue {
+	fn default () -> Self {
+		Self::new()
+	}
+}
+
+impl SoundSource for MusicQueue {
+
+	fn channels (&self) -> u16 {
+		self.channels
+	}
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn reset (&mut self) {}
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		self.process_events();
+		if self.current.is_none() {
+			self.next(false);
+		}
+
+		// every song may end in the same buffer, if they are all
+		// empty
+		let mut len = 0;
+		let mut empty = 0;
+		while len < buffer.len() && empty <= self.upcoming.len() + self.played.len() + 1 {
+			let Some(entry) = self.current.as_mut() else {
+				break;
+			};
+			let written = entry.write_samples(&mut buffer[len..]);
+			len += written;
+			empty = if written == 0 { empty + 1 } else { 0 };
+			if len < buffer.len() {
+				self.next(true);
+			}
+		}
+		buffer[len..].fill(0);
+		self.publish();
+		buffer.len()
+	}
+
+}
+
+
+
+/// queues and skips the songs of a [`MusicQueue`] from any thread
+///
+/// the changes apply from the next mixed buffer
+#[derive(Clone)]
+pub struct MusicQueueControls {
+	events: Arc<Queue<QueueEvent>>,
+	playing: Arc<AtomicU64>,
+	next_id: Arc<AtomicU64>,
+	channels: u16,
+	sample_rate: u32
+}
+
+impl MusicQueueControls {
+
+
+	/// add `song` at the end of the queue, returns its id
+	///
+	/// the start of the song is decoded before this returns. fails if
+	/// the song can't be converted to the channels of the queue, or
+	/// if too many changes are waiting
+	pub fn enqueue <T: SoundSource + Send + 'static> (&self, song: T) -> Result<u64, Error> {
+		let source = converter::convert(Box::new(song), self.channels, self.sample_rate, ResampleQuality::default())?;
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		self.send(QueueEvent::Enqueue(Entry::new(id, source)))?;
+		Ok(id)
+	}
+
+
+	/// start the next song now
+	pub fn skip (&self) -> Result<(), Error> {
+		self.send(QueueEvent::Skip)
+	}
+
+
+	/// stop and remove every song
+	pub fn clear (&self) -> Result<(), Error> {
+		self.send(QueueEvent::Clear)
+	}
+
+
+	/// pick the next song at random from the ones left
+	pub fn set_shuffle (&self, shuffle: bool) -> Result<(), Error> {
+		self.send(QueueEvent::SetShuffle(shuffle))
+	}
+
+
+	/// `Repeat::Off` by default
+	pub fn set_repeat (&self, repeat: Repeat) -> Result<(), Error> {
+		self.send(QueueEvent::SetRepeat(repeat))
+	}
+
+
+	/// the id of the song playing, as of the last mixed buffer
+	pub fn playing (&self) -> Option<u64> {
+		Some(self.playing.load(Ordering::Relaxed)).filter(|&x| x != NONE)
+	}
+
+
+	fn send (&self, event: QueueEvent) -> Result<(), Error> {
+		self.events.push(event).map_err(|_| Error::QueueFull)
+	}
+
+
+}
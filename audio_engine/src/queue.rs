@@ -0,0 +1,231 @@
+
+
+
+//! A bounded lock-free queue, used to pass messages to and from the audio thread.
+
+
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+
+
+struct Slot<T> {
+
+	/// tells if the slot is ready to be written to or read from,
+	/// relative to the current position of `head` and `tail`
+	sequence: AtomicUsize,
+	value: UnsafeCell<MaybeUninit<T>>
+
+}
+
+
+
+/// bounded multi producer, multi consumer queue
+///
+/// `push` and `pop` never block and never allocate, which makes
+/// them safe to call from inside the audio callback
+///
+/// based on Dmitry Vyukov's bounded MPMC queue
+pub struct Queue<T> {
+
+	slots: Box<[Slot<T>]>,
+	mask: usize,
+	/// position of the next `pop`
+	head: AtomicUsize,
+	/// position of the next `push`
+	tail: AtomicUsize
+
+}
+
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Queue<T> {
+
+
+	/// create a new queue that can hold at least `capacity` values
+	///
+	/// the capacity is rounded up to the next power of two
+	pub fn with_capacity (capacity: usize) -> Self {
+		let capacity = capacity.max(2).next_power_of_two();
+		let slots = (0..capacity)
+			.map(|i| Slot {
+				sequence: AtomicUsize::new(i),
+				value: UnsafeCell::new(MaybeUninit::uninit())
+			})
+			.collect();
+
+		Self {
+			slots,
+			mask: capacity - 1,
+			head: AtomicUsize::new(0),
+			tail: AtomicUsize::new(0)
+		}
+	}
+
+
+	/// push a value to the end of the queue
+	///
+	/// if the queue is full, the value is given back in the `Err`
+	pub fn push (&self, value: T) -> Result<(), T> {
+		let mut pos = self.tail.load(Ordering::Relaxed);
+		loop {
+			let slot = &self.slots[pos & self.mask];
+			let sequence = slot.sequence.load(Ordering::Acquire);
+			let diff = (sequence as isize).wrapping_sub(pos as isize);
+
+			if diff == 0 {
+				match self.tail.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+					Ok(_) => {
+						// SAFETY: winning the compare exchange gives this thread
+						// exclusive access to the slot until `sequence` is updated
+						unsafe { (*slot.value.get()).write(value) };
+						slot.sequence.store(pos.wrapping_add(1), Ordering::Release);
+						return Ok(());
+					},
+					Err(x) => pos = x
+				}
+			} else if diff < 0 {
+				// the slot was not read yet, the queue is full
+				return Err(value);
+			} else {
+				pos = self.tail.load(Ordering::Relaxed);
+			}
+		}
+	}
+
+
+	/// pop the value at the front of the queue, if any
+	pub fn pop (&self) -> Option<T> {
+		let mut pos = self.head.load(Ordering::Relaxed);
+		loop {
+			let slot = &self.slots[pos & self.mask];
+			let sequence = slot.sequence.load(Ordering::Acquire);
+			let diff = (sequence as isize).wrapping_sub(pos.wrapping_add(1) as isize);
+
+			if diff == 0 {
+				match self.head.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+					Ok(_) => {
+						// SAFETY: the slot was written by `push` before it
+						// published `sequence`, and only this thread won it
+						let value = unsafe { (*slot.value.get()).assume_init_read() };
+						slot.sequence.store(pos.wrapping_add(self.mask + 1), Ordering::Release);
+						return Some(value);
+					},
+					Err(x) => pos = x
+				}
+			} else if diff < 0 {
+				// the slot was not written yet, the queue is empty
+				return None;
+			} else {
+				pos = self.head.load(Ordering::Relaxed);
+			}
+		}
+	}
+
+
+}
+
+impl<T> Drop for Queue<T> {
+	fn drop (&mut self) {
+		while self.pop().is_some() {}
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::sync::Arc;
+	use std::thread;
+
+	use super::Queue;
+
+
+	#[test]
+	fn fifo () {
+		let queue = Queue::with_capacity(8);
+		for i in 0..5 {
+			queue.push(i).unwrap();
+		}
+		assert_eq!((0..5).map(|_| queue.pop().unwrap()).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+		assert_eq!(queue.pop(), None);
+	}
+
+
+	#[test]
+	fn full_at_capacity () {
+		// rounded up to 8
+		let queue = Queue::with_capacity(5);
+		for i in 0..8 {
+			assert_eq!(queue.push(i), Ok(()));
+		}
+		assert_eq!(queue.push(8), Err(8));
+		assert_eq!(queue.pop(), Some(0));
+		assert_eq!(queue.push(8), Ok(()));
+		assert_eq!(queue.push(9), Err(9));
+	}
+
+
+	#[test]
+	fn wraps_around () {
+		let queue = Queue::with_capacity(4);
+		let mut next = 0;
+		// pushes 3 and pops 3 at a time, so every lap starts on another slot
+		for i in 0..20 {
+			for x in 0..3 {
+				queue.push(i * 3 + x).unwrap();
+			}
+			for _ in 0..3 {
+				assert_eq!(queue.pop(), Some(next));
+				next += 1;
+			}
+		}
+		assert_eq!(queue.pop(), None);
+	}
+
+
+	#[test]
+	fn many_producers () {
+		const PRODUCERS: usize = 4;
+		const VALUES: usize = 10000;
+		let queue = Arc::new(Queue::with_capacity(64));
+		let producers: Vec<_> = (0..PRODUCERS)
+			.map(|p| {
+				let queue = queue.clone();
+				thread::spawn(move || {
+					for i in 0..VALUES {
+						let mut value = (p, i);
+						while let Err(x) = queue.push(value) {
+							value = x;
+							thread::yield_now();
+						}
+					}
+				})
+			})
+			.collect();
+		// the values of each producer come out in the order it pushed them
+		let mut next = [0; PRODUCERS];
+		let mut received = 0;
+		while received < PRODUCERS * VALUES {
+			match queue.pop() {
+				Some((p, i)) => {
+					assert_eq!(i, next[p]);
+					next[p] += 1;
+					received += 1;
+				},
+				None => thread::yield_now()
+			}
+		}
+		for producer in producers {
+			producer.join().unwrap();
+		}
+		assert_eq!(next, [VALUES; PRODUCERS]);
+		assert_eq!(queue.pop(), None);
+	}
+
+
+}
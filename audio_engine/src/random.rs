@@ -0,0 +1,124 @@
+
+
+
+//! Random variations of a sound, so repeated sounds like footsteps and impacts don't sound the same.
+//!
+//! Each play picks one of the samples by its weight, never the one played last, and a volume and
+//! pitch from their ranges. The pitch is a change of speed, like a tape, which is what makes
+//! small variations sound natural.
+
+
+
+use crate::sound_data::SoundData;
+
+
+
+/// one of many [`SoundData`]s, picked at random each time it is
+/// played with [`AudioEngine::play_random`](crate::AudioEngine::play_random)
+///
+/// ```ignore
+/// let mut steps = RandomSound::new()
+///     .add(engine.load(WavDecoder::new(step1)?), 1.0)
+///     .add(engine.load(WavDecoder::new(step2)?), 1.0)
+///     .add(engine.load(WavDecoder::new(scuff)?), 0.3)
+///     .pitch_range(-1.0, 1.0)
+///     .volume_range(0.8, 1.0);
+/// engine.play_random(&mut steps)?;
+/// ```
+#[derive(Clone)]
+pub struct RandomSound {
+	variations: Vec<(SoundData, f32)>,
+	/// in semitones
+	pitch: (f32, f32),
+	volume: (f32, f32),
+	no_repeat: bool,
+	last: Option<usize>,
+	rng: u32
+}
+
+impl RandomSound {
+
+
+	/// no variations, the same pitch and volume every time, and never
+	/// the same variation twice in a row
+	pub fn new () -> Self {
+		Self {
+			variations: vec![],
+			pitch: (0.0, 0.0),
+			volume: (1.0, 1.0),
+			no_repeat: true,
+			last: None,
+			rng: 0x2545_F491
+		}
+	}
+
+
+	/// a variation, picked `weight` times as often as one of weight
+	/// `1.0`
+	pub fn add (mut self, data: SoundData, weight: f32) -> Self {
+		self.variations.push((data, weight.max(0.0)));
+		self
+	}
+
+
+	/// in semitones, for example `-1.0` to `1.0`
+	pub fn pitch_range (mut self, min: f32, max: f32) -> Self {
+		self.pitch = (min, max);
+		self
+	}
+
+
+	/// the gain, for example `0.8` to `1.0`
+	pub fn volume_range (mut self, min: f32, max: f32) -> Self {
+		self.volume = (min, max);
+		self
+	}
+
+
+	/// never pick the variation played last, `true` by default. it is
+	/// still picked if it is the only one
+	pub fn no_repeat (mut self, no_repeat: bool) -> Self {
+		self.no_repeat = no_repeat;
+		self
+	}
+
+
+	/// pick the next variation, with its volume and speed
+	pub (crate) fn pick (&mut self) -> Option<(SoundData, f32, f32)> {
+		let allowed = |this: &Self, i: usize| !this.no_repeat || this.last != Some(i) || this.variations.len() == 1;
+		let total: f32 = (0..self.variations.len()).filter(|&i| allowed(self, i)).map(|i| self.variations[i].1).sum();
+		let mut left = self.random() * total;
+		let mut picked = None;
+		for i in (0..self.variations.len()).filter(|&i| allowed(self, i)) {
+			// the last allowed one takes what rounding leaves over
+			picked = Some(i);
+			left -= self.variations[i].1;
+			if left < 0.0 {
+				break;
+			}
+		}
+		let picked = picked?;
+		self.last = Some(picked);
+
+		let semitones = self.pitch.0 + (self.pitch.1 - self.pitch.0) * self.random();
+		let volume = self.volume.0 + (self.volume.1 - self.volume.0) * self.random();
+		Some((self.variations[picked].0.clone(), volume, 2f32.powf(semitones / 12.0)))
+	}
+
+
+	/// a random number from `0.0` to `1.0`
+	fn random (&mut self) -> f32 {
+		self.rng ^= self.rng << 13;
+		self.rng ^= self.rng >> 17;
+		self.rng ^= self.rng << 5;
+		self.rng as f32 / u32::MAX as f32
+	}
+
+
+}
+
+impl Default for RandomSound {
+	fn default () -> Self {
+		Self::new()
+	}
+}
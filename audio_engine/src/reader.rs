@@ -0,0 +1,211 @@
+
+
+
+//! Readers to give the decoders, for files too big to load at once.
+//!
+//! The decoders read a few bytes at a time and seek often, which is slow on a bare [`File`](std::fs::File). A
+//! [`SeekBufReader`] keeps its buffer across seeks that land inside of it, which
+//! [`BufReader`](std::io::BufReader) doesn't. A [`MappedFile`] maps the file instead, so its pages
+//! are read by the OS as they are needed and stay in its cache, and nothing is copied to the heap.
+
+
+
+#[cfg(feature = "mmap")]
+use std::fs::File;
+use std::io::{ self, BufRead, Read, Seek, SeekFrom };
+#[cfg(feature = "mmap")]
+use std::io::Cursor;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+
+
+
+/// the buffer of [`SeekBufReader::new`]
+const DEFAULT_CAPACITY: usize = 64 * 1024;
+
+
+
+/// a buffered reader that can seek inside its buffer
+///
+/// ```ignore
+/// let ambience = WavDecoder::new(SeekBufReader::new(File::open("forest.wav")?)?)?;
+/// engine.new_sound(StreamingDecoder::new(ambience), |x| x)?.play();
+/// ```
+pub struct SeekBufReader <T: Read + Seek> {
+
+	inner: T,
+	buffer: Box<[u8]>,
+	/// where `buffer` starts in `inner`, which is at `start + len`
+	start: u64,
+	/// the unread bytes of `buffer`
+	pos: usize,
+	len: usize
+
+}
+
+impl <T: Read + Seek> SeekBufReader<T> {
+
+
+	/// read `inner` from where it is, 64 KiB at a time
+	pub fn new (inner: T) -> io::Result<Self> {
+		Self::with_capacity(DEFAULT_CAPACITY, inner)
+	}
+
+
+	/// read `inner` from where it is, `capacity` bytes at a time
+	pub fn with_capacity (capacity: usize, mut inner: T) -> io::Result<Self> {
+		Ok(Self {
+			start: inner.stream_position()?,
+			inner,
+			buffer: vec![0; capacity.max(1)].into_boxed_slice(),
+			pos: 0,
+			len: 0
+		})
+	}
+
+
+	pub fn into_inner (self) -> T {
+		self.inner
+	}
+
+
+}
+
+impl <T: Read + Seek> Read for SeekBufReader<T> {
+
+	fn read (&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+		// a big read skips the buffer
+		if self.pos == self.len && buffer.len() >= self.buffer.len() {
+			let len = self.inner.read(buffer)?;
+			self.start += (self.len + len) as u64;
+			self.pos = 0;
+			self.len = 0;
+			return Ok(len);
+		}
+		let available = self.fill_buf()?;
+		let len = available.len().min(buffer.len());
+		buffer[..len].copy_from_slice(&available[..len]);
+		self.consume(len);
+		Ok(len)
+	}
+
+}
+
+impl <T: Read + Seek> BufRead for SeekBufReader<T> {
+
+	fn fill_buf (&mut self) -> io::Result<&[u8]> {
+		if self.pos == self.len {
+			self.start += self.len as u64;
+			self.pos = 0;
+			self.len = 0;
+			self.len = self.inner.read(&mut self.buffer)?;
+		}
+		Ok(&self.buffer[self.pos..self.len])
+	}
+
+	fn consume (&mut self, len: usize) {
+		self.pos = (self.pos + len).min(self.len);
+	}
+
+}
+
+impl <T: Read + Seek> Seek for SeekBufReader<T> {
+
+	fn seek (&mut self, position: SeekFrom) -> io::Result<u64> {
+		let target = match position {
+			SeekFrom::Start(x) => x,
+			SeekFrom::Current(x) => (self.start + self.pos as u64)
+				.checked_add_signed(x)
+				.ok_or(io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?,
+			// the end is only known by `inner`
+			SeekFrom::End(_) => {
+				self.start = self.inner.seek(position)?;
+				self.pos = 0;
+				self.len = 0;
+				return Ok(self.start);
+			}
+		};
+		if target >= self.start && target <= self.start + self.len as u64 {
+			self.pos = (target - self.start) as usize;
+		} else {
+			self.inner.seek(SeekFrom::Start(target))?;
+			self.start = target;
+			self.pos = 0;
+			self.len = 0;
+		}
+		Ok(target)
+	}
+
+}
+
+
+
+/// a file mapped in memory, read like a file
+///
+/// ```ignore
+/// // SAFETY: the assets are not changed while the game runs
+/// let file = unsafe { MappedFile::open("forest.flac")? };
+/// engine.new_sound(FlacDecoder::new(file)?, |x| x)?.play();
+/// ```
+#[cfg(feature = "mmap")]
+pub struct MappedFile(Cursor<memmap2::Mmap>);
+
+#[cfg(feature = "mmap")]
+impl MappedFile {
+
+
+	/// map the file at `path`
+	///
+	/// # Safety
+	///
+	/// the file must not be changed or truncated while it's mapped,
+	/// by this program or another, which would change or remove the
+	/// bytes under the decoder
+	pub unsafe fn open (path: impl AsRef<Path>) -> io::Result<Self> {
+		let file = File::open(path)?;
+		let map = memmap2::Mmap::map(&file)?;
+		// the decoders read it from the start to the end
+		#[cfg(unix)]
+		let _ = map.advise(memmap2::Advice::Sequential);
+		Ok(Self(Cursor::new(map)))
+	}
+
+
+	/// the bytes of the file
+	pub fn as_bytes (&self) -> &[u8] {
+		self.0.get_ref()
+	}
+
+
+}
+
+#[cfg(feature = "mmap")]
+impl Read for MappedFile {
+
+	fn read (&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+		self.0.read(buffer)
+	}
+
+}
+
+#[cfg(feature = "mmap")]
+impl BufRead for MappedFile {
+
+	fn fill_buf (&mut self) -> io::Result<&[u8]> {
+		self.0.fill_buf()
+	}
+
+	fn consume (&mut self, len: usize) {
+		self.0.consume(len)
+	}
+
+}
+
+#[cfg(feature = "mmap")]
+impl Seek for MappedFile {
+
+	fn seek (&mut self, position: SeekFrom) -> io::Result<u64> {
+		self.0.seek(position)
+	}
+
+}
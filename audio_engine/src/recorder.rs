@@ -0,0 +1,123 @@
+
+
+
+//! Recording the output of the engine to a wav file.
+//!
+//! The file is written on its own thread, from a tap of the mix, so a slow disk never
+//! stalls the audio thread.
+
+
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::thread::{ self, JoinHandle };
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::tap::Tap;
+
+
+
+/// how long the writing thread sleeps once the tap is empty
+const WRITE_INTERVAL: Duration = Duration::from_millis(50);
+
+
+
+/// a wav file being recorded, see [`AudioEngine::record`]
+///
+/// the file is finished by [`Recording::stop`], or when this is
+/// dropped
+///
+/// [`AudioEngine::record`]: crate::AudioEngine::record
+pub struct Recording {
+
+	stop: Arc<AtomicBool>,
+	join: Option<JoinHandle<Result<(), hound::Error>>>
+
+}
+
+impl Recording {
+
+
+	pub (crate) fn start (path: &Path, mut tap: Tap) -> Result<Self, hound::Error> {
+		let spec = hound::WavSpec {
+			channels: tap.channels(),
+			sample_rate: tap.sample_rate(),
+			bits_per_sample: 16,
+			sample_format: hound::SampleFormat::Int
+		};
+		let writer = hound::WavWriter::create(path, spec)?;
+
+		let stop = Arc::new(AtomicBool::new(false));
+		let join = {
+			let stop = stop.clone();
+			thread::Builder::new()
+				.name("audio recording".into())
+				.spawn(move || write(writer, &mut tap, &stop))
+				.expect("failed to spawn the recording thread")
+		};
+
+		Ok(Self { stop, join: Some(join) })
+	}
+
+
+	/// write what is left of the mix and finish the file
+	pub fn stop (mut self) -> Result<(), Error> {
+		Ok(self.finish()?)
+	}
+
+
+	fn finish (&mut self) -> Result<(), hound::Error> {
+		self.stop.store(true, Ordering::Release);
+		match self.join.take() {
+			Some(join) => join.join().expect("the recording thread panicked"),
+			None => Ok(())
+		}
+	}
+
+
+}
+
+impl Drop for Recording {
+	fn drop (&mut self) {
+		if let Err(err) = self.finish() {
+			log::error!("failed to write recording: {}", err);
+		}
+	}
+}
+
+
+
+/// the loop of the writing thread
+fn write (
+	mut writer: hound::WavWriter<BufWriter<File>>,
+	tap: &mut Tap,
+	stop: &AtomicBool
+) -> Result<(), hound::Error> {
+
+	let mut buffer = vec![0; 4096];
+	loop {
+		// checked before reading, so everything mixed before the stop
+		// is written
+		let stopping = stop.load(Ordering::Acquire);
+		let len = tap.read(&mut buffer);
+		for x in &buffer[..len] {
+			writer.write_sample(*x)?;
+		}
+		if len == 0 {
+			if stopping {
+				break;
+			}
+			thread::sleep(WRITE_INTERVAL);
+		}
+	}
+
+	if tap.dropped() > 0 {
+		log::warn!("recording lost {} frames", tap.dropped());
+	}
+	writer.finalize()
+
+}
@@ -0,0 +1,105 @@
+
+
+
+//! The last seconds of the mix, kept for instant replays.
+//!
+//! The mixer writes every buffer it outputs to a ring that overwrites its oldest samples, and
+//! never waits for anything. Saving copies the end of the ring and checks afterwards that the
+//! mixer didn't overwrite what was copied, so the ring holds a second more than was asked for.
+
+
+
+use std::path::Path;
+use std::sync::atomic::{ AtomicI16, AtomicUsize, Ordering };
+use std::time::Duration;
+
+
+
+/// kept on top of the length of the replay, for the mixer to write
+/// to while it is saved
+const MARGIN: Duration = Duration::from_secs(1);
+
+
+
+/// the last samples of the mix, see
+/// [`AudioEngine::enable_replay`](crate::AudioEngine::enable_replay)
+pub struct Replay {
+
+	/// interleaved samples, its length is a power of two
+	samples: Box<[AtomicI16]>,
+	mask: usize,
+	/// how many samples were ever written, only written by the mixer
+	tail: AtomicUsize,
+	/// the longest replay, in samples
+	length: usize,
+	channels: u16,
+	sample_rate: u32
+
+}
+
+impl Replay {
+
+
+	pub fn new (length: Duration, channels: u16, sample_rate: u32) -> Self {
+		let samples = |duration: Duration| (duration.as_secs_f64() * sample_rate as f64) as usize * channels as usize;
+		let capacity = (samples(length) + samples(MARGIN)).max(2).next_power_of_two();
+		Self {
+			samples: (0..capacity).map(|_| AtomicI16::new(0)).collect(),
+			mask: capacity - 1,
+			tail: AtomicUsize::new(0),
+			length: samples(length),
+			channels,
+			sample_rate
+		}
+	}
+
+
+	/// add `samples` after the others, over the oldest ones
+	pub fn write (&self, samples: &[i16]) {
+		let tail = self.tail.load(Ordering::Relaxed);
+		for (i, x) in samples.iter().enumerate() {
+			self.samples[tail.wrapping_add(i) & self.mask].store(*x, Ordering::Relaxed);
+		}
+		self.tail.store(tail.wrapping_add(samples.len()), Ordering::Release);
+	}
+
+
+	/// copy the last `duration` that was written, or less if that
+	/// much wasn't kept
+	pub fn last (&self, duration: Duration) -> Vec<i16> {
+		let channels = self.channels as usize;
+		let asked = (duration.as_secs_f64() * self.sample_rate as f64) as usize * channels;
+		let tail = self.tail.load(Ordering::Acquire);
+		let len = asked.min(self.length).min(tail) / channels * channels;
+		let start = tail.wrapping_sub(len);
+		let mut samples: Vec<i16> = (0..len).map(|i| self.samples[start.wrapping_add(i) & self.mask].load(Ordering::Relaxed)).collect();
+
+		// the mixer may have come around to the oldest copied samples,
+		// they are dropped
+		let written = self.tail.load(Ordering::Acquire).wrapping_sub(tail);
+		let free = self.samples.len() - len;
+		if written > free {
+			let overwritten = (written - free).div_ceil(channels) * channels;
+			samples.drain(..overwritten.min(len));
+		}
+		samples
+	}
+
+
+	/// write the last `duration` to a wav file at `path`
+	pub fn save (&self, duration: Duration, path: &Path) -> Result<(), hound::Error> {
+		let spec = hound::WavSpec {
+			channels: self.channels,
+			sample_rate: self.sample_rate,
+			bits_per_sample: 16,
+			sample_format: hound::SampleFormat::Int
+		};
+		let mut writer = hound::WavWriter::create(path, spec)?;
+		for x in self.last(duration) {
+			writer.write_sample(x)?;
+		}
+		writer.finalize()
+	}
+
+
+}
@@ -0,0 +1,181 @@
+
+
+
+//! A Freeverb style reverb.
+//!
+//! Each channel goes through eight parallel comb filters and four allpass filters in series.
+//! The delays are tuned for 44.1kHz and scaled to the sample rate of the mix.
+
+
+
+use crate::effect::Effect;
+
+
+
+/// delays of the comb filters, in frames at 44.1kHz
+const COMB_DELAYS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+/// delays of the allpass filters, in frames at 44.1kHz
+const ALLPASS_DELAYS: [usize; 4] = [556, 441, 341, 225];
+/// added to the delays of each channel after the first, so the
+/// channels don't sound the same
+const STEREO_SPREAD: usize = 23;
+
+const ALLPASS_FEEDBACK: f32 = 0.5;
+/// the input is scaled down, the combs add up to a lot more
+const INPUT_GAIN: f32 = 0.015;
+
+// how the config maps to the filters, from the original Freeverb
+const SCALE_ROOM: f32 = 0.28;
+const OFFSET_ROOM: f32 = 0.7;
+const SCALE_DAMPING: f32 = 0.4;
+const SCALE_WET: f32 = 3.0;
+
+
+
+/// settings of a [`Reverb`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReverbConfig {
+
+	/// how long the reverb lasts, from `0.0` to `1.0`
+	pub room_size: f32,
+	/// how fast the high frequencies die out, from `0.0` to `1.0`
+	pub damping: f32,
+	/// gain of the reverberated sound
+	pub wet: f32,
+	/// gain of the original sound, `0.0` on a send bus
+	pub dry: f32
+
+}
+
+impl Default for ReverbConfig {
+	fn default () -> Self {
+		Self {
+			room_size: 0.5,
+			damping: 0.5,
+			wet: 0.33,
+			dry: 1.0
+		}
+	}
+}
+
+
+
+struct Comb {
+	buffer: Vec<f32>,
+	position: usize,
+	/// state of the low pass in the feedback
+	filter: f32
+}
+
+impl Comb {
+
+	fn process (&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+		let output = self.buffer[self.position];
+		self.filter = output * (1.0 - damping) + self.filter * damping;
+		self.buffer[self.position] = input + self.filter * feedback;
+		self.position = (self.position + 1) % self.buffer.len();
+		output
+	}
+
+}
+
+
+
+struct Allpass {
+	buffer: Vec<f32>,
+	position: usize
+}
+
+impl Allpass {
+
+	fn process (&mut self, input: f32) -> f32 {
+		let delayed = self.buffer[self.position];
+		self.buffer[self.position] = input + delayed * ALLPASS_FEEDBACK;
+		self.position = (self.position + 1) % self.buffer.len();
+		delayed - input
+	}
+
+}
+
+
+
+/// the filters of one channel
+struct Channel {
+	combs: Vec<Comb>,
+	allpasses: Vec<Allpass>
+}
+
+impl Channel {
+
+	fn new (sample_rate: u32, spread: usize) -> Self {
+		let scale = |delay: usize| ((delay + spread) as f32 * sample_rate as f32 / 44100.0).max(1.0) as usize;
+		Self {
+			combs: COMB_DELAYS.iter()
+				.map(|delay| Comb { buffer: vec![0.0; scale(*delay)], position: 0, filter: 0.0 })
+				.collect(),
+			allpasses: ALLPASS_DELAYS.iter()
+				.map(|delay| Allpass { buffer: vec![0.0; scale(*delay)], position: 0 })
+				.collect()
+		}
+	}
+
+}
+
+
+
+/// a reverb, as an [`Effect`]
+///
+/// it is best used once, on a group that sounds send to, see
+/// [`AudioEngine::reverb`](crate::AudioEngine::reverb)
+pub struct Reverb {
+
+	config: ReverbConfig,
+	/// the filters of each channel, made for `sample_rate`
+	channels: Vec<Channel>,
+	sample_rate: u32
+
+}
+
+impl Reverb {
+
+
+	pub fn new (config: ReverbConfig) -> Self {
+		Self {
+			config,
+			channels: vec![],
+			sample_rate: 0
+		}
+	}
+
+
+}
+
+impl Effect for Reverb {
+
+	fn process (&mut self, frames: &mut [f32], channels: u16, sample_rate: u32) {
+		// the filters are made on the first call, and again if the
+		// output changes
+		if self.channels.len() != channels as usize || self.sample_rate != sample_rate {
+			self.channels = (0..channels as usize).map(|c| Channel::new(sample_rate, c * STEREO_SPREAD)).collect();
+			self.sample_rate = sample_rate;
+		}
+
+		let feedback = self.config.room_size.clamp(0.0, 1.0) * SCALE_ROOM + OFFSET_ROOM;
+		let damping = self.config.damping.clamp(0.0, 1.0) * SCALE_DAMPING;
+		let wet = self.config.wet * SCALE_WET;
+		for frame in frames.chunks_exact_mut(channels as usize) {
+			for (x, channel) in frame.iter_mut().zip(self.channels.iter_mut()) {
+				let input = *x * INPUT_GAIN;
+				let mut output = 0.0;
+				for comb in channel.combs.iter_mut() {
+					output += comb.process(input, feedback, damping);
+				}
+				for allpass in channel.allpasses.iter_mut() {
+					output = allpass.process(output);
+				}
+				*x = *x * self.config.dry + output * wet;
+			}
+		}
+	}
+
+}
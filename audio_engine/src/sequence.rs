@@ -0,0 +1,82 @@
+
+
+
+//! Sounds played one after the other, one for each trigger, like the hits of a combo.
+
+
+
+use crate::sound_data::SoundData;
+
+
+
+/// [`SoundData`]s played in order, the next one each time it is
+/// played with [`AudioEngine::play_sequence`](crate::AudioEngine::play_sequence)
+///
+/// ```
+/// # use std::time::Duration;
+/// # use audio_engine::{ AudioEngine, SequenceSound, SquareWave, SoundSourceExt };
+/// # let (engine, _backend) = AudioEngine::offline(2, 48000);
+/// # let hit = |x| engine.load(SquareWave::new(x).take_duration(Duration::from_millis(50)));
+/// # let (punch, kick) = (hit(200.0), hit(80.0));
+/// let mut combo = SequenceSound::new().step(punch.clone()).step(punch).step(kick).looping(false);
+/// engine.play_sequence(&mut combo)?;
+/// // once the combo is broken
+/// combo.reset();
+/// # Ok::<(), audio_engine::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct SequenceSound {
+	steps: Vec<SoundData>,
+	/// the index of the step played next
+	next: usize,
+	looping: bool
+}
+
+impl SequenceSound {
+
+
+	/// no steps, starting again after the last one
+	pub fn new () -> Self {
+		Self { steps: vec![], next: 0, looping: true }
+	}
+
+
+	pub fn step (mut self, data: SoundData) -> Self {
+		self.steps.push(data);
+		self
+	}
+
+
+	/// start again from the first step after the last one, `true` by
+	/// default. otherwise nothing plays until [`SequenceSound::reset`]
+	pub fn looping (mut self, looping: bool) -> Self {
+		self.looping = looping;
+		self
+	}
+
+
+	/// go back to the first step
+	pub fn reset (&mut self) {
+		self.next = 0;
+	}
+
+
+	/// the step to play, and move on to the next one, `None` once
+	/// there are none left
+	pub fn next_step (&mut self) -> Option<SoundData> {
+		if self.next == self.steps.len() && self.looping {
+			self.next = 0;
+		}
+		let step = self.steps.get(self.next)?.clone();
+		self.next += 1;
+		Some(step)
+	}
+
+
+}
+
+impl Default for SequenceSound {
+	fn default () -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,62 @@
+
+
+
+//! Snapshots of the mix, the volumes and filters of the groups, blended to in one call.
+//!
+//! A snapshot only names the groups it changes, the others keep their settings. The way back is
+//! a snapshot too, of the normal mix.
+
+
+
+use crate::mixer::{ Group, GroupId };
+
+
+
+/// a state of the mix, blended to with
+/// [`AudioEngine::transition_to_snapshot`](crate::AudioEngine::transition_to_snapshot)
+///
+/// ```
+/// # use std::time::Duration;
+/// # use audio_engine::{ AudioEngine, Snapshot };
+/// # let (engine, _backend) = AudioEngine::offline(2, 48000);
+/// # let (music, sfx) = (engine.create_group("music"), engine.create_group("sfx"));
+/// engine.add_snapshot("underwater", Snapshot::new().volume(&music, 0.5).low_pass(&sfx, 600.0))?;
+/// engine.add_snapshot("default", Snapshot::new().volume(&music, 1.0).low_pass(&sfx, 20000.0))?;
+/// engine.transition_to_snapshot("underwater", Duration::from_millis(500))?;
+/// # Ok::<(), audio_engine::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+	pub (crate) volumes: Vec<(GroupId, f32)>,
+	pub (crate) low_passes: Vec<(GroupId, f32)>
+}
+
+impl Snapshot {
+
+
+	pub fn new () -> Self {
+		Self::default()
+	}
+
+
+	/// the volume of `group`, like [`Group::set_volume`]
+	pub fn volume (mut self, group: &Group, volume: f32) -> Self {
+		self.volumes.retain(|x| x.0 != group.id);
+		self.volumes.push((group.id, volume));
+		self
+	}
+
+
+	/// the cutoff of a low pass on the whole group, in Hz, to muffle
+	/// it. `20000.0` lets everything through
+	///
+	/// the filter is added after the effects of the group the first
+	/// time, which mixes the group on its own like an effect does
+	pub fn low_pass (mut self, group: &Group, cutoff: f32) -> Self {
+		self.low_passes.retain(|x| x.0 != group.id);
+		self.low_passes.push((group.id, cutoff));
+		self
+	}
+
+
+}
@@ -0,0 +1,211 @@
+
+
+
+//! Decoded sounds, shared between every sound that plays them.
+
+
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::mixer::{ Direction, InstanceLimit, SoundSource, StealPolicy };
+
+
+
+/// how many frames are decoded at a time
+const DECODE_CHUNK_FRAMES: usize = 4096;
+
+
+
+/// samples decoded once, and played by any number of sounds
+///
+/// cloning is cheap, the samples are shared. use
+/// [`SoundData::source`] to play them
+#[derive(Clone)]
+pub struct SoundData {
+
+	/// interleaved
+	samples: Arc<[i16]>,
+	channels: u16,
+	sample_rate: u32,
+	loop_region: Option<(u64, u64)>,
+	/// the most sounds of these samples that play at once
+	max_instances: Option<(u32, StealPolicy)>
+
+}
+
+impl SoundData {
+
+
+	/// wrap already decoded, interleaved samples
+	pub fn new (samples: impl Into<Arc<[i16]>>, channels: u16, sample_rate: u32) -> Self {
+		Self {
+			samples: samples.into(),
+			channels,
+			sample_rate,
+			loop_region: None,
+			max_instances: None
+		}
+	}
+
+
+	/// decode the whole `source`
+	///
+	/// this reads until the source ends, so it must not loop. the
+	/// loop region of the source is kept
+	pub fn decode <T: SoundSource> (mut source: T) -> Self {
+		let channels = source.channels();
+		let chunk = DECODE_CHUNK_FRAMES * channels as usize;
+
+		let total = source.total_frames().unwrap_or(0) as usize * channels as usize;
+		let mut samples = Vec::with_capacity(total.max(chunk));
+		loop {
+			let len = samples.len();
+			samples.resize(len + chunk, 0);
+			let written = source.write_samples(&mut samples[len..]);
+			samples.truncate(len + written);
+			if written < chunk {
+				break;
+			}
+		}
+
+		Self {
+			loop_region: source.loop_region(),
+			..Self::new(samples, channels, source.sample_rate())
+		}
+	}
+
+
+	pub fn channels (&self) -> u16 {
+		self.channels
+	}
+
+
+	pub fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	/// play at most `max` sounds of these samples at once, the sounds
+	/// made after this call from it or its clones
+	///
+	/// a sound that starts over the limit stops one that plays, with
+	/// a short fade, as `policy` chooses, or the least important ones
+	/// are made virtual. so many copies of the same
+	/// explosion don't turn into mush
+	pub fn max_instances (mut self, max: u32, policy: StealPolicy) -> Self {
+		self.max_instances = Some((max, policy));
+		self
+	}
+
+
+	/// interleaved
+	pub (crate) fn samples (&self) -> &[i16] {
+		&self.samples
+	}
+
+
+	/// the length in frames (samples per channel)
+	pub fn frames (&self) -> u64 {
+		(self.samples.len() / self.channels as usize) as u64
+	}
+
+
+	pub fn duration (&self) -> Duration {
+		Duration::from_secs_f64(self.frames() as f64 / self.sample_rate as f64)
+	}
+
+
+	/// a source that plays these samples from the start
+	pub fn source (&self) -> SoundDataSource {
+		SoundDataSource {
+			data: self.clone(),
+			position: 0,
+			reverse: false
+		}
+	}
+
+
+}
+
+
+
+/// plays the samples of a [`SoundData`], forward or backward
+pub struct SoundDataSource {
+
+	data: SoundData,
+	/// index of the next sample to write, backward it is the one
+	/// after it
+	position: usize,
+	reverse: bool
+
+}
+
+impl SoundSource for SoundDataSource {
+
+
+	fn reset (&mut self) {
+		self.position = if self.reverse { self.data.samples.len() } else { 0 };
+	}
+
+
+	fn channels (&self) -> u16 {
+		self.data.channels
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.data.sample_rate
+	}
+
+
+	fn total_frames (&self) -> Option<u64> {
+		Some(self.data.frames())
+	}
+
+
+	/// sample accurate
+	fn seek (&mut self, frame: u64) -> bool {
+		let position = frame.saturating_mul(self.data.channels as u64);
+		self.position = position.min(self.data.samples.len() as u64) as usize;
+		true
+	}
+
+
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		self.data.loop_region
+	}
+
+
+	fn set_direction (&mut self, direction: Direction) -> bool {
+		self.reverse = direction == Direction::Reverse;
+		true
+	}
+
+
+	fn instance_limit (&self) -> Option<InstanceLimit> {
+		let (max, policy) = self.data.max_instances?;
+		Some(InstanceLimit { key: self.data.samples.as_ptr() as usize, max, policy })
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		if self.reverse {
+			let channels = self.data.channels as usize;
+			let len = (buffer.len().min(self.position) / channels) * channels;
+			let samples = &self.data.samples[self.position - len..self.position];
+			for (frame, x) in buffer[..len].chunks_exact_mut(channels).zip(samples.chunks_exact(channels).rev()) {
+				frame.copy_from_slice(x);
+			}
+			self.position -= len;
+			return len;
+		}
+		let samples = &self.data.samples[self.position..];
+		let len = samples.len().min(buffer.len());
+		buffer[..len].copy_from_slice(&samples[..len]);
+		self.position += len;
+		len
+	}
+
+
+}
@@ -0,0 +1,386 @@
+
+
+
+//! SoundFont 2 files, played by a [`Synth`].
+//!
+//! Only what shapes the sound of a note is read: the key and velocity ranges of the zones, their
+//! samples and loops, tuning, attenuation, and the attack, decay, sustain and release of the
+//! volume envelope. Modulators, filters, the other envelope and the LFOs are ignored, as are
+//! 24 bit samples, which play at 16 bits.
+
+
+
+use std::io::{ self, Read };
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::envelope::Envelope;
+use crate::synth::{ Synth, SynthSample };
+
+
+
+/// the bank of the drum kits
+pub (crate) const DRUM_BANK: u16 = 128;
+
+const START_OFFSET: usize = 0;
+const END_OFFSET: usize = 1;
+const LOOP_START_OFFSET: usize = 2;
+const LOOP_END_OFFSET: usize = 3;
+const START_COARSE_OFFSET: usize = 4;
+const END_COARSE_OFFSET: usize = 12;
+const ATTACK: usize = 34;
+const DECAY: usize = 36;
+const SUSTAIN: usize = 37;
+const RELEASE: usize = 38;
+const INSTRUMENT: usize = 41;
+const KEY_RANGE: usize = 43;
+const VELOCITY_RANGE: usize = 44;
+const ATTENUATION: usize = 48;
+const LOOP_START_COARSE_OFFSET: usize = 45;
+const LOOP_END_COARSE_OFFSET: usize = 50;
+const COARSE_TUNE: usize = 51;
+const FINE_TUNE: usize = 52;
+const SAMPLE: usize = 53;
+const SAMPLE_MODES: usize = 54;
+const ROOT_KEY: usize = 58;
+const GENERATORS: usize = 61;
+
+
+
+fn invalid (message: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+
+/// the generators of a zone, `None` when not set
+#[derive(Clone, Copy)]
+struct Zone {
+	generators: [Option<i16>; GENERATORS]
+}
+
+impl Zone {
+
+	/// `self` with the generators of `local` over it
+	fn merge (&self, local: &Zone) -> Zone {
+		let mut zone = *self;
+		for (x, y) in zone.generators.iter_mut().zip(local.generators.iter()) {
+			*x = y.or(*x);
+		}
+		zone
+	}
+
+	fn get (&self, generator: usize) -> Option<i16> {
+		self.generators[generator]
+	}
+
+	/// the low and high bytes of a range generator
+	fn range (&self, generator: usize) -> (u8, u8) {
+		self.get(generator).map_or((0, 127), |x| ((x as u16 & 0xff) as u8, (x as u16 >> 8) as u8))
+	}
+
+}
+
+
+
+struct SampleHeader {
+	start: u32,
+	end: u32,
+	loop_start: u32,
+	loop_end: u32,
+	sample_rate: u32,
+	original_pitch: u8,
+	/// in cents
+	pitch_correction: i8,
+	kind: u16
+}
+
+
+
+/// the sample playing the notes of a preset
+struct Preset {
+	name: String,
+	bank: u16,
+	program: u8,
+	samples: Arc<[SynthSample]>
+}
+
+
+
+/// the instruments of a SoundFont 2 file
+///
+/// cheap to clone, the samples are shared
+#[derive(Clone)]
+pub struct SoundFont {
+	presets: Arc<[Preset]>
+}
+
+impl SoundFont {
+
+
+	/// read a whole `.sf2` file
+	pub fn new (mut data: impl Read) -> io::Result<Self> {
+		let mut bytes = vec![];
+		data.read_to_end(&mut bytes)?;
+
+		let (id, riff) = chunks(&bytes).next().ok_or_else(|| invalid("empty soundfont"))?;
+		if &id != b"RIFF" || riff.get(..4) != Some(b"sfbk") {
+			return Err(invalid("not a soundfont"));
+		}
+
+		let (mut smpl, mut pdta) = (None, None);
+		for (id, list) in chunks(&riff[4..]) {
+			if &id != b"LIST" || list.len() < 4 {
+				continue;
+			}
+			match &list[..4] {
+				b"sdta" => smpl = chunks(&list[4..]).find(|(id, _)| id == b"smpl").map(|(_, x)| x),
+				b"pdta" => pdta = Some(&list[4..]),
+				_ => ()
+			}
+		}
+		let samples: Arc<[f32]> = smpl.ok_or_else(|| invalid("soundfont without samples"))?
+			.chunks_exact(2)
+			.map(|x| i16::from_le_bytes([x[0], x[1]]) as f32 / 32768.0)
+			.collect();
+
+		let pdta = pdta.ok_or_else(|| invalid("soundfont without presets"))?;
+		let find = |name: &[u8; 4], size: usize| {
+			chunks(pdta)
+				.find(|(id, _)| id == name)
+				.map(|(_, x)| x.chunks_exact(size).collect::<Vec<_>>())
+				.ok_or_else(|| invalid("soundfont without preset data"))
+		};
+		let phdr = find(b"phdr", 38)?;
+		let pbag = find(b"pbag", 4)?;
+		let pgen = find(b"pgen", 4)?;
+		let inst = find(b"inst", 22)?;
+		let ibag = find(b"ibag", 4)?;
+		let igen = find(b"igen", 4)?;
+		let shdr = find(b"shdr", 46)?;
+
+		let headers: Vec<_> = shdr.iter()
+			.map(|x| SampleHeader {
+				start: u32_at(x, 20),
+				end: u32_at(x, 24),
+				loop_start: u32_at(x, 28),
+				loop_end: u32_at(x, 32),
+				sample_rate: u32_at(x, 36),
+				original_pitch: x[40],
+				pitch_correction: x[41] as i8,
+				kind: u16_at(x, 44)
+			})
+			.collect();
+
+		// the zones of each instrument, with their global zone merged in
+		let instruments: Vec<Vec<Zone>> = inst.windows(2)
+			.map(|x| zones(&ibag, &igen, u16_at(x[0], 20), u16_at(x[1], 20), SAMPLE))
+			.collect();
+
+		let mut presets = vec![];
+		for header in phdr.windows(2) {
+			let (name, header, next) = (name(header[0]), header[0], header[1]);
+			let mut regions = vec![];
+			for zone in zones(&pbag, &pgen, u16_at(header, 24), u16_at(next, 24), INSTRUMENT) {
+				let Some(instrument) = zone.get(INSTRUMENT).and_then(|x| instruments.get(x as u16 as usize)) else {
+					continue;
+				};
+				for local in instrument {
+					let Some(sample) = local.get(SAMPLE).and_then(|x| headers.get(x as u16 as usize)) else {
+						continue;
+					};
+					if let Some(region) = region(&samples, sample, &zone, local) {
+						regions.push(region);
+					}
+				}
+			}
+			presets.push(Preset {
+				name,
+				bank: u16_at(header, 22),
+				program: u16_at(header, 20).min(127) as u8,
+				samples: regions.into()
+			});
+		}
+
+		Ok(Self { presets: presets.into() })
+	}
+
+
+	/// the bank, program and name of every preset
+	pub fn presets (&self) -> impl Iterator<Item = (u16, u8, &str)> {
+		self.presets.iter().map(|x| (x.bank, x.program, x.name.as_str()))
+	}
+
+
+	/// a synth playing the preset `program` of `bank`, if there is one
+	///
+	/// the drum kits are in bank `128`
+	pub fn synth (&self, bank: u16, program: u8) -> Option<Synth> {
+		self.presets.iter()
+			.find(|x| x.bank == bank && x.program == program)
+			.map(|x| Synth::from_samples(x.samples.clone()))
+	}
+
+
+	/// the samples of a preset, or of the same program in the first
+	/// bank, or of the first preset, like general midi players do
+	pub (crate) fn preset (&self, bank: u16, program: u8) -> Arc<[SynthSample]> {
+		let fallback = if bank == DRUM_BANK { DRUM_BANK } else { 0 };
+		self.presets.iter()
+			.find(|x| x.bank == bank && x.program == program)
+			.or_else(|| self.presets.iter().find(|x| x.bank == fallback && x.program == program))
+			.or_else(|| self.presets.iter().find(|x| x.bank == fallback))
+			.or_else(|| self.presets.first())
+			.map_or_else(|| Arc::new([]) as Arc<[SynthSample]>, |x| x.samples.clone())
+	}
+
+
+}
+
+
+
+/// the chunks of a RIFF list, by id
+fn chunks (mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+	std::iter::from_fn(move || {
+		if data.len() < 8 {
+			return None;
+		}
+		let id = [data[0], data[1], data[2], data[3]];
+		let len = (u32_at(data, 4) as usize).min(data.len() - 8);
+		let chunk = &data[8..8 + len];
+		// chunks are padded to an even length
+		data = &data[(8 + len + len % 2).min(data.len())..];
+		Some((id, chunk))
+	})
+}
+
+
+
+/// the zones of the bags from `start` to `end`, with the global zone
+/// merged into the others. a zone is global when it is the first and
+/// doesn't end with a `last` generator
+fn zones (bags: &[&[u8]], generators: &[&[u8]], start: u16, end: u16, last: usize) -> Vec<Zone> {
+	let mut zones = vec![];
+	let mut global = Zone { generators: [None; GENERATORS] };
+	for i in start as usize..(end as usize).min(bags.len()) {
+		let first = u16_at(bags[i], 0) as usize;
+		// the generators of a bag end where the next bag starts, there
+		// is a terminal bag after the last
+		let next = bags.get(i + 1).map_or(generators.len(), |x| u16_at(x, 0) as usize);
+		let mut zone = Zone { generators: [None; GENERATORS] };
+		for generator in generators.get(first..next.min(generators.len())).unwrap_or(&[]) {
+			let kind = u16_at(generator, 0) as usize;
+			if kind < GENERATORS {
+				zone.generators[kind] = Some(u16_at(generator, 2) as i16);
+			}
+		}
+		if zone.get(last).is_some() {
+			zones.push(global.merge(&zone));
+		} else if i == start as usize {
+			global = zone;
+		}
+	}
+	zones
+}
+
+
+
+/// the region of `sample` played by the instrument zone `local` in the
+/// preset zone `preset`, if their ranges overlap
+fn region (samples: &Arc<[f32]>, sample: &SampleHeader, preset: &Zone, local: &Zone) -> Option<SynthSample> {
+	// rom samples aren't in the file
+	if sample.kind & 0x8000 != 0 {
+		return None;
+	}
+
+	let intersect = |a: (u8, u8), b: (u8, u8)| {
+		let range = (a.0.max(b.0), a.1.min(b.1));
+		(range.0 <= range.1).then_some(range)
+	};
+	let keys = intersect(preset.range(KEY_RANGE), local.range(KEY_RANGE))?;
+	let velocities = intersect(preset.range(VELOCITY_RANGE), local.range(VELOCITY_RANGE))?;
+
+	// the preset adds to the instrument
+	let value = |generator: usize, default: i16| {
+		local.get(generator).unwrap_or(default) as i32 + preset.get(generator).unwrap_or(0) as i32
+	};
+	let offset = |fine: usize, coarse: usize| {
+		local.get(fine).unwrap_or(0) as i64 + local.get(coarse).unwrap_or(0) as i64 * 32768
+	};
+	let position = |base: u32, fine: usize, coarse: usize| {
+		(base as i64 + offset(fine, coarse)).clamp(0, samples.len() as i64) as usize
+	};
+
+	let start = position(sample.start, START_OFFSET, START_COARSE_OFFSET);
+	let end = position(sample.end, END_OFFSET, END_COARSE_OFFSET).max(start);
+	let loop_start = position(sample.loop_start, LOOP_START_OFFSET, LOOP_START_COARSE_OFFSET).clamp(start, end);
+	let loop_end = position(sample.loop_end, LOOP_END_OFFSET, LOOP_END_COARSE_OFFSET).clamp(loop_start, end);
+	let mode = local.get(SAMPLE_MODES).unwrap_or(0) & 3;
+
+	let root = match local.get(ROOT_KEY) {
+		Some(x @ 0..=127) => x as u8,
+		_ if sample.original_pitch <= 127 => sample.original_pitch,
+		_ => 60
+	};
+	let tune = value(COARSE_TUNE, 0) as f32 + (value(FINE_TUNE, 0) + sample.pitch_correction as i32) as f32 / 100.0;
+
+	// linked left and right samples both play, mixed to mono
+	let linked = if sample.kind & 6 != 0 { 0.5 } else { 1.0 };
+	let gain = centibels(value(ATTENUATION, 0)) * linked;
+
+	let envelope = Envelope {
+		attack: timecents(value(ATTACK, -12000)),
+		decay: timecents(value(DECAY, -12000)),
+		sustain: centibels(value(SUSTAIN, 0)),
+		release: timecents(value(RELEASE, -12000))
+	};
+
+	Some(SynthSample {
+		samples: samples.clone(),
+		start,
+		end,
+		sample_rate: sample.sample_rate.max(1),
+		root_note: root,
+		tune,
+		gain,
+		keys: Some(keys),
+		velocities,
+		looped: (matches!(mode, 1 | 3) && loop_end > loop_start).then_some((loop_start, loop_end)),
+		loop_in_release: mode == 1,
+		envelope: Some(envelope)
+	})
+}
+
+
+
+/// the gain of an attenuation in centibels
+fn centibels (attenuation: i32) -> f32 {
+	10f32.powf(-attenuation.clamp(0, 1440) as f32 / 200.0)
+}
+
+
+
+/// a duration in timecents, `0` being a second
+fn timecents (timecents: i32) -> Duration {
+	Duration::from_secs_f32(2f32.powf(timecents.clamp(-12000, 8000) as f32 / 1200.0))
+}
+
+
+
+/// the name of a preset, up to its first nul
+fn name (header: &[u8]) -> String {
+	let name = &header[..20];
+	let len = name.iter().position(|&x| x == 0).unwrap_or(name.len());
+	String::from_utf8_lossy(&name[..len]).trim().to_string()
+}
+
+
+
+fn u16_at (data: &[u8], i: usize) -> u16 {
+	u16::from_le_bytes([data[i], data[i + 1]])
+}
+
+
+fn u32_at (data: &[u8], i: usize) -> u32 {
+	u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+}
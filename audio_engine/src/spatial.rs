@@ -0,0 +1,304 @@
+
+
+
+//! Sounds placed in 3D around a listener.
+//!
+//! A sound with a position gets quieter with its distance to the listener, following its
+//! [`Attenuation`], and is panned to the side it is on. Both are computed for every mixed
+//! buffer and smoothed over it. The velocities of the sound and of the listener shift its
+//! pitch, like a passing car.
+
+
+
+use std::sync::{ Arc, Mutex };
+
+use crate::easing::Ramp;
+use crate::hrtf::Binaural;
+use crate::mixer::{ self, Command, Mixer };
+use crate::queue::Queue;
+
+
+
+/// in units per second, the units being meters
+const SPEED_OF_SOUND: f32 = 343.0;
+
+
+
+/// where the sounds are heard from, see
+/// [`AudioEngine::listener`](crate::AudioEngine::listener)
+pub struct Listener {
+
+	pub (crate) mixer: Arc<Mutex<Mixer>>,
+	pub (crate) commands: Arc<Queue<Command>>
+
+}
+
+impl Listener {
+
+
+	pub fn set_position (&mut self, position: [f32; 3]) {
+		mixer::send(&self.mixer, &self.commands, Command::SetListenerPosition(position));
+	}
+
+
+	/// in units per second, for the doppler shift
+	pub fn set_velocity (&mut self, velocity: [f32; 3]) {
+		mixer::send(&self.mixer, &self.commands, Command::SetListenerVelocity(velocity));
+	}
+
+
+	/// how strong the doppler shift is, `1.0` is real and `0.0` turns
+	/// it off
+	///
+	/// the speed of sound is taken as 343 units per second, so a game
+	/// that isn't in meters can scale it with this
+	pub fn set_doppler_factor (&mut self, factor: f32) {
+		mixer::send(&self.mixer, &self.commands, Command::SetDopplerFactor(factor));
+	}
+
+
+	/// the direction the listener looks at, and the direction of the
+	/// top of their head
+	///
+	/// by default they look at `-z` with `+y` up, so `+x` is on the
+	/// right
+	pub fn set_orientation (&mut self, forward: [f32; 3], up: [f32; 3]) {
+		mixer::send(&self.mixer, &self.commands, Command::SetListenerOrientation(forward, up));
+	}
+
+
+}
+
+
+
+/// how the gain of a placed sound falls with its distance
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttenuationModel {
+	/// straight down to silence at the max distance
+	Linear,
+	/// like sounds in open air, the gain halves when the distance doubles
+	/// with a rolloff of `1.0`
+	Inverse {
+		rolloff: f32
+	},
+	/// falls faster the farther the sound is, higher rolloffs fall
+	/// faster
+	Exponential {
+		rolloff: f32
+	},
+	/// gains at distances, in order of distance, with straight lines
+	/// between them. the gain before the first point is the one of the
+	/// first point, and after the last the one of the last
+	Custom(Vec<(f32, f32)>)
+}
+
+
+
+/// how a placed sound rolls off, see
+/// [`Sound::set_attenuation`](crate::Sound::set_attenuation)
+///
+/// the distance is clamped between `min_distance` and `max_distance`,
+/// so the sound is at full volume when closer than the min, and
+/// doesn't get quieter past the max
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attenuation {
+
+	pub model: AttenuationModel,
+	pub min_distance: f32,
+	pub max_distance: f32
+
+}
+
+impl Attenuation {
+
+	/// the gain at `distance`
+	pub fn gain (&self, distance: f32) -> f32 {
+		let min = self.min_distance.max(1e-3);
+		let max = self.max_distance.max(min);
+		let distance = distance.clamp(min, max);
+		let gain = match &self.model {
+			AttenuationModel::Linear => if max > min { 1.0 - (distance - min) / (max - min) } else { 1.0 },
+			AttenuationModel::Inverse { rolloff } => min / (min + rolloff * (distance - min)),
+			AttenuationModel::Exponential { rolloff } => (distance / min).powf(-rolloff),
+			AttenuationModel::Custom(points) => curve(points, distance)
+		};
+		gain.clamp(0.0, 1.0)
+	}
+
+}
+
+impl Default for Attenuation {
+	fn default () -> Self {
+		Self {
+			model: AttenuationModel::Inverse { rolloff: 1.0 },
+			min_distance: 1.0,
+			max_distance: f32::INFINITY
+		}
+	}
+}
+
+
+
+/// the value at `x` along `points`, sorted by their `x`, joined by
+/// lines and flat past the ends
+pub (crate) fn curve (points: &[(f32, f32)], x: f32) -> f32 {
+	let Some(&(first, y)) = points.first() else {
+		return 1.0;
+	};
+	if x <= first {
+		return y;
+	}
+	for pair in points.windows(2) {
+		let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+		if x <= x1 {
+			let t = if x1 > x0 { (x - x0) / (x1 - x0) } else { 1.0 };
+			return y0 + (y1 - y0) * t;
+		}
+	}
+	points[points.len() - 1].1
+}
+
+
+
+/// the listener, as the mixer sees it
+pub (crate) struct ListenerState {
+	pub position: [f32; 3],
+	pub velocity: [f32; 3],
+	pub forward: [f32; 3],
+	pub up: [f32; 3],
+	pub doppler_factor: f32
+}
+
+impl ListenerState {
+
+	/// the unit vectors to the front, the right and the top of the
+	/// listener
+	pub fn basis (&self) -> ([f32; 3], [f32; 3], [f32; 3]) {
+		let forward = normalize(self.forward);
+		let right = normalize(cross(self.forward, self.up));
+		(forward, right, cross(right, forward))
+	}
+
+}
+
+impl Default for ListenerState {
+	fn default () -> Self {
+		Self {
+			position: [0.0; 3],
+			velocity: [0.0; 3],
+			forward: [0.0, 0.0, -1.0],
+			up: [0.0, 1.0, 0.0],
+			doppler_factor: 1.0
+		}
+	}
+}
+
+
+
+/// the position of a sound, and its gain and pan from the listener
+pub (crate) struct Emitter {
+
+	pub position: [f32; 3],
+	pub velocity: [f32; 3],
+	pub gain: Ramp,
+	pub pan: Ramp,
+	/// the speed of the sound is multiplied by this
+	pub doppler: f32,
+	/// where the sound is from the listener, right, up and front
+	pub direction: [f32; 3],
+	/// the same, along the axes of the world
+	pub world_direction: [f32; 3],
+	/// set once the sound is rendered with an hrtf
+	pub binaural: Option<Binaural>,
+	/// the gain and pan are not smoothed before the first buffer
+	started: bool
+
+}
+
+impl Emitter {
+
+
+	pub fn new (position: [f32; 3]) -> Self {
+		Self {
+			position,
+			velocity: [0.0; 3],
+			gain: Ramp::new(1.0),
+			pan: Ramp::new(0.0),
+			doppler: 1.0,
+			direction: [0.0, 0.0, 1.0],
+			world_direction: [0.0, 0.0, -1.0],
+			binaural: None,
+			started: false
+		}
+	}
+
+
+	/// move the gain and pan to where they are heard from `listener`,
+	/// over the next `frames`
+	///
+	/// a sound that isn't `panned` is placed by an hrtf or by the
+	/// ambisonics
+	pub fn update (&mut self, listener: &ListenerState, attenuation: &Attenuation, panned: bool, frames: u32) {
+		let offset = sub(self.position, listener.position);
+		let distance = length(offset);
+		let gain = attenuation.gain(distance);
+
+		let (forward, right, up) = listener.basis();
+		if distance > 0.0 {
+			self.world_direction = [offset[0] / distance, offset[1] / distance, offset[2] / distance];
+			self.direction = [dot(offset, right) / distance, dot(offset, up) / distance, dot(offset, forward) / distance];
+		}
+
+		// the side of the listener the sound is on, a sound right in
+		// front or behind stays in the center
+		let pan = if panned { self.direction[0] } else { 0.0 };
+
+		// the speeds of the listener and of the sound toward each other,
+		// kept under the speed of sound
+		self.doppler = if distance > 0.0 && listener.doppler_factor > 0.0 {
+			let direction = self.world_direction;
+			let max = SPEED_OF_SOUND * 0.9;
+			let listener_speed = (dot(listener.velocity, direction) * listener.doppler_factor).clamp(-max, max);
+			let sound_speed = (-dot(self.velocity, direction) * listener.doppler_factor).clamp(-max, max);
+			(SPEED_OF_SOUND + listener_speed) / (SPEED_OF_SOUND - sound_speed)
+		} else {
+			1.0
+		};
+
+		// a sound that doesn't move isn't smoothed, so its pan is only
+		// computed once per buffer
+		let frames = if self.started { frames } else { 0 };
+		self.gain.set(gain, if gain == self.gain.value { 0 } else { frames });
+		self.pan.set(pan, if pan == self.pan.value { 0 } else { frames });
+		self.started = true;
+	}
+
+
+}
+
+
+
+fn sub (a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+	[a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+
+pub (crate) fn dot (a: [f32; 3], b: [f32; 3]) -> f32 {
+	a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+
+fn cross (a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+	[a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+
+fn length (a: [f32; 3]) -> f32 {
+	dot(a, a).sqrt()
+}
+
+
+fn normalize (a: [f32; 3]) -> [f32; 3] {
+	let length = length(a);
+	if length > 0.0 { [a[0] / length, a[1] / length, a[2] / length] } else { a }
+}
@@ -0,0 +1,93 @@
+
+
+
+//! The stereo image: widening or narrowing it with mid/side, and folding it down to mono.
+
+
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU32, Ordering };
+
+use crate::effect::Effect;
+
+
+
+/// changes the width of a stereo sound, as an [`Effect`]
+///
+/// the sound is split in mid, what both channels share, and side,
+/// what differs between them, and the side is scaled by the width.
+/// does nothing unless there are exactly two channels
+pub struct StereoWidth {
+	width: Arc<AtomicU32>
+}
+
+impl StereoWidth {
+
+
+	/// a `width` of `0.0` is mono, `1.0` leaves the sound as it is and
+	/// up to `2.0` makes it wider
+	pub fn new (width: f32) -> Self {
+		Self { width: Arc::new(AtomicU32::new(width.to_bits())) }
+	}
+
+
+	/// change the width after it was given to a sound or group
+	pub fn controls (&self) -> StereoWidthControls {
+		StereoWidthControls { width: self.width.clone() }
+	}
+
+
+}
+
+impl Effect for StereoWidth {
+
+	fn process (&mut self, frames: &mut [f32], channels: u16, _: u32) {
+		if channels != 2 {
+			return;
+		}
+		let width = f32::from_bits(self.width.load(Ordering::Relaxed)).clamp(0.0, 2.0);
+		for frame in frames.chunks_exact_mut(2) {
+			let mid = (frame[0] + frame[1]) * 0.5;
+			let side = (frame[0] - frame[1]) * 0.5 * width;
+			frame[0] = mid + side;
+			frame[1] = mid - side;
+		}
+	}
+
+}
+
+
+
+/// changes a [`StereoWidth`] from any thread
+#[derive(Clone)]
+pub struct StereoWidthControls {
+	width: Arc<AtomicU32>
+}
+
+impl StereoWidthControls {
+
+
+	pub fn set_width (&self, width: f32) {
+		self.width.store(width.to_bits(), Ordering::Relaxed);
+	}
+
+
+	pub fn width (&self) -> f32 {
+		f32::from_bits(self.width.load(Ordering::Relaxed))
+	}
+
+
+}
+
+
+
+/// put the average of the channels of every frame in all of them
+pub (crate) fn to_mono (frames: &mut [f32], channels: u16) {
+	if channels < 2 {
+		return;
+	}
+	for frame in frames.chunks_exact_mut(channels as usize) {
+		let mono = frame.iter().sum::<f32>() / channels as f32;
+		frame.fill(mono);
+	}
+}
@@ -0,0 +1,333 @@
+
+
+
+//! Sounds decoded ahead of time on a background thread.
+//!
+//! The audio thread only reads from a lock-free ring of samples, so a slow read from storage
+//! never stalls the mix. If the ring runs dry, the sound plays silence until enough is decoded.
+
+
+
+use log::debug;
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, AtomicI16, AtomicU32, AtomicU64, AtomicUsize, Ordering };
+use std::thread::{ self, Thread };
+use std::time::Duration;
+
+use crate::mixer::SoundSource;
+
+
+
+/// decoded ahead by [`StreamingDecoder::new`]
+const DEFAULT_PREFETCH: Duration = Duration::from_secs(1);
+
+/// how many frames the decoding thread reads at a time
+const CHUNK_FRAMES: usize = 1024;
+
+/// how long the decoding thread sleeps when it has nothing to do,
+/// in case a wake up was missed
+const IDLE_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// a seek to the start, used by `reset`
+const NO_SEEK: u64 = u64::MAX;
+
+
+
+/// state shared by the decoding thread and the audio thread
+struct Shared {
+
+	/// ring of interleaved samples, its length is a power of two
+	ring: Box<[AtomicI16]>,
+	mask: usize,
+	/// position of the next read, only written by the audio thread
+	head: AtomicUsize,
+	/// position of the next write, only written by the decoding thread
+	tail: AtomicUsize,
+	/// bumped by the audio thread on every seek or reset
+	requested: AtomicU32,
+	/// the frame of the last seek, `NO_SEEK` for a reset
+	target: AtomicU64,
+	/// the last request handled by the decoding thread
+	handled: AtomicU32,
+	/// where the samples of the `handled` request start in the ring
+	start: AtomicUsize,
+	/// the source ended, since the `handled` request
+	ended: AtomicBool,
+	/// the `StreamingDecoder` was dropped
+	closed: AtomicBool
+
+}
+
+impl Shared {
+
+	fn available (&self) -> usize {
+		self.tail.load(Ordering::Acquire).wrapping_sub(self.head.load(Ordering::Acquire))
+	}
+
+}
+
+
+
+/// Streaming Decoder
+///
+/// decodes a source on a background thread, for long sounds like
+/// music that would take too much memory decoded at once. a
+/// `prefetch` of the sound is kept decoded ahead, which covers slow
+/// reads from storage. if the audio thread catches up anyway, the
+/// sound plays silence until part of the prefetch is decoded again,
+/// see [`StreamingDecoder::underruns`]
+///
+/// seeking and resetting are done by the decoding thread, so they
+/// take effect after a small delay. this includes the jump back to
+/// the start of a looping sound
+pub struct StreamingDecoder {
+
+	shared: Arc<Shared>,
+	thread: Thread,
+	channels: u16,
+	sample_rate: u32,
+	total_frames: Option<u64>,
+	loop_region: Option<(u64, u64)>,
+	/// the samples of the last request are being played
+	synced: bool,
+	/// playing silence until the ring fills to `resume_at` samples
+	recovering: bool,
+	resume_at: usize,
+	underruns: Arc<AtomicU32>
+
+}
+
+impl StreamingDecoder {
+
+
+	/// start decoding `source` on a new thread, one second ahead
+	pub fn new <T: SoundSource + Send + 'static> (source: T) -> Self {
+		Self::with_prefetch(source, DEFAULT_PREFETCH)
+	}
+
+
+	/// start decoding `source` on a new thread, `prefetch` ahead
+	pub fn with_prefetch <T: SoundSource + Send + 'static> (source: T, prefetch: Duration) -> Self {
+		let channels = source.channels();
+		let sample_rate = source.sample_rate();
+		let frames = (prefetch.as_secs_f64() * sample_rate as f64) as usize;
+		let len = (frames.max(CHUNK_FRAMES * 2) * channels as usize).next_power_of_two();
+
+		let shared = Arc::new(Shared {
+			ring: (0..len).map(|_| AtomicI16::new(0)).collect(),
+			mask: len - 1,
+			head: AtomicUsize::new(0),
+			tail: AtomicUsize::new(0),
+			requested: AtomicU32::new(0),
+			target: AtomicU64::new(NO_SEEK),
+			handled: AtomicU32::new(0),
+			start: AtomicUsize::new(0),
+			ended: AtomicBool::new(false),
+			closed: AtomicBool::new(false)
+		});
+
+		let total_frames = source.total_frames();
+		let loop_region = source.loop_region();
+		let thread = {
+			let shared = shared.clone();
+			thread::Builder::new()
+				.name("audio streaming".into())
+				.spawn(move || decode(source, &shared))
+				.expect("failed to spawn the streaming thread")
+				.thread()
+				.clone()
+		};
+
+		Self {
+			shared,
+			thread,
+			channels,
+			sample_rate,
+			total_frames,
+			loop_region,
+			synced: true,
+			// wait for the first samples, instead of starting with an underrun
+			recovering: true,
+			resume_at: len / 4,
+			underruns: Arc::new(AtomicU32::new(0))
+		}
+	}
+
+
+	/// how many times the sound ran out of decoded samples
+	///
+	/// the counter is shared, so it can be read after the decoder is
+	/// moved into the engine
+	pub fn underruns (&self) -> Arc<AtomicU32> {
+		self.underruns.clone()
+	}
+
+
+	/// how full the ring is, from `0.0` to `1.0`
+	#[cfg(feature = "http")]
+	pub (crate) fn buffered (&self) -> f32 {
+		if !self.synced {
+			return 0.0;
+		}
+		self.shared.available() as f32 / self.shared.ring.len() as f32
+	}
+
+
+	/// playing silence until enough is decoded
+	#[cfg(feature = "http")]
+	pub (crate) fn is_buffering (&self) -> bool {
+		self.recovering
+	}
+
+
+	/// ask the decoding thread to move to `target`
+	fn request (&mut self, target: u64) {
+		self.shared.target.store(target, Ordering::Release);
+		self.shared.requested.fetch_add(1, Ordering::AcqRel);
+		self.synced = false;
+		self.recovering = true;
+		self.thread.unpark();
+	}
+
+
+}
+
+impl SoundSource for StreamingDecoder {
+
+
+	fn reset (&mut self) {
+		self.request(NO_SEEK);
+	}
+
+
+	fn channels (&self) -> u16 {
+		self.channels
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	fn total_frames (&self) -> Option<u64> {
+		self.total_frames
+	}
+
+
+	/// the jump back is delayed like any seek, so it isn't seamless
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		self.loop_region
+	}
+
+
+	/// as precise as the seek of the streamed source. if that source
+	/// can't seek, it keeps playing from where it was
+	fn seek (&mut self, frame: u64) -> bool {
+		self.request(frame.min(NO_SEEK - 1));
+		true
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+
+		let shared = &*self.shared;
+		if !self.synced {
+			let requested = shared.requested.load(Ordering::Acquire);
+			if shared.handled.load(Ordering::Acquire) == requested {
+				// drop what was decoded before the request
+				shared.head.store(shared.start.load(Ordering::Acquire), Ordering::Release);
+				self.synced = true;
+			}
+		}
+
+		// the end is published after the last samples, so if it is
+		// seen here, every sample of the source is available
+		let ended = self.synced && shared.ended.load(Ordering::Acquire);
+		let available = if self.synced { shared.available() } else { 0 };
+		if self.recovering && (available >= self.resume_at || ended) {
+			self.recovering = false;
+		}
+
+		let mut len = 0;
+		if !self.recovering {
+			let head = shared.head.load(Ordering::Relaxed);
+			len = available.min(buffer.len());
+			for (i, b) in buffer[..len].iter_mut().enumerate() {
+				*b = shared.ring[head.wrapping_add(i) & shared.mask].load(Ordering::Relaxed);
+			}
+			shared.head.store(head.wrapping_add(len), Ordering::Release);
+			self.thread.unpark();
+
+			if len < buffer.len() && ended {
+				return len;
+			}
+			if len < buffer.len() {
+				self.underruns.fetch_add(1, Ordering::Relaxed);
+				self.recovering = true;
+			}
+		}
+
+		for b in buffer[len..].iter_mut() {
+			*b = 0;
+		}
+		buffer.len()
+
+	}
+
+
+}
+
+impl Drop for StreamingDecoder {
+	fn drop (&mut self) {
+		// the thread is not joined, this may be dropped on the audio thread
+		self.shared.closed.store(true, Ordering::Release);
+		self.thread.unpark();
+	}
+}
+
+
+
+/// the loop of the decoding thread
+fn decode <T: SoundSource> (mut source: T, shared: &Shared) {
+
+	let chunk = CHUNK_FRAMES * source.channels() as usize;
+	let mut scratch = vec![0; chunk];
+	let mut handled = 0;
+
+	while !shared.closed.load(Ordering::Acquire) {
+
+		let requested = shared.requested.load(Ordering::Acquire);
+		if requested != handled {
+			match shared.target.load(Ordering::Acquire) {
+				NO_SEEK => source.reset(),
+				frame => if !source.seek(frame) {
+					debug!("streamed sound source can't seek");
+				}
+			}
+			handled = requested;
+			shared.ended.store(false, Ordering::Release);
+			shared.start.store(shared.tail.load(Ordering::Relaxed), Ordering::Release);
+			shared.handled.store(handled, Ordering::Release);
+		}
+
+		let free = shared.ring.len() - shared.available();
+		if shared.ended.load(Ordering::Relaxed) || free < chunk {
+			thread::park_timeout(IDLE_TIMEOUT);
+			continue;
+		}
+
+		let len = source.write_samples(&mut scratch);
+		let tail = shared.tail.load(Ordering::Relaxed);
+		for (i, x) in scratch[..len].iter().enumerate() {
+			shared.ring[tail.wrapping_add(i) & shared.mask].store(*x, Ordering::Relaxed);
+		}
+		shared.tail.store(tail.wrapping_add(len), Ordering::Release);
+		if len < chunk {
+			shared.ended.store(true, Ordering::Release);
+		}
+
+	}
+
+}
@@ -0,0 +1,233 @@
+
+
+
+//! Time-stretching, to change the tempo of a sound without changing its pitch.
+//!
+//! This is WSOLA: the output is made of overlapped segments of the input, each faded with a
+//! Hann window, that are taken from the input further apart or closer together than they are
+//! laid in the output. Each segment is moved a little from where the tempo puts it, to where it
+//! continues the last one best, so the waveforms line up and nothing phases or clicks.
+
+
+
+use crate::mixer::SoundSource;
+
+
+
+/// the length of a segment, a hop is half of it
+const SEGMENT_SECONDS: f64 = 0.04;
+
+/// how far a segment may move from where the tempo puts it
+const TOLERANCE_SECONDS: f64 = 0.01;
+
+/// the stride of the coarse search for the best segment
+const COARSE_STEP: usize = 4;
+
+/// frames read from the source at a time
+const CHUNK_FRAMES: usize = 512;
+
+
+
+/// stretches the samples of a source by a tempo, `2.0` is twice as
+/// fast at the same pitch
+pub (crate) struct Stretcher {
+
+	channels: usize,
+	tempo: f64,
+	/// the frames of a segment, of a hop between two in the output,
+	/// and of the tolerance of the search
+	size: usize,
+	hop: usize,
+	tolerance: usize,
+	window: Box<[f32]>,
+	/// interleaved frames of the source, the first is frame `start`
+	input: Vec<f32>,
+	start: u64,
+	/// the frame after the last one of the source, once it ended
+	end: Option<u64>,
+	/// where the tempo puts the next segment
+	analysis: f64,
+	/// where the last segment continues, the next one is matched to it
+	continuation: Option<u64>,
+	/// the segments added up, the first `ready` frames are finished,
+	/// `written` of them were written out
+	output: Box<[f32]>,
+	ready: usize,
+	written: usize,
+	/// the frame of the source that plays
+	played: f64,
+	chunk: Box<[i16]>
+
+}
+
+impl Stretcher {
+
+
+	pub fn new (channels: u16, sample_rate: u32) -> Self {
+		let channels = channels.max(1) as usize;
+		let hop = ((SEGMENT_SECONDS * sample_rate as f64) as usize / 2).max(COARSE_STEP);
+		let size = hop * 2;
+		Self {
+			channels,
+			tempo: 1.0,
+			size,
+			hop,
+			tolerance: (TOLERANCE_SECONDS * sample_rate as f64) as usize,
+			window: (0..size).map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / size as f32).cos()).collect(),
+			input: vec![],
+			start: 0,
+			end: None,
+			analysis: 0.0,
+			continuation: None,
+			output: vec![0.0; size * channels].into_boxed_slice(),
+			ready: 0,
+			written: 0,
+			played: 0.0,
+			chunk: vec![0; CHUNK_FRAMES * channels].into_boxed_slice()
+		}
+	}
+
+
+	/// from `0.125` to `8.0`, the tempo of the sound with the speed
+	/// of its pitch shift taken out
+	pub fn set_tempo (&mut self, tempo: f32) {
+		self.tempo = tempo.clamp(0.125, 8.0) as f64;
+	}
+
+
+	/// forget the frames that were read, must be called together with
+	/// the `reset` or `seek` of the source
+	pub fn reset (&mut self) {
+		self.input.clear();
+		self.start = 0;
+		self.end = None;
+		self.analysis = 0.0;
+		self.continuation = None;
+		self.output.fill(0.0);
+		self.ready = 0;
+		self.written = 0;
+		self.played = 0.0;
+	}
+
+
+	/// how many frames were read from the source after the one that
+	/// plays
+	pub fn lag (&self) -> u64 {
+		let read = self.start + (self.input.len() / self.channels) as u64;
+		read.saturating_sub(self.played as u64)
+	}
+
+
+	/// read the source until frame `frame` is in `input`, or it ends
+	fn fill (&mut self, source: &mut dyn SoundSource, frame: u64) {
+		while self.end.is_none() && self.start + (self.input.len() / self.channels) as u64 <= frame {
+			let len = source.write_samples(&mut self.chunk);
+			self.input.extend(self.chunk[..len].iter().map(|x| *x as f32));
+			if len < self.chunk.len() {
+				self.end = Some(self.start + (self.input.len() / self.channels) as u64);
+			}
+		}
+	}
+
+
+	/// the sample of `channel` at `frame`, silent out of the input
+	fn sample (&self, frame: u64, channel: usize) -> f32 {
+		frame.checked_sub(self.start)
+			.and_then(|x| self.input.get(x as usize * self.channels + channel))
+			.copied()
+			.unwrap_or(0.0)
+	}
+
+
+	/// how much the overlap of a segment at `frame` looks like the
+	/// continuation of the last one
+	fn similarity (&self, frame: u64, continuation: u64, step: usize) -> f32 {
+		let overlap = self.size - self.hop;
+		let (mut product, mut energy) = (0.0, 1e-3);
+		for i in (0..overlap).step_by(step) {
+			let a: f32 = (0..self.channels).map(|c| self.sample(frame + i as u64, c)).sum();
+			let b: f32 = (0..self.channels).map(|c| self.sample(continuation + i as u64, c)).sum();
+			product += a * b;
+			energy += a * a;
+		}
+		product / energy.sqrt()
+	}
+
+
+	/// add the next segment to the output
+	fn next_segment (&mut self, source: &mut dyn SoundSource) {
+		let target = self.analysis.round() as u64;
+		let low = target.saturating_sub(self.tolerance as u64).max(self.start);
+		let high = target + self.tolerance as u64;
+		self.fill(source, high + self.size as u64);
+
+		let frame = match self.continuation {
+			None => target,
+			Some(continuation) => {
+				// a coarse search, then a fine one around the best
+				let best = |this: &Self, range: std::ops::RangeInclusive<u64>, step: usize| {
+					range.step_by(step)
+						.map(|x| (x, this.similarity(x, continuation, step.min(2))))
+						.fold((target, f32::MIN), |a, b| if b.1 > a.1 { b } else { a })
+						.0
+				};
+				let coarse = best(self, low..=high, COARSE_STEP);
+				let step = COARSE_STEP as u64;
+				best(self, coarse.saturating_sub(step).max(low)..=(coarse + step).min(high), 1)
+			}
+		};
+
+		// the finished hop was written, the rest of the output moves
+		// to its start
+		let channels = self.channels;
+		self.output.copy_within(self.hop * channels.., 0);
+		self.output[(self.size - self.hop) * channels..].fill(0.0);
+		for i in 0..self.size {
+			// the first segment starts at full volume
+			let window = if self.continuation.is_none() && i < self.hop { 1.0 } else { self.window[i] };
+			for c in 0..channels {
+				self.output[i * channels + c] += self.sample(frame + i as u64, c) * window;
+			}
+		}
+		self.ready = self.hop;
+		self.written = 0;
+		self.continuation = Some(frame + self.hop as u64);
+		self.analysis += self.hop as f64 * self.tempo;
+
+		// the frames before the next search are not needed anymore
+		let keep = (self.analysis as u64).saturating_sub(self.tolerance as u64).min(frame + self.hop as u64);
+		if keep > self.start {
+			let drop = ((keep - self.start) as usize * channels).min(self.input.len());
+			self.input.drain(..drop);
+			self.start += (drop / channels) as u64;
+		}
+	}
+
+
+	/// write the samples of `source` at the tempo to `buffer`
+	///
+	/// works like [`SoundSource::write_samples`]
+	pub fn write_samples (&mut self, source: &mut dyn SoundSource, buffer: &mut [i16]) -> usize {
+		let channels = self.channels;
+		let mut len = 0;
+		while len < buffer.len() {
+			if self.end.is_some_and(|end| self.played >= end as f64) {
+				break;
+			}
+			if self.written == self.ready {
+				self.next_segment(source);
+			}
+			let frames = (self.ready - self.written).min((buffer.len() - len) / channels);
+			let output = &self.output[self.written * channels..(self.written + frames) * channels];
+			for (x, y) in buffer[len..len + frames * channels].iter_mut().zip(output) {
+				*x = y.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+			}
+			self.written += frames;
+			self.played += frames as f64 * self.tempo;
+			len += frames * channels;
+		}
+		len
+	}
+
+
+}
@@ -0,0 +1,526 @@
+
+
+
+//! A small wavetable and sampler synthesizer, played with notes from any thread.
+//!
+//! A [`Synth`] is a [`SoundSource`] that plays either a single cycle waveform at the pitch of
+//! each note, or recorded samples, sped up or slowed down to the note. The samples can be
+//! split over ranges of notes and velocities, which is also how SoundFont presets are played.
+//! Notes are sent through its [`SynthControls`] and take a voice each, the oldest voice being
+//! stolen when they are all taken.
+
+
+
+use std::sync::Arc;
+
+use crate::envelope::{ Envelope, EnvelopeState };
+use crate::mixer::SoundSource;
+use crate::queue::Queue;
+use crate::sound_data::SoundData;
+
+
+
+/// the sample rate of the synth, unless set otherwise
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// notes sent between two buffers, more are dropped
+const NOTE_QUEUE_CAPACITY: usize = 256;
+
+
+
+/// a recorded note of a [`Synth::sampler`]
+#[derive(Clone)]
+pub struct SynthSample {
+
+	/// mixed down to mono, may be shared with other samples
+	pub (crate) samples: Arc<[f32]>,
+	/// the frames of `samples` that are played
+	pub (crate) start: usize,
+	pub (crate) end: usize,
+	pub (crate) sample_rate: u32,
+	pub (crate) root_note: u8,
+	/// semitones added to the pitch
+	pub (crate) tune: f32,
+	pub (crate) gain: f32,
+	/// the lowest and highest notes it plays, `None` to play the notes
+	/// it is the closest to
+	pub (crate) keys: Option<(u8, u8)>,
+	pub (crate) velocities: (u8, u8),
+	/// the start and end frames of the loop, in `samples`
+	pub (crate) looped: Option<(usize, usize)>,
+	/// the loop carries on after the note is released, otherwise the
+	/// sample plays to its end
+	pub (crate) loop_in_release: bool,
+	/// replaces the envelope of the synth
+	pub (crate) envelope: Option<Envelope>
+
+}
+
+impl SynthSample {
+
+
+	/// the samples of `data`, which is the midi note `root_note`
+	///
+	/// played once, a note ends with the sample
+	pub fn new (data: &SoundData, root_note: u8) -> Self {
+		let channels = data.channels().max(1) as usize;
+		let samples: Arc<[f32]> = data.samples()
+			.chunks_exact(channels)
+			.map(|x| x.iter().map(|&x| x as f32).sum::<f32>() / (channels as f32 * i16::MAX as f32))
+			.collect();
+		Self {
+			start: 0,
+			end: samples.len(),
+			samples,
+			sample_rate: data.sample_rate(),
+			root_note,
+			tune: 0.0,
+			gain: 1.0,
+			keys: None,
+			velocities: (0, 127),
+			looped: None,
+			loop_in_release: false,
+			envelope: None
+		}
+	}
+
+
+	/// a single cycle waveform, looped at the frequency of the note
+	fn cycle (table: Arc<[f32]>) -> Self {
+		let len = table.len();
+		Self {
+			samples: table,
+			start: 0,
+			end: len,
+			// a cycle per period of the A at 440Hz
+			sample_rate: (len as u32).saturating_mul(440).max(1),
+			root_note: 69,
+			tune: 0.0,
+			gain: 1.0,
+			keys: None,
+			velocities: (0, 127),
+			looped: (len > 0).then_some((0, len)),
+			loop_in_release: true,
+			envelope: None
+		}
+	}
+
+
+	/// loop the frames from `start` to `end` while the note is held,
+	/// for sounds that sustain
+	pub fn looped (mut self, start: u64, end: u64) -> Self {
+		let end = (end as usize).min(self.end);
+		let start = (start as usize).min(end);
+		self.looped = (end > start).then_some((start, end));
+		self
+	}
+
+
+	/// only play the notes from `low` to `high`, for multisamples
+	///
+	/// every sample with the note in its range plays, and the samples
+	/// with no range only play when none does, the closest of them to
+	/// the note
+	pub fn keys (mut self, low: u8, high: u8) -> Self {
+		self.keys = Some((low, high));
+		self
+	}
+
+
+	/// only play the notes with a velocity from `low` to `high`, for
+	/// layers recorded at different strengths
+	pub fn velocities (mut self, low: u8, high: u8) -> Self {
+		self.velocities = (low, high);
+		self
+	}
+
+
+	fn plays (&self, velocity: u8) -> bool {
+		(self.velocities.0..=self.velocities.1).contains(&velocity)
+	}
+
+
+}
+
+
+
+enum NoteEvent {
+	On(u8, u8),
+	Off(u8),
+	AllOff
+}
+
+
+
+/// a note being played
+struct Voice {
+
+	note: u8,
+	gain: f32,
+	sample: SynthSample,
+	/// in frames of the sample
+	position: f64,
+	/// how much `position` moves per frame
+	step: f64,
+	envelope: EnvelopeState,
+	/// lower voices started earlier, and are stolen first
+	age: u64,
+	/// the key of the note is down
+	held: bool,
+	/// the envelope is released, which happens after the key is up
+	/// and the sustain pedal too
+	released: bool
+
+}
+
+impl Voice {
+
+	fn release (&mut self, sample_rate: u32) {
+		self.released = true;
+		self.envelope.release(sample_rate);
+	}
+
+}
+
+
+
+/// a synthesizer, as a [`SoundSource`]
+///
+/// it is mono and never ends. notes are played with its
+/// [`controls`](Synth::controls), from the next mixed buffer
+///
+/// ```
+/// # use audio_engine::{ AudioEngine, Synth };
+/// # let (engine, _backend) = AudioEngine::offline(2, 48000);
+/// # let table: Vec<f32> = (0..64).map(|i| (i as f32 / 64.0 * std::f32::consts::TAU).sin()).collect();
+/// let synth = Synth::wavetable(table).polyphony(8);
+/// let keys = synth.controls();
+/// engine.new_sound(synth, |x| x)?.play();
+/// keys.note_on(60, 100);
+/// # Ok::<(), audio_engine::Error>(())
+/// ```
+pub struct Synth {
+
+	samples: Arc<[SynthSample]>,
+	voices: Vec<Voice>,
+	notes: Arc<Queue<NoteEvent>>,
+	polyphony: usize,
+	envelope: Envelope,
+	amplitude: f32,
+	sample_rate: u32,
+	/// the age of the next voice
+	age: u64,
+	/// released notes keep playing until the pedal is up
+	sustain: bool,
+	/// in semitones, added to the pitch of every voice
+	bend: f32
+
+}
+
+impl Synth {
+
+
+	pub (crate) fn from_samples (samples: Arc<[SynthSample]>) -> Self {
+		Self {
+			samples,
+			voices: Vec::with_capacity(16),
+			notes: Arc::new(Queue::with_capacity(NOTE_QUEUE_CAPACITY)),
+			polyphony: 16,
+			envelope: Envelope::default(),
+			amplitude: 0.5,
+			sample_rate: DEFAULT_SAMPLE_RATE,
+			age: 0,
+			sustain: false,
+			bend: 0.0
+		}
+	}
+
+
+	/// play the single cycle waveform `table`, from `-1.0` to `1.0`, at
+	/// the frequency of each note
+	///
+	/// the table is read with linear interpolation, so longer tables
+	/// sound smoother. an empty table is silent
+	pub fn wavetable (table: impl Into<Arc<[f32]>>) -> Self {
+		Self::from_samples(Arc::new([SynthSample::cycle(table.into())]))
+	}
+
+
+	/// play the samples that match each note, at the pitch of the note,
+	/// see [`SynthSample::keys`]
+	pub fn sampler (samples: Vec<SynthSample>) -> Self {
+		Self::from_samples(samples.into())
+	}
+
+
+	/// how many notes play at once, 16 by default
+	pub fn polyphony (mut self, polyphony: usize) -> Self {
+		self.polyphony = polyphony.max(1);
+		self.voices.reserve(self.polyphony);
+		self
+	}
+
+
+	/// the envelope of every note, released with the note
+	pub fn envelope (mut self, envelope: Envelope) -> Self {
+		self.envelope = envelope;
+		self
+	}
+
+
+	/// the gain of a note at full velocity, `0.5` by default
+	pub fn amplitude (mut self, amplitude: f32) -> Self {
+		self.amplitude = amplitude;
+		self
+	}
+
+
+	/// 48kHz by default
+	pub fn sample_rate (mut self, sample_rate: u32) -> Self {
+		self.sample_rate = sample_rate.max(1);
+		self
+	}
+
+
+	/// play notes after the synth was given to a sound
+	pub fn controls (&self) -> SynthControls {
+		SynthControls { notes: self.notes.clone() }
+	}
+
+
+	/// play new notes with `samples`, the notes playing keep theirs
+	pub (crate) fn set_samples (&mut self, samples: Arc<[SynthSample]>) {
+		self.samples = samples;
+	}
+
+
+	pub (crate) fn set_amplitude (&mut self, amplitude: f32) {
+		self.amplitude = amplitude;
+	}
+
+
+	/// in semitones
+	pub (crate) fn set_bend (&mut self, bend: f32) {
+		self.bend = bend;
+	}
+
+
+	pub (crate) fn set_sustain (&mut self, sustain: bool) {
+		self.sustain = sustain;
+		if !sustain {
+			for voice in self.voices.iter_mut().filter(|x| !x.held && !x.released) {
+				voice.release(self.sample_rate);
+			}
+		}
+	}
+
+
+	/// no note is playing
+	pub (crate) fn is_silent (&self) -> bool {
+		self.voices.is_empty()
+	}
+
+
+	/// stop every note right away
+	pub (crate) fn stop (&mut self) {
+		self.voices.clear();
+		self.sustain = false;
+		self.bend = 0.0;
+	}
+
+
+	pub (crate) fn note_on (&mut self, note: u8, velocity: u8) {
+		if velocity == 0 {
+			return self.note_off(Some(note));
+		}
+		let velocity = velocity.min(127);
+
+		// every sample with the note in its range, or else the closest
+		let samples = self.samples.clone();
+		let mut playing = samples.iter()
+			.filter(|x| x.plays(velocity) && x.keys.is_some_and(|(low, high)| (low..=high).contains(&note)))
+			.peekable();
+		if playing.peek().is_none() {
+			let closest = samples.iter()
+				.filter(|x| x.plays(velocity) && x.keys.is_none())
+				.min_by_key(|x| (x.root_note as i32 - note as i32).abs());
+			if let Some(sample) = closest {
+				self.start_voice(sample, note, velocity);
+			}
+			return;
+		}
+		for sample in playing {
+			self.start_voice(sample, note, velocity);
+		}
+	}
+
+
+	fn start_voice (&mut self, sample: &SynthSample, note: u8, velocity: u8) {
+		// the oldest released voice is stolen first, then the oldest
+		if self.voices.len() >= self.polyphony {
+			let oldest = self.voices.iter()
+				.enumerate()
+				.min_by_key(|(_, x)| (!x.released, x.held, x.age))
+				.map(|(i, _)| i);
+			if let Some(i) = oldest {
+				self.voices.swap_remove(i);
+			}
+		}
+
+		let ratio = 2f64.powf((note as f64 - sample.root_note as f64 + sample.tune as f64) / 12.0);
+		let mut envelope = EnvelopeState::new(sample.envelope.unwrap_or(self.envelope));
+		envelope.start(self.sample_rate);
+		self.voices.push(Voice {
+			note,
+			gain: velocity as f32 / 127.0 * sample.gain,
+			sample: sample.clone(),
+			position: sample.start as f64,
+			step: ratio * sample.sample_rate as f64 / self.sample_rate as f64,
+			envelope,
+			age: self.age,
+			held: true,
+			released: false
+		});
+		self.age += 1;
+	}
+
+
+	pub (crate) fn note_off (&mut self, note: Option<u8>) {
+		for voice in self.voices.iter_mut().filter(|x| x.held && note.is_none_or(|note| x.note == note)) {
+			voice.held = false;
+			if !self.sustain {
+				voice.release(self.sample_rate);
+			}
+		}
+	}
+
+
+	fn process_notes (&mut self) {
+		while let Some(event) = self.notes.pop() {
+			match event {
+				NoteEvent::On(note, velocity) => self.note_on(note, velocity),
+				NoteEvent::Off(note) => self.note_off(Some(note)),
+				NoteEvent::AllOff => self.note_off(None)
+			}
+		}
+	}
+
+
+	/// add the voices to `mix`
+	pub (crate) fn render (&mut self, mix: &mut [f32]) {
+		let bend = 2f64.powf(self.bend as f64 / 12.0);
+		for voice in self.voices.iter_mut() {
+			play(voice, bend, self.amplitude, mix);
+		}
+		self.voices.retain(|x| !x.envelope.is_released());
+	}
+
+
+}
+
+impl SoundSource for Synth {
+
+
+	fn channels (&self) -> u16 {
+		1
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	/// stop every note
+	fn reset (&mut self) {
+		self.stop();
+	}
+
+
+	/// never ends
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		self.process_notes();
+
+		let mut mix = [0.0f32; 256];
+		for chunk in buffer.chunks_mut(mix.len()) {
+			let mix = &mut mix[..chunk.len()];
+			mix.fill(0.0);
+			self.render(mix);
+			for (x, &y) in chunk.iter_mut().zip(mix.iter()) {
+				*x = (y * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+			}
+		}
+		buffer.len()
+	}
+
+
+}
+
+
+
+/// add `voice` to `mix`, with its pitch multiplied by `bend`, and
+/// release it when its sample ends
+fn play (voice: &mut Voice, bend: f64, amplitude: f32, mix: &mut [f32]) {
+	let Voice { sample, position, step, envelope, gain, released, .. } = voice;
+	let looped = sample.looped.filter(|_| !*released || sample.loop_in_release);
+	let step = *step * bend;
+	let gain = *gain * amplitude;
+	for x in mix.iter_mut() {
+		if let Some((start, end)) = looped {
+			if *position >= end as f64 {
+				*position = start as f64 + (*position - end as f64) % (end - start) as f64;
+			}
+		}
+		let i = *position as usize;
+		if i >= sample.end {
+			// silent right away, the sample ended
+			*released = true;
+			envelope.release(0);
+			envelope.next();
+			return;
+		}
+		let next = match looped {
+			Some((start, end)) if i + 1 >= end => start,
+			_ => i + 1
+		};
+		let a = sample.samples[i];
+		let b = if next < sample.end { sample.samples[next] } else { 0.0 };
+		let t = (*position - i as f64) as f32;
+		*x += (a + (b - a) * t) * gain * envelope.next();
+		*position += step;
+	}
+}
+
+
+
+/// plays the notes of a [`Synth`] from any thread
+///
+/// notes are midi note numbers, `60` being the middle C, with a
+/// velocity from `0` to `127`
+#[derive(Clone)]
+pub struct SynthControls {
+	notes: Arc<Queue<NoteEvent>>
+}
+
+impl SynthControls {
+
+
+	/// start `note`, a velocity of `0` releases it
+	pub fn note_on (&self, note: u8, velocity: u8) {
+		let _ = self.notes.push(NoteEvent::On(note, velocity));
+	}
+
+
+	/// release `note`, it fades out along the release of the envelope
+	pub fn note_off (&self, note: u8) {
+		let _ = self.notes.push(NoteEvent::Off(note));
+	}
+
+
+	/// release every note
+	pub fn all_notes_off (&self) {
+		let _ = self.notes.push(NoteEvent::AllOff);
+	}
+
+
+}
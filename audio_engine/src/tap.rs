@@ -0,0 +1,149 @@
+
+
+
+//! Copies of the final mix, read outside of the audio thread.
+//!
+//! The mixer writes every buffer it outputs to a lock-free ring, and the reader takes them out
+//! at its own pace. A reader that falls behind loses whole buffers, the mix never waits for it.
+
+
+
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, AtomicI16, AtomicU64, AtomicUsize, Ordering };
+
+
+
+/// state shared by the mixer and the reader
+struct Ring {
+
+	/// interleaved samples, its length is a power of two
+	samples: Box<[AtomicI16]>,
+	mask: usize,
+	/// position of the next read, only written by the reader
+	head: AtomicUsize,
+	/// position of the next write, only written by the mixer
+	tail: AtomicUsize,
+	/// frames that did not fit in the ring
+	dropped: AtomicU64,
+	/// the reader was dropped
+	closed: AtomicBool
+
+}
+
+
+
+/// create a tap that holds at least `capacity` samples
+pub (crate) fn tap (capacity: usize, channels: u16, sample_rate: u32) -> (TapWriter, Tap) {
+	let capacity = capacity.max(2).next_power_of_two();
+	let ring = Arc::new(Ring {
+		samples: (0..capacity).map(|_| AtomicI16::new(0)).collect(),
+		mask: capacity - 1,
+		head: AtomicUsize::new(0),
+		tail: AtomicUsize::new(0),
+		dropped: AtomicU64::new(0),
+		closed: AtomicBool::new(false)
+	});
+	(TapWriter { ring: ring.clone(), channels }, Tap { ring, channels, sample_rate })
+}
+
+
+
+/// the end of a tap owned by the mixer, or by the backend of an
+/// [`AudioInput`](crate::AudioInput)
+pub struct TapWriter {
+	ring: Arc<Ring>,
+	channels: u16
+}
+
+impl TapWriter {
+
+
+	pub fn is_closed (&self) -> bool {
+		self.ring.closed.load(Ordering::Acquire)
+	}
+
+
+	/// copy `samples` to the ring, or drop them all if they don't fit
+	///
+	/// never writes part of a buffer, so the reader stays aligned to
+	/// the frames
+	pub fn write (&self, samples: &[i16]) {
+		let ring = &*self.ring;
+		let tail = ring.tail.load(Ordering::Relaxed);
+		let free = ring.samples.len() - tail.wrapping_sub(ring.head.load(Ordering::Acquire));
+		if free < samples.len() {
+			let frames = samples.len() / self.channels as usize;
+			ring.dropped.fetch_add(frames as u64, Ordering::Relaxed);
+			return;
+		}
+		for (i, x) in samples.iter().enumerate() {
+			ring.samples[tail.wrapping_add(i) & ring.mask].store(*x, Ordering::Relaxed);
+		}
+		ring.tail.store(tail.wrapping_add(samples.len()), Ordering::Release);
+	}
+
+
+}
+
+
+
+/// copies of the final mix, see [`AudioEngine::tap`]
+///
+/// for oscilloscopes and other visualizations. the samples are
+/// interleaved, and taken after the master volume. read them
+/// regularly, the mixer drops whole buffers once the tap is full.
+/// the mixer stops writing once this is dropped
+///
+/// [`AudioEngine::tap`]: crate::AudioEngine::tap
+pub struct Tap {
+	ring: Arc<Ring>,
+	channels: u16,
+	sample_rate: u32
+}
+
+impl Tap {
+
+
+	pub fn channels (&self) -> u16 {
+		self.channels
+	}
+
+
+	pub fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	/// move the oldest samples to `buffer`, returns how many
+	pub fn read (&mut self, buffer: &mut [i16]) -> usize {
+		let ring = &*self.ring;
+		let head = ring.head.load(Ordering::Relaxed);
+		let available = ring.tail.load(Ordering::Acquire).wrapping_sub(head);
+		let len = available.min(buffer.len());
+		for (i, b) in buffer[..len].iter_mut().enumerate() {
+			*b = ring.samples[head.wrapping_add(i) & ring.mask].load(Ordering::Relaxed);
+		}
+		ring.head.store(head.wrapping_add(len), Ordering::Release);
+		len
+	}
+
+
+	/// how many samples can be read
+	pub (crate) fn available (&self) -> usize {
+		self.ring.tail.load(Ordering::Acquire).wrapping_sub(self.ring.head.load(Ordering::Relaxed))
+	}
+
+
+	/// how many frames were lost because the reader was too slow
+	pub fn dropped (&self) -> u64 {
+		self.ring.dropped.load(Ordering::Relaxed)
+	}
+
+
+}
+
+impl Drop for Tap {
+	fn drop (&mut self) {
+		self.ring.closed.store(true, Ordering::Release);
+	}
+}
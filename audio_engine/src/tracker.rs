@@ -0,0 +1,824 @@
+
+
+
+//! A player of ProTracker modules, the `.mod` files of the Amiga and of the demoscene.
+//!
+//! The 31 sample formats with 1 to 32 channels are read, with the effects of ProTracker
+//! except the tremolo, inverted loops and filter. The channels are panned hard left and
+//! right in the Amiga order, toned down so headphones aren't tiring. XM, S3M and IT modules
+//! are not supported.
+
+
+
+use std::io::{ self, Read };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU32, Ordering };
+
+use crate::mixer::SoundSource;
+
+
+
+/// the sample rate of the player, unless set otherwise
+const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// the clock of the Amiga sound chip, on PAL machines, divided by the
+/// period of a note gives its frequency
+const PAULA_CLOCK: f64 = 3_546_894.6;
+
+const ROWS: usize = 64;
+
+/// how far the channels are panned, `1.0` is hard left or right
+const SEPARATION: f32 = 0.6;
+
+/// no jump was asked by the controls
+const NO_JUMP: u32 = u32::MAX;
+
+
+
+fn invalid (message: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+
+struct Sample {
+	data: Vec<i8>,
+	/// in 1/8 of a semitone
+	finetune: i8,
+	volume: u8,
+	/// start and end of the loop, in bytes
+	looped: Option<(usize, usize)>
+}
+
+
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Note {
+	sample: u8,
+	period: u16,
+	effect: u8,
+	param: u8
+}
+
+
+
+/// what a channel plays
+#[derive(Default)]
+struct Channel {
+
+	/// the index of the sample, from 1, `0` for none
+	sample: usize,
+	/// in bytes of the sample
+	position: f64,
+	period: f64,
+	/// the period the tone portamento slides to
+	target: f64,
+	volume: i32,
+	pan: f32,
+	/// the effect of the current row, and its parameter
+	effect: u8,
+	param: u8,
+	/// the last parameters of the effects that reuse them
+	porta_speed: u8,
+	vibrato: (u8, u8),
+	vibrato_position: u8,
+	/// the period added by the vibrato and the arpeggio this tick
+	offset: f64,
+	/// the row where the pattern loop starts, and how many times to go
+	/// back to it
+	loop_row: usize,
+	loop_count: u8,
+	/// the note waiting for a note delay
+	delayed: Option<Note>
+
+}
+
+
+
+/// plays a ProTracker module, as a stereo [`SoundSource`]
+///
+/// the song ends at its last position, or when it jumps back to a row
+/// it already played, which is how most modules loop. with
+/// [`looped`](ModDecoder::looped) it carries on instead, like a player
+/// set to repeat
+pub struct ModDecoder {
+
+	samples: Vec<Sample>,
+	patterns: Vec<Vec<Note>>,
+	/// the patterns of the song, in order
+	order: Vec<u8>,
+	restart: usize,
+	channels: Vec<Channel>,
+	sample_rate: u32,
+	looped: bool,
+	/// the order and row played
+	position: usize,
+	row: usize,
+	tick: u32,
+	speed: u32,
+	tempo: u32,
+	/// rows the current one is repeated for, by a pattern delay
+	delay: u32,
+	/// the position and row of a pattern break or a position jump
+	/// at the end of the row
+	jump: Option<(usize, usize)>,
+	/// frames left in the current tick
+	remaining: usize,
+	/// bits of the rows already played, by position, to find where
+	/// the song loops
+	visited: Vec<u64>,
+	done: bool,
+	shared: Arc<Shared>
+
+}
+
+impl ModDecoder {
+
+
+	/// read a whole `.mod` file
+	pub fn new (mut data: impl Read) -> io::Result<Self> {
+		let mut bytes = vec![];
+		data.read_to_end(&mut bytes)?;
+		if bytes.len() < 1084 {
+			return Err(invalid("module too short"));
+		}
+
+		let channels = match &bytes[1080..1084] {
+			b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => 4,
+			b"FLT8" => 8,
+			[n, b'C', b'H', b'N'] if n.is_ascii_digit() => (n - b'0') as usize,
+			[a, b, b'C', b'H'] if a.is_ascii_digit() && b.is_ascii_digit() => ((a - b'0') * 10 + b - b'0') as usize,
+			_ => return Err(invalid("unsupported module format"))
+		};
+		if !(1..=32).contains(&channels) {
+			return Err(invalid("unsupported module channels"));
+		}
+
+		let length = (bytes[950] as usize).clamp(1, 128);
+		let restart = bytes[951] as usize;
+		let order = bytes[952..952 + length].to_vec();
+		let count = bytes[952..1080].iter().max().map_or(0, |&x| x as usize + 1);
+
+		let mut offset = 1084;
+		let mut patterns = Vec::with_capacity(count);
+		for _ in 0..count {
+			let data = bytes.get(offset..offset + ROWS * channels * 4).ok_or(io::ErrorKind::UnexpectedEof)?;
+			patterns.push(data.chunks_exact(4)
+				.map(|x| Note {
+					sample: (x[0] & 0xf0) | (x[2] >> 4),
+					period: ((x[0] as u16 & 0x0f) << 8) | x[1] as u16,
+					effect: x[2] & 0x0f,
+					param: x[3]
+				})
+				.collect());
+			offset += ROWS * channels * 4;
+		}
+
+		let mut samples = Vec::with_capacity(31);
+		for header in bytes[20..950].chunks_exact(30) {
+			let word = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]) as usize * 2;
+			let len = word(22);
+			// the last sample is often cut short in ripped modules
+			let data: Vec<i8> = bytes.get(offset..).unwrap_or(&[]).iter().take(len).map(|&x| x as i8).collect();
+			offset += len;
+			let (start, repeat) = (word(26), word(28));
+			let looped = (repeat > 2 && start < data.len()).then(|| (start, (start + repeat).min(data.len())));
+			samples.push(Sample {
+				data,
+				finetune: (((header[24] & 0x0f) << 4) as i8) >> 4,
+				volume: header[25].min(64),
+				looped
+			});
+		}
+
+		let mut decoder = Self {
+			samples,
+			patterns,
+			order,
+			restart,
+			channels: (0..channels).map(|_| Channel::default()).collect(),
+			sample_rate: DEFAULT_SAMPLE_RATE,
+			looped: false,
+			position: 0,
+			row: 0,
+			tick: 0,
+			speed: 6,
+			tempo: 125,
+			delay: 0,
+			jump: None,
+			remaining: 0,
+			visited: vec![0; length],
+			done: false,
+			shared: Arc::new(Shared { jump: AtomicU32::new(NO_JUMP), position: AtomicU32::new(0) })
+		};
+		decoder.reset();
+		Ok(decoder)
+	}
+
+
+	/// 48kHz by default
+	pub fn sample_rate (mut self, sample_rate: u32) -> Self {
+		self.sample_rate = sample_rate.max(1);
+		// the first tick, counted at the last rate by `reset`
+		self.remaining = self.tick_frames();
+		self
+	}
+
+
+	/// carry on from the restart position of the song when it ends,
+	/// instead of ending the sound
+	pub fn looped (mut self, looped: bool) -> Self {
+		self.looped = looped;
+		self
+	}
+
+
+	/// jump through the song after the decoder was given to a sound
+	pub fn controls (&self) -> ModControls {
+		ModControls { shared: self.shared.clone() }
+	}
+
+
+	/// the frames of a tick at the current tempo
+	fn tick_frames (&self) -> usize {
+		(self.sample_rate as f64 * 2.5 / self.tempo as f64).round() as usize
+	}
+
+
+	/// start playing the song at `position`, on `row`
+	fn go_to (&mut self, position: usize, row: usize) {
+		if position >= self.order.len() {
+			if !self.looped {
+				self.done = true;
+				return;
+			}
+			self.visited.fill(0);
+			return self.go_to(if self.restart < self.order.len() { self.restart } else { 0 }, 0);
+		}
+		// a row played twice is the song looping, unless a pattern loop
+		// repeats it
+		let looping = self.channels.iter().any(|x| x.loop_count > 0);
+		if !looping && self.visited[position] & (1 << row) != 0 {
+			if !self.looped {
+				self.done = true;
+				return;
+			}
+			self.visited.fill(0);
+		}
+		self.visited[position] |= 1 << row;
+		self.position = position;
+		self.row = row;
+		self.shared.position.store(position as u32, Ordering::Relaxed);
+	}
+
+
+	fn note (&self, channel: usize) -> Note {
+		let pattern = self.order[self.position] as usize;
+		self.patterns.get(pattern).map_or(Note::default(), |x| x[self.row * self.channels.len() + channel])
+	}
+
+
+	/// start the notes and play the effects of the first tick of a row
+	fn start_row (&mut self) {
+		for i in 0..self.channels.len() {
+			let note = self.note(i);
+			let channel = &mut self.channels[i];
+			channel.effect = note.effect;
+			channel.param = note.param;
+			channel.delayed = None;
+			if note.effect == 0x0e && note.param >> 4 == 0x0d && note.param & 0x0f > 0 {
+				channel.delayed = Some(note);
+				continue;
+			}
+			self.trigger(i, note);
+		}
+	}
+
+
+	/// play `note` on `channel`, with the effects of its first tick
+	fn trigger (&mut self, i: usize, note: Note) {
+		let (x, y) = (note.param >> 4, note.param & 0x0f);
+		let samples = &self.samples;
+		let channel = &mut self.channels[i];
+
+		if note.sample > 0 && (note.sample as usize) <= samples.len() {
+			channel.sample = note.sample as usize;
+			channel.volume = samples[channel.sample - 1].volume as i32;
+		}
+		if note.period > 0 {
+			let finetune = channel.sample.checked_sub(1).map_or(0, |x| samples[x].finetune);
+			let period = note.period as f64 * 2f64.powf(-(finetune as f64) / 96.0);
+			if matches!(note.effect, 0x03 | 0x05) {
+				channel.target = period;
+			} else {
+				channel.period = period;
+				channel.position = 0.0;
+				channel.vibrato_position = 0;
+			}
+		}
+
+		match note.effect {
+			0x03 if note.param > 0 => channel.porta_speed = note.param,
+			0x04 => {
+				if x > 0 { channel.vibrato.0 = x; }
+				if y > 0 { channel.vibrato.1 = y; }
+			},
+			0x08 => channel.pan = (note.param as f32 / 127.5 - 1.0).clamp(-1.0, 1.0),
+			0x09 if note.period > 0 => channel.position = (note.param as usize * 256) as f64,
+			0x0b => self.jump = Some((note.param as usize, 0)),
+			0x0c => channel.volume = note.param.min(64) as i32,
+			0x0d => {
+				let row = (x * 10 + y) as usize;
+				let position = self.jump.map_or(self.position + 1, |(x, _)| x);
+				self.jump = Some((position, if row < ROWS { row } else { 0 }));
+			},
+			0x0e => match x {
+				0x1 => channel.period -= y as f64,
+				0x2 => channel.period += y as f64,
+				0x6 if y == 0 => channel.loop_row = self.row,
+				0x6 => {
+					if channel.loop_count == 0 {
+						channel.loop_count = y;
+					} else {
+						channel.loop_count -= 1;
+					}
+					if channel.loop_count > 0 {
+						self.jump = Some((self.position, channel.loop_row));
+					}
+				},
+				0xa => channel.volume = (channel.volume + y as i32).min(64),
+				0xb => channel.volume = (channel.volume - y as i32).max(0),
+				0xc if y == 0 => channel.volume = 0,
+				0xe => self.delay = y as u32,
+				_ => ()
+			},
+			0x0f if note.param == 0 => (),
+			0x0f if note.param < 32 => self.speed = note.param as u32,
+			0x0f => self.tempo = note.param as u32,
+			_ => ()
+		}
+		channel.period = channel.period.clamp(28.0, 3424.0);
+		channel.offset = 0.0;
+	}
+
+
+	/// play the effects of the ticks after the first
+	fn update_effects (&mut self) {
+		let tick = self.tick;
+		for i in 0..self.channels.len() {
+			let channel = &mut self.channels[i];
+			let (x, y) = (channel.param >> 4, channel.param & 0x0f);
+			channel.offset = 0.0;
+			let volume_slide = |channel: &mut Channel| {
+				channel.volume = (channel.volume + x as i32 - y as i32).clamp(0, 64);
+			};
+			match channel.effect {
+				0x00 if channel.param > 0 => {
+					let semitones = [0, x, y][tick as usize % 3];
+					channel.offset = channel.period * (2f64.powf(-(semitones as f64) / 12.0) - 1.0);
+				},
+				0x01 => channel.period = (channel.period - channel.param as f64).max(28.0),
+				0x02 => channel.period = (channel.period + channel.param as f64).min(3424.0),
+				0x03 => porta(channel),
+				0x04 => vibrato(channel),
+				0x05 => {
+					porta(channel);
+					volume_slide(channel);
+				},
+				0x06 => {
+					vibrato(channel);
+					volume_slide(channel);
+				},
+				0x0a => volume_slide(channel),
+				0x0e => match x {
+					0x9 if y > 0 && tick.is_multiple_of(y as u32) => channel.position = 0.0,
+					0xc if tick == y as u32 => channel.volume = 0,
+					0xd if tick == y as u32 => {
+						if let Some(note) = channel.delayed.take() {
+							self.trigger(i, note);
+						}
+					},
+					_ => ()
+				},
+				_ => ()
+			}
+		}
+	}
+
+
+	/// move to the next tick, and to the next row after the last tick
+	fn next_tick (&mut self) {
+		if let Some(position) = match self.shared.jump.swap(NO_JUMP, Ordering::Relaxed) {
+			NO_JUMP => None,
+			x => Some(x as usize)
+		} {
+			self.visited.fill(0);
+			self.tick = 0;
+			self.delay = 0;
+			self.jump = None;
+			self.go_to(position.min(self.order.len()), 0);
+			if !self.done {
+				self.start_row();
+			}
+			return;
+		}
+
+		self.tick += 1;
+		if self.tick < self.speed * (self.delay + 1) {
+			self.update_effects();
+			return;
+		}
+
+		self.tick = 0;
+		self.delay = 0;
+		let (position, row) = self.jump.take().unwrap_or(if self.row + 1 < ROWS {
+			(self.position, self.row + 1)
+		} else {
+			(self.position + 1, 0)
+		});
+		self.go_to(position, row);
+		if !self.done {
+			self.start_row();
+		}
+	}
+
+
+	/// add the channels to `frame`, left and right
+	fn mix (&mut self, frame: &mut [f32; 2]) {
+		let count = self.channels.len();
+		for channel in self.channels.iter_mut() {
+			let Some(sample) = channel.sample.checked_sub(1).map(|x| &self.samples[x]) else {
+				continue;
+			};
+			if channel.period <= 0.0 {
+				continue;
+			}
+			let end = sample.looped.map_or(sample.data.len(), |(_, end)| end);
+			if let Some((start, end)) = sample.looped {
+				while channel.position >= end as f64 {
+					channel.position -= (end - start) as f64;
+				}
+			}
+			let i = channel.position as usize;
+			if i >= end {
+				continue;
+			}
+			let next = match sample.looped {
+				Some((start, end)) if i + 1 >= end => start,
+				_ => i + 1
+			};
+			let a = sample.data[i] as f32;
+			let b = sample.data.get(next).map_or(0.0, |&x| x as f32);
+			let t = (channel.position - i as f64) as f32;
+			let x = (a + (b - a) * t) / 128.0 * channel.volume as f32 / 64.0 / (count as f32 / 2.0).max(1.0);
+			frame[0] += x * (1.0 - channel.pan) * 0.5;
+			frame[1] += x * (1.0 + channel.pan) * 0.5;
+
+			let period = (channel.period + channel.offset).max(1.0);
+			channel.position += PAULA_CLOCK / period / self.sample_rate as f64;
+		}
+	}
+
+
+}
+
+impl SoundSource for ModDecoder {
+
+
+	fn channels (&self) -> u16 {
+		2
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	/// play the song again from the start
+	fn reset (&mut self) {
+		for (i, channel) in self.channels.iter_mut().enumerate() {
+			// left, right, right, left
+			let side = if matches!(i % 4, 0 | 3) { -1.0 } else { 1.0 };
+			*channel = Channel { pan: side * SEPARATION, ..Channel::default() };
+		}
+		self.visited.fill(0);
+		self.speed = 6;
+		self.tempo = 125;
+		self.tick = 0;
+		self.delay = 0;
+		self.jump = None;
+		self.done = false;
+		self.go_to(0, 0);
+		self.start_row();
+		self.remaining = self.tick_frames();
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let mut written = 0;
+		for frame in buffer.chunks_exact_mut(2) {
+			if self.remaining == 0 {
+				self.next_tick();
+				self.remaining = self.tick_frames();
+			}
+			if self.done {
+				break;
+			}
+			let mut mix = [0.0; 2];
+			self.mix(&mut mix);
+			frame[0] = (mix[0] * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+			frame[1] = (mix[1] * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+			self.remaining -= 1;
+			written += 2;
+		}
+		written
+	}
+
+
+}
+
+
+
+/// the position shared with the controls
+struct Shared {
+	/// the position to jump to, or `NO_JUMP`
+	jump: AtomicU32,
+	position: AtomicU32
+}
+
+
+
+/// jumps through the song of a [`ModDecoder`] from any thread
+///
+/// the positions are indexes in the order of the patterns, which is
+/// how songs are split into parts
+#[derive(Clone)]
+pub struct ModControls {
+	shared: Arc<Shared>
+}
+
+impl ModControls {
+
+
+	/// play the song from `position`, on the next tick. a position
+	/// after the last ends the song
+	pub fn jump_to (&self, position: usize) {
+		self.shared.jump.store(position.min(NO_JUMP as usize - 1) as u32, Ordering::Relaxed);
+	}
+
+
+	/// the position playing
+	pub fn position (&self) -> usize {
+		self.shared.position.load(Ordering::Relaxed) as usize
+	}
+
+
+}
+
+
+
+/// slide the period to the target of the tone portamento
+fn porta (channel: &mut Channel) {
+	let speed = channel.porta_speed as f64;
+	if channel.target <= 0.0 {
+		return;
+	}
+	channel.period = if channel.period < channel.target {
+		(channel.period + speed).min(channel.target)
+	} else {
+		(channel.period - speed).max(channel.target)
+	};
+}
+
+
+
+fn vibrato (channel: &mut Channel) {
+	let (speed, depth) = channel.vibrato;
+	let phase = channel.vibrato_position as f64 / 64.0 * std::f64::consts::TAU;
+	channel.offset = phase.sin() * 255.0 * depth as f64 / 128.0;
+	channel.vibrato_position = (channel.vibrato_position + speed) % 64;
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use crate::mixer::SoundSource;
+	use super::{ ModDecoder, ROWS };
+
+
+	/// a note at a row and a channel, with its effect and parameter,
+	/// and a period of 0 unless it's given
+	#[derive(Clone, Copy)]
+	struct At {
+		row: usize,
+		channel: usize,
+		period: u16,
+		effect: u8,
+		param: u8
+	}
+
+
+	/// an effect without a note
+	fn at (row: usize, channel: usize, effect: u8, param: u8) -> At {
+		At { row, channel, period: 0, effect, param }
+	}
+
+
+	/// a 4 channel module without samples, playing `order`, with as
+	/// many patterns as the order uses
+	fn module (order: &[u8], notes: &[(usize, At)]) -> Vec<u8> {
+		let mut bytes = vec![0; 1084];
+		bytes[950] = order.len() as u8;
+		bytes[952..952 + order.len()].copy_from_slice(order);
+		bytes[1080..1084].copy_from_slice(b"M.K.");
+		let patterns = *order.iter().max().unwrap() as usize + 1;
+		let start = bytes.len();
+		bytes.resize(start + patterns * ROWS * 4 * 4, 0);
+		for &(pattern, note) in notes {
+			let i = start + ((pattern * ROWS + note.row) * 4 + note.channel) * 4;
+			bytes[i] = (note.period >> 8) as u8;
+			bytes[i + 1] = note.period as u8;
+			bytes[i + 2] = note.effect;
+			bytes[i + 3] = note.param;
+		}
+		bytes
+	}
+
+
+	/// the position and row of every row played, until the song ends
+	fn rows (decoder: &mut ModDecoder) -> Vec<(usize, usize)> {
+		let mut rows = vec![];
+		while !decoder.done && rows.len() < 1000 {
+			rows.push((decoder.position, decoder.row));
+			loop {
+				decoder.next_tick();
+				if decoder.done || decoder.tick == 0 {
+					break;
+				}
+			}
+		}
+		rows
+	}
+
+
+	/// the rows from `start` to `end` of `position`
+	fn range (position: usize, start: usize, end: usize) -> impl Iterator<Item = (usize, usize)> {
+		(start..end).map(move |row| (position, row))
+	}
+
+
+	/// the decoder of a module that must be valid
+	fn open (bytes: &[u8]) -> ModDecoder {
+		ModDecoder::new(bytes).unwrap()
+	}
+
+
+	#[test]
+	fn formats () {
+		let with_tag = |tag: &[u8; 4]| {
+			let mut bytes = vec![0; 1084 + 32 * ROWS * 4];
+			bytes[950] = 1;
+			bytes[1080..1084].copy_from_slice(tag);
+			ModDecoder::new(&bytes[..]).map(|x| x.channels.len())
+		};
+		assert_eq!(with_tag(b"M.K.").unwrap(), 4);
+		assert_eq!(with_tag(b"FLT8").unwrap(), 8);
+		assert_eq!(with_tag(b"6CHN").unwrap(), 6);
+		assert_eq!(with_tag(b"12CH").unwrap(), 12);
+		assert!(with_tag(b"0CHN").is_err());
+		assert!(with_tag(b"33CH").is_err());
+		assert!(with_tag(b"XM  ").is_err());
+		assert!(ModDecoder::new(&[0; 1083][..]).is_err());
+		// a pattern cut short
+		assert!(ModDecoder::new(&module(&[0], &[])[..2000]).is_err());
+	}
+
+
+	#[test]
+	fn plays_in_order () {
+		// a pattern can be played at two positions
+		let mut decoder = open(&module(&[1, 0, 1], &[]));
+		let expected: Vec<_> = range(0, 0, ROWS).chain(range(1, 0, ROWS)).chain(range(2, 0, ROWS)).collect();
+		assert_eq!(rows(&mut decoder), expected);
+		assert!(rows(&mut decoder).is_empty());
+	}
+
+
+	#[test]
+	fn end_of_the_song () {
+		let mut decoder = open(&module(&[0], &[])).sample_rate(1000);
+		// 64 rows of 6 ticks of 20 frames, at 125 bpm
+		let mut frames = 0;
+		let mut buffer = [0; 64];
+		loop {
+			let len = decoder.write_samples(&mut buffer);
+			frames += len / 2;
+			if len < buffer.len() {
+				break;
+			}
+		}
+		assert_eq!(frames, ROWS * 6 * 20);
+		decoder.reset();
+		assert_eq!(rows(&mut decoder), range(0, 0, ROWS).collect::<Vec<_>>());
+	}
+
+
+	#[test]
+	fn pattern_break () {
+		let mut decoder = open(&module(&[0, 1], &[(0, at(2, 3, 0x0d, 0x12))]));
+		let expected: Vec<_> = range(0, 0, 3).chain(range(1, 12, ROWS)).collect();
+		assert_eq!(rows(&mut decoder), expected);
+	}
+
+
+	#[test]
+	fn position_jump () {
+		let mut decoder = open(&module(&[0, 1, 2], &[(0, at(1, 0, 0x0b, 2))]));
+		let expected: Vec<_> = range(0, 0, 2).chain(range(2, 0, ROWS)).collect();
+		assert_eq!(rows(&mut decoder), expected);
+		// with a break on the same row, to its row
+		let mut decoder = open(&module(&[0, 1, 2], &[(0, at(1, 0, 0x0b, 2)), (0, at(1, 1, 0x0d, 0x05))]));
+		let expected: Vec<_> = range(0, 0, 2).chain(range(2, 5, ROWS)).collect();
+		assert_eq!(rows(&mut decoder), expected);
+		// past the end
+		let mut decoder = open(&module(&[0], &[(0, at(0, 0, 0x0b, 9))]));
+		assert_eq!(rows(&mut decoder), [(0, 0)]);
+	}
+
+
+	#[test]
+	fn loop_detection () {
+		let song = module(&[0, 1], &[(1, at(3, 0, 0x0b, 0))]);
+		let expected: Vec<_> = range(0, 0, ROWS).chain(range(1, 0, 4)).collect();
+		assert_eq!(rows(&mut open(&song)), expected);
+		// looped, it carries on from the jump
+		let rows = rows(&mut open(&song).looped(true));
+		assert_eq!(rows.len(), 1000);
+		assert_eq!(rows[..expected.len()], expected[..]);
+		assert_eq!(rows[expected.len()..2 * expected.len()], expected[..]);
+	}
+
+
+	#[test]
+	fn pattern_loop () {
+		let mut decoder = open(&module(&[0], &[(0, at(2, 1, 0x0e, 0x60)), (0, at(4, 1, 0x0e, 0x62))]));
+		let expected: Vec<_> = range(0, 0, 5).chain(range(0, 2, 5)).chain(range(0, 2, ROWS)).collect();
+		assert_eq!(rows(&mut decoder), expected);
+	}
+
+
+	#[test]
+	fn speed_and_pattern_delay () {
+		let mut decoder = open(&module(&[0], &[(0, at(0, 0, 0x0f, 3)), (0, at(1, 0, 0x0e, 0xe2))]));
+		let mut ticks = vec![];
+		for _ in 0..3 {
+			let row = decoder.row;
+			let mut count = 0;
+			while decoder.row == row {
+				decoder.next_tick();
+				count += 1;
+			}
+			ticks.push(count);
+		}
+		// the delayed row is played three times as long
+		assert_eq!(ticks, [3, 9, 3]);
+	}
+
+
+	#[test]
+	fn note_delay () {
+		let note = At { row: 0, channel: 2, period: 428, effect: 0x0e, param: 0xd3 };
+		let mut decoder = open(&module(&[0], &[(0, note)]));
+		for tick in 0..3 {
+			assert_eq!(decoder.tick, tick);
+			assert_eq!(decoder.channels[2].period, 0.0);
+			decoder.next_tick();
+		}
+		assert_eq!(decoder.channels[2].period, 428.0);
+		// without a delay, on the first tick
+		let note = At { effect: 0, param: 0, ..note };
+		assert_eq!(ModDecoder::new(&module(&[0], &[(0, note)])[..]).unwrap().channels[2].period, 428.0);
+	}
+
+
+	#[test]
+	fn controls () {
+		let mut decoder = open(&module(&[0, 1, 2], &[]));
+		let controls = decoder.controls();
+		decoder.next_tick();
+		controls.jump_to(2);
+		decoder.next_tick();
+		assert_eq!((decoder.position, decoder.row, controls.position()), (2, 0, 2));
+		controls.jump_to(0);
+		decoder.next_tick();
+		assert_eq!(rows(&mut decoder)[..ROWS], range(0, 0, ROWS).collect::<Vec<_>>()[..]);
+		controls.jump_to(7);
+		decoder.next_tick();
+		assert!(decoder.done);
+	}
+
+
+}
@@ -0,0 +1,166 @@
+
+
+
+//! A musical clock, to start sounds on the beat.
+//!
+//! The transport counts beats from the frame it was started on, at its tempo. A change of
+//! tempo keeps the beat the transport is on, and counts from there. Quantized sounds are
+//! scheduled on the frame of the next beat or bar, like [`Sound::play_at`](crate::Sound::play_at).
+
+
+
+use std::sync::{ Arc, Mutex };
+
+use crate::mixer::{ self, Command, Mixer };
+use crate::queue::Queue;
+
+
+
+/// where a quantized sound starts, see
+/// [`Sound::play_quantized`](crate::Sound::play_quantized)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantize {
+	NextBeat,
+	NextBar,
+	/// the next multiple of this many beats, like `0.5` for eighth
+	/// notes in 4/4
+	NextBeats(f32)
+}
+
+
+
+/// the tempo and time signature the music is played in, see
+/// [`AudioEngine::transport`](crate::AudioEngine::transport)
+///
+/// changes apply from the next mixed buffer
+pub struct Transport {
+
+	pub (crate) mixer: Arc<Mutex<Mixer>>,
+	pub (crate) commands: Arc<Queue<Command>>
+
+}
+
+impl Transport {
+
+
+	/// in beats per minute, `120` by default
+	pub fn set_tempo (&mut self, bpm: f32) {
+		mixer::send(&self.mixer, &self.commands, Command::SetTempo(bpm));
+	}
+
+
+	/// `4/4` by default. the tempo counts the beats of the bar, so
+	/// `6/8` at 120 bpm plays 120 eighth notes a minute
+	pub fn set_time_signature (&mut self, beats_per_bar: u32, beat_unit: u32) {
+		mixer::send(&self.mixer, &self.commands, Command::SetTimeSignature(beats_per_bar, beat_unit));
+	}
+
+
+	/// start the first bar on the next mixed buffer
+	pub fn restart (&mut self) {
+		mixer::send(&self.mixer, &self.commands, Command::RestartTransport);
+	}
+
+
+	pub fn tempo (&self) -> f32 {
+		self.mixer.lock().unwrap().transport().bpm
+	}
+
+
+	pub fn time_signature (&self) -> (u32, u32) {
+		let mixer = self.mixer.lock().unwrap();
+		(mixer.transport().beats_per_bar, mixer.transport().beat_unit)
+	}
+
+
+	/// the beats since the transport started, at the start of the
+	/// next mixed buffer
+	pub fn beats (&self) -> f64 {
+		let mixer = self.mixer.lock().unwrap();
+		mixer.transport().beats_at(mixer.clock(), mixer.sample_rate.0)
+	}
+
+
+	/// the bar and the beat in it, both from `0`
+	pub fn bar_and_beat (&self) -> (u64, f64) {
+		let mixer = self.mixer.lock().unwrap();
+		let beats = mixer.transport().beats_at(mixer.clock(), mixer.sample_rate.0);
+		let bar = mixer.transport().beats_per_bar.max(1) as f64;
+		((beats / bar).floor() as u64, beats.rem_euclid(bar))
+	}
+
+
+}
+
+
+
+/// the transport, as the mixer sees it
+pub struct TransportState {
+
+	pub bpm: f32,
+	pub beats_per_bar: u32,
+	pub beat_unit: u32,
+	/// the frame the beats are counted from, and the beat it was
+	origin_frame: u64,
+	origin_beats: f64
+
+}
+
+impl TransportState {
+
+
+	pub fn new () -> Self {
+		Self {
+			bpm: 120.0,
+			beats_per_bar: 4,
+			beat_unit: 4,
+			origin_frame: 0,
+			origin_beats: 0.0
+		}
+	}
+
+
+	/// the beats since the start, at `frame`
+	pub fn beats_at (&self, frame: u64, sample_rate: u32) -> f64 {
+		let seconds = frame.saturating_sub(self.origin_frame) as f64 / sample_rate.max(1) as f64;
+		self.origin_beats + seconds * self.bpm.max(1e-3) as f64 / 60.0
+	}
+
+
+	/// the frame `beats` are at, after the origin
+	fn frame_of (&self, beats: f64, sample_rate: u32) -> u64 {
+		let seconds = (beats - self.origin_beats).max(0.0) * 60.0 / self.bpm.max(1e-3) as f64;
+		self.origin_frame + (seconds * sample_rate as f64).round() as u64
+	}
+
+
+	/// change the tempo from `frame`, on the beat it is at
+	pub fn set_tempo (&mut self, bpm: f32, frame: u64, sample_rate: u32) {
+		self.origin_beats = self.beats_at(frame, sample_rate);
+		self.origin_frame = frame;
+		self.bpm = bpm;
+	}
+
+
+	/// make `frame` the first beat
+	pub fn restart (&mut self, frame: u64) {
+		self.origin_beats = 0.0;
+		self.origin_frame = frame;
+	}
+
+
+	/// the first frame on `quantize` at or after `frame`
+	pub fn next (&self, quantize: Quantize, frame: u64, sample_rate: u32) -> u64 {
+		let grid = match quantize {
+			Quantize::NextBeat => 1.0,
+			Quantize::NextBar => self.beats_per_bar.max(1) as f64,
+			Quantize::NextBeats(beats) => beats.max(1e-3) as f64
+		};
+		let beats = self.beats_at(frame, sample_rate);
+		// a frame rounded just before the beat is still on it
+		let next = ((beats - 1e-9) / grid).ceil() * grid;
+		self.frame_of(next, sample_rate).max(frame)
+	}
+
+
+}
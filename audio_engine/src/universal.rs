@@ -0,0 +1,257 @@
+
+
+
+//! A decoder of every format symphonia reads, like AAC, ALAC, MP3 or the audio of MKV and WebM.
+//!
+//! The format is probed from the first bytes of the file, helped by its extension when it is
+//! known. The first audio track is played, and packets are decoded as they are mixed.
+
+
+
+use log::error;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{ CODEC_TYPE_NULL, Decoder, DecoderOptions };
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{ FormatOptions, FormatReader, SeekMode, SeekTo };
+use symphonia::core::io::{ MediaSource, MediaSourceStream };
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{ Time, TimeBase };
+
+use std::io::{ self, Read, Seek, SeekFrom };
+use std::sync::{ Mutex, PoisonError };
+
+use crate::mixer::SoundSource;
+
+
+
+/// `T` as a [`MediaSource`], which has to be `Sync`
+struct Source<T: Seek + Read + Send>(Mutex<T>);
+
+impl<T: Seek + Read + Send> Read for Source<T> {
+	fn read (&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+		self.0.get_mut().unwrap_or_else(PoisonError::into_inner).read(buffer)
+	}
+}
+
+impl<T: Seek + Read + Send> Seek for Source<T> {
+	fn seek (&mut self, position: SeekFrom) -> io::Result<u64> {
+		self.0.get_mut().unwrap_or_else(PoisonError::into_inner).seek(position)
+	}
+}
+
+impl<T: Seek + Read + Send> MediaSource for Source<T> {
+	fn is_seekable (&self) -> bool {
+		true
+	}
+	fn byte_len (&self) -> Option<u64> {
+		None
+	}
+}
+
+
+
+/// Symphonia File Decoder
+///
+/// ```ignore
+/// let file = BufReader::new(File::open("music.m4a")?);
+/// let music = SymphoniaDecoder::with_extension(file, "m4a")?;
+/// ```
+pub struct SymphoniaDecoder {
+
+	format: Box<dyn FormatReader>,
+	decoder: Box<dyn Decoder>,
+	track_id: u32,
+	time_base: Option<TimeBase>,
+	channels: u16,
+	sample_rate: u32,
+	frames: Option<u64>,
+	/// the last decoded packet, interleaved
+	buffer: Option<SampleBuffer<i16>>,
+	/// how many samples of `buffer` were already written out
+	index: usize,
+	/// samples to drop after a seek, to land on the exact frame
+	skip: usize,
+	/// the stream reached its end or failed to decode
+	done: bool
+
+}
+
+impl SymphoniaDecoder {
+
+
+	/// Create a new decoder, probing the format of `data`
+	pub fn new (data: impl Seek + Read + Send + 'static) -> Result<Self, SymphoniaError> {
+		Self::probe(data, Hint::new())
+	}
+
+
+	/// Create a new decoder, trying the format of the file extension
+	/// `extension` first, like `"m4a"`
+	pub fn with_extension (data: impl Seek + Read + Send + 'static, extension: &str) -> Result<Self, SymphoniaError> {
+		let mut hint = Hint::new();
+		hint.with_extension(extension);
+		Self::probe(data, hint)
+	}
+
+
+	pub (crate) fn probe (data: impl Seek + Read + Send + 'static, hint: Hint) -> Result<Self, SymphoniaError> {
+		let stream = MediaSourceStream::new(Box::new(Source(Mutex::new(data))), Default::default());
+		let format = symphonia::default::get_probe()
+			.format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())?
+			.format;
+		let track = format
+			.tracks()
+			.iter()
+			.find(|x| x.codec_params.codec != CODEC_TYPE_NULL && x.codec_params.sample_rate.is_some())
+			.ok_or(SymphoniaError::Unsupported("no audio track"))?;
+		let decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+		let params = &track.codec_params;
+		let mut this = Self {
+			track_id: track.id,
+			time_base: params.time_base,
+			channels: params.channels.map_or(0, |x| x.count() as u16),
+			sample_rate: params.sample_rate.unwrap_or(0),
+			frames: params.n_frames,
+			format,
+			decoder,
+			buffer: None,
+			index: 0,
+			skip: 0,
+			done: false
+		};
+		// some formats only say their channels in the packets
+		if this.channels == 0 {
+			if !this.next_packet() {
+				return Err(SymphoniaError::Unsupported("no audio packet"));
+			}
+			this.index = 0;
+		}
+		Ok(this)
+	}
+
+
+	/// decode the next packet of the track into `buffer`
+	///
+	/// return false if there is nothing more to decode
+	fn next_packet (&mut self) -> bool {
+		loop {
+			let packet = match self.format.next_packet() {
+				Ok(packet) if packet.track_id() != self.track_id => continue,
+				Ok(packet) => packet,
+				Err(SymphoniaError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => return false,
+				Err(err) => {
+					error!("error while reading the stream: {}", err);
+					return false;
+				}
+			};
+			match self.decoder.decode(&packet) {
+				Ok(audio) => {
+					let spec = *audio.spec();
+					let frames = audio.capacity() as u64;
+					if self.buffer.as_ref().is_none_or(|x| x.capacity() < frames as usize * spec.channels.count()) {
+						self.buffer = Some(SampleBuffer::new(frames, spec));
+					}
+					let buffer = self.buffer.as_mut().unwrap();
+					buffer.copy_interleaved_ref(audio);
+					self.channels = spec.channels.count() as u16;
+					self.sample_rate = spec.rate;
+					self.index = self.skip.min(buffer.len());
+					self.skip -= self.index;
+					if self.index < buffer.len() {
+						return true;
+					}
+				},
+				// a broken packet is skipped
+				Err(SymphoniaError::DecodeError(err)) => error!("error while decoding the stream: {}", err),
+				Err(err) => {
+					error!("error while decoding the stream: {}", err);
+					return false;
+				}
+			}
+		}
+	}
+
+
+	/// the frame of the timestamp `ts`
+	fn frame_of (&self, ts: u64) -> u64 {
+		match self.time_base {
+			Some(time_base) => {
+				let time = time_base.calc_time(ts);
+				time.seconds * self.sample_rate as u64 + (time.frac * self.sample_rate as f64).round() as u64
+			},
+			None => ts
+		}
+	}
+
+
+}
+
+impl SoundSource for SymphoniaDecoder {
+
+
+	fn reset (&mut self) {
+		self.seek(0);
+	}
+
+
+	/// sample accurate, when the format can seek
+	fn seek (&mut self, frame: u64) -> bool {
+		let rate = self.sample_rate.max(1) as u64;
+		let ts = match self.time_base {
+			Some(time_base) => time_base.calc_timestamp(Time::new(frame / rate, (frame % rate) as f64 / rate as f64)),
+			None => frame
+		};
+		match self.format.seek(SeekMode::Accurate, SeekTo::TimeStamp { ts, track_id: self.track_id }) {
+			Ok(seeked) => {
+				self.decoder.reset();
+				self.skip = frame.saturating_sub(self.frame_of(seeked.actual_ts)) as usize * self.channels as usize;
+				// the next write decodes from where it seeked
+				self.index = self.buffer.as_ref().map_or(0, |x| x.len());
+				self.done = false;
+			},
+			Err(err) => {
+				error!("error while seeking the stream: {}", err);
+				self.done = true;
+			}
+		}
+		true
+	}
+
+
+	fn channels (&self) -> u16 {
+		self.channels
+	}
+
+
+	fn sample_rate (&self) -> u32 {
+		self.sample_rate
+	}
+
+
+	fn total_frames (&self) -> Option<u64> {
+		self.frames
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+
+		let mut len = 0;
+		while len < buffer.len() && !self.done {
+			let available = self.buffer.as_ref().map_or(0, |x| x.len());
+			if self.index == available && !self.next_packet() {
+				self.done = true;
+				break;
+			}
+			let samples = &self.buffer.as_ref().unwrap().samples()[self.index..];
+			let n = samples.len().min(buffer.len() - len);
+			buffer[len..len + n].copy_from_slice(&samples[..n]);
+			self.index += n;
+			len += n;
+		}
+		len
+
+	}
+
+
+}
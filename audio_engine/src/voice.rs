@@ -0,0 +1,316 @@
+
+
+
+//! Voice processing of the captured audio, for when the platform doesn't do it.
+//!
+//! The audio is measured in blocks of 10ms, and each block sets the gain the next samples move
+//! to. The noise floor is the quietest the input was lately, blocks well over it are speech,
+//! and the others are turned down. Echo is only suppressed: the mic is turned down while the
+//! mix is louder than it, as a speakerphone does, and for a moment after.
+//!
+//! The [`VoiceDetector`] follows the noise floor the same way, to tell when someone speaks.
+
+
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::meter::Meter;
+
+
+
+/// the length of a measured block
+const BLOCK_SECONDS: f32 = 0.01;
+
+/// how fast the gain follows its target
+const SMOOTHING_SECONDS: f32 = 0.01;
+
+/// how fast the noise floor rises, per block, it falls at once
+const FLOOR_RISE: f32 = 1.002;
+
+/// a block this many times over the noise floor is speech
+const SPEECH_RATIO: f32 = 3.0;
+
+/// blocks quieter than this are never speech, -60 dBFS
+const SILENCE_RMS: f32 = 0.001;
+
+/// blocks of speech in a row that start speaking, so a click doesn't
+const SPEECH_BLOCKS: u32 = 3;
+
+/// how long the detector stays speaking after the voice stops, so
+/// it doesn't stop between words
+const DEFAULT_HANGOVER: Duration = Duration::from_millis(300);
+
+/// the gain of the noise between words, -20 dB
+const NOISE_GAIN: f32 = 0.1;
+
+/// the rms the gain control brings the voice to, -20 dBFS
+const TARGET_RMS: f32 = 0.1;
+
+/// the range of the gain control
+const MIN_GAIN: f32 = 0.25;
+const MAX_GAIN: f32 = 10.0;
+
+/// the gain of the mic while the output is heard, -20 dB
+const ECHO_GAIN: f32 = 0.1;
+
+/// the mix is heard by the mic over this rms, -40 dBFS
+const ECHO_THRESHOLD: f32 = 0.01;
+
+/// blocks the mic stays down after the output is quiet, for the
+/// echo of the room to fade
+const ECHO_HOLD_BLOCKS: u32 = 20;
+
+
+
+/// what the engine does to the captured samples
+pub (crate) struct VoiceProcessor {
+
+	noise_suppression: bool,
+	gain_control: bool,
+	/// the level of the mix, to suppress its echo
+	echo: Option<Arc<Meter>>,
+	channels: usize,
+	block_frames: usize,
+	smoothing: f32,
+	/// the sum of the squares of the block so far, and its frames
+	sum: f32,
+	frames: usize,
+	/// the rms of the quietest recent blocks
+	floor: f32,
+	/// the gain of the gain control
+	level: f32,
+	echo_hold: u32,
+	/// the gain applied, and the one it moves to
+	gain: f32,
+	target: f32
+
+}
+
+impl VoiceProcessor {
+
+
+	/// `echo` is the meter of the mix to suppress, if any
+	pub fn new (noise_suppression: bool, gain_control: bool, echo: Option<Arc<Meter>>, channels: u16, sample_rate: u32) -> Self {
+		let sample_rate = sample_rate.max(1) as f32;
+		Self {
+			noise_suppression,
+			gain_control,
+			echo,
+			channels: channels.max(1) as usize,
+			block_frames: ((sample_rate * BLOCK_SECONDS) as usize).max(1),
+			smoothing: 1.0 - (-1.0 / (SMOOTHING_SECONDS * sample_rate)).exp(),
+			sum: 0.0,
+			frames: 0,
+			floor: 1.0,
+			level: 1.0,
+			echo_hold: 0,
+			gain: 1.0,
+			target: 1.0
+		}
+	}
+
+
+	/// process interleaved `samples`, in place
+	pub fn process (&mut self, samples: &mut [i16]) {
+		for frame in samples.chunks_exact_mut(self.channels) {
+			for x in frame.iter_mut() {
+				let y = *x as f32 / i16::MAX as f32;
+				self.sum += y * y;
+				*x = (*x as f32 * self.gain).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+			}
+			self.gain += (self.target - self.gain) * self.smoothing;
+			self.frames += 1;
+			if self.frames == self.block_frames {
+				let rms = (self.sum / (self.frames * self.channels) as f32).sqrt();
+				self.update(rms);
+				self.sum = 0.0;
+				self.frames = 0;
+			}
+		}
+	}
+
+
+	/// set the target gain from the rms of the last block
+	fn update (&mut self, rms: f32) {
+		self.floor = follow_floor(self.floor, rms);
+		let speech = rms > self.floor * SPEECH_RATIO;
+
+		let mut target = 1.0;
+		if self.noise_suppression && !speech {
+			target *= NOISE_GAIN;
+		}
+
+		if self.gain_control {
+			// only the voice moves the gain, it is turned down faster
+			// than it is turned up so it doesn't clip long
+			if speech {
+				let wanted = (TARGET_RMS / rms).clamp(MIN_GAIN, MAX_GAIN);
+				let speed = if wanted < self.level { 0.1 } else { 0.02 };
+				self.level += (wanted - self.level) * speed;
+			}
+			target *= self.level;
+		}
+
+		if let Some(meter) = &self.echo {
+			// the mix is echoed when it is loud, unless the voice is
+			// louder, when both talk
+			let output = meter.levels().rms;
+			if output > ECHO_THRESHOLD && rms < output {
+				self.echo_hold = ECHO_HOLD_BLOCKS;
+			} else {
+				self.echo_hold = self.echo_hold.saturating_sub(1);
+			}
+			if self.echo_hold > 0 {
+				target *= ECHO_GAIN;
+			}
+		}
+
+		self.target = target;
+	}
+
+
+}
+
+
+
+/// the noise floor after a block of `rms`
+fn follow_floor (floor: f32, rms: f32) -> f32 {
+	if rms < floor { rms.max(1e-5) } else { floor * FLOOR_RISE }
+}
+
+
+
+/// a change seen by a [`VoiceDetector`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceEvent {
+	/// someone started speaking
+	Speaking,
+	/// the voice stopped for longer than the hangover
+	Silent
+}
+
+
+
+/// tells when the captured audio has speech in it, for push to talk
+/// or to mute players who don't speak
+///
+/// give it the samples read from an [`AudioInput`](crate::AudioInput).
+/// it compares the level of the voice band to the noise floor, which
+/// rises slowly, so steady noise like a fan is not taken as speech
+pub struct VoiceDetector {
+
+	channels: usize,
+	block_frames: usize,
+	/// the voice band is over this, as a one pole high pass
+	high_pass: f32,
+	previous: f32,
+	filtered: f32,
+	sum: f32,
+	frames: usize,
+	floor: f32,
+	/// a block is speech over `floor` times this
+	ratio: f32,
+	/// blocks of speech in a row, and blocks since the last one
+	speech: u32,
+	quiet: u32,
+	hangover: u32,
+	speaking: bool,
+	events: Vec<VoiceEvent>
+
+}
+
+impl VoiceDetector {
+
+
+	/// for samples of `channels` at `sample_rate`, with a sensitivity
+	/// of `0.5`
+	pub fn new (channels: u16, sample_rate: u32) -> Self {
+		let sample_rate = sample_rate.max(1) as f32;
+		let block_frames = ((sample_rate * BLOCK_SECONDS) as usize).max(1);
+		Self {
+			channels: channels.max(1) as usize,
+			block_frames,
+			// under about 200Hz is hum and rumble
+			high_pass: (-2.0 * std::f32::consts::PI * 200.0 / sample_rate).exp(),
+			previous: 0.0,
+			filtered: 0.0,
+			sum: 0.0,
+			frames: 0,
+			floor: 1.0,
+			ratio: 1.0,
+			speech: 0,
+			quiet: 0,
+			hangover: 0,
+			speaking: false,
+			events: vec![]
+		}
+		.sensitivity(0.5)
+		.hangover(DEFAULT_HANGOVER)
+	}
+
+
+	/// from `0.0` to `1.0`, how far over the noise floor the voice
+	/// must be, from 23 dB to 3 dB
+	pub fn sensitivity (mut self, sensitivity: f32) -> Self {
+		let db = 3.0 + (1.0 - sensitivity.clamp(0.0, 1.0)) * 20.0;
+		self.ratio = 10f32.powf(db / 20.0);
+		self
+	}
+
+
+	/// how long it stays speaking after the voice stops, `300ms` by
+	/// default
+	pub fn hangover (mut self, hangover: Duration) -> Self {
+		self.hangover = (hangover.as_secs_f32() / BLOCK_SECONDS).round() as u32;
+		self
+	}
+
+
+	pub fn is_speaking (&self) -> bool {
+		self.speaking
+	}
+
+
+	/// measure some interleaved `samples`, returns what changed
+	/// during them, in order
+	pub fn process (&mut self, samples: &[i16]) -> impl Iterator<Item = VoiceEvent> + '_ {
+		self.events.clear();
+		for frame in samples.chunks_exact(self.channels) {
+			let x = frame.iter().map(|&x| x as f32).sum::<f32>() / (self.channels as f32 * i16::MAX as f32);
+			self.filtered = self.high_pass * (self.filtered + x - self.previous);
+			self.previous = x;
+			self.sum += self.filtered * self.filtered;
+			self.frames += 1;
+			if self.frames == self.block_frames {
+				let rms = (self.sum / self.frames as f32).sqrt();
+				self.update(rms);
+				self.sum = 0.0;
+				self.frames = 0;
+			}
+		}
+		self.events.drain(..)
+	}
+
+
+	fn update (&mut self, rms: f32) {
+		self.floor = follow_floor(self.floor, rms);
+		if rms > self.floor * self.ratio && rms > SILENCE_RMS {
+			self.speech += 1;
+			self.quiet = 0;
+		} else {
+			self.speech = 0;
+			self.quiet += 1;
+		}
+
+		if !self.speaking && self.speech >= SPEECH_BLOCKS {
+			self.speaking = true;
+			self.events.push(VoiceEvent::Speaking);
+		} else if self.speaking && self.quiet > self.hangover {
+			self.speaking = false;
+			self.events.push(VoiceEvent::Silent);
+		}
+	}
+
+
+}
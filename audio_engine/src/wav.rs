@@ -4,45 +4,88 @@
 use hound::WavReader;
 use log::error;
 
-use std::io::{ Read, Seek };
+use std::io::{ Read, Seek, SeekFrom };
 
 use crate::mixer::SoundSource;
 
 
 
+/// the longest chunk other than `data` that is read, bigger ones are
+/// skipped
+const MAX_CHUNK: usize = 1 << 16;
+
+
+
 /// Wav File Decoder
+///
+/// reads integer pcm of 8 to 32 bits, floats of 32 or 64 bits, a-law,
+/// µ-law and ima adpcm
 pub struct WavDecoder <T: Seek + Read + Send + 'static> {
 
-	reader: WavReader<T>,
+	reader: Reader<T>,
 	channels: u16,
-	sample_rate: u32
+	sample_rate: u32,
+	loop_region: Option<(u64, u64)>
+
+}
+
+
 
+/// the formats hound reads, and the others
+enum Reader <T: Seek + Read> {
+	Hound(WavReader<T>),
+	Coded(Coded<T>)
 }
 
 impl <T: Seek + Read + Send + 'static> WavDecoder<T> {
 
 
 	/// Create a new wav file decoder
-	pub fn new (data: T) -> Result<Self, hound::Error> {
+	///
+	/// the loop of the `smpl` chunk, or between the first two points
+	/// of the `cue ` chunk, is the loop region of the sound, see
+	/// [`Sound::set_loop_region`](crate::Sound::set_loop_region)
+	pub fn new (mut data: T) -> Result<Self, hound::Error> {
+		let start = data.stream_position()?;
+		let chunks = read_chunks(&mut data);
+		let loop_region = chunks.as_ref().and_then(|x| x.loop_region);
+
+		// the formats hound doesn't read
+		let format = chunks.as_ref().and_then(|x| x.format);
+		if let (Some(chunks), Some(format), Some(encoding)) = (&chunks, format, format.and_then(Format::encoding)) {
+			let (offset, len) = chunks.data.ok_or(hound::Error::FormatError("no data chunk"))?;
+			return Ok(Self {
+				reader: Reader::Coded(Coded::new(data, encoding, &format, offset, len, chunks.fact)?),
+				channels: format.channels,
+				sample_rate: format.sample_rate,
+				loop_region
+			});
+		}
+
+		data.seek(SeekFrom::Start(start))?;
 		let reader = WavReader::new(data)?;
+		if reader.spec().sample_rate == 0 {
+			return Err(hound::Error::FormatError("wav sample rate of 0"));
+		}
 		Ok(Self {
 			channels: reader.spec().channels,
 			sample_rate: reader.spec().sample_rate,
-			reader
+			loop_region,
+			reader: Reader::Hound(reader)
 		})
 	}
 
 
 	fn inner_write_sample <S: hound::Sample> (
-		&mut self,
+		reader: &mut WavReader<T>,
 		buffer: &mut [i16],
 		to_i16: impl Fn(S) -> i16
 	) -> usize {
 
-		let mut samples = self.reader.samples::<S>();
-		for i in 0..buffer.len() {
+		let mut samples = reader.samples::<S>();
+		for (i, b) in buffer.iter_mut().enumerate() {
 			if let Some(sample) = samples.next() {
-				buffer[i] = match sample {
+				*b = match sample {
 					Ok(x) => to_i16(x),
 					Err(err) => {
 						error!("error while decoding wav: {}", err);
@@ -63,13 +106,28 @@ impl <T: Seek + Read + Send + 'static> WavDecoder<T> {
 	}
 
 
+}
+
+#[cfg(all(target_os = "android", feature = "android-assets"))]
+impl WavDecoder<crate::asset::Asset> {
+
+
+	/// Create a new wav file decoder from an asset of the APK
+	///
+	/// `path` is relative to the assets folder, see
+	/// [`set_asset_manager`](crate::set_asset_manager)
+	pub fn from_asset (path: &str) -> Result<Self, hound::Error> {
+		Self::new(crate::asset::Asset::open(path)?)
+	}
+
+
 }
 
 impl <T: Seek + Read + Send + 'static> SoundSource for WavDecoder<T> {
 
 
 	fn reset (&mut self) {
-		self.reader.seek(0).unwrap();
+		self.seek(0);
 	}
 
 
@@ -83,22 +141,55 @@ impl <T: Seek + Read + Send + 'static> SoundSource for WavDecoder<T> {
 	}
 
 
+	fn total_frames (&self) -> Option<u64> {
+		match &self.reader {
+			Reader::Hound(reader) => Some(reader.duration() as u64),
+			Reader::Coded(coded) => Some(coded.frames)
+		}
+	}
+
+
+	/// sample accurate
+	fn seek (&mut self, frame: u64) -> bool {
+		let result = match &mut self.reader {
+			Reader::Hound(reader) => {
+				let frame = frame.min(reader.duration() as u64) as u32;
+				reader.seek(frame).map_err(hound::Error::from)
+			},
+			Reader::Coded(coded) => coded.seek(frame)
+		};
+		if let Err(err) = result {
+			error!("error while seeking wav: {}", err);
+		}
+		true
+	}
+
+
+	fn loop_region (&self) -> Option<(u64, u64)> {
+		self.loop_region
+	}
+
+
 	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
 
-		let sample_format = self.reader.spec().sample_format;
-		let bits_per_sample = self.reader.spec().bits_per_sample;
+		let reader = match &mut self.reader {
+			Reader::Hound(reader) => reader,
+			Reader::Coded(coded) => return coded.write_samples(buffer)
+		};
+		let sample_format = reader.spec().sample_format;
+		let bits_per_sample = reader.spec().bits_per_sample;
 
 		match (sample_format, bits_per_sample) {
-			(hound::SampleFormat::Float, _) => self.inner_write_sample(buffer, f32_to_i16),
+			(hound::SampleFormat::Float, _) => Self::inner_write_sample(reader, buffer, f32_to_i16),
 			// 24bit or 32bit
 			(hound::SampleFormat::Int, x) if x > 16 => {
-				self.inner_write_sample(buffer, |x: i32| (x >> (bits_per_sample - 16)) as i16)
+				Self::inner_write_sample(reader, buffer, |x: i32| (x >> (bits_per_sample - 16)) as i16)
 			},
 			// 16bit
-			(hound::SampleFormat::Int, x) if x == 16 => self.inner_write_sample(buffer, |x: i16| x),
+			(hound::SampleFormat::Int, 16) => Self::inner_write_sample(reader, buffer, |x: i16| x),
 			// 8bit
 			(hound::SampleFormat::Int, _) => {
-				self.inner_write_sample(buffer, |x: i8| (x as i16) << 8)
+				Self::inner_write_sample(reader, buffer, |x: i8| (x as i16) << 8)
 			}
 		}
 
@@ -109,13 +200,430 @@ impl <T: Seek + Read + Send + 'static> SoundSource for WavDecoder<T> {
 
 
 
-fn f32_to_i16 (mut x: f32) -> i16 {
-	if x > 1.0 {
-		x = 1.0
+/// what `read_chunks` found in a wav file
+struct Chunks {
+	format: Option<Format>,
+	/// the offset and the length of the samples
+	data: Option<(u64, u64)>,
+	/// the number of frames, only needed by compressed formats
+	fact: Option<u64>,
+	/// the loop of the file, as a start and an exclusive end
+	loop_region: Option<(u64, u64)>
+}
+
+
+
+/// read the chunks of a wav file
+///
+/// `None` if the file can't be read, in which case hound reports the
+/// error
+fn read_chunks <T: Read + Seek> (data: &mut T) -> Option<Chunks> {
+	let mut header = [0; 12];
+	data.read_exact(&mut header).ok()?;
+	if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+		return None;
+	}
+
+	let mut chunks = Chunks { format: None, data: None, fact: None, loop_region: None };
+	let mut cues = vec![];
+	let mut chunk = [0; 8];
+	while data.read_exact(&mut chunk).is_ok() {
+		let id = &chunk[0..4];
+		// chunks are padded to an even length
+		let len = u32_at(&chunk, 4) as usize;
+		let padded = len + len % 2;
+		if id == b"data" {
+			chunks.data = Some((data.stream_position().ok()?, len as u64));
+			data.seek(SeekFrom::Current(padded as i64)).ok()?;
+		} else if matches!(id, b"smpl" | b"cue " | b"fmt " | b"fact") && len <= MAX_CHUNK {
+			let mut body = vec![0; padded];
+			data.read_exact(&mut body).ok()?;
+			if id == b"smpl" {
+				// the loops follow a header of 36 bytes, each is 24 bytes
+				// with its start and inclusive end at 8 and 12
+				if u32_at(&body, 28) > 0 && body.len() >= 60 {
+					chunks.loop_region = Some((u32_at(&body, 44) as u64, u32_at(&body, 48) as u64 + 1));
+				}
+			} else if id == b"cue " {
+				// each point is 24 bytes after the count, its frame is at 20
+				let count = (u32_at(&body, 0) as usize).min(body.len().saturating_sub(4) / 24);
+				cues = (0..count).map(|i| u32_at(&body, 4 + i * 24 + 20) as u64).collect();
+			} else if id == b"fmt " {
+				chunks.format = Format::read(&body);
+			} else {
+				chunks.fact = Some(u32_at(&body, 0) as u64);
+			}
+		} else {
+			data.seek(SeekFrom::Current(padded as i64)).ok()?;
+		}
+	}
+
+	cues.sort_unstable();
+	if chunks.loop_region.is_none() && cues.len() >= 2 {
+		chunks.loop_region = Some((cues[0], cues[1]));
+	}
+	Some(chunks)
+}
+
+
+
+const FORMAT_PCM: u16 = 0x1;
+const FORMAT_FLOAT: u16 = 0x3;
+const FORMAT_ALAW: u16 = 0x6;
+const FORMAT_MULAW: u16 = 0x7;
+const FORMAT_IMA_ADPCM: u16 = 0x11;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+
+
+/// the `fmt ` chunk
+#[derive(Clone, Copy)]
+struct Format {
+	/// the tag of the format, or of the sub format of an extensible one
+	tag: u16,
+	channels: u16,
+	sample_rate: u32,
+	block_align: u16,
+	/// the size of the samples in the file
+	bits_per_sample: u16,
+	/// the bits of the samples that are used, or the samples per
+	/// block of ima adpcm, `0` when not given
+	extra: u16
+}
+
+impl Format {
+
+
+	fn read (body: &[u8]) -> Option<Self> {
+		if body.len() < 16 {
+			return None;
+		}
+		let u16_at = |index| u16::from_le_bytes([body[index], body[index + 1]]);
+		let extra = if body.len() >= 20 { u16_at(18) } else { 0 };
+		let mut tag = u16_at(0);
+		// the sub format is a guid that starts with the tag
+		if tag == FORMAT_EXTENSIBLE && body.len() >= 26 {
+			tag = u16_at(24);
+		}
+		Some(Self {
+			tag,
+			channels: u16_at(2),
+			sample_rate: u32_at(body, 4),
+			block_align: u16_at(12),
+			bits_per_sample: u16_at(14),
+			extra
+		})
+	}
+
+
+	/// how the samples are decoded, `None` for the formats hound
+	/// reads
+	fn encoding (self) -> Option<Encoding> {
+		if self.channels == 0 || self.sample_rate == 0 {
+			return None;
+		}
+		match (self.tag, self.bits_per_sample) {
+			(FORMAT_ALAW, 8) => Some(Encoding::ALaw),
+			(FORMAT_MULAW, 8) => Some(Encoding::MuLaw),
+			(FORMAT_FLOAT, 64) => Some(Encoding::Float64),
+			// the samples are left aligned in a bigger container, like
+			// 24 bits in 32
+			(FORMAT_PCM, bits) if self.extra != 0 && self.extra != bits && bits >= 16 && bits.is_multiple_of(8) => {
+				Some(Encoding::Int { bytes: bits as usize / 8 })
+			},
+			(FORMAT_IMA_ADPCM, 4) => {
+				let channels = self.channels as usize;
+				let block_align = self.block_align as usize;
+				if block_align <= 4 * channels || !(block_align - 4 * channels).is_multiple_of(4 * channels) {
+					return None;
+				}
+				Some(Encoding::ImaAdpcm { block_align, samples_per_block: ima_samples(block_align, channels) })
+			},
+			_ => None
+		}
+	}
+
+
+}
+
+
+
+#[derive(Clone, Copy)]
+enum Encoding {
+	ALaw,
+	MuLaw,
+	Float64,
+	/// the top 16 bits of `bytes` are used
+	Int {
+		bytes: usize
+	},
+	/// blocks of `block_align` bytes
+	ImaAdpcm {
+		block_align: usize,
+		samples_per_block: usize
+	}
+}
+
+impl Encoding {
+
+
+	/// the bytes of a sample, for the formats with a fixed size
+	fn bytes (self) -> usize {
+		match self {
+			Encoding::ALaw | Encoding::MuLaw => 1,
+			Encoding::Float64 => 8,
+			Encoding::Int { bytes } => bytes,
+			Encoding::ImaAdpcm { .. } => 1
+		}
+	}
+
+
+}
+
+
+
+/// the decoder of the formats hound doesn't read
+struct Coded <T: Seek + Read> {
+	data: T,
+	encoding: Encoding,
+	channels: usize,
+	/// where the samples start in `data`, and their length in bytes
+	offset: u64,
+	len: u64,
+	frames: u64,
+	/// the next frame to read, or the first frame of `decoded` for ima
+	/// adpcm
+	frame: u64,
+	/// bytes read from `data`, kept to avoid allocating
+	bytes: Vec<u8>,
+	/// the samples of the block being played, for ima adpcm
+	decoded: Vec<i16>,
+	/// the next of `decoded` to play
+	index: usize
+}
+
+impl <T: Seek + Read> Coded<T> {
+
+
+	fn new (data: T, encoding: Encoding, format: &Format, offset: u64, len: u64, fact: Option<u64>) -> Result<Self, hound::Error> {
+		let channels = format.channels as usize;
+		let frames = match encoding {
+			Encoding::ImaAdpcm { block_align, samples_per_block } => {
+				let blocks = len / block_align as u64;
+				// the last block can be shorter
+				let last = ima_samples((len % block_align as u64) as usize, channels);
+				let frames = blocks * samples_per_block as u64 + last as u64;
+				fact.map_or(frames, |x| x.min(frames))
+			},
+			_ => len / (encoding.bytes() * channels) as u64
+		};
+		let mut this = Self {
+			data,
+			encoding,
+			channels,
+			offset,
+			len,
+			frames,
+			frame: 0,
+			bytes: vec![],
+			decoded: vec![],
+			index: 0
+		};
+		this.seek(0)?;
+		Ok(this)
+	}
+
+
+	fn seek (&mut self, frame: u64) -> Result<(), hound::Error> {
+		let frame = frame.min(self.frames);
+		match self.encoding {
+			Encoding::ImaAdpcm { block_align, samples_per_block } => {
+				let block = frame / samples_per_block as u64;
+				self.data.seek(SeekFrom::Start(self.offset + block * block_align as u64))?;
+				self.frame = block * samples_per_block as u64;
+				self.decode_block()?;
+				self.index = (frame - self.frame) as usize * self.channels;
+			},
+			encoding => {
+				self.data.seek(SeekFrom::Start(self.offset + frame * (encoding.bytes() * self.channels) as u64))?;
+				self.frame = frame;
+			}
+		}
+		Ok(())
+	}
+
+
+	fn write_samples (&mut self, buffer: &mut [i16]) -> usize {
+		let result = match self.encoding {
+			Encoding::ImaAdpcm { .. } => self.write_adpcm(buffer),
+			_ => self.write_pcm(buffer)
+		};
+		result.unwrap_or_else(|(len, err)| {
+			error!("error while decoding wav: {}", err);
+			len
+		})
+	}
+
+
+	/// the formats with a fixed size per sample
+	fn write_pcm (&mut self, buffer: &mut [i16]) -> Result<usize, (usize, hound::Error)> {
+		let frames = ((buffer.len() / self.channels) as u64).min(self.frames - self.frame) as usize;
+		let bytes = self.encoding.bytes();
+		self.bytes.resize(frames * self.channels * bytes, 0);
+		self.data.read_exact(&mut self.bytes).map_err(|err| (0, err.into()))?;
+		self.frame += frames as u64;
+
+		for (out, sample) in buffer.iter_mut().zip(self.bytes.chunks_exact(bytes)) {
+			*out = match self.encoding {
+				Encoding::ALaw => alaw_to_i16(sample[0]),
+				Encoding::MuLaw => mulaw_to_i16(sample[0]),
+				Encoding::Float64 => f32_to_i16(f64::from_le_bytes(sample.try_into().unwrap()) as f32),
+				_ => i16::from_le_bytes([sample[bytes - 2], sample[bytes - 1]])
+			};
+		}
+		Ok(frames * self.channels)
+	}
+
+
+	fn write_adpcm (&mut self, buffer: &mut [i16]) -> Result<usize, (usize, hound::Error)> {
+		let mut len = 0;
+		while len < buffer.len() {
+			if self.index == self.decoded.len() {
+				self.frame += (self.decoded.len() / self.channels) as u64;
+				if self.frame >= self.frames {
+					break;
+				}
+				self.decode_block().map_err(|err| (len, err))?;
+				self.index = 0;
+			}
+			// the last block can have samples past the end, given by the
+			// fact chunk
+			let end = (self.decoded.len()).min((self.frames - self.frame) as usize * self.channels);
+			let count = (end.saturating_sub(self.index)).min(buffer.len() - len);
+			if count == 0 {
+				self.index = self.decoded.len();
+				continue;
+			}
+			buffer[len..len + count].copy_from_slice(&self.decoded[self.index..self.index + count]);
+			self.index += count;
+			len += count;
+		}
+		Ok(len)
+	}
+
+
+	/// decode the ima adpcm block at the position of `data` into
+	/// `decoded`
+	fn decode_block (&mut self) -> Result<(), hound::Error> {
+		let Encoding::ImaAdpcm { block_align, samples_per_block } = self.encoding else {
+			return Ok(());
+		};
+		let channels = self.channels;
+		let start = self.offset + self.frame / samples_per_block as u64 * block_align as u64;
+		let size = (self.offset + self.len).saturating_sub(start).min(block_align as u64) as usize;
+		self.bytes.resize(size, 0);
+		self.data.read_exact(&mut self.bytes)?;
+		self.decoded.clear();
+		let samples = ima_samples(size, channels);
+		if samples == 0 {
+			return Ok(());
+		}
+		self.decoded.resize(samples * channels, 0);
+		let end = 4 * channels + (samples - 1) * channels / 2;
+
+		for c in 0..channels {
+			let header = &self.bytes[c * 4..c * 4 + 4];
+			let mut predictor = i16::from_le_bytes([header[0], header[1]]) as i32;
+			let mut step_index = (header[2] as usize).min(88);
+			self.decoded[c] = predictor as i16;
+			// after the headers, every channel has 4 bytes of 8 samples in
+			// turn, the low nibble first
+			for (i, group) in self.bytes[4 * channels..end].chunks(4 * channels).enumerate() {
+				for (j, byte) in group[c * 4..].iter().take(4).enumerate() {
+					for (k, nibble) in [byte & 0xF, byte >> 4].into_iter().enumerate() {
+						let step = IMA_STEPS[step_index];
+						let mut diff = step >> 3;
+						if nibble & 1 != 0 { diff += step >> 2; }
+						if nibble & 2 != 0 { diff += step >> 1; }
+						if nibble & 4 != 0 { diff += step; }
+						predictor = if nibble & 8 != 0 { predictor - diff } else { predictor + diff };
+						predictor = predictor.clamp(i16::MIN as i32, i16::MAX as i32);
+						step_index = (step_index as i32 + IMA_INDICES[nibble as usize]).clamp(0, 88) as usize;
+						let sample = 1 + i * 8 + j * 2 + k;
+						self.decoded[sample * channels + c] = predictor as i16;
+					}
+				}
+			}
+		}
+		Ok(())
 	}
-	if x < -1.0 {
-		x = -1.0
+
+
+}
+
+
+
+/// the samples of each channel in an ima adpcm block of `size` bytes
+///
+/// a 4 bytes header per channel with the first sample, then 2 samples
+/// per byte. a short last block can end inside a group of samples,
+/// which are only whole for mono
+fn ima_samples (size: usize, channels: usize) -> usize {
+	if size <= 4 * channels {
+		return 0;
 	}
+	let data = size - 4 * channels;
+	let data = if channels == 1 { data } else { data - data % (4 * channels) };
+	data * 2 / channels + 1
+}
+
+
+
+const IMA_INDICES: [i32; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+const IMA_STEPS: [i32; 89] = [
+	7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66, 73,
+	80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+	494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+	2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+	10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767
+];
+
+
+
+/// g.711 a-law, the sign bit set is positive
+pub (crate) fn alaw_to_i16 (x: u8) -> i16 {
+	let x = x ^ 0x55;
+	let exponent = (x >> 4) & 7;
+	let mantissa = (x & 0xF) as i16;
+	let magnitude = if exponent == 0 {
+		(mantissa << 4) + 8
+	} else {
+		((mantissa << 4) + 0x108) << (exponent - 1)
+	};
+	if x & 0x80 != 0 { magnitude } else { -magnitude }
+}
+
+
+/// g.711 µ-law, stored inverted
+pub (crate) fn mulaw_to_i16 (x: u8) -> i16 {
+	let x = !x;
+	let exponent = (x >> 4) & 7;
+	let mantissa = (x & 0xF) as i16;
+	let magnitude = (((mantissa << 3) + 0x84) << exponent) - 0x84;
+	if x & 0x80 != 0 { -magnitude } else { magnitude }
+}
+
+
+
+fn u32_at (bytes: &[u8], index: usize) -> u32 {
+	bytes.get(index..index + 4).map_or(0, |x| u32::from_le_bytes(x.try_into().unwrap()))
+}
+
+
+
+pub (crate) fn f32_to_i16 (x: f32) -> i16 {
+	let x = x.clamp(-1.0, 1.0);
 	if x >= 0.0 {
 		(x * i16::MAX as f32) as i16
 	} else {
@@ -124,3 +632,206 @@ fn f32_to_i16 (mut x: f32) -> i16 {
 }
 
 
+
+
+
+#[cfg(test)]
+mod tests {
+
+	use std::io::Cursor;
+
+	use super::*;
+
+
+	/// a `fmt ` chunk body, with `extra` after the 16 bytes of pcm
+	fn format (tag: u16, channels: u16, block_align: u16, bits: u16, extra: &[u8]) -> Vec<u8> {
+		let mut body = vec![];
+		body.extend_from_slice(&tag.to_le_bytes());
+		body.extend_from_slice(&channels.to_le_bytes());
+		body.extend_from_slice(&8000u32.to_le_bytes());
+		body.extend_from_slice(&(8000 * block_align as u32).to_le_bytes());
+		body.extend_from_slice(&block_align.to_le_bytes());
+		body.extend_from_slice(&bits.to_le_bytes());
+		body.extend_from_slice(extra);
+		body
+	}
+
+
+	/// a wav file of the chunks
+	fn wav (chunks: &[(&[u8; 4], &[u8])]) -> WavDecoder<Cursor<Vec<u8>>> {
+		WavDecoder::new(Cursor::new(file(chunks))).unwrap()
+	}
+
+
+	fn file (chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+		let mut body = b"WAVE".to_vec();
+		for (id, data) in chunks {
+			body.extend_from_slice(*id);
+			body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+			body.extend_from_slice(data);
+			if data.len() % 2 == 1 {
+				body.push(0);
+			}
+		}
+		let mut file = b"RIFF".to_vec();
+		file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+		file.extend_from_slice(&body);
+		file
+	}
+
+
+	fn read_all (decoder: &mut impl SoundSource) -> Vec<i16> {
+		let mut samples = vec![];
+		let mut buffer = [0; 6];
+		loop {
+			let len = decoder.write_samples(&mut buffer);
+			samples.extend_from_slice(&buffer[..len]);
+			if len < buffer.len() {
+				return samples;
+			}
+		}
+	}
+
+
+	#[test]
+	fn alaw () {
+		let mut decoder = wav(&[(b"fmt ", &format(FORMAT_ALAW, 1, 1, 8, &[])), (b"data", &[0xD5, 0x55, 0xAA, 0x2A, 0x80])]);
+		assert_eq!(read_all(&mut decoder), [8, -8, 32256, -32256, 5504]);
+	}
+
+
+	#[test]
+	fn mulaw () {
+		let mut decoder = wav(&[(b"fmt ", &format(FORMAT_MULAW, 1, 1, 8, &[])), (b"data", &[0xFF, 0x80, 0x00, 0xF0, 0x70])]);
+		assert_eq!(read_all(&mut decoder), [0, 32124, -32124, 120, -120]);
+	}
+
+
+	#[test]
+	fn float64 () {
+		let data: Vec<u8> = [0.5f64, -1.0, 2.0, 0.0].iter().flat_map(|x| x.to_le_bytes()).collect();
+		let mut decoder = wav(&[(b"fmt ", &format(FORMAT_FLOAT, 2, 16, 64, &[])), (b"data", &data)]);
+		assert_eq!(decoder.channels(), 2);
+		assert_eq!(decoder.total_frames(), Some(2));
+		assert_eq!(read_all(&mut decoder), [16383, -32768, 32767, 0]);
+	}
+
+
+	#[test]
+	fn padded_int () {
+		// 24 valid bits in 32, with the pcm guid
+		let mut extra = vec![22, 0, 24, 0, 0, 0, 0, 0];
+		extra.extend_from_slice(&FORMAT_PCM.to_le_bytes());
+		extra.extend_from_slice(&[0; 14]);
+		let format = format(FORMAT_EXTENSIBLE, 1, 4, 32, &extra);
+		let mut decoder = wav(&[(b"fmt ", &format), (b"data", &[0x00, 0x56, 0x34, 0x12, 0x00, 0x00, 0x00, 0x80])]);
+		assert_eq!(read_all(&mut decoder), [0x1234, i16::MIN]);
+		assert!(decoder.seek(1));
+		assert_eq!(read_all(&mut decoder), [i16::MIN]);
+	}
+
+
+	#[test]
+	fn samples_per_block () {
+		let format = |channels, block_align| Format { tag: FORMAT_IMA_ADPCM, channels, sample_rate: 8000, block_align, bits_per_sample: 4, extra: 2 };
+		let samples = |format: Format| match format.encoding() {
+			Some(Encoding::ImaAdpcm { samples_per_block, .. }) => Some(samples_per_block),
+			_ => None
+		};
+		assert_eq!(samples(format(1, 8)), Some(9));
+		assert_eq!(samples(format(1, 256)), Some(505));
+		assert_eq!(samples(format(2, 2048)), Some(2041));
+		// no room for the samples, or not a whole group of them
+		assert_eq!(samples(format(1, 4)), None);
+		assert_eq!(samples(format(2, 12)), None);
+	}
+
+
+	/// 2 blocks of 9 samples and a last one of 5
+	fn ima_mono (fact: Option<u32>) -> WavDecoder<Cursor<Vec<u8>>> {
+		let data = [
+			0x00, 0x00, 0, 0, 0x74, 0x00, 0x00, 0x00,
+			0xE8, 0x03, 0, 0, 0x47, 0x00, 0x00, 0x00,
+			0x0C, 0xFE, 20, 0, 0x9C, 0x31
+		];
+		let format = format(FORMAT_IMA_ADPCM, 1, 8, 4, &[2, 0, 9, 0]);
+		match fact {
+			Some(frames) => wav(&[(b"fmt ", &format), (b"fact", &frames.to_le_bytes()), (b"data", &data)]),
+			None => wav(&[(b"fmt ", &format), (b"data", &data)])
+		}
+	}
+
+
+	const IMA_MONO: [i16; 23] = [
+		0, 7, 23, 25, 27, 29, 30, 31, 32,
+		1000, 1011, 1029, 1031, 1033, 1035, 1036, 1037, 1038,
+		-500, -556, -578, -559, -516
+	];
+
+
+	#[test]
+	fn zero_sample_rate () {
+		let mut extensible = vec![22, 0, 24, 0, 0, 0, 0, 0];
+		extensible.extend_from_slice(&FORMAT_PCM.to_le_bytes());
+		extensible.extend_from_slice(&[0; 14]);
+		let formats = [
+			format(FORMAT_PCM, 1, 2, 16, &[]),
+			format(FORMAT_ALAW, 1, 1, 8, &[]),
+			format(FORMAT_MULAW, 1, 1, 8, &[]),
+			format(FORMAT_FLOAT, 1, 8, 64, &[]),
+			format(FORMAT_EXTENSIBLE, 1, 4, 32, &extensible),
+			format(FORMAT_IMA_ADPCM, 1, 8, 4, &[2, 0, 9, 0])
+		];
+		for mut format in formats {
+			format[4..8].fill(0);
+			let file = file(&[(b"fmt ", &format), (b"data", &[0; 16])]);
+			assert!(WavDecoder::new(Cursor::new(file)).is_err(), "format {}", u16::from_le_bytes([format[0], format[1]]));
+		}
+	}
+
+
+	#[test]
+	fn ima_adpcm () {
+		let mut decoder = ima_mono(None);
+		assert_eq!(decoder.total_frames(), Some(23));
+		// the low nibble first, and every block from its own header
+		assert_eq!(read_all(&mut decoder), IMA_MONO);
+	}
+
+
+	#[test]
+	fn ima_adpcm_fact () {
+		let mut decoder = ima_mono(Some(20));
+		assert_eq!(decoder.total_frames(), Some(20));
+		assert_eq!(read_all(&mut decoder), IMA_MONO[..20]);
+	}
+
+
+	#[test]
+	fn ima_adpcm_seek () {
+		let mut decoder = ima_mono(None);
+		for frame in [0, 8, 9, 10, 17, 18, 22] {
+			assert!(decoder.seek(frame));
+			assert_eq!(read_all(&mut decoder), IMA_MONO[frame as usize..], "from {}", frame);
+		}
+		decoder.reset();
+		assert_eq!(read_all(&mut decoder), IMA_MONO);
+	}
+
+
+	#[test]
+	fn ima_adpcm_stereo () {
+		// the header of each channel, then 4 bytes of each in turn
+		let data = [
+			0x00, 0x00, 0, 0, 0x9C, 0xFF, 5, 0,
+			0x12, 0x34, 0x56, 0x78, 0xFE, 0xDC, 0xBA, 0x98
+		];
+		let mut decoder = wav(&[(b"fmt ", &format(FORMAT_IMA_ADPCM, 2, 16, 4, &[2, 0, 9, 0])), (b"data", &data)]);
+		let left = [0, 3, 4, 11, 18, 31, 49, 47, 81];
+		let right = [-100, -119, -157, -207, -281, -331, -394, -402, -424];
+		let expected: Vec<i16> = left.into_iter().zip(right).flat_map(|(l, r)| [l, r]).collect();
+		assert_eq!(read_all(&mut decoder), expected);
+	}
+
+
+}